@@ -202,6 +202,24 @@ pub fn generate_status_provide_key_impl(
     Some(pk_impl)
 }
 
+pub fn generate_status_provide_conditions_impl(
+    analysis: &ResourceAnalysis,
+) -> proc_macro2::TokenStream {
+    let status_struct_name = analysis.status.generated_ident.clone();
+
+    quote! {
+        impl crate::resources::condition::ProvideConditions for #status_struct_name {
+            fn conditions(&self) -> &[crate::resources::condition::Condition] {
+                &self.conditions
+            }
+
+            fn set_conditions(&mut self, conditions: Vec<crate::resources::condition::Condition>) {
+                self.conditions = conditions;
+            }
+        }
+    }
+}
+
 pub fn generate_build_info_impl(analysis: &ResourceAnalysis) -> proc_macro2::TokenStream {
     let enum_name = syn::Ident::new(&analysis.args.name, Span::call_site());
     let enum_name_str = enum_name.to_string();
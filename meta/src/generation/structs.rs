@@ -57,6 +57,12 @@ pub fn generate_status_struct(
     item.fields.iter_mut().for_each(|field| {
         field.vis = syn::Visibility::Public(syn::token::Pub { span: field.span() });
     });
+    if let syn::Fields::Named(fields) = &mut item.fields {
+        fields.named.push(syn::parse_quote! {
+            #[serde(default)]
+            pub conditions: Vec<crate::resources::condition::Condition>
+        });
+    }
     item.ident = status_info.generated_ident.clone();
     item.vis = syn::Visibility::Public(syn::token::Pub {
         span: item.ident.span(),
@@ -10,7 +10,8 @@ use analysis::{analyze_resource, validate_versions};
 use generation::{
     generate_build_info_impl, generate_conversion_methods, generate_provide_key_impl,
     generate_provide_metadata_impl, generate_provide_metadata_impls, generate_schema_enum_variants,
-    generate_status_provide_key_impl, generate_status_struct, generate_type_aliases,
+    generate_status_provide_conditions_impl, generate_status_provide_key_impl,
+    generate_status_struct, generate_type_aliases,
     generate_version_enum_variants, generate_version_struct,
 };
 use types::ResourceArgs;
@@ -122,6 +123,7 @@ fn generate_output(
     let provide_key_impl = generate_provide_key_impl(&analysis);
     let provide_metadata_impl = generate_provide_metadata_impl(&analysis);
     let provide_key_impl_status = generate_status_provide_key_impl(&analysis);
+    let provide_conditions_impl_status = generate_status_provide_conditions_impl(&analysis);
     let build_info_impl = generate_build_info_impl(&analysis);
     let type_aliases = generate_type_aliases(&analysis);
     let conversion_methods = generate_conversion_methods(&analysis);
@@ -151,6 +153,8 @@ fn generate_output(
 
         #provide_key_impl_status
 
+        #provide_conditions_impl_status
+
         #build_info_impl
 
         #type_aliases
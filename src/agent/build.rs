@@ -1,37 +1,150 @@
+use std::{
+    sync::{Arc, Mutex, Weak},
+    time::Duration,
+};
+
 use anyhow::{Result, bail};
 use rand::seq::IndexedRandom;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    agent::data::Collections,
+    constants::{
+        DEFAULT_AGENT_TENANT, DEFAULT_BUILDER_ALLOCATION_TTL_SECS,
+        DEFAULT_BUILDER_CLIENT_CERT_TTL_MINUTES, DEFAULT_BUILDER_GC_INTERVAL_SECS,
+    },
+    machinery::store::{Key, PartialKey, Store},
+    resources::core::GcReport,
+    utils::time::now_millis,
+};
 
 #[derive(Debug, Clone)]
 pub struct BuildAgentConfig {
     pub remote_build_ca_cert_path: String,
     pub remote_build_ca_key_path: String,
     pub builders_pool: Vec<String>,
+    /// Where to write the revocation list for issued client certs, for builders to pick up.
+    pub remote_build_crl_path: Option<String>,
 }
 
 pub struct BuildAgent {
     pub remote_build_ca_cert_pem: String,
     pub remote_build_ca_key_pem: String,
     pub builders_pool: Vec<String>,
+    remote_build_crl_path: Option<String>,
+    store: Arc<Store>,
+    last_gc_report: Mutex<Option<GcReport>>,
 }
 
 pub struct BuilderAuth {
+    pub allocation_id: String,
     pub host: String,
     pub client_cert_pem: String,
     pub client_key_pem: String,
     pub ca_cert_pem: String,
 }
 
+/// A builder handed out to a client, released by the client when the build is done or reclaimed
+/// once it expires.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuilderAllocation {
+    pub id: String,
+    pub tenant: String,
+    pub user: String,
+    pub host: String,
+    pub cert_serial: String,
+    pub cert_not_after: u64,
+    pub created_at: u64,
+    pub expires_at: u64,
+}
+
+/// A client cert revoked before its expiry, kept until the cert expires on its own.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuilderRevocation {
+    pub cert_serial: String,
+    pub cert_not_after: u64,
+    pub revoked_at: u64,
+}
+
+impl BuilderAllocation {
+    fn key_for(id: &str) -> Key<BuilderAllocation> {
+        Key::<BuilderAllocation>::not_namespaced()
+            .tenant(DEFAULT_AGENT_TENANT)
+            .collection(Collections::BuilderAllocation)
+            .key(id)
+            .as_ref()
+            .into()
+    }
+}
+
+impl BuilderRevocation {
+    fn key_for(serial: &str) -> Key<BuilderRevocation> {
+        Key::<BuilderRevocation>::not_namespaced()
+            .tenant(DEFAULT_AGENT_TENANT)
+            .collection(Collections::BuilderRevocation)
+            .key(serial)
+            .as_ref()
+            .into()
+    }
+}
+
 impl BuildAgent {
-    pub fn new(config: BuildAgentConfig) -> Result<Self> {
+    pub fn new(config: BuildAgentConfig, store: Arc<Store>) -> Result<Self> {
         let remote_build_ca_key_pem = std::fs::read_to_string(config.remote_build_ca_key_path)?;
         let remote_build_ca_cert_pem = std::fs::read_to_string(config.remote_build_ca_cert_path)?;
         let builders_pool = config.builders_pool;
 
-        Ok(Self {
+        let agent = Self {
             remote_build_ca_cert_pem,
             remote_build_ca_key_pem,
             builders_pool,
-        })
+            remote_build_crl_path: config.remote_build_crl_path,
+            store,
+            last_gc_report: Mutex::new(None),
+        };
+        // the builders refuse clients until they have a list to check
+        agent.write_crl()?;
+
+        Ok(agent)
+    }
+
+    /// Periodically reclaims expired allocations, stops once the agent is dropped.
+    pub fn start_gc(self: &Arc<Self>) {
+        let agent = Arc::downgrade(self);
+
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(Duration::from_secs(DEFAULT_BUILDER_GC_INTERVAL_SECS));
+
+            loop {
+                interval.tick().await;
+
+                let Some(agent) = Weak::upgrade(&agent) else {
+                    break;
+                };
+
+                if let Err(e) = agent.collect_garbage() {
+                    warn!("failed to collect stale builder allocations: {}", e);
+                }
+            }
+        });
+    }
+
+    fn list_allocations(&self) -> Result<Vec<BuilderAllocation>> {
+        let key = PartialKey::<BuilderAllocation>::not_namespaced()
+            .tenant(DEFAULT_AGENT_TENANT)
+            .collection(Collections::BuilderAllocation);
+
+        self.store.list(&key)
+    }
+
+    fn list_revocations(&self) -> Result<Vec<BuilderRevocation>> {
+        let key = PartialKey::<BuilderRevocation>::not_namespaced()
+            .tenant(DEFAULT_AGENT_TENANT)
+            .collection(Collections::BuilderRevocation);
+
+        self.store.list(&key)
     }
 
     pub fn pick_and_authorize_builder(
@@ -39,7 +152,26 @@ impl BuildAgent {
         tenant: impl AsRef<str>,
         user: impl AsRef<str>,
     ) -> Result<BuilderAuth> {
-        let Some(builder) = self.builders_pool.choose(&mut rand::rng()) else {
+        let allocations = self.list_allocations()?;
+        let now = now_millis();
+
+        // prefer the builders with the fewest builds in flight
+        let load = |builder: &String| {
+            allocations
+                .iter()
+                .filter(|a| a.host == *builder && a.expires_at > now)
+                .count()
+        };
+        let Some(min_load) = self.builders_pool.iter().map(load).min() else {
+            bail!("No builder found");
+        };
+        let candidates = self
+            .builders_pool
+            .iter()
+            .filter(|b| load(*b) == min_load)
+            .collect::<Vec<_>>();
+
+        let Some(builder) = candidates.choose(&mut rand::rng()) else {
             bail!("No builder found");
         };
 
@@ -53,44 +185,185 @@ impl BuildAgent {
         tenant: impl AsRef<str>,
         user: impl AsRef<str>,
     ) -> Result<BuilderAuth> {
-        let (client_cert_pem, client_key_pem, ca_cert_pem) =
-            cert_gen::issue_client_cert_with_uri_san(
-                &self.remote_build_ca_cert_pem,
-                &self.remote_build_ca_key_pem,
-                format!("{}@{}", tenant.as_ref(), user.as_ref()).as_str(),
-                format!(
-                    "spiffe://lttle.cloud/tenant/{}/user/{}",
-                    tenant.as_ref(),
-                    user.as_ref()
-                )
-                .as_str(),
-                10,
-            )?;
+        let issued = cert_gen::issue_client_cert_with_uri_san(
+            &self.remote_build_ca_cert_pem,
+            &self.remote_build_ca_key_pem,
+            format!("{}@{}", tenant.as_ref(), user.as_ref()).as_str(),
+            format!(
+                "spiffe://lttle.cloud/tenant/{}/user/{}",
+                tenant.as_ref(),
+                user.as_ref()
+            )
+            .as_str(),
+            DEFAULT_BUILDER_CLIENT_CERT_TTL_MINUTES,
+        )?;
+
+        let now = now_millis();
+        let allocation = BuilderAllocation {
+            id: uuid::Uuid::new_v4().to_string(),
+            tenant: tenant.as_ref().to_string(),
+            user: user.as_ref().to_string(),
+            host: builder.as_ref().to_string(),
+            cert_serial: issued.serial.clone(),
+            cert_not_after: issued.not_after,
+            created_at: now,
+            expires_at: now + DEFAULT_BUILDER_ALLOCATION_TTL_SECS * 1000,
+        };
+        self.store
+            .put(&BuilderAllocation::key_for(&allocation.id), &allocation)?;
 
         Ok(BuilderAuth {
+            allocation_id: allocation.id,
             host: builder.as_ref().to_string(),
-            client_cert_pem,
-            client_key_pem,
-            ca_cert_pem,
+            client_cert_pem: issued.client_cert_pem,
+            client_key_pem: issued.client_key_pem,
+            ca_cert_pem: issued.ca_cert_pem,
         })
     }
+
+    /// Releases an allocation owned by `tenant` and revokes its client cert.
+    pub fn release_builder(&self, tenant: impl AsRef<str>, allocation_id: &str) -> Result<()> {
+        let key = BuilderAllocation::key_for(allocation_id);
+        let Some(allocation) = self.store.get(&key)? else {
+            // already released or reclaimed
+            return Ok(());
+        };
+
+        if allocation.tenant != tenant.as_ref() {
+            bail!("Builder allocation not found");
+        }
+
+        self.revoke(&allocation)?;
+        self.store.delete(key)?;
+        self.write_crl()?;
+
+        Ok(())
+    }
+
+    pub fn last_gc_report(&self) -> Option<GcReport> {
+        self.last_gc_report
+            .lock()
+            .expect("gc report poisoned")
+            .clone()
+    }
+
+    pub fn collect_garbage(&self) -> Result<()> {
+        let mut report = GcReport {
+            ran_at: now_millis(),
+            reclaimed_allocations: 0,
+            expired_revocations: 0,
+            error: None,
+        };
+
+        let result = self.sweep(&mut report);
+        report.error = result.as_ref().err().map(|e| e.to_string());
+        *self.last_gc_report.lock().expect("gc report poisoned") = Some(report);
+
+        result
+    }
+
+    fn sweep(&self, report: &mut GcReport) -> Result<()> {
+        let now = report.ran_at;
+        let mut changed = false;
+
+        for allocation in self.list_allocations()? {
+            if allocation.expires_at > now {
+                continue;
+            }
+
+            info!(
+                "reclaiming stale builder allocation {} ({}@{} on {})",
+                allocation.id, allocation.user, allocation.tenant, allocation.host
+            );
+            self.revoke(&allocation)?;
+            self.store
+                .delete(BuilderAllocation::key_for(&allocation.id))?;
+            report.reclaimed_allocations += 1;
+            changed = true;
+        }
+
+        // an expired cert is rejected anyway, no need to keep listing it
+        for revocation in self.list_revocations()? {
+            if revocation.cert_not_after > now {
+                continue;
+            }
+
+            self.store
+                .delete(BuilderRevocation::key_for(&revocation.cert_serial))?;
+            report.expired_revocations += 1;
+            changed = true;
+        }
+
+        if changed {
+            self.write_crl()?;
+        }
+
+        Ok(())
+    }
+
+    fn revoke(&self, allocation: &BuilderAllocation) -> Result<()> {
+        if allocation.cert_not_after <= now_millis() {
+            return Ok(());
+        }
+
+        let revocation = BuilderRevocation {
+            cert_serial: allocation.cert_serial.clone(),
+            cert_not_after: allocation.cert_not_after,
+            revoked_at: now_millis(),
+        };
+
+        self.store.put(
+            &BuilderRevocation::key_for(&revocation.cert_serial),
+            &revocation,
+        )?;
+
+        Ok(())
+    }
+
+    fn write_crl(&self) -> Result<()> {
+        let Some(crl_path) = &self.remote_build_crl_path else {
+            return Ok(());
+        };
+
+        let crl_pem = cert_gen::issue_crl(
+            &self.remote_build_ca_cert_pem,
+            &self.remote_build_ca_key_pem,
+            &self.list_revocations()?,
+        )?;
+
+        std::fs::write(crl_path, crl_pem)?;
+        Ok(())
+    }
 }
 
 mod cert_gen {
+    use anyhow::bail;
     use rcgen::{
-        Certificate, CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, IsCa,
-        Issuer, KeyPair, KeyUsagePurpose, SanType,
+        Certificate, CertificateParams, CertificateRevocationListParams, DistinguishedName, DnType,
+        ExtendedKeyUsagePurpose, IsCa, Issuer, KeyIdMethod, KeyPair, KeyUsagePurpose,
+        RevokedCertParams, SanType, SerialNumber,
     };
     use time::{Duration, OffsetDateTime};
 
-    /// Returns (client_cert_pem, client_key_pem, ca_cert_pem)
+    use super::BuilderRevocation;
+
+    pub struct IssuedClientCert {
+        pub client_cert_pem: String,
+        pub client_key_pem: String,
+        pub ca_cert_pem: String,
+        /// Hex encoded
+        pub serial: String,
+        /// Unix millis
+        pub not_after: u64,
+    }
+
     pub fn issue_client_cert_with_uri_san(
         ca_cert_pem: &str, // your CA (or intermediate) cert in PEM
         ca_key_pem: &str,  // matching CA private key (kept server-side)
         subject_cn: &str,  // e.g., "user-abc@tenant-xyz"
         uri_san: &str,     // e.g., "spiffe://lttle.cloud/tenant/xyz/user/abc"
         ttl_minutes: i64,  // e.g., 10
-    ) -> anyhow::Result<(String, String, String)> {
+    ) -> anyhow::Result<IssuedClientCert> {
         let ca_key = KeyPair::from_pem(ca_key_pem)?;
         let issuer = Issuer::from_ca_cert_pem(ca_cert_pem, &ca_key)?; // uses your existing CA cert to sign
 
@@ -99,11 +372,17 @@ mod cert_gen {
         let mut dn = DistinguishedName::new();
         dn.push(DnType::CommonName, subject_cn);
 
+        // explicit serial, so the cert can be revoked later
+        let mut serial = rand::random::<[u8; 16]>();
+        serial[0] &= 0x7f;
+
         let now = OffsetDateTime::now_utc();
+        let not_after = now + Duration::minutes(ttl_minutes);
         let mut params = CertificateParams::new(Vec::<String>::new())?;
         params.distinguished_name = dn;
+        params.serial_number = Some(SerialNumber::from_slice(&serial));
         params.not_before = now - Duration::minutes(1);
-        params.not_after = now + Duration::minutes(ttl_minutes);
+        params.not_after = not_after;
         params.is_ca = IsCa::NoCa;
         params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
         params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
@@ -112,8 +391,52 @@ mod cert_gen {
             .push(SanType::URI(uri_san.try_into()?));
 
         let client_cert: Certificate = params.signed_by(&client_key, &issuer)?;
-        let client_cert_pem = client_cert.pem();
-        let client_key_pem = client_key.serialize_pem();
-        Ok((client_cert_pem, client_key_pem, ca_cert_pem.to_string()))
+
+        Ok(IssuedClientCert {
+            client_cert_pem: client_cert.pem(),
+            client_key_pem: client_key.serialize_pem(),
+            ca_cert_pem: ca_cert_pem.to_string(),
+            serial: hex::encode(serial),
+            not_after: (not_after.unix_timestamp_nanos() / 1_000_000) as u64,
+        })
+    }
+
+    /// Returns the CRL listing `revocations`, signed by the CA, in PEM.
+    pub fn issue_crl(
+        ca_cert_pem: &str,
+        ca_key_pem: &str,
+        revocations: &[BuilderRevocation],
+    ) -> anyhow::Result<String> {
+        let ca_key = KeyPair::from_pem(ca_key_pem)?;
+        let issuer = Issuer::from_ca_cert_pem(ca_cert_pem, &ca_key)?;
+
+        let mut revoked_certs = Vec::with_capacity(revocations.len());
+        for revocation in revocations {
+            let Ok(serial) = hex::decode(&revocation.cert_serial) else {
+                bail!("invalid cert serial: {}", revocation.cert_serial);
+            };
+
+            revoked_certs.push(RevokedCertParams {
+                serial_number: SerialNumber::from_slice(&serial),
+                revocation_time: OffsetDateTime::from_unix_timestamp_nanos(
+                    revocation.revoked_at as i128 * 1_000_000,
+                )?,
+                reason_code: None,
+                invalidity_date: None,
+            });
+        }
+
+        let now = OffsetDateTime::now_utc();
+        let params = CertificateRevocationListParams {
+            this_update: now,
+            next_update: now + Duration::days(1),
+            crl_number: SerialNumber::from((now.unix_timestamp() as u64).to_be_bytes().to_vec()),
+            issuing_distribution_point: None,
+            revoked_certs,
+            key_identifier_method: KeyIdMethod::Sha256,
+        };
+
+        let crl = params.signed_by(&issuer)?;
+        Ok(crl.pem()?)
     }
 }
@@ -14,5 +14,14 @@ pub struct CertProvider {
 #[derive(Debug, Clone)]
 pub struct CertificateAgentConfig {
     pub providers: Vec<CertProvider>,
+    /// Where certificates are cached on disk, when `disk_cache` is on.
     pub certs_base_dir: String,
+    /// Also write issued certificates to `certs_base_dir`, for tools reading them from disk.
+    pub disk_cache: bool,
+    /// Key sealing the private keys of certificates kept in the store.
+    pub store_key: [u8; 32],
+    /// Days before expiry to renew certificates that don't configure their own renewal.
+    pub renewal_days_before_expiry: u32,
+    /// Suffix of the internal service hostnames the internal CA issues for, eg. `svc.<zone-suffix>`.
+    pub internal_domain_suffix: String,
 }
@@ -0,0 +1,245 @@
+use anyhow::Result;
+use rcgen::{
+    BasicConstraints, CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose,
+    GeneralSubtree, IsCa, Issuer, KeyPair, KeyUsagePurpose, NameConstraints, SanType, SerialNumber,
+};
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
+
+/// Root of the internal CA of a tenant, generated on first use.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredInternalCa {
+    pub cert_pem: String,
+    pub key_pem: String,
+    /// Unix millis
+    pub not_after: u64,
+    /// Root replaced by the last rotation, trusted along with the current one for a while so
+    /// certificates it signed keep working until they are issued again.
+    #[serde(default)]
+    pub previous_cert_pem: Option<String>,
+    /// Unix millis
+    #[serde(default)]
+    pub previous_trusted_until: Option<u64>,
+}
+
+impl StoredInternalCa {
+    /// Roots to trust at `now` (unix millis), the current one first.
+    pub fn trust_bundle(&self, now: u64) -> String {
+        match (&self.previous_cert_pem, self.previous_trusted_until) {
+            (Some(previous), Some(until)) if until > now => {
+                format!("{}{}", self.cert_pem, previous)
+            }
+            _ => self.cert_pem.clone(),
+        }
+    }
+
+    /// Whether `certificate` was signed by the current root.
+    pub fn signed(&self, certificate: &StoredInternalCertificate) -> bool {
+        certificate.ca_cert_pem.starts_with(&self.cert_pem)
+    }
+}
+
+/// Short-lived certificate issued by the internal CA of a tenant for a certificate resource.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredInternalCertificate {
+    pub cert_pem: String,
+    pub key_pem: String,
+    pub ca_cert_pem: String,
+    pub domains: Vec<String>,
+    /// Unix millis
+    pub not_before: u64,
+    /// Unix millis
+    pub not_after: u64,
+}
+
+fn random_serial() -> SerialNumber {
+    let mut serial = rand::random::<[u8; 16]>();
+    serial[0] &= 0x7f;
+    SerialNumber::from_slice(&serial)
+}
+
+fn unix_millis(time: OffsetDateTime) -> u64 {
+    (time.unix_timestamp_nanos() / 1_000_000) as u64
+}
+
+/// Self-signed root only allowed to sign names under `domain_suffix`, so trusting it inside an
+/// image can't be abused for public names.
+pub(super) fn generate_ca(
+    tenant: &str,
+    domain_suffix: &str,
+    ttl_days: i64,
+) -> Result<StoredInternalCa> {
+    let key = KeyPair::generate()?;
+
+    let mut dn = DistinguishedName::new();
+    dn.push(
+        DnType::CommonName,
+        format!("ignition internal CA ({})", tenant),
+    );
+
+    let now = OffsetDateTime::now_utc();
+    let not_after = now + Duration::days(ttl_days);
+    let mut params = CertificateParams::new(Vec::<String>::new())?;
+    params.distinguished_name = dn;
+    params.serial_number = Some(random_serial());
+    params.not_before = now - Duration::minutes(1);
+    params.not_after = not_after;
+    params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+    params.key_usages = vec![
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+        KeyUsagePurpose::DigitalSignature,
+    ];
+    params.name_constraints = Some(NameConstraints {
+        permitted_subtrees: vec![GeneralSubtree::DnsName(domain_suffix.to_string())],
+        excluded_subtrees: vec![],
+    });
+
+    let cert = params.self_signed(&key)?;
+
+    Ok(StoredInternalCa {
+        cert_pem: cert.pem(),
+        key_pem: key.serialize_pem(),
+        not_after: unix_millis(not_after),
+        previous_cert_pem: None,
+        previous_trusted_until: None,
+    })
+}
+
+/// Replaces the root of `current` with a new one, the current root stays trusted for
+/// `overlap_hours` so the certificates it signed can be issued again meanwhile.
+pub(super) fn rotate_ca(
+    current: &StoredInternalCa,
+    tenant: &str,
+    domain_suffix: &str,
+    ttl_days: i64,
+    overlap_hours: i64,
+) -> Result<StoredInternalCa> {
+    let mut ca = generate_ca(tenant, domain_suffix, ttl_days)?;
+
+    let now = OffsetDateTime::now_utc();
+    let trusted_until = unix_millis(now + Duration::hours(overlap_hours)).min(current.not_after);
+    ca.previous_cert_pem = Some(current.cert_pem.clone());
+    ca.previous_trusted_until = Some(trusted_until);
+
+    Ok(ca)
+}
+
+/// Issues a certificate for `domains` usable both as a server and as a client, the `uri_san`
+/// names the workload on the other end of mTLS connections.
+pub(super) fn issue_certificate(
+    ca: &StoredInternalCa,
+    key: KeyPair,
+    domains: &[String],
+    uri_san: &str,
+    ttl_hours: u32,
+) -> Result<StoredInternalCertificate> {
+    let ca_key = KeyPair::from_pem(&ca.key_pem)?;
+    let issuer = Issuer::from_ca_cert_pem(&ca.cert_pem, &ca_key)?;
+
+    let mut dn = DistinguishedName::new();
+    if let Some(domain) = domains.first() {
+        dn.push(DnType::CommonName, domain.as_str());
+    }
+
+    let now = OffsetDateTime::now_utc();
+    let not_before = now - Duration::minutes(1);
+    let not_after = now + Duration::hours(ttl_hours as i64);
+    let mut params = CertificateParams::new(domains.to_vec())?;
+    params.distinguished_name = dn;
+    params.serial_number = Some(random_serial());
+    params.not_before = not_before;
+    params.not_after = not_after;
+    params.is_ca = IsCa::NoCa;
+    params.key_usages = vec![
+        KeyUsagePurpose::DigitalSignature,
+        KeyUsagePurpose::KeyEncipherment,
+    ];
+    params.extended_key_usages = vec![
+        ExtendedKeyUsagePurpose::ServerAuth,
+        ExtendedKeyUsagePurpose::ClientAuth,
+    ];
+    params
+        .subject_alt_names
+        .push(SanType::URI(uri_san.try_into()?));
+
+    let cert = params.signed_by(&key, &issuer)?;
+
+    Ok(StoredInternalCertificate {
+        cert_pem: cert.pem(),
+        key_pem: key.serialize_pem(),
+        ca_cert_pem: ca.trust_bundle(unix_millis(now)),
+        domains: domains.to_vec(),
+        not_before: unix_millis(not_before),
+        not_after: unix_millis(not_after),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use x509_parser::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_issue_internal_certificate() {
+        let ca = generate_ca("tenant", "svc.lttle.local", 1).unwrap();
+        let certificate = issue_certificate(
+            &ca,
+            KeyPair::generate().unwrap(),
+            &["api.default.svc.lttle.local".to_string()],
+            "spiffe://svc.lttle.local/tenant/tenant/ns/default/certificate/api",
+            24,
+        )
+        .unwrap();
+        assert_eq!(certificate.ca_cert_pem, ca.cert_pem);
+        assert!(certificate.not_after > certificate.not_before);
+
+        let (_, pem) = parse_x509_pem(certificate.cert_pem.as_bytes()).unwrap();
+        let (_, cert) = X509Certificate::from_der(&pem.contents).unwrap();
+
+        let eku = cert.extended_key_usage().unwrap().unwrap().value;
+        assert!(eku.server_auth && eku.client_auth);
+
+        let san = cert.subject_alternative_name().unwrap().unwrap().value;
+        assert!(
+            san.general_names
+                .contains(&GeneralName::DNSName("api.default.svc.lttle.local"))
+        );
+    }
+
+    #[test]
+    fn test_rotate_internal_ca() {
+        let ca = generate_ca("tenant", "svc.lttle.local", 1).unwrap();
+        let domains = ["api.default.svc.lttle.local".to_string()];
+        let uri_san = "spiffe://svc.lttle.local/tenant/tenant/ns/default/certificate/api";
+
+        let before =
+            issue_certificate(&ca, KeyPair::generate().unwrap(), &domains, uri_san, 24).unwrap();
+        assert!(ca.signed(&before));
+
+        let rotated = rotate_ca(&ca, "tenant", "svc.lttle.local", 1, 48).unwrap();
+        assert_ne!(rotated.cert_pem, ca.cert_pem);
+        assert!(!rotated.signed(&before));
+
+        // both roots are trusted during the overlap, capped to the life of the previous one
+        let now = unix_millis(OffsetDateTime::now_utc());
+        assert_eq!(rotated.previous_trusted_until, Some(ca.not_after));
+        assert_eq!(
+            rotated.trust_bundle(now),
+            format!("{}{}", rotated.cert_pem, ca.cert_pem)
+        );
+        assert_eq!(rotated.trust_bundle(ca.not_after), rotated.cert_pem);
+
+        let after = issue_certificate(
+            &rotated,
+            KeyPair::generate().unwrap(),
+            &domains,
+            uri_san,
+            24,
+        )
+        .unwrap();
+        assert!(rotated.signed(&after));
+        assert_eq!(after.ca_cert_pem, rotated.trust_bundle(now));
+    }
+}
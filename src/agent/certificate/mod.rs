@@ -1,17 +1,147 @@
 pub mod config;
+pub mod internal;
+pub mod storage;
 
 use crate::{
     agent::data::Collections,
-    constants::DEFAULT_AGENT_TENANT,
-    machinery::store::{Key, Store},
+    constants::{
+        DEFAULT_AGENT_TENANT, DEFAULT_INTERNAL_CA_ROTATION_DAYS_BEFORE_EXPIRY,
+        DEFAULT_INTERNAL_CA_ROTATION_OVERLAP_HOURS, DEFAULT_INTERNAL_CA_TTL_DAYS,
+    },
+    machinery::store::{Key, Store, now_millis},
+    resources::certificate::{CertificateChainEntry, CertificateKeyAlgorithm},
 };
-use anyhow::{Result, anyhow};
+use anyhow::{Result, anyhow, bail};
 use config::CertificateAgentConfig;
 use instant_acme::{Account, NewAccount, NewOrder, Order};
+use internal::{StoredInternalCa, StoredInternalCertificate};
+use rcgen::{
+    CertificateParams, DistinguishedName, KeyPair, PKCS_ECDSA_P256_SHA256, PKCS_ECDSA_P384_SHA384,
+    PKCS_RSA_SHA256, RsaKeySize,
+};
 use serde::{Deserialize, Serialize};
-use std::{path::PathBuf, sync::Arc};
-use tokio::fs::{create_dir_all, remove_file, write};
-use x509_parser::prelude::*;
+use std::{
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::PathBuf,
+    sync::Arc,
+};
+use storage::{KeySealer, StoredCertificate};
+use tokio::{
+    fs::{create_dir_all, remove_file, rename, write},
+    sync::Mutex,
+};
+use tracing::{info, warn};
+use x509_parser::{pem::Pem, prelude::*};
+
+/// Most names ACME providers accept on a single certificate (Let's Encrypt's limit).
+pub const MAX_CERTIFICATE_DOMAINS: usize = 100;
+
+/// Lowercases and dedupes the names of a certificate, checking that wildcards only stand for the
+/// first label, eg. `*.customer.example.com`.
+pub fn normalize_certificate_domains(domains: &[String]) -> Result<Vec<String>> {
+    let mut normalized: Vec<String> = vec![];
+    for domain in domains {
+        let domain = domain.trim().trim_end_matches('.').to_ascii_lowercase();
+
+        let name = domain.strip_prefix("*.").unwrap_or(&domain);
+        if name.is_empty() || name.contains('*') || !name.contains('.') {
+            bail!(
+                "Invalid certificate domain '{}', wildcards are only allowed as the first label",
+                domain
+            );
+        }
+
+        if !normalized.contains(&domain) {
+            normalized.push(domain);
+        }
+    }
+
+    if normalized.is_empty() {
+        bail!("A certificate needs at least one domain");
+    }
+    if normalized.len() > MAX_CERTIFICATE_DOMAINS {
+        bail!(
+            "A certificate covers at most {} domains, got {}",
+            MAX_CERTIFICATE_DOMAINS,
+            normalized.len()
+        );
+    }
+
+    Ok(normalized)
+}
+
+/// Key algorithm of certificates that don't ask for one.
+pub const DEFAULT_CERTIFICATE_KEY_ALGORITHM: CertificateKeyAlgorithm =
+    CertificateKeyAlgorithm::EcdsaP256;
+
+/// Generates the private key of a certificate.
+pub fn generate_certificate_key(algorithm: &CertificateKeyAlgorithm) -> Result<KeyPair> {
+    let key = match algorithm {
+        CertificateKeyAlgorithm::EcdsaP256 => KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256)?,
+        CertificateKeyAlgorithm::EcdsaP384 => KeyPair::generate_for(&PKCS_ECDSA_P384_SHA384)?,
+        CertificateKeyAlgorithm::Rsa2048 => {
+            KeyPair::generate_rsa_for(&PKCS_RSA_SHA256, RsaKeySize::_2048)?
+        }
+        CertificateKeyAlgorithm::Rsa3072 => {
+            KeyPair::generate_rsa_for(&PKCS_RSA_SHA256, RsaKeySize::_3072)?
+        }
+        CertificateKeyAlgorithm::Rsa4096 => {
+            KeyPair::generate_rsa_for(&PKCS_RSA_SHA256, RsaKeySize::_4096)?
+        }
+    };
+
+    Ok(key)
+}
+
+/// DER encoded CSR for `domains`, to finalize an ACME order with our own key.
+pub fn certificate_signing_request(key: &KeyPair, domains: &[String]) -> Result<Vec<u8>> {
+    let mut params = CertificateParams::new(domains.to_vec())?;
+    params.distinguished_name = DistinguishedName::new();
+    let csr = params.serialize_request(key)?;
+
+    Ok(csr.der().to_vec())
+}
+
+/// What the certificate status shows about a served certificate chain.
+#[derive(Debug, Clone)]
+pub struct CertificateDetails {
+    pub issuer_name: String,
+    pub subject_alt_names: Vec<String>,
+    pub chain: Vec<CertificateChainEntry>,
+}
+
+fn asn1_time_to_rfc3339(time: ASN1Time) -> Result<String> {
+    let time = chrono::DateTime::<chrono::Utc>::from_timestamp(time.timestamp(), 0)
+        .ok_or_else(|| anyhow!("Failed to convert certificate time to DateTime"))?;
+
+    Ok(time.to_rfc3339())
+}
+
+fn subject_alt_names(cert: &X509Certificate) -> Vec<String> {
+    let Ok(Some(san)) = cert.subject_alternative_name() else {
+        return vec![];
+    };
+
+    san.value
+        .general_names
+        .iter()
+        .filter_map(|name| match name {
+            GeneralName::DNSName(name) => Some(name.to_string()),
+            GeneralName::URI(uri) => Some(uri.to_string()),
+            GeneralName::IPAddress(bytes) => match bytes.len() {
+                4 => <[u8; 4]>::try_from(*bytes)
+                    .ok()
+                    .map(|ip| IpAddr::from(Ipv4Addr::from(ip)).to_string()),
+                16 => <[u8; 16]>::try_from(*bytes)
+                    .ok()
+                    .map(|ip| IpAddr::from(Ipv6Addr::from(ip)).to_string()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct StoredAcmeAccount {
@@ -31,11 +161,19 @@ pub struct StoredAcmeChallenge {
 pub struct CertificateAgent {
     store: Arc<Store>,
     config: CertificateAgentConfig,
+    key_sealer: KeySealer,
+    /// Keeps concurrent issuances from generating two roots for the same tenant.
+    internal_ca_lock: Mutex<()>,
 }
 
 impl CertificateAgent {
     pub async fn new(store: Arc<Store>, config: CertificateAgentConfig) -> Result<Arc<Self>> {
-        Ok(Arc::new(Self { store, config }))
+        Ok(Arc::new(Self {
+            store,
+            key_sealer: KeySealer::new(&config.store_key),
+            config,
+            internal_ca_lock: Mutex::new(()),
+        }))
     }
 
     pub fn config(&self) -> &CertificateAgentConfig {
@@ -154,9 +292,10 @@ impl CertificateAgent {
             .await?
             .ok_or_else(|| anyhow!("ACME account not found for provider '{}'", provider_name))?;
 
-        let identifiers: Vec<instant_acme::Identifier> = domains
-            .iter()
-            .map(|domain| instant_acme::Identifier::Dns(domain.clone()))
+        // one order for all the names, the certificate carries them all as SANs
+        let identifiers: Vec<instant_acme::Identifier> = normalize_certificate_domains(&domains)?
+            .into_iter()
+            .map(instant_acme::Identifier::Dns)
             .collect();
 
         let new_order = NewOrder::new(&identifiers);
@@ -231,11 +370,101 @@ impl CertificateAgent {
         Ok((not_before, not_after))
     }
 
+    /// Issuer, names and chain of a PEM certificate chain, leaf first.
+    pub fn inspect_certificate_chain(&self, cert_chain_pem: &str) -> Result<CertificateDetails> {
+        let mut details: Option<CertificateDetails> = None;
+
+        for pem in Pem::iter_from_buffer(cert_chain_pem.as_bytes()) {
+            let pem = pem.map_err(|e| anyhow!("Failed to parse PEM: {:?}", e))?;
+            let cert = pem
+                .parse_x509()
+                .map_err(|e| anyhow!("Failed to parse X.509 certificate: {:?}", e))?;
+
+            let entry = CertificateChainEntry {
+                subject: cert.subject().to_string(),
+                issuer: cert.issuer().to_string(),
+                serial: cert.raw_serial_as_string(),
+                not_before: asn1_time_to_rfc3339(cert.validity().not_before)?,
+                not_after: asn1_time_to_rfc3339(cert.validity().not_after)?,
+            };
+
+            match details.as_mut() {
+                Some(details) => details.chain.push(entry),
+                None => {
+                    details = Some(CertificateDetails {
+                        issuer_name: cert.issuer().to_string(),
+                        subject_alt_names: subject_alt_names(&cert),
+                        chain: vec![entry],
+                    })
+                }
+            }
+        }
+
+        details.ok_or_else(|| anyhow!("No certificate found in the chain"))
+    }
+
+    fn certificate_key(domain: &str) -> Key<StoredCertificate> {
+        Key::<StoredCertificate>::not_namespaced()
+            .tenant(DEFAULT_AGENT_TENANT)
+            .collection(Collections::Certificate)
+            .key(domain)
+            .as_ref()
+            .into()
+    }
+
+    /// Issued certificate of `domain`, as last stored by any node.
+    pub fn get_stored_certificate(&self, domain: &str) -> Result<Option<StoredCertificate>> {
+        self.store.get(Self::certificate_key(domain))
+    }
+
+    /// Certificate chain and private key PEMs of a stored certificate.
+    pub fn open_certificate(&self, certificate: &StoredCertificate) -> Result<(String, String)> {
+        let private_key_pem = self.key_sealer.open(&certificate.sealed_private_key)?;
+
+        Ok((certificate.cert_chain_pem.clone(), private_key_pem))
+    }
+
     pub async fn store_certificate(
         &self,
         cert_chain_pem: String,
         private_key_pem: String,
         domains: Vec<String>,
+    ) -> Result<()> {
+        let sealed_private_key = self.key_sealer.seal(&private_key_pem)?;
+        for domain in domains.iter() {
+            let certificate = StoredCertificate {
+                domain: domain.clone(),
+                cert_chain_pem: cert_chain_pem.clone(),
+                sealed_private_key: sealed_private_key.clone(),
+                updated_at: now_millis(),
+            };
+            self.store
+                .put(Self::certificate_key(domain), &certificate)?;
+        }
+
+        if !self.config.disk_cache {
+            return Ok(());
+        }
+
+        // the store is the source of truth, the cache only has to be best effort
+        if let Err(e) = self
+            .write_certificate_cache(&cert_chain_pem, &private_key_pem, &domains)
+            .await
+        {
+            warn!(
+                "Failed to cache certificate for {:?} on disk: {}",
+                domains, e
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn write_certificate_cache(
+        &self,
+        cert_chain_pem: &str,
+        private_key_pem: &str,
+        domains: &[String],
     ) -> Result<()> {
         let base_dir = PathBuf::from(self.config.certs_base_dir.clone());
         if !base_dir.exists() {
@@ -246,22 +475,197 @@ impl CertificateAgent {
             let cert_path = base_dir.join(format!("{}.cert", domain));
             let key_path = base_dir.join(format!("{}.key", domain));
 
-            write(&cert_path, cert_chain_pem.as_bytes()).await?;
-            write(&key_path, private_key_pem.as_bytes()).await?;
+            // the proxy reloads certificates as they change, never let it read a partial file
+            write_atomically(&key_path, private_key_pem.as_bytes()).await?;
+            write_atomically(&cert_path, cert_chain_pem.as_bytes()).await?;
         }
 
         Ok(())
     }
 
+    fn internal_ca_key(tenant: &str) -> Key<StoredInternalCa> {
+        Key::<StoredInternalCa>::not_namespaced()
+            .tenant(tenant)
+            .collection(Collections::InternalCa)
+            .key("root")
+            .as_ref()
+            .into()
+    }
+
+    fn internal_certificate_key(
+        tenant: &str,
+        namespace: &str,
+        name: &str,
+    ) -> Key<StoredInternalCertificate> {
+        Key::<StoredInternalCertificate>::namespaced()
+            .tenant(tenant)
+            .collection(Collections::InternalCertificate)
+            .namespace(namespace)
+            .key(name)
+            .as_ref()
+            .into()
+    }
+
+    /// Whether `domain` is an internal service hostname, eg. `api.default.svc.<zone-suffix>`.
+    pub fn is_internal_domain(&self, domain: &str) -> bool {
+        domain
+            .trim_start_matches("*.")
+            .ends_with(&format!(".{}", self.config.internal_domain_suffix))
+    }
+
+    /// Root of the internal CA of the tenant, generated the first time it's asked for and
+    /// rotated once it gets close to its expiry.
+    pub async fn internal_ca(&self, tenant: &str) -> Result<StoredInternalCa> {
+        self.load_internal_ca(tenant, false).await
+    }
+
+    /// Replaces the root of the internal CA of the tenant, eg. when its key leaked. The previous
+    /// root stays trusted for a while, the internal certificates have to be issued again by then.
+    pub async fn rotate_internal_ca(&self, tenant: &str) -> Result<StoredInternalCa> {
+        self.load_internal_ca(tenant, true).await
+    }
+
+    async fn load_internal_ca(&self, tenant: &str, rotate: bool) -> Result<StoredInternalCa> {
+        let _guard = self.internal_ca_lock.lock().await;
+
+        let key = Self::internal_ca_key(tenant);
+        let ca = match self.store.get(key.clone())? {
+            Some(ca) => {
+                let rotation_time = ca.not_after.saturating_sub(
+                    DEFAULT_INTERNAL_CA_ROTATION_DAYS_BEFORE_EXPIRY * 24 * 60 * 60 * 1000,
+                );
+                if !rotate && rotation_time > now_millis() {
+                    return Ok(ca);
+                }
+
+                info!("Rotating internal CA of tenant {}", tenant);
+                internal::rotate_ca(
+                    &ca,
+                    tenant,
+                    &self.config.internal_domain_suffix,
+                    DEFAULT_INTERNAL_CA_TTL_DAYS,
+                    DEFAULT_INTERNAL_CA_ROTATION_OVERLAP_HOURS,
+                )?
+            }
+            None => {
+                info!("Generating internal CA for tenant {}", tenant);
+                internal::generate_ca(
+                    tenant,
+                    &self.config.internal_domain_suffix,
+                    DEFAULT_INTERNAL_CA_TTL_DAYS,
+                )?
+            }
+        };
+        self.store.put(key, &ca)?;
+
+        Ok(ca)
+    }
+
+    /// Issues a certificate for internal service hostnames signed by the tenant's internal CA,
+    /// replacing the previous one of the certificate resource.
+    pub async fn issue_internal_certificate(
+        &self,
+        tenant: &str,
+        namespace: &str,
+        name: &str,
+        domains: &[String],
+        ttl_hours: u32,
+        key_algorithm: &CertificateKeyAlgorithm,
+    ) -> Result<StoredInternalCertificate> {
+        let ca = self.internal_ca(tenant).await?;
+        let key = generate_certificate_key(key_algorithm)?;
+
+        let uri_san = format!(
+            "spiffe://{}/tenant/{}/ns/{}/certificate/{}",
+            self.config.internal_domain_suffix, tenant, namespace, name
+        );
+        let certificate = internal::issue_certificate(&ca, key, domains, &uri_san, ttl_hours)?;
+        self.store.put(
+            Self::internal_certificate_key(tenant, namespace, name),
+            &certificate,
+        )?;
+
+        Ok(certificate)
+    }
+
+    pub fn get_internal_certificate(
+        &self,
+        tenant: &str,
+        namespace: &str,
+        name: &str,
+    ) -> Result<Option<StoredInternalCertificate>> {
+        self.store
+            .get(Self::internal_certificate_key(tenant, namespace, name))
+    }
+
+    pub fn delete_internal_certificate(
+        &self,
+        tenant: &str,
+        namespace: &str,
+        name: &str,
+    ) -> Result<()> {
+        self.store
+            .delete(Self::internal_certificate_key(tenant, namespace, name))
+    }
+
     pub async fn delete_certificate(&self, domains: Vec<String>) -> Result<()> {
         let base_dir = PathBuf::from(self.config.certs_base_dir.clone());
         for domain in domains.iter() {
+            self.store.delete(Self::certificate_key(domain))?;
+
+            // cached or left over from before certificates were kept in the store
             let cert_path = base_dir.join(format!("{}.cert", domain));
             let key_path = base_dir.join(format!("{}.key", domain));
-            remove_file(&cert_path).await?;
-            remove_file(&key_path).await?;
+            for path in [cert_path, key_path] {
+                if let Err(e) = remove_file(&path).await {
+                    if e.kind() != ErrorKind::NotFound {
+                        return Err(e.into());
+                    }
+                }
+            }
         }
 
         Ok(())
     }
 }
+
+async fn write_atomically(path: &PathBuf, contents: &[u8]) -> Result<()> {
+    let mut partial_path = path.clone().into_os_string();
+    partial_path.push(".partial");
+    write(&partial_path, contents).await?;
+    rename(&partial_path, path).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_certificate_domains() {
+        let domains = normalize_certificate_domains(&[
+            "*.Customer.example.com".to_string(),
+            "customer.example.com.".to_string(),
+            "customer.example.com".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(
+            domains,
+            vec!["*.customer.example.com", "customer.example.com"]
+        );
+
+        assert!(normalize_certificate_domains(&["a.*.example.com".to_string()]).is_err());
+        assert!(normalize_certificate_domains(&["*.com".to_string()]).is_err());
+        assert!(normalize_certificate_domains(&[]).is_err());
+    }
+
+    #[test]
+    fn test_generate_certificate_key() {
+        let key = generate_certificate_key(&CertificateKeyAlgorithm::EcdsaP384).unwrap();
+        assert_eq!(key.algorithm(), &PKCS_ECDSA_P384_SHA384);
+
+        let csr = certificate_signing_request(&key, &["example.com".to_string()]).unwrap();
+        assert!(!csr.is_empty());
+    }
+}
@@ -2,12 +2,23 @@ pub enum Collections {
     ServiceIpReservation,
     VmIpReservation,
     Volume,
+    VolumeQuota,
     Image,
     ImageLayer,
     AcmeAccount,
     AcmeChallenge,
     TrackedResourceOwner,
     TcpPortAllocation,
+    BuilderAllocation,
+    BuilderRevocation,
+    BandwidthUsage,
+    DnsTxtRecord,
+    DnsZoneRecord,
+    DnssecKey,
+    InternalCa,
+    InternalCertificate,
+    Certificate,
+    Event,
 }
 
 impl AsRef<str> for Collections {
@@ -16,12 +27,23 @@ impl AsRef<str> for Collections {
             Collections::ServiceIpReservation => "service_ip_reservations",
             Collections::VmIpReservation => "vm_ip_reservations",
             Collections::Volume => "volumes",
+            Collections::VolumeQuota => "volume_quotas",
             Collections::Image => "images",
             Collections::ImageLayer => "image_layers",
             Collections::AcmeAccount => "acme_accounts",
             Collections::AcmeChallenge => "acme_challenges",
             Collections::TrackedResourceOwner => "tracked_resource_owners",
             Collections::TcpPortAllocation => "tcp_port_allocations",
+            Collections::BuilderAllocation => "builder_allocations",
+            Collections::BuilderRevocation => "builder_revocations",
+            Collections::BandwidthUsage => "bandwidth_usage",
+            Collections::DnsTxtRecord => "dns_txt_records",
+            Collections::DnsZoneRecord => "dns_zone_records",
+            Collections::DnssecKey => "dnssec_keys",
+            Collections::InternalCa => "internal_cas",
+            Collections::InternalCertificate => "internal_certificates",
+            Collections::Certificate => "certificates",
+            Collections::Event => "events",
         }
     }
 }
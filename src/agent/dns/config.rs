@@ -1,11 +1,170 @@
+use std::{fmt, net::SocketAddr};
+
+use anyhow::{Result, anyhow, bail};
+use hickory_resolver::config::{NameServerConfig, Protocol, ResolverConfig};
+
 #[derive(Debug, Clone)]
 pub struct DnsAgentConfig {
     /// DNS zone suffix (e.g., "lttle.local")
     pub zone_suffix: String,
     /// TTL for DNS records in seconds
     pub default_ttl: u32,
-    /// Upstream DNS servers for passthrough (e.g., ["8.8.8.8:53", "8.8.4.4:53"])
-    pub upstream_dns_servers: Vec<String>,
+    /// Upstream DNS servers for passthrough, tried in order
+    pub upstream_dns_servers: Vec<DnsUpstream>,
     /// Region root domain (e.g., "eu.lttle.host")
     pub region_root_domain: String,
+    /// Public address answering for the region root domain when it's delegated to this node,
+    /// needed for dns-01 ACME challenges (e.g., "203.0.113.10")
+    pub zone_bind_address: Option<String>,
+    /// Answer the hosts of the proxy's external bindings with the service gateway to machines,
+    /// and with the ingress address to everyone else
+    pub split_horizon: bool,
+    /// Public address of the proxy, defaults to its external bind address
+    pub ingress_address: Option<String>,
+    /// Log every query with its answer, to debug resolution issues
+    pub log_queries: bool,
+    /// Sign the answers of the zone server with DNSSEC, the key is generated on first start
+    pub dnssec: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsUpstreamProtocol {
+    Udp,
+    /// DNS over TLS
+    Tls,
+    /// DNS over HTTPS
+    Https,
+}
+
+/// Upstream DNS server, written as `8.8.8.8:53`, `tls://1.1.1.1#cloudflare-dns.com` or
+/// `https://1.1.1.1#cloudflare-dns.com`. The name after `#` is the one the server certificate is
+/// verified against, the port defaults to 53, 853 and 443 respectively.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsUpstream {
+    pub address: SocketAddr,
+    pub protocol: DnsUpstreamProtocol,
+    pub tls_name: Option<String>,
+}
+
+impl DnsUpstream {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let spec = spec.trim();
+        let (protocol, rest) = if let Some(rest) = spec.strip_prefix("tls://") {
+            (DnsUpstreamProtocol::Tls, rest)
+        } else if let Some(rest) = spec.strip_prefix("https://") {
+            (DnsUpstreamProtocol::Https, rest)
+        } else if let Some(rest) = spec.strip_prefix("udp://") {
+            (DnsUpstreamProtocol::Udp, rest)
+        } else {
+            (DnsUpstreamProtocol::Udp, spec)
+        };
+
+        let (address, tls_name) = match rest.split_once('#') {
+            Some((address, tls_name)) if !tls_name.trim().is_empty() => {
+                (address, Some(tls_name.trim().to_string()))
+            }
+            Some(_) => bail!("Missing TLS name in upstream DNS server: {}", spec),
+            None => (rest, None),
+        };
+
+        if protocol == DnsUpstreamProtocol::Udp && tls_name.is_some() {
+            bail!("TLS name set for plain upstream DNS server: {}", spec);
+        }
+        if protocol != DnsUpstreamProtocol::Udp && tls_name.is_none() {
+            bail!(
+                "Upstream DNS server {} needs the name of its certificate, eg. {}#dns.example.com",
+                spec,
+                spec
+            );
+        }
+
+        let address = match address.parse::<SocketAddr>() {
+            Ok(address) => address,
+            Err(_) => {
+                let ip = address
+                    .trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid upstream DNS server address: {}", spec))?;
+                SocketAddr::new(ip, protocol.default_port())
+            }
+        };
+
+        Ok(Self {
+            address,
+            protocol,
+            tls_name,
+        })
+    }
+
+    fn name_server_config(&self) -> NameServerConfig {
+        let protocol = match self.protocol {
+            DnsUpstreamProtocol::Udp => Protocol::Udp,
+            DnsUpstreamProtocol::Tls => Protocol::Tls,
+            DnsUpstreamProtocol::Https => Protocol::Https,
+        };
+
+        let mut config = NameServerConfig::new(self.address, protocol);
+        config.tls_dns_name = self.tls_name.clone();
+        config
+    }
+}
+
+impl DnsUpstreamProtocol {
+    fn default_port(&self) -> u16 {
+        match self {
+            DnsUpstreamProtocol::Udp => 53,
+            DnsUpstreamProtocol::Tls => 853,
+            DnsUpstreamProtocol::Https => 443,
+        }
+    }
+}
+
+impl fmt::Display for DnsUpstream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.protocol, &self.tls_name) {
+            (DnsUpstreamProtocol::Tls, Some(name)) => write!(f, "tls://{}#{}", self.address, name),
+            (DnsUpstreamProtocol::Https, Some(name)) => {
+                write!(f, "https://{}#{}", self.address, name)
+            }
+            _ => write!(f, "{}", self.address),
+        }
+    }
+}
+
+/// Resolver config forwarding to `upstreams`, in the order they are listed.
+pub fn upstream_resolver_config(upstreams: &[DnsUpstream]) -> ResolverConfig {
+    let mut resolver_config = ResolverConfig::new();
+    for upstream in upstreams {
+        resolver_config.add_name_server(upstream.name_server_config());
+    }
+
+    resolver_config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_upstream() {
+        let upstream = DnsUpstream::parse("8.8.8.8:53").unwrap();
+        assert_eq!(upstream.protocol, DnsUpstreamProtocol::Udp);
+        assert_eq!(upstream.address, "8.8.8.8:53".parse().unwrap());
+
+        let upstream = DnsUpstream::parse("tls://1.1.1.1#cloudflare-dns.com").unwrap();
+        assert_eq!(upstream.protocol, DnsUpstreamProtocol::Tls);
+        assert_eq!(upstream.address, "1.1.1.1:853".parse().unwrap());
+        assert_eq!(upstream.tls_name.as_deref(), Some("cloudflare-dns.com"));
+
+        let upstream =
+            DnsUpstream::parse("https://[2606:4700:4700::1111]#cloudflare-dns.com").unwrap();
+        assert_eq!(
+            upstream.address,
+            "[2606:4700:4700::1111]:443".parse().unwrap()
+        );
+
+        assert!(DnsUpstream::parse("tls://1.1.1.1").is_err());
+        assert!(DnsUpstream::parse("8.8.8.8#dns.google").is_err());
+    }
 }
@@ -0,0 +1,335 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Result, anyhow};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use hickory_proto::{
+    rr::{Name, RData, Record, RecordType, rdata::NULL},
+    serialize::binary::{BinEncodable, BinEncoder},
+};
+use ring::{
+    digest::{SHA256, digest},
+    rand::SystemRandom,
+    signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    agent::data::Collections,
+    constants::DEFAULT_AGENT_TENANT,
+    machinery::store::{Key, Store},
+    resources::dns_record::normalize_dns_name,
+};
+
+/// ECDSA P-256 with SHA-256, small signatures keep signed answers well within a UDP datagram.
+const ALGORITHM_ECDSAP256SHA256: u8 = 13;
+const DIGEST_TYPE_SHA256: u8 = 2;
+/// Zone key with the secure entry point bit, a single key signs the whole zone.
+const DNSKEY_FLAGS: u16 = 257;
+const DNSKEY_PROTOCOL: u8 = 3;
+const DNS_CLASS_IN: u16 = 1;
+/// Pseudo type marking a name that doesn't exist in compact denial answers (RFC 9824).
+const TYPE_NXNAME: u16 = 128;
+
+/// Signatures are backdated to tolerate resolvers with a slow clock.
+const SIGNATURE_BACKDATE: Duration = Duration::from_secs(60 * 60);
+const SIGNATURE_VALIDITY: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Signing key of a zone, generated the first time the zone is signed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredDnssecKey {
+    pub zone: String,
+    /// Base64 of the PKCS#8 document of the ECDSA P-256 key.
+    pub pkcs8: String,
+    /// Unix millis
+    pub created_at: u64,
+}
+
+fn dnssec_key_key(zone: &str) -> Key<StoredDnssecKey> {
+    Key::<StoredDnssecKey>::not_namespaced()
+        .tenant(DEFAULT_AGENT_TENANT)
+        .collection(Collections::DnssecKey)
+        .key(normalize_dns_name(zone))
+        .as_ref()
+        .into()
+}
+
+/// Signs the answers of a zone online, as they are served.
+pub struct ZoneSigner {
+    zone: Name,
+    key_pair: EcdsaKeyPair,
+    rng: SystemRandom,
+    dnskey: Vec<u8>,
+    key_tag: u16,
+}
+
+impl ZoneSigner {
+    pub fn load_or_generate(store: &Store, zone: &str) -> Result<Self> {
+        let key = dnssec_key_key(zone);
+        let stored = match store.get(key.clone())? {
+            Some(stored) => stored,
+            None => {
+                let rng = SystemRandom::new();
+                let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+                    .map_err(|_| anyhow!("Failed to generate DNSSEC key for {}", zone))?;
+
+                let stored = StoredDnssecKey {
+                    zone: normalize_dns_name(zone),
+                    pkcs8: BASE64_STANDARD.encode(pkcs8.as_ref()),
+                    created_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64,
+                };
+                store.put(key, &stored)?;
+                stored
+            }
+        };
+
+        Self::from_pkcs8(zone, &BASE64_STANDARD.decode(&stored.pkcs8)?)
+    }
+
+    fn from_pkcs8(zone: &str, pkcs8: &[u8]) -> Result<Self> {
+        let rng = SystemRandom::new();
+        let key_pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8, &rng)
+            .map_err(|e| anyhow!("Invalid DNSSEC key for {}: {}", zone, e))?;
+
+        // the public key is the uncompressed point, DNSKEY keeps it without the 0x04 prefix
+        let mut dnskey = DNSKEY_FLAGS.to_be_bytes().to_vec();
+        dnskey.push(DNSKEY_PROTOCOL);
+        dnskey.push(ALGORITHM_ECDSAP256SHA256);
+        dnskey.extend_from_slice(&key_pair.public_key().as_ref()[1..]);
+
+        Ok(Self {
+            zone: Name::from_ascii(format!("{}.", normalize_dns_name(zone)))?,
+            key_tag: key_tag(&dnskey),
+            key_pair,
+            rng,
+            dnskey,
+        })
+    }
+
+    pub fn key_tag(&self) -> u16 {
+        self.key_tag
+    }
+
+    pub fn dnskey_record(&self, ttl: u32) -> Record {
+        raw_record(
+            self.zone.clone(),
+            ttl,
+            RecordType::DNSKEY,
+            self.dnskey.clone(),
+        )
+    }
+
+    /// DS record to publish at the parent zone, in presentation format.
+    pub fn ds_record(&self) -> String {
+        let mut data = encode_name(&self.zone);
+        data.extend_from_slice(&self.dnskey);
+        let digest = digest(&SHA256, &data)
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<String>();
+
+        format!(
+            "{} IN DS {} {} {} {}",
+            self.zone, self.key_tag, ALGORITHM_ECDSAP256SHA256, DIGEST_TYPE_SHA256, digest
+        )
+    }
+
+    /// RRSIG covering `records`, which share their name, type and TTL.
+    pub fn sign_rrset(&self, records: &[Record]) -> Result<Record> {
+        let first = records
+            .first()
+            .ok_or_else(|| anyhow!("Can't sign an empty record set"))?;
+        let name = first.name().clone();
+        let record_type = first.record_type();
+        let ttl = first.ttl();
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let inception = (now - SIGNATURE_BACKDATE).as_secs() as u32;
+        let expiration = (now + SIGNATURE_VALIDITY).as_secs() as u32;
+
+        let labels = name.iter().filter(|label| *label != b"*").count() as u8;
+
+        let mut rrsig = u16::from(record_type).to_be_bytes().to_vec();
+        rrsig.push(ALGORITHM_ECDSAP256SHA256);
+        rrsig.push(labels);
+        rrsig.extend_from_slice(&ttl.to_be_bytes());
+        rrsig.extend_from_slice(&expiration.to_be_bytes());
+        rrsig.extend_from_slice(&inception.to_be_bytes());
+        rrsig.extend_from_slice(&self.key_tag.to_be_bytes());
+        rrsig.extend(encode_name(&self.zone));
+
+        // RFC 4034 3.1.8.1: the RRSIG fields then every record in canonical form and order
+        let owner = encode_name(&name);
+        let mut rdatas = records
+            .iter()
+            .filter_map(|record| record.data())
+            .map(encode_rdata)
+            .collect::<Result<Vec<_>>>()?;
+        rdatas.sort();
+        rdatas.dedup();
+
+        let mut signed_data = rrsig.clone();
+        for rdata in rdatas {
+            signed_data.extend_from_slice(&owner);
+            signed_data.extend_from_slice(&u16::from(record_type).to_be_bytes());
+            signed_data.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+            signed_data.extend_from_slice(&ttl.to_be_bytes());
+            signed_data.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            signed_data.extend_from_slice(&rdata);
+        }
+
+        let signature = self
+            .key_pair
+            .sign(&self.rng, &signed_data)
+            .map_err(|_| anyhow!("Failed to sign {} {}", name, record_type))?;
+        rrsig.extend_from_slice(signature.as_ref());
+
+        Ok(raw_record(name, ttl, RecordType::RRSIG, rrsig))
+    }
+
+    /// NSEC denying every type of `name` but `types` (RFC 9824 compact denial), its next name is
+    /// the immediate successor so it proves nothing about other names. Without `types`, the name
+    /// doesn't exist.
+    pub fn denial_record(&self, name: &Name, types: &[RecordType], ttl: u32) -> Result<Record> {
+        let next = Name::from_labels(std::iter::once(&b"\0"[..]).chain(name.iter()))?;
+
+        let mut bitmap_types = types.iter().map(|t| u16::from(*t)).collect::<Vec<_>>();
+        bitmap_types.push(u16::from(RecordType::RRSIG));
+        bitmap_types.push(u16::from(RecordType::NSEC));
+        if types.is_empty() {
+            bitmap_types.push(TYPE_NXNAME);
+        }
+
+        let mut nsec = encode_name(&next);
+        nsec.extend(type_bitmap(&bitmap_types));
+
+        Ok(raw_record(name.clone(), ttl, RecordType::NSEC, nsec))
+    }
+}
+
+fn raw_record(name: Name, ttl: u32, record_type: RecordType, data: Vec<u8>) -> Record {
+    Record::from_rdata(
+        name,
+        ttl,
+        RData::Unknown {
+            code: record_type,
+            rdata: NULL::with(data),
+        },
+    )
+}
+
+/// Uncompressed, lowercased wire form of `name`.
+fn encode_name(name: &Name) -> Vec<u8> {
+    let mut out = vec![];
+    for label in name.iter() {
+        out.push(label.len() as u8);
+        out.extend(label.iter().map(|byte| byte.to_ascii_lowercase()));
+    }
+    out.push(0);
+    out
+}
+
+fn encode_rdata(rdata: &RData) -> Result<Vec<u8>> {
+    let mut out = vec![];
+    let mut encoder = BinEncoder::new(&mut out);
+    encoder.set_canonical_names(true);
+    rdata.emit(&mut encoder)?;
+    Ok(out)
+}
+
+/// RFC 4034 appendix B.
+fn key_tag(dnskey: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    for (i, byte) in dnskey.iter().enumerate() {
+        sum += if i % 2 == 0 {
+            (*byte as u32) << 8
+        } else {
+            *byte as u32
+        };
+    }
+    sum += (sum >> 16) & 0xffff;
+    (sum & 0xffff) as u16
+}
+
+/// RFC 4034 4.1.2: types grouped in windows of 256, each a bitmap trimmed after its last type.
+fn type_bitmap(types: &[u16]) -> Vec<u8> {
+    let mut types = types.to_vec();
+    types.sort();
+    types.dedup();
+
+    let mut out = vec![];
+    let mut window = 0u8;
+    let mut bitmap = Vec::<u8>::new();
+    for record_type in types {
+        let (type_window, offset) = ((record_type >> 8) as u8, (record_type & 0xff) as usize);
+        if type_window != window && !bitmap.is_empty() {
+            out.push(window);
+            out.push(bitmap.len() as u8);
+            out.append(&mut bitmap);
+        }
+        window = type_window;
+
+        if bitmap.len() <= offset / 8 {
+            bitmap.resize(offset / 8 + 1, 0);
+        }
+        bitmap[offset / 8] |= 0x80 >> (offset % 8);
+    }
+    if !bitmap.is_empty() {
+        out.push(window);
+        out.push(bitmap.len() as u8);
+        out.append(&mut bitmap);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::rdata::A;
+    use ring::signature::{ECDSA_P256_SHA256_FIXED, UnparsedPublicKey};
+
+    #[test]
+    fn test_type_bitmap() {
+        // RFC 4034 4.3: A MX RRSIG NSEC TYPE1234
+        let bitmap = type_bitmap(&[1, 15, 46, 47, 1234]);
+
+        let mut expected = vec![0x00, 0x06, 0x40, 0x01, 0x00, 0x00, 0x00, 0x03];
+        expected.extend([0x04, 0x1b]);
+        expected.extend([0u8; 26]);
+        expected.push(0x20);
+        assert_eq!(bitmap, expected);
+    }
+
+    #[test]
+    fn test_sign_rrset() {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let signer = ZoneSigner::from_pkcs8("eu.example.com", pkcs8.as_ref()).unwrap();
+
+        let name = Name::from_ascii("www-acme.eu.example.com.").unwrap();
+        let record = Record::from_rdata(
+            name.clone(),
+            300,
+            RData::A(A("203.0.113.10".parse().unwrap())),
+        );
+        let rrsig = signer.sign_rrset(&[record]).unwrap();
+        assert_eq!(rrsig.record_type(), RecordType::RRSIG);
+
+        let Some(RData::Unknown { rdata, .. }) = rrsig.data() else {
+            panic!("RRSIG should carry raw data");
+        };
+        let rrsig = rdata.anything().to_vec();
+        let (fields, signature) = rrsig.split_at(rrsig.len() - 64);
+
+        let mut signed_data = fields.to_vec();
+        signed_data.extend(encode_name(&name));
+        signed_data.extend([0, 1, 0, 1, 0, 0, 1, 44, 0, 4, 203, 0, 113, 10]);
+
+        let public_key = UnparsedPublicKey::new(
+            &ECDSA_P256_SHA256_FIXED,
+            [&[0x04], &signer.dnskey[4..]].concat(),
+        );
+        assert!(public_key.verify(&signed_data, signature).is_ok());
+    }
+}
@@ -1,5 +1,5 @@
-use std::net::{Ipv4Addr, SocketAddr};
-use std::time::Duration;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::{Duration, Instant};
 
 use hickory_proto::{
     op::{MessageType, OpCode, ResponseCode},
@@ -7,17 +7,31 @@ use hickory_proto::{
 };
 use hickory_resolver::{
     TokioAsyncResolver,
-    config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts},
+    config::{ResolverOpts, ServerOrderingStrategy},
+    error::ResolveErrorKind,
 };
 use hickory_server::{
     authority::MessageResponseBuilder,
     server::{Request, RequestHandler, ResponseHandler, ResponseInfo},
 };
-use tracing::{debug, warn};
-
-use crate::resources::metadata::{Metadata, Namespace};
+use tracing::{debug, info, warn};
+
+use crate::{
+    controller::health_probe::failing_health_probes,
+    repository::Repository,
+    resources::{
+        Convert,
+        machine::MachinePhase,
+        metadata::{Metadata, Namespace},
+        service::ServiceLatest,
+    },
+};
 
-use super::DnsHandler;
+use super::{
+    DnsHandler,
+    config::{DnsUpstream, upstream_resolver_config},
+    metrics::DnsQueryZone,
+};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 enum DnsSubdomain {
@@ -25,7 +39,65 @@ enum DnsSubdomain {
     Host,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ServiceAnswer {
+    Address(String),
+    /// The service exists but none of its machines can answer, its record is withheld.
+    Unavailable,
+    NotFound,
+}
+
+/// Whether a machine in `phase` can take connections, possibly after being woken up.
+fn machine_phase_available(phase: &MachinePhase) -> bool {
+    !matches!(
+        phase,
+        MachinePhase::Stopping | MachinePhase::Stopped | MachinePhase::Error { .. }
+    )
+}
+
+/// Whether a service target, a machine or the replicas of a machine group, is running or can be
+/// woken up and isn't failing its health probes. A group is available as long as one of its
+/// replicas is.
+fn target_available(
+    repository: &Repository,
+    tenant: &str,
+    name: String,
+    namespace: Namespace,
+) -> bool {
+    let machine_repo = repository.machine(tenant);
+    let machine_available =
+        |name: String| match machine_repo.get_status(Metadata::new(name, namespace.clone())) {
+            Ok(Some(status)) => {
+                Some(machine_phase_available(&status.phase) && !failing_health_probes(&status))
+            }
+            _ => None,
+        };
+
+    if let Some(available) = machine_available(name.clone()) {
+        return available;
+    }
+
+    match repository
+        .machine_group(tenant)
+        .get_status(Metadata::new(name, namespace.clone()))
+    {
+        Ok(Some(group)) => group
+            .machines
+            .into_iter()
+            .any(|replica| machine_available(replica) == Some(true)),
+        _ => false,
+    }
+}
+
 impl DnsHandler {
+    fn query_zone(&self, address: &str) -> DnsQueryZone {
+        if address.ends_with(&format!(".{}.", self.zone_suffix)) {
+            DnsQueryZone::Internal
+        } else {
+            DnsQueryZone::External
+        }
+    }
+
     fn parse_subdomain(&self, address: &str) -> Option<DnsSubdomain> {
         if address == format!("host.{}.", self.zone_suffix) {
             return Some(DnsSubdomain::Host);
@@ -67,47 +139,71 @@ impl DnsHandler {
     }
 
     pub(super) fn create_upstream_resolver(
-        upstream_dns_servers: &[String],
+        upstream_dns_servers: &[DnsUpstream],
     ) -> Option<TokioAsyncResolver> {
         if upstream_dns_servers.is_empty() {
             return None;
         }
 
-        let mut resolver_config = ResolverConfig::new();
-
-        for server in upstream_dns_servers {
-            if let Ok(addr) = server.parse::<SocketAddr>() {
-                resolver_config.add_name_server(NameServerConfig {
-                    socket_addr: addr,
-                    protocol: Protocol::Udp,
-                    tls_dns_name: None,
-                    trust_negative_responses: true,
-                    bind_addr: None,
-                });
-            }
-        }
+        let resolver_config = upstream_resolver_config(upstream_dns_servers);
 
         let mut opts = ResolverOpts::default();
         opts.timeout = Duration::from_secs(2);
         opts.attempts = 2;
+        // fail over in the configured order, one server at a time
+        opts.server_ordering_strategy = ServerOrderingStrategy::UserProvidedOrder;
+        opts.num_concurrent_reqs = 1;
 
         Some(TokioAsyncResolver::tokio(resolver_config, opts))
     }
 
-    async fn resolve_service(&self, name: &str, namespace: &str, tenant: &str) -> Option<String> {
+    async fn resolve_service(&self, name: &str, namespace: &str, tenant: &str) -> ServiceAnswer {
         // Look up service in repository
         let service_repo = self.repository.service(tenant.to_string());
 
         let metadata = Metadata::new(name.to_string(), Namespace::specified(namespace));
 
-        if let Ok(Some((_, status))) = service_repo.get_with_status(metadata) {
-            return status.service_ip;
+        let Ok(Some((service, status))) = service_repo.get_with_status(metadata) else {
+            return ServiceAnswer::NotFound;
+        };
+
+        // clients would be handed an address nothing answers on, let them fail fast instead
+        if !self.has_available_target(&service.latest(), tenant) {
+            debug!(
+                "Withholding service {}.{}, none of its machines is available",
+                name, namespace
+            );
+            return ServiceAnswer::Unavailable;
         }
 
-        None
+        match status.service_ip {
+            Some(service_ip) => ServiceAnswer::Address(service_ip),
+            None => ServiceAnswer::NotFound,
+        }
     }
 
-    async fn handle_query(&self, request: &Request) -> Vec<Record> {
+    /// Whether the target of `service`, one of its backends or its canary is running or can be
+    /// woken up, going by the phase in the machine statuses.
+    fn has_available_target(&self, service: &ServiceLatest, tenant: &str) -> bool {
+        let mut targets = vec![(
+            service.target.name.clone(),
+            service.target.namespace.clone(),
+        )];
+        for backend in service.target.backends.iter().flatten() {
+            targets.push((backend.name.clone(), backend.namespace.clone()));
+        }
+        if let Some(canary) = &service.target.canary {
+            targets.push((canary.name.clone(), canary.namespace.clone()));
+        }
+
+        targets.into_iter().any(|(name, namespace)| {
+            let namespace =
+                Namespace::from_value_or_default(namespace.or(service.namespace.clone()));
+            target_available(&self.repository, tenant, name, namespace)
+        })
+    }
+
+    async fn handle_query(&self, request: &Request) -> (ResponseCode, Vec<Record>) {
         let query = request.query();
         let name = query.name();
         let record_type = query.query_type();
@@ -117,7 +213,7 @@ impl DnsHandler {
             std::net::SocketAddr::V4(addr) => addr.ip().to_string(),
             std::net::SocketAddr::V6(addr) => {
                 debug!("IPv6 source address not supported: {}", addr);
-                return vec![];
+                return (ResponseCode::NoError, vec![]);
             }
         };
 
@@ -140,7 +236,7 @@ impl DnsHandler {
 
         // Only handle A record queries for now
         if record_type != RecordType::A {
-            return vec![];
+            return (ResponseCode::NoError, vec![]);
         }
 
         // Parse the query name
@@ -155,34 +251,75 @@ impl DnsHandler {
                         namespace,
                     } => {
                         // Service query: <service>.<namespace>.svc.<zone_suffix>
-                        if let Some(service_ip) =
-                            self.resolve_service(&resource_name, &namespace, t).await
-                        {
-                            if let Ok(ip) = service_ip.parse::<Ipv4Addr>() {
-                                return vec![Record::from_rdata(
-                                    name.clone().into(),
-                                    self.default_ttl,
-                                    RData::A(A(ip)),
-                                )];
+                        match self.resolve_service(&resource_name, &namespace, t).await {
+                            ServiceAnswer::Address(service_ip) => {
+                                if let Ok(ip) = service_ip.parse::<Ipv4Addr>() {
+                                    return (
+                                        ResponseCode::NoError,
+                                        vec![Record::from_rdata(
+                                            name.clone().into(),
+                                            self.default_ttl,
+                                            RData::A(A(ip)),
+                                        )],
+                                    );
+                                }
                             }
+                            // the name exists, it just has no address for now
+                            ServiceAnswer::Unavailable => return (ResponseCode::NoError, vec![]),
+                            ServiceAnswer::NotFound => {}
                         }
                     }
                     DnsSubdomain::Host => {
-                        return vec![Record::from_rdata(
+                        return (
+                            ResponseCode::NoError,
+                            vec![Record::from_rdata(
+                                name.clone().into(),
+                                self.default_ttl,
+                                RData::A(A(self.net_agent.service_gateway())),
+                            )],
+                        );
+                    }
+                }
+            }
+        }
+
+        // hosts of external bindings: machines reach the proxy on the service gateway, so traffic
+        // between them never goes through the public address
+        if let Some(proxy) = &self.proxy {
+            if proxy.serves_host(&name_str) {
+                let address = match tenant {
+                    Some(_) => Some(IpAddr::V4(self.net_agent.service_gateway())),
+                    None => self.ingress_address,
+                };
+
+                if let Some(IpAddr::V4(ip)) = address {
+                    return (
+                        ResponseCode::NoError,
+                        vec![Record::from_rdata(
                             name.clone().into(),
                             self.default_ttl,
-                            RData::A(A(self.net_agent.service_gateway())),
-                        )];
-                    }
+                            RData::A(A(ip)),
+                        )],
+                    );
                 }
             }
         }
 
+        // names in the zone are never forwarded, nothing upstream knows them
+        if self.query_zone(&name_str) == DnsQueryZone::Internal {
+            return (ResponseCode::NXDomain, vec![]);
+        }
+
         // If not an internal service, try upstream DNS resolution
         if let Some(ref resolver) = self.upstream_resolver {
             debug!("Forwarding query to upstream DNS: {}", name_str);
 
-            match resolver.lookup_ip(name_str.trim_end_matches('.')).await {
+            let started_at = Instant::now();
+            let lookup = resolver.lookup_ip(name_str.trim_end_matches('.')).await;
+            self.metrics
+                .record_forward(started_at.elapsed(), lookup.is_err());
+
+            match lookup {
                 Ok(lookup) => {
                     let records: Vec<Record> = lookup
                         .iter()
@@ -198,19 +335,37 @@ impl DnsHandler {
 
                     if !records.is_empty() {
                         debug!("Upstream DNS returned {} records", records.len());
-                        return records;
+                        return (ResponseCode::NoError, records);
                     }
                 }
                 Err(e) => {
                     debug!("Upstream DNS lookup failed: {}", e);
+
+                    if let ResolveErrorKind::NoRecordsFound { response_code, .. } = e.kind() {
+                        return (*response_code, vec![]);
+                    }
+                    return (ResponseCode::ServFail, vec![]);
                 }
             }
         }
 
-        vec![]
+        (ResponseCode::NoError, vec![])
     }
 }
 
+/// Query log line, enabled with `log_queries` to debug resolution issues.
+pub(super) fn log_query(request: &Request, response_code: ResponseCode, answers: usize) {
+    let query = request.query();
+    info!(
+        "DNS query from {}: {} {:?} -> {} ({} answers)",
+        request.src(),
+        query.name(),
+        query.query_type(),
+        response_code,
+        answers
+    );
+}
+
 #[async_trait::async_trait]
 impl RequestHandler for DnsHandler {
     async fn handle_request<R: ResponseHandler>(
@@ -221,10 +376,16 @@ impl RequestHandler for DnsHandler {
         let response = MessageResponseBuilder::from_message_request(request);
 
         if request.message_type() == MessageType::Query && request.op_code() == OpCode::Query {
-            let answers = self.handle_query(request).await;
+            let (response_code, answers) = self.handle_query(request).await;
+
+            let zone = self.query_zone(&request.query().name().to_string());
+            self.metrics.record_query(zone, response_code);
+            if self.log_queries {
+                log_query(request, response_code, answers.len());
+            }
 
             if answers.is_empty() {
-                let response_message = response.error_msg(request.header(), ResponseCode::NoError);
+                let response_message = response.error_msg(request.header(), response_code);
                 response_handle
                     .send_response(response_message)
                     .await
@@ -257,3 +418,80 @@ impl RequestHandler for DnsHandler {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Weak};
+
+    use super::*;
+    use crate::{
+        machinery::store::Store,
+        resources::{machine::MachineStatus, machine_group::MachineGroupStatus},
+    };
+
+    async fn set_machine_phase(repository: &Repository, name: &str, phase: MachinePhase) {
+        let mut status: MachineStatus = serde_json::from_value(
+            serde_json::json!({ "hash": 0, "phase": "idle", "conditions": [] }),
+        )
+        .unwrap();
+        status.phase = phase;
+
+        repository
+            .machine("tenant")
+            .set_status(Metadata::new(name, Namespace::Default), status)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_target_available_for_machine_group() {
+        let store_dir = tempfile::tempdir().unwrap();
+        let store = Store::new(store_dir.path()).await.unwrap();
+        let repository = Repository::new(Arc::new(store), Weak::new());
+
+        repository
+            .machine_group("tenant")
+            .set_status(
+                Metadata::new("web", Namespace::Default),
+                MachineGroupStatus {
+                    hash: 0,
+                    replicas: 2,
+                    machines: vec!["web-a".to_string(), "web-b".to_string()],
+                    draining: vec![],
+                    request_count: None,
+                    sampled_at: None,
+                    observed_rps: None,
+                    observed_cpu: None,
+                    low_load_since: None,
+                    last_scaled_at: None,
+                    conditions: vec![],
+                },
+            )
+            .await
+            .unwrap();
+        set_machine_phase(&repository, "web-a", MachinePhase::Stopped).await;
+        set_machine_phase(&repository, "web-b", MachinePhase::Stopped).await;
+
+        let available = |name: &str| {
+            target_available(&repository, "tenant", name.to_string(), Namespace::Default)
+        };
+        assert!(!available("web"));
+
+        set_machine_phase(&repository, "web-b", MachinePhase::Ready).await;
+        assert!(available("web"));
+        assert!(!available("web-a"));
+        assert!(available("web-b"));
+        assert!(!available("missing"));
+
+        // a ready machine failing its probes is as good as gone
+        repository
+            .machine("tenant")
+            .patch_status(Metadata::new("web-b", Namespace::Default), |status| {
+                status.health_probe_failures = Some(3);
+            })
+            .await
+            .unwrap();
+        assert!(!available("web-b"));
+        assert!(!available("web"));
+    }
+}
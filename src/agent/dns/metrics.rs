@@ -0,0 +1,174 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use hickory_proto::op::ResponseCode;
+
+use crate::agent::proxy::metrics::{render_header, render_value};
+
+/// Upper bounds (in seconds) of the upstream forward latency histogram buckets.
+const FORWARD_LATENCY_BUCKETS: [f64; 10] =
+    [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 2.0];
+
+/// Zone a query was answered for, the label queries are counted by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DnsQueryZone {
+    /// Service and host names under the zone suffix, answered to machines.
+    Internal,
+    /// Anything else asked by machines, forwarded upstream.
+    External,
+    /// Public queries for the region root domain.
+    Region,
+}
+
+impl DnsQueryZone {
+    fn label(&self) -> &'static str {
+        match self {
+            DnsQueryZone::Internal => "internal",
+            DnsQueryZone::External => "external",
+            DnsQueryZone::Region => "region",
+        }
+    }
+}
+
+/// DNS server metrics, rendered in the Prometheus text format next to the proxy ones. NXDOMAIN
+/// rates are derived at query time from the `rcode` label.
+#[derive(Debug, Default)]
+pub struct DnsMetrics {
+    queries: Mutex<BTreeMap<(DnsQueryZone, String), u64>>,
+    forward_errors: AtomicU64,
+    forward_latency_buckets: [AtomicU64; FORWARD_LATENCY_BUCKETS.len()],
+    forward_latency_sum_us: AtomicU64,
+    forward_latency_count: AtomicU64,
+}
+
+impl DnsMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_query(&self, zone: DnsQueryZone, response_code: ResponseCode) {
+        let rcode = format!("{:?}", response_code).to_ascii_lowercase();
+        *self
+            .queries
+            .lock()
+            .expect("dns metrics poisoned")
+            .entry((zone, rcode))
+            .or_default() += 1;
+    }
+
+    /// A lookup forwarded to the upstream servers, failed lookups included.
+    pub fn record_forward(&self, duration: Duration, failed: bool) {
+        if failed {
+            self.forward_errors.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let seconds = duration.as_secs_f64();
+        if let Some(index) = FORWARD_LATENCY_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+        {
+            self.forward_latency_buckets[index].fetch_add(1, Ordering::Relaxed);
+        }
+        self.forward_latency_sum_us
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+        self.forward_latency_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        render_header(
+            &mut out,
+            "ignition_dns_queries_total",
+            "counter",
+            "Queries answered by the DNS servers, by zone and response code",
+        );
+        for ((zone, rcode), count) in self.queries.lock().expect("dns metrics poisoned").iter() {
+            render_value(
+                &mut out,
+                "ignition_dns_queries_total",
+                &format!("zone=\"{}\"", zone.label()),
+                Some(("rcode", rcode)),
+                count,
+            );
+        }
+
+        render_header(
+            &mut out,
+            "ignition_dns_forward_errors_total",
+            "counter",
+            "Lookups forwarded upstream that failed, NXDOMAIN answers included",
+        );
+        let _ = writeln!(
+            out,
+            "ignition_dns_forward_errors_total {}",
+            self.forward_errors.load(Ordering::Relaxed)
+        );
+
+        render_header(
+            &mut out,
+            "ignition_dns_forward_duration_seconds",
+            "histogram",
+            "Time until the upstream servers answered a forwarded lookup",
+        );
+        let mut cumulative = 0;
+        for (bound, count) in FORWARD_LATENCY_BUCKETS
+            .iter()
+            .zip(self.forward_latency_buckets.iter())
+        {
+            cumulative += count.load(Ordering::Relaxed);
+            render_value(
+                &mut out,
+                "ignition_dns_forward_duration_seconds_bucket",
+                &format!("le=\"{}\"", bound),
+                None,
+                cumulative,
+            );
+        }
+
+        let total = self.forward_latency_count.load(Ordering::Relaxed);
+        render_value(
+            &mut out,
+            "ignition_dns_forward_duration_seconds_bucket",
+            "le=\"+Inf\"",
+            None,
+            total,
+        );
+
+        let sum = self.forward_latency_sum_us.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(out, "ignition_dns_forward_duration_seconds_sum {}", sum);
+        let _ = writeln!(out, "ignition_dns_forward_duration_seconds_count {}", total);
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queries_by_zone_and_rcode() {
+        let metrics = DnsMetrics::new();
+        metrics.record_query(DnsQueryZone::Internal, ResponseCode::NoError);
+        metrics.record_query(DnsQueryZone::Internal, ResponseCode::NXDomain);
+        metrics.record_query(DnsQueryZone::Internal, ResponseCode::NXDomain);
+        metrics.record_forward(Duration::from_millis(3), false);
+
+        let rendered = metrics.render();
+        assert!(
+            rendered.contains("ignition_dns_queries_total{zone=\"internal\",rcode=\"nxdomain\"} 2")
+        );
+        assert!(
+            rendered.contains("ignition_dns_queries_total{zone=\"internal\",rcode=\"noerror\"} 1")
+        );
+        assert!(rendered.contains("ignition_dns_forward_duration_seconds_bucket{le=\"0.005\"} 1"));
+    }
+}
@@ -1,24 +1,47 @@
 pub mod config;
+pub mod dnssec;
 mod handler;
+pub mod metrics;
+mod zone;
 
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
 
 use anyhow::{Result, bail};
 use hickory_server::ServerFuture;
 use tokio::{net::UdpSocket, spawn, sync::Mutex, task::JoinHandle};
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 use crate::{
-    agent::{dns::config::DnsAgentConfig, net::NetAgent},
+    agent::{
+        dns::{
+            config::DnsAgentConfig,
+            dnssec::ZoneSigner,
+            metrics::DnsMetrics,
+            zone::{
+                DnsTxtRecord, DnsZoneRecord, RegionZoneHandler, txt_record_key, zone_record_key,
+            },
+        },
+        net::NetAgent,
+        proxy::ProxyAgent,
+    },
     constants::DEFAULT_NAMESPACE,
+    machinery::store::Store,
     repository::Repository,
+    resources::dns_record::DnsRecordValue,
 };
 
 pub struct DnsAgent {
     config: DnsAgentConfig,
+    store: Arc<Store>,
     net_agent: Arc<NetAgent>,
+    proxy: Arc<ProxyAgent>,
     repository: Arc<Repository>,
+    metrics: Arc<DnsMetrics>,
     server_task: Arc<Mutex<Option<JoinHandle<()>>>>,
+    zone_server_task: Arc<Mutex<Option<JoinHandle<()>>>>,
 }
 
 struct DnsHandler {
@@ -27,19 +50,30 @@ struct DnsHandler {
     default_ttl: u32,
     zone_suffix: String,
     upstream_resolver: Option<hickory_resolver::TokioAsyncResolver>,
+    /// Set with split-horizon answers, for the hosts of the proxy's external bindings.
+    proxy: Option<Arc<ProxyAgent>>,
+    ingress_address: Option<IpAddr>,
+    metrics: Arc<DnsMetrics>,
+    log_queries: bool,
 }
 
 impl DnsAgent {
     pub async fn new(
         config: DnsAgentConfig,
+        store: Arc<Store>,
         net_agent: Arc<NetAgent>,
+        proxy: Arc<ProxyAgent>,
         repository: Arc<Repository>,
     ) -> Result<Arc<Self>> {
         Ok(Arc::new(Self {
             config,
+            store,
             net_agent,
+            proxy,
             repository,
+            metrics: Arc::new(DnsMetrics::new()),
             server_task: Arc::new(Mutex::new(None)),
+            zone_server_task: Arc::new(Mutex::new(None)),
         }))
     }
 
@@ -47,6 +81,31 @@ impl DnsAgent {
         format!("{}.{}.svc.{}", name, namespace, self.config.zone_suffix)
     }
 
+    /// Address public clients reach the proxy on, answered for the hosts of its external
+    /// bindings to anyone but machines.
+    fn ingress_address(&self) -> Option<IpAddr> {
+        match &self.config.ingress_address {
+            Some(address) => match address.parse() {
+                Ok(address) => Some(address),
+                Err(_) => {
+                    warn!("Invalid DNS ingress address: {}", address);
+                    None
+                }
+            },
+            None => self
+                .proxy
+                .config()
+                .external_bind_address
+                .parse::<IpAddr>()
+                .ok()
+                .filter(|address| !address.is_unspecified()),
+        }
+    }
+
+    fn split_horizon_proxy(&self) -> Option<Arc<ProxyAgent>> {
+        self.config.split_horizon.then(|| self.proxy.clone())
+    }
+
     pub async fn start(&self) -> Result<()> {
         let mut server_task = self.server_task.lock().await;
         if server_task.is_some() {
@@ -65,6 +124,10 @@ impl DnsAgent {
             upstream_resolver: DnsHandler::create_upstream_resolver(
                 &self.config.upstream_dns_servers,
             ),
+            proxy: self.split_horizon_proxy(),
+            ingress_address: self.ingress_address(),
+            metrics: self.metrics.clone(),
+            log_queries: self.config.log_queries,
         };
 
         let mut server = ServerFuture::new(handler);
@@ -81,6 +144,63 @@ impl DnsAgent {
         *server_task = Some(task);
         info!("DNS server started successfully on {}", bind_addr);
 
+        if let Some(zone_bind_address) = &self.config.zone_bind_address {
+            self.start_zone_server(zone_bind_address).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn start_zone_server(&self, bind_address: &str) -> Result<()> {
+        let mut zone_server_task = self.zone_server_task.lock().await;
+        if zone_server_task.is_some() {
+            bail!("DNS zone server already running");
+        }
+
+        let bind_addr = SocketAddr::new(bind_address.parse()?, 53);
+        info!(
+            "Starting DNS zone server for {} on {}",
+            self.config.region_root_domain, bind_addr
+        );
+
+        let signer = if self.config.dnssec {
+            let signer =
+                ZoneSigner::load_or_generate(&self.store, &self.config.region_root_domain)?;
+            info!(
+                "DNSSEC signing {} with key {}, publish this DS record at the parent zone: {}",
+                self.config.region_root_domain,
+                signer.key_tag(),
+                signer.ds_record()
+            );
+            Some(Arc::new(signer))
+        } else {
+            None
+        };
+
+        let handler = RegionZoneHandler {
+            store: self.store.clone(),
+            region_root_domain: self.config.region_root_domain.clone(),
+            default_ttl: self.config.default_ttl,
+            proxy: self.split_horizon_proxy(),
+            ingress_address: self.ingress_address(),
+            metrics: self.metrics.clone(),
+            log_queries: self.config.log_queries,
+            signer,
+        };
+
+        let mut server = ServerFuture::new(handler);
+
+        server.register_socket(UdpSocket::bind(bind_addr).await?);
+
+        let task = spawn(async move {
+            match server.block_until_done().await {
+                Ok(_) => info!("DNS zone server stopped"),
+                Err(e) => error!("DNS zone server error: {}", e),
+            }
+        });
+
+        *zone_server_task = Some(task);
+
         Ok(())
     }
 
@@ -90,6 +210,12 @@ impl DnsAgent {
             info!("Stopping DNS server");
             task.abort();
         }
+
+        let mut zone_server_task = self.zone_server_task.lock().await;
+        if let Some(task) = zone_server_task.take() {
+            info!("Stopping DNS zone server");
+            task.abort();
+        }
         Ok(())
     }
 
@@ -125,4 +251,65 @@ impl DnsAgent {
     pub fn config(&self) -> &DnsAgentConfig {
         &self.config
     }
+
+    pub fn metrics(&self) -> &DnsMetrics {
+        &self.metrics
+    }
+
+    /// Whether dns-01 challenges for `domain` can be answered by this node, ie. the domain is in
+    /// the region root domain and the zone server is enabled.
+    pub fn can_publish_acme_challenge(&self, domain: &str) -> bool {
+        self.config.zone_bind_address.is_some()
+            && self.is_region_domain(domain.trim_start_matches("*."))
+    }
+
+    /// Name of the TXT record of a dns-01 challenge, shared by a domain and its wildcard.
+    pub fn acme_challenge_record_name(domain: &str) -> String {
+        format!("_acme-challenge.{}", domain.trim_start_matches("*."))
+    }
+
+    /// Adds `value` to the TXT record `name`, served by the zone server.
+    pub fn publish_txt_record(&self, name: &str, value: &str) -> Result<()> {
+        let key = txt_record_key(name);
+        let mut record = self.store.get(key.clone())?.unwrap_or(DnsTxtRecord {
+            name: name.to_string(),
+            values: vec![],
+        });
+
+        if !record.values.iter().any(|v| v == value) {
+            record.values.push(value.to_string());
+        }
+
+        self.store.put(key, &record)
+    }
+
+    pub fn remove_txt_record(&self, name: &str) -> Result<()> {
+        self.store.delete(txt_record_key(name))
+    }
+
+    /// Whether the zone server is enabled, ie. records in the region root domain can be served.
+    pub fn serves_region_zone(&self) -> bool {
+        self.config.zone_bind_address.is_some()
+    }
+
+    /// Publishes the record of a `DnsRecord` resource, replacing the one of the same type.
+    pub fn publish_zone_record(
+        &self,
+        name: &str,
+        ttl: Option<u32>,
+        value: &DnsRecordValue,
+    ) -> Result<()> {
+        let record = DnsZoneRecord {
+            name: name.to_string(),
+            ttl,
+            value: value.clone(),
+        };
+
+        self.store
+            .put(zone_record_key(name, value.record_type()), &record)
+    }
+
+    pub fn remove_zone_record(&self, name: &str, record_type: &str) -> Result<()> {
+        self.store.delete(zone_record_key(name, record_type))
+    }
 }
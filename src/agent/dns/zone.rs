@@ -0,0 +1,549 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use hickory_proto::{
+    op::{Edns, MessageType, OpCode, ResponseCode},
+    rr::{
+        Name, RData, Record, RecordType,
+        rdata::{A, AAAA, CNAME, MX, SOA, TXT},
+    },
+};
+use hickory_server::{
+    authority::MessageResponseBuilder,
+    server::{Request, RequestHandler, ResponseHandler, ResponseInfo},
+};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::{
+    agent::{
+        data::Collections,
+        dns::{
+            dnssec::ZoneSigner,
+            handler::log_query,
+            metrics::{DnsMetrics, DnsQueryZone},
+        },
+        proxy::ProxyAgent,
+    },
+    constants::DEFAULT_AGENT_TENANT,
+    machinery::store::{Key, PartialKey, Store},
+    resources::dns_record::{DnsRecordValue, normalize_dns_name},
+};
+
+/// TXT record published in the region root domain, eg. an ACME dns-01 challenge.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DnsTxtRecord {
+    pub name: String,
+    pub values: Vec<String>,
+}
+
+pub(super) fn txt_record_key(name: &str) -> Key<DnsTxtRecord> {
+    Key::<DnsTxtRecord>::not_namespaced()
+        .tenant(DEFAULT_AGENT_TENANT)
+        .collection(Collections::DnsTxtRecord)
+        .key(normalize_name(name))
+        .as_ref()
+        .into()
+}
+
+/// Record of a `DnsRecord` resource, published in a zone delegated to its tenant.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DnsZoneRecord {
+    pub name: String,
+    pub ttl: Option<u32>,
+    pub value: DnsRecordValue,
+}
+
+pub(super) fn zone_record_key(name: &str, record_type: &str) -> Key<DnsZoneRecord> {
+    Key::<DnsZoneRecord>::not_namespaced()
+        .tenant(DEFAULT_AGENT_TENANT)
+        .collection(Collections::DnsZoneRecord)
+        .key(format!(
+            "{}/{}",
+            normalize_name(name),
+            record_type.to_ascii_lowercase()
+        ))
+        .as_ref()
+        .into()
+}
+
+fn normalize_name(name: &str) -> String {
+    normalize_dns_name(name)
+}
+
+fn fqdn(name: &str) -> Option<Name> {
+    Name::from_ascii(format!("{}.", normalize_name(name))).ok()
+}
+
+/// TXT values longer than 255 bytes are split in several character strings, resolvers join them
+/// back, eg. for DKIM keys.
+fn txt_character_strings(value: &str) -> Vec<String> {
+    let mut strings = vec![];
+    let mut current = String::new();
+    for c in value.chars() {
+        if current.len() + c.len_utf8() > 255 {
+            strings.push(std::mem::take(&mut current));
+        }
+        current.push(c);
+    }
+    strings.push(current);
+    strings
+}
+
+fn record_data(value: &DnsRecordValue) -> Vec<RData> {
+    match value {
+        DnsRecordValue::A { addresses } => addresses
+            .iter()
+            .filter_map(|address| address.trim().parse::<Ipv4Addr>().ok())
+            .map(|ip| RData::A(A(ip)))
+            .collect(),
+        DnsRecordValue::Aaaa { addresses } => addresses
+            .iter()
+            .filter_map(|address| address.trim().parse::<Ipv6Addr>().ok())
+            .map(|ip| RData::AAAA(AAAA(ip)))
+            .collect(),
+        DnsRecordValue::Cname { target } => fqdn(target)
+            .map(|target| RData::CNAME(CNAME(target)))
+            .into_iter()
+            .collect(),
+        DnsRecordValue::Txt { values } => values
+            .iter()
+            .map(|value| RData::TXT(TXT::new(txt_character_strings(value))))
+            .collect(),
+        DnsRecordValue::Mx { exchanges } => exchanges
+            .iter()
+            .filter_map(|exchange| {
+                fqdn(&exchange.exchange).map(|name| RData::MX(MX::new(exchange.preference, name)))
+            })
+            .collect(),
+    }
+}
+
+/// Answers public queries for the region root domain, when it's delegated to this node. Only the
+/// published TXT records and the records of `DnsRecord` resources are served, nothing is ever
+/// forwarded upstream.
+pub(super) struct RegionZoneHandler {
+    pub store: Arc<Store>,
+    pub region_root_domain: String,
+    pub default_ttl: u32,
+    /// Set with split-horizon answers, the hosts of the proxy's external bindings resolve to
+    /// the ingress address.
+    pub proxy: Option<Arc<ProxyAgent>>,
+    pub ingress_address: Option<IpAddr>,
+    pub metrics: Arc<DnsMetrics>,
+    pub log_queries: bool,
+    /// Set with DNSSEC, signs the answers to resolvers asking for signatures.
+    pub signer: Option<Arc<ZoneSigner>>,
+}
+
+impl RegionZoneHandler {
+    fn apex(&self) -> Option<Name> {
+        fqdn(&self.region_root_domain)
+    }
+
+    /// The zone has no transfers, the SOA only carries the negative caching TTL.
+    fn soa_record(&self) -> Option<Record> {
+        let apex = self.apex()?;
+        let rname = Name::from_ascii(format!("hostmaster.{}", apex)).ok()?;
+        let ttl = self.default_ttl;
+        let soa = SOA::new(apex.clone(), rname, 1, 3600, 600, 86400, ttl);
+
+        Some(Record::from_rdata(apex, ttl, RData::SOA(soa)))
+    }
+
+    /// Records only served at the apex of the zone.
+    fn lookup_apex(&self, name: &Name, record_type: RecordType) -> Vec<Record> {
+        if self.apex().as_ref() != Some(name) {
+            return vec![];
+        }
+
+        match record_type {
+            RecordType::SOA => self.soa_record().into_iter().collect(),
+            RecordType::DNSKEY => self
+                .signer
+                .iter()
+                .map(|signer| signer.dnskey_record(self.default_ttl))
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    fn in_zone(&self, name: &str) -> bool {
+        let name = normalize_name(name);
+        let zone = normalize_name(&self.region_root_domain);
+
+        name == zone || name.ends_with(&format!(".{}", zone))
+    }
+
+    fn lookup_txt(&self, name: &str) -> Vec<String> {
+        match self.store.get(txt_record_key(name)) {
+            Ok(record) => record.map(|r| r.values).unwrap_or_default(),
+            Err(e) => {
+                warn!("Failed to look up TXT record {}: {}", name, e);
+                vec![]
+            }
+        }
+    }
+
+    fn lookup_zone_records(&self, name: &Name, record_type: RecordType) -> Vec<Record> {
+        let key = zone_record_key(&name.to_string(), &record_type.to_string());
+        let record = match self.store.get(key) {
+            Ok(Some(record)) => record,
+            Ok(None) => return vec![],
+            Err(e) => {
+                warn!("Failed to look up {} record {}: {}", record_type, name, e);
+                return vec![];
+            }
+        };
+
+        let ttl = record.ttl.unwrap_or(self.default_ttl);
+        record_data(&record.value)
+            .into_iter()
+            .map(|data| Record::from_rdata(name.clone(), ttl, data))
+            .collect()
+    }
+
+    /// Whether a record or a proxied host exists below `name`. Such a name exists even without
+    /// records of its own, and is answered with NODATA rather than NXDOMAIN (RFC 8020).
+    fn has_names_below(&self, name: &Name) -> bool {
+        let suffix = format!(".{}", normalize_name(&name.to_string()));
+        let is_below = |record_name: &str| normalize_name(record_name).ends_with(&suffix);
+
+        let txt_records = PartialKey::<DnsTxtRecord>::not_namespaced()
+            .tenant(DEFAULT_AGENT_TENANT)
+            .collection(Collections::DnsTxtRecord);
+        let zone_records = PartialKey::<DnsZoneRecord>::not_namespaced()
+            .tenant(DEFAULT_AGENT_TENANT)
+            .collection(Collections::DnsZoneRecord);
+
+        let below = match (
+            self.store.list(&txt_records),
+            self.store.list(&zone_records),
+        ) {
+            (Ok(txt_records), Ok(zone_records)) => {
+                txt_records.iter().any(|record| is_below(&record.name))
+                    || zone_records.iter().any(|record| is_below(&record.name))
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                warn!("Failed to look up the records below {}: {}", name, e);
+                false
+            }
+        };
+
+        below
+            || self
+                .proxy
+                .as_ref()
+                .is_some_and(|proxy| proxy.serves_host_below(&name.to_string()))
+    }
+
+    /// Types published under `name`, names without any are answered with NXDOMAIN unless
+    /// other names exist below them.
+    fn existing_types(&self, name: &Name) -> Vec<RecordType> {
+        let name_str = name.to_string();
+        let mut types = vec![];
+
+        if self.apex().as_ref() == Some(name) {
+            types.push(RecordType::SOA);
+            if self.signer.is_some() {
+                types.push(RecordType::DNSKEY);
+            }
+        }
+
+        if !self.lookup_txt(&name_str).is_empty() {
+            types.push(RecordType::TXT);
+        }
+        if let Some(proxy) = &self.proxy {
+            if proxy.serves_host(&name_str) {
+                match self.ingress_address {
+                    Some(IpAddr::V4(_)) => types.push(RecordType::A),
+                    Some(IpAddr::V6(_)) => types.push(RecordType::AAAA),
+                    None => {}
+                }
+            }
+        }
+
+        for record_type in [
+            RecordType::A,
+            RecordType::AAAA,
+            RecordType::CNAME,
+            RecordType::TXT,
+            RecordType::MX,
+        ] {
+            let key = zone_record_key(&name_str, &record_type.to_string());
+            if matches!(self.store.get(key), Ok(Some(_))) {
+                types.push(record_type);
+            }
+        }
+
+        types.sort_by_key(|record_type| u16::from(*record_type));
+        types.dedup();
+        types
+    }
+
+    /// Answers followed by one RRSIG per record set.
+    fn sign_answers(&self, signer: &ZoneSigner, answers: Vec<Record>) -> Vec<Record> {
+        let mut signed = vec![];
+        let mut record_types = answers.iter().map(|a| a.record_type()).collect::<Vec<_>>();
+        record_types.dedup();
+
+        for record_type in record_types {
+            let rrset = answers
+                .iter()
+                .filter(|answer| answer.record_type() == record_type)
+                .cloned()
+                .collect::<Vec<_>>();
+            match signer.sign_rrset(&rrset) {
+                Ok(rrsig) => signed.push(rrsig),
+                Err(e) => warn!("Failed to sign {} answers: {}", record_type, e),
+            }
+        }
+
+        let mut answers = answers;
+        answers.extend(signed);
+        answers
+    }
+
+    /// SOA and NSEC proving `name` has no record of the queried type, or doesn't exist at all,
+    /// with their signatures.
+    fn signed_denial(&self, signer: &ZoneSigner, name: &Name, types: &[RecordType]) -> Vec<Record> {
+        let mut authority = vec![];
+        if let Some(soa) = self.soa_record() {
+            if let Ok(rrsig) = signer.sign_rrset(&[soa.clone()]) {
+                authority.push(soa);
+                authority.push(rrsig);
+            }
+        }
+
+        match signer
+            .denial_record(name, types, self.default_ttl)
+            .and_then(|nsec| Ok((signer.sign_rrset(&[nsec.clone()])?, nsec)))
+        {
+            Ok((rrsig, nsec)) => {
+                authority.push(nsec);
+                authority.push(rrsig);
+            }
+            Err(e) => warn!("Failed to sign denial of {}: {}", name, e),
+        }
+
+        authority
+    }
+
+    /// The ingress address for a host of the proxy's external bindings, machines get the service
+    /// gateway from the internal DNS server instead.
+    fn lookup_ingress(&self, name: &Name, record_type: RecordType) -> Vec<Record> {
+        let Some(proxy) = &self.proxy else {
+            return vec![];
+        };
+        if !proxy.serves_host(&name.to_string()) {
+            return vec![];
+        }
+
+        let data = match (self.ingress_address, record_type) {
+            (Some(IpAddr::V4(ip)), RecordType::A) => RData::A(A(ip)),
+            (Some(IpAddr::V6(ip)), RecordType::AAAA) => RData::AAAA(AAAA(ip)),
+            _ => return vec![],
+        };
+
+        vec![Record::from_rdata(name.clone(), self.default_ttl, data)]
+    }
+}
+
+#[async_trait::async_trait]
+impl RequestHandler for RegionZoneHandler {
+    async fn handle_request<R: ResponseHandler>(
+        &self,
+        request: &Request,
+        mut response_handle: R,
+    ) -> ResponseInfo {
+        let mut response = MessageResponseBuilder::from_message_request(request);
+
+        if request.message_type() != MessageType::Query || request.op_code() != OpCode::Query {
+            self.metrics
+                .record_query(DnsQueryZone::Region, ResponseCode::NotImp);
+            let response_message = response.error_msg(request.header(), ResponseCode::NotImp);
+            return response_handle
+                .send_response(response_message)
+                .await
+                .map_err(|e| warn!("Error sending DNS response: {}", e))
+                .ok()
+                .expect("DNS response handler should return ResponseInfo");
+        }
+
+        let query = request.query();
+        let name = query.name().to_string();
+        debug!(
+            "Zone query from {}: {} {:?}",
+            request.src(),
+            name,
+            query.query_type()
+        );
+
+        if !self.in_zone(&name) {
+            self.metrics
+                .record_query(DnsQueryZone::Region, ResponseCode::Refused);
+            if self.log_queries {
+                log_query(request, ResponseCode::Refused, 0);
+            }
+
+            let response_message = response.error_msg(request.header(), ResponseCode::Refused);
+            return response_handle
+                .send_response(response_message)
+                .await
+                .map_err(|e| warn!("Error sending DNS response: {}", e))
+                .ok()
+                .expect("DNS response handler should return ResponseInfo");
+        }
+
+        let query_name: Name = query.name().clone().into();
+        let mut answers = if query.query_type() == RecordType::TXT {
+            self.lookup_txt(&name)
+                .into_iter()
+                .map(|value| {
+                    Record::from_rdata(
+                        query_name.clone(),
+                        self.default_ttl,
+                        RData::TXT(TXT::new(vec![value])),
+                    )
+                })
+                .collect::<Vec<_>>()
+        } else {
+            vec![]
+        };
+        answers.extend(self.lookup_zone_records(&query_name, query.query_type()));
+        if answers.is_empty() {
+            answers = self.lookup_ingress(&query_name, query.query_type());
+        }
+
+        if answers.is_empty() {
+            answers = self.lookup_apex(&query_name, query.query_type());
+        }
+
+        // an alias answers for every type, the resolver follows it
+        if answers.is_empty() && query.query_type() != RecordType::CNAME {
+            answers = self.lookup_zone_records(&query_name, RecordType::CNAME);
+        }
+
+        let existing_types = if answers.is_empty() {
+            self.existing_types(&query_name)
+        } else {
+            vec![]
+        };
+        let mut response_code = if answers.is_empty()
+            && existing_types.is_empty()
+            && !self.has_names_below(&query_name)
+        {
+            ResponseCode::NXDomain
+        } else {
+            ResponseCode::NoError
+        };
+
+        let dnssec_ok = request.edns().is_some_and(|edns| edns.dnssec_ok());
+        let mut authority = vec![];
+        match (&self.signer, dnssec_ok) {
+            (Some(signer), true) => {
+                if answers.is_empty() {
+                    // compact denial: a missing name is a name without any type, nothing else
+                    // in the zone has to be enumerated
+                    authority = self.signed_denial(signer, &query_name, &existing_types);
+                    response_code = ResponseCode::NoError;
+                } else {
+                    answers = self.sign_answers(signer, answers);
+                }
+            }
+            _ => {
+                if answers.is_empty() {
+                    authority.extend(self.soa_record());
+                }
+            }
+        }
+
+        if let Some(request_edns) = request.edns() {
+            let mut edns = Edns::new();
+            edns.set_dnssec_ok(request_edns.dnssec_ok() && self.signer.is_some());
+            edns.set_max_payload(request_edns.max_payload().max(512));
+            response.edns(edns);
+        }
+
+        self.metrics
+            .record_query(DnsQueryZone::Region, response_code);
+        if self.log_queries {
+            log_query(request, response_code, answers.len());
+        }
+
+        let mut header = *request.header();
+        header.set_response_code(response_code);
+        header.set_answer_count(answers.len() as u16);
+        header.set_authoritative(true);
+        header.set_message_type(MessageType::Response);
+
+        let response_message = response.build(header, answers.iter(), &[], authority.iter(), &[]);
+        response_handle
+            .send_response(response_message)
+            .await
+            .map_err(|e| warn!("Error sending DNS response: {}", e))
+            .ok()
+            .expect("DNS response handler should return ResponseInfo")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_txt_character_strings() {
+        assert_eq!(txt_character_strings("v=spf1 -all"), vec!["v=spf1 -all"]);
+
+        let long = "a".repeat(600);
+        let strings = txt_character_strings(&long);
+        assert_eq!(
+            strings.iter().map(|s| s.len()).collect::<Vec<_>>(),
+            vec![255, 255, 90]
+        );
+        assert_eq!(strings.concat(), long);
+    }
+
+    #[tokio::test]
+    async fn test_has_names_below() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(Store::new(dir.path()).await.unwrap());
+        store
+            .put(
+                zone_record_key("api.eu.acme.example.com", "A"),
+                DnsZoneRecord {
+                    name: "api.eu.acme.example.com".to_string(),
+                    ttl: None,
+                    value: DnsRecordValue::A {
+                        addresses: vec!["192.0.2.1".to_string()],
+                    },
+                },
+            )
+            .unwrap();
+
+        let handler = RegionZoneHandler {
+            store,
+            region_root_domain: "example.com".to_string(),
+            default_ttl: 300,
+            proxy: None,
+            ingress_address: None,
+            metrics: Arc::new(DnsMetrics::new()),
+            log_queries: false,
+            signer: None,
+        };
+
+        let name = |name: &str| Name::from_ascii(name).unwrap();
+        assert!(handler.has_names_below(&name("eu.acme.example.com.")));
+        assert!(handler.has_names_below(&name("acme.example.com.")));
+        assert!(!handler.has_names_below(&name("api.eu.acme.example.com.")));
+        assert!(!handler.has_names_below(&name("us.acme.example.com.")));
+        assert!(
+            handler
+                .existing_types(&name("eu.acme.example.com."))
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,272 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, Weak},
+    time::Duration,
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    agent::data::Collections,
+    constants::{DEFAULT_AGENT_TENANT, DEFAULT_EVENT_GC_INTERVAL_SECS},
+    controller::context::ControllerKey,
+    machinery::store::{Key, PartialKey, Store, now_millis},
+    resources::core::{EventReason, ResourceEvent},
+};
+
+#[derive(Debug, Clone)]
+pub struct EventsAgentConfig {
+    /// How long an event is kept after it was last seen.
+    pub ttl: Duration,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredEvent {
+    id: String,
+    tenant: String,
+    kind: String,
+    namespace: Option<String>,
+    name: String,
+    #[serde(flatten)]
+    event: ResourceEvent,
+}
+
+impl StoredEvent {
+    fn key(&self) -> Key<StoredEvent> {
+        Key::<StoredEvent>::not_namespaced()
+            .tenant(DEFAULT_AGENT_TENANT)
+            .collection(resource_collection(
+                &self.tenant,
+                &self.kind,
+                self.namespace.as_deref(),
+                &self.name,
+            ))
+            .key(format!("{:020}-{}", self.event.first_seen, self.id))
+            .as_ref()
+            .into()
+    }
+}
+
+/// Events are keyed under the resource they are for, listing those of one resource only scans
+/// its prefix.
+fn resource_collection(tenant: &str, kind: &str, namespace: Option<&str>, name: &str) -> String {
+    format!(
+        "{}/{}/{}/{}/{}",
+        Collections::Event.as_ref(),
+        tenant,
+        kind,
+        namespace.unwrap_or("-"),
+        name
+    )
+}
+
+/// Keeps the recent events of resources, emitted by controllers and agents.
+pub struct EventsAgent {
+    config: EventsAgentConfig,
+    store: Arc<Store>,
+    /// Last event of each resource, to count repeated events instead of storing each of them.
+    last_events: Mutex<HashMap<ControllerKey, StoredEvent>>,
+}
+
+impl EventsAgent {
+    pub fn new(config: EventsAgentConfig, store: Arc<Store>) -> Self {
+        Self {
+            config,
+            store,
+            last_events: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records an event for a resource. Failures are only logged, events are informative.
+    pub fn emit(&self, key: &ControllerKey, reason: EventReason, message: impl Into<String>) {
+        let message = message.into();
+        if let Err(e) = self.record(key, reason, &message) {
+            warn!(
+                "failed to record {:?} event for {}: {}",
+                reason,
+                key.to_string(),
+                e
+            );
+        }
+    }
+
+    fn record(&self, key: &ControllerKey, reason: EventReason, message: &str) -> Result<()> {
+        let mut last_events = self.last_events.lock().expect("last events poisoned");
+        let now = now_millis();
+
+        if let Some(last) = last_events.get_mut(key) {
+            let expired =
+                now.saturating_sub(last.event.last_seen) > self.config.ttl.as_millis() as u64;
+            if !expired && last.event.reason == reason && last.event.message == message {
+                last.event.count += 1;
+                last.event.last_seen = now;
+                return self.store.put(last.key(), &*last);
+            }
+        }
+
+        let event = StoredEvent {
+            id: Uuid::new_v4().to_string(),
+            tenant: key.tenant.clone(),
+            kind: key.kind.tag().to_string(),
+            namespace: key.metadata().namespace,
+            name: key.name.clone(),
+            event: ResourceEvent {
+                reason,
+                message: message.to_string(),
+                count: 1,
+                first_seen: now,
+                last_seen: now,
+            },
+        };
+
+        self.store.put(event.key(), &event)?;
+        last_events.insert(key.clone(), event);
+
+        Ok(())
+    }
+
+    /// Events of a resource, oldest first.
+    pub fn list(
+        &self,
+        tenant: &str,
+        kind: &str,
+        namespace: Option<&str>,
+        name: &str,
+    ) -> Result<Vec<ResourceEvent>> {
+        let key = PartialKey::<StoredEvent>::not_namespaced()
+            .tenant(DEFAULT_AGENT_TENANT)
+            .collection(resource_collection(tenant, kind, namespace, name));
+
+        let mut events = self
+            .store
+            .list(&key)?
+            .into_iter()
+            .map(|e| e.event)
+            .collect::<Vec<_>>();
+
+        events.sort_by_key(|e| e.last_seen);
+
+        Ok(events)
+    }
+
+    fn collect_garbage(&self) -> Result<()> {
+        let cutoff = now_millis().saturating_sub(self.config.ttl.as_millis() as u64);
+
+        self.last_events
+            .lock()
+            .expect("last events poisoned")
+            .retain(|_, last| last.event.last_seen >= cutoff);
+
+        let key = PartialKey::<StoredEvent>::not_namespaced()
+            .tenant(DEFAULT_AGENT_TENANT)
+            .collection(Collections::Event);
+        let prefix = PartialKey::from(&key).to_string();
+
+        // deleted by the key they were stored under, events stored before they were keyed by
+        // resource expire too
+        let mut removed = 0;
+        for stored_key in self.store.list_keys(&key)? {
+            let Some(stored_key) = stored_key.strip_prefix(&prefix) else {
+                continue;
+            };
+            let key = Key::<StoredEvent>::not_namespaced()
+                .tenant(DEFAULT_AGENT_TENANT)
+                .collection(Collections::Event)
+                .key(stored_key);
+
+            let Some(event) = self.store.get(&key)? else {
+                continue;
+            };
+
+            if event.event.last_seen < cutoff {
+                self.store.delete(&key)?;
+                removed += 1;
+            }
+        }
+
+        if removed > 0 {
+            info!("removed {} expired events", removed);
+        }
+
+        Ok(())
+    }
+
+    pub fn start_gc(self: &Arc<Self>) {
+        let agent = Arc::downgrade(self);
+
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(Duration::from_secs(DEFAULT_EVENT_GC_INTERVAL_SECS));
+
+            loop {
+                interval.tick().await;
+
+                let Some(agent) = Weak::upgrade(&agent) else {
+                    break;
+                };
+
+                if let Err(e) = agent.collect_garbage() {
+                    warn!("failed to collect expired events: {}", e);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resource_index::ResourceKind;
+
+    #[tokio::test]
+    async fn test_list_events_of_resource() {
+        let dir = tempfile::tempdir().expect("failed to create tempdir");
+        let store = Store::new(dir.path())
+            .await
+            .expect("failed to create store");
+
+        let agent = EventsAgent::new(
+            EventsAgentConfig {
+                ttl: Duration::from_secs(60),
+            },
+            Arc::new(store),
+        );
+
+        let web = ControllerKey::new("tenant", ResourceKind::Machine, Some("default"), "web");
+        let worker = ControllerKey::new(
+            "tenant",
+            ResourceKind::Machine,
+            Some("default"),
+            "web-worker",
+        );
+
+        agent.emit(&web, EventReason::MachineStarted, "started");
+        agent.emit(&web, EventReason::MachineStarted, "started");
+        agent.emit(&worker, EventReason::MachineFailed, "exited with 1");
+
+        let kind = ResourceKind::Machine.tag();
+
+        let events = agent
+            .list("tenant", kind, Some("default"), "web")
+            .expect("failed to list events");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].reason, EventReason::MachineStarted);
+        assert_eq!(events[0].count, 2);
+
+        let events = agent
+            .list("tenant", kind, Some("default"), "web-worker")
+            .expect("failed to list events");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].reason, EventReason::MachineFailed);
+
+        assert!(
+            agent
+                .list("tenant", kind, Some("other"), "web")
+                .expect("failed to list events")
+                .is_empty()
+        );
+    }
+}
@@ -1,10 +1,21 @@
+mod archive;
 pub mod credentials;
+pub mod extraction;
+pub mod gc;
+pub mod labels;
 pub mod oci;
+pub mod signature;
 mod unpacker;
 
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex, Weak},
+    time::Duration,
+};
 
-use anyhow::{Result, bail};
+use anyhow::{Result, anyhow, bail};
 use oci_client::Reference;
 use serde::{Deserialize, Serialize};
 use tokio::task::spawn_blocking;
@@ -13,12 +24,26 @@ use tracing::{info, warn};
 use crate::{
     agent::{
         data::Collections,
-        image::credentials::InternalCredentialsProvider,
+        image::{
+            credentials::{InternalCredentialsProvider, OciCredentialsProvider},
+            extraction::{ExtractedImage, ExtractionCache, ExtractionProgress},
+            gc::{GcCandidate, ImageGcPolicy},
+            labels::ImageLabelPolicy,
+            signature::{ImageSignaturePolicy, SignatureEnforcement, SignatureVerifier},
+        },
         volume::{VolumeAgent, fs},
     },
     api::auth::AuthHandler,
     constants::DEFAULT_AGENT_TENANT,
+    controller::scheduler::Scheduler,
     machinery::store::{Key, PartialKey, Store},
+    resources::{
+        Convert, ProvideMetadata,
+        core::{
+            ImageGcRemoval, ImageGcReport, ImageInspection, ImageInspectionLayer, ImagePullProgress,
+        },
+        metadata::Namespace,
+    },
     utils::time::now_millis,
 };
 
@@ -26,6 +51,11 @@ use crate::{
 pub struct ImageAgentConfig {
     pub base_path: String,
     pub internal_registry_service: String,
+    pub signature_policy: Option<ImageSignaturePolicy>,
+    /// Images lacking the labels it requires fail to pull when set.
+    pub label_policy: Option<ImageLabelPolicy>,
+    /// Unused images are only collected periodically when set.
+    pub gc_policy: Option<ImageGcPolicy>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -43,14 +73,29 @@ pub struct Image {
     pub timestamp: u64,
     pub volume_id: String,
     pub layer_ids: Vec<String>,
+    /// Labels from the image config (eg. `org.opencontainers.image.revision`).
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    /// Annotations from the image manifest.
+    #[serde(default)]
+    pub annotations: BTreeMap<String, String>,
 }
 
 pub struct ImageAgent {
     store: Arc<Store>,
     volume_agent: Arc<VolumeAgent>,
-    base_layers_path: PathBuf,
+    extraction_cache: ExtractionCache,
     auth_handler: Arc<AuthHandler>,
     internal_registry_service: String,
+    /// Extractions in progress, by image reference.
+    pulls: Mutex<HashMap<String, Arc<ExtractionProgress>>>,
+    /// Pulls in progress from start to end, by image reference, with how many there are.
+    pulls_in_flight: Mutex<HashMap<String, usize>>,
+    scheduler: Weak<Scheduler>,
+    signature_verifier: Option<SignatureVerifier>,
+    label_policy: Option<ImageLabelPolicy>,
+    gc_policy: Option<ImageGcPolicy>,
+    last_gc_report: Mutex<Option<ImageGcReport>>,
 }
 
 impl ImageAgent {
@@ -59,23 +104,27 @@ impl ImageAgent {
         store: Arc<Store>,
         volume_agent: Arc<VolumeAgent>,
         auth_handler: Arc<AuthHandler>,
+        scheduler: Weak<Scheduler>,
     ) -> Result<Self> {
-        let base_path = PathBuf::from(&config.base_path);
-        if !base_path.exists() {
-            tokio::fs::create_dir_all(&base_path).await?;
-        }
-
-        let base_layers_path = base_path.join("layers");
-        if !base_layers_path.exists() {
-            tokio::fs::create_dir_all(&base_layers_path).await?;
-        }
+        let extraction_cache = ExtractionCache::new(&config.base_path).await?;
+        let signature_verifier = match config.signature_policy {
+            Some(policy) => Some(SignatureVerifier::new(policy).await?),
+            None => None,
+        };
 
         Ok(Self {
             store,
             volume_agent,
-            base_layers_path,
+            extraction_cache,
             auth_handler,
             internal_registry_service: config.internal_registry_service,
+            pulls: Mutex::new(HashMap::new()),
+            pulls_in_flight: Mutex::new(HashMap::new()),
+            scheduler,
+            signature_verifier,
+            label_policy: config.label_policy,
+            gc_policy: config.gc_policy,
+            last_gc_report: Mutex::new(None),
         })
     }
 
@@ -133,6 +182,17 @@ impl ImageAgent {
         tenant: String,
         reference: Reference,
     ) -> Result<Option<Image>> {
+        // images loaded from an archive are never looked up in the registry
+        if let Some(loaded) = self
+            .extraction_cache
+            .get_loaded(&tenant, &reference.to_string())
+            .await?
+        {
+            return Ok(self
+                .image_by_reference(&reference.to_string())?
+                .filter(|image| image.digest == loaded.digest));
+        }
+
         let credentials_provider = InternalCredentialsProvider::new(
             self.auth_handler.clone(),
             self.internal_registry_service.clone(),
@@ -156,86 +216,347 @@ impl ImageAgent {
         Ok(None)
     }
 
-    pub async fn image_pull(&self, tenant: String, reference: Reference) -> Result<Image> {
+    /// Layers downloaded and unpacked so far for the pull of `reference`, if one is in progress.
+    pub fn pull_progress(&self, reference: &str) -> Option<ImagePullProgress> {
+        self.pulls
+            .lock()
+            .expect("pulls poisoned")
+            .get(reference)
+            .map(|progress| progress.snapshot(reference))
+    }
+
+    /// A file to receive an archive for `image_load`.
+    pub fn archive_file(&self) -> Result<tempfile::NamedTempFile> {
+        self.extraction_cache.archive_file()
+    }
+
+    /// Loads a `docker save` or OCI layout archive uploaded by `tenant`. Its machines use the
+    /// image for `reference` from then on, instead of the one of the registry.
+    pub async fn image_load(
+        &self,
+        tenant: String,
+        reference: Reference,
+        archive_path: &Path,
+    ) -> Result<ExtractedImage> {
         let credentials_provider = InternalCredentialsProvider::new(
             self.auth_handler.clone(),
             self.internal_registry_service.clone(),
-            tenant,
+            tenant.clone(),
         );
 
-        let (manifest, digest, config) =
-            oci::fetch_manifest(&credentials_provider, &reference).await?;
+        self.extraction_cache
+            .extract_archive(&credentials_provider, &tenant, &reference, archive_path)
+            .await
+    }
 
-        if let Some(existing_image) = self.image_by_reference(&reference.to_string())? {
-            info!(
-                "existing image found for reference {}: {}",
-                reference.to_string(),
-                existing_image.id
-            );
-            if existing_image.digest == digest {
-                return Ok(existing_image);
+    /// The manifest and config `reference` resolves to for `tenant`, the way `image_pull` would
+    /// resolve it, along with the stored image and volume when it was already pulled.
+    pub async fn image_inspect(
+        &self,
+        tenant: String,
+        reference: Reference,
+    ) -> Result<ImageInspection> {
+        let reference_str = reference.to_string();
+
+        let (manifest, digest, config) = match self
+            .extraction_cache
+            .get_loaded(&tenant, &reference_str)
+            .await?
+        {
+            Some(loaded) => {
+                let Some((manifest, config)) =
+                    self.extraction_cache.get_manifest(&loaded.key).await?
+                else {
+                    bail!("manifest of loaded image {} not found", loaded.digest);
+                };
+                (manifest, loaded.digest, config)
+            }
+            None => {
+                let credentials_provider = InternalCredentialsProvider::new(
+                    self.auth_handler.clone(),
+                    self.internal_registry_service.clone(),
+                    tenant.clone(),
+                );
+
+                match oci::fetch_manifest(&credentials_provider, &reference).await {
+                    Ok(fetched) => fetched,
+                    Err(e) if oci::is_transport_error(&e) => {
+                        // as for pulls, an image extracted ahead of time stands in for the registry
+                        let Some(extracted) = self
+                            .extraction_cache
+                            .get_by_reference(&tenant, &reference_str)
+                            .await?
+                        else {
+                            return Err(e);
+                        };
+                        let Some((manifest, config)) =
+                            self.extraction_cache.get_manifest(&extracted.key).await?
+                        else {
+                            return Err(e);
+                        };
+                        (manifest, extracted.digest, config)
+                    }
+                    Err(e) => return Err(e),
+                }
             }
         };
 
-        // we are noew ready to pull the image
-        // 1. see what layers we already have, and what we need to pull
-        let mut layers_to_pull = Vec::new();
-        for layer in manifest.layers.iter() {
-            if let Some(_) = self.layer(&layer.digest)? {
-                continue;
+        let image = self
+            .image_list()?
+            .into_iter()
+            .filter(|image| image.reference == reference_str && image.digest == digest)
+            .max_by_key(|image| image.timestamp);
+        let volume = match &image {
+            Some(image) => self.volume_agent.volume(&image.volume_id)?,
+            None => None,
+        };
+
+        let container_config = config.config.unwrap_or_default();
+        let mut exposed_ports = container_config
+            .exposed_ports
+            .unwrap_or_default()
+            .into_iter()
+            .collect::<Vec<_>>();
+        exposed_ports.sort();
+
+        Ok(ImageInspection {
+            reference: reference_str,
+            digest,
+            entrypoint: container_config.entrypoint,
+            cmd: container_config.cmd,
+            env: container_config.env.unwrap_or_default(),
+            exposed_ports,
+            user: container_config.user,
+            working_dir: container_config.working_dir,
+            labels: container_config
+                .labels
+                .unwrap_or_default()
+                .into_iter()
+                .collect(),
+            annotations: manifest
+                .annotations
+                .unwrap_or_default()
+                .into_iter()
+                .collect(),
+            layers: manifest
+                .layers
+                .into_iter()
+                .map(|layer| ImageInspectionLayer {
+                    digest: layer.digest,
+                    media_type: layer.media_type,
+                    size_bytes: layer.size.max(0) as u64,
+                })
+                .collect(),
+            image_id: image.as_ref().map(|image| image.id.clone()),
+            pulled_at: image.as_ref().map(|image| image.timestamp),
+            volume_id: image.map(|image| image.volume_id),
+            volume_size_bytes: volume.map(|volume| volume.sparse_size),
+        })
+    }
+
+    /// Applies the signature policy of `tenant` to the image it is about to use.
+    async fn check_signature(
+        &self,
+        tenant: &str,
+        credentials_provider: &impl OciCredentialsProvider,
+        reference: &Reference,
+        digest: &str,
+    ) -> Result<()> {
+        let Some(verifier) = &self.signature_verifier else {
+            return Ok(());
+        };
+
+        let enforcement = verifier.enforcement(tenant);
+        if enforcement == SignatureEnforcement::Off {
+            return Ok(());
+        }
+
+        // multi-platform images are usually signed through their index, which only vouches
+        // for the manifest if it lists it
+        let mut result = verifier
+            .verify(credentials_provider, reference, digest)
+            .await;
+        if result.is_err() {
+            if let Ok(Some((index, index_digest))) =
+                oci::fetch_index(credentials_provider, reference).await
+            {
+                result = if index.manifests.iter().any(|entry| entry.digest == digest) {
+                    verifier
+                        .verify(credentials_provider, reference, &index_digest)
+                        .await
+                } else {
+                    Err(anyhow!("index {} doesn't list {}", index_digest, digest))
+                };
             }
+        }
 
-            layers_to_pull.push(layer);
+        match result {
+            Ok(()) => {
+                info!("verified the signature of {} ({})", reference, digest);
+                Ok(())
+            }
+            Err(e) if enforcement == SignatureEnforcement::Warn => {
+                warn!("using {} without a valid signature: {}", reference, e);
+                Ok(())
+            }
+            Err(e) => bail!(
+                "image {} rejected by the signature policy: {}",
+                reference,
+                e
+            ),
         }
+    }
 
-        // 2. pull the needed layers and create entry for each layer
-        for layer in layers_to_pull {
-            let layer_path = self.base_layers_path.join(&layer.digest);
-            let layer_path = layer_path.to_str().unwrap();
+    /// Applies the label policy to the image it is about to use.
+    fn check_labels(&self, reference: &Reference, labels: &BTreeMap<String, String>) -> Result<()> {
+        match &self.label_policy {
+            Some(policy) => policy.check(&reference.to_string(), labels),
+            None => Ok(()),
+        }
+    }
 
-            oci::pull_layer(&credentials_provider, &reference, &layer, layer_path).await?;
+    pub async fn image_pull(&self, tenant: String, reference: Reference) -> Result<Image> {
+        // the image pulled is only used by a machine after the pull, the GC keeps it until then
+        let _pull = self.begin_pull(&reference.to_string());
+
+        let credentials_provider = InternalCredentialsProvider::new(
+            self.auth_handler.clone(),
+            self.internal_registry_service.clone(),
+            tenant.clone(),
+        );
+
+        let loaded = self
+            .extraction_cache
+            .get_loaded(&tenant, &reference.to_string())
+            .await?;
+
+        let extracted = if let Some(loaded) = loaded {
+            info!(
+                "using image {} loaded from an archive for {}",
+                loaded.digest, reference
+            );
+            self.check_signature(&tenant, &credentials_provider, &reference, &loaded.digest)
+                .await?;
+
+            if let Some(existing_image) = self.image_by_reference(&reference.to_string())? {
+                if existing_image.digest == loaded.digest {
+                    self.check_labels(&reference, &existing_image.labels)?;
+                    return self.touch_image(existing_image);
+                }
+            }
+
+            loaded
+        } else {
+            match oci::fetch_manifest(&credentials_provider, &reference).await {
+                Ok((manifest, digest, config)) => {
+                    self.check_signature(&tenant, &credentials_provider, &reference, &digest)
+                        .await?;
+
+                    if let Some(existing_image) = self.image_by_reference(&reference.to_string())? {
+                        info!(
+                            "existing image found for reference {}: {}",
+                            reference.to_string(),
+                            existing_image.id
+                        );
+                        if existing_image.digest == digest {
+                            self.check_labels(&reference, &existing_image.labels)?;
+                            return self.touch_image(existing_image);
+                        }
+                    };
+
+                    let progress = Arc::new(ExtractionProgress::default());
+                    self.pulls
+                        .lock()
+                        .expect("pulls poisoned")
+                        .insert(reference.to_string(), progress.clone());
+
+                    let extracted = self
+                        .extraction_cache
+                        .extract(
+                            &credentials_provider,
+                            Some(&tenant),
+                            &reference,
+                            &manifest,
+                            &digest,
+                            config,
+                            &progress,
+                        )
+                        .await;
+
+                    self.pulls
+                        .lock()
+                        .expect("pulls poisoned")
+                        .remove(&reference.to_string());
+
+                    extracted?
+                }
+                // only when the registry is out of reach, an image extracted ahead of time still
+                // works; a denied pull must not fall back to what someone else pulled
+                Err(e) if oci::is_transport_error(&e) => {
+                    let Some(extracted) = self
+                        .extraction_cache
+                        .get_by_reference(&tenant, &reference.to_string())
+                        .await?
+                    else {
+                        return Err(e);
+                    };
+                    warn!(
+                        "failed to fetch manifest for {}, using extracted image {}: {}",
+                        reference.to_string(),
+                        extracted.digest,
+                        e
+                    );
+                    self.check_signature(
+                        &tenant,
+                        &credentials_provider,
+                        &reference,
+                        &extracted.digest,
+                    )
+                    .await?;
+
+                    if let Some(existing_image) = self.image_by_reference(&reference.to_string())? {
+                        if existing_image.digest == extracted.digest {
+                            self.check_labels(&reference, &existing_image.labels)?;
+                            return self.touch_image(existing_image);
+                        }
+                    }
+
+                    extracted
+                }
+                Err(e) => return Err(e),
+            }
+        };
+
+        self.check_labels(&reference, &extracted.labels)?;
+
+        for layer_digest in extracted.layer_ids.iter() {
+            if self.layer(layer_digest)?.is_some() {
+                continue;
+            }
 
             let layer_entry = ImageLayer {
                 timestamp: now_millis(),
-                digest: layer.digest.clone(),
-                path: layer_path.to_string(),
+                digest: layer_digest.clone(),
+                path: self
+                    .extraction_cache
+                    .layer_path(layer_digest)
+                    .to_string_lossy()
+                    .to_string(),
             };
 
             let key = Key::<ImageLayer>::not_namespaced()
                 .tenant(DEFAULT_AGENT_TENANT)
                 .collection(Collections::ImageLayer)
-                .key(&layer.digest);
+                .key(layer_digest);
 
             if let Err(e) = self.store.put(&key, &layer_entry) {
                 warn!("failed to store layer entry: {}", e);
             }
         }
 
-        // 3. create temp dir
-        let temp_dir = tempfile::tempdir()?;
-
-        // 4. unpack the layers
-        for layer in manifest.layers.iter() {
-            let layer_entry = self.layer(&layer.digest)?;
-            let Some(layer_entry) = layer_entry else {
-                bail!("Layer not found: {}", layer.digest);
-            };
-
-            let layer_path = PathBuf::from(&layer_entry.path);
-
-            oci::uncompress_layer(&layer_path, &temp_dir.path()).await?;
-        }
-
-        // 5. write config for takeoff
-        if let Some(config) = config.config {
-            let config_path = temp_dir.path().join("./etc/lttle/oci-config.json");
-            tokio::fs::create_dir_all(config_path.parent().unwrap()).await?;
-            tokio::fs::write(config_path, serde_json::to_string_pretty(&config)?).await?;
-        }
-
         info!("measuring size");
-        // 6. create the volume from temp dir
-        let dir_size_path = temp_dir.path().to_path_buf();
+        // create the volume from the extracted root filesystem
+        let dir_size_path = extracted.rootfs_path.clone();
         let dir_size_bytes =
             spawn_blocking(move || fs::dir_size_in_bytes_recursive(dir_size_path)).await??;
 
@@ -248,7 +569,7 @@ impl ImageAgent {
 
         let volume = match self
             .volume_agent
-            .volume_create_ext4_sparse(temp_dir.path().to_str().unwrap(), Some(sparse_size))
+            .volume_create_ext4_sparse(extracted.rootfs_path.to_str().unwrap(), Some(sparse_size))
             .await
         {
             Ok(volume) => volume,
@@ -260,7 +581,6 @@ impl ImageAgent {
 
         info!("volume created");
 
-        // 7. create image
         let image_id = uuid::Uuid::new_v4().to_string();
         let key = Key::<Image>::not_namespaced()
             .tenant(DEFAULT_AGENT_TENANT)
@@ -272,10 +592,12 @@ impl ImageAgent {
         let image = Image {
             id: image_id,
             reference: reference.to_string(),
-            digest,
+            digest: extracted.digest,
             timestamp: now_millis(),
             volume_id: volume.id,
-            layer_ids: manifest.layers.iter().map(|l| l.digest.clone()).collect(),
+            layer_ids: extracted.layer_ids,
+            labels: extracted.labels,
+            annotations: extracted.annotations,
         };
         if let Err(e) = self.store.put(&key, &image) {
             warn!("failed to store image entry: {}", e);
@@ -285,13 +607,269 @@ impl ImageAgent {
 
         Ok(image)
     }
+
+    /// Marks an image as pulled now, the GC counts its age from its last pull.
+    fn touch_image(&self, mut image: Image) -> Result<Image> {
+        image.timestamp = now_millis();
+
+        let key = Key::<Image>::not_namespaced()
+            .tenant(DEFAULT_AGENT_TENANT)
+            .collection(Collections::Image)
+            .key(&image.id);
+        self.store.put(&key, &image)?;
+
+        Ok(image)
+    }
+
+    fn begin_pull(&self, reference: &str) -> PullInFlight<'_> {
+        *self
+            .pulls_in_flight
+            .lock()
+            .expect("pulls in flight poisoned")
+            .entry(reference.to_string())
+            .or_default() += 1;
+
+        PullInFlight {
+            pulls: &self.pulls_in_flight,
+            reference: reference.to_string(),
+        }
+    }
+
+    /// The ids of the images machines run on and the references machines are set to run.
+    fn machine_images(&self) -> Result<(HashSet<String>, HashSet<String>)> {
+        let Some(scheduler) = self.scheduler.upgrade() else {
+            bail!("the scheduler is not running, the images machines use are unknown");
+        };
+
+        let mut ids = HashSet::new();
+        let mut references = HashSet::new();
+        for tenant in scheduler.store.list_tenants()? {
+            let machine_repo = scheduler.repository.machine(tenant);
+            for machine in machine_repo.list(Namespace::Unspecified)? {
+                if let Some(reference) = machine
+                    .latest()
+                    .image
+                    .and_then(|image| Reference::from_str(&image).ok())
+                {
+                    references.insert(reference.to_string());
+                }
+
+                if let Some(image_id) = machine_repo
+                    .get_status(machine.metadata())?
+                    .and_then(|status| status.image_id)
+                {
+                    ids.insert(image_id);
+                }
+            }
+        }
+
+        Ok((ids, references))
+    }
+
+    pub fn last_gc_report(&self) -> Option<ImageGcReport> {
+        self.last_gc_report
+            .lock()
+            .expect("gc report poisoned")
+            .clone()
+    }
+
+    /// Removes the images the GC policy lets go, along with their volumes, extracted root
+    /// filesystems and the layers nothing refers to anymore. A dry run only reports them.
+    pub async fn collect_garbage(&self, dry_run: bool) -> Result<ImageGcReport> {
+        let policy = self.gc_policy.clone().unwrap_or_default();
+        let now = now_millis();
+
+        let (machine_image_ids, machine_references) = self.machine_images()?;
+        let pulls_in_flight = self
+            .pulls_in_flight
+            .lock()
+            .expect("pulls in flight poisoned")
+            .keys()
+            .cloned()
+            .collect::<HashSet<_>>();
+        let images = self.image_list()?;
+        // a machine set to run a reference runs its latest image once (re)started
+        let mut latest_by_reference: HashMap<&str, &Image> = HashMap::new();
+        for image in images.iter() {
+            let latest = latest_by_reference
+                .entry(image.reference.as_str())
+                .or_insert(image);
+            if image.timestamp > latest.timestamp {
+                *latest = image;
+            }
+        }
+        let latest_ids = latest_by_reference
+            .into_iter()
+            .filter(|(reference, _)| machine_references.contains(*reference))
+            .map(|(_, image)| image.id.clone())
+            .collect::<HashSet<_>>();
+
+        // machines run on overlay clones of the image volumes
+        let volumes = self.volume_agent.volume_list()?;
+        let candidates = images
+            .into_iter()
+            .map(|image| GcCandidate {
+                in_use: volumes
+                    .iter()
+                    .any(|volume| volume.cloned_from.as_deref() == Some(&image.volume_id))
+                    || machine_image_ids.contains(&image.id)
+                    || latest_ids.contains(&image.id)
+                    || pulls_in_flight.contains(&image.reference),
+                size_bytes: volumes
+                    .iter()
+                    .find(|volume| volume.id == image.volume_id)
+                    .map(|volume| volume.sparse_size)
+                    .unwrap_or(0),
+                image,
+            })
+            .collect::<Vec<_>>();
+
+        let disk_usage_bytes = self.extraction_cache.disk_usage().await?
+            + candidates.iter().map(|c| c.size_bytes).sum::<u64>();
+
+        let removals = gc::plan(&policy, candidates, disk_usage_bytes, now);
+
+        let mut report = ImageGcReport {
+            ran_at: now,
+            dry_run,
+            enabled: self.gc_policy.is_some(),
+            disk_usage_bytes,
+            removed: removals
+                .iter()
+                .map(|(candidate, reason)| ImageGcRemoval {
+                    image_id: candidate.image.id.clone(),
+                    reference: candidate.image.reference.clone(),
+                    digest: candidate.image.digest.clone(),
+                    pulled_at: candidate.image.timestamp,
+                    size_bytes: candidate.size_bytes,
+                    reason: reason.to_string(),
+                })
+                .collect(),
+            removed_layers: 0,
+        };
+
+        if dry_run {
+            return Ok(report);
+        }
+
+        for (candidate, reason) in removals.iter() {
+            let image = &candidate.image;
+            info!(
+                "removing image {} ({}@{}): {}",
+                image.id, image.reference, image.digest, reason
+            );
+
+            let key = Key::<Image>::not_namespaced()
+                .tenant(DEFAULT_AGENT_TENANT)
+                .collection(Collections::Image)
+                .key(&image.id);
+            self.store.delete(key)?;
+
+            if let Err(e) = self.volume_agent.volume_delete(&image.volume_id).await {
+                warn!("failed to delete volume of image {}: {}", image.id, e);
+            }
+        }
+
+        // root filesystems still used by another image stay, the ones loaded from archives are
+        // kept under keys of their own
+        let remaining = self.image_list()?;
+        let removed_digests = removals
+            .iter()
+            .map(|(candidate, _)| candidate.image.digest.clone())
+            .collect::<HashSet<_>>();
+        for digest in removed_digests {
+            if remaining.iter().any(|image| image.digest == digest) {
+                continue;
+            }
+            self.extraction_cache.remove(&digest).await?;
+        }
+
+        // a pull in progress may not have written its image yet
+        if self.pulls.lock().expect("pulls poisoned").is_empty() {
+            let keep = remaining
+                .iter()
+                .flat_map(|image| image.layer_ids.iter().cloned())
+                .collect::<HashSet<_>>();
+            let removed_layers = self
+                .extraction_cache
+                .remove_unreferenced_layers(&keep, Duration::from_millis(policy.min_age_millis()))
+                .await?;
+
+            for digest in removed_layers.iter() {
+                let key = Key::<ImageLayer>::not_namespaced()
+                    .tenant(DEFAULT_AGENT_TENANT)
+                    .collection(Collections::ImageLayer)
+                    .key(digest);
+                self.store.delete(key)?;
+            }
+            report.removed_layers = removed_layers.len();
+        }
+
+        if !report.removed.is_empty() || report.removed_layers > 0 {
+            info!(
+                "image gc removed {} images and {} layers",
+                report.removed.len(),
+                report.removed_layers
+            );
+        }
+        *self.last_gc_report.lock().expect("gc report poisoned") = Some(report.clone());
+
+        Ok(report)
+    }
+
+    /// Periodically collects unused images when a GC policy is set, stops once the agent is
+    /// dropped.
+    pub fn start_gc(self: &Arc<Self>) {
+        let Some(policy) = &self.gc_policy else {
+            return;
+        };
+
+        let interval_secs = policy.interval_secs();
+        let agent = Arc::downgrade(self);
+
+        tokio::spawn(async move {
+            // not right away, the machines using images are only known once the scheduler runs
+            let period = Duration::from_secs(interval_secs);
+            let mut interval =
+                tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+
+            loop {
+                interval.tick().await;
+
+                let Some(agent) = Weak::upgrade(&agent) else {
+                    break;
+                };
+
+                if let Err(e) = agent.collect_garbage(false).await {
+                    warn!("failed to collect unused images: {}", e);
+                }
+            }
+        });
+    }
+}
+
+/// Marks a pull as in progress until dropped.
+struct PullInFlight<'a> {
+    pulls: &'a Mutex<HashMap<String, usize>>,
+    reference: String,
+}
+
+impl Drop for PullInFlight<'_> {
+    fn drop(&mut self) {
+        let mut pulls = self.pulls.lock().expect("pulls in flight poisoned");
+        if let Some(count) = pulls.get_mut(&self.reference) {
+            *count -= 1;
+            if *count == 0 {
+                pulls.remove(&self.reference);
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::agent::volume::VolumeAgentConfig;
-    use std::str::FromStr;
 
     #[tokio::test]
     #[ignore]
@@ -306,6 +884,9 @@ mod tests {
             VolumeAgent::new(
                 VolumeAgentConfig {
                     base_path: volume_base_dir.path().to_str().unwrap().to_string(),
+                    backup_storage: None,
+                    encryption_key: None,
+                    default_quota_bytes: None,
                 },
                 store.clone(),
             )
@@ -328,10 +909,14 @@ mod tests {
             ImageAgentConfig {
                 base_path: images_base_dir.path().to_str().unwrap().to_string(),
                 internal_registry_service: "test".to_string(),
+                signature_policy: None,
+                label_policy: None,
+                gc_policy: None,
             },
             store,
             volume_agent,
             auth_handler,
+            Weak::new(),
         )
         .await
         .unwrap();
@@ -0,0 +1,410 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Result, bail};
+use flate2::read::GzDecoder;
+use oci_client::{
+    config::ConfigFile,
+    manifest::{OciDescriptor, OciImageIndex, OciImageManifest},
+};
+use ring::digest::{Context, SHA256};
+use serde::Deserialize;
+
+use crate::agent::image::oci;
+
+const DOCKER_MANIFEST_FILE: &str = "manifest.json";
+const OCI_INDEX_FILE: &str = "index.json";
+const DOCKER_LAYER_MEDIA_TYPE: &str = "application/vnd.docker.image.rootfs.diff.tar";
+const DOCKER_GZIP_LAYER_MEDIA_TYPE: &str = "application/vnd.docker.image.rootfs.diff.tar.gzip";
+const OCI_IMAGE_INDEX_MEDIA_TYPE: &str = "application/vnd.oci.image.index.v1+json";
+const DOCKER_MANIFEST_LIST_MEDIA_TYPE: &str =
+    "application/vnd.docker.distribution.manifest.list.v2+json";
+/// Indexes nested deeper than this are not followed.
+const MAX_INDEX_DEPTH: usize = 4;
+
+/// An entry of the `manifest.json` written by `docker save`.
+#[derive(Debug, Deserialize)]
+struct DockerArchiveManifest {
+    #[serde(rename = "Config")]
+    config: String,
+    #[serde(rename = "Layers")]
+    layers: Vec<String>,
+}
+
+/// An image read from an unpacked `docker save` or OCI layout archive.
+#[derive(Debug)]
+pub struct ImageArchive {
+    pub manifest: OciImageManifest,
+    /// Manifest digest for OCI layouts, config digest (the image id) for `docker save` archives.
+    pub digest: String,
+    pub config: ConfigFile,
+    /// Files of the layers, in the order of the manifest.
+    pub layer_paths: Vec<PathBuf>,
+}
+
+/// Unpacks an archive, compressed with gzip or not, to `dest_dir`. Only files and directories
+/// are unpacked, the blobs are read and moved into the cache afterwards and a link could point
+/// them anywhere on the host.
+pub fn unpack_archive(archive_path: &Path, dest_dir: &Path) -> Result<()> {
+    let reader = BufReader::new(File::open(archive_path)?);
+    let reader: Box<dyn Read> = if is_gzip(archive_path)? {
+        Box::new(GzDecoder::new(reader))
+    } else {
+        Box::new(reader)
+    };
+
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_pax_global_extensions() {
+            continue;
+        }
+        if !entry_type.is_file() && !entry_type.is_dir() {
+            bail!(
+                "unsupported entry in archive: {} ({:?})",
+                entry.path()?.display(),
+                entry_type
+            );
+        }
+
+        entry.unpack_in(dest_dir)?;
+    }
+
+    Ok(())
+}
+
+impl ImageArchive {
+    /// Reads the image of an unpacked archive. `docker save` archives are recognized by their
+    /// `manifest.json`, which newer Docker versions write next to an OCI layout.
+    pub fn open(dir: &Path) -> Result<Self> {
+        if dir.join(DOCKER_MANIFEST_FILE).exists() {
+            Self::open_docker(dir)
+        } else if dir.join(OCI_INDEX_FILE).exists() {
+            Self::open_oci_layout(dir)
+        } else {
+            bail!("not a docker save or OCI layout archive");
+        }
+    }
+
+    fn open_docker(dir: &Path) -> Result<Self> {
+        let manifests: Vec<DockerArchiveManifest> =
+            serde_json::from_slice(&std::fs::read(dir.join(DOCKER_MANIFEST_FILE))?)?;
+        let manifest = match manifests.as_slice() {
+            [manifest] => manifest,
+            [] => bail!("the archive holds no image"),
+            _ => bail!(
+                "the archive holds {} images, save a single one",
+                manifests.len()
+            ),
+        };
+
+        let config_path = archive_path(dir, &manifest.config)?;
+        let config_bytes = std::fs::read(&config_path)?;
+        let config: ConfigFile = serde_json::from_slice(&config_bytes)?;
+
+        let mut layers = Vec::new();
+        let mut layer_paths = Vec::new();
+        for layer in manifest.layers.iter() {
+            let layer_path = archive_path(dir, layer)?;
+            let media_type = if is_gzip(&layer_path)? {
+                DOCKER_GZIP_LAYER_MEDIA_TYPE
+            } else {
+                DOCKER_LAYER_MEDIA_TYPE
+            };
+
+            layers.push(OciDescriptor {
+                media_type: media_type.to_string(),
+                digest: file_digest(&layer_path)?,
+                size: std::fs::metadata(&layer_path)?.len() as i64,
+                ..Default::default()
+            });
+            layer_paths.push(layer_path);
+        }
+
+        Ok(Self {
+            manifest: OciImageManifest {
+                config: OciDescriptor {
+                    digest: bytes_digest(&config_bytes),
+                    size: config_bytes.len() as i64,
+                    ..Default::default()
+                },
+                layers,
+                ..Default::default()
+            },
+            digest: bytes_digest(&config_bytes),
+            config,
+            layer_paths,
+        })
+    }
+
+    fn open_oci_layout(dir: &Path) -> Result<Self> {
+        let mut index: OciImageIndex =
+            serde_json::from_slice(&std::fs::read(dir.join(OCI_INDEX_FILE))?)?;
+
+        for _ in 0..MAX_INDEX_DEPTH {
+            let entry = if index.manifests.len() == 1 {
+                index.manifests[0].clone()
+            } else {
+                let Some(digest) = oci::host_platform_resolver(&index.manifests) else {
+                    bail!(
+                        "the archive holds no image for linux/{}",
+                        oci::host_architecture()
+                    );
+                };
+                index
+                    .manifests
+                    .into_iter()
+                    .find(|entry| entry.digest == digest)
+                    .expect("resolved entry is in the index")
+            };
+
+            let blob = read_blob(dir, &entry.digest)?;
+            if entry.media_type == OCI_IMAGE_INDEX_MEDIA_TYPE
+                || entry.media_type == DOCKER_MANIFEST_LIST_MEDIA_TYPE
+            {
+                index = serde_json::from_slice(&blob)?;
+                continue;
+            }
+
+            let manifest: OciImageManifest = serde_json::from_slice(&blob)?;
+            let config: ConfigFile =
+                serde_json::from_slice(&read_blob(dir, &manifest.config.digest)?)?;
+            let mut layer_paths = Vec::new();
+            for layer in manifest.layers.iter() {
+                let layer_path = blob_path(dir, &layer.digest)?;
+                check_digest(&layer.digest, &file_digest(&layer_path)?)?;
+                layer_paths.push(layer_path);
+            }
+
+            return Ok(Self {
+                manifest,
+                digest: entry.digest,
+                config,
+                layer_paths,
+            });
+        }
+
+        bail!("the archive nests indexes deeper than {}", MAX_INDEX_DEPTH);
+    }
+}
+
+/// Path of a file named by the archive, which must stay inside it.
+fn archive_path(dir: &Path, name: &str) -> Result<PathBuf> {
+    let path = Path::new(name);
+    if path.is_absolute()
+        || path
+            .components()
+            .any(|c| matches!(c, std::path::Component::ParentDir))
+    {
+        bail!("invalid path in archive: {}", name);
+    }
+
+    Ok(dir.join(path))
+}
+
+fn blob_path(dir: &Path, digest: &str) -> Result<PathBuf> {
+    let Some((algorithm, hex)) = digest.split_once(':') else {
+        bail!("invalid digest: {}", digest);
+    };
+
+    archive_path(dir, &format!("blobs/{}/{}", algorithm, hex))
+}
+
+/// Reads a blob of an OCI layout, its digest is only trusted once the content matches it.
+fn read_blob(dir: &Path, digest: &str) -> Result<Vec<u8>> {
+    let blob = std::fs::read(blob_path(dir, digest)?)?;
+    check_digest(digest, &bytes_digest(&blob))?;
+
+    Ok(blob)
+}
+
+fn check_digest(expected: &str, actual: &str) -> Result<()> {
+    if expected != actual {
+        bail!("blob {} doesn't match its digest ({})", expected, actual);
+    }
+
+    Ok(())
+}
+
+fn is_gzip(path: &Path) -> Result<bool> {
+    let mut magic = [0u8; 2];
+    let read = File::open(path)?.read(&mut magic)?;
+    Ok(read == 2 && magic == [0x1f, 0x8b])
+}
+
+fn bytes_digest(bytes: &[u8]) -> String {
+    format!(
+        "sha256:{}",
+        hex::encode(ring::digest::digest(&SHA256, bytes).as_ref())
+    )
+}
+
+fn file_digest(path: &Path) -> Result<String> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut context = Context::new(&SHA256);
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        context.update(&buffer[..read]);
+    }
+
+    Ok(format!("sha256:{}", hex::encode(context.finish().as_ref())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_archive_path() {
+        let dir = Path::new("/archive");
+
+        assert_eq!(
+            archive_path(dir, "abc/layer.tar").unwrap(),
+            PathBuf::from("/archive/abc/layer.tar")
+        );
+        assert!(archive_path(dir, "../etc/passwd").is_err());
+        assert!(archive_path(dir, "/etc/passwd").is_err());
+        assert_eq!(
+            blob_path(dir, "sha256:abc").unwrap(),
+            PathBuf::from("/archive/blobs/sha256/abc")
+        );
+        assert!(blob_path(dir, "abc").is_err());
+    }
+
+    #[test]
+    fn test_open_docker_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("config.json"),
+            br#"{"architecture":"amd64","os":"linux","rootfs":{"type":"layers","diff_ids":[]}}"#,
+        )
+        .unwrap();
+        std::fs::create_dir(dir.path().join("abc")).unwrap();
+        std::fs::write(dir.path().join("abc/layer.tar"), b"layer").unwrap();
+        std::fs::write(
+            dir.path().join(DOCKER_MANIFEST_FILE),
+            br#"[{"Config":"config.json","RepoTags":["app:dev"],"Layers":["abc/layer.tar"]}]"#,
+        )
+        .unwrap();
+
+        let archive = ImageArchive::open(dir.path()).unwrap();
+
+        assert_eq!(archive.layer_paths, vec![dir.path().join("abc/layer.tar")]);
+        assert_eq!(archive.manifest.layers.len(), 1);
+        assert_eq!(
+            archive.manifest.layers[0].media_type,
+            DOCKER_LAYER_MEDIA_TYPE
+        );
+        assert_eq!(archive.manifest.layers[0].digest, bytes_digest(b"layer"));
+        assert_eq!(
+            archive.digest,
+            bytes_digest(&std::fs::read(dir.path().join("config.json")).unwrap())
+        );
+    }
+
+    fn write_blob(dir: &Path, bytes: &[u8]) -> String {
+        let digest = bytes_digest(bytes);
+        let path = blob_path(dir, &digest).unwrap();
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, bytes).unwrap();
+        digest
+    }
+
+    fn write_oci_layout(dir: &Path) -> String {
+        let layer = write_blob(dir, b"layer");
+        let config = write_blob(
+            dir,
+            br#"{"architecture":"amd64","os":"linux","rootfs":{"type":"layers","diff_ids":[]}}"#,
+        );
+        let manifest = write_blob(
+            dir,
+            format!(
+                r#"{{"schemaVersion":2,"mediaType":"application/vnd.oci.image.manifest.v1+json","config":{{"mediaType":"application/vnd.oci.image.config.v1+json","digest":"{}","size":80}},"layers":[{{"mediaType":"application/vnd.oci.image.layer.v1.tar","digest":"{}","size":5}}]}}"#,
+                config, layer
+            )
+            .as_bytes(),
+        );
+        std::fs::write(
+            dir.join(OCI_INDEX_FILE),
+            format!(
+                r#"{{"schemaVersion":2,"manifests":[{{"mediaType":"application/vnd.oci.image.manifest.v1+json","digest":"{}","size":300}}]}}"#,
+                manifest
+            ),
+        )
+        .unwrap();
+
+        manifest
+    }
+
+    #[test]
+    fn test_open_oci_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = write_oci_layout(dir.path());
+
+        let archive = ImageArchive::open(dir.path()).unwrap();
+
+        assert_eq!(archive.digest, manifest);
+        assert_eq!(archive.manifest.layers[0].digest, bytes_digest(b"layer"));
+    }
+
+    #[test]
+    fn test_open_oci_layout_rejects_mismatched_digests() {
+        let dir = tempfile::tempdir().unwrap();
+        write_oci_layout(dir.path());
+        std::fs::write(
+            blob_path(dir.path(), &bytes_digest(b"layer")).unwrap(),
+            b"not the layer",
+        )
+        .unwrap();
+
+        assert!(ImageArchive::open(dir.path()).is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = write_oci_layout(dir.path());
+        // an index claiming the digest of another image
+        let claimed = bytes_digest(b"another image");
+        std::fs::rename(
+            blob_path(dir.path(), &manifest).unwrap(),
+            blob_path(dir.path(), &claimed).unwrap(),
+        )
+        .unwrap();
+        let index = std::fs::read_to_string(dir.path().join(OCI_INDEX_FILE)).unwrap();
+        std::fs::write(
+            dir.path().join(OCI_INDEX_FILE),
+            index.replace(&manifest, &claimed),
+        )
+        .unwrap();
+
+        assert!(ImageArchive::open(dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_unpack_archive_rejects_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("image.tar");
+
+        let mut builder = tar::Builder::new(File::create(&archive_path).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "index.json", "/etc/passwd")
+            .unwrap();
+        builder.finish().unwrap();
+        drop(builder);
+
+        let dest_dir = dir.path().join("unpacked");
+        std::fs::create_dir(&dest_dir).unwrap();
+
+        assert!(unpack_archive(&archive_path, &dest_dir).is_err());
+        assert!(std::fs::symlink_metadata(dest_dir.join("index.json")).is_err());
+    }
+}
@@ -0,0 +1,725 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Result, bail};
+use futures_util::{StreamExt, TryStreamExt, future, stream};
+use oci_client::{
+    Reference,
+    config::ConfigFile,
+    manifest::{OciDescriptor, OciImageManifest},
+};
+use serde::{Deserialize, Serialize};
+use tokio::{process::Command, sync::mpsc, task::spawn_blocking};
+use tracing::{info, warn};
+
+use crate::{
+    agent::{
+        image::{
+            archive::{self, ImageArchive},
+            credentials::OciCredentialsProvider,
+            oci,
+        },
+        volume::fs,
+    },
+    resources::core::{ImageLayerProgress, ImagePullProgress},
+};
+
+const ROOTFS_DIR: &str = "rootfs";
+const IMAGE_METADATA_FILE: &str = "image.json";
+const MANIFEST_FILE: &str = "manifest.json";
+const CONFIG_FILE: &str = "config.json";
+const OCI_CONFIG_PATH: &str = "etc/lttle/oci-config.json";
+/// Extracted images loaded from archives are kept apart from the ones addressed by digest.
+const LOADED_IMAGE_PREFIX: &str = "loaded-";
+/// Layers of an image downloaded at the same time.
+const MAX_PARALLEL_LAYER_PULLS: usize = 4;
+
+/// An image unpacked in the extraction cache, ready to be turned into a volume.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExtractedImage {
+    pub reference: String,
+    pub digest: String,
+    pub layer_ids: Vec<String>,
+    pub labels: BTreeMap<String, String>,
+    pub annotations: BTreeMap<String, String>,
+    /// Directory of the image in the cache, its digest unless it was loaded from an archive.
+    #[serde(skip)]
+    pub key: String,
+    #[serde(skip)]
+    pub rootfs_path: PathBuf,
+}
+
+/// Progress of an extraction, updated as layers are downloaded and unpacked.
+#[derive(Debug, Default)]
+pub struct ExtractionProgress {
+    layers: Mutex<Vec<ImageLayerProgress>>,
+}
+
+impl ExtractionProgress {
+    fn start(&self, manifest: &OciImageManifest) {
+        *self.layers.lock().expect("extraction progress poisoned") = manifest
+            .layers
+            .iter()
+            .map(|layer| ImageLayerProgress {
+                digest: layer.digest.clone(),
+                size_bytes: layer.size.max(0) as u64,
+                downloaded_bytes: 0,
+                unpacked: false,
+            })
+            .collect();
+    }
+
+    fn update(&self, digest: &str, f: impl Fn(&mut ImageLayerProgress)) {
+        let mut layers = self.layers.lock().expect("extraction progress poisoned");
+        for layer in layers.iter_mut().filter(|layer| layer.digest == digest) {
+            f(layer);
+        }
+    }
+
+    fn downloaded(&self, digest: &str, bytes: u64) {
+        self.update(digest, |layer| layer.downloaded_bytes += bytes);
+    }
+
+    fn pulled(&self, digest: &str) {
+        self.update(digest, |layer| layer.downloaded_bytes = layer.size_bytes);
+    }
+
+    fn unpacked(&self, digest: &str) {
+        self.update(digest, |layer| layer.unpacked = true);
+    }
+
+    pub fn snapshot(&self, reference: &str) -> ImagePullProgress {
+        ImagePullProgress {
+            reference: Some(reference.to_string()),
+            layers: self
+                .layers
+                .lock()
+                .expect("extraction progress poisoned")
+                .clone(),
+        }
+    }
+}
+
+/// Layers and root filesystems of images, addressed by digest. The daemon and
+/// `convert-image-tool` share the same layout, so an image extracted ahead of time is reused as
+/// is, even when its registry can't be reached.
+///
+/// ```text
+/// <base>/layers/<layer digest>
+/// <base>/extracted/<manifest digest>/{image.json,manifest.json,config.json,rootfs/}
+/// <base>/extracted/loaded-<tenant and digest hash>/...
+/// <base>/references/<tenant and reference hash>  -> manifest digest
+/// <base>/loaded/<tenant and reference hash>  -> key of an image loaded from an archive
+/// ```
+///
+/// Images loaded from archives are extracted under a key of their own, whatever digest the
+/// archive claims, so they never stand in for the image another tenant pulls.
+///
+/// Layers are shared by every image referencing them, and an image built on top of an extracted
+/// one starts from a copy of its root filesystem instead of unpacking the shared layers again.
+#[derive(Debug, Clone)]
+pub struct ExtractionCache {
+    layers_path: PathBuf,
+    extracted_path: PathBuf,
+    references_path: PathBuf,
+    loaded_path: PathBuf,
+    /// Layers being pulled, so images sharing a layer download it once.
+    pulling_layers: Arc<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>>,
+}
+
+impl ExtractionCache {
+    pub async fn new(base_path: impl AsRef<Path>) -> Result<Self> {
+        let base_path = base_path.as_ref();
+        let cache = Self {
+            layers_path: base_path.join("layers"),
+            extracted_path: base_path.join("extracted"),
+            references_path: base_path.join("references"),
+            loaded_path: base_path.join("loaded"),
+            pulling_layers: Arc::new(Mutex::new(HashMap::new())),
+        };
+
+        for path in [
+            &cache.layers_path,
+            &cache.extracted_path,
+            &cache.references_path,
+            &cache.loaded_path,
+        ] {
+            tokio::fs::create_dir_all(path).await?;
+        }
+
+        Ok(cache)
+    }
+
+    pub fn layer_path(&self, digest: &str) -> PathBuf {
+        self.layers_path.join(digest)
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<ExtractedImage>> {
+        let image_path = self.extracted_path.join(key);
+        let metadata_path = image_path.join(IMAGE_METADATA_FILE);
+        if !metadata_path.exists() {
+            return Ok(None);
+        }
+
+        let mut image: ExtractedImage =
+            serde_json::from_slice(&tokio::fs::read(metadata_path).await?)?;
+        image.key = key.to_string();
+        image.rootfs_path = image_path.join(ROOTFS_DIR);
+
+        Ok(Some(image))
+    }
+
+    /// Manifest and config of an extracted image.
+    pub async fn get_manifest(&self, key: &str) -> Result<Option<(OciImageManifest, ConfigFile)>> {
+        let image_path = self.extracted_path.join(key);
+        let manifest_path = image_path.join(MANIFEST_FILE);
+        let config_path = image_path.join(CONFIG_FILE);
+        if !manifest_path.exists() || !config_path.exists() {
+            return Ok(None);
+        }
+
+        let manifest = serde_json::from_slice(&tokio::fs::read(manifest_path).await?)?;
+        let config = serde_json::from_slice(&tokio::fs::read(config_path).await?)?;
+
+        Ok(Some((manifest, config)))
+    }
+
+    /// The image last extracted for `reference` by `tenant`, or else by `convert-image-tool`.
+    /// Pulls of other tenants never show up, their credentials may reach images `tenant` can't.
+    pub async fn get_by_reference(
+        &self,
+        tenant: &str,
+        reference: &str,
+    ) -> Result<Option<ExtractedImage>> {
+        for reference_path in [
+            self.reference_path(Some(tenant), reference),
+            self.reference_path(None, reference),
+        ] {
+            if !reference_path.exists() {
+                continue;
+            }
+
+            let digest = tokio::fs::read_to_string(reference_path).await?;
+            return self.get(digest.trim()).await;
+        }
+
+        Ok(None)
+    }
+
+    /// The image `tenant` loaded from an archive for `reference`.
+    pub async fn get_loaded(
+        &self,
+        tenant: &str,
+        reference: &str,
+    ) -> Result<Option<ExtractedImage>> {
+        let loaded_path = self.loaded_reference_path(tenant, reference);
+        if !loaded_path.exists() {
+            return Ok(None);
+        }
+
+        let key = tokio::fs::read_to_string(loaded_path).await?;
+        self.get(key.trim()).await
+    }
+
+    /// A file to receive an uploaded archive, on the same filesystem as the cache.
+    pub fn archive_file(&self) -> Result<tempfile::NamedTempFile> {
+        Ok(tempfile::NamedTempFile::new_in(&self.extracted_path)?)
+    }
+
+    /// Pulls the missing layers of the image and unpacks them, unless the image was already
+    /// extracted. Without a `tenant`, the image was seeded by the operator and every tenant can
+    /// fall back to it.
+    pub async fn extract(
+        &self,
+        credentials_provider: &impl OciCredentialsProvider,
+        tenant: Option<&str>,
+        reference: &Reference,
+        manifest: &OciImageManifest,
+        digest: &str,
+        config: ConfigFile,
+        progress: &ExtractionProgress,
+    ) -> Result<ExtractedImage> {
+        let image = self
+            .unpack_image(
+                credentials_provider,
+                reference,
+                manifest,
+                digest,
+                digest,
+                config,
+                progress,
+            )
+            .await?;
+        self.set_reference(tenant, reference, digest).await?;
+
+        Ok(image)
+    }
+
+    /// Extracts the image of a `docker save` or OCI layout archive for `tenant`. Its layers join
+    /// the cache as if they were pulled, so none is downloaded, their digests were checked when
+    /// the archive was opened. The image is only found through `get_loaded`, a tenant can't
+    /// shadow the registry images of others.
+    pub async fn extract_archive(
+        &self,
+        credentials_provider: &impl OciCredentialsProvider,
+        tenant: &str,
+        reference: &Reference,
+        archive_path: &Path,
+    ) -> Result<ExtractedImage> {
+        let archive_dir = tempfile::tempdir_in(&self.extracted_path)?;
+
+        let archive = {
+            let archive_path = archive_path.to_path_buf();
+            let archive_dir = archive_dir.path().to_path_buf();
+            spawn_blocking(move || {
+                archive::unpack_archive(&archive_path, &archive_dir)?;
+                ImageArchive::open(&archive_dir)
+            })
+            .await??
+        };
+
+        for (layer, layer_file) in archive
+            .manifest
+            .layers
+            .iter()
+            .zip(archive.layer_paths.iter())
+        {
+            let layer_path = self.layer_path(&layer.digest);
+            if !layer_path.exists() {
+                tokio::fs::rename(layer_file, layer_path).await?;
+            }
+        }
+
+        let image = self
+            .unpack_image(
+                credentials_provider,
+                reference,
+                &archive.manifest,
+                &loaded_image_key(tenant, &archive.digest),
+                &archive.digest,
+                archive.config,
+                &ExtractionProgress::default(),
+            )
+            .await?;
+        tokio::fs::write(
+            self.loaded_reference_path(tenant, &reference.to_string()),
+            &image.key,
+        )
+        .await?;
+
+        info!(
+            "image {} loaded from an archive for {} ({})",
+            image.digest, reference, tenant
+        );
+        Ok(image)
+    }
+
+    /// Unpacks the image to the `key` directory, unless it is already there.
+    async fn unpack_image(
+        &self,
+        credentials_provider: &impl OciCredentialsProvider,
+        reference: &Reference,
+        manifest: &OciImageManifest,
+        key: &str,
+        digest: &str,
+        config: ConfigFile,
+        progress: &ExtractionProgress,
+    ) -> Result<ExtractedImage> {
+        if let Some(image) = self.get(key).await? {
+            info!("image {} already extracted for {}", digest, reference);
+            return Ok(image);
+        }
+
+        for layer in manifest.layers.iter() {
+            if !oci::is_layer_supported(layer).await? {
+                bail!(
+                    "layer {} of {} has an unsupported media type: {}",
+                    layer.digest,
+                    reference,
+                    layer.media_type
+                );
+            }
+        }
+
+        progress.start(manifest);
+
+        let layer_ids = manifest
+            .layers
+            .iter()
+            .map(|l| l.digest.clone())
+            .collect::<Vec<_>>();
+
+        let staging_dir = tempfile::tempdir_in(&self.extracted_path)?;
+        let rootfs_path = staging_dir.path().join(ROOTFS_DIR);
+
+        let mut reused_layers = 0;
+        if let Some(base) = self.find_base_image(&layer_ids).await? {
+            match copy_rootfs(&base.rootfs_path, &rootfs_path).await {
+                Ok(()) => {
+                    info!(
+                        "reusing {} layers of image {} for {}",
+                        base.layer_ids.len(),
+                        base.digest,
+                        digest
+                    );
+                    reused_layers = base.layer_ids.len();
+                    for layer_id in base.layer_ids.iter() {
+                        progress.unpacked(layer_id);
+                    }
+                }
+                Err(e) => {
+                    warn!(
+                        "failed to copy the root filesystem of {}: {}",
+                        base.digest, e
+                    );
+                    if rootfs_path.exists() {
+                        tokio::fs::remove_dir_all(&rootfs_path).await?;
+                    }
+                }
+            }
+        }
+
+        // layers are pulled in parallel and each one is unpacked, in order, as soon as it and
+        // the ones below it are complete. They land in the layer cache first, other images reuse
+        // them, and are decompressed from there as a stream, never held in memory whole
+        let (pulled_tx, mut pulled_rx) = mpsc::unbounded_channel();
+        let pull = async move {
+            stream::iter(manifest.layers.iter().enumerate())
+                .map(|(index, layer)| async move {
+                    self.pull_layer(credentials_provider, reference, layer, progress)
+                        .await?;
+                    progress.pulled(&layer.digest);
+                    anyhow::Ok((index, layer))
+                })
+                .buffered(MAX_PARALLEL_LAYER_PULLS)
+                .try_for_each(|(index, layer)| {
+                    if index >= reused_layers {
+                        let _ = pulled_tx.send(layer);
+                    }
+                    future::ready(Ok(()))
+                })
+                .await
+        };
+        let unpack = async {
+            while let Some(layer) = pulled_rx.recv().await {
+                oci::uncompress_layer(layer, self.layer_path(&layer.digest), &rootfs_path).await?;
+                progress.unpacked(&layer.digest);
+            }
+            anyhow::Ok(())
+        };
+        tokio::try_join!(pull, unpack)?;
+
+        tokio::fs::write(
+            staging_dir.path().join(CONFIG_FILE),
+            serde_json::to_vec_pretty(&config)?,
+        )
+        .await?;
+
+        // config for takeoff, the one of a reused image must not leak into this one
+        let config_path = rootfs_path.join(OCI_CONFIG_PATH);
+        if let Some(config) = &config.config {
+            tokio::fs::create_dir_all(config_path.parent().unwrap()).await?;
+            tokio::fs::write(config_path, serde_json::to_string_pretty(config)?).await?;
+        } else if config_path.exists() {
+            tokio::fs::remove_file(config_path).await?;
+        }
+
+        let image_path = self.extracted_path.join(key);
+        let image = ExtractedImage {
+            reference: reference.to_string(),
+            digest: digest.to_string(),
+            layer_ids,
+            labels: config
+                .config
+                .and_then(|c| c.labels)
+                .unwrap_or_default()
+                .into_iter()
+                .collect(),
+            annotations: manifest
+                .annotations
+                .clone()
+                .unwrap_or_default()
+                .into_iter()
+                .collect(),
+            key: key.to_string(),
+            rootfs_path: image_path.join(ROOTFS_DIR),
+        };
+        tokio::fs::write(
+            staging_dir.path().join(MANIFEST_FILE),
+            serde_json::to_vec_pretty(manifest)?,
+        )
+        .await?;
+        tokio::fs::write(
+            staging_dir.path().join(IMAGE_METADATA_FILE),
+            serde_json::to_vec_pretty(&image)?,
+        )
+        .await?;
+
+        // another extraction of the same image may have won the race, the staging directory
+        // goes away either way
+        if let Err(e) = tokio::fs::rename(staging_dir.path(), &image_path).await {
+            if !image_path.join(IMAGE_METADATA_FILE).exists() {
+                return Err(e.into());
+            }
+        }
+
+        info!("image {} extracted for {}", digest, reference);
+        Ok(image)
+    }
+
+    /// Pulls a layer unless it is already cached. Layers only show up under their digest once
+    /// complete.
+    async fn pull_layer(
+        &self,
+        credentials_provider: &impl OciCredentialsProvider,
+        reference: &Reference,
+        layer: &OciDescriptor,
+        progress: &ExtractionProgress,
+    ) -> Result<()> {
+        let lock = self
+            .pulling_layers
+            .lock()
+            .expect("pulling layers poisoned")
+            .entry(layer.digest.clone())
+            .or_default()
+            .clone();
+
+        let result = {
+            let _guard = lock.lock().await;
+            self.pull_missing_layer(credentials_provider, reference, layer, progress)
+                .await
+        };
+
+        drop(lock);
+        self.pulling_layers
+            .lock()
+            .expect("pulling layers poisoned")
+            .retain(|_, lock| Arc::strong_count(lock) > 1);
+
+        result
+    }
+
+    async fn pull_missing_layer(
+        &self,
+        credentials_provider: &impl OciCredentialsProvider,
+        reference: &Reference,
+        layer: &OciDescriptor,
+        progress: &ExtractionProgress,
+    ) -> Result<()> {
+        let layer_path = self.layer_path(&layer.digest);
+        if layer_path.exists() {
+            info!("layer {} already pulled", layer.digest);
+            return Ok(());
+        }
+
+        let partial_path = self.layers_path.join(format!("{}.partial", layer.digest));
+        oci::pull_layer(
+            credentials_provider,
+            reference,
+            layer,
+            &partial_path,
+            &|bytes| progress.downloaded(&layer.digest, bytes),
+        )
+        .await?;
+        tokio::fs::rename(&partial_path, &layer_path).await?;
+
+        Ok(())
+    }
+
+    /// Bytes used by the layers and extracted images.
+    pub async fn disk_usage(&self) -> Result<u64> {
+        let layers_path = self.layers_path.clone();
+        let extracted_path = self.extracted_path.clone();
+
+        spawn_blocking(move || {
+            Ok(fs::dir_size_in_bytes_recursive(layers_path)?
+                + fs::dir_size_in_bytes_recursive(extracted_path)?)
+        })
+        .await?
+    }
+
+    /// Removes an extracted image. Its layers stay until `remove_unreferenced_layers`.
+    pub async fn remove(&self, digest: &str) -> Result<()> {
+        let image_path = self.extracted_path.join(digest);
+        if !image_path.exists() {
+            return Ok(());
+        }
+
+        // hidden first, so the image is never seen half removed
+        let removing_path = self.extracted_path.join(format!(".{}.removing", digest));
+        tokio::fs::rename(&image_path, &removing_path).await?;
+        tokio::fs::remove_dir_all(&removing_path).await?;
+
+        Ok(())
+    }
+
+    /// Removes the layers no extracted image and none of `keep` refers to, unless written less
+    /// than `min_age` ago. Returns the digests of the removed layers.
+    pub async fn remove_unreferenced_layers(
+        &self,
+        keep: &HashSet<String>,
+        min_age: Duration,
+    ) -> Result<Vec<String>> {
+        let mut referenced = keep.clone();
+
+        let mut entries = tokio::fs::read_dir(&self.extracted_path).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let Some(key) = entry
+                .file_name()
+                .to_str()
+                .filter(|name| !name.starts_with('.'))
+                .map(|name| name.to_string())
+            else {
+                continue;
+            };
+            if let Some(image) = self.get(&key).await? {
+                referenced.extend(image.layer_ids);
+            }
+        }
+
+        let mut removed = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.layers_path).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let Some(digest) = entry.file_name().to_str().map(|name| name.to_string()) else {
+                continue;
+            };
+            // partial downloads belong to pulls in progress
+            if digest.ends_with(".partial") || referenced.contains(&digest) {
+                continue;
+            }
+
+            let age = entry
+                .metadata()
+                .await?
+                .modified()?
+                .elapsed()
+                .unwrap_or_default();
+            if age < min_age {
+                continue;
+            }
+
+            tokio::fs::remove_file(entry.path()).await?;
+            removed.push(digest);
+        }
+
+        Ok(removed)
+    }
+
+    /// The extracted image with the most layers that are all the first layers of `layer_ids`.
+    async fn find_base_image(&self, layer_ids: &[String]) -> Result<Option<ExtractedImage>> {
+        let mut base: Option<ExtractedImage> = None;
+
+        let mut entries = tokio::fs::read_dir(&self.extracted_path).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            // staging directories of extractions in progress are hidden, and the images of a
+            // tenant's archive are no base for the images of others
+            let Some(key) = entry
+                .file_name()
+                .to_str()
+                .filter(|name| !name.starts_with('.') && !name.starts_with(LOADED_IMAGE_PREFIX))
+                .map(|name| name.to_string())
+            else {
+                continue;
+            };
+            let Some(image) = self.get(&key).await? else {
+                continue;
+            };
+
+            if !is_layer_prefix(&image.layer_ids, layer_ids) {
+                continue;
+            }
+
+            if base
+                .as_ref()
+                .is_none_or(|base| base.layer_ids.len() < image.layer_ids.len())
+            {
+                base = Some(image);
+            }
+        }
+
+        Ok(base)
+    }
+
+    async fn set_reference(
+        &self,
+        tenant: Option<&str>,
+        reference: &Reference,
+        digest: &str,
+    ) -> Result<()> {
+        tokio::fs::write(self.reference_path(tenant, &reference.to_string()), digest).await?;
+        Ok(())
+    }
+
+    fn reference_path(&self, tenant: Option<&str>, reference: &str) -> PathBuf {
+        let key = match tenant {
+            Some(tenant) => format!("{}\n{}", tenant, reference),
+            None => reference.to_string(),
+        };
+        self.references_path
+            .join(blake3::hash(key.as_bytes()).to_hex().as_str())
+    }
+
+    fn loaded_reference_path(&self, tenant: &str, reference: &str) -> PathBuf {
+        let key = format!("{}\n{}", tenant, reference);
+        self.loaded_path
+            .join(blake3::hash(key.as_bytes()).to_hex().as_str())
+    }
+}
+
+fn loaded_image_key(tenant: &str, digest: &str) -> String {
+    let key = format!("{}\n{}", tenant, digest);
+    format!(
+        "{}{}",
+        LOADED_IMAGE_PREFIX,
+        blake3::hash(key.as_bytes()).to_hex()
+    )
+}
+
+fn is_layer_prefix(base: &[String], layer_ids: &[String]) -> bool {
+    !base.is_empty() && base.len() <= layer_ids.len() && layer_ids.starts_with(base)
+}
+
+/// Copies a root filesystem, sharing the file data where the filesystem supports it.
+async fn copy_rootfs(source: &Path, destination: &Path) -> Result<()> {
+    let output = Command::new("cp")
+        .arg("-a")
+        .arg("--reflink=auto")
+        .arg(source)
+        .arg(destination)
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        bail!(
+            "cp exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn test_is_layer_prefix() {
+        let layers = ids(&["a", "b", "c"]);
+
+        assert!(is_layer_prefix(&ids(&["a"]), &layers));
+        assert!(is_layer_prefix(&ids(&["a", "b", "c"]), &layers));
+        assert!(!is_layer_prefix(&ids(&["b"]), &layers));
+        assert!(!is_layer_prefix(&ids(&["a", "b", "c", "d"]), &layers));
+        assert!(!is_layer_prefix(&ids(&[]), &layers));
+    }
+}
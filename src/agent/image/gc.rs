@@ -0,0 +1,237 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    agent::image::Image,
+    constants::{
+        DEFAULT_IMAGE_GC_INTERVAL_SECS, DEFAULT_IMAGE_GC_KEEP_PER_REFERENCE,
+        DEFAULT_IMAGE_GC_MIN_AGE_MINS,
+    },
+};
+
+const BYTES_PER_GIB: u64 = 1024 * 1024 * 1024;
+
+/// Which images are removed. Images used by a machine are always kept.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ImageGcPolicy {
+    /// Digests kept for each reference, newest first. Default: 2
+    #[serde(rename = "keep-per-reference")]
+    pub keep_per_reference: Option<usize>,
+    /// Disk the images may use, unused ones are removed oldest first above it.
+    #[serde(rename = "max-disk-usage-gib")]
+    pub max_disk_usage_gib: Option<u64>,
+    /// Images pulled more recently than this are never removed. Default: 60
+    #[serde(rename = "min-age-mins")]
+    pub min_age_mins: Option<u64>,
+    /// Default: 600
+    #[serde(rename = "interval-secs")]
+    pub interval_secs: Option<u64>,
+}
+
+impl ImageGcPolicy {
+    pub fn keep_per_reference(&self) -> usize {
+        self.keep_per_reference
+            .unwrap_or(DEFAULT_IMAGE_GC_KEEP_PER_REFERENCE)
+    }
+
+    pub fn max_disk_usage_bytes(&self) -> Option<u64> {
+        self.max_disk_usage_gib.map(|gib| gib * BYTES_PER_GIB)
+    }
+
+    pub fn min_age_millis(&self) -> u64 {
+        self.min_age_mins.unwrap_or(DEFAULT_IMAGE_GC_MIN_AGE_MINS) * 60 * 1000
+    }
+
+    pub fn interval_secs(&self) -> u64 {
+        self.interval_secs.unwrap_or(DEFAULT_IMAGE_GC_INTERVAL_SECS)
+    }
+}
+
+/// A stored image as the collection sees it.
+#[derive(Debug, Clone)]
+pub struct GcCandidate {
+    pub image: Image,
+    pub in_use: bool,
+    pub size_bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GcReason {
+    Superseded,
+    DiskUsage,
+}
+
+impl std::fmt::Display for GcReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GcReason::Superseded => write!(f, "superseded"),
+            GcReason::DiskUsage => write!(f, "disk-usage"),
+        }
+    }
+}
+
+/// The images to remove: the ones past the digests kept for their reference, then the oldest
+/// ones while the disk usage is above the limit.
+pub fn plan(
+    policy: &ImageGcPolicy,
+    candidates: Vec<GcCandidate>,
+    disk_usage_bytes: u64,
+    now: u64,
+) -> Vec<(GcCandidate, GcReason)> {
+    let removable = |c: &GcCandidate| {
+        !c.in_use && now.saturating_sub(c.image.timestamp) >= policy.min_age_millis()
+    };
+
+    let mut by_reference: BTreeMap<String, Vec<GcCandidate>> = BTreeMap::new();
+    for candidate in candidates {
+        by_reference
+            .entry(candidate.image.reference.clone())
+            .or_default()
+            .push(candidate);
+    }
+
+    let mut removed = Vec::new();
+    let mut kept = Vec::new();
+    for (_, mut images) in by_reference {
+        images.sort_by(|a, b| b.image.timestamp.cmp(&a.image.timestamp));
+        for (index, candidate) in images.into_iter().enumerate() {
+            if index >= policy.keep_per_reference() && removable(&candidate) {
+                removed.push((candidate, GcReason::Superseded));
+            } else {
+                kept.push(candidate);
+            }
+        }
+    }
+
+    let Some(max_disk_usage_bytes) = policy.max_disk_usage_bytes() else {
+        return removed;
+    };
+
+    let mut usage = disk_usage_bytes.saturating_sub(
+        removed
+            .iter()
+            .map(|(candidate, _)| candidate.size_bytes)
+            .sum(),
+    );
+
+    kept.sort_by(|a, b| a.image.timestamp.cmp(&b.image.timestamp));
+    for candidate in kept {
+        if usage <= max_disk_usage_bytes {
+            break;
+        }
+        if !removable(&candidate) {
+            continue;
+        }
+
+        usage = usage.saturating_sub(candidate.size_bytes);
+        removed.push((candidate, GcReason::DiskUsage));
+    }
+
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: u64 = 60 * 60 * 1000;
+
+    fn candidate(id: &str, reference: &str, timestamp: u64, in_use: bool) -> GcCandidate {
+        GcCandidate {
+            image: Image {
+                id: id.to_string(),
+                reference: reference.to_string(),
+                digest: format!("sha256:{}", id),
+                timestamp,
+                volume_id: format!("volume-{}", id),
+                layer_ids: vec![],
+                labels: BTreeMap::new(),
+                annotations: BTreeMap::new(),
+            },
+            in_use,
+            size_bytes: BYTES_PER_GIB,
+        }
+    }
+
+    fn removed_ids(removed: &[(GcCandidate, GcReason)]) -> Vec<(&str, GcReason)> {
+        removed
+            .iter()
+            .map(|(candidate, reason)| (candidate.image.id.as_str(), *reason))
+            .collect()
+    }
+
+    #[test]
+    fn test_plan_keeps_newest_per_reference() {
+        let now = 10 * HOUR;
+        let policy = ImageGcPolicy {
+            keep_per_reference: Some(1),
+            ..Default::default()
+        };
+
+        let removed = plan(
+            &policy,
+            vec![
+                candidate("a1", "app", HOUR, false),
+                candidate("a2", "app", 2 * HOUR, true),
+                candidate("a3", "app", 3 * HOUR, false),
+                candidate("a4", "app", now, false),
+                candidate("b1", "other", HOUR, false),
+            ],
+            0,
+            now,
+        );
+
+        assert_eq!(
+            removed_ids(&removed),
+            vec![("a3", GcReason::Superseded), ("a1", GcReason::Superseded)]
+        );
+    }
+
+    #[test]
+    fn test_plan_skips_recent_images() {
+        let now = 10 * HOUR;
+        let policy = ImageGcPolicy {
+            keep_per_reference: Some(1),
+            ..Default::default()
+        };
+
+        let removed = plan(
+            &policy,
+            vec![
+                candidate("a1", "app", now - HOUR / 2, false),
+                candidate("a2", "app", now, false),
+            ],
+            0,
+            now,
+        );
+
+        assert!(removed.is_empty());
+    }
+
+    #[test]
+    fn test_plan_enforces_disk_usage() {
+        let now = 10 * HOUR;
+        let policy = ImageGcPolicy {
+            max_disk_usage_gib: Some(2),
+            ..Default::default()
+        };
+
+        let removed = plan(
+            &policy,
+            vec![
+                candidate("a1", "app", HOUR, true),
+                candidate("b1", "other", 2 * HOUR, false),
+                candidate("c1", "third", 3 * HOUR, false),
+                candidate("d1", "fourth", 4 * HOUR, false),
+            ],
+            4 * BYTES_PER_GIB,
+            now,
+        );
+
+        assert_eq!(
+            removed_ids(&removed),
+            vec![("b1", GcReason::DiskUsage), ("c1", GcReason::DiskUsage)]
+        );
+    }
+}
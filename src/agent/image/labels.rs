@@ -0,0 +1,81 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+
+/// Labels images must carry before machines use them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ImageLabelPolicy {
+    /// Labels of the image config every image needs (eg. `org.opencontainers.image.revision`),
+    /// with any value.
+    #[serde(default)]
+    pub required: Vec<String>,
+}
+
+impl ImageLabelPolicy {
+    /// The required labels `labels` lacks, an empty value counts as missing.
+    pub fn missing<'a>(&'a self, labels: &BTreeMap<String, String>) -> Vec<&'a str> {
+        self.required
+            .iter()
+            .filter(|label| labels.get(*label).is_none_or(|value| value.is_empty()))
+            .map(|label| label.as_str())
+            .collect()
+    }
+
+    pub fn check(&self, reference: &str, labels: &BTreeMap<String, String>) -> Result<()> {
+        let missing = self.missing(labels);
+        if !missing.is_empty() {
+            bail!(
+                "image {} rejected by the label policy, missing labels: {}",
+                reference,
+                missing.join(", ")
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_labels() {
+        let policy = ImageLabelPolicy {
+            required: vec![
+                "org.opencontainers.image.revision".to_string(),
+                "org.opencontainers.image.source".to_string(),
+            ],
+        };
+
+        let mut labels = BTreeMap::new();
+        assert_eq!(
+            policy.missing(&labels),
+            vec![
+                "org.opencontainers.image.revision",
+                "org.opencontainers.image.source"
+            ]
+        );
+
+        labels.insert(
+            "org.opencontainers.image.revision".to_string(),
+            "4f1c2e9".to_string(),
+        );
+        labels.insert("org.opencontainers.image.source".to_string(), String::new());
+        assert_eq!(
+            policy.missing(&labels),
+            vec!["org.opencontainers.image.source"]
+        );
+        assert!(policy.check("nginx:latest", &labels).is_err());
+
+        labels.insert(
+            "org.opencontainers.image.source".to_string(),
+            "https://github.com/acme/app".to_string(),
+        );
+        assert!(policy.missing(&labels).is_empty());
+        assert!(policy.check("nginx:latest", &labels).is_ok());
+
+        assert!(ImageLabelPolicy::default().missing(&labels).is_empty());
+    }
+}
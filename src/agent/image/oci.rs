@@ -1,22 +1,49 @@
-use std::path::Path;
+use std::{
+    io,
+    path::Path,
+    pin::Pin,
+    task::{Context, Poll},
+};
 
-use anyhow::Result;
+use anyhow::{Result, bail};
 use oci_client::{
     Client, Reference,
     client::{ClientConfig, ClientProtocol},
     config::ConfigFile,
-    manifest::{OciDescriptor, OciImageManifest},
+    errors::OciDistributionError,
+    manifest::{ImageIndexEntry, OciDescriptor, OciImageIndex, OciImageManifest, OciManifest},
     secrets::RegistryAuth,
 };
+use tokio::io::AsyncWrite;
 use tracing::{error, info};
 
-use crate::agent::image::{credentials::OciCredentialsProvider, unpacker};
+use crate::agent::image::{
+    credentials::OciCredentialsProvider,
+    unpacker::{self, Compression},
+};
 
 pub const SUPPORTED_LAYER_MEDIA_TYPES: &[&str] = &[
     "application/vnd.docker.image.rootfs.diff.tar.gzip",
+    "application/vnd.docker.image.rootfs.diff.tar",
     "application/vnd.oci.image.layer.v1.tar+gzip",
+    "application/vnd.oci.image.layer.v1.tar+zstd",
+    "application/vnd.oci.image.layer.v1.tar",
 ];
 
+fn layer_compression(media_type: &str) -> Option<Compression> {
+    if !SUPPORTED_LAYER_MEDIA_TYPES.contains(&media_type) {
+        return None;
+    }
+
+    if media_type.ends_with("gzip") {
+        Some(Compression::Gzip)
+    } else if media_type.ends_with("zstd") {
+        Some(Compression::Zstd)
+    } else {
+        Some(Compression::None)
+    }
+}
+
 pub async fn create_default_oci_client(
     credentials_provider: &impl OciCredentialsProvider,
     reference: &Reference,
@@ -25,6 +52,7 @@ pub async fn create_default_oci_client(
 
     let client = Client::new(ClientConfig {
         protocol: ClientProtocol::Https,
+        platform_resolver: Some(Box::new(host_platform_resolver)),
         ..Default::default()
     });
 
@@ -35,8 +63,33 @@ pub async fn create_default_oci_client(
     Ok((client, auth))
 }
 
+/// Architecture of the host, as named in image indexes.
+pub fn host_architecture() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        arch => arch,
+    }
+}
+
+/// Picks the image of a manifest list to run on this host. Machines are Linux guests with the
+/// architecture of the host, whatever OS the resolution runs on.
+pub fn host_platform_resolver(manifests: &[ImageIndexEntry]) -> Option<String> {
+    let architecture = host_architecture();
+
+    manifests
+        .iter()
+        .find(|entry| {
+            entry
+                .platform
+                .as_ref()
+                .is_some_and(|p| p.os == "linux" && p.architecture == architecture)
+        })
+        .map(|entry| entry.digest.clone())
+}
+
 pub async fn is_layer_supported(layer: &OciDescriptor) -> Result<bool> {
-    Ok(SUPPORTED_LAYER_MEDIA_TYPES.contains(&layer.media_type.as_str()))
+    Ok(layer_compression(&layer.media_type).is_some())
 }
 
 pub async fn fetch_manifest(
@@ -45,30 +98,77 @@ pub async fn fetch_manifest(
 ) -> Result<(OciImageManifest, String, ConfigFile)> {
     let (client, auth) = create_default_oci_client(credentials_provider, reference).await?;
 
-    let (manifest, digest, config) = client.pull_manifest_and_config(reference, &auth).await?;
+    let (manifest, digest, config) = client
+        .pull_manifest_and_config(reference, &auth)
+        .await
+        .map_err(|e| {
+            let message = format!(
+                "failed to fetch the linux/{} manifest of {}: {}",
+                host_architecture(),
+                reference,
+                e
+            );
+            anyhow::Error::new(e).context(message)
+        })?;
 
     let config: ConfigFile = serde_json::from_slice(&config.as_bytes())?;
 
     Ok((manifest, digest, config))
 }
 
+/// Whether the registry couldn't be reached at all, rather than answering with an error such as
+/// a denied pull.
+pub fn is_transport_error(error: &anyhow::Error) -> bool {
+    let is_transport = |e: &reqwest::Error| e.is_connect() || e.is_timeout();
+
+    error
+        .chain()
+        .any(|cause| match cause.downcast_ref::<OciDistributionError>() {
+            Some(OciDistributionError::RequestError(e)) => is_transport(e),
+            _ => cause
+                .downcast_ref::<reqwest::Error>()
+                .is_some_and(is_transport),
+        })
+}
+
+/// The index `reference` points to and its digest, `None` when it's a single manifest rather
+/// than the index of a multi-platform image.
+pub async fn fetch_index(
+    credentials_provider: &impl OciCredentialsProvider,
+    reference: &Reference,
+) -> Result<Option<(OciImageIndex, String)>> {
+    let (client, auth) = create_default_oci_client(credentials_provider, reference).await?;
+
+    match client.pull_manifest(reference, &auth).await? {
+        (OciManifest::ImageIndex(index), digest) => Ok(Some((index, digest))),
+        (OciManifest::Image(_), _) => Ok(None),
+    }
+}
+
 pub async fn pull_layer(
     credentials_provider: &impl OciCredentialsProvider,
     reference: &Reference,
     layer: &OciDescriptor,
     file_path: impl AsRef<Path>,
+    on_progress: &(dyn Fn(u64) + Send + Sync),
 ) -> Result<()> {
     let (client, _) = create_default_oci_client(credentials_provider, reference).await?;
 
-    let mut data: Vec<u8> = Vec::new();
+    // streamed to disk, layers can be larger than what we want to hold in memory
+    let mut file = tokio::fs::File::create(&file_path).await?;
 
-    let pull_result = client.pull_blob(reference, layer, &mut data).await;
+    let writer = ProgressWriter {
+        inner: &mut file,
+        on_progress,
+    };
+    let pull_result = client.pull_blob(reference, layer, writer).await;
     match pull_result {
         Ok(_) => {
+            file.sync_all().await?;
             info!(
                 "Layer {} pulled successfully ({} bytes)",
                 layer.digest,
-                data.len()
+                file.metadata().await?.len()
             );
         }
         Err(e) => {
@@ -77,20 +177,111 @@ pub async fn pull_layer(
         }
     }
 
-    tokio::fs::write(&file_path, data).await?;
-
     Ok(())
 }
 
+/// Reports the bytes written through it.
+struct ProgressWriter<'a, W> {
+    inner: W,
+    on_progress: &'a (dyn Fn(u64) + Send + Sync),
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for ProgressWriter<'_, W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = &result {
+            (self.on_progress)(*written as u64);
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
 pub async fn uncompress_layer(
+    layer: &OciDescriptor,
     file_path: impl AsRef<Path>,
     dir_path: impl AsRef<Path>,
 ) -> Result<()> {
+    let Some(compression) = layer_compression(&layer.media_type) else {
+        bail!(
+            "layer {} has an unsupported media type: {}",
+            layer.digest,
+            layer.media_type
+        );
+    };
+
     let file_path = file_path.as_ref().to_owned();
     let dir_path = dir_path.as_ref().to_owned();
 
-    tokio::task::spawn_blocking(move || unpacker::unpack_gzipped_tar(file_path, dir_path))
+    tokio::task::spawn_blocking(move || unpacker::unpack_tar(file_path, dir_path, compression))
         .await??;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_platform_resolver() {
+        let manifests: Vec<ImageIndexEntry> = serde_json::from_value(serde_json::json!([
+            {
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "digest": "sha256:windows",
+                "size": 1,
+                "platform": { "architecture": host_architecture(), "os": "windows" }
+            },
+            {
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "digest": "sha256:attestation",
+                "size": 1,
+                "platform": { "architecture": "unknown", "os": "unknown" }
+            },
+            {
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "digest": "sha256:linux",
+                "size": 1,
+                "platform": { "architecture": host_architecture(), "os": "linux" }
+            }
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            host_platform_resolver(&manifests),
+            Some("sha256:linux".to_string())
+        );
+        assert_eq!(host_platform_resolver(&manifests[..2]), None);
+    }
+
+    #[test]
+    fn test_layer_compression() {
+        assert_eq!(
+            layer_compression("application/vnd.oci.image.layer.v1.tar+zstd"),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(
+            layer_compression("application/vnd.docker.image.rootfs.diff.tar.gzip"),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(
+            layer_compression("application/vnd.oci.image.layer.v1.tar"),
+            Some(Compression::None)
+        );
+        assert_eq!(
+            layer_compression("application/vnd.oci.image.layer.v1.tar+bzip2"),
+            None
+        );
+    }
+}
@@ -0,0 +1,547 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, anyhow, bail};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use oci_client::Reference;
+use ring::signature::{
+    ECDSA_P256_SHA256_ASN1, ECDSA_P256_SHA384_ASN1, ECDSA_P384_SHA256_ASN1, ECDSA_P384_SHA384_ASN1,
+    UnparsedPublicKey, VerificationAlgorithm,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use x509_parser::{
+    oid_registry::{OID_SIG_ECDSA_WITH_SHA256, OID_SIG_ECDSA_WITH_SHA384},
+    pem::Pem,
+    prelude::{FromDer, GeneralName, X509Certificate},
+    x509::SubjectPublicKeyInfo,
+};
+
+use crate::agent::image::{credentials::OciCredentialsProvider, oci};
+
+const SIGNATURE_ANNOTATION: &str = "dev.cosignproject.cosign/signature";
+const CERTIFICATE_ANNOTATION: &str = "dev.sigstore.cosign/certificate";
+const CHAIN_ANNOTATION: &str = "dev.sigstore.cosign/chain";
+
+/// Length of an uncompressed P-256 point, a P-384 one is 97 bytes long.
+const P256_POINT_LEN: usize = 65;
+const P384_POINT_LEN: usize = 97;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub enum SignatureEnforcement {
+    #[serde(rename = "off")]
+    Off,
+    /// Unsigned images are deployed, with a warning.
+    #[serde(rename = "warn")]
+    Warn,
+    /// Unsigned images fail to pull.
+    #[serde(rename = "deny")]
+    Deny,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct ImageSignaturePolicy {
+    /// PEM public keys images can be signed with (`cosign sign --key`).
+    #[serde(default)]
+    pub keys: Vec<PathBuf>,
+    /// PEM root certificates keyless signatures have to chain up to.
+    #[serde(rename = "fulcio-roots", default)]
+    pub fulcio_roots: Vec<PathBuf>,
+    /// Emails or URIs allowed to sign keyless, anyone certified by the roots when empty.
+    #[serde(default)]
+    pub identities: Vec<String>,
+    /// Enforcement for tenants without their own, off when not set.
+    pub enforcement: Option<SignatureEnforcement>,
+    #[serde(rename = "tenant", default)]
+    pub tenants: Vec<TenantSignatureEnforcement>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct TenantSignatureEnforcement {
+    pub tenant: String,
+    pub enforcement: SignatureEnforcement,
+}
+
+impl ImageSignaturePolicy {
+    pub fn enforcement(&self, tenant: &str) -> SignatureEnforcement {
+        self.tenants
+            .iter()
+            .find(|t| t.tenant == tenant)
+            .map(|t| t.enforcement)
+            .or(self.enforcement)
+            .unwrap_or(SignatureEnforcement::Off)
+    }
+}
+
+/// A cosign signature of an image manifest, one layer of the signature image.
+#[derive(Debug, Clone)]
+struct CosignSignature {
+    /// Simple signing payload, naming the signed manifest digest.
+    payload: Vec<u8>,
+    signature: Vec<u8>,
+    /// PEM Fulcio certificate of a keyless signature.
+    certificate: Option<String>,
+    chain: Option<String>,
+}
+
+/// Verifies the cosign signatures stored next to images, under the `sha256-<digest>.sig` tag.
+///
+/// Keyless signatures are accepted when their certificate chains up to a configured Fulcio
+/// root. Their transparency log entries aren't checked, neither is the validity period of the
+/// short-lived certificates, which would need the log's timestamps.
+pub struct SignatureVerifier {
+    policy: ImageSignaturePolicy,
+    /// Public keys, as uncompressed EC points.
+    keys: Vec<Vec<u8>>,
+    /// DER root certificates.
+    fulcio_roots: Vec<Vec<u8>>,
+}
+
+impl SignatureVerifier {
+    pub async fn new(policy: ImageSignaturePolicy) -> Result<Self> {
+        let mut keys = vec![];
+        for path in &policy.keys {
+            for contents in read_pem_file(path).await? {
+                let (_, key) = SubjectPublicKeyInfo::from_der(&contents).map_err(|e| {
+                    anyhow!("Failed to parse public key {}: {:?}", path.display(), e)
+                })?;
+                keys.push(key.subject_public_key.data.to_vec());
+            }
+        }
+
+        let mut fulcio_roots = vec![];
+        for path in &policy.fulcio_roots {
+            fulcio_roots.extend(read_pem_file(path).await?);
+        }
+
+        Ok(Self {
+            policy,
+            keys,
+            fulcio_roots,
+        })
+    }
+
+    pub fn enforcement(&self, tenant: &str) -> SignatureEnforcement {
+        self.policy.enforcement(tenant)
+    }
+
+    /// Checks that the manifest `digest` of `reference` has at least one valid signature.
+    pub async fn verify(
+        &self,
+        credentials_provider: &impl OciCredentialsProvider,
+        reference: &Reference,
+        digest: &str,
+    ) -> Result<()> {
+        let signatures = fetch_signatures(credentials_provider, reference, digest).await?;
+        if signatures.is_empty() {
+            bail!("no signature found for {}", digest);
+        }
+
+        let mut errors = vec![];
+        for signature in signatures {
+            match self.verify_signature(&signature, digest) {
+                Ok(()) => return Ok(()),
+                Err(e) => errors.push(e.to_string()),
+            }
+        }
+
+        bail!("no valid signature for {}: {}", digest, errors.join(", "))
+    }
+
+    fn verify_signature(&self, signature: &CosignSignature, digest: &str) -> Result<()> {
+        check_payload_digest(&signature.payload, digest)?;
+
+        if let Some(certificate) = &signature.certificate {
+            return self.verify_keyless(signature, certificate);
+        }
+
+        let signed_by_key = self
+            .keys
+            .iter()
+            .any(|key| verify_ecdsa(key, &signature.payload, &signature.signature).is_ok());
+        if !signed_by_key {
+            bail!("signature doesn't match any of the configured keys");
+        }
+
+        Ok(())
+    }
+
+    fn verify_keyless(&self, signature: &CosignSignature, certificate: &str) -> Result<()> {
+        let leaf_der = pem_contents(certificate.as_bytes())?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("empty signing certificate"))?;
+        let chain_der = match &signature.chain {
+            Some(chain) => pem_contents(chain.as_bytes())?,
+            None => vec![],
+        };
+
+        let leaf = parse_certificate(&leaf_der)?;
+        let chain = chain_der
+            .iter()
+            .map(|der| parse_certificate(der))
+            .collect::<Result<Vec<_>>>()?;
+        let roots = self
+            .fulcio_roots
+            .iter()
+            .map(|der| parse_certificate(der))
+            .collect::<Result<Vec<_>>>()?;
+
+        verify_ecdsa(
+            &leaf.public_key().subject_public_key.data,
+            &signature.payload,
+            &signature.signature,
+        )?;
+        check_code_signing(&leaf)?;
+        verify_chain(&leaf, &chain, &roots)?;
+
+        if !self.policy.identities.is_empty() {
+            let identities = certificate_identities(&leaf);
+            if !identities
+                .iter()
+                .any(|identity| self.policy.identities.contains(identity))
+            {
+                bail!(
+                    "signed by {}, which isn't an allowed identity",
+                    identities.join(", ")
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+async fn read_pem_file(path: &Path) -> Result<Vec<Vec<u8>>> {
+    let contents = tokio::fs::read(path)
+        .await
+        .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+
+    pem_contents(&contents).map_err(|e| anyhow!("Failed to parse {}: {}", path.display(), e))
+}
+
+fn pem_contents(pem: &[u8]) -> Result<Vec<Vec<u8>>> {
+    Pem::iter_from_buffer(pem)
+        .map(|pem| {
+            pem.map(|pem| pem.contents)
+                .map_err(|e| anyhow!("invalid PEM: {:?}", e))
+        })
+        .collect()
+}
+
+fn parse_certificate(der: &[u8]) -> Result<X509Certificate<'_>> {
+    let (_, certificate) = X509Certificate::from_der(der)
+        .map_err(|e| anyhow!("Failed to parse certificate: {:?}", e))?;
+    Ok(certificate)
+}
+
+/// Tag cosign stores the signatures of a manifest digest under.
+fn signature_tag(digest: &str) -> String {
+    format!("{}.sig", digest.replace(':', "-"))
+}
+
+async fn fetch_signatures(
+    credentials_provider: &impl OciCredentialsProvider,
+    reference: &Reference,
+    digest: &str,
+) -> Result<Vec<CosignSignature>> {
+    let signature_reference = Reference::with_tag(
+        reference.registry().to_string(),
+        reference.repository().to_string(),
+        signature_tag(digest),
+    );
+
+    let (client, auth) =
+        oci::create_default_oci_client(credentials_provider, &signature_reference).await?;
+    let (manifest, _) = client
+        .pull_image_manifest(&signature_reference, &auth)
+        .await
+        .map_err(|e| anyhow!("failed to fetch {}: {}", signature_reference, e))?;
+
+    let mut signatures = vec![];
+    for layer in manifest.layers.iter() {
+        let annotations = layer.annotations.clone().unwrap_or_default();
+        let Some(signature) = annotations.get(SIGNATURE_ANNOTATION) else {
+            continue;
+        };
+
+        let mut payload = vec![];
+        client
+            .pull_blob(&signature_reference, layer, &mut payload)
+            .await?;
+
+        signatures.push(CosignSignature {
+            payload,
+            signature: BASE64_STANDARD.decode(signature)?,
+            certificate: annotations.get(CERTIFICATE_ANNOTATION).cloned(),
+            chain: annotations.get(CHAIN_ANNOTATION).cloned(),
+        });
+    }
+
+    Ok(signatures)
+}
+
+/// The payload has to name the manifest, or a signature of another image could be replayed.
+fn check_payload_digest(payload: &[u8], digest: &str) -> Result<()> {
+    let payload: Value = serde_json::from_slice(payload)?;
+    let signed_digest = payload
+        .pointer("/critical/image/docker-manifest-digest")
+        .and_then(|d| d.as_str())
+        .ok_or_else(|| anyhow!("signature payload without a manifest digest"))?;
+
+    if signed_digest != digest {
+        bail!("signature is for {}", signed_digest);
+    }
+
+    Ok(())
+}
+
+fn verify_ecdsa(public_key: &[u8], message: &[u8], signature: &[u8]) -> Result<()> {
+    let algorithm: &'static dyn VerificationAlgorithm = match public_key.len() {
+        P256_POINT_LEN => &ECDSA_P256_SHA256_ASN1,
+        P384_POINT_LEN => &ECDSA_P384_SHA384_ASN1,
+        _ => bail!("unsupported public key, only ECDSA P-256 and P-384 keys are"),
+    };
+
+    UnparsedPublicKey::new(algorithm, public_key)
+        .verify(message, signature)
+        .map_err(|_| anyhow!("invalid signature"))
+}
+
+fn verify_issued_by(certificate: &X509Certificate, issuer: &X509Certificate) -> Result<()> {
+    if certificate.issuer() != issuer.subject() {
+        bail!("issued by another certificate");
+    }
+
+    // Without these any certificate of the chain, a signing one included, could issue others.
+    if !matches!(issuer.basic_constraints(), Ok(Some(constraints)) if constraints.value.ca) {
+        bail!("issued by a certificate that isn't a CA");
+    }
+    if !matches!(issuer.key_usage(), Ok(Some(usage)) if usage.value.key_cert_sign()) {
+        bail!("issued by a certificate that can't sign certificates");
+    }
+
+    let sha384 = match &certificate.signature_algorithm.algorithm {
+        oid if *oid == OID_SIG_ECDSA_WITH_SHA256 => false,
+        oid if *oid == OID_SIG_ECDSA_WITH_SHA384 => true,
+        oid => bail!("unsupported certificate signature algorithm {}", oid),
+    };
+
+    let issuer_key = &issuer.public_key().subject_public_key.data;
+    let algorithm: &'static dyn VerificationAlgorithm = match (issuer_key.len(), sha384) {
+        (P256_POINT_LEN, false) => &ECDSA_P256_SHA256_ASN1,
+        (P256_POINT_LEN, true) => &ECDSA_P256_SHA384_ASN1,
+        (P384_POINT_LEN, false) => &ECDSA_P384_SHA256_ASN1,
+        (P384_POINT_LEN, true) => &ECDSA_P384_SHA384_ASN1,
+        _ => bail!("unsupported issuer key"),
+    };
+
+    UnparsedPublicKey::new(algorithm, issuer_key)
+        .verify(
+            certificate.tbs_certificate.as_ref(),
+            &certificate.signature_value.data,
+        )
+        .map_err(|_| anyhow!("invalid certificate signature"))
+}
+
+fn check_code_signing(certificate: &X509Certificate) -> Result<()> {
+    if !matches!(certificate.extended_key_usage(), Ok(Some(usage)) if usage.value.code_signing) {
+        bail!("signing certificate isn't issued for code signing");
+    }
+
+    Ok(())
+}
+
+/// Walks up from `leaf` through the certificates of `chain` until one of `roots` issued it.
+fn verify_chain(
+    leaf: &X509Certificate,
+    chain: &[X509Certificate],
+    roots: &[X509Certificate],
+) -> Result<()> {
+    let mut certificate = leaf;
+    for _ in 0..=chain.len() {
+        if roots
+            .iter()
+            .any(|root| verify_issued_by(certificate, root).is_ok())
+        {
+            return Ok(());
+        }
+
+        let Some(issuer) = chain
+            .iter()
+            .find(|issuer| verify_issued_by(certificate, issuer).is_ok())
+        else {
+            break;
+        };
+        certificate = issuer;
+    }
+
+    bail!("certificate doesn't chain up to a configured Fulcio root")
+}
+
+fn certificate_identities(certificate: &X509Certificate) -> Vec<String> {
+    let Ok(Some(names)) = certificate.subject_alternative_name() else {
+        return vec![];
+    };
+
+    names
+        .value
+        .general_names
+        .iter()
+        .filter_map(|name| match name {
+            GeneralName::RFC822Name(email) => Some(email.to_string()),
+            GeneralName::URI(uri) => Some(uri.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rcgen::{
+        BasicConstraints, CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose,
+        IsCa, Issuer, KeyUsagePurpose,
+    };
+    use ring::{
+        rand::SystemRandom,
+        signature::{ECDSA_P256_SHA256_ASN1_SIGNING, EcdsaKeyPair, KeyPair},
+    };
+
+    use super::*;
+
+    const DIGEST: &str = "sha256:4f6e1b2d0c3a";
+
+    fn payload(digest: &str) -> Vec<u8> {
+        serde_json::to_vec(&serde_json::json!({
+            "critical": {
+                "identity": { "docker-reference": "registry.example.com/app" },
+                "image": { "docker-manifest-digest": digest },
+                "type": "cosign container image signature"
+            },
+            "optional": null
+        }))
+        .unwrap()
+    }
+
+    fn key_pair() -> EcdsaKeyPair {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng).unwrap()
+    }
+
+    /// Issues a certificate named `name`, self-signed without an issuer, returning it in DER
+    /// and PEM along with its key.
+    fn certificate(
+        name: &str,
+        ca: bool,
+        issuer: Option<&(Vec<u8>, String, rcgen::KeyPair)>,
+    ) -> (Vec<u8>, String, rcgen::KeyPair) {
+        let key = rcgen::KeyPair::generate().unwrap();
+        let mut dn = DistinguishedName::new();
+        dn.push(DnType::CommonName, name);
+
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.distinguished_name = dn;
+        if ca {
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            params.key_usages = vec![KeyUsagePurpose::KeyCertSign];
+        } else {
+            params.is_ca = IsCa::NoCa;
+            params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+            params.extended_key_usages = vec![ExtendedKeyUsagePurpose::CodeSigning];
+        }
+
+        let certificate = match issuer {
+            Some((_, issuer_pem, issuer_key)) => {
+                let issuer = Issuer::from_ca_cert_pem(issuer_pem, issuer_key).unwrap();
+                params.signed_by(&key, &issuer).unwrap()
+            }
+            None => params.self_signed(&key).unwrap(),
+        };
+
+        (certificate.der().to_vec(), certificate.pem(), key)
+    }
+
+    fn verifier(keys: Vec<Vec<u8>>) -> SignatureVerifier {
+        SignatureVerifier {
+            policy: ImageSignaturePolicy {
+                keys: vec![],
+                fulcio_roots: vec![],
+                identities: vec![],
+                enforcement: Some(SignatureEnforcement::Deny),
+                tenants: vec![],
+            },
+            keys,
+            fulcio_roots: vec![],
+        }
+    }
+
+    #[test]
+    fn test_verify_key_signature() {
+        let key_pair = key_pair();
+        let payload = payload(DIGEST);
+        let signature = CosignSignature {
+            signature: key_pair
+                .sign(&SystemRandom::new(), &payload)
+                .unwrap()
+                .as_ref()
+                .to_vec(),
+            payload,
+            certificate: None,
+            chain: None,
+        };
+
+        let verifier = verifier(vec![key_pair.public_key().as_ref().to_vec()]);
+        assert!(verifier.verify_signature(&signature, DIGEST).is_ok());
+        assert!(
+            verifier
+                .verify_signature(&signature, "sha256:0000")
+                .is_err()
+        );
+
+        let other_verifier = verifier(vec![key_pair().public_key().as_ref().to_vec()]);
+        assert!(other_verifier.verify_signature(&signature, DIGEST).is_err());
+    }
+
+    #[test]
+    fn test_enforcement() {
+        let policy = ImageSignaturePolicy {
+            keys: vec![],
+            fulcio_roots: vec![],
+            identities: vec![],
+            enforcement: Some(SignatureEnforcement::Warn),
+            tenants: vec![TenantSignatureEnforcement {
+                tenant: "acme".to_string(),
+                enforcement: SignatureEnforcement::Deny,
+            }],
+        };
+
+        assert_eq!(policy.enforcement("acme"), SignatureEnforcement::Deny);
+        assert_eq!(policy.enforcement("other"), SignatureEnforcement::Warn);
+        assert_eq!(signature_tag(DIGEST), "sha256-4f6e1b2d0c3a.sig");
+    }
+
+    #[test]
+    fn test_verify_chain_rejects_forged_chain() {
+        let root = certificate("fulcio root", true, None);
+        let intermediate = certificate("fulcio intermediate", true, Some(&root));
+        let leaf = certificate("signer", false, Some(&intermediate));
+        // A signing certificate issuing another one, for an identity it was never given.
+        let forged = certificate("forged", false, Some(&leaf));
+
+        let root_certificate = parse_certificate(&root.0).unwrap();
+        let intermediate_certificate = parse_certificate(&intermediate.0).unwrap();
+        let leaf_certificate = parse_certificate(&leaf.0).unwrap();
+        let forged_certificate = parse_certificate(&forged.0).unwrap();
+
+        let roots = [root_certificate.clone()];
+        let chain = [intermediate_certificate.clone(), leaf_certificate.clone()];
+        assert!(check_code_signing(&leaf_certificate).is_ok());
+        assert!(verify_chain(&leaf_certificate, &chain, &roots).is_ok());
+        assert!(verify_chain(&forged_certificate, &chain, &roots).is_err());
+
+        // Certifying a signing certificate with another one doesn't make it a CA either.
+        assert!(check_code_signing(&intermediate_certificate).is_err());
+        assert!(verify_issued_by(&forged_certificate, &leaf_certificate).is_err());
+    }
+}
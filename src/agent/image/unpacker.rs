@@ -2,26 +2,43 @@ use anyhow::Result;
 use flate2::bufread::GzDecoder;
 use std::{
     fs::{self, File},
-    io::{self, BufReader},
+    io::{self, BufReader, Read},
     path::Path,
     path::PathBuf,
 };
 use tar::{Archive, EntryType};
 use tracing::{error, info};
 
-pub fn unpack_gzipped_tar(tar_path: impl AsRef<Path>, dest_dir: impl AsRef<Path>) -> Result<()> {
+/// How the tar archive of a layer is compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+pub fn unpack_tar(
+    tar_path: impl AsRef<Path>,
+    dest_dir: impl AsRef<Path>,
+    compression: Compression,
+) -> Result<()> {
     let tar_path = tar_path.as_ref();
     let dest_dir = dest_dir.as_ref();
 
     info!(
-        "Unpacking tar archive {} to {}",
+        "Unpacking {:?} tar archive {} to {}",
+        compression,
         tar_path.display(),
         dest_dir.display()
     );
 
     let file = File::open(tar_path)?;
     let reader = BufReader::new(file);
-    let decoder = GzDecoder::new(reader);
+    let decoder: Box<dyn Read> = match compression {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => Box::new(GzDecoder::new(reader)),
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(reader)?),
+    };
     let mut archive = Archive::new(decoder);
 
     // Configure archive settings
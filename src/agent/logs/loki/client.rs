@@ -92,6 +92,17 @@ pub struct LogStream {
     pub values: Vec<[String; 2]>,
 }
 
+#[derive(Debug, Serialize)]
+pub struct PushRequest {
+    pub streams: Vec<PushStream>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PushStream {
+    pub stream: HashMap<String, String>,
+    pub values: Vec<[String; 2]>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct TailResponse {
     pub streams: Vec<LogStream>,
@@ -99,6 +110,14 @@ pub struct TailResponse {
     pub dropped_entries: Option<Vec<DroppedEntry>>,
 }
 
+/// A deletion requested earlier, as listed by the log store.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeleteRequest {
+    pub query: String,
+    /// Unix timestamp in seconds.
+    pub end_time: f64,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct DroppedEntry {
     pub labels: HashMap<String, String>,
@@ -176,6 +195,78 @@ impl LokiClient {
         Ok(loki_response)
     }
 
+    /// Push log entries, grouped in streams by their labels
+    pub async fn push(&self, request: &PushRequest) -> Result<()> {
+        let url = format!("{}/loki/api/v1/push", self.base_url);
+
+        let mut request = self.client.post(&url).json(request);
+
+        if let Some(auth) = &self.auth_header {
+            request = request.header("Authorization", auth);
+        }
+
+        let response = request.send().await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(anyhow!("Request failed with status {}: {}", status, body));
+        }
+
+        Ok(())
+    }
+
+    /// Request the deletion of the entries matching a stream selector, between `start` and `end`
+    ///
+    /// `start` and `end` are unix timestamps in seconds, `start` defaults to the first entry.
+    /// The entries are removed by the compactor, which needs deletion enabled.
+    pub async fn delete(&self, query: &str, start: Option<u64>, end: u64) -> Result<()> {
+        let url = format!("{}/loki/api/v1/delete", self.base_url);
+
+        let mut params = vec![("query", query.to_string()), ("end", end.to_string())];
+        if let Some(start) = start {
+            params.push(("start", start.to_string()));
+        }
+
+        let mut request = self.client.post(&url).query(&params);
+
+        if let Some(auth) = &self.auth_header {
+            request = request.header("Authorization", auth);
+        }
+
+        let response = request.send().await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(anyhow!("Request failed with status {}: {}", status, body));
+        }
+
+        Ok(())
+    }
+
+    /// List the deletions requested so far, processed or not
+    pub async fn list_deletes(&self) -> Result<Vec<DeleteRequest>> {
+        let url = format!("{}/loki/api/v1/delete", self.base_url);
+
+        let mut request = self.client.get(&url);
+
+        if let Some(auth) = &self.auth_header {
+            request = request.header("Authorization", auth);
+        }
+
+        let response = request.send().await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(anyhow!("Request failed with status {}: {}", status, body));
+        }
+
+        let requests: Vec<DeleteRequest> = response.json().await?;
+        Ok(requests)
+    }
+
     /// Tail logs in real-time using WebSocket streaming
     ///
     /// Returns an async stream that yields `TailResponse` items.
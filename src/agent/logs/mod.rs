@@ -1,23 +1,43 @@
 use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
     ops::RangeInclusive,
     pin::Pin,
+    sync::Arc,
     task::{Context, Poll},
+    time::Duration,
 };
 
 use anyhow::Result;
 use futures_util::Stream;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::warn;
 
 use crate::{
-    agent::logs::loki::{
-        Direction, LokiClient, LokiResponse, QueryData, QueryRangeParams, TailParams, TailResponse,
-        TailStream,
+    agent::{
+        logs::{
+            loki::{
+                Direction, LokiClient, LokiResponse, PushRequest, PushStream, QueryData,
+                QueryRangeParams, TailParams, TailResponse, TailStream,
+            },
+            otlp::{DEFAULT_OTLP_INGEST_PORT, start_otlp_ingest_server},
+            retention::{LogRetentionConfig, enforce_log_retention},
+        },
+        metrics::MetricsAgent,
+        net::NetAgent,
     },
-    resources::core::{LogStreamItem, LogStreamTarget},
+    resources::core::{LogFilter, LogLevel, LogStreamItem, LogStreamTarget},
 };
 
+const ACCESS_LOG_QUEUE_SIZE: usize = 8192;
+const ACCESS_LOG_BATCH_SIZE: usize = 512;
+const ACCESS_LOG_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
 pub mod loki;
+pub mod otlp;
+pub mod retention;
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
 #[serde(tag = "type")]
@@ -33,12 +53,32 @@ pub struct LokiStoreConfig {
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
 pub struct LogsAgentConfig {
-    pub otel_ingest_endpoint: String,
+    /// OTLP endpoint machines export their logs to, the built-in ingest when not set.
+    pub otel_ingest_endpoint: Option<String>,
+    /// Port of the built-in OTLP ingest on the service gateway. It runs when set, or when no
+    /// external endpoint is configured.
+    pub otlp_ingest_port: Option<u16>,
     pub store: LogsStoreConfig,
+    pub retention: LogRetentionConfig,
 }
 
 pub struct LogsAgent {
     config: LogsAgentConfig,
+    access_log_tx: mpsc::Sender<AccessLogEntry>,
+    /// Address of the built-in OTLP ingest, when it runs.
+    otlp_ingest_address: Option<SocketAddr>,
+    /// Whether the ingest forwards metrics, to a configured metrics store.
+    metrics_ingest: bool,
+}
+
+/// A single line emitted by the proxy for a service, shipped in batches to the log store.
+#[derive(Debug)]
+pub struct AccessLogEntry {
+    pub tenant: String,
+    pub namespace: String,
+    pub service: String,
+    pub timestamp_ns: u64,
+    pub line: String,
 }
 
 #[derive(Debug)]
@@ -53,13 +93,35 @@ pub enum LogStreamOrigin {
         name: String,
         namespace: Option<String>,
     },
+    Service {
+        tenant: String,
+        name: String,
+        namespace: Option<String>,
+    },
+}
+
+/// Quotes a value as a LogQL string literal.
+fn logql_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Values of the `detected_level` label at the given level or above.
+fn detected_levels_from(level: LogLevel) -> &'static str {
+    match level {
+        LogLevel::Debug => "trace|debug|info|warn|error|critical|fatal",
+        LogLevel::Info => "info|warn|error|critical|fatal",
+        LogLevel::Warn => "warn|error|critical|fatal",
+        LogLevel::Error => "error|critical|fatal",
+    }
 }
 
 impl LogStreamOrigin {
-    pub fn loki_log_query(&self) -> String {
+    /// Builds the LogQL query for the origin, the filters are pushed down to the log store.
+    pub fn loki_log_query(&self, filter: &LogFilter) -> String {
         let tenant = match self {
             LogStreamOrigin::Machine { tenant, .. } => tenant,
             LogStreamOrigin::Group { tenant, .. } => tenant,
+            LogStreamOrigin::Service { tenant, .. } => tenant,
         };
 
         let mut loki_log_query = vec![format!("service_tenant = \"{}\"", tenant)];
@@ -71,22 +133,100 @@ impl LogStreamOrigin {
             LogStreamOrigin::Group { name, .. } => {
                 format!("service_group = \"{}\"", name)
             }
+            // access logs don't carry service_name, so they never mix with machine logs
+            LogStreamOrigin::Service { name, .. } => {
+                format!("proxy_service = \"{}\"", name)
+            }
         });
 
         if let Some(namespace) = match self {
             LogStreamOrigin::Machine { namespace, .. } => namespace,
             LogStreamOrigin::Group { namespace, .. } => namespace,
+            LogStreamOrigin::Service { namespace, .. } => namespace,
         } {
             loki_log_query.push(format!("service_namespace = \"{}\"", namespace));
         }
 
-        format!("{{ {} }}", loki_log_query.join(", "))
+        if let Some(stream) = &filter.stream {
+            loki_log_query.push(format!(
+                "log_stream = \"{}\"",
+                match stream {
+                    LogStreamTarget::Stdout => "stdout",
+                    LogStreamTarget::Stderr => "stderr",
+                }
+            ));
+        }
+
+        let mut loki_log_query = format!("{{ {} }}", loki_log_query.join(", "));
+
+        if let Some(contains) = &filter.contains {
+            loki_log_query.push_str(&format!(" |= {}", logql_string(contains)));
+        }
+
+        if let Some(regex) = &filter.regex {
+            loki_log_query.push_str(&format!(" |~ {}", logql_string(regex)));
+        }
+
+        if let Some(level) = filter.level {
+            loki_log_query.push_str(&format!(
+                " | detected_level =~ \"{}\"",
+                detected_levels_from(level)
+            ));
+        }
+
+        loki_log_query
     }
 }
 
 impl LogsAgent {
-    pub fn new(config: LogsAgentConfig) -> Self {
-        Self { config }
+    pub fn new(
+        config: LogsAgentConfig,
+        net: Arc<NetAgent>,
+        metrics: Option<Arc<MetricsAgent>>,
+    ) -> Self {
+        let (access_log_tx, access_log_rx) = mpsc::channel(ACCESS_LOG_QUEUE_SIZE);
+
+        let LogsStoreConfig::Loki(loki_config) = &config.store;
+        tokio::spawn(ship_access_logs(
+            LokiClient::new(loki_config.url.clone()),
+            access_log_rx,
+        ));
+        tokio::spawn(enforce_log_retention(
+            LokiClient::new(loki_config.url.clone()),
+            config.retention.clone(),
+        ));
+
+        let otlp_ingest_port = match (&config.otel_ingest_endpoint, config.otlp_ingest_port) {
+            (_, Some(port)) => Some(port),
+            (None, None) => Some(DEFAULT_OTLP_INGEST_PORT),
+            (Some(_), None) => None,
+        };
+        let otlp_ingest_address =
+            otlp_ingest_port.map(|port| SocketAddr::new(net.service_gateway().into(), port));
+
+        let metrics_ingest = otlp_ingest_address.is_some() && metrics.is_some();
+        if let Some(address) = otlp_ingest_address {
+            let loki_client = LokiClient::new(loki_config.url.clone());
+            tokio::spawn(async move {
+                if let Err(e) = start_otlp_ingest_server(address, net, loki_client, metrics).await {
+                    warn!("OTLP ingest stopped: {}", e);
+                }
+            });
+        }
+
+        Self {
+            config,
+            access_log_tx,
+            otlp_ingest_address,
+            metrics_ingest,
+        }
+    }
+
+    /// Queues an access log line, dropping it if the shipper can't keep up.
+    pub fn push_access_log(&self, entry: AccessLogEntry) {
+        if self.access_log_tx.try_send(entry).is_err() {
+            warn!("access log queue is full, dropping entry");
+        }
     }
 
     fn get_loki_client(&self) -> Result<LokiClient> {
@@ -95,16 +235,29 @@ impl LogsAgent {
     }
 
     pub fn get_otel_ingest_endpoint(&self) -> String {
-        self.config.otel_ingest_endpoint.clone()
+        match (&self.config.otel_ingest_endpoint, self.otlp_ingest_address) {
+            (Some(endpoint), _) => endpoint.clone(),
+            (None, Some(address)) => format!("http://{}/v1/logs", address),
+            (None, None) => String::new(),
+        }
+    }
+
+    /// OTLP endpoint machines export their metrics to, when the ingest forwards them.
+    pub fn get_otel_metrics_endpoint(&self) -> Option<String> {
+        match (self.metrics_ingest, self.otlp_ingest_address) {
+            (true, Some(address)) => Some(format!("http://{}/v1/metrics", address)),
+            _ => None,
+        }
     }
 
     pub async fn query(
         &self,
         origin: LogStreamOrigin,
         range: RangeInclusive<u64>,
+        filter: &LogFilter,
     ) -> Result<Vec<LogStreamItem>> {
         let loki_client = self.get_loki_client()?;
-        let loki_log_query = origin.loki_log_query();
+        let loki_log_query = origin.loki_log_query(filter);
 
         let mut start_ts = *range.start();
         let end_ts = *range.end();
@@ -173,9 +326,9 @@ impl LogsAgent {
         Ok(output)
     }
 
-    pub async fn stream(&self, origin: LogStreamOrigin) -> Result<LogStream> {
+    pub async fn stream(&self, origin: LogStreamOrigin, filter: &LogFilter) -> Result<LogStream> {
         let loki_client = self.get_loki_client()?;
-        let loki_log_query = origin.loki_log_query();
+        let loki_log_query = origin.loki_log_query(filter);
 
         Ok(LogStream {
             inner: loki_client.tail(TailParams::new(loki_log_query)).await?,
@@ -183,6 +336,54 @@ impl LogsAgent {
     }
 }
 
+async fn ship_access_logs(client: LokiClient, mut rx: mpsc::Receiver<AccessLogEntry>) {
+    let mut batch = Vec::with_capacity(ACCESS_LOG_BATCH_SIZE);
+
+    while let Some(entry) = rx.recv().await {
+        batch.push(entry);
+
+        // give the batch a moment to fill up before pushing
+        let flush = tokio::time::sleep(ACCESS_LOG_FLUSH_INTERVAL);
+        tokio::pin!(flush);
+        while batch.len() < ACCESS_LOG_BATCH_SIZE {
+            tokio::select! {
+                _ = &mut flush => break,
+                entry = rx.recv() => match entry {
+                    Some(entry) => batch.push(entry),
+                    None => break,
+                },
+            }
+        }
+
+        let mut streams: BTreeMap<(String, String, String), Vec<[String; 2]>> = BTreeMap::new();
+        for entry in batch.drain(..) {
+            streams
+                .entry((entry.tenant, entry.namespace, entry.service))
+                .or_default()
+                .push([entry.timestamp_ns.to_string(), entry.line]);
+        }
+
+        let request = PushRequest {
+            streams: streams
+                .into_iter()
+                .map(|((tenant, namespace, service), values)| PushStream {
+                    stream: HashMap::from([
+                        ("service_tenant".to_string(), tenant),
+                        ("service_namespace".to_string(), namespace),
+                        ("proxy_service".to_string(), service),
+                        ("log_stream".to_string(), "stdout".to_string()),
+                    ]),
+                    values,
+                })
+                .collect(),
+        };
+
+        if let Err(e) = client.push(&request).await {
+            warn!("failed to push access logs: {}", e);
+        }
+    }
+}
+
 pub struct LogStream {
     inner: TailStream,
 }
@@ -225,3 +426,34 @@ impl Stream for LogStream {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filters_pushed_into_logql() {
+        let origin = LogStreamOrigin::Machine {
+            tenant: "acme".to_string(),
+            name: "api".to_string(),
+            namespace: None,
+        };
+        let filter = LogFilter {
+            stream: Some(LogStreamTarget::Stderr),
+            contains: Some("say \"hi\"".to_string()),
+            regex: Some("took \\d+ms".to_string()),
+            level: Some(LogLevel::Warn),
+        };
+
+        assert_eq!(
+            origin.loki_log_query(&filter),
+            "{ service_tenant = \"acme\", service_name = \"api\", log_stream = \"stderr\" } \
+             |= \"say \\\"hi\\\"\" |~ \"took \\\\d+ms\" \
+             | detected_level =~ \"warn|error|critical|fatal\""
+        );
+        assert_eq!(
+            origin.loki_log_query(&LogFilter::default()),
+            "{ service_tenant = \"acme\", service_name = \"api\" }"
+        );
+    }
+}
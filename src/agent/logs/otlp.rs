@@ -0,0 +1,524 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Read,
+    net::SocketAddr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Result, bail};
+use axum::{
+    Router,
+    body::Bytes,
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use flate2::read::GzDecoder;
+use opentelemetry_proto::tonic::{
+    collector::{
+        logs::v1::{ExportLogsServiceRequest, ExportLogsServiceResponse},
+        metrics::v1::{ExportMetricsServiceRequest, ExportMetricsServiceResponse},
+    },
+    common::v1::{AnyValue, KeyValue, any_value::Value},
+    metrics::v1::{Metric, metric::Data},
+};
+use prost::Message;
+use serde::{Serialize, de::DeserializeOwned};
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+
+use crate::agent::{
+    logs::loki::{LokiClient, PushRequest, PushStream},
+    metrics::MetricsAgent,
+    net::NetAgent,
+};
+
+/// Default port of the built-in ingest, the standard OTLP/HTTP port.
+pub const DEFAULT_OTLP_INGEST_PORT: u16 = 4318;
+
+/// Header guests name their tenant in, kept from when they exported to Loki directly.
+const TENANT_HEADER: &str = "x-scope-orgid";
+
+/// Gzip requests are turned away past this size once decompressed, a small body can inflate to
+/// gigabytes.
+const MAX_DECOMPRESSED_BODY_SIZE: u64 = 32 * 1024 * 1024;
+
+/// Resource attributes copied onto every datapoint, the metrics store only turns datapoint
+/// attributes into labels.
+const DATAPOINT_RESOURCE_ATTRIBUTES: [&str; 3] =
+    ["service.tenant", "service.namespace", "service.name"];
+
+struct OtlpIngestState {
+    net: Arc<NetAgent>,
+    loki_client: LokiClient,
+    metrics: Option<Arc<MetricsAgent>>,
+}
+
+/// Serves OTLP/HTTP log and metric exports of the machines, in protobuf or JSON, and writes them
+/// to the log and metrics stores. A request is only accepted from a machine address, its data is
+/// labelled with the tenant owning the address whatever its resource attributes say.
+pub async fn start_otlp_ingest_server(
+    bind_address: SocketAddr,
+    net: Arc<NetAgent>,
+    loki_client: LokiClient,
+    metrics: Option<Arc<MetricsAgent>>,
+) -> Result<()> {
+    let app = Router::new()
+        .route("/v1/logs", post(ingest_logs))
+        .route("/v1/metrics", post(ingest_metrics))
+        .with_state(Arc::new(OtlpIngestState {
+            net,
+            loki_client,
+            metrics,
+        }));
+
+    info!("starting OTLP ingest on {}", bind_address);
+    let listener = TcpListener::bind(bind_address).await?;
+
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// The tenant owning the source address of a request, unless it claims another one.
+fn authenticate(
+    state: &OtlpIngestState,
+    remote: SocketAddr,
+    headers: &HeaderMap,
+) -> Result<String, Response> {
+    let tenant = match state.net.ip_reservation_lookup(remote.ip().to_string()) {
+        Ok(Some(reservation)) => reservation.tenant,
+        _ => return Err((StatusCode::FORBIDDEN, "unknown source address").into_response()),
+    };
+
+    if let Some(claimed) = headers.get(TENANT_HEADER).and_then(|v| v.to_str().ok()) {
+        if claimed != tenant {
+            return Err((StatusCode::FORBIDDEN, "tenant mismatch").into_response());
+        }
+    }
+
+    Ok(tenant)
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("application/json"))
+}
+
+fn is_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|encoding| encoding.eq_ignore_ascii_case("gzip"))
+}
+
+fn export_response<T: Message + Serialize + Default>(json: bool) -> Response {
+    let response = T::default();
+    if json {
+        (
+            [(header::CONTENT_TYPE, "application/json")],
+            serde_json::to_vec(&response).unwrap_or_default(),
+        )
+            .into_response()
+    } else {
+        (
+            [(header::CONTENT_TYPE, "application/x-protobuf")],
+            response.encode_to_vec(),
+        )
+            .into_response()
+    }
+}
+
+async fn ingest_logs(
+    State(state): State<Arc<OtlpIngestState>>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
+    let tenant = match authenticate(&state, remote, &headers) {
+        Ok(tenant) => tenant,
+        Err(response) => return response,
+    };
+
+    let json = is_json(&headers);
+    let request = match decode_request::<ExportLogsServiceRequest>(&body, json, is_gzip(&headers)) {
+        Ok(request) => request,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+
+    let push_request = loki_push_request(&tenant, request);
+    if !push_request.streams.is_empty() {
+        if let Err(e) = state.loki_client.push(&push_request).await {
+            warn!("failed to push ingested logs for {}: {}", tenant, e);
+            return (StatusCode::SERVICE_UNAVAILABLE, "log store unavailable").into_response();
+        }
+    }
+
+    export_response::<ExportLogsServiceResponse>(json)
+}
+
+async fn ingest_metrics(
+    State(state): State<Arc<OtlpIngestState>>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
+    let Some(metrics) = state.metrics.clone() else {
+        return (StatusCode::NOT_FOUND, "metrics store not configured").into_response();
+    };
+
+    let tenant = match authenticate(&state, remote, &headers) {
+        Ok(tenant) => tenant,
+        Err(response) => return response,
+    };
+
+    let json = is_json(&headers);
+    let mut request =
+        match decode_request::<ExportMetricsServiceRequest>(&body, json, is_gzip(&headers)) {
+            Ok(request) => request,
+            Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        };
+
+    stamp_metrics_tenant(&tenant, &mut request);
+    if let Err(e) = metrics.push(&request).await {
+        warn!("failed to push ingested metrics for {}: {}", tenant, e);
+        return (StatusCode::SERVICE_UNAVAILABLE, "metrics store unavailable").into_response();
+    }
+
+    export_response::<ExportMetricsServiceResponse>(json)
+}
+
+fn decode_request<T: Message + DeserializeOwned + Default>(
+    body: &[u8],
+    json: bool,
+    gzip: bool,
+) -> Result<T> {
+    let mut decompressed = vec![];
+    let body = if gzip {
+        GzDecoder::new(body)
+            .take(MAX_DECOMPRESSED_BODY_SIZE + 1)
+            .read_to_end(&mut decompressed)?;
+        if decompressed.len() as u64 > MAX_DECOMPRESSED_BODY_SIZE {
+            bail!(
+                "OTLP request is over {} bytes decompressed",
+                MAX_DECOMPRESSED_BODY_SIZE
+            );
+        }
+        &decompressed[..]
+    } else {
+        body
+    };
+
+    if json {
+        return Ok(serde_json::from_slice(body)?);
+    }
+
+    match T::decode(body) {
+        Ok(request) => Ok(request),
+        Err(e) => bail!("invalid OTLP request: {}", e),
+    }
+}
+
+/// Overwrites the tenant resource attribute of every series with the authenticated one, and
+/// stamps the tenant, namespace and name of the resource onto its datapoints, replacing any
+/// the guest set there.
+fn stamp_metrics_tenant(tenant: &str, request: &mut ExportMetricsServiceRequest) {
+    for resource_metrics in &mut request.resource_metrics {
+        let resource = resource_metrics
+            .resource
+            .get_or_insert_with(Default::default);
+        resource
+            .attributes
+            .retain(|attribute| attribute.key != "service.tenant");
+        resource.attributes.push(KeyValue {
+            key: "service.tenant".to_string(),
+            value: Some(AnyValue {
+                value: Some(Value::StringValue(tenant.to_string())),
+            }),
+        });
+
+        let stamped = resource
+            .attributes
+            .iter()
+            .filter(|attribute| DATAPOINT_RESOURCE_ATTRIBUTES.contains(&attribute.key.as_str()))
+            .cloned()
+            .collect::<Vec<_>>();
+        let metrics = resource_metrics
+            .scope_metrics
+            .iter_mut()
+            .flat_map(|scope_metrics| scope_metrics.metrics.iter_mut());
+        for metric in metrics {
+            for attributes in datapoint_attributes(metric) {
+                attributes.retain(|attribute| {
+                    !DATAPOINT_RESOURCE_ATTRIBUTES.contains(&attribute.key.as_str())
+                });
+                attributes.extend(stamped.iter().cloned());
+            }
+        }
+    }
+}
+
+fn datapoint_attributes(metric: &mut Metric) -> Vec<&mut Vec<KeyValue>> {
+    match &mut metric.data {
+        Some(Data::Gauge(gauge)) => gauge
+            .data_points
+            .iter_mut()
+            .map(|point| &mut point.attributes)
+            .collect(),
+        Some(Data::Sum(sum)) => sum
+            .data_points
+            .iter_mut()
+            .map(|point| &mut point.attributes)
+            .collect(),
+        Some(Data::Histogram(histogram)) => histogram
+            .data_points
+            .iter_mut()
+            .map(|point| &mut point.attributes)
+            .collect(),
+        Some(Data::ExponentialHistogram(histogram)) => histogram
+            .data_points
+            .iter_mut()
+            .map(|point| &mut point.attributes)
+            .collect(),
+        Some(Data::Summary(summary)) => summary
+            .data_points
+            .iter_mut()
+            .map(|point| &mut point.attributes)
+            .collect(),
+        None => vec![],
+    }
+}
+
+fn any_value_string(value: &AnyValue) -> String {
+    match &value.value {
+        Some(Value::StringValue(s)) => s.clone(),
+        Some(Value::BoolValue(b)) => b.to_string(),
+        Some(Value::IntValue(i)) => i.to_string(),
+        Some(Value::DoubleValue(d)) => d.to_string(),
+        Some(other) => format!("{:?}", other),
+        None => String::new(),
+    }
+}
+
+fn attribute(attributes: &[KeyValue], key: &str) -> Option<String> {
+    attributes
+        .iter()
+        .find(|attribute| attribute.key == key)
+        .and_then(|attribute| attribute.value.as_ref())
+        .map(any_value_string)
+}
+
+/// Groups the records in streams labelled like the ones queried for machine logs.
+fn loki_push_request(tenant: &str, request: ExportLogsServiceRequest) -> PushRequest {
+    let now_ns = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default();
+
+    let mut streams: BTreeMap<Vec<(String, String)>, Vec<[String; 2]>> = BTreeMap::new();
+    for resource_logs in request.resource_logs {
+        let resource_attributes = resource_logs
+            .resource
+            .map(|resource| resource.attributes)
+            .unwrap_or_default();
+
+        let mut resource_labels = vec![("service_tenant".to_string(), tenant.to_string())];
+        for (attribute_key, label) in [
+            ("service.name", "service_name"),
+            ("service.namespace", "service_namespace"),
+            ("service.group", "service_group"),
+        ] {
+            if let Some(value) = attribute(&resource_attributes, attribute_key) {
+                resource_labels.push((label.to_string(), value));
+            }
+        }
+
+        for scope_logs in resource_logs.scope_logs {
+            for record in scope_logs.log_records {
+                let mut labels = resource_labels.clone();
+                labels.push((
+                    "log_stream".to_string(),
+                    attribute(&record.attributes, "log.stream")
+                        .unwrap_or_else(|| "stdout".to_string()),
+                ));
+                labels.sort();
+
+                let timestamp = [record.time_unix_nano, record.observed_time_unix_nano]
+                    .into_iter()
+                    .find(|timestamp| *timestamp > 0)
+                    .unwrap_or(now_ns);
+                let line = record
+                    .body
+                    .as_ref()
+                    .map(any_value_string)
+                    .unwrap_or_default();
+
+                streams
+                    .entry(labels)
+                    .or_default()
+                    .push([timestamp.to_string(), line]);
+            }
+        }
+    }
+
+    PushRequest {
+        streams: streams
+            .into_iter()
+            .map(|(labels, values)| PushStream {
+                stream: labels.into_iter().collect::<HashMap<_, _>>(),
+                values,
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry_proto::tonic::{
+        logs::v1::{LogRecord, ResourceLogs, ScopeLogs},
+        metrics::v1::{Gauge, NumberDataPoint, ResourceMetrics, ScopeMetrics},
+        resource::v1::Resource,
+    };
+
+    fn string_attribute(key: &str, value: &str) -> KeyValue {
+        KeyValue {
+            key: key.to_string(),
+            value: Some(AnyValue {
+                value: Some(Value::StringValue(value.to_string())),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_push_request_labels_with_authenticated_tenant() {
+        let request = ExportLogsServiceRequest {
+            resource_logs: vec![ResourceLogs {
+                resource: Some(Resource {
+                    attributes: vec![
+                        string_attribute("service.name", "api"),
+                        string_attribute("service.tenant", "someone-else"),
+                    ],
+                    ..Default::default()
+                }),
+                scope_logs: vec![ScopeLogs {
+                    log_records: vec![LogRecord {
+                        time_unix_nano: 42,
+                        body: Some(AnyValue {
+                            value: Some(Value::StringValue("listening".to_string())),
+                        }),
+                        attributes: vec![string_attribute("log.stream", "stderr")],
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+
+        let push_request = loki_push_request("acme", request);
+        assert_eq!(push_request.streams.len(), 1);
+
+        let stream = &push_request.streams[0];
+        assert_eq!(stream.stream["service_tenant"], "acme");
+        assert_eq!(stream.stream["service_name"], "api");
+        assert_eq!(stream.stream["log_stream"], "stderr");
+        assert_eq!(
+            stream.values,
+            vec![["42".to_string(), "listening".to_string()]]
+        );
+    }
+
+    #[test]
+    fn test_metrics_stamped_with_authenticated_tenant() {
+        let mut request = ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                resource: Some(Resource {
+                    attributes: vec![
+                        string_attribute("service.name", "api"),
+                        string_attribute("service.tenant", "someone-else"),
+                    ],
+                    ..Default::default()
+                }),
+                scope_metrics: vec![ScopeMetrics {
+                    metrics: vec![Metric {
+                        name: "process.cpu.utilization".to_string(),
+                        data: Some(Data::Gauge(Gauge {
+                            data_points: vec![NumberDataPoint {
+                                attributes: vec![
+                                    string_attribute("state", "user"),
+                                    string_attribute("service.tenant", "someone-else"),
+                                ],
+                                ..Default::default()
+                            }],
+                        })),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+
+        stamp_metrics_tenant("acme", &mut request);
+
+        let attributes = &request.resource_metrics[0]
+            .resource
+            .as_ref()
+            .unwrap()
+            .attributes;
+        assert_eq!(
+            attribute(attributes, "service.tenant").as_deref(),
+            Some("acme")
+        );
+        assert_eq!(
+            attribute(attributes, "service.name").as_deref(),
+            Some("api")
+        );
+        assert_eq!(attributes.len(), 2);
+
+        let Some(Data::Gauge(gauge)) =
+            &request.resource_metrics[0].scope_metrics[0].metrics[0].data
+        else {
+            panic!("expected a gauge");
+        };
+        let attributes = &gauge.data_points[0].attributes;
+        assert_eq!(attribute(attributes, "state").as_deref(), Some("user"));
+        assert_eq!(
+            attribute(attributes, "service.tenant").as_deref(),
+            Some("acme")
+        );
+        assert_eq!(
+            attribute(attributes, "service.name").as_deref(),
+            Some("api")
+        );
+        assert_eq!(attributes.len(), 3);
+    }
+
+    #[test]
+    fn test_decode_request_rejects_gzip_bombs() {
+        use flate2::{Compression, write::GzEncoder};
+        use std::io::Write;
+
+        let gzip = |data: &[u8]| {
+            let mut encoder = GzEncoder::new(vec![], Compression::default());
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap()
+        };
+
+        let request = ExportMetricsServiceRequest::default();
+        let body = gzip(&request.encode_to_vec());
+        assert!(decode_request::<ExportMetricsServiceRequest>(&body, false, true).is_ok());
+
+        let bomb = gzip(&vec![0; MAX_DECOMPRESSED_BODY_SIZE as usize + 1]);
+        assert!(decode_request::<ExportMetricsServiceRequest>(&bomb, false, true).is_err());
+    }
+}
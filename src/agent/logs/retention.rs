@@ -0,0 +1,228 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::agent::logs::loki::{DeleteRequest, LokiClient};
+
+/// How often the retention is enforced, the log store prunes asynchronously anyway.
+const RETENTION_ENFORCE_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct LogRetentionConfig {
+    /// Days logs are kept for, forever when not set.
+    #[serde(rename = "default-days")]
+    pub default_days: Option<u32>,
+    /// Retention of a tenant, or of one of its namespaces, in place of the default.
+    #[serde(rename = "override", default)]
+    pub overrides: Vec<LogRetentionOverride>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct LogRetentionOverride {
+    pub tenant: String,
+    /// The whole tenant when not set.
+    pub namespace: Option<String>,
+    pub days: u32,
+}
+
+/// A deletion enforcing the retention: the entries matching `selector` older than `days`.
+#[derive(Debug, Clone, PartialEq)]
+struct RetentionRule {
+    selector: String,
+    days: u32,
+}
+
+fn selector(matchers: Vec<String>) -> String {
+    format!("{{ {} }}", matchers.join(", "))
+}
+
+/// Splits the streams in disjoint selectors, each carrying the retention that applies to it:
+/// namespace overrides win over tenant overrides, which win over the default.
+fn retention_rules(config: &LogRetentionConfig) -> Vec<RetentionRule> {
+    let mut tenants: BTreeMap<&str, (Option<u32>, BTreeMap<&str, u32>)> = BTreeMap::new();
+    for retention in &config.overrides {
+        let (tenant_days, namespaces) = tenants.entry(&retention.tenant).or_default();
+        match &retention.namespace {
+            Some(namespace) => {
+                namespaces.insert(namespace, retention.days);
+            }
+            None => *tenant_days = Some(retention.days),
+        }
+    }
+
+    let mut rules = vec![];
+
+    if let Some(days) = config.default_days {
+        let mut matchers = vec!["service_tenant =~ \".+\"".to_string()];
+        for tenant in tenants.keys() {
+            matchers.push(format!("service_tenant != \"{}\"", tenant));
+        }
+        rules.push(RetentionRule {
+            selector: selector(matchers),
+            days,
+        });
+    }
+
+    for (tenant, (tenant_days, namespaces)) in tenants {
+        if let Some(days) = tenant_days.or(config.default_days) {
+            let mut matchers = vec![format!("service_tenant = \"{}\"", tenant)];
+            for namespace in namespaces.keys() {
+                matchers.push(format!("service_namespace != \"{}\"", namespace));
+            }
+            rules.push(RetentionRule {
+                selector: selector(matchers),
+                days,
+            });
+        }
+
+        for (namespace, days) in namespaces {
+            rules.push(RetentionRule {
+                selector: selector(vec![
+                    format!("service_tenant = \"{}\"", tenant),
+                    format!("service_namespace = \"{}\"", namespace),
+                ]),
+                days,
+            });
+        }
+    }
+
+    rules
+}
+
+/// The time up to which the deletions already requested for `selector` reach, processed or not.
+fn requested_until(requests: &[DeleteRequest], selector: &str) -> Option<u64> {
+    requests
+        .iter()
+        .filter(|request| request.query == selector)
+        .map(|request| request.end_time as u64)
+        .max()
+}
+
+/// Periodically asks the log store to delete the entries past their retention. Each request only
+/// covers what the earlier ones for the same selector didn't, so they don't pile up.
+pub async fn enforce_log_retention(client: LokiClient, config: LogRetentionConfig) {
+    let rules = retention_rules(&config);
+    if rules.is_empty() {
+        return;
+    }
+
+    info!("enforcing log retention with {} rules", rules.len());
+
+    let mut interval = tokio::time::interval(RETENTION_ENFORCE_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        // kept by the log store, so requests made before a restart count too
+        let requested = match client.list_deletes().await {
+            Ok(requested) => requested,
+            Err(e) => {
+                warn!("failed to list the requested log deletions: {}", e);
+                continue;
+            }
+        };
+
+        for rule in &rules {
+            let end = now.saturating_sub(rule.days as u64 * SECONDS_PER_DAY);
+            let start = requested_until(&requested, &rule.selector);
+            if start.is_some_and(|start| start >= end) {
+                continue;
+            }
+
+            if let Err(e) = client.delete(&rule.selector, start, end).await {
+                warn!(
+                    "failed to enforce log retention for {}: {}",
+                    rule.selector, e
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requested_until() {
+        let requests = vec![
+            DeleteRequest {
+                query: "{ service_tenant = \"free\" }".to_string(),
+                end_time: 1_000.0,
+            },
+            DeleteRequest {
+                query: "{ service_tenant = \"free\" }".to_string(),
+                end_time: 22_600.5,
+            },
+            DeleteRequest {
+                query: "{ service_tenant = \"acme\" }".to_string(),
+                end_time: 50_000.0,
+            },
+        ];
+
+        assert_eq!(
+            requested_until(&requests, "{ service_tenant = \"free\" }"),
+            Some(22_600)
+        );
+        assert_eq!(
+            requested_until(&requests, "{ service_tenant = \"other\" }"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_overrides_carved_out_of_default() {
+        let config = LogRetentionConfig {
+            default_days: Some(30),
+            overrides: vec![
+                LogRetentionOverride {
+                    tenant: "acme".to_string(),
+                    namespace: Some("audit".to_string()),
+                    days: 365,
+                },
+                LogRetentionOverride {
+                    tenant: "free".to_string(),
+                    namespace: None,
+                    days: 7,
+                },
+            ],
+        };
+
+        assert_eq!(
+            retention_rules(&config),
+            vec![
+                RetentionRule {
+                    selector: "{ service_tenant =~ \".+\", service_tenant != \"acme\", \
+                               service_tenant != \"free\" }"
+                        .to_string(),
+                    days: 30,
+                },
+                RetentionRule {
+                    selector: "{ service_tenant = \"acme\", service_namespace != \"audit\" }"
+                        .to_string(),
+                    days: 30,
+                },
+                RetentionRule {
+                    selector: "{ service_tenant = \"acme\", service_namespace = \"audit\" }"
+                        .to_string(),
+                    days: 365,
+                },
+                RetentionRule {
+                    selector: "{ service_tenant = \"free\" }".to_string(),
+                    days: 7,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Result, anyhow};
+use tracing::warn;
+
+/// Scheduling period of `cpu.max`, the kernel default.
+const CPU_PERIOD_US: u64 = 100_000;
+/// `cpu.weight` given per vcpu, the kernel default weight of a cgroup.
+const CPU_WEIGHT_PER_VCPU: u64 = 100;
+const CPU_WEIGHT_MAX: u64 = 10_000;
+
+/// Prepares the cgroup v2 directory `root` to hold a threaded cgroup per machine. The daemon
+/// moves itself in, so the vcpu threads it spawns can be placed in the machine cgroups below.
+pub fn init_cgroup_root(root: &Path) -> Result<()> {
+    fs::create_dir_all(root)?;
+    fs::write(root.join("cgroup.procs"), std::process::id().to_string())
+        .map_err(|e| anyhow!("failed to move the daemon to {}: {}", root.display(), e))?;
+    fs::write(root.join("cgroup.subtree_control"), "+cpu").map_err(|e| {
+        anyhow!(
+            "failed to enable the cpu controller in {}: {}",
+            root.display(),
+            e
+        )
+    })?;
+
+    Ok(())
+}
+
+/// `cpu.max` of a machine: `limit_millis` of CPU time per second, all of its vcpus without a
+/// limit.
+fn cpu_max(vcpus: u8, limit_millis: Option<u32>) -> String {
+    let limit_millis = limit_millis.unwrap_or(vcpus as u32 * 1000) as u64;
+    format!("{} {}", limit_millis * CPU_PERIOD_US / 1000, CPU_PERIOD_US)
+}
+
+/// Machines with more vcpus get a bigger share of a busy host.
+fn cpu_weight(vcpus: u8) -> u64 {
+    (vcpus as u64 * CPU_WEIGHT_PER_VCPU).clamp(1, CPU_WEIGHT_MAX)
+}
+
+/// Directory of the cgroup of machine `name`, namespaced names like `<tenant>-<ns>/<name>`
+/// would otherwise nest it in a cgroup that doesn't exist.
+fn cgroup_name(name: &str) -> String {
+    format!("machine-{}", name.replace('/', "."))
+}
+
+/// Threaded cgroup the vcpu threads of a machine run in, removed once the last vcpu is gone.
+#[derive(Debug)]
+pub struct MachineCgroup {
+    path: PathBuf,
+}
+
+impl MachineCgroup {
+    pub fn create(root: &Path, name: &str, vcpus: u8, limit_millis: Option<u32>) -> Result<Self> {
+        let path = root.join(cgroup_name(name));
+        if !path.exists() {
+            fs::create_dir(&path)?;
+        }
+
+        fs::write(path.join("cgroup.type"), "threaded")?;
+        fs::write(path.join("cpu.max"), cpu_max(vcpus, limit_millis))?;
+        fs::write(path.join("cpu.weight"), cpu_weight(vcpus).to_string())?;
+
+        Ok(Self { path })
+    }
+
+    /// Moves the calling thread into the cgroup.
+    pub fn add_current_thread(&self) -> Result<()> {
+        let tid = unsafe { libc::gettid() };
+        fs::write(self.path.join("cgroup.threads"), tid.to_string()).map_err(|e| {
+            anyhow!(
+                "failed to move thread {} to {}: {}",
+                tid,
+                self.path.display(),
+                e
+            )
+        })
+    }
+}
+
+impl Drop for MachineCgroup {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir(&self.path) {
+            warn!("failed to remove cgroup {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cpu_max() {
+        assert_eq!(cpu_max(2, None), "200000 100000");
+        assert_eq!(cpu_max(2, Some(500)), "50000 100000");
+        assert_eq!(cpu_max(1, Some(1500)), "150000 100000");
+    }
+
+    #[test]
+    fn test_cpu_weight() {
+        assert_eq!(cpu_weight(1), 100);
+        assert_eq!(cpu_weight(4), 400);
+        assert_eq!(cpu_weight(200), CPU_WEIGHT_MAX);
+    }
+
+    #[test]
+    fn test_create_namespaced() {
+        let root = tempfile::tempdir().unwrap();
+        let cgroup = MachineCgroup::create(root.path(), "acme-prod/api", 2, Some(500)).unwrap();
+
+        assert_eq!(cgroup.path, root.path().join("machine-acme-prod.api"));
+        assert_eq!(
+            fs::read_to_string(cgroup.path.join("cpu.max")).unwrap(),
+            "50000 100000"
+        );
+    }
+}
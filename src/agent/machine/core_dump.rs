@@ -0,0 +1,138 @@
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Result, bail};
+use tracing::warn;
+
+const CORE_DUMP_EXTENSION: &str = "core";
+const PARTIAL_CORE_DUMP_EXTENSION: &str = "core.partial";
+
+/// How many cores are kept per machine, older ones are removed as new ones come in.
+pub const CORE_DUMPS_KEPT_PER_MACHINE: usize = 5;
+
+#[derive(Debug, Clone)]
+pub struct CoreDumpInfo {
+    /// Milliseconds since the epoch at which the upload started.
+    pub id: String,
+    pub created_at: u64,
+    pub size: u64,
+}
+
+/// A core being received from the guest, chunk by chunk. Anything past `max_size` is dropped.
+pub struct CoreDumpUpload {
+    id: String,
+    dir: PathBuf,
+    file: Option<File>,
+    size: u64,
+    max_size: u64,
+}
+
+impl CoreDumpUpload {
+    pub fn start(dir: &Path, max_size: u64) -> Result<Self> {
+        std::fs::create_dir_all(dir)?;
+
+        let id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)?
+            .as_millis()
+            .to_string();
+        let file = File::create(dir.join(format!("{}.{}", id, PARTIAL_CORE_DUMP_EXTENSION)))?;
+
+        Ok(Self {
+            id,
+            dir: dir.to_path_buf(),
+            file: Some(file),
+            size: 0,
+            max_size,
+        })
+    }
+
+    pub fn write(&mut self, chunk: &[u8]) {
+        let Some(file) = &mut self.file else {
+            return;
+        };
+
+        let len = chunk
+            .len()
+            .min(self.max_size.saturating_sub(self.size) as usize);
+        if let Err(e) = file.write_all(&chunk[..len]) {
+            warn!("Failed to write core dump {}: {}", self.id, e);
+            self.file = None;
+            return;
+        }
+
+        self.size += len as u64;
+        if self.size >= self.max_size {
+            warn!("Core dump {} truncated at {} bytes", self.id, self.max_size);
+            self.file = None;
+        }
+    }
+
+    /// Makes the core visible to `list_core_dumps` and returns its id.
+    pub fn finish(self) -> Result<String> {
+        drop(self.file);
+
+        std::fs::rename(
+            self.dir
+                .join(format!("{}.{}", self.id, PARTIAL_CORE_DUMP_EXTENSION)),
+            self.dir
+                .join(format!("{}.{}", self.id, CORE_DUMP_EXTENSION)),
+        )?;
+        prune_core_dumps(&self.dir, CORE_DUMPS_KEPT_PER_MACHINE)?;
+
+        Ok(self.id)
+    }
+}
+
+/// Cores stored in `dir`, newest first.
+pub fn list_core_dumps(dir: &Path) -> Result<Vec<CoreDumpInfo>> {
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut core_dumps = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let Some(id) = file_name.strip_suffix(&format!(".{}", CORE_DUMP_EXTENSION)) else {
+            continue;
+        };
+        let Ok(created_at) = id.parse::<u64>() else {
+            continue;
+        };
+
+        core_dumps.push(CoreDumpInfo {
+            id: id.to_string(),
+            created_at,
+            size: entry.metadata()?.len(),
+        });
+    }
+
+    core_dumps.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    Ok(core_dumps)
+}
+
+pub fn core_dump_path(dir: &Path, id: &str) -> Result<PathBuf> {
+    // ids are timestamps, anything else could point outside of the directory
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_digit()) {
+        bail!("Invalid core dump id '{}'", id);
+    }
+
+    let path = dir.join(format!("{}.{}", id, CORE_DUMP_EXTENSION));
+    if !path.exists() {
+        bail!("Core dump '{}' not found", id);
+    }
+
+    Ok(path)
+}
+
+fn prune_core_dumps(dir: &Path, keep: usize) -> Result<()> {
+    for core_dump in list_core_dumps(dir)?.into_iter().skip(keep) {
+        std::fs::remove_file(dir.join(format!("{}.{}", core_dump.id, CORE_DUMP_EXTENSION)))?;
+    }
+
+    Ok(())
+}
@@ -1,52 +1,64 @@
 use std::{
     collections::HashMap,
     path::PathBuf,
-    sync::{Arc, Barrier, Weak},
+    sync::{
+        Arc, Barrier, Weak,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
     time::{Duration, Instant},
 };
 
 use anyhow::{Result, anyhow, bail};
 use event_manager::{EventManager, MutEventSubscriber};
 use kvm_ioctls::VmFd;
-use takeoff_proto::proto::{LogsTelemetryConfig, MountPoint, TakeoffInitArgs};
+use takeoff_proto::proto::{
+    CoreDumpConfig, DebugTraceConfig, HealthConfig, LogsTelemetryConfig, MetricsTelemetryConfig,
+    MountPoint, TakeoffInitArgs, VolumeUsageReport,
+};
 use tempfile::tempdir;
 use tokio::{
     fs::create_dir_all,
     io::{AsyncReadExt, AsyncWriteExt},
     net::TcpStream,
-    sync::{RwLock, broadcast, mpsc, oneshot},
+    sync::{RwLock, broadcast, mpsc, oneshot, watch},
     task::JoinHandle,
     time::sleep,
 };
 use tracing::{info, warn};
 use vm_allocator::AddressAllocator;
 use vm_device::device_manager::IoManager;
-use vm_memory::{GuestAddress, GuestMemory, GuestMemoryMmap};
+use vm_memory::{GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
 
 use crate::{
     agent::{
         image::Image,
         machine::{
             MachineAgentConfig,
+            cgroup::MachineCgroup,
             state_machine::{MachineStateMachine, StateCommand},
             vm::{
                 constants::SERIAL_IRQ,
                 devices::{
-                    DeviceEvent, VmDevices, alloc::IrqAllocator, setup_devices,
-                    virtio::block::get_block_mount_source_by_index,
+                    DeviceEvent, VmDevices, alloc::IrqAllocator, legacy::serial::ConsoleOutput,
+                    setup_devices, virtio::block::get_block_mount_source_by_index,
                 },
                 kernel::{create_cmdline, load_kernel},
                 kvm::create_and_verify_kvm,
                 memory::{create_memory, create_mmio_allocator},
-                vcpu::{Vcpu, VcpuEvent, VcpuEventType},
+                vcpu::{Vcpu, VcpuCpuTime, VcpuEvent, VcpuEventType},
             },
         },
-        volume::Volume,
+        volume::{Volume, encryption::VolumeKey},
     },
+    constants::DEFAULT_CORE_DUMP_MAX_SIZE_MIB,
     controller::{context::ControllerKey, scheduler::Scheduler},
 };
 
 const CONNECTION_CHECK_INTERVAL: Duration = Duration::from_secs(3);
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const CONSOLE_INPUT_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+/// Console input is given up on after a second of the guest not reading any of it.
+const CONSOLE_INPUT_MAX_STALLS: u32 = 100;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum MachineState {
@@ -88,6 +100,8 @@ pub enum SnapshotStrategy {
 pub struct MachineResources {
     pub cpu: u8,
     pub memory: u64,
+    /// CPU time the vcpus get per second of wall time, in millis. Defaults to all of them.
+    pub cpu_limit: Option<u32>,
 }
 
 #[derive(Debug, Clone)]
@@ -104,6 +118,12 @@ pub struct MachineConfig {
     pub volume_mounts: Vec<VolumeMountConfig>,
     pub network: NetworkConfig,
     pub logs_telemetry_config: LogsTelemetryConfig,
+    pub metrics_telemetry_config: Option<MetricsTelemetryConfig>,
+    pub debug_trace: Option<DebugTraceConfig>,
+    pub health: Option<HealthConfig>,
+    pub kernel_params: Vec<String>,
+    /// Clone of a flash machine kept resumed, it never suspends on inactivity.
+    pub prewarm: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -112,6 +132,8 @@ pub struct VolumeMountConfig {
     pub mount_at: String,
     pub read_only: bool,
     pub root: bool,
+    /// Set for encrypted volumes, the block device encrypts and decrypts with it.
+    pub encryption_key: Option<VolumeKey>,
 }
 
 #[derive(Debug, Clone)]
@@ -124,6 +146,18 @@ pub struct NetworkConfig {
     pub dns_servers: Vec<String>,
 }
 
+/// Resource counters of a machine, cumulative since it was created except for memory.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MachineUsageCounters {
+    pub cpu_time: Duration,
+    pub memory_resident_bytes: u64,
+    /// Bytes sent by the host to the machine, the tap's transmitted bytes.
+    pub network_rx_bytes: u64,
+    pub network_tx_bytes: u64,
+    pub disk_read_bytes: u64,
+    pub disk_written_bytes: u64,
+}
+
 pub enum MachineStopReason {
     Stop,
     Suspend,
@@ -150,12 +184,58 @@ impl TrafficAwareMode {
     }
 }
 
+/// Bytes proxied to and from machines, from the client's point of view: `in` is received from
+/// clients, `out` is sent back to them.
+#[derive(Debug, Default)]
+pub struct TrafficCounters {
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    unflushed_in: AtomicU64,
+    unflushed_out: AtomicU64,
+}
+
+impl TrafficCounters {
+    pub fn record_in(&self, bytes: u64) {
+        self.bytes_in.fetch_add(bytes, Ordering::Relaxed);
+        self.unflushed_in.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn record_out(&self, bytes: u64) {
+        self.bytes_out.fetch_add(bytes, Ordering::Relaxed);
+        self.unflushed_out.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn bytes_in(&self) -> u64 {
+        self.bytes_in.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_out(&self) -> u64 {
+        self.bytes_out.load(Ordering::Relaxed)
+    }
+
+    /// Bytes in and out counted since the previous call, for persisting usage incrementally.
+    pub fn take_unflushed(&self) -> (u64, u64) {
+        (
+            self.unflushed_in.swap(0, Ordering::Relaxed),
+            self.unflushed_out.swap(0, Ordering::Relaxed),
+        )
+    }
+
+    /// Puts back bytes taken by `take_unflushed` that couldn't be persisted.
+    pub fn restore_unflushed(&self, bytes_in: u64, bytes_out: u64) {
+        self.unflushed_in.fetch_add(bytes_in, Ordering::Relaxed);
+        self.unflushed_out.fetch_add(bytes_out, Ordering::Relaxed);
+    }
+}
+
 pub struct TrafficAwareConnection {
     machine: MachineRef,
     pub upstream_socket: TcpStream,
     state: Arc<RwLock<ConnectionState>>,
     last_activity: Arc<RwLock<Instant>>,
     mode: TrafficAwareMode,
+    idle_timeout: Option<Duration>,
+    traffic: Option<Arc<TrafficCounters>>,
 }
 
 impl TrafficAwareConnection {
@@ -163,6 +243,7 @@ impl TrafficAwareConnection {
         machine: MachineRef,
         target_port: u16,
         mode: TrafficAwareMode,
+        connect_timeout: Option<Duration>,
     ) -> Result<Self> {
         let machine_ip = machine.config.network.ip_address.clone();
         let address = format!("{machine_ip}:{}", target_port);
@@ -172,7 +253,13 @@ impl TrafficAwareConnection {
 
         // Try connecting with timeout and retry logic
         let upstream_socket =
-            match Self::connect_with_retry(&address, 3, Duration::from_secs(5)).await {
+            match Self::connect_with_retry(
+                &address,
+                3,
+                connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT),
+            )
+            .await
+            {
                 Ok(socket) => socket,
                 Err(e) => {
                     // If connection fails, remove the flash lock we just added
@@ -180,6 +267,7 @@ impl TrafficAwareConnection {
                     return Err(e);
                 }
             };
+        machine.open_connections.fetch_add(1, Ordering::Relaxed);
 
         Ok(Self {
             machine,
@@ -187,9 +275,21 @@ impl TrafficAwareConnection {
             state: Arc::new(RwLock::new(ConnectionState::Active)),
             last_activity: Arc::new(RwLock::new(Instant::now())),
             mode,
+            idle_timeout: None,
+            traffic: None,
         })
     }
 
+    /// Closes the proxied streams once no bytes went through in either direction for `timeout`.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.idle_timeout = timeout;
+    }
+
+    /// Counts the bytes proxied by `proxy_from_client` into `traffic`.
+    pub fn set_traffic_counters(&mut self, traffic: Option<Arc<TrafficCounters>>) {
+        self.traffic = traffic;
+    }
+
     async fn connect_with_retry(
         address: &str,
         max_retries: u32,
@@ -220,10 +320,10 @@ impl TrafficAwareConnection {
                 }
                 Err(_) => {
                     warn!(
-                        "Connection attempt {} to {} timed out after {}s",
+                        "Connection attempt {} to {} timed out after {}ms",
                         attempt + 1,
                         address,
-                        timeout.as_secs()
+                        timeout.as_millis()
                     );
                     last_error = Some(anyhow!("Connection timeout"));
                 }
@@ -275,6 +375,7 @@ impl TrafficAwareConnection {
         }
 
         *last_activity = Instant::now();
+        *self.machine.last_traffic_time.write().await = Some(*last_activity);
     }
 
     async fn check_inactivity(&self) {
@@ -298,6 +399,13 @@ impl TrafficAwareConnection {
         }
     }
 
+    fn check_interval(&self) -> Duration {
+        match self.idle_timeout {
+            Some(idle_timeout) => CONNECTION_CHECK_INTERVAL.min(idle_timeout),
+            None => CONNECTION_CHECK_INTERVAL,
+        }
+    }
+
     pub async fn proxy_from_client<T>(&mut self, mut tls_stream: T) -> Result<()>
     where
         T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
@@ -319,6 +427,9 @@ impl TrafficAwareConnection {
                                     // Upstream write failed, close both connections
                                     break;
                                 }
+                                if let Some(traffic) = &self.traffic {
+                                    traffic.record_in(n as u64);
+                                }
                             } else {
                                 // TLS stream closed, close both connections
                                 break;
@@ -341,6 +452,9 @@ impl TrafficAwareConnection {
                                     // TLS write failed, close both connections
                                     break;
                                 }
+                                if let Some(traffic) = &self.traffic {
+                                    traffic.record_out(n as u64);
+                                }
                             } else {
                                 // Upstream closed, close both connections
                                 break;
@@ -353,10 +467,21 @@ impl TrafficAwareConnection {
                     }
                 }
 
-                _ = sleep(CONNECTION_CHECK_INTERVAL) => {
+                _ = sleep(self.check_interval()) => {
                     if matches!(self.mode, TrafficAwareMode::Enabled { .. }) {
                         self.check_inactivity().await;
                     }
+
+                    if let Some(idle_timeout) = self.idle_timeout {
+                        if self.last_activity.read().await.elapsed() >= idle_timeout {
+                            info!(
+                                "Closing connection to machine '{}' after being idle for {}s",
+                                self.machine.config.name,
+                                idle_timeout.as_secs()
+                            );
+                            break;
+                        }
+                    }
                 }
             }
         }
@@ -370,6 +495,8 @@ impl TrafficAwareConnection {
 
 impl Drop for TrafficAwareConnection {
     fn drop(&mut self) {
+        self.machine.open_connections.fetch_sub(1, Ordering::Relaxed);
+
         let machine = self.machine.clone();
         let state = self.state.clone();
 
@@ -383,6 +510,22 @@ impl Drop for TrafficAwareConnection {
     }
 }
 
+/// Keeps a machine awake for traffic that isn't carried over a TCP connection (eg. UDP),
+/// releasing it on drop.
+pub struct MachineAwakeGuard {
+    machine: MachineRef,
+}
+
+impl Drop for MachineAwakeGuard {
+    fn drop(&mut self) {
+        let machine = self.machine.clone();
+
+        tokio::spawn(async move {
+            let _ = machine.send_flash_unlock().await;
+        });
+    }
+}
+
 #[allow(unused)]
 pub struct Machine {
     pub config: MachineConfig,
@@ -408,6 +551,17 @@ pub struct Machine {
     last_start_time: Arc<tokio::sync::RwLock<Option<Instant>>>,
     last_ready_time: Arc<tokio::sync::RwLock<Option<Instant>>>,
     last_exit_code: Arc<tokio::sync::RwLock<Option<i32>>>,
+    last_core_dump: Arc<tokio::sync::RwLock<Option<String>>>,
+    // Last time the proxy moved bytes to or from the machine, for idle suspend policies
+    last_traffic_time: tokio::sync::RwLock<Option<Instant>>,
+
+    // Draining machines get no new connections, they are removed once the open ones close
+    draining: AtomicBool,
+    open_connections: AtomicU64,
+    // Machines suspended by the user stay suspended until resumed, traffic doesn't wake them
+    user_suspended: AtomicBool,
+    // CPU time of the vcpu threads, shared with the vcpus
+    vcpu_cpu_time: Arc<VcpuCpuTime>,
 
     // Legacy fields for compatibility (will be removed later)
     vcpu_event_tx: async_broadcast::Sender<VcpuEvent>,
@@ -433,6 +587,9 @@ impl Machine {
         // init kernel cmdline
         let mut kernel_cmd = create_cmdline(&config)?;
         kernel_cmd.insert_str(&agent_config.kernel_cmd_init)?;
+        for param in config.kernel_params.iter() {
+            kernel_cmd.insert_str(param)?;
+        }
 
         let takeoff_args: TakeoffInitArgs = TakeoffInitArgs {
             envs: config.envs.clone(),
@@ -445,9 +602,16 @@ impl Machine {
                     source: get_block_mount_source_by_index(index as u16),
                     target: mount.mount_at.clone(),
                     read_only: mount.read_only,
+                    filesystem: mount.volume.filesystem,
                 })
                 .collect(),
             logs_telemetry_config: config.logs_telemetry_config.clone(),
+            debug_trace: config.debug_trace.clone(),
+            health: config.health.clone(),
+            core_dump: Some(CoreDumpConfig {
+                max_size: DEFAULT_CORE_DUMP_MAX_SIZE_MIB * 1024 * 1024,
+            }),
+            metrics_telemetry_config: config.metrics_telemetry_config.clone(),
         };
 
         let mut io_manager = IoManager::new();
@@ -483,6 +647,7 @@ impl Machine {
             &mut event_manager,
             &mut kernel_cmd,
             log_path.to_string_lossy().as_ref(),
+            &agent_config.core_dumps_path.join(&config.name),
             device_event_tx.clone(),
         )
         .await?;
@@ -519,6 +684,17 @@ impl Machine {
         let barrier = Arc::new(Barrier::new(config.resources.cpu as usize));
         let (vcpu_event_tx, _vcpu_event_rx) = async_broadcast::broadcast::<VcpuEvent>(128);
 
+        let cgroup = match &agent_config.cgroup_root {
+            Some(cgroup_root) => Some(Arc::new(MachineCgroup::create(
+                cgroup_root,
+                &config.name,
+                config.resources.cpu,
+                config.resources.cpu_limit,
+            )?)),
+            None => None,
+        };
+
+        let vcpu_cpu_time = Arc::new(VcpuCpuTime::default());
         let mut vcpus = vec![];
         for i in 0..config.resources.cpu {
             let vcpu = Vcpu::new(
@@ -532,6 +708,8 @@ impl Machine {
                 kernel_start_address.clone(),
                 config.resources.cpu as u8,
                 i,
+                cgroup.clone(),
+                vcpu_cpu_time.clone(),
             )
             .await?;
             vcpus.push(vcpu);
@@ -566,6 +744,12 @@ impl Machine {
             last_start_time: last_start_time.clone(),
             last_ready_time: last_ready_time.clone(),
             last_exit_code: last_exit_code.clone(),
+            last_core_dump: Arc::new(tokio::sync::RwLock::new(None)),
+            last_traffic_time: tokio::sync::RwLock::new(None),
+            draining: AtomicBool::new(false),
+            open_connections: AtomicU64::new(0),
+            user_suspended: AtomicBool::new(false),
+            vcpu_cpu_time,
             vcpu_event_tx,
             device_event_tx,
             vcpu_start_barrier: barrier,
@@ -636,10 +820,16 @@ impl Machine {
         // Device watcher - sends commands instead of direct state changes
         let device_command_tx = command_tx.clone();
         let device_event_rx = machine.device_event_tx.new_receiver();
+        let last_core_dump = machine.last_core_dump.clone();
         let _device_watcher = tokio::spawn(async move {
             let mut rx = device_event_rx;
             while let Ok(event) = rx.recv().await {
                 let command = match event {
+                    DeviceEvent::CoreDump(id) => {
+                        // sent ahead of the exit code, so it is known once the machine stops
+                        *last_core_dump.write().await = Some(id);
+                        continue;
+                    }
                     DeviceEvent::UserSpaceReady => StateCommand::SystemDeviceReady,
                     DeviceEvent::StopRequested => StateCommand::SystemStopRequested,
                     DeviceEvent::FlashLock => StateCommand::SystemFlashLock,
@@ -662,7 +852,12 @@ impl Machine {
         self: &Arc<Self>,
         target_port: u16,
         inactivity_timeout: Option<Duration>,
+        connect_timeout: Option<Duration>,
     ) -> Result<TrafficAwareConnection> {
+        if self.is_user_suspended() {
+            bail!("Machine is suspended, resume it to connect");
+        }
+
         let current_state = self.get_state().await;
 
         let inactivity_mode = match inactivity_timeout {
@@ -673,12 +868,24 @@ impl Machine {
         };
 
         if current_state == MachineState::Ready {
-            return TrafficAwareConnection::new(self.clone(), target_port, inactivity_mode).await;
+            return TrafficAwareConnection::new(
+                self.clone(),
+                target_port,
+                inactivity_mode,
+                connect_timeout,
+            )
+            .await;
         }
 
         if current_state == MachineState::Booting {
             self.wait_for_state(MachineState::Ready).await?;
-            return TrafficAwareConnection::new(self.clone(), target_port, inactivity_mode).await;
+            return TrafficAwareConnection::new(
+                self.clone(),
+                target_port,
+                inactivity_mode,
+                connect_timeout,
+            )
+            .await;
         }
 
         // Wait for suspension to complete if machine is suspending
@@ -688,7 +895,8 @@ impl Machine {
             );
             self.wait_for_state(MachineState::Suspended).await?;
             // Recursively call get_connection to handle the Suspended state
-            return Box::pin(self.get_connection(target_port, inactivity_timeout)).await;
+            return Box::pin(self.get_connection(target_port, inactivity_timeout, connect_timeout))
+                .await;
         }
 
         if !matches!(
@@ -702,17 +910,63 @@ impl Machine {
 
         let state_after_lock = self.get_state().await;
         if state_after_lock == MachineState::Ready {
-            return TrafficAwareConnection::new(self.clone(), target_port, inactivity_mode).await;
+            return TrafficAwareConnection::new(
+                self.clone(),
+                target_port,
+                inactivity_mode,
+                connect_timeout,
+            )
+            .await;
         }
         if state_after_lock == MachineState::Booting {
             self.wait_for_state(MachineState::Ready).await?;
-            return TrafficAwareConnection::new(self.clone(), target_port, inactivity_mode).await;
+            return TrafficAwareConnection::new(
+                self.clone(),
+                target_port,
+                inactivity_mode,
+                connect_timeout,
+            )
+            .await;
         }
 
         self.start().await?;
         self.wait_for_state(MachineState::Ready).await?;
 
-        TrafficAwareConnection::new(self.clone(), target_port, inactivity_mode).await
+        TrafficAwareConnection::new(self.clone(), target_port, inactivity_mode, connect_timeout)
+            .await
+    }
+
+    /// Starts the machine if needed and keeps it awake until the guard is dropped.
+    pub async fn hold_awake(self: &Arc<Self>) -> Result<MachineAwakeGuard> {
+        if self.is_user_suspended() {
+            bail!("Machine is suspended, resume it to connect");
+        }
+
+        loop {
+            match self.get_state().await {
+                MachineState::Ready => break,
+                MachineState::Booting => {
+                    self.wait_for_state(MachineState::Ready).await?;
+                    break;
+                }
+                MachineState::Suspending => {
+                    self.wait_for_state(MachineState::Suspended).await?;
+                }
+                MachineState::Idle | MachineState::Stopped | MachineState::Suspended => {
+                    self.start().await?;
+                    self.wait_for_state(MachineState::Ready).await?;
+                    break;
+                }
+                state => bail!("Machine can't be started from state: {:?}", state),
+            }
+        }
+
+        self.send_flash_lock().await?;
+        *self.last_traffic_time.write().await = Some(Instant::now());
+
+        Ok(MachineAwakeGuard {
+            machine: self.clone(),
+        })
     }
 
     // Connection management is now handled by state machine
@@ -741,6 +995,19 @@ impl Machine {
         }
     }
 
+    /// Time since the machine last got traffic through the proxy, or since it last became
+    /// ready if that's more recent.
+    pub async fn get_idle_duration(&self) -> Option<Duration> {
+        let last_ready_time = *self.last_ready_time.read().await;
+        let last_traffic_time = *self.last_traffic_time.read().await;
+
+        last_ready_time
+            .into_iter()
+            .chain(last_traffic_time)
+            .max()
+            .map(|since| since.elapsed())
+    }
+
     pub async fn get_first_boot_duration(&self) -> Option<Duration> {
         let first_boot_duration = self.first_boot_duration.read().await;
         first_boot_duration.clone()
@@ -750,6 +1017,168 @@ impl Machine {
         self.last_exit_code.read().await.clone()
     }
 
+    pub async fn get_last_core_dump(&self) -> Option<String> {
+        self.last_core_dump.read().await.clone()
+    }
+
+    /// Exposes the new size of a volume to the guest, which then grows its filesystem.
+    /// Returns false when the volume is not attached to this machine.
+    pub fn notify_volume_resized(&self, volume_id: &str) -> Result<bool> {
+        let mut attached = false;
+        for block in self.devices.blocks.iter() {
+            let mut block = block.lock().unwrap();
+            if block.volume_id() != volume_id {
+                continue;
+            }
+
+            block.resize()?;
+            attached = true;
+        }
+
+        if attached {
+            self.devices
+                .guest_manager
+                .lock()
+                .unwrap()
+                .notify_volumes_resized();
+        }
+
+        Ok(attached)
+    }
+
+    /// Stops (or resumes) routing new connections to the machine, eg. before a scale-down
+    /// removes it. Open connections are left alone.
+    pub fn set_draining(&self, draining: bool) {
+        self.draining.store(draining, Ordering::Relaxed);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Suspends the machine on behalf of the user, it isn't woken up by traffic until
+    /// `user_resume` is called.
+    pub async fn user_suspend(&self) -> Result<()> {
+        self.user_suspended.store(true, Ordering::Relaxed);
+        self.suspend().await
+    }
+
+    pub async fn user_resume(&self) -> Result<()> {
+        self.user_suspended.store(false, Ordering::Relaxed);
+        self.start().await
+    }
+
+    pub fn is_user_suspended(&self) -> bool {
+        self.user_suspended.load(Ordering::Relaxed)
+    }
+
+    /// Attaches to the serial console: the offset of the recent output, to read it from with
+    /// `read_console`, and a receiver notified as the machine prints more.
+    pub fn attach_console(&self) -> (u64, watch::Receiver<u64>) {
+        self.devices.serial.lock().unwrap().attach()
+    }
+
+    pub fn read_console(&self, offset: u64) -> ConsoleOutput {
+        self.devices.serial.lock().unwrap().read_output(offset)
+    }
+
+    /// Types `input` into the serial console, waiting for the guest to drain the receive FIFO
+    /// when it's full.
+    pub async fn write_console(&self, mut input: &[u8]) -> Result<()> {
+        let mut stalled = 0;
+        while !input.is_empty() {
+            let written = self.devices.serial.lock().unwrap().enqueue_input(input)?;
+            input = &input[written..];
+
+            if written == 0 {
+                stalled += 1;
+                if stalled > CONSOLE_INPUT_MAX_STALLS {
+                    bail!("the machine isn't reading its console");
+                }
+                tokio::time::sleep(CONSOLE_INPUT_RETRY_INTERVAL).await;
+            } else {
+                stalled = 0;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Connections the proxy currently has open to the machine.
+    pub fn open_connections(&self) -> u64 {
+        self.open_connections.load(Ordering::Relaxed)
+    }
+
+    /// Last usage reported by the guest for each mounted volume, the root filesystem aside.
+    pub fn volume_usage(&self) -> Vec<(&VolumeMountConfig, VolumeUsageReport)> {
+        let reports = self.devices.guest_manager.lock().unwrap().volume_usage();
+
+        reports
+            .into_iter()
+            .filter_map(|report| {
+                let mount = self.config.volume_mounts.get(report.mount_index as usize)?;
+                if mount.root {
+                    return None;
+                }
+
+                Some((mount, report))
+            })
+            .collect()
+    }
+
+    /// Reads the resource counters of the machine, memory is what's resident of the guest memory.
+    pub fn usage_counters(&self) -> MachineUsageCounters {
+        let tap_counter = |name: &str| {
+            std::fs::read_to_string(format!(
+                "/sys/class/net/{}/statistics/{}",
+                self.config.network.tap_device, name
+            ))
+            .ok()
+            .and_then(|value| value.trim().parse::<u64>().ok())
+            .unwrap_or_default()
+        };
+
+        let mut counters = MachineUsageCounters {
+            cpu_time: self.vcpu_cpu_time.total(),
+            memory_resident_bytes: self.resident_memory_bytes(),
+            network_rx_bytes: tap_counter("tx_bytes"),
+            network_tx_bytes: tap_counter("rx_bytes"),
+            ..Default::default()
+        };
+
+        for block in self.devices.blocks.iter() {
+            let io = block.lock().unwrap().io();
+            counters.disk_read_bytes += io.read_bytes.load(Ordering::Relaxed);
+            counters.disk_written_bytes += io.written_bytes.load(Ordering::Relaxed);
+        }
+
+        counters
+    }
+
+    fn resident_memory_bytes(&self) -> u64 {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+
+        self.guest_memory
+            .iter()
+            .map(|region| {
+                let len = region.len() as usize;
+                let mut pages = vec![0u8; len.div_ceil(page_size)];
+                let result = unsafe {
+                    libc::mincore(
+                        region.as_ptr() as *mut libc::c_void,
+                        len,
+                        pages.as_mut_ptr(),
+                    )
+                };
+                if result != 0 {
+                    return 0;
+                }
+
+                pages.iter().filter(|page| *page & 1 == 1).count() as u64 * page_size as u64
+            })
+            .sum()
+    }
+
     pub async fn start(&self) -> Result<()> {
         let (tx, rx) = oneshot::channel();
         self.send_command(StateCommand::UserStart { reply: tx })
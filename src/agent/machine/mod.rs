@@ -1,3 +1,5 @@
+pub mod cgroup;
+pub mod core_dump;
 pub mod machine;
 pub mod state_machine;
 pub mod vm;
@@ -5,12 +7,22 @@ pub mod vm;
 use anyhow::{Result, bail};
 use papaya::HashMap;
 use std::{
+    collections::BTreeMap,
     path::{Path, PathBuf},
     sync::{Arc, Weak},
 };
+use takeoff_proto::proto::VolumeUsageReport;
 
 use crate::{
-    agent::machine::machine::{Machine, MachineConfig, MachineRef},
+    agent::{
+        machine::{
+            cgroup::init_cgroup_root,
+            core_dump::{CoreDumpInfo, core_dump_path, list_core_dumps},
+            machine::{Machine, MachineConfig, MachineRef},
+        },
+        proxy::metrics::{render_header, render_value},
+    },
+    constants::DEFAULT_NAMESPACE,
     controller::scheduler::Scheduler,
 };
 
@@ -20,6 +32,17 @@ pub struct MachineAgentConfig {
     pub initrd_path: String,
     pub kernel_cmd_init: String,
     pub transient_state_path: PathBuf,
+    pub core_dumps_path: PathBuf,
+    /// cgroup v2 directory the CPU limits of the machines are enforced in, not enforced without.
+    pub cgroup_root: Option<PathBuf>,
+    pub node: NodeInfo,
+}
+
+/// A host machines can be placed on, with the labels placement constraints are matched against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeInfo {
+    pub name: String,
+    pub labels: BTreeMap<String, String>,
 }
 
 pub struct MachineAgent {
@@ -34,6 +57,10 @@ impl MachineAgent {
             tokio::fs::create_dir_all(&config.transient_state_path).await?;
         }
 
+        if let Some(cgroup_root) = &config.cgroup_root {
+            init_cgroup_root(cgroup_root)?;
+        }
+
         Ok(Self {
             config,
             scheduler,
@@ -41,11 +68,35 @@ impl MachineAgent {
         })
     }
 
+    /// Nodes machines can be placed on, only the local one for now.
+    pub fn nodes(&self) -> Vec<NodeInfo> {
+        vec![self.config.node.clone()]
+    }
+
     pub fn transient_dir(&self, rel: impl AsRef<Path>) -> String {
         let path = self.config.transient_state_path.clone().join(rel);
         path.to_string_lossy().to_string()
     }
 
+    /// Cores collected from the guests of a machine, they outlive the machine process until the
+    /// machine resource is deleted.
+    pub fn list_core_dumps(&self, machine_name: &str) -> Result<Vec<CoreDumpInfo>> {
+        list_core_dumps(&self.config.core_dumps_path.join(machine_name))
+    }
+
+    pub fn core_dump_path(&self, machine_name: &str, id: &str) -> Result<PathBuf> {
+        core_dump_path(&self.config.core_dumps_path.join(machine_name), id)
+    }
+
+    pub async fn delete_core_dumps(&self, machine_name: &str) -> Result<()> {
+        let dir = self.config.core_dumps_path.join(machine_name);
+        if dir.exists() {
+            tokio::fs::remove_dir_all(dir).await?;
+        }
+
+        Ok(())
+    }
+
     pub fn get_machine(&self, name: &str) -> Option<MachineRef> {
         let machines = self.machines.pin();
         machines.get(name).cloned()
@@ -56,6 +107,72 @@ impl MachineAgent {
         machines.values().cloned().collect()
     }
 
+    /// Last usage of a volume reported by one of the machines mounting it.
+    pub fn volume_usage(&self, volume_id: &str) -> Option<VolumeUsageReport> {
+        self.list_machines().iter().find_map(|machine| {
+            machine
+                .volume_usage()
+                .into_iter()
+                .find(|(mount, _)| mount.volume.id == volume_id)
+                .map(|(_, report)| report)
+        })
+    }
+
+    /// Used and total space of the filesystems of mounted volumes, in the Prometheus text
+    /// format, one series per machine and mount.
+    pub fn render_volume_metrics(&self) -> String {
+        let machines = self.list_machines();
+        let mut usage = Vec::new();
+        for machine in machines.iter() {
+            let key = &machine.config.controller_key;
+            let labels = format!(
+                "tenant=\"{}\",namespace=\"{}\",machine=\"{}\"",
+                key.tenant,
+                key.namespace.as_deref().unwrap_or(DEFAULT_NAMESPACE),
+                key.name
+            );
+
+            for (mount, report) in machine.volume_usage() {
+                usage.push((labels.clone(), mount.mount_at.clone(), report));
+            }
+        }
+
+        let mut out = String::new();
+        render_header(
+            &mut out,
+            "ignition_volume_used_bytes",
+            "gauge",
+            "Space used on the filesystem of a mounted volume, as last reported by the machine",
+        );
+        for (labels, path, report) in usage.iter() {
+            render_value(
+                &mut out,
+                "ignition_volume_used_bytes",
+                labels,
+                Some(("path", path)),
+                report.used_bytes,
+            );
+        }
+
+        render_header(
+            &mut out,
+            "ignition_volume_size_bytes",
+            "gauge",
+            "Total space of the filesystem of a mounted volume",
+        );
+        for (labels, path, report) in usage.iter() {
+            render_value(
+                &mut out,
+                "ignition_volume_size_bytes",
+                labels,
+                Some(("path", path)),
+                report.size_bytes,
+            );
+        }
+
+        out
+    }
+
     pub async fn create_machine(&self, config: MachineConfig) -> Result<MachineRef> {
         let machine = Machine::new(&self.config, config, self.scheduler.clone()).await?;
 
@@ -78,7 +195,21 @@ impl MachineAgent {
 
         machines
             .values()
-            .find(|m| m.config.network_tag == network_tag)
+            .find(|m| m.config.network_tag == network_tag && !m.is_draining())
             .cloned()
     }
+
+    pub async fn get_machines_by_network_tag(&self, network_tag: &str) -> Vec<MachineRef> {
+        let machines = self.machines.pin();
+
+        let mut machines = machines
+            .values()
+            .filter(|m| m.config.network_tag == network_tag && !m.is_draining())
+            .cloned()
+            .collect::<Vec<_>>();
+
+        // stable order so round-robin rotation is meaningful between calls
+        machines.sort_by(|a, b| a.config.name.cmp(&b.config.name));
+        machines
+    }
 }
@@ -537,6 +537,11 @@ impl MachineStateMachine {
 
     async fn handle_stop_requested(&mut self) -> Result<()> {
         match self.resources.config.mode {
+            // prewarmed clones go through the snapshot point and are resumed right away
+            MachineMode::Flash { .. } if self.resources.config.prewarm => {
+                self.handle_user_suspend().await?;
+                self.handle_user_start().await
+            }
             MachineMode::Flash { .. } => self.handle_user_suspend().await,
             MachineMode::Regular => self.handle_user_stop().await,
         }
@@ -628,6 +633,11 @@ impl MachineStateMachine {
     }
 
     async fn handle_last_flash_lock_removed(&mut self) -> Result<()> {
+        // prewarmed clones stay resumed while idle
+        if self.resources.config.prewarm {
+            return Ok(());
+        }
+
         if let MachineMode::Flash {
             suspend_timeout, ..
         } = &self.resources.config.mode
@@ -832,6 +842,7 @@ impl MachineStateMachine {
         }
 
         let suspend_timeout = match &self.resources.config.mode {
+            MachineMode::Flash { .. } if self.resources.config.prewarm => return Ok(()),
             MachineMode::Flash { suspend_timeout, .. } => *suspend_timeout,
             MachineMode::Regular => return Ok(()),
         };
@@ -1,6 +1,11 @@
-use std::io::Write;
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufWriter, Write},
+};
 
-use anyhow::Error;
+use anyhow::{Error, Result, anyhow};
+use tokio::sync::watch;
 use tracing::warn;
 use vm_device::{
     MutDevicePio,
@@ -11,8 +16,99 @@ use vm_superio::{
     serial::{NoEvents, SerialEvents},
 };
 
+use crate::agent::machine::vm::devices::legacy::trigger::EventFdTrigger;
+
+/// Output kept for consoles attaching later, so they see how the machine got where it is, and
+/// for attached consoles that fall behind.
+const CONSOLE_HISTORY_BYTES: usize = 64 * 1024;
+
 pub struct SerialWrapper<T: Trigger, EV: SerialEvents, W: Write>(pub Serial<T, EV, W>);
 
+pub type ConsoleSerial = SerialWrapper<EventFdTrigger, NoEvents, SerialOutput>;
+
+/// Where the guest writes the serial port to: the machine log and the attached consoles.
+pub struct SerialOutput {
+    log: BufWriter<File>,
+    history: VecDeque<u8>,
+    /// Bytes written since the machine was created, consoles read from the history by offset.
+    written_tx: watch::Sender<u64>,
+}
+
+/// Console output read from an offset, `dropped` bytes of it were already out of the history.
+#[derive(Debug, Default)]
+pub struct ConsoleOutput {
+    pub data: Vec<u8>,
+    pub dropped: u64,
+}
+
+impl SerialOutput {
+    pub fn new(log: File) -> Self {
+        let (written_tx, _) = watch::channel(0);
+
+        Self {
+            log: BufWriter::new(log),
+            history: VecDeque::with_capacity(CONSOLE_HISTORY_BYTES),
+            written_tx,
+        }
+    }
+
+    fn history_start(&self) -> u64 {
+        *self.written_tx.borrow() - self.history.len() as u64
+    }
+}
+
+impl Write for SerialOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.log.write(buf)?;
+
+        let overflow = (self.history.len() + written).saturating_sub(CONSOLE_HISTORY_BYTES);
+        self.history.drain(..overflow.min(self.history.len()));
+        self.history.extend(&buf[..written]);
+
+        // one byte at a time from the UART, readers pick up whatever piled up once woken
+        self.written_tx
+            .send_modify(|total| *total += written as u64);
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.log.flush()
+    }
+}
+
+impl ConsoleSerial {
+    /// The offset of the recent output and a receiver notified as more is written.
+    pub fn attach(&self) -> (u64, watch::Receiver<u64>) {
+        let output = self.0.writer();
+        (output.history_start(), output.written_tx.subscribe())
+    }
+
+    /// The output written since `offset`, as much of it as the history still holds.
+    pub fn read_output(&self, offset: u64) -> ConsoleOutput {
+        let output = self.0.writer();
+        let start = output.history_start();
+        let dropped = start.saturating_sub(offset);
+        let skip = offset.saturating_sub(start) as usize;
+
+        ConsoleOutput {
+            data: output.history.iter().skip(skip).copied().collect(),
+            dropped,
+        }
+    }
+
+    /// Queues input for the guest, returns how much of it fit in the receive FIFO.
+    pub fn enqueue_input(&mut self, input: &[u8]) -> Result<usize> {
+        if self.0.fifo_capacity() == 0 {
+            return Ok(0);
+        }
+
+        self.0
+            .enqueue_raw_bytes(input)
+            .map_err(|e| anyhow!("failed to queue console input: {:?}", e))
+    }
+}
+
 impl<T: Trigger<E = Error>, W: Write> MutDevicePio for SerialWrapper<T, NoEvents, W> {
     fn pio_read(&mut self, _base: PioAddress, offset: PioAddressOffset, data: &mut [u8]) {
         if data.len() != 1 {
@@ -10,6 +10,7 @@ impl Trigger for EventFdTrigger {
     type E = anyhow::Error;
 
     fn trigger(&self) -> Result<()> {
+        self.0.write(1)?;
         Ok(())
     }
 }
@@ -1,13 +1,17 @@
 use std::{
+    collections::BTreeMap,
     net::Ipv4Addr,
+    path::PathBuf,
     sync::{Arc, Mutex},
     time::Duration,
 };
 
-use tracing::warn;
+use takeoff_proto::proto::VolumeUsageReport;
+use tracing::{info, warn};
 use vm_memory::{Bytes, GuestAddress, GuestMemoryMmap};
 
 use crate::agent::machine::{
+    core_dump::CoreDumpUpload,
     machine::SnapshotStrategy,
     vm::{
         constants::{MMIO_LEN, MMIO_START},
@@ -37,10 +41,16 @@ const CMD_FLASH_UNLOCK: u8 = CMD_OFFSET + 1;
 const READ_OFFSET_LAST_BOOT_TIME: u64 = 0;
 const READ_OFFSET_FIRST_BOOT_TIME: u64 = 8;
 const READ_OFFSET_TAKEOFF_ARGS_LEN: u64 = 16;
+const READ_OFFSET_VOLUMES_RESIZED: u64 = 24;
 
 const WRITE_OFFSET_TRIGGER: u64 = 0;
 const WRITE_OFFSET_CMD: u64 = 8;
 const WRITE_OFFSET_TAKEOFF_ARGS: u64 = 16;
+const WRITE_OFFSET_CORE_DUMP: u64 = 24;
+const WRITE_OFFSET_CORE_DUMP_END: u64 = 32;
+const WRITE_OFFSET_VOLUME_USAGE: u64 = 40;
+
+const CORE_DUMP_MAX_CHUNK_SIZE: usize = 4096;
 
 #[allow(unused)]
 #[derive(Debug, Clone, Copy)]
@@ -144,6 +154,11 @@ pub struct GuestManagerDevice {
     first_boot_duration: Option<Duration>,
     last_boot_duration: Option<Duration>,
     snapshot_strategy: Option<SnapshotStrategy>,
+    core_dump_dir: PathBuf,
+    core_dump_max_size: u64,
+    core_dump_upload: Option<CoreDumpUpload>,
+    volumes_resized: u64,
+    volume_usage: BTreeMap<u8, VolumeUsageReport>,
 }
 
 impl GuestManagerDevice {
@@ -160,11 +175,18 @@ impl GuestManagerDevice {
         takeoff_args: Vec<u8>,
         device_event_tx: async_broadcast::Sender<DeviceEvent>,
         snapshot_strategy: Option<SnapshotStrategy>,
+        core_dump_dir: PathBuf,
+        core_dump_max_size: u64,
     ) -> Arc<Mutex<Self>> {
         let guest_manager = Self {
             memory,
             takeoff_args,
             snapshot_strategy,
+            core_dump_dir,
+            core_dump_max_size,
+            core_dump_upload: None,
+            volumes_resized: 0,
+            volume_usage: BTreeMap::new(),
             listen_trigger_count: 0,
             first_boot_duration: None,
             last_boot_duration: None,
@@ -185,6 +207,16 @@ impl GuestManagerDevice {
         self.snapshot_strategy = snapshot_strategy;
     }
 
+    /// Takeoff polls this counter and grows the filesystems of the volumes when it changes.
+    pub fn notify_volumes_resized(&mut self) {
+        self.volumes_resized += 1;
+    }
+
+    /// Last usage takeoff reported for each mounted volume, by mount index.
+    pub fn volume_usage(&self) -> Vec<VolumeUsageReport> {
+        self.volume_usage.values().copied().collect()
+    }
+
     pub fn mmio_read(&mut self, offset: vm_device::bus::MmioAddressOffset, data: &mut [u8]) {
         if data.len() != 8 {
             warn!("invalid read data length {}", data.len());
@@ -199,6 +231,7 @@ impl GuestManagerDevice {
                 .first_boot_duration
                 .map(|duration: Duration| duration.as_micros() as u64),
             READ_OFFSET_TAKEOFF_ARGS_LEN => self.process_args_read(),
+            READ_OFFSET_VOLUMES_RESIZED => Some(self.volumes_resized),
             _ => {
                 warn!("unhandled read offset {}", offset);
                 return;
@@ -214,6 +247,9 @@ impl GuestManagerDevice {
             WRITE_OFFSET_TRIGGER => self.process_trigger(data),
             WRITE_OFFSET_CMD => self.process_cmd(data),
             WRITE_OFFSET_TAKEOFF_ARGS => self.process_args_write(data),
+            WRITE_OFFSET_CORE_DUMP => self.process_core_dump_chunk(data),
+            WRITE_OFFSET_CORE_DUMP_END => self.process_core_dump_end(),
+            WRITE_OFFSET_VOLUME_USAGE => self.process_volume_usage(data),
             _ => {
                 warn!("unhandled write offset {}", offset);
                 false
@@ -325,6 +361,73 @@ impl GuestManagerDevice {
             );
         }
 
+        return false;
+    }
+    fn process_core_dump_chunk(&mut self, data: &[u8]) -> bool {
+        let Ok(data) = <[u8; 8]>::try_from(data) else {
+            warn!("invalid core dump chunk data length {}", data.len());
+            return false;
+        };
+        let encoded_chunk = u64::from_le_bytes(data);
+
+        // Decode: upper 32 bits = chunk length, lower 32 bits = physical address
+        let len = (encoded_chunk >> 32) as usize;
+        let ptr = encoded_chunk & 0xFFFFFFFF;
+        if len > CORE_DUMP_MAX_CHUNK_SIZE {
+            warn!("Core dump chunk of {} bytes is too large", len);
+            return false;
+        }
+
+        if self.core_dump_upload.is_none() {
+            match CoreDumpUpload::start(&self.core_dump_dir, self.core_dump_max_size) {
+                Ok(upload) => self.core_dump_upload = Some(upload),
+                Err(e) => {
+                    warn!("Failed to start core dump upload: {}", e);
+                    return false;
+                }
+            }
+        }
+
+        let mut chunk = vec![0u8; len];
+        if let Err(e) = self.memory.read_slice(&mut chunk, GuestAddress(ptr)) {
+            warn!("Failed to read core dump chunk from ptr {}: {:?}", ptr, e);
+            return false;
+        }
+
+        if let Some(upload) = &mut self.core_dump_upload {
+            upload.write(&chunk);
+        }
+
+        return false;
+    }
+
+    fn process_core_dump_end(&mut self) -> bool {
+        let Some(upload) = self.core_dump_upload.take() else {
+            return false;
+        };
+
+        match upload.finish() {
+            Ok(id) => {
+                info!("Received core dump {}", id);
+                self.device_event_tx
+                    .try_broadcast(DeviceEvent::CoreDump(id))
+                    .ok();
+            }
+            Err(e) => warn!("Failed to store core dump: {}", e),
+        }
+
+        return false;
+    }
+
+    fn process_volume_usage(&mut self, data: &[u8]) -> bool {
+        let Ok(data) = <[u8; 8]>::try_from(data) else {
+            warn!("invalid volume usage data length {}", data.len());
+            return false;
+        };
+
+        let report = VolumeUsageReport::decode(u64::from_le_bytes(data));
+        self.volume_usage.insert(report.mount_index, report);
+
         return false;
     }
 }
@@ -5,7 +5,7 @@ pub mod virtio;
 
 use std::{
     fs::OpenOptions,
-    io::BufWriter,
+    path::Path,
     sync::{Arc, Mutex},
 };
 
@@ -31,7 +31,10 @@ use crate::agent::machine::{
         cpu_ref::mptable::MpTable,
         devices::{
             alloc::IrqAllocator,
-            legacy::{serial::SerialWrapper, trigger::EventFdTrigger},
+            legacy::{
+                serial::{ConsoleSerial, SerialOutput, SerialWrapper},
+                trigger::EventFdTrigger,
+            },
             meta::guest_manager::GuestManagerDevice,
             virtio::{Env, block::device::Block, mmio::MmioConfig, net::device::Net},
         },
@@ -40,6 +43,7 @@ use crate::agent::machine::{
 
 #[derive(Clone)]
 pub struct VmDevices {
+    pub serial: Arc<Mutex<ConsoleSerial>>,
     pub guest_manager: Arc<Mutex<GuestManagerDevice>>,
     pub net: Arc<Mutex<Net>>,
     pub blocks: Vec<Arc<Mutex<Block>>>,
@@ -52,6 +56,8 @@ pub enum DeviceEvent {
     FlashLock,
     FlashUnlock,
     ExitCode(i32),
+    /// A core was received from the guest and stored under this id.
+    CoreDump(String),
 }
 
 pub async fn setup_devices(
@@ -66,6 +72,7 @@ pub async fn setup_devices(
     event_manager: &mut EventManager<Arc<Mutex<dyn MutEventSubscriber + Send>>>,
     kernel_cmdline: &mut Cmdline,
     log_path: &str,
+    core_dump_dir: &Path,
     device_event_tx: async_broadcast::Sender<DeviceEvent>,
 ) -> Result<VmDevices> {
     setup_memory_regions(kvm, vm_fd.clone(), memory)?;
@@ -73,7 +80,7 @@ pub async fn setup_devices(
     MpTable::new(machine_config.resources.cpu, MAX_IRQ as u8)?.write(memory)?;
 
     setup_irq_controller(vm_fd.clone())?;
-    setup_serial_console(vm_fd.clone(), io_manager, log_path)?;
+    let serial = setup_serial_console(vm_fd.clone(), io_manager, log_path)?;
 
     let snapshot_strategy = match &machine_config.mode {
         MachineMode::Regular => None,
@@ -90,6 +97,12 @@ pub async fn setup_devices(
         takeoff_args_bytes,
         device_event_tx.clone(),
         snapshot_strategy,
+        core_dump_dir.to_path_buf(),
+        takeoff_args
+            .core_dump
+            .as_ref()
+            .map(|core_dump| core_dump.max_size)
+            .unwrap_or(0),
     );
 
     let net = setup_network_device(
@@ -121,6 +134,7 @@ pub async fn setup_devices(
     }
 
     Ok(VmDevices {
+        serial,
         guest_manager,
         net,
         blocks,
@@ -171,7 +185,7 @@ fn setup_serial_console(
     vm_fd: Arc<VmFd>,
     io_manager: &mut IoManager,
     log_path: &str,
-) -> Result<()> {
+) -> Result<Arc<Mutex<ConsoleSerial>>> {
     let irq_fd = EventFdTrigger::new(libc::EFD_NONBLOCK)?;
 
     register_irq_fd(vm_fd, &irq_fd, SERIAL_IRQ)?;
@@ -184,15 +198,13 @@ fn setup_serial_console(
         .append(true)
         .open(log_path)?;
 
-    let log_file = BufWriter::new(log_file);
-
-    let serial = Serial::new(irq_fd.try_clone()?, log_file);
+    let serial = Serial::new(irq_fd.try_clone()?, SerialOutput::new(log_file));
     let serial = SerialWrapper(serial);
     let serial = Arc::new(Mutex::new(serial));
 
     io_manager.register_pio(range, serial.clone())?;
 
-    Ok(())
+    Ok(serial)
 }
 
 fn setup_network_device(
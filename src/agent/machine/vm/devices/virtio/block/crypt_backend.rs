@@ -0,0 +1,164 @@
+use std::io::{Seek, SeekFrom};
+
+use vm_memory::{
+    ReadVolatile, VolatileMemoryError, VolatileSlice, WriteVolatile, bitmap::BitmapSlice,
+};
+use vmm_sys_util::{
+    file_traits::FileSync,
+    write_zeroes::{PunchHole, WriteZeroesAt},
+};
+
+use crate::agent::{
+    machine::vm::devices::virtio::block::overlay_backend::OverlayBackend,
+    volume::encryption::{SECTOR_SIZE, SectorCipher},
+};
+
+/// Decrypts what the guest reads and encrypts what it writes for volumes with a key, passes
+/// everything through to the overlay backend otherwise.
+///
+/// Empty sectors are stored as zeroes (see `SectorCipher`), so punching holes and writing
+/// zeroes go straight to the overlay.
+pub struct CryptBackend {
+    inner: OverlayBackend,
+    cipher: Option<SectorCipher>,
+    pos: u64,
+}
+
+impl CryptBackend {
+    pub fn new(inner: OverlayBackend, cipher: Option<SectorCipher>) -> Self {
+        Self {
+            inner,
+            cipher,
+            pos: 0,
+        }
+    }
+}
+
+fn cipher_error(e: anyhow::Error) -> VolatileMemoryError {
+    VolatileMemoryError::IOError(std::io::Error::other(e))
+}
+
+/// The sectors covering `len` bytes at `pos`, as (first sector, sector count).
+fn sector_span(pos: u64, len: usize) -> (u64, usize) {
+    let first_sector = pos / SECTOR_SIZE as u64;
+    let end_sector = (pos + len as u64).div_ceil(SECTOR_SIZE as u64);
+
+    (first_sector, (end_sector - first_sector) as usize)
+}
+
+/// Reads and decrypts whole sectors, what lies past the end of the volume reads as zeroes.
+fn read_sectors(
+    inner: &mut OverlayBackend,
+    cipher: &mut SectorCipher,
+    first_sector: u64,
+    buf: &mut [u8],
+) -> Result<(), VolatileMemoryError> {
+    inner
+        .seek(SeekFrom::Start(first_sector * SECTOR_SIZE as u64))
+        .map_err(VolatileMemoryError::IOError)?;
+
+    let mut done = 0;
+    while done < buf.len() {
+        let read = inner.read_volatile(&mut VolatileSlice::from(&mut buf[done..]))?;
+        if read == 0 {
+            break;
+        }
+        done += read;
+    }
+
+    cipher.decrypt(first_sector, buf).map_err(cipher_error)
+}
+
+impl Seek for CryptBackend {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        if self.cipher.is_none() {
+            return self.inner.seek(pos);
+        }
+
+        // reading and writing whole sectors moves the inner position, ours is the one the
+        // guest asked for
+        self.pos = match pos {
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset).ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "invalid seek to a negative or overflowing position",
+                )
+            })?,
+            pos => self.inner.seek(pos)?,
+        };
+        Ok(self.pos)
+    }
+}
+
+impl ReadVolatile for CryptBackend {
+    fn read_volatile<B: BitmapSlice>(
+        &mut self,
+        slice: &mut VolatileSlice<B>,
+    ) -> Result<usize, VolatileMemoryError> {
+        let Some(cipher) = &mut self.cipher else {
+            return self.inner.read_volatile(slice);
+        };
+
+        let (first_sector, sectors) = sector_span(self.pos, slice.len());
+        let mut buf = vec![0u8; sectors * SECTOR_SIZE];
+        read_sectors(&mut self.inner, cipher, first_sector, &mut buf)?;
+
+        let offset = (self.pos - first_sector * SECTOR_SIZE as u64) as usize;
+        slice.copy_from(&buf[offset..offset + slice.len()]);
+
+        self.pos += slice.len() as u64;
+        Ok(slice.len())
+    }
+}
+
+impl WriteVolatile for CryptBackend {
+    fn write_volatile<B: BitmapSlice>(
+        &mut self,
+        slice: &VolatileSlice<B>,
+    ) -> Result<usize, VolatileMemoryError> {
+        let Some(cipher) = &mut self.cipher else {
+            return self.inner.write_volatile(slice);
+        };
+
+        let (first_sector, sectors) = sector_span(self.pos, slice.len());
+        let mut buf = vec![0u8; sectors * SECTOR_SIZE];
+        let offset = (self.pos - first_sector * SECTOR_SIZE as u64) as usize;
+
+        // partial sectors keep the rest of their contents
+        if offset != 0 || slice.len() % SECTOR_SIZE != 0 {
+            read_sectors(&mut self.inner, cipher, first_sector, &mut buf)?;
+        }
+
+        slice.copy_to(&mut buf[offset..offset + slice.len()]);
+        cipher
+            .encrypt(first_sector, &mut buf)
+            .map_err(cipher_error)?;
+
+        self.inner
+            .seek(SeekFrom::Start(first_sector * SECTOR_SIZE as u64))
+            .map_err(VolatileMemoryError::IOError)?;
+        self.inner
+            .write_all_volatile(&VolatileSlice::from(buf.as_mut_slice()))?;
+
+        self.pos += slice.len() as u64;
+        Ok(slice.len())
+    }
+}
+
+impl FileSync for CryptBackend {
+    fn fsync(&mut self) -> std::io::Result<()> {
+        self.inner.fsync()
+    }
+}
+
+impl PunchHole for CryptBackend {
+    fn punch_hole(&mut self, off: u64, len: u64) -> std::io::Result<()> {
+        self.inner.punch_hole(off, len)
+    }
+}
+
+impl WriteZeroesAt for CryptBackend {
+    fn write_zeroes_at(&mut self, off: u64, len: usize) -> std::io::Result<usize> {
+        self.inner.write_zeroes_at(off, len)
+    }
+}
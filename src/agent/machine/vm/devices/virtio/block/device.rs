@@ -3,7 +3,7 @@ use std::{
     fs::{File, OpenOptions},
     io::{Seek, SeekFrom},
     path::PathBuf,
-    sync::{Arc, Mutex},
+    sync::{Arc, Mutex, atomic::Ordering},
 };
 
 use anyhow::{Result, anyhow};
@@ -12,17 +12,20 @@ use virtio_device::{VirtioConfig, VirtioDeviceActions, VirtioDeviceType, VirtioM
 use virtio_queue::{Queue, QueueT};
 use vm_device::{MutDeviceMmio, bus::MmioAddress, device_manager::IoManager};
 
-use crate::agent::machine::{
-    machine::VolumeMountConfig,
-    vm::devices::virtio::{
-        Env, SingleFdSignalQueue,
-        block::overlay_backend::OverlayBackend,
-        features::{VIRTIO_F_IN_ORDER, VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_VERSION_1},
-        mmio::VirtioMmioDeviceConfig,
+use crate::agent::{
+    machine::{
+        machine::VolumeMountConfig,
+        vm::devices::virtio::{
+            Env, SingleFdSignalQueue, VIRTIO_MMIO_INT_CONFIG,
+            block::{crypt_backend::CryptBackend, overlay_backend::OverlayBackend},
+            features::{VIRTIO_F_IN_ORDER, VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_VERSION_1},
+            mmio::VirtioMmioDeviceConfig,
+        },
     },
+    volume::encryption::SectorCipher,
 };
 
-use super::handler::{BlockHandler, QueueHandler};
+use super::handler::{BlockHandler, BlockIoCounters, QueueHandler};
 
 pub const BLOCK_DEVICE_ID: u32 = 2;
 
@@ -62,6 +65,7 @@ pub struct Block {
     device: VirtioMmioDeviceConfig,
     config: VolumeMountConfig,
     handler: Option<Arc<Mutex<QueueHandler>>>,
+    io: Arc<BlockIoCounters>,
 }
 
 impl Block {
@@ -90,6 +94,7 @@ impl Block {
             device,
             config,
             handler: None,
+            io: Arc::new(BlockIoCounters::default()),
         };
         let block = Arc::new(Mutex::new(block));
 
@@ -97,29 +102,39 @@ impl Block {
 
         Ok(block)
     }
-}
 
-impl VirtioDeviceType for Block {
-    fn device_type(&self) -> u32 {
-        BLOCK_DEVICE_ID
+    pub fn volume_id(&self) -> &str {
+        &self.config.volume.id
     }
-}
 
-impl Borrow<VirtioConfig<Queue>> for Block {
-    fn borrow(&self) -> &VirtioConfig<Queue> {
-        &self.device.virtio
-    }
-}
-impl BorrowMut<VirtioConfig<Queue>> for Block {
-    fn borrow_mut(&mut self) -> &mut VirtioConfig<Queue> {
-        &mut self.device.virtio
+    pub fn io(&self) -> Arc<BlockIoCounters> {
+        self.io.clone()
     }
-}
 
-impl VirtioDeviceActions for Block {
-    type E = anyhow::Error;
+    /// Picks up the new size of the volume file and tells the driver its capacity changed.
+    pub fn resize(&mut self) -> Result<()> {
+        let cfg = VirtioBlockConfig::new(&self.config.volume.path.clone().into())?;
+        self.device.virtio.config_space = cfg.as_bytes().to_vec();
+        self.device.virtio.config_generation = self.device.virtio.config_generation.wrapping_add(1);
 
-    fn activate(&mut self) -> Result<()> {
+        let Some(handler) = self.handler.clone() else {
+            // the driver reads the capacity when it activates the device
+            return Ok(());
+        };
+
+        let disk = self.open_disk()?;
+        handler.lock().unwrap().inner.disk = disk;
+
+        self.device
+            .virtio
+            .interrupt_status
+            .fetch_or(VIRTIO_MMIO_INT_CONFIG, Ordering::SeqCst);
+        self.device.irqfd.write(1)?;
+
+        Ok(())
+    }
+
+    fn open_disk(&self) -> Result<StdIoBackend<CryptBackend>> {
         let mut features = self.device.virtio.driver_features;
         if self.config.read_only {
             features |= 1 << VIRTIO_BLK_F_RO;
@@ -143,8 +158,39 @@ impl VirtioDeviceActions for Block {
 
             OverlayBackend::new_readwrite(src_file, ov_file)?
         };
-        let disk =
-            StdIoBackend::new(backend, features).map_err(|_| anyhow!("failed to create disk"))?;
+
+        let cipher = match &self.config.encryption_key {
+            Some(key) => Some(SectorCipher::new(key)?),
+            None => None,
+        };
+        let backend = CryptBackend::new(backend, cipher);
+
+        StdIoBackend::new(backend, features).map_err(|_| anyhow!("failed to create disk"))
+    }
+}
+
+impl VirtioDeviceType for Block {
+    fn device_type(&self) -> u32 {
+        BLOCK_DEVICE_ID
+    }
+}
+
+impl Borrow<VirtioConfig<Queue>> for Block {
+    fn borrow(&self) -> &VirtioConfig<Queue> {
+        &self.device.virtio
+    }
+}
+impl BorrowMut<VirtioConfig<Queue>> for Block {
+    fn borrow_mut(&mut self) -> &mut VirtioConfig<Queue> {
+        &mut self.device.virtio
+    }
+}
+
+impl VirtioDeviceActions for Block {
+    type E = anyhow::Error;
+
+    fn activate(&mut self) -> Result<()> {
+        let disk = self.open_disk()?;
 
         let driver_notify = SingleFdSignalQueue {
             irqfd: self.device.irqfd.clone(),
@@ -158,6 +204,7 @@ impl VirtioDeviceActions for Block {
             queue: self.device.virtio.queues.remove(0),
             memory: self.device.memory.clone(),
             disk,
+            io: self.io.clone(),
         };
 
         let handler = Arc::new(Mutex::new(QueueHandler {
@@ -1,22 +1,55 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
+};
+
 use anyhow::{Result, anyhow};
 use event_manager::{EventOps, EventSet, Events, MutEventSubscriber};
 use tracing::warn;
-use virtio_blk::{request::Request, stdio_executor::StdIoBackend};
+use virtio_blk::{
+    request::{Request, RequestType},
+    stdio_executor::StdIoBackend,
+};
 use virtio_queue::{Queue, QueueOwnedT, QueueState, QueueT};
 use vm_memory::GuestMemoryMmap;
 use vmm_sys_util::eventfd::EventFd;
 
 use crate::agent::machine::vm::devices::virtio::{
-    SignalUsedQueue, SingleFdSignalQueue, block::overlay_backend::OverlayBackend,
+    SignalUsedQueue, SingleFdSignalQueue, block::crypt_backend::CryptBackend,
 };
 
 const IOEVENT_DATA: u32 = 0;
 
+/// Bytes the driver read from and wrote to the device since it was created.
+#[derive(Debug, Default)]
+pub struct BlockIoCounters {
+    pub read_bytes: AtomicU64,
+    pub written_bytes: AtomicU64,
+}
+
+impl BlockIoCounters {
+    fn record(&self, request: &Request) {
+        let counter = match request.request_type() {
+            RequestType::In => &self.read_bytes,
+            RequestType::Out => &self.written_bytes,
+            _ => return,
+        };
+
+        let bytes = request
+            .data()
+            .iter()
+            .map(|(_, len)| *len as u64)
+            .sum::<u64>();
+        counter.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+
 pub struct BlockHandler<S: SignalUsedQueue> {
     pub driver_notify: S,
     pub queue: Queue,
     pub memory: GuestMemoryMmap,
-    pub disk: StdIoBackend<OverlayBackend>,
+    pub disk: StdIoBackend<CryptBackend>,
+    pub io: Arc<BlockIoCounters>,
 }
 
 impl<S: SignalUsedQueue> BlockHandler<S> {
@@ -26,10 +59,14 @@ impl<S: SignalUsedQueue> BlockHandler<S> {
 
             while let Some(mut chain) = self.queue.iter(&self.memory)?.next() {
                 let used_len = match Request::parse(&mut chain) {
-                    Ok(request) => self
-                        .disk
-                        .process_request(&self.memory, &request)
-                        .map_err(|_| anyhow!("block: failed to process request"))?,
+                    Ok(request) => {
+                        let used_len = self
+                            .disk
+                            .process_request(&self.memory, &request)
+                            .map_err(|_| anyhow!("block: failed to process request"))?;
+                        self.io.record(&request);
+                        used_len
+                    }
                     Err(e) => {
                         warn!("block: failed to parse request: {}", e);
                         0
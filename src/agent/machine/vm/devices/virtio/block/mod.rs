@@ -1,3 +1,4 @@
+pub mod crypt_backend;
 pub mod device;
 pub mod handler;
 pub mod overlay_backend;
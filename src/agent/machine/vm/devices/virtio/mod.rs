@@ -37,6 +37,7 @@ pub mod features {
 }
 
 const VIRTIO_MMIO_INT_VRING: u8 = 0x01;
+pub const VIRTIO_MMIO_INT_CONFIG: u8 = 0x02;
 pub const VIRTIO_MMIO_QUEUE_NOTIFY_OFFSET: u64 = 0x50;
 
 pub struct Env<'a> {
@@ -92,6 +92,84 @@ pub fn create_cmdline(_machine_config: &MachineConfig) -> Result<Cmdline> {
     Ok(cmdline)
 }
 
+/// Kernel parameters a machine may set without a value.
+const KERNEL_FLAG_PARAMS: &[&str] = &["quiet", "debug", "nosmt", "nopti", "nokaslr"];
+
+/// Kernel parameters a machine may set, with the values they accept.
+const KERNEL_VALUE_PARAMS: &[(&str, &[&str])] = &[
+    ("loglevel", &["0", "1", "2", "3", "4", "5", "6", "7"]),
+    ("mitigations", &["off", "auto", "auto,nosmt"]),
+    ("transparent_hugepage", &["always", "madvise", "never"]),
+    ("init_on_alloc", &["0", "1"]),
+    ("init_on_free", &["0", "1"]),
+    ("random.trust_cpu", &["on", "off"]),
+];
+
+/// Modules configured by the hypervisor, their options can't be overridden.
+const RESERVED_KERNEL_MODULES: &[&str] = &["virtio_mmio"];
+
+/// Prefixes that look like module options but aren't, `sysctl.` sets any sysctl at boot.
+const DENIED_KERNEL_PARAM_PREFIXES: &[&str] = &["sysctl"];
+
+/// Checks a kernel parameter of a machine against the allowlist: a few well known parameters
+/// and `<module>.<option>[=value]` module options.
+pub fn validate_kernel_param(param: &str) -> Result<()> {
+    let (key, value) = match param.split_once('=') {
+        Some((key, value)) => (key, Some(value)),
+        None => (param, None),
+    };
+
+    if let Some((_, values)) = KERNEL_VALUE_PARAMS.iter().find(|(name, _)| *name == key) {
+        if value.is_some_and(|value| values.contains(&value)) {
+            return Ok(());
+        }
+        bail!(
+            "Kernel parameter {} must be set to one of: {}",
+            key,
+            values.join(", ")
+        );
+    }
+
+    if KERNEL_FLAG_PARAMS.contains(&key) {
+        if value.is_none() {
+            return Ok(());
+        }
+        bail!("Kernel parameter {} doesn't take a value", key);
+    }
+
+    let Some((module, option)) = key.split_once('.') else {
+        bail!("Kernel parameter {} is not allowed", key);
+    };
+    if DENIED_KERNEL_PARAM_PREFIXES.contains(&module) {
+        bail!("Kernel parameter {} is not allowed", key);
+    }
+
+    let valid_module = !module.is_empty()
+        && module
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    let valid_option = !option.is_empty()
+        && option
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.');
+    // quotes and spaces would let a value spill into other parameters
+    let valid_value = value.is_none_or(|value| {
+        !value.is_empty()
+            && value
+                .chars()
+                .all(|c| c.is_ascii_graphic() && c != '"' && c != '\'')
+    });
+    if !valid_module || !valid_option || !valid_value {
+        bail!("Invalid kernel parameter {}", param);
+    }
+
+    if RESERVED_KERNEL_MODULES.contains(&module) {
+        bail!("Options of the {} module are set by the hypervisor", module);
+    }
+
+    Ok(())
+}
+
 fn load_initrd(
     initrd_path: impl AsRef<Path>,
     memory: &GuestMemoryMmap,
@@ -128,3 +206,27 @@ fn load_initrd(
 
     Ok((GuestAddress(aligned_address), size))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_kernel_param() {
+        assert!(validate_kernel_param("quiet").is_ok());
+        assert!(validate_kernel_param("mitigations=off").is_ok());
+        assert!(validate_kernel_param("loglevel=3").is_ok());
+        assert!(validate_kernel_param("nf_conntrack.hashsize=65536").is_ok());
+
+        assert!(validate_kernel_param("quiet=1").is_err());
+        assert!(validate_kernel_param("mitigations").is_err());
+        assert!(validate_kernel_param("loglevel=9").is_err());
+        assert!(validate_kernel_param("init=/bin/sh").is_err());
+        assert!(validate_kernel_param("console=ttyS1").is_err());
+        assert!(validate_kernel_param("virtio_mmio.device=4K@0xd0000000:5").is_err());
+        assert!(validate_kernel_param("sysctl.vm.swappiness=10").is_err());
+        assert!(validate_kernel_param("sysctl.kernel.modules_disabled=0").is_err());
+        assert!(validate_kernel_param("foo.bar=\"a b\"").is_err());
+        assert!(validate_kernel_param("--").is_err());
+    }
+}
@@ -22,6 +22,7 @@ use vm_device::{
 use vm_memory::{Address, Bytes, GuestAddress, GuestMemoryMmap};
 use vmm_sys_util::signal::{Killable, register_signal_handler};
 
+use crate::agent::machine::cgroup::MachineCgroup;
 use crate::agent::machine::vm::{
     constants::{
         BOOT_STACK_POINTER, PDE_START, PDPTE_START, PML4_START, X86_CR0_PE, X86_CR0_PG,
@@ -66,6 +67,65 @@ pub enum VcpuExitReason {
     Suspend,
 }
 
+/// CPU time spent by the vcpu threads of a machine, across its starts.
+#[derive(Debug, Default)]
+pub struct VcpuCpuTime {
+    // thread ids of the running vcpu threads and the CPU time of the ones that exited
+    inner: Mutex<(Vec<libc::pid_t>, Duration)>,
+}
+
+impl VcpuCpuTime {
+    fn enter(self: &Arc<Self>) -> VcpuCpuTimeGuard {
+        let tid = unsafe { libc::gettid() };
+        self.inner.lock().unwrap().0.push(tid);
+
+        VcpuCpuTimeGuard {
+            cpu_time: self.clone(),
+            tid,
+        }
+    }
+
+    pub fn total(&self) -> Duration {
+        let inner = self.inner.lock().unwrap();
+        let (running, exited) = &*inner;
+
+        running
+            .iter()
+            .filter_map(|tid| thread_cpu_time(*tid))
+            .fold(*exited, |total, time| total + time)
+    }
+}
+
+struct VcpuCpuTimeGuard {
+    cpu_time: Arc<VcpuCpuTime>,
+    tid: libc::pid_t,
+}
+
+impl Drop for VcpuCpuTimeGuard {
+    fn drop(&mut self) {
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        let result = unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut ts) };
+        let spent = match result {
+            0 => Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32),
+            _ => Duration::ZERO,
+        };
+
+        let mut inner = self.cpu_time.inner.lock().unwrap();
+        inner.0.retain(|tid| *tid != self.tid);
+        inner.1 += spent;
+    }
+}
+
+/// The first field of the schedstat of a thread is the time it spent on a CPU, in nanoseconds.
+fn thread_cpu_time(tid: libc::pid_t) -> Option<Duration> {
+    let schedstat = std::fs::read_to_string(format!("/proc/self/task/{}/schedstat", tid)).ok()?;
+    let nanos = schedstat.split_whitespace().next()?.parse::<u64>().ok()?;
+    Some(Duration::from_nanos(nanos))
+}
+
 pub struct Vcpu {
     pub count: u8,
     pub index: u8,
@@ -78,6 +138,8 @@ pub struct Vcpu {
     io_manager: Arc<IoManager>,
     vcpu_event_tx: async_broadcast::Sender<VcpuEvent>,
     guest_manager: Arc<Mutex<GuestManagerDevice>>,
+    cgroup: Option<Arc<MachineCgroup>>,
+    cpu_time: Arc<VcpuCpuTime>,
 }
 
 thread_local!(static THIS_VCPU_FD: RefCell<Option<(usize, i32)>> = RefCell::new(None));
@@ -170,6 +232,8 @@ impl Vcpu {
         start_addr: GuestAddress,
         vcpu_count: u8,
         index: u8,
+        cgroup: Option<Arc<MachineCgroup>>,
+        cpu_time: Arc<VcpuCpuTime>,
     ) -> Result<Self> {
         let base_cpuid = kvm.get_supported_cpuid(kvm_bindings::KVM_MAX_CPUID_ENTRIES)?;
         let supported_msrs = cpu_ref::msrs::supported_guest_msrs(kvm)?;
@@ -192,6 +256,8 @@ impl Vcpu {
             barrier,
             vcpu_event_tx,
             guest_manager,
+            cgroup,
+            cpu_time,
         };
 
         vcpu.configure_cpuid()?;
@@ -356,6 +422,14 @@ impl Vcpu {
         Self::setup_signal_handler()?;
         self.setup_thread_local()?;
 
+        // vcpu threads are spawned on every start, each one joins the machine cgroup
+        if let Some(cgroup) = &self.cgroup {
+            if let Err(e) = cgroup.add_current_thread() {
+                warn!("Vcpu {} runs without a CPU limit: {}", self.index, e);
+            }
+        }
+        let _cpu_time = self.cpu_time.enter();
+
         // Clear any lingering immediate_exit flag from previous suspend
         self.vcpu_fd.set_kvm_immediate_exit(0);
 
@@ -0,0 +1,339 @@
+use std::{
+    ops::RangeInclusive,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Result, anyhow};
+use opentelemetry_proto::tonic::{
+    collector::metrics::v1::ExportMetricsServiceRequest,
+    common::v1::{AnyValue, KeyValue, any_value::Value},
+    metrics::v1::{
+        AggregationTemporality, Metric, NumberDataPoint, ResourceMetrics, ScopeMetrics, Sum,
+        metric::Data, number_data_point,
+    },
+    resource::v1::Resource,
+};
+use prost::Message;
+use reqwest::{Client, RequestBuilder, header};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::agent::proxy::metrics::ProxyMetrics;
+
+/// Step of the series returned for a machine, close to the interval machines export at.
+const MACHINE_USAGE_STEP: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+#[serde(tag = "type")]
+pub enum MetricsStoreConfig {
+    /// Prometheus with the OTLP receiver enabled (`--web.enable-otlp-receiver`).
+    #[serde(rename = "prometheus")]
+    Prometheus(PrometheusStoreConfig),
+    #[serde(rename = "mimir")]
+    Mimir(MimirStoreConfig),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct PrometheusStoreConfig {
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct MimirStoreConfig {
+    pub url: String,
+    /// Sent as `X-Scope-OrgID`, for multi-tenant Mimir.
+    pub tenant: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MetricsAgentConfig {
+    pub store: MetricsStoreConfig,
+    /// How often the agent metrics are pushed to the store.
+    pub export_interval: Duration,
+}
+
+/// Resource usage of a machine over a time range, as `(unix seconds, value)` samples.
+#[derive(Debug, Clone)]
+pub struct MachineUsage {
+    pub cpu_utilization: Vec<(u64, f64)>,
+    pub memory_used_bytes: Vec<(u64, f64)>,
+    pub memory_total_bytes: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PrometheusResponse {
+    data: PrometheusData,
+}
+
+#[derive(Debug, Deserialize)]
+struct PrometheusData {
+    result: Vec<PrometheusSeries>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PrometheusSeries {
+    values: Vec<(f64, String)>,
+}
+
+/// Writes metrics to the metrics store over OTLP and reads them back with PromQL.
+pub struct MetricsAgent {
+    config: MetricsAgentConfig,
+    client: Client,
+}
+
+impl MetricsAgent {
+    pub fn new(config: MetricsAgentConfig) -> Self {
+        Self {
+            config,
+            client: Client::new(),
+        }
+    }
+
+    fn otlp_url(&self) -> String {
+        match &self.config.store {
+            MetricsStoreConfig::Prometheus(store) => {
+                format!("{}/api/v1/otlp/v1/metrics", store.url)
+            }
+            MetricsStoreConfig::Mimir(store) => format!("{}/otlp/v1/metrics", store.url),
+        }
+    }
+
+    fn query_range_url(&self) -> String {
+        match &self.config.store {
+            MetricsStoreConfig::Prometheus(store) => format!("{}/api/v1/query_range", store.url),
+            MetricsStoreConfig::Mimir(store) => {
+                format!("{}/prometheus/api/v1/query_range", store.url)
+            }
+        }
+    }
+
+    fn with_tenant(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.config.store {
+            MetricsStoreConfig::Mimir(MimirStoreConfig {
+                tenant: Some(tenant),
+                ..
+            }) => request.header("X-Scope-OrgID", tenant),
+            _ => request,
+        }
+    }
+
+    /// Pushes an OTLP export to the store.
+    pub async fn push(&self, request: &ExportMetricsServiceRequest) -> Result<()> {
+        let response = self
+            .with_tenant(self.client.post(self.otlp_url()))
+            .header(header::CONTENT_TYPE, "application/x-protobuf")
+            .body(request.encode_to_vec())
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(anyhow!("Request failed with status {}: {}", status, body));
+        }
+
+        Ok(())
+    }
+
+    /// Evaluates a PromQL query returning a single series, over a range of unix seconds.
+    pub async fn query_range(
+        &self,
+        query: &str,
+        range: RangeInclusive<u64>,
+        step: Duration,
+    ) -> Result<Vec<(u64, f64)>> {
+        let response = self
+            .with_tenant(self.client.get(self.query_range_url()))
+            .query(&[
+                ("query", query.to_string()),
+                ("start", range.start().to_string()),
+                ("end", range.end().to_string()),
+                ("step", format!("{}s", step.as_secs().max(1))),
+            ])
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(anyhow!("Request failed with status {}: {}", status, body));
+        }
+
+        let response: PrometheusResponse = response.json().await?;
+        let Some(series) = response.data.result.into_iter().next() else {
+            return Ok(vec![]);
+        };
+
+        Ok(series
+            .values
+            .into_iter()
+            .filter_map(|(timestamp, value)| Some((timestamp as u64, value.parse().ok()?)))
+            .collect())
+    }
+
+    /// Resource usage exported by a machine over a range of unix seconds.
+    pub async fn machine_usage(
+        &self,
+        tenant: &str,
+        namespace: &str,
+        name: &str,
+        range: RangeInclusive<u64>,
+    ) -> Result<MachineUsage> {
+        let selector = machine_selector(tenant, namespace, name);
+        let query = |metric: &str| format!("max({}{})", metric, selector);
+
+        let cpu_utilization = self
+            .query_range(
+                &query("machine_cpu_utilization"),
+                range.clone(),
+                MACHINE_USAGE_STEP,
+            )
+            .await?;
+        let memory_used_bytes = self
+            .query_range(
+                &query("machine_memory_used"),
+                range.clone(),
+                MACHINE_USAGE_STEP,
+            )
+            .await?;
+        let memory_total_bytes = self
+            .query_range(&query("machine_memory_total"), range, MACHINE_USAGE_STEP)
+            .await?
+            .last()
+            .map(|(_, value)| *value);
+
+        Ok(MachineUsage {
+            cpu_utilization,
+            memory_used_bytes,
+            memory_total_bytes,
+        })
+    }
+
+    /// Periodically pushes the request counts of the proxy bindings.
+    pub fn start_exporter(self: &Arc<Self>, proxy_metrics: Arc<ProxyMetrics>) {
+        let agent = self.clone();
+        tokio::spawn(async move {
+            let start_time_ns = unix_nanos();
+            let mut interval = tokio::time::interval(agent.config.export_interval);
+            loop {
+                interval.tick().await;
+
+                let request = proxy_requests_export(&proxy_metrics, start_time_ns, unix_nanos());
+                if let Err(e) = agent.push(&request).await {
+                    warn!("failed to push agent metrics: {}", e);
+                }
+            }
+        });
+    }
+}
+
+fn unix_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+fn string_attribute(key: &str, value: &str) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: Some(AnyValue {
+            value: Some(Value::StringValue(value.to_string())),
+        }),
+    }
+}
+
+/// Quotes a value as a PromQL string literal.
+fn promql_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Selects the series of a machine, by the attributes the OTLP ingest stamps on its datapoints.
+fn machine_selector(tenant: &str, namespace: &str, name: &str) -> String {
+    format!(
+        "{{service_tenant={}, service_namespace={}, service_name={}}}",
+        promql_string(tenant),
+        promql_string(namespace),
+        promql_string(name)
+    )
+}
+
+/// The cumulative request counts of the proxy bindings, as `ignition.proxy.requests`.
+fn proxy_requests_export(
+    proxy_metrics: &ProxyMetrics,
+    start_time_ns: u64,
+    time_ns: u64,
+) -> ExportMetricsServiceRequest {
+    let data_points = proxy_metrics
+        .request_counts()
+        .into_iter()
+        .map(|(labels, requests)| NumberDataPoint {
+            attributes: labels
+                .iter()
+                .map(|(key, value)| string_attribute(key, value))
+                .collect(),
+            start_time_unix_nano: start_time_ns,
+            time_unix_nano: time_ns,
+            value: Some(number_data_point::Value::AsInt(requests as i64)),
+            ..Default::default()
+        })
+        .collect();
+
+    ExportMetricsServiceRequest {
+        resource_metrics: vec![ResourceMetrics {
+            resource: Some(Resource {
+                attributes: vec![string_attribute("service.name", "ignitiond")],
+                ..Default::default()
+            }),
+            scope_metrics: vec![ScopeMetrics {
+                metrics: vec![Metric {
+                    name: "ignition.proxy.requests".to_string(),
+                    description: "Requests received by the proxy".to_string(),
+                    data: Some(Data::Sum(Sum {
+                        data_points,
+                        aggregation_temporality: AggregationTemporality::Cumulative as i32,
+                        is_monotonic: true,
+                    })),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        }],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proxy_requests_export() {
+        let proxy_metrics = ProxyMetrics::new();
+        let binding = proxy_metrics.binding("svc", &[("tenant", "acme")]);
+        binding.record_request();
+        binding.record_request();
+
+        let request = proxy_requests_export(&proxy_metrics, 1, 2);
+        let metric = &request.resource_metrics[0].scope_metrics[0].metrics[0];
+        let Some(Data::Sum(sum)) = &metric.data else {
+            panic!("expected a sum");
+        };
+
+        assert!(sum.is_monotonic);
+        assert_eq!(sum.data_points.len(), 1);
+        assert_eq!(
+            sum.data_points[0].value,
+            Some(number_data_point::Value::AsInt(2))
+        );
+        assert_eq!(
+            sum.data_points[0].attributes,
+            vec![
+                string_attribute("binding", "svc"),
+                string_attribute("tenant", "acme")
+            ]
+        );
+    }
+}
@@ -2,10 +2,12 @@ pub mod build;
 pub mod certificate;
 pub mod data;
 pub mod dns;
+pub mod events;
 pub mod image;
 pub mod job;
 pub mod logs;
 pub mod machine;
+pub mod metrics;
 pub mod net;
 pub mod openai;
 pub mod port_allocator;
@@ -22,10 +24,12 @@ use crate::{
         build::{BuildAgent, BuildAgentConfig},
         certificate::{CertificateAgent, config::CertificateAgentConfig},
         dns::{DnsAgent, config::DnsAgentConfig},
+        events::{EventsAgent, EventsAgentConfig},
         image::{ImageAgent, ImageAgentConfig},
         job::JobAgent,
         logs::{LogsAgent, LogsAgentConfig},
         machine::{MachineAgent, MachineAgentConfig},
+        metrics::{MetricsAgent, MetricsAgentConfig},
         net::{NetAgent, NetAgentConfig},
         openai::{OpenAIAgent, OpenAIAgentConfig},
         port_allocator::{PortAllocator, TcpPortRange},
@@ -50,6 +54,8 @@ pub struct AgentConfig {
     pub dns_config: DnsAgentConfig,
     pub cert_config: CertificateAgentConfig,
     pub logs_config: LogsAgentConfig,
+    pub events_config: EventsAgentConfig,
+    pub metrics_config: Option<MetricsAgentConfig>,
     pub openai_config: Option<OpenAIAgentConfig>,
     pub build_config: Option<BuildAgentConfig>,
     pub tcp_port_range: Option<TcpPortRange>,
@@ -65,6 +71,8 @@ pub struct Agent {
     dns: Arc<DnsAgent>,
     certificate: Arc<CertificateAgent>,
     logs: Arc<LogsAgent>,
+    events: Arc<EventsAgent>,
+    metrics: Option<Arc<MetricsAgent>>,
     tracker: Arc<TrackerAgent>,
     port_allocator: Arc<PortAllocator>,
     openai: Option<Arc<OpenAIAgent>>,
@@ -89,26 +97,61 @@ impl Agent {
                 store.clone(),
                 volume.clone(),
                 auth_handler.clone(),
+                scheduler.clone(),
             )
             .await?,
         );
+        image.start_gc();
+
         let machine =
             Arc::new(MachineAgent::new(config.machine_config.clone(), scheduler.clone()).await?);
 
         let certificate = CertificateAgent::new(store.clone(), config.cert_config.clone()).await?;
 
+        let metrics = config
+            .metrics_config
+            .clone()
+            .map(|config| Arc::new(MetricsAgent::new(config)));
+
+        let logs = Arc::new(LogsAgent::new(
+            config.logs_config.clone(),
+            net.clone(),
+            metrics.clone(),
+        ));
+
+        let events = Arc::new(EventsAgent::new(config.events_config.clone(), store.clone()));
+        events.start_gc();
+
+        let tracker = Arc::new(TrackerAgent::new(store.clone()));
+        tracker.start_usage_sampler(machine.clone());
+
+        let mut proxy_config = config.proxy_config.clone();
+        if config.dns_config.split_horizon {
+            // machines resolve external hosts to the service gateway, next to their DNS server
+            proxy_config.internal_bind_address = Some(net.service_gateway().to_string());
+        }
+
         let proxy = ProxyAgent::new(
-            config.proxy_config.clone(),
+            proxy_config,
             machine.clone(),
             certificate.clone(),
+            logs.clone(),
+            tracker.clone(),
         )
         .await?;
 
-        let dns = DnsAgent::new(config.dns_config.clone(), net.clone(), repository).await?;
-
-        let logs = Arc::new(LogsAgent::new(config.logs_config.clone()));
+        if let Some(metrics) = &metrics {
+            metrics.start_exporter(proxy.metrics());
+        }
 
-        let tracker = Arc::new(TrackerAgent::new(store.clone()));
+        let dns = DnsAgent::new(
+            config.dns_config.clone(),
+            store.clone(),
+            net.clone(),
+            proxy.clone(),
+            repository,
+        )
+        .await?;
 
         let port_allocator = Arc::new(PortAllocator::new(
             store.clone(),
@@ -117,7 +160,11 @@ impl Agent {
         ));
 
         let build = match config.build_config {
-            Some(config) => Some(Arc::new(BuildAgent::new(config)?)),
+            Some(config) => {
+                let build = Arc::new(BuildAgent::new(config, store.clone())?);
+                build.start_gc();
+                Some(build)
+            }
             None => None,
         };
 
@@ -134,6 +181,8 @@ impl Agent {
             dns,
             certificate,
             logs,
+            events,
+            metrics,
             tracker,
             port_allocator,
             openai: config
@@ -179,6 +228,10 @@ impl Agent {
         self.logs.clone()
     }
 
+    pub fn events(&self) -> Arc<EventsAgent> {
+        self.events.clone()
+    }
+
     pub fn tracker(&self) -> Arc<TrackerAgent> {
         self.tracker.clone()
     }
@@ -195,6 +248,14 @@ impl Agent {
         }
     }
 
+    pub fn metrics(&self) -> Result<Arc<MetricsAgent>> {
+        if let Some(metrics) = &self.metrics {
+            Ok(metrics.clone())
+        } else {
+            bail!("Metrics store not configured")
+        }
+    }
+
     pub fn build(&self) -> Result<Arc<BuildAgent>> {
         if let Some(build) = &self.build {
             Ok(build.clone())
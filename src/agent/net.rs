@@ -1,3 +1,4 @@
+pub mod bandwidth;
 pub mod device;
 pub mod ip_range;
 
@@ -10,6 +11,7 @@ use crate::{
     agent::{
         data::Collections,
         net::{
+            bandwidth::tc_set_bandwidth,
             device::{
                 device_create, nl_device_delete, nl_device_exists, nl_device_list_with_prefix,
             },
@@ -145,6 +147,16 @@ impl NetAgent {
         self.device_unchecked(name)
     }
 
+    /// Limits the bits per second a device's machine receives and sends, `None` lifts a limit.
+    pub async fn device_set_bandwidth(
+        &self,
+        name: &str,
+        ingress_bits: Option<u64>,
+        egress_bits: Option<u64>,
+    ) -> Result<()> {
+        tc_set_bandwidth(name, ingress_bits, egress_bits).await
+    }
+
     pub async fn device_list(&self) -> Result<Vec<NetDevice>> {
         let devices = nl_device_list_with_prefix(NET_DEVICE_PREFIX).await?;
 
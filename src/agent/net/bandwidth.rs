@@ -0,0 +1,112 @@
+use anyhow::{Result, anyhow, bail};
+use tokio::process::Command;
+
+/// Smallest burst the egress policer allows, a few full-size frames.
+const MIN_POLICE_BURST_BYTES: u64 = 16 * 1024;
+
+/// Parses a rate in bits per second, eg. `500kbit`, `100mbit` or `1gbit`.
+pub fn parse_rate(rate: &str) -> Result<u64> {
+    let rate = rate.trim();
+    let split = rate
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rate.len());
+    let (number, unit) = rate.split_at(split);
+
+    let number = number
+        .parse::<u64>()
+        .map_err(|_| anyhow!("invalid rate {}, expected eg. 100mbit", rate))?;
+
+    let multiplier = match unit.trim().to_lowercase().as_str() {
+        "bit" => 1,
+        "kbit" => 1_000,
+        "mbit" => 1_000_000,
+        "gbit" => 1_000_000_000,
+        _ => bail!(
+            "invalid rate {}, the unit must be one of bit, kbit, mbit or gbit",
+            rate
+        ),
+    };
+
+    let bits = number
+        .checked_mul(multiplier)
+        .ok_or(anyhow!("rate {} is too large", rate))?;
+    if bits == 0 {
+        bail!("rate must be greater than 0");
+    }
+
+    Ok(bits)
+}
+
+async fn tc(args: &[&str]) -> Result<()> {
+    let output = Command::new("tc").args(args).output().await?;
+
+    if !output.status.success() {
+        bail!(
+            "tc {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(())
+}
+
+/// Limits the traffic of a tap device, in bits per second. The tap's egress is the machine's
+/// ingress, it is shaped with HTB. The machine's egress arrives on the tap's ingress, where
+/// it can only be policed.
+pub async fn tc_set_bandwidth(
+    device: &str,
+    ingress_bits: Option<u64>,
+    egress_bits: Option<u64>,
+) -> Result<()> {
+    // taps are reused across machine restarts, start from a clean slate; deleting qdiscs
+    // that aren't there fails
+    let _ = tc(&["qdisc", "del", "dev", device, "root"]).await;
+    let _ = tc(&["qdisc", "del", "dev", device, "ingress"]).await;
+
+    if let Some(bits) = ingress_bits {
+        let rate = format!("{}bit", bits);
+        tc(&[
+            "qdisc", "add", "dev", device, "root", "handle", "1:", "htb", "default", "10",
+        ])
+        .await?;
+        tc(&[
+            "class", "add", "dev", device, "parent", "1:", "classid", "1:10", "htb", "rate", &rate,
+            "ceil", &rate,
+        ])
+        .await?;
+    }
+
+    if let Some(bits) = egress_bits {
+        let rate = format!("{}bit", bits);
+        // a tenth of a second worth of traffic
+        let burst = (bits / 8 / 10).max(MIN_POLICE_BURST_BYTES).to_string();
+        tc(&["qdisc", "add", "dev", device, "handle", "ffff:", "ingress"]).await?;
+        tc(&[
+            "filter", "add", "dev", device, "parent", "ffff:", "protocol", "all", "prio", "1",
+            "u32", "match", "u32", "0", "0", "police", "rate", &rate, "burst", &burst, "drop",
+        ])
+        .await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!(parse_rate("500kbit").unwrap(), 500_000);
+        assert_eq!(parse_rate("100mbit").unwrap(), 100_000_000);
+        assert_eq!(parse_rate(" 1 Gbit ").unwrap(), 1_000_000_000);
+        assert_eq!(parse_rate("64bit").unwrap(), 64);
+
+        assert!(parse_rate("100").is_err());
+        assert!(parse_rate("100mb").is_err());
+        assert!(parse_rate("mbit").is_err());
+        assert!(parse_rate("0mbit").is_err());
+        assert!(parse_rate("99999999999gbit").is_err());
+    }
+}
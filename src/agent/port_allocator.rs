@@ -1,6 +1,6 @@
 use anyhow::{Result, bail};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::{fmt, sync::Arc};
 use tracing::info;
 
 use crate::{
@@ -42,6 +42,81 @@ impl TcpPortAllocation {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PortConflictReason {
+    Blacklisted,
+    ReservedTcpRange,
+    InUseOnHost,
+    BoundByResource { owner: Option<String> },
+}
+
+/// Admission error for an external binding whose port can't be served.
+#[derive(Debug, Clone)]
+pub struct PortConflict {
+    pub host: String,
+    pub port: u16,
+    pub reason: PortConflictReason,
+    pub suggested_ports: Vec<u16>,
+    pub suggested_tcp_ports: Vec<u16>,
+}
+
+impl fmt::Display for PortConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match &self.reason {
+            PortConflictReason::Blacklisted => "the port is reserved by the platform".to_string(),
+            PortConflictReason::ReservedTcpRange => {
+                "the port is in the range reserved for tcp services".to_string()
+            }
+            PortConflictReason::InUseOnHost => "the port is already in use on the host".to_string(),
+            PortConflictReason::BoundByResource { owner: Some(owner) } => {
+                format!(
+                    "{}:{} is already bound by service {}",
+                    self.host, self.port, owner
+                )
+            }
+            PortConflictReason::BoundByResource { owner: None } => {
+                format!(
+                    "{}:{} is already bound by another tenant",
+                    self.host, self.port
+                )
+            }
+        };
+
+        write!(f, "Port {} is not available: {}", self.port, reason)?;
+
+        if !self.suggested_ports.is_empty() {
+            let ports = self
+                .suggested_ports
+                .iter()
+                .map(|p| p.to_string())
+                .collect::<Vec<_>>();
+            write!(
+                f,
+                "\nAvailable ports for {}: {}",
+                self.host,
+                ports.join(", ")
+            )?;
+        }
+
+        if !self.suggested_tcp_ports.is_empty() {
+            let ports = self
+                .suggested_tcp_ports
+                .iter()
+                .map(|p| p.to_string())
+                .collect::<Vec<_>>();
+            write!(
+                f,
+                "\nFor a dedicated port use a tcp bind, free ports include: {}",
+                ports.join(", ")
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for PortConflict {}
+
 pub struct PortAllocator {
     store: Arc<Store>,
     tracker: Arc<TrackerAgent>,
@@ -134,6 +209,26 @@ impl PortAllocator {
         self.store.get(key)
     }
 
+    pub fn find_free_tcp_ports(&self, count: usize) -> Result<Vec<u16>> {
+        let Some((start, end)) = &self.tcp_port_range else {
+            return Ok(vec![]);
+        };
+
+        let mut ports = Vec::with_capacity(count);
+        for port in *start..=*end {
+            if ports.len() >= count {
+                break;
+            }
+
+            let key = TcpPortAllocation::key_for_port(port);
+            if self.store.get::<TcpPortAllocation>(key)?.is_none() {
+                ports.push(port);
+            }
+        }
+
+        Ok(ports)
+    }
+
     pub fn is_tcp_port_in_range(&self, port: u16) -> bool {
         if let Some((start, end)) = &self.tcp_port_range {
             port >= *start && port <= *end
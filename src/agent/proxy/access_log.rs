@@ -0,0 +1,127 @@
+use std::{
+    net::SocketAddr,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use http_body_util::combinators::BoxBody;
+use hyper::{Request, Response, body::Body};
+use serde::Serialize;
+
+use crate::agent::{
+    logs::{AccessLogEntry, LogsAgent},
+    proxy::ProxyBinding,
+};
+
+/// One line per proxied request. `duration_ms` is measured until the response headers, since
+/// bodies are streamed through after the handler returns.
+#[derive(Debug, Serialize)]
+pub struct AccessLogRecord {
+    pub host: String,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub duration_ms: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub upstream_machine: Option<String>,
+    pub client_ip: Option<String>,
+}
+
+/// Captured before the request is handed to the upstream, which consumes it.
+pub struct PendingAccessLog {
+    started_at: Instant,
+    host: String,
+    method: String,
+    path: String,
+    bytes_in: u64,
+    client_ip: Option<String>,
+}
+
+impl PendingAccessLog {
+    pub fn new<B: Body>(
+        started_at: Instant,
+        host: &str,
+        req: &Request<B>,
+        client_ip: Option<SocketAddr>,
+    ) -> Self {
+        Self {
+            started_at,
+            host: host.to_string(),
+            method: req.method().to_string(),
+            path: req.uri().path().to_string(),
+            bytes_in: req.body().size_hint().exact().unwrap_or_else(|| {
+                req.headers()
+                    .get("content-length")
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or_default()
+            }),
+            client_ip: client_ip.map(|ip| ip.ip().to_string()),
+        }
+    }
+
+    pub fn finish(
+        self,
+        logs_agent: &LogsAgent,
+        binding: &ProxyBinding,
+        upstream_machine: Option<&str>,
+        response: &Response<BoxBody<Bytes, hyper::Error>>,
+    ) {
+        // response bodies are counted as they're streamed, see `count_sent_bytes`
+        if let Some(metrics) = binding.metrics.as_ref() {
+            metrics.traffic().record_in(self.bytes_in);
+        }
+
+        emit_access_log(
+            logs_agent,
+            binding,
+            AccessLogRecord {
+                host: self.host,
+                method: self.method,
+                path: self.path,
+                status: response.status().as_u16(),
+                duration_ms: self.started_at.elapsed().as_millis() as u64,
+                bytes_in: self.bytes_in,
+                bytes_out: response_body_size(response),
+                upstream_machine: upstream_machine.map(|m| m.to_string()),
+                client_ip: self.client_ip,
+            },
+        );
+    }
+}
+
+pub fn emit_access_log(logs_agent: &LogsAgent, binding: &ProxyBinding, record: AccessLogRecord) {
+    // bindings that don't belong to a service (eg. evergreen ports) aren't logged
+    let Some(service) = binding.service.as_ref() else {
+        return;
+    };
+
+    let Ok(line) = serde_json::to_string(&record) else {
+        return;
+    };
+
+    let timestamp_ns = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default();
+
+    logs_agent.push_access_log(AccessLogEntry {
+        tenant: service.tenant.clone(),
+        namespace: service.namespace.clone(),
+        service: service.name.clone(),
+        timestamp_ns,
+        line,
+    });
+}
+
+fn response_body_size(response: &Response<BoxBody<Bytes, hyper::Error>>) -> u64 {
+    response.body().size_hint().exact().unwrap_or_else(|| {
+        response
+            .headers()
+            .get("content-length")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default()
+    })
+}
@@ -0,0 +1,281 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full, combinators::BoxBody};
+use hyper::{Request, Response, StatusCode, body::Incoming};
+use tracing::warn;
+
+const DEFAULT_BASIC_AUTH_REALM: &str = "restricted";
+const FORWARD_AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Request headers that only make sense on the original connection, not sent to the auth
+/// endpoint.
+const FORWARD_AUTH_SKIPPED_HEADERS: &[&str] = &[
+    "host",
+    "connection",
+    "upgrade",
+    "content-length",
+    "transfer-encoding",
+    "keep-alive",
+    "te",
+    "trailer",
+];
+
+/// Authentication required by a binding before a request is proxied.
+#[derive(Debug)]
+pub enum ProxyAuth {
+    Basic(BasicAuth),
+    Forward(ForwardAuth),
+}
+
+pub enum AuthOutcome {
+    /// The request may go through, with these headers added to it.
+    Allow(HeaderMap),
+    /// The response to send back to the client instead.
+    Deny(Response<BoxBody<Bytes, hyper::Error>>),
+}
+
+impl ProxyAuth {
+    /// Headers set by the authentication, any client sent value for them is dropped.
+    pub fn trusted_headers(&self) -> &[HeaderName] {
+        match self {
+            ProxyAuth::Basic(_) => &[],
+            ProxyAuth::Forward(forward) => &forward.response_headers,
+        }
+    }
+
+    pub async fn authorize(
+        &self,
+        req: &Request<Incoming>,
+        host: &str,
+        scheme: &str,
+        client_ip: Option<String>,
+    ) -> AuthOutcome {
+        match self {
+            ProxyAuth::Basic(basic) => {
+                if basic.check(req.headers()) {
+                    AuthOutcome::Allow(HeaderMap::new())
+                } else {
+                    AuthOutcome::Deny(basic.challenge())
+                }
+            }
+            ProxyAuth::Forward(forward) => {
+                match forward.check(req, host, scheme, client_ip).await {
+                    Ok(outcome) => outcome,
+                    Err(e) => {
+                        warn!("Forward auth request to {} failed: {}", forward.url, e);
+                        AuthOutcome::Deny(text_response(
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "authentication unavailable",
+                        ))
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Static `Authorization: Basic` credentials.
+#[derive(Debug)]
+pub struct BasicAuth {
+    realm: String,
+    credentials: Vec<(String, String)>,
+}
+
+impl BasicAuth {
+    pub fn new(realm: Option<String>, credentials: Vec<(String, String)>) -> Self {
+        Self {
+            realm: realm.unwrap_or(DEFAULT_BASIC_AUTH_REALM.to_string()),
+            credentials,
+        }
+    }
+
+    pub fn check(&self, headers: &HeaderMap) -> bool {
+        let Some((username, password)) = headers
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_basic_authorization)
+        else {
+            return false;
+        };
+
+        // every pair is compared, so the time taken doesn't tell which user exists
+        self.credentials
+            .iter()
+            .fold(false, |matched, (expected_username, expected_password)| {
+                let username_matches = constant_time_eq(username.as_bytes(), expected_username);
+                let password_matches = constant_time_eq(password.as_bytes(), expected_password);
+                matched | (username_matches & password_matches)
+            })
+    }
+
+    fn challenge(&self) -> Response<BoxBody<Bytes, hyper::Error>> {
+        let mut response = text_response(StatusCode::UNAUTHORIZED, "unauthorized");
+        if let Ok(value) =
+            HeaderValue::from_str(&format!("Basic realm=\"{}\"", self.realm.replace('"', "")))
+        {
+            response.headers_mut().insert("www-authenticate", value);
+        }
+
+        response
+    }
+}
+
+/// Asks an external endpoint whether a request may go through: a 2xx answer lets it pass, any
+/// other answer is sent back to the client as is (eg. a redirect to a login page).
+#[derive(Debug)]
+pub struct ForwardAuth {
+    url: String,
+    /// Headers of the auth answer copied onto the proxied request, eg. `x-auth-user`.
+    response_headers: Vec<HeaderName>,
+    client: reqwest::Client,
+}
+
+impl ForwardAuth {
+    pub fn new(url: String, response_headers: Vec<String>) -> Result<Self> {
+        let response_headers = response_headers
+            .iter()
+            .map(|name| HeaderName::from_bytes(name.as_bytes()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let client = reqwest::Client::builder()
+            .timeout(FORWARD_AUTH_TIMEOUT)
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+
+        Ok(Self {
+            url,
+            response_headers,
+            client,
+        })
+    }
+
+    async fn check(
+        &self,
+        req: &Request<Incoming>,
+        host: &str,
+        scheme: &str,
+        client_ip: Option<String>,
+    ) -> Result<AuthOutcome> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in req.headers() {
+            if !FORWARD_AUTH_SKIPPED_HEADERS.contains(&name.as_str()) {
+                headers.append(name.clone(), value.clone());
+            }
+        }
+
+        let uri = req
+            .uri()
+            .path_and_query()
+            .map(|pq| pq.as_str())
+            .unwrap_or("/");
+        headers.insert(
+            "x-forwarded-method",
+            HeaderValue::from_str(req.method().as_str())?,
+        );
+        headers.insert("x-forwarded-proto", HeaderValue::from_str(scheme)?);
+        headers.insert("x-forwarded-host", HeaderValue::from_str(host)?);
+        headers.insert("x-forwarded-uri", HeaderValue::from_str(uri)?);
+        if let Some(client_ip) = client_ip {
+            headers.insert("x-forwarded-for", HeaderValue::from_str(&client_ip)?);
+        }
+
+        let response = self.client.get(&self.url).headers(headers).send().await?;
+
+        if response.status().is_success() {
+            let mut allowed = HeaderMap::new();
+            for name in &self.response_headers {
+                for value in response.headers().get_all(name) {
+                    allowed.append(name.clone(), value.clone());
+                }
+            }
+
+            return Ok(AuthOutcome::Allow(allowed));
+        }
+
+        let status = response.status();
+        let mut headers = response.headers().clone();
+        for name in ["connection", "content-length", "transfer-encoding"] {
+            headers.remove(name);
+        }
+        let body = response.bytes().await?;
+
+        let mut denied = Response::new(Full::new(body).map_err(|never| match never {}).boxed());
+        *denied.status_mut() = status;
+        *denied.headers_mut() = headers;
+
+        Ok(AuthOutcome::Deny(denied))
+    }
+}
+
+fn parse_basic_authorization(value: &str) -> Option<(String, String)> {
+    let (scheme, credentials) = value.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+
+    let decoded = BASE64_STANDARD.decode(credentials.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+
+    Some((username.to_string(), password.to_string()))
+}
+
+fn constant_time_eq(a: &[u8], b: &str) -> bool {
+    let b = b.as_bytes();
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn text_response(status: StatusCode, text: &'static str) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let mut response = Response::new(
+        Full::new(Bytes::from(text))
+            .map_err(|never| match never {})
+            .boxed(),
+    );
+    *response.status_mut() = status;
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn authorization(credentials: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "authorization",
+            HeaderValue::from_str(&format!("Basic {}", BASE64_STANDARD.encode(credentials)))
+                .unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn test_basic_auth_check() {
+        let auth = BasicAuth::new(
+            None,
+            vec![
+                ("admin".to_string(), "s3cret".to_string()),
+                ("ci".to_string(), "token:with:colons".to_string()),
+            ],
+        );
+
+        assert!(auth.check(&authorization("admin:s3cret")));
+        assert!(auth.check(&authorization("ci:token:with:colons")));
+        assert!(!auth.check(&authorization("admin:wrong")));
+        assert!(!auth.check(&authorization("ci:s3cret")));
+        assert!(!auth.check(&HeaderMap::new()));
+
+        let mut bearer = HeaderMap::new();
+        bearer.insert("authorization", HeaderValue::from_static("Bearer abc"));
+        assert!(!auth.check(&bearer));
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, combinators::BoxBody};
+use hyper::{
+    Response,
+    body::{Body, Frame, SizeHint},
+};
+use tracing::warn;
+
+use crate::agent::{
+    machine::machine::TrafficCounters,
+    proxy::ProxyBinding,
+    tracker::{BandwidthUsage, TrackerAgent},
+};
+
+/// How often the bytes counted by the proxy are added to the stored usage of each service.
+pub const BANDWIDTH_FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Response body counting the bytes sent to the client as they're streamed.
+pub struct CountedBody {
+    inner: BoxBody<Bytes, hyper::Error>,
+    traffic: Arc<TrafficCounters>,
+}
+
+impl Body for CountedBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, hyper::Error>>> {
+        let this = self.get_mut();
+        let frame = Pin::new(&mut this.inner).poll_frame(cx);
+
+        if let Poll::Ready(Some(Ok(frame))) = &frame {
+            if let Some(data) = frame.data_ref() {
+                this.traffic.record_out(data.len() as u64);
+            }
+        }
+
+        frame
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Counts the body of `response` as sent by the binding.
+pub fn count_sent_bytes(
+    binding: &ProxyBinding,
+    response: Response<BoxBody<Bytes, hyper::Error>>,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let Some(metrics) = binding.metrics.as_ref() else {
+        return response;
+    };
+
+    let traffic = metrics.traffic();
+    response.map(|inner| CountedBody { inner, traffic }.boxed())
+}
+
+/// Adds the bytes the binding proxied since the last flush to the usage of its service.
+pub async fn flush_bandwidth_usage(tracker: &TrackerAgent, binding: &ProxyBinding) {
+    // bindings that don't belong to a service (eg. evergreen ports) aren't billed
+    let (Some(service), Some(metrics)) = (binding.service.as_ref(), binding.metrics.as_ref())
+    else {
+        return;
+    };
+
+    let traffic = metrics.traffic();
+    let (bytes_in, bytes_out) = traffic.take_unflushed();
+    if bytes_in == 0 && bytes_out == 0 {
+        return;
+    }
+
+    let usage = BandwidthUsage {
+        namespace: service.namespace.clone(),
+        service: service.name.clone(),
+        bytes_in,
+        bytes_out,
+        updated_at: 0,
+    };
+    if let Err(e) = tracker.add_bandwidth_usage(&service.tenant, usage).await {
+        warn!(
+            "Failed to store bandwidth usage of service {}/{}: {}",
+            service.namespace, service.name, e
+        );
+        // counted again on the next flush
+        traffic.restore_unflushed(bytes_in, bytes_out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http_body_util::Full;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_counted_body() {
+        let traffic = Arc::new(TrafficCounters::default());
+        let body = CountedBody {
+            inner: Full::new(Bytes::from_static(b"hello world"))
+                .map_err(|never| match never {})
+                .boxed(),
+            traffic: traffic.clone(),
+        };
+
+        let collected = body.collect().await.unwrap().to_bytes();
+        assert_eq!(collected.len(), 11);
+        assert_eq!(traffic.bytes_out(), 11);
+        assert_eq!(traffic.take_unflushed(), (0, 11));
+        assert_eq!(traffic.take_unflushed(), (0, 0));
+    }
+}
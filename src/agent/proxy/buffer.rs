@@ -0,0 +1,98 @@
+use axum::http::HeaderMap;
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full, Limited, combinators::BoxBody};
+use hyper::{Method, Request, Uri, Version, body::Incoming};
+
+use anyhow::{Result, anyhow};
+
+/// Requests are only buffered up to this size, larger ones are streamed through.
+const MAX_BUFFERED_BODY_SIZE: u64 = 1024 * 1024;
+
+/// A request read in full, so it can be sent more than once (mirrored or replayed).
+#[derive(Debug, Clone)]
+pub struct BufferedRequest {
+    method: Method,
+    uri: Uri,
+    version: Version,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl BufferedRequest {
+    /// Reads the request, failing once the body grows past the buffered size. Bodies without a
+    /// content length are only known to fit once read.
+    pub async fn read(req: Request<Incoming>) -> Result<Self> {
+        let (parts, body) = req.into_parts();
+        let body = Limited::new(body, MAX_BUFFERED_BODY_SIZE as usize)
+            .collect()
+            .await
+            .map_err(|e| anyhow!("Failed to buffer request body: {}", e))?
+            .to_bytes();
+
+        Ok(Self {
+            method: parts.method,
+            uri: parts.uri,
+            version: parts.version,
+            headers: parts.headers,
+            body,
+        })
+    }
+
+    pub fn is_idempotent(&self) -> bool {
+        self.method.is_idempotent()
+    }
+
+    pub fn to_request(&self) -> Request<BoxBody<Bytes, hyper::Error>> {
+        let mut req = Request::new(
+            Full::new(self.body.clone())
+                .map_err(|never| match never {})
+                .boxed(),
+        );
+        *req.method_mut() = self.method.clone();
+        *req.uri_mut() = self.uri.clone();
+        *req.version_mut() = self.version;
+        *req.headers_mut() = self.headers.clone();
+
+        req
+    }
+}
+
+/// Only requests with a small, known size body can be buffered, upgrades and streamed bodies
+/// are left alone.
+pub fn is_bufferable(headers: &HeaderMap) -> bool {
+    if headers.contains_key("upgrade") || headers.contains_key("transfer-encoding") {
+        return false;
+    }
+
+    match headers.get("content-length") {
+        Some(length) => length
+            .to_str()
+            .ok()
+            .and_then(|length| length.parse::<u64>().ok())
+            .is_some_and(|length| length <= MAX_BUFFERED_BODY_SIZE),
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn test_is_bufferable() {
+        let mut headers = HeaderMap::new();
+        assert!(is_bufferable(&headers));
+
+        headers.insert("content-length", HeaderValue::from_static("512"));
+        assert!(is_bufferable(&headers));
+
+        headers.insert("content-length", HeaderValue::from_static("104857600"));
+        assert!(!is_bufferable(&headers));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("transfer-encoding", HeaderValue::from_static("chunked"));
+        assert!(!is_bufferable(&headers));
+    }
+}
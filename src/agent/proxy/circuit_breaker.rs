@@ -0,0 +1,144 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+const DEFAULT_CONSECUTIVE_FAILURES: u32 = 5;
+const DEFAULT_EJECTION_TIME: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CircuitBreakerConfig {
+    /// Failures in a row that eject a machine, defaults to 5.
+    pub consecutive_failures: Option<u32>,
+    /// How long an ejected machine is left out before a probe is let through, defaults to 30s.
+    pub ejection_time: Option<Duration>,
+}
+
+/// Keeps failing machines of a binding out of rotation. Once the ejection time is over a single
+/// request probes the machine (half-open): success puts it back, failure ejects it again.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    machines: Mutex<HashMap<String, CircuitState>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum CircuitState {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen { since: Instant },
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            machines: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> &CircuitBreakerConfig {
+        &self.config
+    }
+
+    fn ejection_time(&self) -> Duration {
+        self.config.ejection_time.unwrap_or(DEFAULT_EJECTION_TIME)
+    }
+
+    /// Whether traffic may be sent to the machine right now. Letting a request through an
+    /// expired ejection makes it the half-open probe, turning away others until it completes
+    /// (or is abandoned for longer than the ejection time).
+    pub fn try_acquire(&self, machine: &str) -> bool {
+        let mut machines = self.machines.lock().expect("circuit breaker poisoned");
+        let Some(state) = machines.get_mut(machine) else {
+            return true;
+        };
+
+        let now = Instant::now();
+        let probe = match state {
+            CircuitState::Closed { .. } => return true,
+            CircuitState::Open { until } => now >= *until,
+            CircuitState::HalfOpen { since } => now.duration_since(*since) >= self.ejection_time(),
+        };
+
+        if probe {
+            *state = CircuitState::HalfOpen { since: now };
+        }
+
+        probe
+    }
+
+    pub fn record_success(&self, machine: &str) {
+        self.machines
+            .lock()
+            .expect("circuit breaker poisoned")
+            .remove(machine);
+    }
+
+    pub fn record_failure(&self, machine: &str) {
+        let threshold = self
+            .config
+            .consecutive_failures
+            .unwrap_or(DEFAULT_CONSECUTIVE_FAILURES)
+            .max(1);
+        let open = CircuitState::Open {
+            until: Instant::now() + self.ejection_time(),
+        };
+
+        let mut machines = self.machines.lock().expect("circuit breaker poisoned");
+        let state = machines
+            .entry(machine.to_string())
+            .or_insert(CircuitState::Closed { failures: 0 });
+
+        *state = match state {
+            CircuitState::Closed { failures } if *failures + 1 < threshold => {
+                CircuitState::Closed {
+                    failures: *failures + 1,
+                }
+            }
+            _ => open,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ejects_after_consecutive_failures() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            consecutive_failures: Some(2),
+            ejection_time: Some(Duration::from_secs(60)),
+        });
+
+        breaker.record_failure("m1");
+        assert!(breaker.try_acquire("m1"));
+
+        breaker.record_failure("m1");
+        assert!(!breaker.try_acquire("m1"));
+        assert!(breaker.try_acquire("m2"));
+
+        breaker.record_success("m1");
+        assert!(breaker.try_acquire("m1"));
+    }
+
+    #[test]
+    fn test_half_open_lets_one_probe_through() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            consecutive_failures: Some(1),
+            ejection_time: Some(Duration::from_millis(20)),
+        });
+
+        breaker.record_failure("m1");
+        assert!(!breaker.try_acquire("m1"));
+
+        std::thread::sleep(Duration::from_millis(25));
+        assert!(breaker.try_acquire("m1"));
+        assert!(!breaker.try_acquire("m1"));
+
+        breaker.record_failure("m1");
+        assert!(!breaker.try_acquire("m1"));
+    }
+}
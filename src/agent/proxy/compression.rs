@@ -0,0 +1,454 @@
+use std::{
+    io::Write,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use axum::http::{HeaderMap, HeaderValue};
+use brotli::CompressorWriter;
+use bytes::Bytes;
+use flate2::{
+    Compression,
+    write::{GzEncoder, ZlibEncoder},
+};
+use http_body_util::{BodyExt, combinators::BoxBody};
+use hyper::{
+    Method, Response, StatusCode,
+    body::{Body, Frame},
+};
+
+const DEFAULT_MIN_SIZE: u64 = 1024;
+/// Fast enough to compress on the fly, close to gzip's ratio at its best.
+const BROTLI_QUALITY: u32 = 4;
+const BROTLI_WINDOW_BITS: u32 = 22;
+const BROTLI_BUFFER_SIZE: usize = 4096;
+const DEFAULT_CONTENT_TYPES: &[&str] = &[
+    "text/*",
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "application/wasm",
+    "application/manifest+json",
+    "image/svg+xml",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContentEncoding {
+    Brotli,
+    Gzip,
+    Deflate,
+}
+
+impl ContentEncoding {
+    fn header_value(&self) -> HeaderValue {
+        match self {
+            ContentEncoding::Brotli => HeaderValue::from_static("br"),
+            ContentEncoding::Gzip => HeaderValue::from_static("gzip"),
+            ContentEncoding::Deflate => HeaderValue::from_static("deflate"),
+        }
+    }
+}
+
+/// Compresses HTTP responses the upstream sent uncompressed, for clients that accept it.
+#[derive(Clone, Debug)]
+pub struct ResponseCompression {
+    min_size: u64,
+    /// Content types to compress, either exact (`application/json`) or a whole type (`text/*`).
+    content_types: Vec<String>,
+}
+
+impl ResponseCompression {
+    pub fn new(min_size: Option<u64>, content_types: Option<Vec<String>>) -> Self {
+        let content_types = content_types
+            .unwrap_or_else(|| {
+                DEFAULT_CONTENT_TYPES
+                    .iter()
+                    .map(|t| t.to_string())
+                    .collect()
+            })
+            .into_iter()
+            .map(|t| t.trim().to_ascii_lowercase())
+            .collect();
+
+        Self {
+            min_size: min_size.unwrap_or(DEFAULT_MIN_SIZE),
+            content_types,
+        }
+    }
+
+    /// The encoding preferred by the client among the supported ones, read before the request
+    /// goes upstream.
+    pub fn negotiate(&self, method: &Method, headers: &HeaderMap) -> Option<ContentEncoding> {
+        if method == Method::HEAD {
+            return None;
+        }
+
+        let accept_encoding = headers
+            .get_all("accept-encoding")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect::<Vec<_>>()
+            .join(",");
+
+        negotiate_encoding(&accept_encoding)
+    }
+
+    pub fn compress(
+        &self,
+        encoding: Option<ContentEncoding>,
+        mut response: Response<BoxBody<Bytes, hyper::Error>>,
+    ) -> Response<BoxBody<Bytes, hyper::Error>> {
+        if !self.covers(response.status(), response.headers()) {
+            return response;
+        }
+
+        // caches must not hand a compressed answer to a client that didn't ask for it
+        response
+            .headers_mut()
+            .append("vary", HeaderValue::from_static("accept-encoding"));
+
+        let Some(encoding) = encoding else {
+            return response;
+        };
+
+        let headers = response.headers_mut();
+        headers.remove("content-length");
+        headers.remove("accept-ranges");
+        headers.insert("content-encoding", encoding.header_value());
+
+        // the compressed bytes differ, so a strong validator no longer holds
+        let weak_etag = headers
+            .get("etag")
+            .and_then(|etag| etag.to_str().ok())
+            .filter(|etag| !etag.starts_with("W/"))
+            .and_then(|etag| HeaderValue::from_str(&format!("W/{}", etag)).ok());
+        if let Some(etag) = weak_etag {
+            headers.insert("etag", etag);
+        }
+
+        response.map(|body| CompressedBody::new(body, encoding).boxed())
+    }
+
+    fn covers(&self, status: StatusCode, headers: &HeaderMap) -> bool {
+        if status.is_informational()
+            || status == StatusCode::NO_CONTENT
+            || status == StatusCode::NOT_MODIFIED
+            || status == StatusCode::PARTIAL_CONTENT
+        {
+            return false;
+        }
+
+        if headers.contains_key("content-encoding") || headers.contains_key("content-range") {
+            return false;
+        }
+
+        let no_transform = headers
+            .get_all("cache-control")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .any(|value| value.to_ascii_lowercase().contains("no-transform"));
+        if no_transform {
+            return false;
+        }
+
+        let too_small = headers
+            .get("content-length")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok())
+            .is_some_and(|length| length < self.min_size);
+        if too_small {
+            return false;
+        }
+
+        let Some(content_type) = headers
+            .get("content-type")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .map(|value| value.trim().to_ascii_lowercase())
+        else {
+            return false;
+        };
+
+        self.content_types
+            .iter()
+            .any(|pattern| match pattern.strip_suffix("/*") {
+                Some(kind) => content_type
+                    .split_once('/')
+                    .is_some_and(|(content_kind, _)| content_kind == kind),
+                None => *pattern == content_type,
+            })
+    }
+}
+
+/// Picks the encoding with the highest `q` value, brotli then gzip winning ties.
+fn negotiate_encoding(accept_encoding: &str) -> Option<ContentEncoding> {
+    let mut brotli = None;
+    let mut gzip = None;
+    let mut deflate = None;
+    let mut wildcard = None;
+
+    for entry in accept_encoding.split(',') {
+        let mut parts = entry.split(';');
+        let coding = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let quality = parts
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        match coding.as_str() {
+            "br" => brotli = Some(quality),
+            "gzip" | "x-gzip" => gzip = Some(quality),
+            "deflate" => deflate = Some(quality),
+            "*" => wildcard = Some(quality),
+            _ => {}
+        }
+    }
+
+    let brotli = brotli.or(wildcard).unwrap_or(0.0);
+    let gzip = gzip.or(wildcard).unwrap_or(0.0);
+    let deflate = deflate.or(wildcard).unwrap_or(0.0);
+
+    if brotli > 0.0 && brotli >= gzip && brotli >= deflate {
+        Some(ContentEncoding::Brotli)
+    } else if gzip > 0.0 && gzip >= deflate {
+        Some(ContentEncoding::Gzip)
+    } else if deflate > 0.0 {
+        Some(ContentEncoding::Deflate)
+    } else {
+        None
+    }
+}
+
+enum Encoder {
+    Brotli(Box<CompressorWriter<Vec<u8>>>),
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    // the encoders write into memory, which can't fail
+    fn write(&mut self, data: &[u8]) {
+        match self {
+            Encoder::Brotli(encoder) => encoder.write_all(data),
+            Encoder::Gzip(encoder) => encoder.write_all(data),
+            Encoder::Deflate(encoder) => encoder.write_all(data),
+        }
+        .expect("compressing into memory failed")
+    }
+
+    fn flush(&mut self) -> Bytes {
+        match self {
+            Encoder::Brotli(encoder) => encoder.flush(),
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Deflate(encoder) => encoder.flush(),
+        }
+        .expect("compressing into memory failed");
+        self.take_output()
+    }
+
+    fn take_output(&mut self) -> Bytes {
+        let output = match self {
+            Encoder::Brotli(encoder) => encoder.get_mut(),
+            Encoder::Gzip(encoder) => encoder.get_mut(),
+            Encoder::Deflate(encoder) => encoder.get_mut(),
+        };
+        Bytes::from(std::mem::take(output))
+    }
+
+    fn finish(self) -> Bytes {
+        match self {
+            Encoder::Brotli(encoder) => Ok(encoder.into_inner()),
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Deflate(encoder) => encoder.finish(),
+        }
+        .map(Bytes::from)
+        .expect("compressing into memory failed")
+    }
+}
+
+/// Compresses a response body as it streams. Whatever was compressed so far is flushed whenever
+/// the upstream pauses, so streamed responses aren't held back.
+struct CompressedBody {
+    inner: BoxBody<Bytes, hyper::Error>,
+    encoder: Option<Encoder>,
+    unflushed: bool,
+    trailers: Option<HeaderMap>,
+}
+
+impl CompressedBody {
+    fn new(inner: BoxBody<Bytes, hyper::Error>, encoding: ContentEncoding) -> Self {
+        let encoder = match encoding {
+            ContentEncoding::Brotli => Encoder::Brotli(Box::new(CompressorWriter::new(
+                Vec::new(),
+                BROTLI_BUFFER_SIZE,
+                BROTLI_QUALITY,
+                BROTLI_WINDOW_BITS,
+            ))),
+            ContentEncoding::Gzip => Encoder::Gzip(GzEncoder::new(Vec::new(), Compression::fast())),
+            ContentEncoding::Deflate => {
+                Encoder::Deflate(ZlibEncoder::new(Vec::new(), Compression::fast()))
+            }
+        };
+
+        Self {
+            inner,
+            encoder: Some(encoder),
+            unflushed: false,
+            trailers: None,
+        }
+    }
+}
+
+impl Body for CompressedBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, hyper::Error>>> {
+        let this = self.get_mut();
+
+        loop {
+            let Some(encoder) = this.encoder.as_mut() else {
+                return Poll::Ready(this.trailers.take().map(|t| Ok(Frame::trailers(t))));
+            };
+
+            let frame = match Pin::new(&mut this.inner).poll_frame(cx) {
+                Poll::Pending if this.unflushed => {
+                    this.unflushed = false;
+                    let output = encoder.flush();
+                    if output.is_empty() {
+                        return Poll::Pending;
+                    }
+                    return Poll::Ready(Some(Ok(Frame::data(output))));
+                }
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(frame) => frame,
+            };
+
+            match frame {
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(data) => {
+                        encoder.write(&data);
+                        this.unflushed = true;
+                        let output = encoder.take_output();
+                        if !output.is_empty() {
+                            return Poll::Ready(Some(Ok(Frame::data(output))));
+                        }
+                        continue;
+                    }
+                    Err(frame) => {
+                        // trailers end the body, they go out after the compressed data
+                        if let Ok(trailers) = frame.into_trailers() {
+                            this.trailers = Some(trailers);
+                        }
+                    }
+                },
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                None => {}
+            }
+
+            let Some(encoder) = this.encoder.take() else {
+                continue;
+            };
+            return Poll::Ready(Some(Ok(Frame::data(encoder.finish()))));
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.encoder.is_none() && self.trailers.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::read::GzDecoder;
+    use http_body_util::Full;
+    use std::io::Read;
+
+    use super::*;
+
+    fn response(content_type: &str, body: &'static str) -> Response<BoxBody<Bytes, hyper::Error>> {
+        let mut response = Response::new(
+            Full::new(Bytes::from(body))
+                .map_err(|never| match never {})
+                .boxed(),
+        );
+        response
+            .headers_mut()
+            .insert("content-type", HeaderValue::from_str(content_type).unwrap());
+        response
+            .headers_mut()
+            .insert("content-length", HeaderValue::from(body.len()));
+        response
+    }
+
+    #[test]
+    fn test_negotiate_encoding() {
+        assert_eq!(
+            negotiate_encoding("gzip, deflate, br"),
+            Some(ContentEncoding::Brotli)
+        );
+        assert_eq!(
+            negotiate_encoding("gzip, deflate"),
+            Some(ContentEncoding::Gzip)
+        );
+        assert_eq!(
+            negotiate_encoding("br;q=0.5, gzip"),
+            Some(ContentEncoding::Gzip)
+        );
+        assert_eq!(
+            negotiate_encoding("gzip;q=0.5, deflate"),
+            Some(ContentEncoding::Deflate)
+        );
+        assert_eq!(negotiate_encoding("*"), Some(ContentEncoding::Brotli));
+        assert_eq!(negotiate_encoding("gzip;q=0, *;q=0"), None);
+        assert_eq!(
+            negotiate_encoding("br, identity"),
+            Some(ContentEncoding::Brotli)
+        );
+        assert_eq!(negotiate_encoding("compress, identity"), None);
+        assert_eq!(negotiate_encoding(""), None);
+    }
+
+    #[tokio::test]
+    async fn test_compress_response() {
+        let compression = ResponseCompression::new(Some(16), None);
+        let body = "hello hello hello hello hello hello";
+
+        let compressed =
+            compression.compress(Some(ContentEncoding::Gzip), response("text/html", body));
+        assert_eq!(compressed.headers()["content-encoding"], "gzip");
+        assert_eq!(compressed.headers()["vary"], "accept-encoding");
+        assert!(!compressed.headers().contains_key("content-length"));
+
+        let bytes = compressed.into_body().collect().await.unwrap().to_bytes();
+        let mut decompressed = String::new();
+        GzDecoder::new(&bytes[..])
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, body);
+
+        let compressed =
+            compression.compress(Some(ContentEncoding::Brotli), response("text/html", body));
+        assert_eq!(compressed.headers()["content-encoding"], "br");
+
+        let bytes = compressed.into_body().collect().await.unwrap().to_bytes();
+        let mut decompressed = String::new();
+        brotli::Decompressor::new(&bytes[..], 4096)
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, body);
+
+        let image = compression.compress(Some(ContentEncoding::Gzip), response("image/png", body));
+        assert!(!image.headers().contains_key("content-encoding"));
+
+        let small = compression.compress(Some(ContentEncoding::Gzip), response("text/css", "a{}"));
+        assert!(!small.headers().contains_key("content-encoding"));
+
+        let not_accepted = compression.compress(None, response("application/json", body));
+        assert!(!not_accepted.headers().contains_key("content-encoding"));
+        assert_eq!(not_accepted.headers()["vary"], "accept-encoding");
+    }
+}
@@ -0,0 +1,82 @@
+use std::sync::Arc;
+
+use axum::http::HeaderValue;
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full, combinators::BoxBody};
+use hyper::{Response, StatusCode};
+use papaya::HashMap;
+
+use crate::agent::proxy::{BindingMode, ProxyBinding, ProxyBindingService};
+
+const BUILTIN_NOT_FOUND_PAGE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Not found</title>
+<style>
+body { margin: 0; min-height: 100vh; display: flex; align-items: center; justify-content: center; font-family: system-ui, sans-serif; background: #0b0b0f; color: #e6e6eb; }
+main { text-align: center; }
+h1 { font-size: 4rem; margin: 0; }
+p { color: #9a9aa5; }
+</style>
+</head>
+<body>
+<main>
+<h1>404</h1>
+<p>There is nothing deployed at this address.</p>
+</main>
+</body>
+</html>
+"#;
+
+/// Receives the HTTP(S) requests whose host matches no binding: either a designated service or
+/// a static not found page.
+#[derive(Debug, Clone)]
+pub struct DefaultBackend {
+    service: Option<ProxyBindingService>,
+    not_found_page: Bytes,
+}
+
+impl DefaultBackend {
+    pub fn new(service: Option<ProxyBindingService>, not_found_page: Option<Bytes>) -> Self {
+        Self {
+            service,
+            not_found_page: not_found_page
+                .unwrap_or(Bytes::from_static(BUILTIN_NOT_FOUND_PAGE.as_bytes())),
+        }
+    }
+
+    /// An external binding of the catch-all service, when one is configured and deployed.
+    pub fn binding(&self, bindings: &Arc<HashMap<String, ProxyBinding>>) -> Option<ProxyBinding> {
+        let service = self.service.as_ref()?;
+
+        let bindings = bindings.pin_owned();
+        bindings
+            .values()
+            .find(|b| {
+                matches!(b.mode, BindingMode::External { .. })
+                    && b.service.as_ref().is_some_and(|s| {
+                        s.tenant == service.tenant
+                            && s.namespace == service.namespace
+                            && s.name == service.name
+                    })
+            })
+            .cloned()
+    }
+
+    pub fn not_found(&self) -> Response<BoxBody<Bytes, hyper::Error>> {
+        let mut response = Response::new(
+            Full::new(self.not_found_page.clone())
+                .map_err(|never| match never {})
+                .boxed(),
+        );
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        response.headers_mut().insert(
+            "content-type",
+            HeaderValue::from_static("text/html; charset=utf-8"),
+        );
+
+        response
+    }
+}
@@ -0,0 +1,149 @@
+use axum::http::{HeaderMap, HeaderValue};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full, combinators::BoxBody};
+use hyper::{Response, StatusCode};
+
+/// Errors the proxy answers with itself when the upstream can't.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorPage {
+    BadGateway,
+    ServiceUnavailable,
+    Maintenance,
+}
+
+impl ErrorPage {
+    fn status(&self) -> StatusCode {
+        match self {
+            ErrorPage::BadGateway => StatusCode::BAD_GATEWAY,
+            ErrorPage::ServiceUnavailable | ErrorPage::Maintenance => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+        }
+    }
+
+    fn message(&self) -> &'static str {
+        match self {
+            ErrorPage::BadGateway => "bad gateway, the service failed to answer",
+            ErrorPage::ServiceUnavailable => "service unavailable, it may be starting up",
+            ErrorPage::Maintenance => "service under maintenance, please come back later",
+        }
+    }
+}
+
+/// HTML bodies of a binding replacing the builtin error pages, unset ones keep the builtin page.
+#[derive(Debug, Clone, Default)]
+pub struct ErrorPages {
+    pub bad_gateway: Option<Bytes>,
+    pub service_unavailable: Option<Bytes>,
+    pub maintenance: Option<Bytes>,
+}
+
+impl ErrorPages {
+    fn custom(&self, page: ErrorPage) -> Option<Bytes> {
+        match page {
+            ErrorPage::BadGateway => self.bad_gateway.clone(),
+            ErrorPage::ServiceUnavailable => self.service_unavailable.clone(),
+            ErrorPage::Maintenance => self.maintenance.clone(),
+        }
+    }
+}
+
+/// Whether the client is a browser, which gets an HTML page rather than a plain text error.
+pub fn accepts_html(headers: &HeaderMap) -> bool {
+    headers
+        .get_all("accept")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.contains("text/html"))
+}
+
+pub fn error_response(
+    page: ErrorPage,
+    custom: Option<&ErrorPages>,
+    html: bool,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let (body, content_type) = if html {
+        let body = custom
+            .and_then(|pages| pages.custom(page))
+            .unwrap_or_else(|| Bytes::from(builtin_page(page)));
+        (body, "text/html; charset=utf-8")
+    } else {
+        (Bytes::from(page.message()), "text/plain; charset=utf-8")
+    };
+
+    let mut response = Response::new(Full::new(body).map_err(|never| match never {}).boxed());
+    *response.status_mut() = page.status();
+    response
+        .headers_mut()
+        .insert("content-type", HeaderValue::from_static(content_type));
+    response
+        .headers_mut()
+        .insert("cache-control", HeaderValue::from_static("no-store"));
+
+    response
+}
+
+fn builtin_page(page: ErrorPage) -> String {
+    let status = page.status();
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{reason}</title>
+<style>
+body {{ margin: 0; min-height: 100vh; display: flex; align-items: center; justify-content: center; font-family: system-ui, sans-serif; background: #0b0b0f; color: #e6e6eb; }}
+main {{ text-align: center; }}
+h1 {{ font-size: 4rem; margin: 0; }}
+p {{ color: #9a9aa5; }}
+</style>
+</head>
+<body>
+<main>
+<h1>{code}</h1>
+<p>{message}</p>
+</main>
+</body>
+</html>
+"#,
+        reason = status.canonical_reason().unwrap_or_default(),
+        code = status.as_u16(),
+        message = page.message(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_response() {
+        let mut headers = HeaderMap::new();
+        assert!(!accepts_html(&headers));
+        headers.insert(
+            "accept",
+            HeaderValue::from_static("text/html,application/xhtml+xml,*/*;q=0.8"),
+        );
+        assert!(accepts_html(&headers));
+
+        let response = error_response(ErrorPage::BadGateway, None, false);
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/plain; charset=utf-8"
+        );
+
+        let pages = ErrorPages {
+            maintenance: Some(Bytes::from_static(b"<h1>back soon</h1>")),
+            ..Default::default()
+        };
+        let response = error_response(ErrorPage::Maintenance, Some(&pages), true);
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(pages.custom(ErrorPage::ServiceUnavailable), None);
+    }
+}
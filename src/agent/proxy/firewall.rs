@@ -0,0 +1,126 @@
+use std::net::IpAddr;
+
+use anyhow::{Context, Result, bail};
+
+/// An IPv4 or IPv6 network, eg. `10.0.0.0/8` or `2001:db8::/32`. A bare address is a network of
+/// its own.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IpNetwork {
+    address: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNetwork {
+    pub fn parse(cidr: &str) -> Result<Self> {
+        let (address, prefix_len) = match cidr.trim().split_once('/') {
+            Some((address, prefix_len)) => (
+                address,
+                Some(
+                    prefix_len
+                        .parse::<u8>()
+                        .context(format!("Invalid CIDR: {}", cidr))?,
+                ),
+            ),
+            None => (cidr.trim(), None),
+        };
+
+        let address = address
+            .parse::<IpAddr>()
+            .context(format!("Invalid CIDR: {}", cidr))?;
+        let max_prefix_len = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+
+        let prefix_len = prefix_len.unwrap_or(max_prefix_len);
+        if prefix_len > max_prefix_len {
+            bail!("Invalid CIDR: {}", cidr);
+        }
+
+        Ok(Self {
+            address,
+            prefix_len,
+        })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.address, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Client address filter of a binding. Denied networks always win, and once there are allowed
+/// networks any other address is rejected.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IpFirewall {
+    pub allow: Vec<IpNetwork>,
+    pub deny: Vec<IpNetwork>,
+}
+
+impl IpFirewall {
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|network| network.contains(ip)) {
+            return false;
+        }
+
+        self.allow.is_empty() || self.allow.iter().any(|network| network.contains(ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn test_network_contains() {
+        let network = IpNetwork::parse("10.1.0.0/16").unwrap();
+        assert!(network.contains(ip("10.1.42.7")));
+        assert!(network.contains(ip("::ffff:10.1.0.1")));
+        assert!(!network.contains(ip("10.2.0.1")));
+
+        let network = IpNetwork::parse("2001:db8::/32").unwrap();
+        assert!(network.contains(ip("2001:db8:1::1")));
+        assert!(!network.contains(ip("2001:db9::1")));
+
+        assert!(
+            IpNetwork::parse("0.0.0.0/0")
+                .unwrap()
+                .contains(ip("8.8.8.8"))
+        );
+        assert!(
+            IpNetwork::parse("10.0.0.1")
+                .unwrap()
+                .contains(ip("10.0.0.1"))
+        );
+        assert!(IpNetwork::parse("10.0.0.0/33").is_err());
+    }
+
+    #[test]
+    fn test_firewall_deny_wins() {
+        let firewall = IpFirewall {
+            allow: vec![IpNetwork::parse("192.168.0.0/16").unwrap()],
+            deny: vec![IpNetwork::parse("192.168.1.0/24").unwrap()],
+        };
+
+        assert!(firewall.allows(ip("192.168.2.1")));
+        assert!(!firewall.allows(ip("192.168.1.1")));
+        assert!(!firewall.allows(ip("8.8.8.8")));
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Result, bail};
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+
+/// Headers the proxy manages itself, rewriting them would break the message framing or routing.
+const PROTECTED_HEADERS: &[&str] = &[
+    "host",
+    "connection",
+    "upgrade",
+    "content-length",
+    "transfer-encoding",
+    "keep-alive",
+    "te",
+    "trailer",
+];
+
+/// Edits applied to the headers of a message, in order: `remove`, then `set` (replacing any
+/// value), then `add` (keeping existing values).
+#[derive(Clone, Debug, Default)]
+pub struct HeaderRules {
+    remove: Vec<HeaderName>,
+    set: Vec<(HeaderName, HeaderValue)>,
+    add: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl HeaderRules {
+    pub fn new(
+        remove: &[String],
+        set: &BTreeMap<String, String>,
+        add: &BTreeMap<String, String>,
+    ) -> Result<Self> {
+        let remove = remove
+            .iter()
+            .map(|name| header_name(name))
+            .collect::<Result<Vec<_>>>()?;
+
+        let values = |headers: &BTreeMap<String, String>| {
+            headers
+                .iter()
+                .map(|(name, value)| {
+                    let Ok(value) = HeaderValue::from_str(value) else {
+                        bail!("Invalid value for header {}", name);
+                    };
+                    Ok((header_name(name)?, value))
+                })
+                .collect::<Result<Vec<_>>>()
+        };
+
+        Ok(Self {
+            remove,
+            set: values(set)?,
+            add: values(add)?,
+        })
+    }
+
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        for name in &self.remove {
+            headers.remove(name);
+        }
+        for (name, value) in &self.set {
+            headers.insert(name.clone(), value.clone());
+        }
+        for (name, value) in &self.add {
+            headers.append(name.clone(), value.clone());
+        }
+    }
+}
+
+/// Header rules of a binding, for the requests sent upstream and the responses sent back.
+#[derive(Clone, Debug, Default)]
+pub struct HeaderRewrite {
+    pub request: HeaderRules,
+    pub response: HeaderRules,
+}
+
+fn header_name(name: &str) -> Result<HeaderName> {
+    let Ok(name) = HeaderName::from_bytes(name.as_bytes()) else {
+        bail!("Invalid header name {:?}", name);
+    };
+
+    if PROTECTED_HEADERS.contains(&name.as_str()) {
+        bail!(
+            "Header {} is managed by the proxy and can't be rewritten",
+            name
+        );
+    }
+
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_rules_apply() {
+        let rules = HeaderRules::new(
+            &["Server".to_string()],
+            &BTreeMap::from([("X-Frame-Options".to_string(), "DENY".to_string())]),
+            &BTreeMap::from([("vary".to_string(), "Origin".to_string())]),
+        )
+        .unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("server", HeaderValue::from_static("nginx"));
+        headers.insert("x-frame-options", HeaderValue::from_static("SAMEORIGIN"));
+        headers.insert("vary", HeaderValue::from_static("Accept-Encoding"));
+        rules.apply(&mut headers);
+
+        assert!(!headers.contains_key("server"));
+        assert_eq!(headers["x-frame-options"], "DENY");
+        assert_eq!(headers.get_all("vary").iter().count(), 2);
+
+        assert!(
+            HeaderRules::new(
+                &["Content-Length".to_string()],
+                &BTreeMap::new(),
+                &BTreeMap::new()
+            )
+            .is_err()
+        );
+        assert!(
+            HeaderRules::new(
+                &[],
+                &BTreeMap::from([("x-bad".to_string(), "a\nb".to_string())]),
+                &BTreeMap::new()
+            )
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,110 @@
+/// Set on requests routed through a wildcard binding, carrying the label matched by `*`.
+pub const WILDCARD_LABEL_HEADER: &str = "x-lttle-wildcard-label";
+
+/// How a requested host matched a binding host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostMatch {
+    Exact,
+    /// Matched `*.suffix`, with the label that stood in for `*`.
+    Wildcard(String),
+}
+
+impl HostMatch {
+    pub fn is_exact(&self) -> bool {
+        matches!(self, HostMatch::Exact)
+    }
+}
+
+pub fn is_wildcard_host(pattern: &str) -> bool {
+    pattern.starts_with("*.")
+}
+
+/// Matches `host` against a binding host. Like wildcard certificates, `*` stands for exactly one
+/// label: `*.preview.example.com` matches `pr-1.preview.example.com` but not
+/// `a.pr-1.preview.example.com` or `preview.example.com`.
+pub fn match_host(pattern: &str, host: &str) -> Option<HostMatch> {
+    if pattern.eq_ignore_ascii_case(host) {
+        return Some(HostMatch::Exact);
+    }
+
+    let suffix = pattern.strip_prefix('*')?;
+    if !suffix.starts_with('.') || host.len() <= suffix.len() {
+        return None;
+    }
+
+    let (label, host_suffix) = host.split_at(host.len() - suffix.len());
+    if !host_suffix.eq_ignore_ascii_case(suffix) || label.is_empty() || label.contains('.') {
+        return None;
+    }
+
+    Some(HostMatch::Wildcard(label.to_ascii_lowercase()))
+}
+
+/// The wildcard name covering `host`: `pr-1.preview.example.com` gives `*.preview.example.com`.
+/// Hosts directly under a TLD don't get one.
+pub fn wildcard_parent(host: &str) -> Option<String> {
+    let (_, parent) = host.split_once('.')?;
+    if !parent.contains('.') {
+        return None;
+    }
+
+    Some(format!("*.{}", parent))
+}
+
+/// Splits `example.com:8080` into the host and port, leaving hosts without a port untouched.
+pub fn split_host_port(host: &str) -> (&str, Option<u16>) {
+    match host.rsplit_once(':') {
+        Some((name, port)) => match port.parse() {
+            Ok(port) => (name, Some(port)),
+            Err(_) => (host, None),
+        },
+        None => (host, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_host() {
+        assert_eq!(
+            match_host("example.com", "Example.com"),
+            Some(HostMatch::Exact)
+        );
+        assert_eq!(
+            match_host("*.preview.example.com", "pr-1.preview.example.com"),
+            Some(HostMatch::Wildcard("pr-1".to_string()))
+        );
+        assert_eq!(
+            match_host("*.preview.example.com", "a.pr-1.preview.example.com"),
+            None
+        );
+        assert_eq!(
+            match_host("*.preview.example.com", "preview.example.com"),
+            None
+        );
+        assert_eq!(
+            match_host("*.preview.example.com", "xpreview.example.com"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_wildcard_parent() {
+        assert_eq!(
+            wildcard_parent("pr-1.preview.example.com"),
+            Some("*.preview.example.com".to_string())
+        );
+        assert_eq!(wildcard_parent("example.com"), None);
+    }
+
+    #[test]
+    fn test_split_host_port() {
+        assert_eq!(
+            split_host_port("example.com:8080"),
+            ("example.com", Some(8080))
+        );
+        assert_eq!(split_host_port("example.com"), ("example.com", None));
+    }
+}
@@ -0,0 +1,174 @@
+use std::{convert::Infallible, future::Future, net::SocketAddr, pin::Pin, sync::Arc};
+
+use anyhow::{Result, bail};
+use bytes::{Buf, Bytes};
+use futures_util::{Stream, stream};
+use h3::{error::StreamError, server::RequestResolver};
+use http_body_util::{BodyExt, StreamBody};
+use hyper::{Request, Response, Version, body::Frame, client::conn::http2::SendRequest};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use quinn::{
+    Endpoint, Incoming,
+    crypto::rustls::{HandshakeData, QuicServerConfig},
+};
+use rustls::{ServerConfig, server::ResolvesServerCert, version::TLS13};
+use tokio::{
+    io::{DuplexStream, duplex},
+    spawn,
+};
+use tracing::{info, warn};
+
+/// HTTP/3 is only served next to the TLS listener of the standard HTTPS port.
+pub const HTTP3_PORT: u16 = 443;
+
+/// Advertised on HTTPS responses so clients switch to HTTP/3 for the next day.
+pub const ALT_SVC: &str = "h3=\":443\"; ma=86400";
+
+/// Bytes buffered in each direction between a QUIC connection and the HTTP server answering it.
+const BRIDGE_BUFFER_SIZE: usize = 256 * 1024;
+
+type RequestBody =
+    StreamBody<Pin<Box<dyn Stream<Item = Result<Frame<Bytes>, StreamError>> + Send>>>;
+
+/// A QUIC connection handed over to the HTTPS server as an HTTP/2 connection on an in memory
+/// stream, so HTTP/3 requests go through the same routing and policies as HTTPS ones.
+pub struct Http3Connection {
+    pub io: DuplexStream,
+    pub client_addr: SocketAddr,
+    pub server_name: Option<String>,
+}
+
+/// TLS for QUIC, with the certificates of the HTTPS listener.
+pub fn quic_server_config(
+    cert_resolver: Arc<dyn ResolvesServerCert>,
+) -> Result<quinn::ServerConfig> {
+    let mut tls_config = ServerConfig::builder_with_protocol_versions(&[&TLS13])
+        .with_no_client_auth()
+        .with_cert_resolver(cert_resolver);
+    tls_config.alpn_protocols = vec![b"h3".to_vec()];
+
+    let crypto = QuicServerConfig::try_from(tls_config)?;
+    Ok(quinn::ServerConfig::with_crypto(Arc::new(crypto)))
+}
+
+pub async fn http3_listener<F, Fut>(
+    addr: String,
+    config: quinn::ServerConfig,
+    on_connection: F,
+) -> Result<Infallible>
+where
+    F: Fn(Http3Connection) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    info!("Starting HTTP/3 listener on {}", addr);
+    let endpoint = Endpoint::server(config, addr.parse()?)?;
+    let on_connection = Arc::new(on_connection);
+
+    loop {
+        let Some(incoming) = endpoint.accept().await else {
+            bail!("HTTP/3 listener on {} closed", addr);
+        };
+        let on_connection = on_connection.clone();
+
+        spawn(async move {
+            if let Err(e) = handle_quic_connection(incoming, on_connection.as_ref()).await {
+                warn!("Error proxying HTTP/3 connection: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle_quic_connection<F, Fut>(incoming: Incoming, on_connection: &F) -> Result<()>
+where
+    F: Fn(Http3Connection) -> Fut,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    let connection = incoming.await?;
+    let server_name = connection
+        .handshake_data()
+        .and_then(|data| data.downcast::<HandshakeData>().ok())
+        .and_then(|data| data.server_name);
+
+    let (io, bridge_io) = duplex(BRIDGE_BUFFER_SIZE);
+    let serve = on_connection(Http3Connection {
+        io,
+        client_addr: connection.remote_address(),
+        server_name,
+    });
+    spawn(async move {
+        if let Err(e) = serve.await {
+            warn!("Error serving HTTP/3 connection: {}", e);
+        }
+    });
+
+    let (sender, bridge) =
+        hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(bridge_io))
+            .await?;
+    spawn(async move {
+        if let Err(e) = bridge.await {
+            warn!("HTTP/3 bridge connection failed: {}", e);
+        }
+    });
+
+    let mut h3_connection =
+        h3::server::Connection::<_, Bytes>::new(h3_quinn::Connection::new(connection)).await?;
+
+    loop {
+        let resolver = match h3_connection.accept().await {
+            Ok(Some(resolver)) => resolver,
+            Ok(None) => return Ok(()),
+            Err(e) if e.is_h3_no_error() => return Ok(()),
+            Err(e) => bail!("HTTP/3 connection failed: {}", e),
+        };
+        let sender = sender.clone();
+
+        spawn(async move {
+            if let Err(e) = forward_request(resolver, sender).await {
+                warn!("Failed to forward HTTP/3 request: {}", e);
+            }
+        });
+    }
+}
+
+async fn forward_request(
+    resolver: RequestResolver<h3_quinn::Connection, Bytes>,
+    mut sender: SendRequest<RequestBody>,
+) -> Result<()> {
+    let (req, stream) = resolver.resolve_request().await?;
+    let (mut send, recv) = stream.split();
+
+    let body = stream::unfold(Some(recv), |recv| async move {
+        let mut recv = recv?;
+        match recv.recv_data().await {
+            Ok(Some(mut data)) => Some((
+                Ok(Frame::data(data.copy_to_bytes(data.remaining()))),
+                Some(recv),
+            )),
+            Ok(None) => None,
+            Err(e) => Some((Err(e), None)),
+        }
+    });
+
+    let (mut parts, ()) = req.into_parts();
+    parts.version = Version::HTTP_2;
+    let response = sender
+        .send_request(Request::from_parts(parts, StreamBody::new(Box::pin(body))))
+        .await?;
+
+    let (parts, mut body) = response.into_parts();
+    send.send_response(Response::from_parts(parts, ())).await?;
+
+    while let Some(frame) = body.frame().await {
+        match frame?.into_data() {
+            Ok(data) => send.send_data(data).await?,
+            Err(frame) => {
+                if let Ok(trailers) = frame.into_trailers() {
+                    send.send_trailers(trailers).await?;
+                }
+            }
+        }
+    }
+    send.finish().await?;
+
+    Ok(())
+}
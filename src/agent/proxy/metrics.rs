@@ -0,0 +1,409 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicI64, AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use crate::agent::machine::machine::TrafficCounters;
+
+/// Upper bounds (in seconds) of the request latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+/// Per-binding proxy metrics, rendered in the Prometheus text format.
+///
+/// Only raw counters and histogram buckets are kept here, rates (RPS) and quantiles
+/// (p50/p95) are derived at query time with `rate()` and `histogram_quantile()`.
+#[derive(Debug, Default)]
+pub struct ProxyMetrics {
+    bindings: RwLock<BTreeMap<String, Arc<BindingMetrics>>>,
+}
+
+#[derive(Debug)]
+pub struct BindingMetrics {
+    labels: String,
+    label_pairs: Vec<(String, String)>,
+    requests: AtomicU64,
+    responses: [AtomicU64; 5],
+    upstream_errors: AtomicU64,
+    rate_limited: AtomicU64,
+    active_connections: AtomicI64,
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    latency_sum_us: AtomicU64,
+    latency_count: AtomicU64,
+    traffic: Arc<TrafficCounters>,
+}
+
+/// Counts as an open connection until dropped.
+pub struct ConnectionGuard {
+    metrics: Arc<BindingMetrics>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.metrics
+            .active_connections
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl ProxyMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the metrics for a binding, keeping the counters across updates of the same binding.
+    pub fn binding(&self, binding_name: &str, labels: &[(&str, &str)]) -> Arc<BindingMetrics> {
+        if let Some(metrics) = self
+            .bindings
+            .read()
+            .expect("proxy metrics poisoned")
+            .get(binding_name)
+        {
+            return metrics.clone();
+        }
+
+        let mut all_labels = vec![("binding", binding_name)];
+        all_labels.extend_from_slice(labels);
+
+        self.bindings
+            .write()
+            .expect("proxy metrics poisoned")
+            .entry(binding_name.to_string())
+            .or_insert_with(|| Arc::new(BindingMetrics::new(&all_labels)))
+            .clone()
+    }
+
+    pub fn remove_binding(&self, binding_name: &str) {
+        self.bindings
+            .write()
+            .expect("proxy metrics poisoned")
+            .remove(binding_name);
+    }
+
+    /// Requests received by each binding so far, with the labels of the binding.
+    pub fn request_counts(&self) -> Vec<(Vec<(String, String)>, u64)> {
+        self.bindings
+            .read()
+            .expect("proxy metrics poisoned")
+            .values()
+            .map(|metrics| {
+                (
+                    metrics.label_pairs.clone(),
+                    metrics.requests.load(Ordering::Relaxed),
+                )
+            })
+            .collect()
+    }
+
+    pub fn render(&self) -> String {
+        let bindings = self.bindings.read().expect("proxy metrics poisoned");
+        let mut out = String::new();
+
+        render_header(
+            &mut out,
+            "ignition_proxy_requests_total",
+            "counter",
+            "Requests received by the proxy",
+        );
+        for metrics in bindings.values() {
+            render_value(
+                &mut out,
+                "ignition_proxy_requests_total",
+                &metrics.labels,
+                None,
+                metrics.requests.load(Ordering::Relaxed),
+            );
+        }
+
+        render_header(
+            &mut out,
+            "ignition_proxy_responses_total",
+            "counter",
+            "Responses sent by the proxy, by status class",
+        );
+        for metrics in bindings.values() {
+            for (index, count) in metrics.responses.iter().enumerate() {
+                render_value(
+                    &mut out,
+                    "ignition_proxy_responses_total",
+                    &metrics.labels,
+                    Some(("status_class", &format!("{}xx", index + 1))),
+                    count.load(Ordering::Relaxed),
+                );
+            }
+        }
+
+        render_header(
+            &mut out,
+            "ignition_proxy_upstream_errors_total",
+            "counter",
+            "Failures to connect to or get a response from the upstream machine",
+        );
+        for metrics in bindings.values() {
+            render_value(
+                &mut out,
+                "ignition_proxy_upstream_errors_total",
+                &metrics.labels,
+                None,
+                metrics.upstream_errors.load(Ordering::Relaxed),
+            );
+        }
+
+        render_header(
+            &mut out,
+            "ignition_proxy_rate_limited_total",
+            "counter",
+            "Requests and connections rejected by rate limits",
+        );
+        for metrics in bindings.values() {
+            render_value(
+                &mut out,
+                "ignition_proxy_rate_limited_total",
+                &metrics.labels,
+                None,
+                metrics.rate_limited.load(Ordering::Relaxed),
+            );
+        }
+
+        render_header(
+            &mut out,
+            "ignition_proxy_active_connections",
+            "gauge",
+            "Connections (or in-flight plain HTTP requests) currently open",
+        );
+        for metrics in bindings.values() {
+            render_value(
+                &mut out,
+                "ignition_proxy_active_connections",
+                &metrics.labels,
+                None,
+                metrics.active_connections.load(Ordering::Relaxed).max(0),
+            );
+        }
+
+        render_header(
+            &mut out,
+            "ignition_proxy_received_bytes_total",
+            "counter",
+            "Bytes received from clients",
+        );
+        for metrics in bindings.values() {
+            render_value(
+                &mut out,
+                "ignition_proxy_received_bytes_total",
+                &metrics.labels,
+                None,
+                metrics.traffic.bytes_in(),
+            );
+        }
+
+        render_header(
+            &mut out,
+            "ignition_proxy_sent_bytes_total",
+            "counter",
+            "Bytes sent back to clients",
+        );
+        for metrics in bindings.values() {
+            render_value(
+                &mut out,
+                "ignition_proxy_sent_bytes_total",
+                &metrics.labels,
+                None,
+                metrics.traffic.bytes_out(),
+            );
+        }
+
+        render_header(
+            &mut out,
+            "ignition_proxy_request_duration_seconds",
+            "histogram",
+            "Time until the upstream response headers were received",
+        );
+        for metrics in bindings.values() {
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(metrics.latency_buckets.iter()) {
+                cumulative += count.load(Ordering::Relaxed);
+                render_value(
+                    &mut out,
+                    "ignition_proxy_request_duration_seconds_bucket",
+                    &metrics.labels,
+                    Some(("le", &bound.to_string())),
+                    cumulative,
+                );
+            }
+
+            let total = metrics.latency_count.load(Ordering::Relaxed);
+            render_value(
+                &mut out,
+                "ignition_proxy_request_duration_seconds_bucket",
+                &metrics.labels,
+                Some(("le", "+Inf")),
+                total,
+            );
+
+            let sum = metrics.latency_sum_us.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+            render_value(
+                &mut out,
+                "ignition_proxy_request_duration_seconds_sum",
+                &metrics.labels,
+                None,
+                sum,
+            );
+            render_value(
+                &mut out,
+                "ignition_proxy_request_duration_seconds_count",
+                &metrics.labels,
+                None,
+                total,
+            );
+        }
+
+        out
+    }
+}
+
+impl BindingMetrics {
+    fn new(labels: &[(&str, &str)]) -> Self {
+        let label_pairs = labels
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<Vec<_>>();
+        let labels = labels
+            .iter()
+            .map(|(key, value)| format!("{}=\"{}\"", key, escape_label_value(value)))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        Self {
+            labels,
+            label_pairs,
+            requests: AtomicU64::new(0),
+            responses: Default::default(),
+            upstream_errors: AtomicU64::new(0),
+            rate_limited: AtomicU64::new(0),
+            active_connections: AtomicI64::new(0),
+            latency_buckets: Default::default(),
+            latency_sum_us: AtomicU64::new(0),
+            latency_count: AtomicU64::new(0),
+            traffic: Arc::new(TrafficCounters::default()),
+        }
+    }
+
+    pub fn record_request(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    pub fn record_response(&self, status: u16, duration: Duration) {
+        let class = (status / 100).clamp(1, 5) as usize - 1;
+        self.responses[class].fetch_add(1, Ordering::Relaxed);
+
+        let seconds = duration.as_secs_f64();
+        if let Some(index) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.latency_buckets[index].fetch_add(1, Ordering::Relaxed);
+        }
+        self.latency_sum_us
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+        self.latency_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_upstream_error(&self) {
+        self.upstream_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_rate_limited(&self) {
+        self.rate_limited.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Bytes proxied for the binding, shared with its machine connections.
+    pub fn traffic(&self) -> Arc<TrafficCounters> {
+        self.traffic.clone()
+    }
+
+    pub fn open_connection(self: &Arc<Self>) -> ConnectionGuard {
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard {
+            metrics: self.clone(),
+        }
+    }
+}
+
+pub(crate) fn render_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+pub(crate) fn render_value(
+    out: &mut String,
+    name: &str,
+    labels: &str,
+    extra_label: Option<(&str, &str)>,
+    value: impl std::fmt::Display,
+) {
+    let _ = match extra_label {
+        Some((key, extra)) => writeln!(
+            out,
+            "{}{{{},{}=\"{}\"}} {}",
+            name,
+            labels,
+            key,
+            escape_label_value(extra),
+            value
+        ),
+        None => writeln!(out, "{}{{{}}} {}", name, labels, value),
+    };
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_is_cumulative() {
+        let metrics = ProxyMetrics::new();
+        let binding = metrics.binding("svc", &[("namespace", "default")]);
+
+        binding.record_request();
+        binding.record_response(200, Duration::from_millis(3));
+        binding.record_response(502, Duration::from_millis(300));
+
+        let rendered = metrics.render();
+        assert!(rendered.contains(
+            "ignition_proxy_request_duration_seconds_bucket{binding=\"svc\",namespace=\"default\",le=\"0.005\"} 1"
+        ));
+        assert!(rendered.contains(
+            "ignition_proxy_request_duration_seconds_bucket{binding=\"svc\",namespace=\"default\",le=\"0.5\"} 2"
+        ));
+        assert!(rendered.contains(
+            "ignition_proxy_responses_total{binding=\"svc\",namespace=\"default\",status_class=\"5xx\"} 1"
+        ));
+    }
+
+    #[test]
+    fn test_connection_guard_decrements() {
+        let metrics = ProxyMetrics::new();
+        let binding = metrics.binding("svc", &[]);
+
+        let guard = binding.open_connection();
+        assert_eq!(binding.active_connections.load(Ordering::Relaxed), 1);
+
+        drop(guard);
+        assert_eq!(binding.active_connections.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,100 @@
+use std::{str::FromStr, sync::Arc, time::Duration};
+
+use anyhow::{Result, bail};
+use axum::http::HeaderValue;
+use http_body_util::BodyExt;
+use hyper::Uri;
+use tokio::{spawn, time::timeout};
+use tracing::{debug, warn};
+
+use crate::agent::{
+    machine::MachineAgent,
+    proxy::{ProxyBinding, buffer::BufferedRequest, get_machine_connection, send_upstream_request},
+};
+
+/// Set on mirrored requests, so the secondary target can avoid side effects.
+pub const MIRROR_HEADER: &str = "x-lttle-mirror";
+const MIRROR_DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug)]
+pub struct MirrorTarget {
+    pub network_tag: String,
+    /// Percentage of the requests copied to the mirror, 0-100.
+    pub percentage: u8,
+}
+
+impl MirrorTarget {
+    /// Whether the next request should be mirrored.
+    pub fn sample(&self) -> bool {
+        rand::random_range(0..100u8) < self.percentage
+    }
+}
+
+/// Sends a copy of the request to the binding's mirror in the background, ignoring the response.
+pub fn mirror_request(
+    machine_agent: &Arc<MachineAgent>,
+    binding: &ProxyBinding,
+    req: &BufferedRequest,
+) {
+    let machine_agent = machine_agent.clone();
+    let binding = binding.clone();
+    let req = req.clone();
+
+    spawn(async move {
+        let limit = binding.timeouts.request.unwrap_or(MIRROR_DEFAULT_TIMEOUT);
+        match timeout(limit, send_mirrored(&machine_agent, &binding, req)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("Failed to mirror request: {}", e),
+            Err(_) => warn!("Mirrored request timed out after {}s", limit.as_secs()),
+        }
+    });
+}
+
+async fn send_mirrored(
+    machine_agent: &Arc<MachineAgent>,
+    binding: &ProxyBinding,
+    req: BufferedRequest,
+) -> Result<()> {
+    let Some(mirror) = binding.mirror.as_ref() else {
+        return Ok(());
+    };
+
+    let machines = machine_agent
+        .get_machines_by_network_tag(&mirror.network_tag)
+        .await;
+    if machines.is_empty() {
+        bail!(
+            "No machine found for mirror network tag {}",
+            mirror.network_tag
+        );
+    }
+    let machine = &machines[rand::random_range(0..machines.len())];
+
+    let connection = get_machine_connection(machine, binding, None).await?;
+
+    let mut req = req.to_request();
+    let path_and_query = req
+        .uri()
+        .path_and_query()
+        .map(|pq| pq.as_str())
+        .unwrap_or("/");
+    let uri = format!(
+        "{}://{}:{}{}",
+        binding.upstream_scheme(),
+        connection.ip_address(),
+        binding.target_port,
+        path_and_query
+    );
+    *req.uri_mut() = Uri::from_str(&uri)?;
+    req.headers_mut()
+        .insert(MIRROR_HEADER, HeaderValue::from_static("1"));
+
+    let response = send_upstream_request(binding, &connection.ip_address(), req).await?;
+    let status = response.status();
+
+    // drain the body so the upstream isn't cut off mid-response
+    response.into_body().collect().await?;
+    debug!("Mirrored request to {} answered {}", uri, status);
+
+    Ok(())
+}
@@ -1,13 +1,48 @@
+pub mod access_log;
+pub mod auth;
+pub mod bandwidth;
+pub mod buffer;
+pub mod circuit_breaker;
+pub mod compression;
+pub mod default_backend;
+pub mod error_pages;
+pub mod firewall;
+pub mod headers;
+pub mod host;
+pub mod http3;
+pub mod metrics;
+pub mod mirror;
+pub mod ocsp;
 pub mod proto;
+pub mod proxy_protocol;
+pub mod rate_limit;
+pub mod replay;
+pub mod retry;
+pub mod sticky;
+pub mod timeouts;
 pub mod tls;
-
-use std::{collections::HashSet, convert::Infallible, str::FromStr, sync::Arc, time::Duration};
+pub mod udp;
+pub mod upstream_tls;
+
+use std::{
+    collections::HashSet,
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
 
 use anyhow::{Result, bail};
 use axum::http::HeaderValue;
 use bytes::Bytes;
-use http_body_util::{BodyExt, Full};
-use hyper::{Method, Request, StatusCode, Uri, Version, service::service_fn, upgrade::Upgraded};
+use http_body_util::{BodyExt, Full, combinators::BoxBody};
+use hyper::{
+    Method, Request, Response, StatusCode, Uri, Version, service::service_fn, upgrade::Upgraded,
+};
 use hyper_util::{
     client::legacy::{Client, connect::HttpConnector},
     rt::{TokioExecutor, TokioIo},
@@ -16,21 +51,48 @@ use hyper_util::{
 use papaya::HashMap;
 use rustls::{ServerConfig, sign::CertifiedKey};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
     net::{TcpListener, TcpStream},
     spawn,
-    task::JoinHandle,
+    task::{JoinHandle, spawn_blocking},
+    time::sleep,
+    try_join,
 };
 use tokio_rustls::TlsAcceptor;
 use tracing::{info, warn};
 
 use crate::agent::{
     certificate::CertificateAgent,
+    logs::LogsAgent,
     machine::{
         MachineAgent,
-        machine::{Machine, TrafficAwareConnection},
+        machine::{Machine, MachineState, TrafficAwareConnection, TrafficCounters},
     },
-    proxy::{proto::SniffedProtocol, tls::ProxyTlsCertResolver},
+    port_allocator::PortConflictReason,
+    proxy::{
+        access_log::PendingAccessLog,
+        auth::{AuthOutcome, ProxyAuth},
+        bandwidth::{BANDWIDTH_FLUSH_INTERVAL, count_sent_bytes, flush_bandwidth_usage},
+        circuit_breaker::CircuitBreaker,
+        compression::ResponseCompression,
+        default_backend::DefaultBackend,
+        error_pages::{ErrorPage, ErrorPages, accepts_html, error_response},
+        firewall::IpFirewall,
+        headers::HeaderRewrite,
+        host::{HostMatch, WILDCARD_LABEL_HEADER, match_host, split_host_port},
+        http3::{ALT_SVC, HTTP3_PORT, Http3Connection, http3_listener, quic_server_config},
+        metrics::{BindingMetrics, ConnectionGuard, ProxyMetrics},
+        mirror::MirrorTarget,
+        proto::SniffedProtocol,
+        rate_limit::{ConnectionPermit, RateLimiter},
+        replay::UpstreamRequest,
+        retry::{CONNECT_ATTEMPTS, RETRY_AFTER_SECS, RetryPolicy, backoff_delay},
+        sticky::{StickySessions, affinity_token},
+        timeouts::{ProxyTimeouts, bounded},
+        tls::ProxyTlsCertResolver,
+        upstream_tls::UpstreamTls,
+    },
+    tracker::TrackerAgent,
 };
 
 #[derive(Debug, Clone)]
@@ -41,6 +103,15 @@ pub struct ProxyAgentConfig {
     pub default_tls_cert_path: String,
     pub default_tls_key_path: String,
     pub blacklisted_seo_domain: String,
+    /// Service receiving HTTP(S) requests for hosts without a binding.
+    pub default_backend_service: Option<ProxyBindingService>,
+    /// Page served for hosts without a binding when there is no default backend service.
+    pub not_found_page_path: Option<String>,
+    /// Also serves HTTPS bindings over HTTP/3, on the UDP side of the HTTPS port.
+    pub http3: bool,
+    /// Address machines reach host routed external bindings on, so traffic between machines
+    /// doesn't go through the external bind address. Set by split-horizon DNS.
+    pub internal_bind_address: Option<String>,
 }
 
 #[allow(unused)]
@@ -53,7 +124,13 @@ pub struct ProxyAgent {
     tls_cert_resolver: Arc<ProxyTlsCertResolver>,
     tls_acceptor: Arc<TlsAcceptor>,
     servers: HashMap<(String, u16), ProxyServer>,
+    udp_servers: HashMap<(String, u16), ProxyServer>,
     certificate_agent: Arc<CertificateAgent>,
+    logs_agent: Arc<LogsAgent>,
+    tracker: Arc<TrackerAgent>,
+    metrics: Arc<ProxyMetrics>,
+    default_backend: Arc<DefaultBackend>,
+    quic_server_config: Option<quinn::ServerConfig>,
 }
 
 #[allow(unused)]
@@ -73,8 +150,179 @@ enum ProxyServerMode {
 pub struct ProxyBinding {
     pub target_network_tag: String,
     pub target_port: u16,
+    pub transport: ProxyTransport,
     pub mode: BindingMode,
     pub inactivity_timeout: Option<Duration>,
+    pub timeouts: ProxyTimeouts,
+    pub upstream_protocol: UpstreamProtocol,
+    pub balancing: ProxyBalancing,
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    /// Ejects machines of the binding that keep failing.
+    pub circuit_breaker: Option<Arc<CircuitBreaker>>,
+    /// Restricts the client addresses allowed to reach the binding.
+    pub firewall: Option<Arc<IpFirewall>>,
+    /// Authentication required before HTTP requests are proxied.
+    pub auth: Option<Arc<ProxyAuth>>,
+    /// Compresses HTTP responses the upstream sent uncompressed.
+    pub compression: Option<ResponseCompression>,
+    /// Keeps HTTP clients on the machine that served their first request.
+    pub sticky_sessions: Option<StickySessions>,
+    /// Header rules for HTTP requests sent upstream and the responses sent back.
+    pub headers: Option<Arc<HeaderRewrite>>,
+    /// Answers HTTP requests with the maintenance page instead of proxying them.
+    pub maintenance: bool,
+    /// HTML pages replacing the builtin 502 and 503 pages.
+    pub error_pages: Option<Arc<ErrorPages>>,
+    /// Retries idempotent HTTP requests that failed upstream.
+    pub retry_policy: Option<Arc<RetryPolicy>>,
+    /// Copies a share of the HTTP requests to a secondary target.
+    pub mirror: Option<MirrorTarget>,
+    /// Prepend a PROXY protocol v2 header to upstream TCP streams.
+    pub proxy_protocol: bool,
+    /// Connect to HTTP upstreams over TLS.
+    pub upstream_tls: Option<Arc<UpstreamTls>>,
+    /// Host header sent upstream, defaults to the binding host over HTTP and the client host
+    /// over HTTPS.
+    pub host_header: Option<UpstreamHostHeader>,
+    /// The service owning the binding, used to attribute access logs.
+    pub service: Option<ProxyBindingService>,
+    /// Assigned by the agent when the binding is set.
+    pub metrics: Option<Arc<BindingMetrics>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ProxyBindingService {
+    pub tenant: String,
+    pub namespace: String,
+    pub name: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum UpstreamProtocol {
+    #[default]
+    Http1,
+    /// HTTP/2 with prior knowledge over cleartext
+    H2c,
+}
+
+impl UpstreamProtocol {
+    fn version(&self) -> Version {
+        match self {
+            UpstreamProtocol::Http1 => Version::HTTP_11,
+            UpstreamProtocol::H2c => Version::HTTP_2,
+        }
+    }
+
+    fn client(
+        &self,
+        connect_timeout: Option<Duration>,
+    ) -> Client<HttpConnector, BoxBody<Bytes, hyper::Error>> {
+        let mut base = HttpConnector::new();
+        base.enforce_http(true);
+        base.set_connect_timeout(connect_timeout);
+
+        let mut builder = Client::builder(TokioExecutor::new());
+        if *self == UpstreamProtocol::H2c {
+            builder.http2_only(true);
+        }
+
+        builder.build(base)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ProxyTransport {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UpstreamHostHeader {
+    /// The host the client requested.
+    Preserve,
+    /// The host of the binding, with any wildcard filled in.
+    Binding,
+    Custom(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct WeightedTarget {
+    pub network_tag: String,
+    pub weight: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct CanaryTarget {
+    pub network_tag: String,
+    pub percentage: u8,
+}
+
+/// Spreads the traffic of a binding across network tags and the machines sharing them.
+#[derive(Clone, Debug, Default)]
+pub struct ProxyBalancing {
+    /// When empty, all traffic goes to the binding's `target_network_tag`.
+    pub weighted_targets: Vec<WeightedTarget>,
+    pub canary: Option<CanaryTarget>,
+    cursor: Arc<AtomicU64>,
+}
+
+impl ProxyBalancing {
+    pub fn new(weighted_targets: Vec<WeightedTarget>, canary: Option<CanaryTarget>) -> Self {
+        Self {
+            weighted_targets,
+            canary,
+            cursor: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Every network tag the binding may send traffic to.
+    fn network_tags<'a>(&'a self, primary_network_tag: &'a str) -> Vec<&'a str> {
+        let mut network_tags = vec![primary_network_tag];
+        for target in self.weighted_targets.iter() {
+            if !network_tags.contains(&target.network_tag.as_str()) {
+                network_tags.push(&target.network_tag);
+            }
+        }
+        if let Some(canary) = &self.canary {
+            if !network_tags.contains(&canary.network_tag.as_str()) {
+                network_tags.push(&canary.network_tag);
+            }
+        }
+
+        network_tags
+    }
+
+    /// Picks the network tag for the next request, along with the rotation index to use
+    /// between the machines sharing that tag.
+    fn next<'a>(&'a self, primary_network_tag: &'a str) -> (&'a str, usize) {
+        let n = self.cursor.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(canary) = &self.canary {
+            if rand::random_range(0..100u8) < canary.percentage {
+                return (&canary.network_tag, n as usize);
+            }
+        }
+
+        let total_weight = self
+            .weighted_targets
+            .iter()
+            .map(|target| target.weight as u64)
+            .sum::<u64>();
+        if total_weight == 0 {
+            return (primary_network_tag, n as usize);
+        }
+
+        let mut slot = n % total_weight;
+        for target in self.weighted_targets.iter() {
+            if slot < target.weight as u64 {
+                return (&target.network_tag, (n / total_weight) as usize);
+            }
+            slot -= target.weight as u64;
+        }
+
+        (primary_network_tag, n as usize)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -94,13 +342,27 @@ pub enum ExternalBindingRouting {
     HttpHostHeader {
         host: String,
     },
+    /// HTTP routing on host and path prefix, so several bindings can share one host. With `tls`
+    /// the connection is terminated like a `TlsSni` binding with a nested HTTP protocol.
+    HttpHostPath {
+        host: String,
+        path_prefix: String,
+        tls: bool,
+    },
     TlsSni {
         host: String,
         nested_protocol: ExternnalBindingRoutingTlsNestedProtocol,
     },
+    /// Routes on the SNI of the ClientHello and forwards the raw TLS stream to the machine.
+    TlsPassthrough {
+        host: String,
+    },
     TcpDirect {
         port: u16,
     },
+    UdpDirect {
+        port: u16,
+    },
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -120,14 +382,47 @@ impl ProxyBinding {
         }
     }
 
+    /// Server machines reach a host routed external binding on, when the external server doesn't
+    /// already accept connections on the internal bind address.
+    pub fn internal_server_key(&self, config: &ProxyAgentConfig) -> Option<(String, u16)> {
+        let internal_bind_address = config.internal_bind_address.as_ref()?;
+        let BindingMode::External { port, routing } = &self.mode else {
+            return None;
+        };
+
+        if self.transport != ProxyTransport::Tcp
+            || matches!(
+                routing,
+                ExternalBindingRouting::TcpDirect { .. } | ExternalBindingRouting::UdpDirect { .. }
+            )
+        {
+            return None;
+        }
+
+        let wildcard_external_bind = config
+            .external_bind_address
+            .parse::<IpAddr>()
+            .is_ok_and(|address| address.is_unspecified());
+        if wildcard_external_bind || *internal_bind_address == config.external_bind_address {
+            return None;
+        }
+
+        Some((internal_bind_address.clone(), *port))
+    }
+
     pub fn public_host(&self) -> Option<String> {
         let host = match &self.mode {
             BindingMode::External { routing, port } => match routing {
                 ExternalBindingRouting::HttpHostHeader { host } => Some((host.clone(), *port)),
+                ExternalBindingRouting::HttpHostPath { host, .. } => Some((host.clone(), *port)),
                 ExternalBindingRouting::TlsSni { host, .. } => Some((host.clone(), *port)),
+                ExternalBindingRouting::TlsPassthrough { host } => Some((host.clone(), *port)),
                 ExternalBindingRouting::TcpDirect { port } => {
                     Some((format!("tcp:{}", port), *port))
                 }
+                ExternalBindingRouting::UdpDirect { port } => {
+                    Some((format!("udp:{}", port), *port))
+                }
             },
             _ => None,
         };
@@ -142,6 +437,67 @@ impl ProxyBinding {
 
         None
     }
+
+    /// The Host header to send upstream, `None` leaves the client's header untouched.
+    fn upstream_host(&self, requested_host: &str, default: UpstreamHostHeader) -> Option<String> {
+        match self.host_header.clone().unwrap_or(default) {
+            UpstreamHostHeader::Preserve => Some(requested_host.to_string()),
+            // the default backend sees the host that was originally requested
+            UpstreamHostHeader::Binding => self
+                .match_host(requested_host)
+                .and(self.public_host_for(requested_host)),
+            UpstreamHostHeader::Custom(host) => Some(host),
+        }
+    }
+
+    fn upstream_scheme(&self) -> &'static str {
+        match self.upstream_tls {
+            Some(_) => "https",
+            None => "http",
+        }
+    }
+
+    /// Like `public_host`, with the wildcard label filled in from the requested host.
+    pub fn public_host_for(&self, requested_host: &str) -> Option<String> {
+        let public_host = self.public_host()?;
+
+        match self.match_host(requested_host) {
+            Some(HostMatch::Wildcard(label)) => Some(public_host.replacen('*', &label, 1)),
+            _ => Some(public_host),
+        }
+    }
+
+    /// Host the binding routes by, a pattern for wildcard bindings.
+    fn routed_host(&self) -> Option<&str> {
+        let BindingMode::External { routing, .. } = &self.mode else {
+            return None;
+        };
+
+        match routing {
+            ExternalBindingRouting::HttpHostHeader { host } => Some(host),
+            ExternalBindingRouting::HttpHostPath { host, .. } => Some(host),
+            ExternalBindingRouting::TlsSni { host, .. } => Some(host),
+            ExternalBindingRouting::TlsPassthrough { host } => Some(host),
+            ExternalBindingRouting::TcpDirect { .. } | ExternalBindingRouting::UdpDirect { .. } => {
+                None
+            }
+        }
+    }
+
+    /// Matches a requested host (optionally with a port) against the binding host.
+    pub fn match_host(&self, requested_host: &str) -> Option<HostMatch> {
+        let BindingMode::External { port, .. } = &self.mode else {
+            return None;
+        };
+        let host = self.routed_host()?;
+
+        let (requested_host, requested_port) = split_host_port(requested_host);
+        if requested_port.is_some_and(|requested_port| requested_port != *port) {
+            return None;
+        }
+
+        match_host(host, requested_host)
+    }
 }
 
 impl ProxyAgent {
@@ -149,6 +505,8 @@ impl ProxyAgent {
         config: ProxyAgentConfig,
         machine_agent: Arc<MachineAgent>,
         certificate_agent: Arc<CertificateAgent>,
+        logs_agent: Arc<LogsAgent>,
+        tracker: Arc<TrackerAgent>,
     ) -> Result<Arc<Self>> {
         info!(
             "Creating new proxy agent with external bind address: {}",
@@ -168,8 +526,10 @@ impl ProxyAgent {
             Arc::new(HashMap::new()),
             default_cert.clone(),
             tls_server_config_builder.crypto_provider().clone(),
-            std::path::PathBuf::from(certificate_agent.config().certs_base_dir.clone()),
+            certificate_agent.clone(),
         ));
+        tls_cert_resolver.clone().watch_certs();
+        tls_cert_resolver.clone().watch_ocsp();
 
         let mut tls_server_config =
             tls_server_config_builder.with_cert_resolver(tls_cert_resolver.clone());
@@ -179,16 +539,36 @@ impl ProxyAgent {
 
         let tls_acceptor = Arc::new(TlsAcceptor::from(Arc::new(tls_server_config)));
 
+        let quic_server_config = config
+            .http3
+            .then(|| quic_server_config(tls_cert_resolver.clone()))
+            .transpose()?;
+
+        let not_found_page = match &config.not_found_page_path {
+            Some(path) => Some(Bytes::from(tokio::fs::read(path).await?)),
+            None => None,
+        };
+        let default_backend = Arc::new(DefaultBackend::new(
+            config.default_backend_service.clone(),
+            not_found_page,
+        ));
+
         let agent = Arc::new(Self {
             config: config.clone(),
             machine_agent,
             bindings: Arc::new(HashMap::new()),
             servers: HashMap::new(),
+            udp_servers: HashMap::new(),
             cert_pool: Arc::new(HashMap::new()),
             default_cert,
             tls_cert_resolver,
             tls_acceptor,
             certificate_agent,
+            logs_agent,
+            tracker,
+            metrics: Arc::new(ProxyMetrics::new()),
+            default_backend,
+            quic_server_config,
         });
 
         for port in config.evergreen_external_ports {
@@ -197,6 +577,7 @@ impl ProxyAgent {
                 &ProxyBinding {
                     target_network_tag: format!("internal-evergreen-{}", port),
                     target_port: port,
+                    transport: ProxyTransport::Tcp,
                     mode: BindingMode::External {
                         port,
                         routing: ExternalBindingRouting::HttpHostHeader {
@@ -204,12 +585,32 @@ impl ProxyAgent {
                         },
                     },
                     inactivity_timeout: None,
+                    timeouts: ProxyTimeouts::default(),
+                    upstream_protocol: UpstreamProtocol::Http1,
+                    balancing: ProxyBalancing::default(),
+                    rate_limiter: None,
+                    circuit_breaker: None,
+                    firewall: None,
+                    auth: None,
+                    compression: None,
+                    sticky_sessions: None,
+                    headers: None,
+                    maintenance: false,
+                    error_pages: None,
+                    retry_policy: None,
+                    mirror: None,
+                    proxy_protocol: false,
+                    upstream_tls: None,
+                    host_header: None,
+                    service: None,
+                    metrics: None,
                 },
                 (config.external_bind_address.clone(), port).into(),
             );
         }
 
         agent.evaluate_bindings().await?;
+        agent.clone().start_bandwidth_usage_flush();
 
         info!("Proxy agent created successfully");
         Ok(agent)
@@ -219,7 +620,64 @@ impl ProxyAgent {
         &self.config
     }
 
-    pub async fn set_binding(&self, binding_name: &str, binding: ProxyBinding) -> Result<()> {
+    pub fn metrics(&self) -> Arc<ProxyMetrics> {
+        self.metrics.clone()
+    }
+
+    /// Periodically adds the bytes proxied for each service to its stored bandwidth usage.
+    fn start_bandwidth_usage_flush(self: Arc<Self>) {
+        spawn(async move {
+            loop {
+                sleep(BANDWIDTH_FLUSH_INTERVAL).await;
+
+                for (_, binding) in self.list_bindings() {
+                    flush_bandwidth_usage(&self.tracker, &binding).await;
+                }
+            }
+        });
+    }
+
+    /// Whether an external binding is routed on `host`, ie. the proxy answers for it.
+    pub fn serves_host(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.');
+        self.bindings
+            .pin()
+            .iter()
+            .any(|(_, binding)| binding.match_host(host).is_some())
+    }
+
+    /// Whether a binding serves a host below `host`, which then exists in DNS even without
+    /// records of its own.
+    pub fn serves_host_below(&self, host: &str) -> bool {
+        let suffix = format!(".{}", host.trim_end_matches('.').to_ascii_lowercase());
+        self.bindings.pin().iter().any(|(_, binding)| {
+            binding
+                .routed_host()
+                .is_some_and(|routed| routed.to_ascii_lowercase().ends_with(&suffix))
+        })
+    }
+
+    pub fn list_bindings(&self) -> Vec<(String, ProxyBinding)> {
+        self.bindings
+            .pin()
+            .iter()
+            .map(|(name, binding)| (name.clone(), binding.clone()))
+            .collect()
+    }
+
+    /// Requests received so far by the bindings targeting `network_tag`, for computing request
+    /// rates. The count restarts from zero with the daemon.
+    pub fn request_count_for_network_tag(&self, network_tag: &str) -> u64 {
+        self.bindings
+            .pin()
+            .iter()
+            .filter(|(_, binding)| binding.target_network_tag == network_tag)
+            .filter_map(|(_, binding)| binding.metrics.as_ref())
+            .map(|metrics| metrics.requests())
+            .sum()
+    }
+
+    pub async fn set_binding(&self, binding_name: &str, mut binding: ProxyBinding) -> Result<()> {
         info!(
             "Setting binding '{}' with target network tag: {}",
             binding_name, binding.target_network_tag
@@ -227,6 +685,50 @@ impl ProxyAgent {
 
         let bindings = self.bindings.pin();
         let previous_binding = bindings.remove(binding_name);
+
+        // keep the limiter state across re-applies of the same limits
+        if let (Some(previous_limiter), Some(limiter)) = (
+            previous_binding.and_then(|b| b.rate_limiter.as_ref()),
+            &binding.rate_limiter,
+        ) {
+            if previous_limiter.config() == limiter.config() {
+                binding.rate_limiter = Some(previous_limiter.clone());
+            }
+        }
+
+        // ejected machines stay ejected across re-applies of the same settings
+        if let (Some(previous_breaker), Some(breaker)) = (
+            previous_binding.and_then(|b| b.circuit_breaker.as_ref()),
+            &binding.circuit_breaker,
+        ) {
+            if previous_breaker.config() == breaker.config() {
+                binding.circuit_breaker = Some(previous_breaker.clone());
+            }
+        }
+
+        // and so does the retry budget
+        if let (Some(previous_policy), Some(policy)) = (
+            previous_binding.and_then(|b| b.retry_policy.as_ref()),
+            &binding.retry_policy,
+        ) {
+            if previous_policy.config() == policy.config() {
+                binding.retry_policy = Some(previous_policy.clone());
+            }
+        }
+
+        let metric_labels = binding
+            .service
+            .as_ref()
+            .map(|service| {
+                vec![
+                    ("tenant", service.tenant.as_str()),
+                    ("namespace", service.namespace.as_str()),
+                    ("service", service.name.as_str()),
+                ]
+            })
+            .unwrap_or_default();
+        binding.metrics = Some(self.metrics.binding(binding_name, &metric_labels));
+
         bindings.insert(binding_name.to_string(), binding);
 
         if let Err(e) = self.evaluate_bindings().await {
@@ -259,6 +761,12 @@ impl ProxyAgent {
             return Err(e);
         };
 
+        // the counters go away with the binding, store what they hold first
+        if let Some(previous_binding) = previous_binding {
+            flush_bandwidth_usage(&self.tracker, previous_binding).await;
+        }
+        self.metrics.remove_binding(binding_name);
+
         info!("Successfully removed binding '{}'", binding_name);
         Ok(())
     }
@@ -281,6 +789,7 @@ impl ProxyAgent {
             .collect::<Vec<(String, u16)>>();
 
         let mut server_keys_set = HashSet::new();
+        let mut udp_server_keys_set = HashSet::new();
         let bindings = self.bindings.pin();
 
         for (_, binding) in bindings.iter() {
@@ -293,7 +802,27 @@ impl ProxyAgent {
                 continue;
             }
 
-            server_keys_set.insert(server_key);
+            match binding.transport {
+                ProxyTransport::Tcp => server_keys_set.insert(server_key),
+                ProxyTransport::Udp => udp_server_keys_set.insert(server_key),
+            };
+
+            if let Some(internal_server_key) = binding.internal_server_key(&self.config) {
+                server_keys_set.insert(internal_server_key);
+            }
+        }
+
+        let udp_servers = self.udp_servers.pin();
+        for (server_key, _) in udp_servers.iter() {
+            if !udp_server_keys_set.contains(server_key) {
+                info!(
+                    "Stopping UDP server for {}:{} (no longer needed)",
+                    server_key.0, server_key.1
+                );
+                if let Some(server) = udp_servers.remove(server_key) {
+                    server.task.abort();
+                }
+            }
         }
 
         let servers = self.servers.pin();
@@ -313,9 +842,24 @@ impl ProxyAgent {
 
         for (_, binding) in bindings.iter() {
             let server_key: (String, u16) = binding.proxy_server_key(&self.config);
-            if !servers.contains_key(&server_key) && !blacklisted_server_keys.contains(&server_key)
-            {
-                self.start_server(binding, server_key);
+            if blacklisted_server_keys.contains(&server_key) {
+                continue;
+            }
+
+            match binding.transport {
+                ProxyTransport::Tcp if !servers.contains_key(&server_key) => {
+                    self.start_server(binding, server_key);
+                }
+                ProxyTransport::Udp if !udp_servers.contains_key(&server_key) => {
+                    self.start_udp_server(binding, server_key);
+                }
+                _ => {}
+            }
+
+            if let Some(internal_server_key) = binding.internal_server_key(&self.config) {
+                if !servers.contains_key(&internal_server_key) {
+                    self.start_server(binding, internal_server_key);
+                }
             }
         }
 
@@ -323,11 +867,111 @@ impl ProxyAgent {
         Ok(())
     }
 
+    /// Checks whether an external binding on `port` could be served, without starting a server.
+    pub async fn check_external_port(&self, port: u16) -> Option<PortConflictReason> {
+        if self.config.blacklisted_external_ports.contains(&port) {
+            return Some(PortConflictReason::Blacklisted);
+        }
+
+        let server_key = (self.config.external_bind_address.clone(), port);
+        if self.servers.pin().contains_key(&server_key)
+            || self.config.evergreen_external_ports.contains(&port)
+        {
+            return None;
+        }
+
+        // nothing of ours listens there yet, make sure the bind won't fail later
+        let address = self.config.external_bind_address.clone();
+        let bound =
+            spawn_blocking(move || std::net::TcpListener::bind((address.as_str(), port)).is_ok())
+                .await;
+        match bound {
+            Ok(true) => None,
+            _ => Some(PortConflictReason::InUseOnHost),
+        }
+    }
+
+    /// Like `check_external_port`, for UDP bindings.
+    pub async fn check_external_udp_port(&self, port: u16) -> Option<PortConflictReason> {
+        if self.config.blacklisted_external_ports.contains(&port) {
+            return Some(PortConflictReason::Blacklisted);
+        }
+
+        // taken by the HTTP/3 listener
+        if self.quic_server_config.is_some() && port == HTTP3_PORT {
+            return Some(PortConflictReason::InUseOnHost);
+        }
+
+        let server_key = (self.config.external_bind_address.clone(), port);
+        if self.udp_servers.pin().contains_key(&server_key) {
+            return None;
+        }
+
+        match tokio::net::UdpSocket::bind((self.config.external_bind_address.as_str(), port)).await
+        {
+            Ok(_) => None,
+            Err(_) => Some(PortConflictReason::InUseOnHost),
+        }
+    }
+
+    pub fn external_ports(&self) -> Vec<u16> {
+        let mut ports = self.config.evergreen_external_ports.clone();
+
+        for (server_key, server) in self.servers.pin().iter() {
+            if matches!(server.proxy_mode, ProxyServerMode::External)
+                && server_key.0 == self.config.external_bind_address
+                && !ports.contains(&server_key.1)
+            {
+                ports.push(server_key.1);
+            }
+        }
+
+        ports
+    }
+
     pub fn invalidate_cert_cache_for_domains(&self, domains: Vec<String>) {
         self.tls_cert_resolver
             .invalidate_cert_cache_for_domains(domains);
     }
 
+    fn start_udp_server(&self, binding: &ProxyBinding, server_key: (String, u16)) {
+        info!(
+            "Starting new UDP server for {}:{}",
+            server_key.0, server_key.1
+        );
+
+        let proxy_mode = match &binding.mode {
+            BindingMode::Internal { .. } => ProxyServerMode::Internal,
+            BindingMode::External { .. } => ProxyServerMode::External,
+        };
+
+        let task_server_key = server_key.clone();
+        let task_config = self.config.clone();
+        let task_machine_agent = self.machine_agent.clone();
+        let task_bindings = self.bindings.clone();
+
+        let task = spawn(async move {
+            udp::udp_listener(
+                task_server_key,
+                task_config,
+                task_machine_agent,
+                task_bindings,
+            )
+            .await?;
+
+            Ok(())
+        });
+
+        let server = ProxyServer {
+            address: server_key.0.clone(),
+            port: server_key.1,
+            task,
+            proxy_mode,
+        };
+
+        self.udp_servers.pin().insert(server_key, server);
+    }
+
     fn start_server(&self, binding: &ProxyBinding, server_key: (String, u16)) {
         let servers = self.servers.pin();
 
@@ -344,7 +988,13 @@ impl ProxyAgent {
         let task_tls_acceptor = self.tls_acceptor.clone();
         let task_binding = binding.clone();
         let task_certificate_agent = self.certificate_agent.clone();
+        let task_logs_agent = self.logs_agent.clone();
+        let task_default_backend = self.default_backend.clone();
         let task_blacklisted_seo_domain = self.config.blacklisted_seo_domain.clone();
+        let task_quic_server_config = self
+            .quic_server_config
+            .clone()
+            .filter(|_| server_key.1 == HTTP3_PORT);
 
         let task = match proxy_mode {
             ProxyServerMode::Internal => spawn(async move {
@@ -380,15 +1030,41 @@ impl ProxyAgent {
                     })
                 } else {
                     spawn(async move {
-                        external_listener(
-                            format!("{}:{}", task_server_key.0, task_server_key.1),
-                            task_machine_agent,
-                            task_bindings,
-                            task_blacklisted_seo_domain,
+                        let addr = format!("{}:{}", task_server_key.0, task_server_key.1);
+                        let alt_svc = task_quic_server_config
+                            .as_ref()
+                            .map(|_| HeaderValue::from_static(ALT_SVC));
+
+                        let listener = external_listener(
+                            addr.clone(),
+                            task_machine_agent.clone(),
+                            task_bindings.clone(),
+                            task_blacklisted_seo_domain.clone(),
                             task_tls_acceptor,
                             task_certificate_agent,
-                        )
-                        .await?;
+                            task_logs_agent.clone(),
+                            task_default_backend.clone(),
+                            alt_svc.clone(),
+                        );
+
+                        let Some(quic_server_config) = task_quic_server_config else {
+                            listener.await?;
+                            return Ok(());
+                        };
+
+                        // the QUIC listener lives and dies with the TCP one of the same port
+                        let http3 = http3_listener(addr, quic_server_config, move |connection| {
+                            handle_http3_connection(
+                                connection,
+                                task_bindings.clone(),
+                                task_blacklisted_seo_domain.clone(),
+                                task_machine_agent.clone(),
+                                task_logs_agent.clone(),
+                                task_default_backend.clone(),
+                                alt_svc.clone(),
+                            )
+                        });
+                        try_join!(listener, http3)?;
 
                         Ok(())
                     })
@@ -410,6 +1086,7 @@ impl ProxyAgent {
 async fn proxy_websocket_upgrade(
     client_upgrade: Result<Upgraded, hyper::Error>,
     upstream_upgrade: Result<Upgraded, hyper::Error>,
+    traffic: Option<Arc<TrafficCounters>>,
 ) -> Result<()> {
     let mut client = match client_upgrade {
         Ok(upgraded) => TokioIo::new(upgraded),
@@ -430,6 +1107,10 @@ async fn proxy_websocket_upgrade(
     // Bidirectionally copy data between client and upstream
     match tokio::io::copy_bidirectional(&mut client, &mut upstream).await {
         Ok((client_to_upstream, upstream_to_client)) => {
+            if let Some(traffic) = traffic {
+                traffic.record_in(client_to_upstream);
+                traffic.record_out(upstream_to_client);
+            }
             info!(
                 "WebSocket connection closed. Bytes transferred - client->upstream: {}, upstream->client: {}",
                 client_to_upstream, upstream_to_client
@@ -450,6 +1131,9 @@ async fn external_listener(
     blacklisted_seo_domain: String,
     tls_acceptor: Arc<TlsAcceptor>,
     certificate_agent: Arc<CertificateAgent>,
+    logs_agent: Arc<LogsAgent>,
+    default_backend: Arc<DefaultBackend>,
+    alt_svc: Option<HeaderValue>,
 ) -> Result<Infallible> {
     info!("Starting external listener on {}", addr);
     let listener = TcpListener::bind(addr).await?;
@@ -460,7 +1144,10 @@ async fn external_listener(
         let machine_agent = machine_agent.clone();
         let tls_acceptor = tls_acceptor.clone();
         let certificate_agent = certificate_agent.clone();
+        let logs_agent = logs_agent.clone();
+        let default_backend = default_backend.clone();
         let blacklisted_seo_domain = blacklisted_seo_domain.clone();
+        let alt_svc = alt_svc.clone();
 
         spawn(async move {
             handle_external_connection(
@@ -470,6 +1157,9 @@ async fn external_listener(
                 blacklisted_seo_domain,
                 tls_acceptor,
                 certificate_agent,
+                logs_agent,
+                default_backend,
+                alt_svc,
             )
             .await
         });
@@ -483,6 +1173,9 @@ async fn handle_external_connection(
     blacklisted_seo_domain: String,
     tls_acceptor: Arc<TlsAcceptor>,
     certificate_agent: Arc<CertificateAgent>,
+    logs_agent: Arc<LogsAgent>,
+    default_backend: Arc<DefaultBackend>,
+    alt_svc: Option<HeaderValue>,
 ) -> Result<()> {
     let protocol = proto::sniff_protocol(&mut stream).await?;
 
@@ -499,6 +1192,8 @@ async fn handle_external_connection(
                 blacklisted_seo_domain,
                 machine_agent,
                 certificate_agent,
+                logs_agent,
+                default_backend,
             )
             .await
         }
@@ -510,13 +1205,38 @@ async fn handle_external_connection(
                 bindings,
                 blacklisted_seo_domain,
                 machine_agent,
+                logs_agent,
+                default_backend,
+                alt_svc,
             )
             .await
         }
         SniffedProtocol::Tls => {
+            if let Some(server_name) = proto::peek_sni(&stream).await? {
+                if let Some(binding) = find_tls_passthrough_binding(&bindings, &server_name) {
+                    info!("Passing through TLS connection for {}", server_name);
+                    return handle_tls_passthrough_connection(
+                        stream,
+                        binding,
+                        machine_agent,
+                        server_name,
+                    )
+                    .await;
+                }
+            }
+
             info!("Handling TLS connection");
             let tls_stream = tls_acceptor.accept(stream).await?;
-            handle_tls_connection(tls_stream, bindings, blacklisted_seo_domain, machine_agent).await
+            handle_tls_connection(
+                tls_stream,
+                bindings,
+                blacklisted_seo_domain,
+                machine_agent,
+                logs_agent,
+                default_backend,
+                alt_svc,
+            )
+            .await
         }
     }
 }
@@ -527,6 +1247,8 @@ async fn handle_http_connection(
     blacklisted_seo_domain: String,
     machine_agent: Arc<MachineAgent>,
     certificate_agent: Arc<CertificateAgent>,
+    logs_agent: Arc<LogsAgent>,
+    default_backend: Arc<DefaultBackend>,
 ) -> Result<()> {
     let client_ip = stream.peer_addr().ok();
 
@@ -537,13 +1259,12 @@ async fn handle_http_connection(
         let bindings = bindings.clone();
         let blacklisted_seo_domain = blacklisted_seo_domain.clone();
         let certificate_agent = certificate_agent.clone();
-
-        let mut base = HttpConnector::new();
-        base.enforce_http(true);
-
-        let client = Client::builder(TokioExecutor::new()).build(base);
+        let logs_agent = logs_agent.clone();
+        let default_backend = default_backend.clone();
 
         async move {
+            let started_at = Instant::now();
+
             // Check if this is a WebSocket upgrade request
             let is_websocket_upgrade = req
                 .headers()
@@ -558,11 +1279,12 @@ async fn handle_http_connection(
             } else {
                 None
             };
-            // Extract host from request headers
+            // Extract host from request headers (h2c requests carry it in :authority instead)
             let target_host = req
                 .headers()
                 .get("host")
                 .and_then(|h| h.to_str().ok())
+                .or_else(|| req.uri().authority().map(|a| a.as_str()))
                 .unwrap_or_default()
                 .to_string();
 
@@ -580,69 +1302,138 @@ async fn handle_http_connection(
                 }
             }
 
-            let Ok(binding) = find_http_binding(&bindings, &target_host) else {
-                if let Ok((binding, _)) = find_tls_binding(&bindings, &target_host) {
-                    if req.method() != Method::GET {
-                        return Err("only GET requests are allowed to be redirected to HTTPS");
+            let path = req.uri().path().to_string();
+            let binding = match find_http_binding(&bindings, &target_host, &path, false) {
+                Ok(binding) => binding,
+                Err(_) => {
+                    if let Ok((binding, _)) = find_tls_binding(&bindings, &target_host) {
+                        if req.method() != Method::GET {
+                            return Err("only GET requests are allowed to be redirected to HTTPS");
+                        }
+
+                        let Some(public_host) = binding.public_host_for(&target_host) else {
+                            return Err("no public host found for binding");
+                        };
+
+                        let path = req.uri().path();
+                        let query = req
+                            .uri()
+                            .query()
+                            .and_then(|q| Some(format!("?{}", q)))
+                            .unwrap_or_default();
+
+                        let new_uri = format!("https://{}{}{}", public_host, path, query);
+
+                        let Ok(location) = HeaderValue::from_str(&new_uri) else {
+                            return Err("failed to parse location");
+                        };
+
+                        let mut response = hyper::Response::new(
+                            Full::new(Bytes::from(vec![]))
+                                .map_err(|never| match never {})
+                                .boxed(),
+                        );
+                        *response.status_mut() = StatusCode::TEMPORARY_REDIRECT;
+                        response.headers_mut().insert("location", location);
+
+                        return Ok(response);
                     }
 
-                    let Some(public_host) = binding.public_host() else {
-                        return Err("no public host found for binding");
+                    let Some(binding) = default_backend.binding(&bindings) else {
+                        return Ok(default_backend.not_found());
                     };
 
-                    let path = req.uri().path();
-                    let query = req
-                        .uri()
-                        .query()
-                        .and_then(|q| Some(format!("?{}", q)))
-                        .unwrap_or_default();
+                    binding
+                }
+            };
 
-                    let new_uri = format!("https://{}{}{}", public_host, path, query);
+            let access_log = PendingAccessLog::new(started_at, &target_host, &req, client_ip);
+            record_request(&binding);
 
-                    let Ok(location) = HeaderValue::from_str(&new_uri) else {
-                        return Err("failed to parse location");
-                    };
+            if !firewall_allows(&binding, client_ip) {
+                let response = forbidden();
+                record_response(&binding, started_at, &response);
+                access_log.finish(&logs_agent, &binding, None, &response);
+                return Ok(response);
+            }
 
-                    let mut response = hyper::Response::new(
-                        Full::new(Bytes::from(vec![]))
-                            .map_err(|never| match never {})
-                            .boxed(),
-                    );
-                    *response.status_mut() = StatusCode::TEMPORARY_REDIRECT;
-                    response.headers_mut().insert("location", location);
+            let html = accepts_html(req.headers());
+            if binding.maintenance {
+                let response =
+                    error_response(ErrorPage::Maintenance, binding.error_pages.as_deref(), html);
+                record_response(&binding, started_at, &response);
+                access_log.finish(&logs_agent, &binding, None, &response);
+                return Ok(response);
+            }
 
+            // plain HTTP connections aren't tied to a binding, so in-flight requests count instead
+            let connection = open_connection(&binding);
+            let permit = match binding.rate_limiter.as_ref() {
+                Some(limiter) => match limiter.try_acquire_connection() {
+                    Some(permit) if limiter.try_acquire_request() => Some(permit),
+                    _ => {
+                        let response = too_many_requests();
+                        record_rate_limited(&binding, started_at, &response);
+                        access_log.finish(&logs_agent, &binding, None, &response);
+                        return Ok(response);
+                    }
+                },
+                None => None,
+            };
+
+            if let Some(response) =
+                authorize(&binding, &mut req, &target_host, "http", client_ip).await
+            {
+                record_response(&binding, started_at, &response);
+                access_log.finish(&logs_agent, &binding, None, &response);
+                return Ok(response);
+            }
+
+            let affinity = binding
+                .sticky_sessions
+                .as_ref()
+                .and_then(|sticky| sticky.affinity(req.headers()));
+            let machine = match find_machine(&machine_agent, &binding, affinity.as_deref()).await {
+                Ok(machine) => machine,
+                Err(e) => {
+                    warn!("Failed to find machine: {}", e);
+                    let response = service_unavailable(&binding, started_at, html);
+                    access_log.finish(&logs_agent, &binding, None, &response);
                     return Ok(response);
                 }
-
-                return Err("failed to find binding for HTTP host");
             };
 
-            let Ok(machine) = find_machine(&machine_agent, &binding.target_network_tag).await
-            else {
-                return Err("failed to find machine");
-            };
+            // a machine woken up by this request may take a moment to accept it
+            let woken = machine.get_state().await != MachineState::Ready;
 
-            let machine_connection = match get_machine_connection(
-                &machine,
-                binding.target_port,
-                binding.inactivity_timeout,
+            let machine_connection = match bounded(
+                binding.timeouts.request_remaining(started_at),
+                get_machine_connection(&machine, &binding, binding.inactivity_timeout),
             )
             .await
             {
-                Ok(conn) => conn,
-                Err(e) => {
+                Some(Ok(conn)) => conn,
+                Some(Err(e)) => {
                     warn!(
                         "Failed to establish connection to machine service {}:{}: {}",
                         machine.config.network.ip_address, binding.target_port, e
                     );
-                    return Err(
-                        "failed to connect to machine service - service may be starting up",
-                    );
+                    record_circuit_outcome(&binding, &machine, false);
+                    let response = service_unavailable(&binding, started_at, html);
+                    access_log.finish(&logs_agent, &binding, Some(&machine.config.name), &response);
+                    return Ok(response);
                 }
-            };
-
+                None => {
+                    record_circuit_outcome(&binding, &machine, false);
+                    let response = gateway_timeout(&binding, started_at);
+                    access_log.finish(&logs_agent, &binding, Some(&machine.config.name), &response);
+                    return Ok(response);
+                }
+            };
+
             let upstream_uri = format!(
-                "http://{}:{}",
+                "{}://{}:{}",
+                binding.upstream_scheme(),
                 machine_connection.ip_address(),
                 binding.target_port
             );
@@ -651,9 +1442,6 @@ async fn handle_http_connection(
                 target_host, upstream_uri
             );
 
-            let client = client.clone();
-            let upstream_uri = upstream_uri.clone();
-
             let original_uri = req.uri();
             let path_and_query = original_uri
                 .path_and_query()
@@ -668,11 +1456,16 @@ async fn handle_http_connection(
             headers.remove("x-forwarded-proto");
             headers.append("x-forwarded-proto", HeaderValue::from_static("https"));
 
-            if let Some(host) = binding.public_host().and_then(|h| h.parse().ok()) {
+            if let Some(host) = binding
+                .upstream_host(&target_host, UpstreamHostHeader::Binding)
+                .and_then(|h| h.parse::<HeaderValue>().ok())
+            {
                 headers.remove("host");
                 headers.append("host", host);
             }
 
+            set_wildcard_label_header(headers, &binding, &target_host);
+
             if let Some(client_ip) =
                 client_ip.and_then(|ip| ip.ip().to_string().parse::<HeaderValue>().ok())
             {
@@ -683,12 +1476,49 @@ async fn handle_http_connection(
                 headers.append("x-real-ip", client_ip);
             }
 
-            *req.version_mut() = Version::HTTP_11;
+            *req.version_mut() = binding.upstream_protocol.version();
 
             info!("Modified request URI: {:?}", req.uri());
 
-            let Ok(mut response) = client.request(req).await else {
-                return Err("failed to get response from origin");
+            let encoding = binding
+                .compression
+                .as_ref()
+                .and_then(|compression| compression.negotiate(req.method(), req.headers()));
+
+            if let Some(headers) = &binding.headers {
+                headers.request.apply(req.headers_mut());
+            }
+
+            let req = match UpstreamRequest::prepare(&machine_agent, &binding, req, woken).await {
+                Ok(req) => req,
+                Err(e) => {
+                    warn!("Failed to read request body: {}", e);
+                    return Err("failed to read request body");
+                }
+            };
+
+            let mut response = match bounded(
+                binding.timeouts.response_remaining(started_at),
+                req.send(&binding, &machine_connection.ip_address()),
+            )
+            .await
+            {
+                Some(Ok(response)) => {
+                    record_circuit_outcome(&binding, &machine, true);
+                    response
+                }
+                Some(Err(_)) => {
+                    record_circuit_outcome(&binding, &machine, false);
+                    let response = bad_gateway(&binding, started_at, html);
+                    access_log.finish(&logs_agent, &binding, Some(&machine.config.name), &response);
+                    return Ok(response);
+                }
+                None => {
+                    record_circuit_outcome(&binding, &machine, false);
+                    let response = gateway_timeout(&binding, started_at);
+                    access_log.finish(&logs_agent, &binding, Some(&machine.config.name), &response);
+                    return Ok(response);
+                }
             };
 
             if target_host.ends_with(&blacklisted_seo_domain) {
@@ -707,10 +1537,16 @@ async fn handle_http_connection(
                     let upstream_upgrade = hyper::upgrade::on(&mut response);
 
                     // Spawn a task to handle the WebSocket proxying
+                    let traffic = binding.metrics.as_ref().map(|metrics| metrics.traffic());
                     spawn(async move {
-                        if let Err(e) =
-                            proxy_websocket_upgrade(client_upgrade.await, upstream_upgrade.await)
-                                .await
+                        let _permit = permit;
+                        let _connection = connection;
+                        if let Err(e) = proxy_websocket_upgrade(
+                            client_upgrade.await,
+                            upstream_upgrade.await,
+                            traffic,
+                        )
+                        .await
                         {
                             warn!("Error proxying WebSocket: {}", e);
                         }
@@ -718,7 +1554,26 @@ async fn handle_http_connection(
                 }
             }
 
-            Ok(response.map(|b| b.boxed()))
+            let mut response = response.map(|b| b.boxed());
+            if let Some(compression) = &binding.compression {
+                response = compression.compress(encoding, response);
+            }
+            if let Some(sticky) = &binding.sticky_sessions {
+                sticky.pin(
+                    response.headers_mut(),
+                    &machine.config.name,
+                    affinity.as_deref(),
+                    false,
+                );
+            }
+            if let Some(headers) = &binding.headers {
+                headers.response.apply(response.headers_mut());
+            }
+            let response = count_sent_bytes(&binding, response);
+            record_response(&binding, started_at, &response);
+            access_log.finish(&logs_agent, &binding, Some(&machine.config.name), &response);
+
+            Ok(response)
         }
     });
 
@@ -739,6 +1594,9 @@ async fn handle_pg_ssl_connection(
     bindings: Arc<HashMap<String, ProxyBinding>>,
     blacklisted_seo_domain: String,
     machine_agent: Arc<MachineAgent>,
+    logs_agent: Arc<LogsAgent>,
+    default_backend: Arc<DefaultBackend>,
+    alt_svc: Option<HeaderValue>,
 ) -> Result<()> {
     // read the SSLRequest message and accept the connection with handle_tls_connection
     let mut _throw_away_buffer = [0u8; 8];
@@ -747,32 +1605,46 @@ async fn handle_pg_ssl_connection(
     stream.write_all(b"S").await?;
 
     let tls_stream = tls_acceptor.accept(stream).await?;
-    handle_tls_connection(tls_stream, bindings, blacklisted_seo_domain, machine_agent).await
+    handle_tls_connection(
+        tls_stream,
+        bindings,
+        blacklisted_seo_domain,
+        machine_agent,
+        logs_agent,
+        default_backend,
+        alt_svc,
+    )
+    .await
 }
 
-async fn handle_https_connection(
-    tls_stream: tokio_rustls::server::TlsStream<TcpStream>,
+async fn handle_https_connection<I>(
+    io: I,
+    client_ip: Option<SocketAddr>,
     bindings: Arc<HashMap<String, ProxyBinding>>,
     blacklisted_seo_domain: String,
     machine_agent: Arc<MachineAgent>,
+    logs_agent: Arc<LogsAgent>,
+    default_backend: Arc<DefaultBackend>,
     server_name: String,
-) -> Result<()> {
-    let client_ip = tls_stream.get_ref().0.peer_addr().ok();
-
-    let io = TokioIo::new(tls_stream);
+    alt_svc: Option<HeaderValue>,
+) -> Result<()>
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let io = TokioIo::new(io);
 
     let svc = service_fn(move |mut req: Request<hyper::body::Incoming>| {
         let machine_agent = machine_agent.clone();
         let bindings = bindings.clone();
         let blacklisted_seo_domain = blacklisted_seo_domain.clone();
         let server_name = server_name.clone();
-
-        let mut base = HttpConnector::new();
-        base.enforce_http(true);
-
-        let client = Client::builder(TokioExecutor::new()).build(base);
+        let logs_agent = logs_agent.clone();
+        let default_backend = default_backend.clone();
+        let alt_svc = alt_svc.clone();
 
         async move {
+            let started_at = Instant::now();
+
             // Check if this is a WebSocket upgrade request
             let is_websocket_upgrade = req
                 .headers()
@@ -807,39 +1679,102 @@ async fn handle_https_connection(
                 }
             };
 
-            let Ok((binding, _)) = find_tls_binding(&bindings, &target_host) else {
-                return Err("failed to find binding for HTTPS host");
+            let path = req.uri().path().to_string();
+            let binding = match find_http_binding(&bindings, &target_host, &path, true) {
+                Ok(binding) => binding,
+                Err(_) => match default_backend.binding(&bindings) {
+                    Some(binding) => binding,
+                    None => return Ok(default_backend.not_found()),
+                },
             };
 
-            let Ok(machine) = find_machine(&machine_agent, &binding.target_network_tag).await
-            else {
-                return Err("failed to find machine");
+            let access_log = PendingAccessLog::new(started_at, &target_host, &req, client_ip);
+            record_request(&binding);
+
+            if !firewall_allows(&binding, client_ip) {
+                let response = forbidden();
+                record_response(&binding, started_at, &response);
+                access_log.finish(&logs_agent, &binding, None, &response);
+                return Ok(response);
+            }
+
+            let html = accepts_html(req.headers());
+            if binding.maintenance {
+                let response =
+                    error_response(ErrorPage::Maintenance, binding.error_pages.as_deref(), html);
+                record_response(&binding, started_at, &response);
+                access_log.finish(&logs_agent, &binding, None, &response);
+                return Ok(response);
+            }
+
+            // the connection slot is held by the TLS connection itself
+            if let Some(limiter) = binding.rate_limiter.as_ref() {
+                if !limiter.try_acquire_request() {
+                    let response = too_many_requests();
+                    record_rate_limited(&binding, started_at, &response);
+                    access_log.finish(&logs_agent, &binding, None, &response);
+                    return Ok(response);
+                }
+            }
+
+            if let Some(response) =
+                authorize(&binding, &mut req, &target_host, "https", client_ip).await
+            {
+                record_response(&binding, started_at, &response);
+                access_log.finish(&logs_agent, &binding, None, &response);
+                return Ok(response);
+            }
+
+            let affinity = binding
+                .sticky_sessions
+                .as_ref()
+                .and_then(|sticky| sticky.affinity(req.headers()));
+            let machine = match find_machine(&machine_agent, &binding, affinity.as_deref()).await {
+                Ok(machine) => machine,
+                Err(e) => {
+                    warn!("Failed to find machine: {}", e);
+                    let response = service_unavailable(&binding, started_at, html);
+                    access_log.finish(&logs_agent, &binding, None, &response);
+                    return Ok(response);
+                }
             };
 
-            let machine_connection =
-                match get_machine_connection(&machine, binding.target_port, None).await {
-                    Ok(conn) => conn,
-                    Err(e) => {
-                        warn!(
-                            "Failed to establish connection to machine service {}:{}: {}",
-                            machine.config.network.ip_address, binding.target_port, e
-                        );
-                        return Err(
-                            "failed to connect to machine service - service may be starting up",
-                        );
-                    }
-                };
+            // a machine woken up by this request may take a moment to accept it
+            let woken = machine.get_state().await != MachineState::Ready;
+
+            let machine_connection = match bounded(
+                binding.timeouts.request_remaining(started_at),
+                get_machine_connection(&machine, &binding, None),
+            )
+            .await
+            {
+                Some(Ok(conn)) => conn,
+                Some(Err(e)) => {
+                    warn!(
+                        "Failed to establish connection to machine service {}:{}: {}",
+                        machine.config.network.ip_address, binding.target_port, e
+                    );
+                    record_circuit_outcome(&binding, &machine, false);
+                    let response = service_unavailable(&binding, started_at, html);
+                    access_log.finish(&logs_agent, &binding, Some(&machine.config.name), &response);
+                    return Ok(response);
+                }
+                None => {
+                    record_circuit_outcome(&binding, &machine, false);
+                    let response = gateway_timeout(&binding, started_at);
+                    access_log.finish(&logs_agent, &binding, Some(&machine.config.name), &response);
+                    return Ok(response);
+                }
+            };
 
             let upstream_uri = format!(
-                "http://{}:{}",
+                "{}://{}:{}",
+                binding.upstream_scheme(),
                 machine_connection.ip_address(),
                 binding.target_port
             );
             info!("Proxying HTTPS connection to {}", upstream_uri);
 
-            let client = client.clone();
-            let upstream_uri = upstream_uri.clone();
-
             let original_uri = req.uri();
             let path_and_query = original_uri
                 .path_and_query()
@@ -856,12 +1791,20 @@ async fn handle_https_connection(
             headers.remove("x-forwarded-proto");
             headers.append("x-forwarded-proto", HeaderValue::from_static("https"));
 
-            if let Ok(host) = HeaderValue::from_str(&target_host) {
+            if let Ok(forwarded_host) = HeaderValue::from_str(&target_host) {
+                headers.append("x-forwarded-host", forwarded_host);
+            }
+
+            if let Some(host) = binding
+                .upstream_host(&target_host, UpstreamHostHeader::Preserve)
+                .and_then(|h| h.parse::<HeaderValue>().ok())
+            {
                 headers.remove("host");
-                headers.append("host", host.clone());
-                headers.append("x-forwarded-host", host);
+                headers.append("host", host);
             }
 
+            set_wildcard_label_header(headers, &binding, &target_host);
+
             if let Some(client_ip) =
                 client_ip.and_then(|ip| ip.ip().to_string().parse::<HeaderValue>().ok())
             {
@@ -872,12 +1815,49 @@ async fn handle_https_connection(
                 headers.append("x-real-ip", client_ip);
             }
 
-            *req.version_mut() = Version::HTTP_11;
+            *req.version_mut() = binding.upstream_protocol.version();
 
             info!("Modified request URI: {:?}", req.uri());
 
-            let Ok(mut response) = client.request(req).await else {
-                return Err("failed to get response from origin");
+            let encoding = binding
+                .compression
+                .as_ref()
+                .and_then(|compression| compression.negotiate(req.method(), req.headers()));
+
+            if let Some(headers) = &binding.headers {
+                headers.request.apply(req.headers_mut());
+            }
+
+            let req = match UpstreamRequest::prepare(&machine_agent, &binding, req, woken).await {
+                Ok(req) => req,
+                Err(e) => {
+                    warn!("Failed to read request body: {}", e);
+                    return Err("failed to read request body");
+                }
+            };
+
+            let mut response = match bounded(
+                binding.timeouts.response_remaining(started_at),
+                req.send(&binding, &machine_connection.ip_address()),
+            )
+            .await
+            {
+                Some(Ok(response)) => {
+                    record_circuit_outcome(&binding, &machine, true);
+                    response
+                }
+                Some(Err(_)) => {
+                    record_circuit_outcome(&binding, &machine, false);
+                    let response = bad_gateway(&binding, started_at, html);
+                    access_log.finish(&logs_agent, &binding, Some(&machine.config.name), &response);
+                    return Ok(response);
+                }
+                None => {
+                    record_circuit_outcome(&binding, &machine, false);
+                    let response = gateway_timeout(&binding, started_at);
+                    access_log.finish(&logs_agent, &binding, Some(&machine.config.name), &response);
+                    return Ok(response);
+                }
             };
 
             // TODO: Uncomment this when we have a stable HTTP -> HTTPS redirect
@@ -887,6 +1867,10 @@ async fn handle_https_connection(
                 HeaderValue::from_static("max-age=86400"),
             );
 
+            if let Some(alt_svc) = alt_svc {
+                response.headers_mut().insert("alt-svc", alt_svc);
+            }
+
             if target_host.ends_with(&blacklisted_seo_domain) {
                 response.headers_mut().append(
                     "X-Robots-Tag",
@@ -903,10 +1887,14 @@ async fn handle_https_connection(
                     let upstream_upgrade = hyper::upgrade::on(&mut response);
 
                     // Spawn a task to handle the WebSocket proxying
+                    let traffic = binding.metrics.as_ref().map(|metrics| metrics.traffic());
                     spawn(async move {
-                        if let Err(e) =
-                            proxy_websocket_upgrade(client_upgrade.await, upstream_upgrade.await)
-                                .await
+                        if let Err(e) = proxy_websocket_upgrade(
+                            client_upgrade.await,
+                            upstream_upgrade.await,
+                            traffic,
+                        )
+                        .await
                         {
                             warn!("Error proxying WebSocket over TLS: {}", e);
                         }
@@ -914,6 +1902,25 @@ async fn handle_https_connection(
                 }
             }
 
+            let mut response = response.map(|b| b.boxed());
+            if let Some(compression) = &binding.compression {
+                response = compression.compress(encoding, response);
+            }
+            if let Some(sticky) = &binding.sticky_sessions {
+                sticky.pin(
+                    response.headers_mut(),
+                    &machine.config.name,
+                    affinity.as_deref(),
+                    true,
+                );
+            }
+            if let Some(headers) = &binding.headers {
+                headers.response.apply(response.headers_mut());
+            }
+            let response = count_sent_bytes(&binding, response);
+            record_response(&binding, started_at, &response);
+            access_log.finish(&logs_agent, &binding, Some(&machine.config.name), &response);
+
             Ok(response)
         }
     });
@@ -929,36 +1936,123 @@ async fn handle_https_connection(
     Ok(())
 }
 
+async fn handle_tls_passthrough_connection(
+    mut stream: TcpStream,
+    binding: ProxyBinding,
+    machine_agent: Arc<MachineAgent>,
+    server_name: String,
+) -> Result<()> {
+    if !firewall_allows(&binding, stream.peer_addr().ok()) {
+        bail!("Connection for TLS server name {server_name} rejected by the firewall");
+    }
+
+    let _permit = acquire_connection_permit(&binding)?;
+    let _connection = open_connection(&binding);
+
+    if let Some(limiter) = binding.rate_limiter.as_ref() {
+        if !limiter.try_acquire_request() {
+            if let Some(metrics) = binding.metrics.as_ref() {
+                metrics.record_rate_limited();
+            }
+            bail!("Rate limit exceeded for TLS server name {server_name}");
+        }
+    }
+
+    let mut machine_connection = connect_upstream(&machine_agent, &binding).await?;
+    send_proxy_header(
+        &mut machine_connection,
+        &binding,
+        stream.peer_addr().ok(),
+        stream.local_addr().ok(),
+    )
+    .await?;
+
+    info!(
+        "Passing TLS connection for {} through to machine on port {}",
+        server_name, binding.target_port
+    );
+
+    // the ClientHello was only peeked, so the machine sees the handshake from the start
+    machine_connection.proxy_from_client(&mut stream).await?;
+
+    Ok(())
+}
+
 async fn handle_tls_connection(
     mut tls_stream: tokio_rustls::server::TlsStream<TcpStream>,
     bindings: Arc<HashMap<String, ProxyBinding>>,
     blacklisted_seo_domain: String,
     machine_agent: Arc<MachineAgent>,
+    logs_agent: Arc<LogsAgent>,
+    default_backend: Arc<DefaultBackend>,
+    alt_svc: Option<HeaderValue>,
 ) -> Result<()> {
-    let (_, server_conn) = tls_stream.get_ref();
+    let (client_stream, server_conn) = tls_stream.get_ref();
+    let client_ip = client_stream.peer_addr().ok();
 
     let Some(server_name) = server_conn.server_name().map(|s| s.to_string()) else {
         warn!("No server name in TLS connection");
         bail!("No server name in TLS connection");
     };
 
-    let (binding, nested_protocol) = find_tls_binding(&bindings, &server_name)?;
+    let Ok((binding, nested_protocol)) = find_tls_binding(&bindings, &server_name) else {
+        // unknown server names are treated as HTTPS, so the default backend can answer them
+        info!("No binding for TLS server name {server_name}, using the default backend");
+        return handle_https_connection(
+            tls_stream,
+            client_ip,
+            bindings,
+            blacklisted_seo_domain,
+            machine_agent,
+            logs_agent,
+            default_backend,
+            server_name,
+            alt_svc,
+        )
+        .await;
+    };
+
+    if !firewall_allows(&binding, client_ip) {
+        bail!("Connection for TLS server name {server_name} rejected by the firewall");
+    }
+
+    let _permit = acquire_connection_permit(&binding)?;
+    let _connection = open_connection(&binding);
 
     if nested_protocol == ExternnalBindingRoutingTlsNestedProtocol::Http {
         info!("Handling HTTP connection over TLS");
         return handle_https_connection(
             tls_stream,
+            client_ip,
             bindings,
             blacklisted_seo_domain,
             machine_agent,
+            logs_agent,
+            default_backend,
             server_name,
+            alt_svc,
         )
         .await;
     }
 
-    let machine = find_machine(&machine_agent, &binding.target_network_tag).await?;
-    let mut machine_connection =
-        get_machine_connection(&machine, binding.target_port, binding.inactivity_timeout).await?;
+    if let Some(limiter) = binding.rate_limiter.as_ref() {
+        if !limiter.try_acquire_request() {
+            if let Some(metrics) = binding.metrics.as_ref() {
+                metrics.record_rate_limited();
+            }
+            bail!("Rate limit exceeded for TLS server name {server_name}");
+        }
+    }
+
+    let mut machine_connection = connect_upstream(&machine_agent, &binding).await?;
+    let (client_stream, _) = tls_stream.get_ref();
+    send_proxy_header(
+        &mut machine_connection,
+        &binding,
+        client_stream.peer_addr().ok(),
+        client_stream.local_addr().ok(),
+    )
+    .await?;
 
     info!(
         "Proxying TLS connection from {} to machine on port {}",
@@ -972,28 +2066,106 @@ async fn handle_tls_connection(
     Ok(())
 }
 
+async fn handle_http3_connection(
+    connection: Http3Connection,
+    bindings: Arc<HashMap<String, ProxyBinding>>,
+    blacklisted_seo_domain: String,
+    machine_agent: Arc<MachineAgent>,
+    logs_agent: Arc<LogsAgent>,
+    default_backend: Arc<DefaultBackend>,
+    alt_svc: Option<HeaderValue>,
+) -> Result<()> {
+    let Some(server_name) = connection.server_name else {
+        warn!("No server name in QUIC connection");
+        bail!("No server name in QUIC connection");
+    };
+
+    // unknown server names are treated as HTTPS, so the default backend can answer them
+    let binding = match find_tls_binding(&bindings, &server_name) {
+        Ok((binding, ExternnalBindingRoutingTlsNestedProtocol::Http)) => Some(binding),
+        Ok(_) => bail!("TLS server name {server_name} is not served over HTTP/3"),
+        Err(_) => None,
+    };
+
+    let (_permit, _connection) = match &binding {
+        Some(binding) => {
+            if !firewall_allows(binding, Some(connection.client_addr)) {
+                bail!("Connection for TLS server name {server_name} rejected by the firewall");
+            }
+            (
+                acquire_connection_permit(binding)?,
+                open_connection(binding),
+            )
+        }
+        None => (None, None),
+    };
+
+    info!("Handling HTTP/3 connection for {}", server_name);
+    handle_https_connection(
+        connection.io,
+        Some(connection.client_addr),
+        bindings,
+        blacklisted_seo_domain,
+        machine_agent,
+        logs_agent,
+        default_backend,
+        server_name,
+        alt_svc,
+    )
+    .await
+}
+
+/// Finds the binding for an HTTP request, preferring the longest matching path prefix. With `tls`
+/// only bindings terminating TLS for the host are considered.
 fn find_http_binding(
     bindings: &Arc<HashMap<String, ProxyBinding>>,
     target_host: &str,
+    path: &str,
+    tls: bool,
 ) -> Result<ProxyBinding> {
     let bindings = bindings.pin_owned();
     bindings
         .values()
-        .find(|b| match &b.mode {
-            BindingMode::External { routing, port } => match routing {
-                ExternalBindingRouting::HttpHostHeader { host }
-                    if *host == target_host || format!("{}:{}", host, port) == target_host =>
-                {
-                    true
+        .filter_map(|b| {
+            let BindingMode::External { routing, .. } = &b.mode else {
+                return None;
+            };
+
+            let prefix_len = match routing {
+                ExternalBindingRouting::HttpHostHeader { .. } if !tls => 0,
+                ExternalBindingRouting::TlsSni {
+                    nested_protocol: ExternnalBindingRoutingTlsNestedProtocol::Http,
+                    ..
+                } if tls => 0,
+                ExternalBindingRouting::HttpHostPath {
+                    path_prefix,
+                    tls: binding_tls,
+                    ..
+                } if *binding_tls == tls && path_has_prefix(path, path_prefix) => {
+                    path_prefix.trim_end_matches('/').len()
                 }
-                _ => false,
-            },
-            _ => false,
+                _ => return None,
+            };
+
+            let host_match = b.match_host(target_host)?;
+
+            // exact hosts win over wildcards, then the longest path prefix
+            Some(((host_match.is_exact(), prefix_len), b))
         })
-        .cloned()
+        .max_by_key(|(rank, _)| *rank)
+        .map(|(_, b)| b.clone())
         .ok_or_else(|| anyhow::anyhow!("No binding found for HTTP host {target_host}"))
 }
 
+/// `/api` matches `/api` and `/api/users`, but not `/apis`.
+fn path_has_prefix(path: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
 fn find_tls_binding(
     bindings: &Arc<HashMap<String, ProxyBinding>>,
     server_name: &str,
@@ -1001,14 +2173,19 @@ fn find_tls_binding(
     let bindings = bindings.pin_owned();
     let binding = bindings
         .values()
-        .find(|b| match &b.mode {
-            BindingMode::External { routing, .. } => match routing {
-                ExternalBindingRouting::TlsSni { host, .. } if *host == server_name => true,
-                _ => false,
-            },
-            _ => false,
+        .filter(|b| {
+            matches!(
+                &b.mode,
+                BindingMode::External {
+                    routing: ExternalBindingRouting::TlsSni { .. }
+                        | ExternalBindingRouting::HttpHostPath { tls: true, .. },
+                    ..
+                }
+            )
         })
-        .cloned();
+        .filter_map(|b| Some((b.match_host(server_name)?.is_exact(), b)))
+        .max_by_key(|(exact, _)| *exact)
+        .map(|(_, b)| b.clone());
 
     let Some(binding) = binding else {
         bail!("No binding found for TLS server name {server_name}");
@@ -1019,6 +2196,9 @@ fn find_tls_binding(
             ExternalBindingRouting::TlsSni {
                 nested_protocol, ..
             } => nested_protocol.clone(),
+            ExternalBindingRouting::HttpHostPath { .. } => {
+                ExternnalBindingRoutingTlsNestedProtocol::Http
+            }
             _ => bail!("No nested protocol found for TLS server name {server_name}"),
         },
         _ => bail!("No nested protocol found for TLS server name {server_name}"),
@@ -1027,24 +2207,429 @@ fn find_tls_binding(
     Ok((binding, nested_protocol))
 }
 
+fn find_tls_passthrough_binding(
+    bindings: &Arc<HashMap<String, ProxyBinding>>,
+    server_name: &str,
+) -> Option<ProxyBinding> {
+    let bindings = bindings.pin_owned();
+    bindings
+        .values()
+        .filter(|b| {
+            matches!(
+                &b.mode,
+                BindingMode::External {
+                    routing: ExternalBindingRouting::TlsPassthrough { .. },
+                    ..
+                }
+            )
+        })
+        .filter_map(|b| Some((b.match_host(server_name)?.is_exact(), b)))
+        .max_by_key(|(exact, _)| *exact)
+        .map(|(_, b)| b.clone())
+}
+
+fn acquire_connection_permit(binding: &ProxyBinding) -> Result<Option<ConnectionPermit>> {
+    let Some(limiter) = binding.rate_limiter.as_ref() else {
+        return Ok(None);
+    };
+
+    let Some(permit) = limiter.try_acquire_connection() else {
+        if let Some(metrics) = binding.metrics.as_ref() {
+            metrics.record_rate_limited();
+        }
+        bail!(
+            "Too many concurrent connections for network tag {}",
+            binding.target_network_tag
+        );
+    };
+
+    Ok(Some(permit))
+}
+
+/// Replaces any client supplied label, so upstreams can trust the header.
+fn set_wildcard_label_header(
+    headers: &mut hyper::HeaderMap,
+    binding: &ProxyBinding,
+    target_host: &str,
+) {
+    headers.remove(WILDCARD_LABEL_HEADER);
+
+    if let Some(HostMatch::Wildcard(label)) = binding.match_host(target_host) {
+        if let Ok(label) = HeaderValue::from_str(&label) {
+            headers.insert(WILDCARD_LABEL_HEADER, label);
+        }
+    }
+}
+
+fn open_connection(binding: &ProxyBinding) -> Option<ConnectionGuard> {
+    binding
+        .metrics
+        .as_ref()
+        .map(|metrics| metrics.open_connection())
+}
+
+fn record_request(binding: &ProxyBinding) {
+    if let Some(metrics) = binding.metrics.as_ref() {
+        metrics.record_request();
+    }
+}
+
+fn record_response(
+    binding: &ProxyBinding,
+    started_at: Instant,
+    response: &Response<BoxBody<Bytes, hyper::Error>>,
+) {
+    if let Some(metrics) = binding.metrics.as_ref() {
+        metrics.record_response(response.status().as_u16(), started_at.elapsed());
+    }
+}
+
+fn record_rate_limited(
+    binding: &ProxyBinding,
+    started_at: Instant,
+    response: &Response<BoxBody<Bytes, hyper::Error>>,
+) {
+    if let Some(metrics) = binding.metrics.as_ref() {
+        metrics.record_rate_limited();
+    }
+    record_response(binding, started_at, response);
+}
+
+fn record_traffic(binding: &ProxyBinding, bytes_in: u64, bytes_out: u64) {
+    if let Some(metrics) = binding.metrics.as_ref() {
+        let traffic = metrics.traffic();
+        traffic.record_in(bytes_in);
+        traffic.record_out(bytes_out);
+    }
+}
+
+fn record_upstream_error(binding: &ProxyBinding) {
+    if let Some(metrics) = binding.metrics.as_ref() {
+        metrics.record_upstream_error();
+    }
+}
+
+fn record_circuit_outcome(binding: &ProxyBinding, machine: &Machine, success: bool) {
+    let Some(circuit_breaker) = binding.circuit_breaker.as_ref() else {
+        return;
+    };
+
+    if success {
+        circuit_breaker.record_success(&machine.config.name);
+    } else {
+        circuit_breaker.record_failure(&machine.config.name);
+    }
+}
+
+/// Finds a machine for the binding and connects to it, counting failures as upstream errors.
+async fn connect_upstream(
+    machine_agent: &Arc<MachineAgent>,
+    binding: &ProxyBinding,
+) -> Result<TrafficAwareConnection> {
+    let result = async {
+        let machine = find_machine(machine_agent, binding, None).await?;
+        let connection =
+            get_machine_connection(&machine, binding, binding.inactivity_timeout).await;
+        record_circuit_outcome(binding, &machine, connection.is_ok());
+
+        connection
+    }
+    .await;
+
+    if result.is_err() {
+        record_upstream_error(binding);
+    }
+
+    result
+}
+
+async fn send_upstream_request(
+    binding: &ProxyBinding,
+    ip_address: &str,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<hyper::body::Incoming>> {
+    match binding.upstream_tls.as_ref() {
+        Some(upstream_tls) => {
+            upstream_tls
+                .request(
+                    ip_address,
+                    binding.target_port,
+                    binding.timeouts.connect,
+                    req,
+                )
+                .await
+        }
+        None => Ok(binding
+            .upstream_protocol
+            .client(binding.timeouts.connect)
+            .request(req)
+            .await?),
+    }
+}
+
+/// Writes the PROXY protocol header ahead of any client bytes, when the binding asks for it.
+async fn send_proxy_header(
+    machine_connection: &mut TrafficAwareConnection,
+    binding: &ProxyBinding,
+    client_addr: Option<SocketAddr>,
+    local_addr: Option<SocketAddr>,
+) -> Result<()> {
+    if !binding.proxy_protocol {
+        return Ok(());
+    }
+
+    let header = proxy_protocol::encode_v2_header(client_addr, local_addr);
+    machine_connection
+        .upstream_socket
+        .write_all(&header)
+        .await?;
+
+    Ok(())
+}
+
+/// Runs the binding's authentication, adding the headers it hands out to the request. Returns the
+/// response to send instead when the request is turned away.
+async fn authorize(
+    binding: &ProxyBinding,
+    req: &mut Request<hyper::body::Incoming>,
+    host: &str,
+    scheme: &str,
+    client_addr: Option<SocketAddr>,
+) -> Option<Response<BoxBody<Bytes, hyper::Error>>> {
+    let auth = binding.auth.as_ref()?;
+    let client_ip = client_addr.map(|addr| addr.ip().to_string());
+
+    match auth.authorize(req, host, scheme, client_ip).await {
+        AuthOutcome::Allow(headers) => {
+            for name in auth.trusted_headers() {
+                req.headers_mut().remove(name);
+            }
+            for (name, value) in headers.iter() {
+                req.headers_mut().append(name.clone(), value.clone());
+            }
+            None
+        }
+        AuthOutcome::Deny(response) => Some(response),
+    }
+}
+
+fn firewall_allows(binding: &ProxyBinding, client_addr: Option<SocketAddr>) -> bool {
+    let Some(firewall) = binding.firewall.as_ref() else {
+        return true;
+    };
+
+    client_addr.is_some_and(|addr| firewall.allows(addr.ip()))
+}
+
+fn forbidden() -> Response<BoxBody<Bytes, hyper::Error>> {
+    let mut response = Response::new(
+        Full::new(Bytes::from("forbidden"))
+            .map_err(|never| match never {})
+            .boxed(),
+    );
+    *response.status_mut() = StatusCode::FORBIDDEN;
+
+    response
+}
+
+fn too_many_requests() -> Response<BoxBody<Bytes, hyper::Error>> {
+    let mut response = Response::new(
+        Full::new(Bytes::from("rate limit exceeded"))
+            .map_err(|never| match never {})
+            .boxed(),
+    );
+    *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+    response
+        .headers_mut()
+        .insert("retry-after", HeaderValue::from_static("1"));
+
+    response
+}
+
+/// Answers a request whose upstream didn't respond within the binding timeouts.
+fn gateway_timeout(
+    binding: &ProxyBinding,
+    started_at: Instant,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    warn!(
+        "Upstream for network tag {} timed out after {}ms",
+        binding.target_network_tag,
+        started_at.elapsed().as_millis()
+    );
+    record_upstream_error(binding);
+
+    let mut response = Response::new(
+        Full::new(Bytes::from("upstream timed out"))
+            .map_err(|never| match never {})
+            .boxed(),
+    );
+    *response.status_mut() = StatusCode::GATEWAY_TIMEOUT;
+    record_response(binding, started_at, &response);
+
+    response
+}
+
+/// Answers a request whose upstream failed to respond.
+fn bad_gateway(
+    binding: &ProxyBinding,
+    started_at: Instant,
+    html: bool,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    record_upstream_error(binding);
+
+    let response = error_response(ErrorPage::BadGateway, binding.error_pages.as_deref(), html);
+    record_response(binding, started_at, &response);
+
+    response
+}
+
+/// Answers a request whose machine couldn't be reached, asking the client to come back shortly.
+fn service_unavailable(
+    binding: &ProxyBinding,
+    started_at: Instant,
+    html: bool,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    record_upstream_error(binding);
+
+    let mut response = error_response(
+        ErrorPage::ServiceUnavailable,
+        binding.error_pages.as_deref(),
+        html,
+    );
+    if let Ok(retry_after) = HeaderValue::from_str(&RETRY_AFTER_SECS.to_string()) {
+        response.headers_mut().insert("retry-after", retry_after);
+    }
+    record_response(binding, started_at, &response);
+
+    response
+}
+
 async fn find_machine(
     machine_agent: &Arc<MachineAgent>,
-    network_tag: &str,
+    binding: &ProxyBinding,
+    affinity: Option<&str>,
 ) -> Result<Arc<Machine>> {
-    machine_agent
-        .get_machine_by_network_tag(network_tag)
-        .await
-        .ok_or_else(|| anyhow::anyhow!("No machine found for network tag {network_tag}"))
+    if let Some(affinity) = affinity {
+        if let Some(machine) = find_pinned_machine(machine_agent, binding, affinity).await {
+            return Ok(machine);
+        }
+    }
+
+    let (network_tag, rotation) = binding.balancing.next(&binding.target_network_tag);
+
+    let mut machines = machine_agent.get_machines_by_network_tag(network_tag).await;
+    if machines.is_empty() && network_tag != binding.target_network_tag {
+        // a backend or canary that is gone shouldn't take the whole service down
+        warn!(
+            "No machine found for network tag {}, falling back to {}",
+            network_tag, binding.target_network_tag
+        );
+        machines = machine_agent
+            .get_machines_by_network_tag(&binding.target_network_tag)
+            .await;
+    }
+
+    if machines.is_empty() {
+        bail!("No machine found for network tag {network_tag}");
+    }
+
+    let Some(circuit_breaker) = binding.circuit_breaker.as_ref() else {
+        return Ok(prefer_prewarmed(&machines, rotation).await);
+    };
+
+    // starting from the rotation, skip the machines ejected by the circuit breaker
+    for offset in 0..machines.len() {
+        let machine = &machines[(rotation + offset) % machines.len()];
+        if circuit_breaker.try_acquire(&machine.config.name) {
+            return Ok(machine.clone());
+        }
+    }
+
+    bail!("All machines for network tag {network_tag} are ejected by the circuit breaker");
+}
+
+/// The machine at `rotation`, or a resumed prewarmed clone instead while it's suspended so the
+/// request doesn't wait for it to wake up. Clones are tried from the rotation on so a burst is
+/// spread over the pool.
+async fn prefer_prewarmed(machines: &[Arc<Machine>], rotation: usize) -> Arc<Machine> {
+    let machine = &machines[rotation % machines.len()];
+    if machine.get_state().await != MachineState::Suspended {
+        return machine.clone();
+    }
+
+    for offset in 1..machines.len() {
+        let clone = &machines[(rotation + offset) % machines.len()];
+        if clone.config.prewarm && clone.get_state().await == MachineState::Ready {
+            return clone.clone();
+        }
+    }
+
+    machine.clone()
+}
+
+/// The machine a sticky session is pinned to, unless it is gone or ejected by the circuit
+/// breaker.
+async fn find_pinned_machine(
+    machine_agent: &Arc<MachineAgent>,
+    binding: &ProxyBinding,
+    affinity: &str,
+) -> Option<Arc<Machine>> {
+    for network_tag in binding.balancing.network_tags(&binding.target_network_tag) {
+        let machines = machine_agent.get_machines_by_network_tag(network_tag).await;
+        let Some(machine) = machines
+            .into_iter()
+            .find(|machine| affinity_token(&machine.config.name) == affinity)
+        else {
+            continue;
+        };
+
+        let allowed = binding
+            .circuit_breaker
+            .as_ref()
+            .is_none_or(|circuit_breaker| circuit_breaker.try_acquire(&machine.config.name));
+        return allowed.then_some(machine);
+    }
+
+    None
 }
 
 async fn get_machine_connection(
     machine: &Arc<Machine>,
-    target_port: u16,
+    binding: &ProxyBinding,
     inactivity_timeout: Option<Duration>,
 ) -> Result<TrafficAwareConnection> {
-    machine
-        .get_connection(target_port, inactivity_timeout)
-        .await
+    let mut attempt = 0;
+    loop {
+        match machine
+            .get_connection(
+                binding.target_port,
+                inactivity_timeout,
+                binding.timeouts.connect,
+            )
+            .await
+        {
+            Ok(mut connection) => {
+                connection.set_idle_timeout(binding.timeouts.idle);
+                connection.set_traffic_counters(
+                    binding.metrics.as_ref().map(|metrics| metrics.traffic()),
+                );
+                return Ok(connection);
+            }
+            Err(e) if attempt + 1 < CONNECT_ATTEMPTS => {
+                let delay = backoff_delay(attempt, rand::random());
+                warn!(
+                    "Connecting to machine {} failed (attempt {}), retrying in {}ms: {}",
+                    machine.config.name,
+                    attempt + 1,
+                    delay.as_millis(),
+                    e
+                );
+                sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
 }
 
 async fn internal_listener(
@@ -1059,28 +2644,35 @@ async fn internal_listener(
     let listener = TcpListener::bind(addr).await?;
 
     loop {
-        let (stream, _) = listener.accept().await?;
+        let (stream, client_addr) = listener.accept().await?;
+        if !firewall_allows(&binding, Some(client_addr)) {
+            info!(
+                "Internal connection from {} rejected by the firewall",
+                client_addr
+            );
+            continue;
+        }
+
         let machine_agent = machine_agent.clone();
         let binding = binding.clone();
 
         spawn(async move {
-            let Some(machine) = machine_agent
-                .get_machine_by_network_tag(&binding.target_network_tag)
-                .await
-            else {
-                warn!(
-                    "No machine found for network tag: {}",
-                    binding.target_network_tag
-                );
-                bail!(
-                    "No machine found for network tag {}",
-                    binding.target_network_tag
-                );
-            };
+            let _connection = open_connection(&binding);
 
-            let mut machine_connection = machine
-                .get_connection(binding.target_port, binding.inactivity_timeout)
-                .await?;
+            let mut machine_connection = match connect_upstream(&machine_agent, &binding).await {
+                Ok(machine_connection) => machine_connection,
+                Err(e) => {
+                    warn!("{}", e);
+                    return Err(e);
+                }
+            };
+            send_proxy_header(
+                &mut machine_connection,
+                &binding,
+                stream.peer_addr().ok(),
+                stream.local_addr().ok(),
+            )
+            .await?;
 
             info!(
                 "Proxying internal connection to machine on port {}",
@@ -1108,6 +2700,14 @@ async fn tcp_listener(
         let (client_stream, client_addr) = listener.accept().await?;
         info!("TCP connection from {}", client_addr);
 
+        if !firewall_allows(&binding, Some(client_addr)) {
+            info!(
+                "TCP connection from {} rejected by the firewall",
+                client_addr
+            );
+            continue;
+        }
+
         let machine_agent = machine_agent.clone();
         let binding = binding.clone();
 
@@ -1124,12 +2724,17 @@ async fn handle_tcp_connection(
     machine_agent: Arc<MachineAgent>,
     binding: ProxyBinding,
 ) -> Result<()> {
-    // Find the target machine
-    let machine = find_machine(&machine_agent, &binding.target_network_tag).await?;
-
-    // Get machine connection
-    let mut machine_connection =
-        get_machine_connection(&machine, binding.target_port, binding.inactivity_timeout).await?;
+    let _permit = acquire_connection_permit(&binding)?;
+    let _connection = open_connection(&binding);
+
+    let mut machine_connection = connect_upstream(&machine_agent, &binding).await?;
+    send_proxy_header(
+        &mut machine_connection,
+        &binding,
+        client_stream.peer_addr().ok(),
+        client_stream.local_addr().ok(),
+    )
+    .await?;
 
     info!(
         "Proxying TCP connection to machine on port {}",
@@ -0,0 +1,279 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Result, anyhow, bail};
+use sha1::{Digest, Sha1};
+use x509_parser::{
+    oid_registry::OID_PKIX_ACCESS_DESCRIPTOR_OCSP,
+    prelude::{FromDer, GeneralName, ParsedExtension, X509Certificate},
+};
+
+/// How long a stapled response is served before fetching a fresh one. Responders usually sign
+/// responses valid for several days, refreshing twice a day keeps well within that.
+pub const OCSP_REFRESH_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// How long to wait before asking a responder again after a failed fetch.
+pub const OCSP_RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+const OCSP_FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// DER of `AlgorithmIdentifier { sha1, NULL }`, the hash OCSP responders are expected to support.
+const SHA1_ALGORITHM_IDENTIFIER: &[u8] = &[
+    0x30, 0x09, 0x06, 0x05, 0x2b, 0x0e, 0x03, 0x02, 0x1a, 0x05, 0x00,
+];
+
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_INTEGER: u8 = 0x02;
+const TAG_ENUMERATED: u8 = 0x0a;
+const TAG_GENERALIZED_TIME: u8 = 0x18;
+/// `[0] EXPLICIT`, the response bytes of a response and the next update of a single response.
+const TAG_CONTEXT_0: u8 = 0xa0;
+
+fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let len_bytes = (len as u32).to_be_bytes();
+        let skip = len_bytes.iter().take_while(|b| **b == 0).count();
+        out.push(0x80 | (len_bytes.len() - skip) as u8);
+        out.extend_from_slice(&len_bytes[skip..]);
+    }
+
+    out.extend_from_slice(content);
+    out
+}
+
+/// Tag, header length and content length of the DER element at the start of `bytes`.
+fn der_header(bytes: &[u8]) -> Option<(u8, usize, usize)> {
+    let tag = *bytes.first()?;
+    let first_len = *bytes.get(1)?;
+    if first_len < 0x80 {
+        return Some((tag, 2, first_len as usize));
+    }
+
+    let len_bytes = (first_len & 0x7f) as usize;
+    if len_bytes == 0 || len_bytes > 4 {
+        return None;
+    }
+
+    let mut len = 0usize;
+    for byte in bytes.get(2..2 + len_bytes)? {
+        len = (len << 8) | *byte as usize;
+    }
+
+    Some((tag, 2 + len_bytes, len))
+}
+
+/// Content of the DER element at the start of `bytes`, when it's tagged `tag`.
+fn der_content(bytes: &[u8], tag: u8) -> Option<&[u8]> {
+    let (element_tag, header_len, len) = der_header(bytes)?;
+    if element_tag != tag {
+        return None;
+    }
+
+    bytes.get(header_len..header_len + len)
+}
+
+/// Tags and contents of the DER elements following each other in `bytes`.
+fn der_elements(mut bytes: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    let mut elements = vec![];
+    while !bytes.is_empty() {
+        let (tag, header_len, len) = der_header(bytes)?;
+        elements.push((tag, bytes.get(header_len..header_len + len)?));
+        bytes = &bytes[header_len + len..];
+    }
+
+    Some(elements)
+}
+
+fn find_element<'a>(bytes: &'a [u8], tag: u8) -> Option<&'a [u8]> {
+    der_elements(bytes)?
+        .into_iter()
+        .find(|(element_tag, _)| *element_tag == tag)
+        .map(|(_, content)| content)
+}
+
+/// `YYYYMMDDHHMMSS[.fff]Z`, the form DER requires.
+fn parse_generalized_time(bytes: &[u8]) -> Option<SystemTime> {
+    let time = std::str::from_utf8(bytes).ok()?.strip_suffix('Z')?;
+    let seconds = time.split('.').next()?;
+    let time = chrono::NaiveDateTime::parse_from_str(seconds, "%Y%m%d%H%M%S").ok()?;
+
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(time.and_utc().timestamp()).ok()?))
+}
+
+/// When the first single response of `response` stops being valid. Responses without a next
+/// update have newer information available at any time.
+pub fn ocsp_next_update(response: &[u8]) -> Option<SystemTime> {
+    // OCSPResponse { responseStatus, [0] ResponseBytes { responseType, response } }
+    let response_bytes = find_element(der_content(response, TAG_SEQUENCE)?, TAG_CONTEXT_0)?;
+    let basic_response =
+        find_element(der_content(response_bytes, TAG_SEQUENCE)?, TAG_OCTET_STRING)?;
+
+    // BasicOCSPResponse { ResponseData { [0] version, responderID, producedAt, responses }, .. }
+    let response_data = find_element(der_content(basic_response, TAG_SEQUENCE)?, TAG_SEQUENCE)?;
+    let responses = find_element(response_data, TAG_SEQUENCE)?;
+
+    // SingleResponse { certID, certStatus, thisUpdate, [0] nextUpdate }, a good status is an
+    // implicit [0] and doesn't get in the way
+    let single_response = find_element(responses, TAG_SEQUENCE)?;
+    let next_update = find_element(single_response, TAG_CONTEXT_0)?;
+
+    parse_generalized_time(der_content(next_update, TAG_GENERALIZED_TIME)?)
+}
+
+/// Responder URL and DER encoded OCSP request asking about `cert_der`, issued by `issuer_der`.
+pub fn ocsp_request(cert_der: &[u8], issuer_der: &[u8]) -> Result<(String, Vec<u8>)> {
+    let (_, cert) = X509Certificate::from_der(cert_der)
+        .map_err(|e| anyhow!("Failed to parse certificate: {:?}", e))?;
+    let (_, issuer) = X509Certificate::from_der(issuer_der)
+        .map_err(|e| anyhow!("Failed to parse issuer certificate: {:?}", e))?;
+
+    let responder_url = cert
+        .extensions()
+        .iter()
+        .find_map(|extension| match extension.parsed_extension() {
+            ParsedExtension::AuthorityInfoAccess(aia) => {
+                aia.accessdescs.iter().find_map(|description| {
+                    match (&description.access_method, &description.access_location) {
+                        (method, GeneralName::URI(uri))
+                            if *method == OID_PKIX_ACCESS_DESCRIPTOR_OCSP =>
+                        {
+                            Some(uri.to_string())
+                        }
+                        _ => None,
+                    }
+                })
+            }
+            _ => None,
+        })
+        .ok_or_else(|| anyhow!("Certificate has no OCSP responder"))?;
+
+    let issuer_name_hash = Sha1::digest(cert.issuer().as_raw());
+    let issuer_key_hash = Sha1::digest(&issuer.public_key().subject_public_key.data);
+
+    // CertID { hashAlgorithm, issuerNameHash, issuerKeyHash, serialNumber }
+    let mut cert_id = SHA1_ALGORITHM_IDENTIFIER.to_vec();
+    cert_id.extend(der(TAG_OCTET_STRING, &issuer_name_hash));
+    cert_id.extend(der(TAG_OCTET_STRING, &issuer_key_hash));
+    cert_id.extend(der(TAG_INTEGER, cert.raw_serial()));
+
+    // OCSPRequest { TBSRequest { requestList [ Request { reqCert } ] } }
+    let request = der(TAG_SEQUENCE, &der(TAG_SEQUENCE, &cert_id));
+    let request_list = der(TAG_SEQUENCE, &request);
+    let tbs_request = der(TAG_SEQUENCE, &request_list);
+
+    Ok((responder_url, der(TAG_SEQUENCE, &tbs_request)))
+}
+
+/// Whether the responder answered with `successful`, anything else isn't worth stapling.
+pub fn ocsp_response_successful(response: &[u8]) -> bool {
+    let Some((TAG_SEQUENCE, header_len, _)) = der_header(response) else {
+        return false;
+    };
+
+    let status = &response[header_len..];
+    matches!(der_header(status), Some((TAG_ENUMERATED, 2, 1))) && status.get(2) == Some(&0)
+}
+
+pub async fn fetch_ocsp_response(
+    client: &reqwest::Client,
+    responder_url: &str,
+    request: Vec<u8>,
+) -> Result<Vec<u8>> {
+    let response = client
+        .post(responder_url)
+        .header("content-type", "application/ocsp-request")
+        .timeout(OCSP_FETCH_TIMEOUT)
+        .body(request)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+
+    if !ocsp_response_successful(&response) {
+        bail!(
+            "OCSP responder {} didn't answer successfully",
+            responder_url
+        );
+    }
+
+    Ok(response.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_der_length() {
+        assert_eq!(der(TAG_INTEGER, &[1]), vec![0x02, 0x01, 0x01]);
+
+        let long = der(TAG_OCTET_STRING, &[0u8; 300]);
+        assert_eq!(&long[..4], &[0x04, 0x82, 0x01, 0x2c]);
+        assert_eq!(der_header(&long), Some((TAG_OCTET_STRING, 4, 300)));
+    }
+
+    #[test]
+    fn test_ocsp_response_successful() {
+        assert!(ocsp_response_successful(&[0x30, 0x03, 0x0a, 0x01, 0x00]));
+        // tryLater
+        assert!(!ocsp_response_successful(&[0x30, 0x03, 0x0a, 0x01, 0x03]));
+        assert!(!ocsp_response_successful(&[]));
+    }
+
+    #[test]
+    fn test_ocsp_next_update() {
+        let single_response = der(
+            TAG_SEQUENCE,
+            &[
+                der(TAG_SEQUENCE, SHA1_ALGORITHM_IDENTIFIER),
+                vec![0x80, 0x00],
+                der(TAG_GENERALIZED_TIME, b"20260101000000Z"),
+                der(
+                    TAG_CONTEXT_0,
+                    &der(TAG_GENERALIZED_TIME, b"20260108000000.5Z"),
+                ),
+            ]
+            .concat(),
+        );
+        let response_data = der(
+            TAG_SEQUENCE,
+            &[
+                der(0xa2, &der(TAG_OCTET_STRING, &[1; 20])),
+                der(TAG_GENERALIZED_TIME, b"20260101000000Z"),
+                der(TAG_SEQUENCE, &single_response),
+            ]
+            .concat(),
+        );
+        let basic_response = der(
+            TAG_SEQUENCE,
+            &[response_data, SHA1_ALGORITHM_IDENTIFIER.to_vec()].concat(),
+        );
+        let response_bytes = der(
+            TAG_SEQUENCE,
+            &[
+                der(
+                    0x06,
+                    &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01],
+                ),
+                der(TAG_OCTET_STRING, &basic_response),
+            ]
+            .concat(),
+        );
+        let response = der(
+            TAG_SEQUENCE,
+            &[vec![0x0a, 0x01, 0x00], der(TAG_CONTEXT_0, &response_bytes)].concat(),
+        );
+
+        assert_eq!(
+            ocsp_next_update(&response),
+            Some(UNIX_EPOCH + Duration::from_secs(1767830400))
+        );
+        assert_eq!(ocsp_next_update(&[0x30, 0x03, 0x0a, 0x01, 0x00]), None);
+    }
+}
@@ -1,9 +1,17 @@
+use std::time::Duration;
+
 use anyhow::Result;
-use tokio::net::TcpStream;
+use tokio::{net::TcpStream, time::sleep};
 
 // ProtocolExtensions
 const PG_SSL_REQUEST_CODE: [u8; 4] = [0x04, 0xD2, 0x16, 0x2F]; // PostgreSQL SSLRequest code
 
+const TLS_RECORD_HEADER_LEN: usize = 5;
+const TLS_MAX_RECORD_LEN: usize = 16384;
+const TLS_HANDSHAKE_CLIENT_HELLO: u8 = 0x01;
+const TLS_EXTENSION_SERVER_NAME: u16 = 0x0000;
+const CLIENT_HELLO_PEEK_ATTEMPTS: usize = 20;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SniffedProtocol {
     Http,
@@ -36,3 +44,158 @@ pub async fn sniff_protocol(stream: &TcpStream) -> Result<SniffedProtocol> {
 
     Ok(SniffedProtocol::Unknown)
 }
+
+/// Reads the SNI of a TLS ClientHello without consuming it from the stream, so the connection can
+/// still be terminated by the proxy or forwarded as-is.
+pub async fn peek_sni(stream: &TcpStream) -> Result<Option<String>> {
+    let mut buf = vec![0u8; TLS_RECORD_HEADER_LEN + TLS_MAX_RECORD_LEN];
+
+    // the ClientHello can span several TCP segments, wait a bit for the whole record
+    for _ in 0..CLIENT_HELLO_PEEK_ATTEMPTS {
+        let n = stream.peek(&mut buf).await?;
+        if n >= TLS_RECORD_HEADER_LEN {
+            let record_len = u16::from_be_bytes([buf[3], buf[4]]) as usize;
+            if n >= TLS_RECORD_HEADER_LEN + record_len {
+                return Ok(parse_sni(&buf[..n]));
+            }
+        }
+
+        sleep(Duration::from_millis(10)).await;
+    }
+
+    Ok(None)
+}
+
+/// Extracts the server name from a TLS record holding a ClientHello.
+pub fn parse_sni(record: &[u8]) -> Option<String> {
+    let mut reader = Reader(record);
+
+    // record header: content type, version, length
+    reader.skip(3)?;
+    let record_len = reader.u16()? as usize;
+    let mut reader = Reader(reader.take(record_len)?);
+
+    if reader.u8()? != TLS_HANDSHAKE_CLIENT_HELLO {
+        return None;
+    }
+    let handshake_len = reader.u24()?;
+    let mut reader = Reader(reader.take(handshake_len)?);
+
+    // client version and random
+    reader.skip(2 + 32)?;
+    let session_id_len = reader.u8()? as usize;
+    reader.skip(session_id_len)?;
+    let cipher_suites_len = reader.u16()? as usize;
+    reader.skip(cipher_suites_len)?;
+    let compression_methods_len = reader.u8()? as usize;
+    reader.skip(compression_methods_len)?;
+
+    let extensions_len = reader.u16()? as usize;
+    let mut extensions = Reader(reader.take(extensions_len)?);
+
+    while !extensions.0.is_empty() {
+        let extension_type = extensions.u16()?;
+        let extension_len = extensions.u16()? as usize;
+        let mut extension = Reader(extensions.take(extension_len)?);
+
+        if extension_type != TLS_EXTENSION_SERVER_NAME {
+            continue;
+        }
+
+        let list_len = extension.u16()? as usize;
+        let mut list = Reader(extension.take(list_len)?);
+        while !list.0.is_empty() {
+            let name_type = list.u8()?;
+            let name_len = list.u16()? as usize;
+            let name = list.take(name_len)?;
+
+            // 0 = host_name
+            if name_type == 0 {
+                return std::str::from_utf8(name)
+                    .ok()
+                    .map(|name| name.to_ascii_lowercase());
+            }
+        }
+    }
+
+    None
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn skip(&mut self, len: usize) -> Option<()> {
+        self.take(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u24(&mut self) -> Option<usize> {
+        self.take(3)
+            .map(|b| ((b[0] as usize) << 16) | ((b[1] as usize) << 8) | b[2] as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client_hello(server_name: &str) -> Vec<u8> {
+        let name = server_name.as_bytes();
+
+        let mut sni = vec![];
+        sni.extend_from_slice(&((name.len() + 3) as u16).to_be_bytes());
+        sni.push(0);
+        sni.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        sni.extend_from_slice(name);
+
+        let mut extensions = vec![];
+        // an unrelated extension first (supported_versions)
+        extensions.extend_from_slice(&[0x00, 0x2b, 0x00, 0x03, 0x02, 0x03, 0x04]);
+        extensions.extend_from_slice(&TLS_EXTENSION_SERVER_NAME.to_be_bytes());
+        extensions.extend_from_slice(&(sni.len() as u16).to_be_bytes());
+        extensions.extend_from_slice(&sni);
+
+        let mut hello = vec![0x03, 0x03];
+        hello.extend_from_slice(&[0u8; 32]);
+        hello.push(0); // session id
+        hello.extend_from_slice(&[0x00, 0x02, 0x13, 0x01]); // cipher suites
+        hello.extend_from_slice(&[0x01, 0x00]); // compression methods
+        hello.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        hello.extend_from_slice(&extensions);
+
+        let mut handshake = vec![TLS_HANDSHAKE_CLIENT_HELLO];
+        handshake.extend_from_slice(&(hello.len() as u32).to_be_bytes()[1..]);
+        handshake.extend_from_slice(&hello);
+
+        let mut record = vec![0x16, 0x03, 0x01];
+        record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+        record.extend_from_slice(&handshake);
+        record
+    }
+
+    #[test]
+    fn test_parse_sni() {
+        let record = client_hello("API.example.com");
+        assert_eq!(parse_sni(&record), Some("api.example.com".to_string()));
+
+        // truncated records don't parse
+        assert_eq!(parse_sni(&record[..record.len() - 4]), None);
+    }
+}
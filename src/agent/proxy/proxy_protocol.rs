@@ -0,0 +1,75 @@
+use std::net::{IpAddr, SocketAddr};
+
+const SIGNATURE: [u8; 12] = [
+    0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+];
+
+const VERSION_2_PROXY: u8 = 0x21;
+const VERSION_2_LOCAL: u8 = 0x20;
+
+const FAMILY_UNSPEC: u8 = 0x00;
+const FAMILY_TCP4: u8 = 0x11;
+const FAMILY_TCP6: u8 = 0x21;
+
+/// Encodes a PROXY protocol v2 header for a proxied TCP connection. Without both addresses a
+/// `LOCAL` header is sent, which tells the upstream to use the connection's own addresses.
+pub fn encode_v2_header(source: Option<SocketAddr>, destination: Option<SocketAddr>) -> Vec<u8> {
+    let mut header = SIGNATURE.to_vec();
+
+    let (Some(source), Some(destination)) = (source, destination) else {
+        header.extend_from_slice(&[VERSION_2_LOCAL, FAMILY_UNSPEC, 0, 0]);
+        return header;
+    };
+
+    header.push(VERSION_2_PROXY);
+
+    match (source.ip(), destination.ip()) {
+        (IpAddr::V4(source_ip), IpAddr::V4(destination_ip)) => {
+            header.push(FAMILY_TCP4);
+            header.extend_from_slice(&12u16.to_be_bytes());
+            header.extend_from_slice(&source_ip.octets());
+            header.extend_from_slice(&destination_ip.octets());
+        }
+        (source_ip, destination_ip) => {
+            // mixed families are sent as IPv4-mapped IPv6 addresses
+            let to_v6 = |ip: IpAddr| match ip {
+                IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                IpAddr::V6(ip) => ip,
+            };
+
+            header.push(FAMILY_TCP6);
+            header.extend_from_slice(&36u16.to_be_bytes());
+            header.extend_from_slice(&to_v6(source_ip).octets());
+            header.extend_from_slice(&to_v6(destination_ip).octets());
+        }
+    }
+
+    header.extend_from_slice(&source.port().to_be_bytes());
+    header.extend_from_slice(&destination.port().to_be_bytes());
+
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_v2_header() {
+        let header = encode_v2_header(
+            Some("203.0.113.7:51234".parse().unwrap()),
+            Some("10.0.0.1:5432".parse().unwrap()),
+        );
+
+        assert_eq!(&header[..12], &SIGNATURE);
+        assert_eq!(&header[12..16], &[0x21, 0x11, 0x00, 0x0C]);
+        assert_eq!(&header[16..20], &[203, 0, 113, 7]);
+        assert_eq!(&header[20..24], &[10, 0, 0, 1]);
+        assert_eq!(&header[24..26], &51234u16.to_be_bytes());
+        assert_eq!(&header[26..28], &5432u16.to_be_bytes());
+        assert_eq!(header.len(), 28);
+
+        let local = encode_v2_header(None, None);
+        assert_eq!(&local[12..], &[0x20, 0x00, 0x00, 0x00]);
+    }
+}
@@ -0,0 +1,142 @@
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU32, Ordering},
+    },
+    time::Instant,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    pub requests_per_second: Option<u32>,
+    /// Requests allowed above the steady rate, defaults to `requests_per_second`.
+    pub burst: Option<u32>,
+    pub max_connections: Option<u32>,
+}
+
+/// Token bucket for requests plus a counter for concurrent connections, shared by every
+/// connection that resolves to the same binding.
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    bucket: Mutex<TokenBucket>,
+    connections: AtomicU32,
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Held for the lifetime of a connection, releases the slot on drop.
+pub struct ConnectionPermit {
+    limiter: Arc<RateLimiter>,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.limiter.connections.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        let capacity = Self::capacity(&config);
+
+        Self {
+            config,
+            bucket: Mutex::new(TokenBucket {
+                tokens: capacity,
+                last_refill: Instant::now(),
+            }),
+            connections: AtomicU32::new(0),
+        }
+    }
+
+    fn capacity(config: &RateLimitConfig) -> f64 {
+        config
+            .burst
+            .or(config.requests_per_second)
+            .unwrap_or_default()
+            .max(1) as f64
+    }
+
+    pub fn config(&self) -> &RateLimitConfig {
+        &self.config
+    }
+
+    pub fn try_acquire_request(&self) -> bool {
+        let Some(rate) = self.config.requests_per_second else {
+            return true;
+        };
+
+        let capacity = Self::capacity(&self.config);
+        let mut bucket = self.bucket.lock().expect("rate limit bucket poisoned");
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate as f64).min(capacity);
+        bucket.last_refill = now;
+
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    pub fn try_acquire_connection(self: &Arc<Self>) -> Option<ConnectionPermit> {
+        let Some(max_connections) = self.config.max_connections else {
+            self.connections.fetch_add(1, Ordering::AcqRel);
+            return Some(ConnectionPermit {
+                limiter: self.clone(),
+            });
+        };
+
+        self.connections
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                (current < max_connections).then_some(current + 1)
+            })
+            .ok()
+            .map(|_| ConnectionPermit {
+                limiter: self.clone(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burst_then_limited() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            requests_per_second: Some(1),
+            burst: Some(3),
+            max_connections: None,
+        });
+
+        assert!(limiter.try_acquire_request());
+        assert!(limiter.try_acquire_request());
+        assert!(limiter.try_acquire_request());
+        assert!(!limiter.try_acquire_request());
+    }
+
+    #[test]
+    fn test_connection_permits_are_released() {
+        let limiter = Arc::new(RateLimiter::new(RateLimitConfig {
+            requests_per_second: None,
+            burst: None,
+            max_connections: Some(1),
+        }));
+
+        let permit = limiter.try_acquire_connection();
+        assert!(permit.is_some());
+        assert!(limiter.try_acquire_connection().is_none());
+
+        drop(permit);
+        assert!(limiter.try_acquire_connection().is_some());
+    }
+}
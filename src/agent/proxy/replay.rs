@@ -0,0 +1,150 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{Result, anyhow};
+use bytes::Bytes;
+use http_body_util::{BodyExt, combinators::BoxBody};
+use hyper::{Request, Response, body::Incoming};
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+use crate::agent::{
+    machine::MachineAgent,
+    proxy::{
+        ProxyBinding,
+        buffer::{BufferedRequest, is_bufferable},
+        mirror::mirror_request,
+        retry::backoff_delay,
+        send_upstream_request,
+        timeouts::bounded,
+    },
+};
+
+/// Attempts at sending a buffered request to a machine that was just woken up, though the
+/// request timeout usually cuts it shorter.
+const REPLAY_ATTEMPTS: u32 = 8;
+const REPLAY_MAX_DELAY: Duration = Duration::from_secs(1);
+
+/// A request on its way upstream, buffered when it may have to be sent more than once.
+pub enum UpstreamRequest {
+    Streamed(Request<BoxBody<Bytes, hyper::Error>>),
+    Buffered {
+        req: BufferedRequest,
+        /// Resend the request when the upstream fails, the machine may still be starting. Only
+        /// idempotent requests are resent after reaching the app, the others only when the
+        /// connection couldn't be made.
+        replay: bool,
+        /// Retry the request under the binding's retry policy.
+        retry: bool,
+    },
+}
+
+impl UpstreamRequest {
+    /// Buffers the request when it gets mirrored, when its machine was woken up for it so it
+    /// can be replayed while the app comes up, or when the retry policy covers it.
+    pub async fn prepare(
+        machine_agent: &Arc<MachineAgent>,
+        binding: &ProxyBinding,
+        req: Request<Incoming>,
+        woken: bool,
+    ) -> Result<Self> {
+        let retry = binding.retry_policy.as_ref().is_some_and(|policy| {
+            policy.record_request();
+            policy.attempts() > 1 && req.method().is_idempotent()
+        });
+        let mirror = binding
+            .mirror
+            .as_ref()
+            .is_some_and(|mirror| mirror.sample());
+
+        if !(mirror || woken || retry) || !is_bufferable(req.headers()) {
+            return Ok(UpstreamRequest::Streamed(req.map(|body| body.boxed())));
+        }
+
+        let req = BufferedRequest::read(req).await?;
+        if mirror {
+            mirror_request(machine_agent, binding, &req);
+        }
+
+        Ok(UpstreamRequest::Buffered {
+            req,
+            replay: woken,
+            retry,
+        })
+    }
+
+    pub async fn send(
+        self,
+        binding: &ProxyBinding,
+        ip_address: &str,
+    ) -> Result<Response<Incoming>> {
+        let (req, replay, retry) = match self {
+            UpstreamRequest::Streamed(req) => {
+                return send_upstream_request(binding, ip_address, req).await;
+            }
+            UpstreamRequest::Buffered { req, replay, retry } => (req, replay, retry),
+        };
+
+        let retry_policy = binding.retry_policy.as_ref().filter(|_| retry);
+        let per_try_timeout = retry_policy.and_then(|policy| policy.per_try_timeout());
+
+        let mut attempt = 0;
+        loop {
+            let result = bounded(
+                per_try_timeout,
+                send_upstream_request(binding, ip_address, req.to_request()),
+            )
+            .await
+            .unwrap_or_else(|| Err(anyhow!("Upstream request timed out")));
+
+            let e = match result {
+                Ok(response) => {
+                    if attempt > 0 {
+                        info!(
+                            "Request to {} succeeded after {} attempts",
+                            ip_address,
+                            attempt + 1
+                        );
+                    }
+                    return Ok(response);
+                }
+                Err(e) => e,
+            };
+
+            // replays while the machine wakes up don't count against the retry budget
+            let can_replay = replay
+                && attempt + 1 < REPLAY_ATTEMPTS
+                && (req.is_idempotent() || is_connect_error(&e));
+            let can_retry = !can_replay
+                && retry_policy.is_some_and(|policy| {
+                    attempt + 1 < policy.attempts() && policy.try_acquire_retry()
+                });
+
+            let delay = match (can_replay, can_retry) {
+                (true, _) => backoff_delay(attempt, rand::random()).min(REPLAY_MAX_DELAY),
+                (false, true) => backoff_delay(attempt, rand::random()),
+                (false, false) => return Err(e),
+            };
+
+            warn!(
+                "Request to {} failed (attempt {}), retrying in {}ms: {}",
+                ip_address,
+                attempt + 1,
+                delay.as_millis(),
+                e
+            );
+            sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// Errors connecting to the upstream, before any of the request was sent.
+fn is_connect_error(e: &anyhow::Error) -> bool {
+    if let Some(e) = e.downcast_ref::<hyper_util::client::legacy::Error>() {
+        return e.is_connect();
+    }
+
+    // upstream TLS connections fail with IO errors while connecting and during the handshake,
+    // the request itself fails with hyper errors
+    e.downcast_ref::<std::io::Error>().is_some()
+}
@@ -0,0 +1,123 @@
+use std::{sync::Mutex, time::Duration};
+
+/// Attempts at connecting to a machine before giving up, mostly to get over the moment a
+/// machine woken up by the request isn't listening yet.
+pub const CONNECT_ATTEMPTS: u32 = 4;
+/// Sent as `Retry-After` when the machine couldn't be reached at all.
+pub const RETRY_AFTER_SECS: u64 = 2;
+
+const BACKOFF_BASE: Duration = Duration::from_millis(100);
+const BACKOFF_MAX: Duration = Duration::from_secs(2);
+
+const DEFAULT_RETRY_ATTEMPTS: u32 = 3;
+const DEFAULT_RETRY_BUDGET_PERCENT: u8 = 20;
+/// Retries the budget starts with, so a binding can retry before it has seen much traffic. Once
+/// spent, retries are only earned back by requests.
+const RETRY_BUDGET_INITIAL_TOKENS: f64 = 10.0;
+const RETRY_BUDGET_MAX_TOKENS: f64 = 100.0;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryConfig {
+    /// Sends of a request in total, including the first one, defaults to 3.
+    pub attempts: Option<u32>,
+    pub per_try_timeout: Option<Duration>,
+    /// Retries allowed as a percentage of the requests, defaults to 20.
+    pub budget_percent: Option<u8>,
+}
+
+/// Retries idempotent HTTP requests on upstream failures. The budget keeps retries to a share of
+/// the traffic, so a failing upstream doesn't get its load multiplied.
+#[derive(Debug)]
+pub struct RetryPolicy {
+    config: RetryConfig,
+    budget: Mutex<f64>,
+}
+
+impl RetryPolicy {
+    pub fn new(config: RetryConfig) -> Self {
+        Self {
+            config,
+            budget: Mutex::new(RETRY_BUDGET_INITIAL_TOKENS),
+        }
+    }
+
+    pub fn config(&self) -> &RetryConfig {
+        &self.config
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.config
+            .attempts
+            .unwrap_or(DEFAULT_RETRY_ATTEMPTS)
+            .max(1)
+    }
+
+    pub fn per_try_timeout(&self) -> Option<Duration> {
+        self.config.per_try_timeout
+    }
+
+    /// Grows the budget by the share of a request.
+    pub fn record_request(&self) {
+        let percent = self
+            .config
+            .budget_percent
+            .unwrap_or(DEFAULT_RETRY_BUDGET_PERCENT);
+
+        let mut budget = self.budget.lock().expect("retry budget poisoned");
+        *budget = (*budget + percent as f64 / 100.0).min(RETRY_BUDGET_MAX_TOKENS);
+    }
+
+    /// Takes a retry out of the budget, if there is one left.
+    pub fn try_acquire_retry(&self) -> bool {
+        let mut budget = self.budget.lock().expect("retry budget poisoned");
+        if *budget < 1.0 {
+            return false;
+        }
+
+        *budget -= 1.0;
+        true
+    }
+}
+
+/// Exponential backoff before retrying after the `attempt`-th failure (starting at 0), jittered
+/// over the upper half of the interval with `jitter` in `[0, 1)` so woken machines don't get
+/// every pending connection at once.
+pub fn backoff_delay(attempt: u32, jitter: f64) -> Duration {
+    let ceiling = BACKOFF_BASE
+        .saturating_mul(1 << attempt.min(16))
+        .min(BACKOFF_MAX);
+
+    ceiling / 2 + ceiling.mul_f64(jitter.clamp(0.0, 1.0)) / 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_delay() {
+        assert_eq!(backoff_delay(0, 0.0), Duration::from_millis(50));
+        assert_eq!(backoff_delay(1, 1.0), Duration::from_millis(200));
+        assert!(backoff_delay(2, 0.5) > backoff_delay(1, 0.5));
+        assert_eq!(backoff_delay(20, 1.0), BACKOFF_MAX);
+    }
+
+    #[test]
+    fn test_retry_budget() {
+        let policy = RetryPolicy::new(RetryConfig {
+            attempts: None,
+            per_try_timeout: None,
+            budget_percent: Some(50),
+        });
+
+        for _ in 0..RETRY_BUDGET_INITIAL_TOKENS as usize {
+            assert!(policy.try_acquire_retry());
+        }
+        assert!(!policy.try_acquire_retry());
+
+        policy.record_request();
+        assert!(!policy.try_acquire_retry());
+        policy.record_request();
+        assert!(policy.try_acquire_retry());
+    }
+}
@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+use axum::http::{HeaderMap, HeaderValue};
+
+pub const DEFAULT_STICKY_COOKIE: &str = "lttle-affinity";
+
+/// Cookie based session affinity: the first response pins the client to its machine, later
+/// requests go back to it for as long as it is around and not ejected.
+#[derive(Clone, Debug)]
+pub struct StickySessions {
+    cookie_name: String,
+    /// Session cookie when unset.
+    max_age: Option<Duration>,
+}
+
+impl StickySessions {
+    pub fn new(cookie_name: Option<String>, max_age: Option<Duration>) -> Self {
+        Self {
+            cookie_name: cookie_name.unwrap_or(DEFAULT_STICKY_COOKIE.to_string()),
+            max_age,
+        }
+    }
+
+    /// The affinity token the client sent, if any.
+    pub fn affinity(&self, headers: &HeaderMap) -> Option<String> {
+        headers
+            .get_all("cookie")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .filter_map(|pair| pair.trim().split_once('='))
+            .find(|(name, _)| *name == self.cookie_name)
+            .map(|(_, value)| value.trim_matches('"').to_string())
+    }
+
+    /// Pins the client to `machine_name` unless it already is.
+    pub fn pin(
+        &self,
+        headers: &mut HeaderMap,
+        machine_name: &str,
+        affinity: Option<&str>,
+        secure: bool,
+    ) {
+        let token = affinity_token(machine_name);
+        if affinity == Some(token.as_str()) {
+            return;
+        }
+
+        let mut cookie = format!(
+            "{}={}; Path=/; HttpOnly; SameSite=Lax",
+            self.cookie_name, token
+        );
+        if let Some(max_age) = self.max_age {
+            cookie.push_str(&format!("; Max-Age={}", max_age.as_secs()));
+        }
+        if secure {
+            cookie.push_str("; Secure");
+        }
+
+        if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+            headers.append("set-cookie", cookie);
+        }
+    }
+}
+
+/// Opaque token standing for a machine, so the cookie doesn't reveal internal names. Stable
+/// across restarts and nodes, so clients keep their machine whichever proxy they reach.
+pub fn affinity_token(machine_name: &str) -> String {
+    let hash = blake3::hash(machine_name.as_bytes());
+    hex::encode(&hash.as_bytes()[..8])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_affinity_token_is_stable() {
+        assert_eq!(affinity_token("machine-a"), "4894f3d8b82640e3");
+        assert_ne!(affinity_token("machine-a"), affinity_token("machine-b"));
+    }
+
+    #[test]
+    fn test_sticky_sessions_cookie() {
+        let sticky = StickySessions::new(None, Some(Duration::from_secs(3600)));
+
+        let mut headers = HeaderMap::new();
+        sticky.pin(&mut headers, "machine-a", None, true);
+        let cookie = headers["set-cookie"].to_str().unwrap().to_string();
+        assert!(cookie.starts_with(&format!(
+            "{}={}",
+            DEFAULT_STICKY_COOKIE,
+            affinity_token("machine-a")
+        )));
+        assert!(cookie.contains("Max-Age=3600"));
+        assert!(cookie.ends_with("; Secure"));
+
+        let mut request = HeaderMap::new();
+        request.insert(
+            "cookie",
+            HeaderValue::from_str(&format!(
+                "theme=dark; {}",
+                cookie.split(';').next().unwrap()
+            ))
+            .unwrap(),
+        );
+        let affinity = sticky.affinity(&request);
+        assert_eq!(affinity, Some(affinity_token("machine-a")));
+
+        // already pinned to the same machine, nothing to set
+        let mut headers = HeaderMap::new();
+        sticky.pin(&mut headers, "machine-a", affinity.as_deref(), true);
+        assert!(!headers.contains_key("set-cookie"));
+
+        sticky.pin(&mut headers, "machine-b", affinity.as_deref(), true);
+        assert!(headers.contains_key("set-cookie"));
+    }
+}
@@ -0,0 +1,77 @@
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+/// Limits on a binding's upstream traffic, unset ones keeping the proxy defaults.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProxyTimeouts {
+    /// Each attempt at opening a connection to the machine.
+    pub connect: Option<Duration>,
+    /// An HTTP request until the upstream response headers, including waking the machine.
+    pub request: Option<Duration>,
+    /// Forwarded TCP streams and UDP sessions get closed after this long without traffic.
+    pub idle: Option<Duration>,
+    /// Waiting for the upstream response headers once the request was sent.
+    pub upstream_response: Option<Duration>,
+}
+
+impl ProxyTimeouts {
+    /// What's left of the request timeout for a request received at `started_at`.
+    pub fn request_remaining(&self, started_at: Instant) -> Option<Duration> {
+        self.request
+            .map(|request| request.saturating_sub(started_at.elapsed()))
+    }
+
+    /// How long to wait for the upstream response: the tightest of the upstream response
+    /// timeout and what's left of the request timeout.
+    pub fn response_remaining(&self, started_at: Instant) -> Option<Duration> {
+        match (self.upstream_response, self.request_remaining(started_at)) {
+            (Some(response), Some(remaining)) => Some(response.min(remaining)),
+            (response, remaining) => response.or(remaining),
+        }
+    }
+}
+
+/// Runs `future` to completion unless `limit` elapses first, in which case `None` is returned.
+pub async fn bounded<F: Future>(limit: Option<Duration>, future: F) -> Option<F::Output> {
+    match limit {
+        Some(limit) => tokio::time::timeout(limit, future).await.ok(),
+        None => Some(future.await),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_response_remaining() {
+        let started_at = Instant::now();
+
+        let timeouts = ProxyTimeouts {
+            request: Some(Duration::from_secs(60)),
+            upstream_response: Some(Duration::from_secs(5)),
+            ..Default::default()
+        };
+        assert_eq!(
+            timeouts.response_remaining(started_at),
+            Some(Duration::from_secs(5))
+        );
+
+        let timeouts = ProxyTimeouts {
+            request: Some(Duration::from_secs(1)),
+            ..Default::default()
+        };
+        assert!(
+            timeouts
+                .response_remaining(started_at)
+                .is_some_and(|remaining| remaining <= Duration::from_secs(1))
+        );
+
+        assert_eq!(
+            ProxyTimeouts::default().response_remaining(started_at),
+            None
+        );
+    }
+}
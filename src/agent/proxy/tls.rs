@@ -1,17 +1,54 @@
 use std::{
+    fmt,
     path::{Path, PathBuf},
     sync::Arc,
+    time::{Duration, SystemTime},
 };
 
 use anyhow::{Result, bail};
-use papaya::HashMap;
+use papaya::{Compute, HashMap, Operation};
 use rustls::{
     crypto::CryptoProvider,
     pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
     server::{ClientHello, ResolvesServerCert},
-    sign::{self, CertifiedKey},
+    sign::CertifiedKey,
 };
-use tracing::{info, warn};
+use tokio::{spawn, time::interval};
+use tracing::{debug, info, warn};
+
+use crate::agent::{
+    certificate::CertificateAgent,
+    proxy::{
+        host::wildcard_parent,
+        ocsp::{
+            OCSP_REFRESH_INTERVAL, OCSP_RETRY_INTERVAL, fetch_ocsp_response, ocsp_next_update,
+            ocsp_request,
+        },
+    },
+};
+
+/// How often pooled certificates are checked for rotation.
+const CERT_RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often pooled certificates are checked for a missing or stale OCSP staple.
+const OCSP_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy)]
+struct OcspState {
+    stapled_at: Option<SystemTime>,
+    /// `nextUpdate` of the stapled response, the staple is dropped past it.
+    expires_at: Option<SystemTime>,
+    attempted_at: SystemTime,
+}
+
+/// Where a pooled certificate was loaded from, telling when it changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CertSource {
+    /// `updated_at` of the certificate in the store.
+    Store(u64),
+    /// Modification time of the files in the disk cache.
+    Disk(SystemTime),
+}
 
 pub async fn load_cert_from_disk(
     cert_file: impl AsRef<Path>,
@@ -44,71 +81,292 @@ pub async fn load_cert_from_disk(
         bail!("Failed to load key from {key_file:?}");
     };
 
-    let Ok(cert_key) = sign::CertifiedKey::from_der(cert_der, key, crypto_provider) else {
-        warn!("Failed to create certified key from certificate and private key");
-        bail!("Failed to load certificate from {cert_file:?}");
+    let cert_key = match certified_key(cert_der, key, crypto_provider) {
+        Ok(cert_key) => cert_key,
+        Err(e) => {
+            warn!("Failed to create certified key from certificate and private key: {e}");
+            bail!("Failed to load certificate from {cert_file:?}");
+        }
     };
 
     info!("Successfully loaded TLS certificate and key");
     Ok(Arc::new(cert_key))
 }
 
-#[derive(Debug)]
+/// Pairs a chain with its private key, refusing a key that isn't the one of the leaf, eg. the
+/// files of a certificate rotated one after the other.
+fn certified_key(
+    cert_der: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+    crypto_provider: &CryptoProvider,
+) -> Result<CertifiedKey> {
+    let cert_key = CertifiedKey::from_der(cert_der, key, crypto_provider)?;
+    cert_key.keys_match()?;
+
+    Ok(cert_key)
+}
+
+/// Serves the certificates kept in the store by the certificate agent, falling back to the ones
+/// on disk, either cached or from before certificates moved to the store.
 #[allow(unused)]
 pub struct ProxyTlsCertResolver {
     cert_pool: Arc<HashMap<String, Arc<CertifiedKey>>>,
+    /// Version of the certificate each pooled one was loaded from.
+    cert_sources: HashMap<String, CertSource>,
+    /// OCSP staple of each pooled certificate, reset when the certificate is reloaded.
+    ocsp_states: HashMap<String, OcspState>,
+    http_client: reqwest::Client,
     default_cert: Arc<CertifiedKey>,
     crypto_provider: Arc<CryptoProvider>,
+    certificate_agent: Arc<CertificateAgent>,
     certs_base_dir: PathBuf,
 }
 
+impl fmt::Debug for ProxyTlsCertResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProxyTlsCertResolver")
+            .field("cert_pool_len", &self.cert_pool.len())
+            .field("certs_base_dir", &self.certs_base_dir)
+            .finish()
+    }
+}
+
 impl ProxyTlsCertResolver {
     pub fn new(
         cert_pool: Arc<HashMap<String, Arc<CertifiedKey>>>,
         default_cert: Arc<CertifiedKey>,
         crypto_provider: Arc<CryptoProvider>,
-        certs_base_dir: PathBuf,
+        certificate_agent: Arc<CertificateAgent>,
     ) -> Self {
         info!(
             "Creating new TLS certificate resolver with {} certificates in pool",
             cert_pool.len()
         );
+        let certs_base_dir = PathBuf::from(certificate_agent.config().certs_base_dir.clone());
         Self {
             cert_pool,
+            cert_sources: HashMap::new(),
+            ocsp_states: HashMap::new(),
+            http_client: reqwest::Client::new(),
             default_cert,
             crypto_provider,
+            certificate_agent,
             certs_base_dir,
         }
     }
 
+    /// Reloads the certificates of `domains`, dropping the ones that are gone. The previous
+    /// certificate keeps being served until the new one loads.
     pub fn invalidate_cert_cache_for_domains(&self, domains: Vec<String>) {
         info!("Invalidating certificate cache for domains: {:?}", domains);
-        let cert_pool = self.cert_pool.pin();
         for domain in domains {
-            cert_pool.remove(&domain);
+            self.reload_cert(&domain);
         }
     }
 
-    pub fn resolve_cert(&self, host: &str) -> Option<Arc<CertifiedKey>> {
+    /// Picks up certificates rotated without going through the proxy agent, eg. renewed by
+    /// another node or process, checking the pooled ones every few seconds.
+    pub fn watch_certs(self: Arc<Self>) {
+        spawn(async move {
+            let mut ticker = interval(CERT_RELOAD_INTERVAL);
+            loop {
+                ticker.tick().await;
+
+                let domains = self.cert_pool.pin().keys().cloned().collect::<Vec<_>>();
+                for domain in domains {
+                    let source = self.cert_source(&domain);
+                    if source != self.cert_sources.pin().get(&domain).copied() {
+                        info!("Certificate for {} changed", domain);
+                        self.reload_cert(&domain);
+                    }
+                }
+            }
+        });
+    }
+
+    /// Staples OCSP responses to the pooled certificates, so clients don't have to ask the
+    /// responder themselves during the handshake.
+    pub fn watch_ocsp(self: Arc<Self>) {
+        spawn(async move {
+            let mut ticker = interval(OCSP_CHECK_INTERVAL);
+            loop {
+                ticker.tick().await;
+
+                let domains = self.cert_pool.pin().keys().cloned().collect::<Vec<_>>();
+                for domain in domains {
+                    self.drop_expired_staple(&domain);
+                    if self.ocsp_due(&domain) {
+                        self.staple_ocsp(&domain).await;
+                    }
+                }
+            }
+        });
+    }
+
+    fn ocsp_due(&self, name: &str) -> bool {
+        let Some(state) = self.ocsp_states.pin().get(name).copied() else {
+            return true;
+        };
+
+        // the state may outlive a staple when the certificate is reloaded meanwhile
+        let stapled = self
+            .cert_pool
+            .pin()
+            .get(name)
+            .is_some_and(|cert| cert.ocsp.is_some());
+
+        let elapsed = |time: SystemTime| time.elapsed().unwrap_or_default();
+        match state.stapled_at {
+            Some(stapled_at) if stapled && elapsed(stapled_at) < OCSP_REFRESH_INTERVAL => false,
+            _ => elapsed(state.attempted_at) >= OCSP_RETRY_INTERVAL,
+        }
+    }
+
+    /// Clients reject a staple past its `nextUpdate`, the certificate is better served without.
+    fn drop_expired_staple(&self, name: &str) {
+        let Some(state) = self.ocsp_states.pin().get(name).copied() else {
+            return;
+        };
+        if !state
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= SystemTime::now())
+        {
+            return;
+        }
+
+        let cert_pool = self.cert_pool.pin();
+        let result = cert_pool.compute(name.to_string(), |entry| match entry {
+            Some((_, cert)) if cert.ocsp.is_some() => {
+                let mut unstapled = (**cert).clone();
+                unstapled.ocsp = None;
+                Operation::Insert(Arc::new(unstapled))
+            }
+            _ => Operation::Abort(()),
+        });
+
+        self.ocsp_states.pin().insert(
+            name.to_string(),
+            OcspState {
+                stapled_at: None,
+                expires_at: None,
+                ..state
+            },
+        );
+        if matches!(result, Compute::Updated { .. }) {
+            info!("Dropped expired OCSP staple of {}", name);
+        }
+    }
+
+    async fn staple_ocsp(&self, name: &str) {
+        let Some(cert) = self.cert_pool.pin().get(name).cloned() else {
+            return;
+        };
+
+        let previous = self.ocsp_states.pin().get(name).copied();
+        let mut state = OcspState {
+            stapled_at: previous.and_then(|state| state.stapled_at),
+            expires_at: previous.and_then(|state| state.expires_at),
+            attempted_at: SystemTime::now(),
+        };
+        self.ocsp_states.pin().insert(name.to_string(), state);
+
+        let (Some(leaf), Some(issuer)) = (cert.cert.first(), cert.cert.get(1)) else {
+            debug!("No issuer in the chain of {}, not stapling OCSP", name);
+            return;
+        };
+
+        let response = match ocsp_request(leaf, issuer) {
+            Ok((responder_url, request)) => {
+                fetch_ocsp_response(&self.http_client, &responder_url, request).await
+            }
+            Err(e) => Err(e),
+        };
+        let response = match response {
+            Ok(response) => response,
+            Err(e) => {
+                debug!("No OCSP response for {}: {}", name, e);
+                return;
+            }
+        };
+
+        let expires_at = ocsp_next_update(&response);
+        if expires_at.is_some_and(|expires_at| expires_at <= SystemTime::now()) {
+            debug!("OCSP response for {} has already expired", name);
+            return;
+        }
+
+        let mut stapled = (*cert).clone();
+        stapled.ocsp = Some(response);
+        let stapled = Arc::new(stapled);
+
+        // swapped only if the certificate wasn't rotated while the response was fetched, the
+        // new one gets its own
         let cert_pool = self.cert_pool.pin();
-        let cert = cert_pool.get(host);
+        let result = cert_pool.compute(name.to_string(), |entry| match entry {
+            Some((_, current)) if Arc::ptr_eq(current, &cert) => Operation::Insert(stapled.clone()),
+            _ => Operation::Abort(()),
+        });
+        if !matches!(result, Compute::Updated { .. }) {
+            return;
+        }
 
-        if let Some(cert) = cert {
+        state.stapled_at = Some(SystemTime::now());
+        state.expires_at = expires_at;
+        self.ocsp_states.pin().insert(name.to_string(), state);
+        info!("Stapled OCSP response for {}", name);
+    }
+
+    fn reload_cert(&self, name: &str) {
+        self.ocsp_states.pin().remove(name);
+
+        let Some(source) = self.cert_source(name) else {
+            self.cert_pool.pin().remove(name);
+            self.cert_sources.pin().remove(name);
+            return;
+        };
+
+        // a half written pair fails to load, it's retried once the files change again
+        let Some(cert) = self.try_load(name, source) else {
+            warn!("Keeping previous certificate for {}", name);
+            return;
+        };
+
+        self.cert_pool.pin().insert(name.to_string(), cert);
+        self.cert_sources.pin().insert(name.to_string(), source);
+        info!("Reloaded certificate for {}", name);
+    }
+
+    pub fn resolve_cert(&self, host: &str) -> Option<Arc<CertifiedKey>> {
+        if let Some(cert) = self.lookup_cert(host) {
             info!("Found specific certificate for host: {}", host);
-            return Some(cert.clone());
+            return Some(cert);
         }
 
-        // Attempt to load from disk on demand if present
-        if let Some(loaded) = self.try_load_from_disk(host) {
-            let cert_pool = self.cert_pool.pin();
-            cert_pool.insert(host.to_string(), loaded.clone());
-            info!("Loaded certificate for host {} from disk", host);
-            return Some(loaded);
+        if let Some(wildcard) = wildcard_parent(host) {
+            if let Some(cert) = self.lookup_cert(&wildcard) {
+                info!("Found wildcard certificate {} for host: {}", wildcard, host);
+                return Some(cert);
+            }
         }
 
         info!("Using default certificate for host: {}", host);
         Some(self.default_cert.clone())
     }
+
+    fn lookup_cert(&self, name: &str) -> Option<Arc<CertifiedKey>> {
+        let cert_pool = self.cert_pool.pin();
+        if let Some(cert) = cert_pool.get(name) {
+            return Some(cert.clone());
+        }
+
+        // Attempt to load on demand if present
+        let source = self.cert_source(name)?;
+        let loaded = self.try_load(name, source)?;
+        cert_pool.insert(name.to_string(), loaded.clone());
+        self.cert_sources.pin().insert(name.to_string(), source);
+        info!("Loaded certificate for {}", name);
+        Some(loaded)
+    }
 }
 
 impl ResolvesServerCert for ProxyTlsCertResolver {
@@ -125,6 +383,72 @@ impl ResolvesServerCert for ProxyTlsCertResolver {
 }
 
 impl ProxyTlsCertResolver {
+    fn cert_source(&self, host: &str) -> Option<CertSource> {
+        match self.certificate_agent.get_stored_certificate(host) {
+            Ok(Some(certificate)) => return Some(CertSource::Store(certificate.updated_at)),
+            Ok(None) => {}
+            Err(e) => warn!("Failed to look up stored certificate for {}: {}", host, e),
+        }
+
+        self.cert_files_modified_at(host).map(CertSource::Disk)
+    }
+
+    fn try_load(&self, host: &str, source: CertSource) -> Option<Arc<CertifiedKey>> {
+        match source {
+            CertSource::Store(_) => self.try_load_from_store(host),
+            CertSource::Disk(_) => self.try_load_from_disk(host),
+        }
+    }
+
+    fn try_load_from_store(&self, host: &str) -> Option<Arc<CertifiedKey>> {
+        let certificate = self
+            .certificate_agent
+            .get_stored_certificate(host)
+            .ok()
+            .flatten()?;
+
+        let (cert_chain_pem, private_key_pem) =
+            match self.certificate_agent.open_certificate(&certificate) {
+                Ok(pems) => pems,
+                Err(e) => {
+                    warn!("Failed to open stored certificate for {}: {}", host, e);
+                    return None;
+                }
+            };
+
+        let Ok(cert_der) = CertificateDer::pem_slice_iter(cert_chain_pem.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+        else {
+            warn!("Failed to parse stored certificate for host {}", host);
+            return None;
+        };
+
+        let Ok(key) = PrivateKeyDer::from_pem_slice(private_key_pem.as_bytes()) else {
+            warn!("Failed to parse stored private key for host {}", host);
+            return None;
+        };
+
+        let cert_key = match certified_key(cert_der, key, &self.crypto_provider) {
+            Ok(cert_key) => cert_key,
+            Err(e) => {
+                warn!("Failed to create certified key for host {}: {}", host, e);
+                return None;
+            }
+        };
+
+        Some(Arc::new(cert_key))
+    }
+
+    fn cert_files_modified_at(&self, host: &str) -> Option<SystemTime> {
+        let cert_path = self.certs_base_dir.join(format!("{}.cert", host));
+        let key_path = self.certs_base_dir.join(format!("{}.key", host));
+
+        let cert_modified_at = std::fs::metadata(cert_path).ok()?.modified().ok()?;
+        let key_modified_at = std::fs::metadata(key_path).ok()?.modified().ok()?;
+
+        Some(cert_modified_at.max(key_modified_at))
+    }
+
     fn try_load_from_disk(&self, host: &str) -> Option<Arc<CertifiedKey>> {
         let cert_path = self.certs_base_dir.join(format!("{}.cert", host));
         let key_path = self.certs_base_dir.join(format!("{}.key", host));
@@ -156,10 +480,12 @@ impl ProxyTlsCertResolver {
             return None;
         };
 
-        let Ok(cert_key) = sign::CertifiedKey::from_der(cert_der, key, &self.crypto_provider)
-        else {
-            warn!("Failed to create certified key for host {}", host);
-            return None;
+        let cert_key = match certified_key(cert_der, key, &self.crypto_provider) {
+            Ok(cert_key) => cert_key,
+            Err(e) => {
+                warn!("Failed to create certified key for host {}: {}", host, e);
+                return None;
+            }
         };
 
         Some(Arc::new(cert_key))
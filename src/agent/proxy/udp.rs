@@ -0,0 +1,263 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Result, bail};
+use papaya::HashMap as ConcurrentHashMap;
+use tokio::{net::UdpSocket, spawn, sync::Mutex, time::timeout};
+use tracing::{info, warn};
+
+use crate::agent::{
+    machine::{
+        MachineAgent,
+        machine::{Machine, MachineAwakeGuard},
+    },
+    proxy::{
+        ProxyAgentConfig, ProxyBinding, ProxyTransport, acquire_connection_permit, firewall_allows,
+        open_connection, record_traffic, record_upstream_error,
+    },
+};
+
+const UDP_MAX_DATAGRAM_SIZE: usize = 65535;
+const UDP_DEFAULT_SESSION_TIMEOUT: Duration = Duration::from_secs(60);
+/// Datagrams buffered per client while its machine is being woken up.
+const UDP_MAX_PENDING_DATAGRAMS: usize = 64;
+
+type UdpSessions = Arc<Mutex<HashMap<SocketAddr, UdpSession>>>;
+
+enum UdpSession {
+    Opening(Vec<Vec<u8>>),
+    Open {
+        upstream: Arc<UdpSocket>,
+        last_activity: Arc<std::sync::Mutex<Instant>>,
+        binding: ProxyBinding,
+    },
+}
+
+/// The UDP binding served on `server_key`, looked up again for every new session as bindings
+/// change while the listener runs.
+fn current_binding(
+    bindings: &ConcurrentHashMap<String, ProxyBinding>,
+    config: &ProxyAgentConfig,
+    server_key: &(String, u16),
+) -> Option<ProxyBinding> {
+    bindings
+        .pin()
+        .iter()
+        .find(|(_, binding)| {
+            binding.transport == ProxyTransport::Udp
+                && binding.proxy_server_key(config) == *server_key
+        })
+        .map(|(_, binding)| binding.clone())
+}
+
+/// Relays datagrams between clients and machines. Each client address gets its own session
+/// (and upstream socket), pinned to a machine by the hash of the flow's 5-tuple.
+pub async fn udp_listener(
+    server_key: (String, u16),
+    config: ProxyAgentConfig,
+    machine_agent: Arc<MachineAgent>,
+    bindings: Arc<ConcurrentHashMap<String, ProxyBinding>>,
+) -> Result<Infallible> {
+    let addr = format!("{}:{}", server_key.0, server_key.1);
+    info!("Starting UDP listener on {}", addr);
+
+    let socket = Arc::new(UdpSocket::bind(&addr).await?);
+    let local_addr = socket.local_addr()?;
+    let sessions: UdpSessions = Arc::new(Mutex::new(HashMap::new()));
+
+    let mut buf = vec![0u8; UDP_MAX_DATAGRAM_SIZE];
+    loop {
+        let (len, client_addr) = socket.recv_from(&mut buf).await?;
+        let datagram = &buf[..len];
+
+        let mut sessions_guard = sessions.lock().await;
+        match sessions_guard.get_mut(&client_addr) {
+            Some(UdpSession::Open {
+                upstream,
+                last_activity,
+                binding,
+            }) => {
+                *last_activity.lock().expect("udp session poisoned") = Instant::now();
+
+                let upstream = upstream.clone();
+                let binding = binding.clone();
+                drop(sessions_guard);
+
+                match upstream.send(datagram).await {
+                    Ok(len) => record_traffic(&binding, len as u64, 0),
+                    Err(e) => warn!("Failed to forward UDP datagram from {}: {}", client_addr, e),
+                }
+            }
+            Some(UdpSession::Opening(pending)) => {
+                if pending.len() < UDP_MAX_PENDING_DATAGRAMS {
+                    pending.push(datagram.to_vec());
+                }
+            }
+            None => {
+                // the binding went away, the listener is about to be stopped
+                let Some(binding) = current_binding(&bindings, &config, &server_key) else {
+                    continue;
+                };
+                if !firewall_allows(&binding, Some(client_addr)) {
+                    continue;
+                }
+
+                sessions_guard.insert(client_addr, UdpSession::Opening(vec![datagram.to_vec()]));
+                drop(sessions_guard);
+
+                let socket = socket.clone();
+                let sessions = sessions.clone();
+                let machine_agent = machine_agent.clone();
+
+                spawn(async move {
+                    if let Err(e) = run_session(
+                        socket,
+                        sessions.clone(),
+                        machine_agent,
+                        binding,
+                        client_addr,
+                        local_addr,
+                    )
+                    .await
+                    {
+                        warn!("UDP session for {} failed: {}", client_addr, e);
+                    }
+
+                    sessions.lock().await.remove(&client_addr);
+                });
+            }
+        }
+    }
+}
+
+async fn run_session(
+    socket: Arc<UdpSocket>,
+    sessions: UdpSessions,
+    machine_agent: Arc<MachineAgent>,
+    binding: ProxyBinding,
+    client_addr: SocketAddr,
+    local_addr: SocketAddr,
+) -> Result<()> {
+    let _permit = acquire_connection_permit(&binding)?;
+    let _connection = open_connection(&binding);
+
+    let (_awake, upstream) =
+        match connect_upstream(&machine_agent, &binding, client_addr, local_addr).await {
+            Ok(upstream) => upstream,
+            Err(e) => {
+                record_upstream_error(&binding);
+                return Err(e);
+            }
+        };
+
+    let last_activity = Arc::new(std::sync::Mutex::new(Instant::now()));
+    let pending = {
+        let mut sessions = sessions.lock().await;
+        let previous = sessions.insert(
+            client_addr,
+            UdpSession::Open {
+                upstream: upstream.clone(),
+                last_activity: last_activity.clone(),
+                binding: binding.clone(),
+            },
+        );
+
+        match previous {
+            Some(UdpSession::Opening(pending)) => pending,
+            _ => vec![],
+        }
+    };
+
+    for datagram in pending {
+        let len = upstream.send(&datagram).await?;
+        record_traffic(&binding, len as u64, 0);
+    }
+
+    info!(
+        "Proxying UDP session from {} to machine on port {}",
+        client_addr, binding.target_port
+    );
+
+    let idle_timeout = binding
+        .timeouts
+        .idle
+        .or(binding.inactivity_timeout)
+        .unwrap_or(UDP_DEFAULT_SESSION_TIMEOUT);
+
+    let mut buf = vec![0u8; UDP_MAX_DATAGRAM_SIZE];
+    loop {
+        match timeout(idle_timeout, upstream.recv(&mut buf)).await {
+            Ok(Ok(len)) => {
+                *last_activity.lock().expect("udp session poisoned") = Instant::now();
+                let len = socket.send_to(&buf[..len], client_addr).await?;
+                record_traffic(&binding, 0, len as u64);
+            }
+            Ok(Err(e)) => return Err(e.into()),
+            Err(_) => {
+                let idle_for = last_activity
+                    .lock()
+                    .expect("udp session poisoned")
+                    .elapsed();
+                if idle_for >= idle_timeout {
+                    info!("UDP session from {} timed out", client_addr);
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+async fn connect_upstream(
+    machine_agent: &Arc<MachineAgent>,
+    binding: &ProxyBinding,
+    client_addr: SocketAddr,
+    local_addr: SocketAddr,
+) -> Result<(MachineAwakeGuard, Arc<UdpSocket>)> {
+    let machine = find_flow_machine(machine_agent, binding, client_addr, local_addr).await?;
+    let awake = machine.hold_awake().await?;
+
+    let upstream = UdpSocket::bind("0.0.0.0:0").await?;
+    upstream
+        .connect((
+            machine.config.network.ip_address.as_str(),
+            binding.target_port,
+        ))
+        .await?;
+
+    Ok((awake, Arc::new(upstream)))
+}
+
+/// Picks the machine for a flow, so a client keeps hitting the same machine across sessions
+/// as long as the set of machines doesn't change.
+async fn find_flow_machine(
+    machine_agent: &Arc<MachineAgent>,
+    binding: &ProxyBinding,
+    client_addr: SocketAddr,
+    local_addr: SocketAddr,
+) -> Result<Arc<Machine>> {
+    let (network_tag, _) = binding.balancing.next(&binding.target_network_tag);
+
+    let mut machines = machine_agent.get_machines_by_network_tag(network_tag).await;
+    if machines.is_empty() && network_tag != binding.target_network_tag {
+        machines = machine_agent
+            .get_machines_by_network_tag(&binding.target_network_tag)
+            .await;
+    }
+
+    if machines.is_empty() {
+        bail!("No machine found for network tag {network_tag}");
+    }
+    machines.sort_by(|a, b| a.config.name.cmp(&b.config.name));
+
+    // the same on every node and across restarts, so a flow keeps its machine
+    let hash = blake3::hash(format!("udp {client_addr} {local_addr}").as_bytes());
+    let hash = u64::from_le_bytes(hash.as_bytes()[..8].try_into()?);
+    let index = (hash % machines.len() as u64) as usize;
+
+    Ok(machines[index].clone())
+}
@@ -0,0 +1,198 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{Result, bail};
+use bytes::Bytes;
+use http_body_util::combinators::BoxBody;
+use hyper::{Request, Response, Uri, Version, body::Incoming};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use rustls::{
+    ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{CryptoProvider, verify_tls12_signature, verify_tls13_signature},
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime, pem::PemObject},
+};
+use tokio::{net::TcpStream, spawn};
+use tokio_rustls::TlsConnector;
+use tracing::warn;
+
+use crate::agent::proxy::{UpstreamProtocol, timeouts::bounded};
+
+/// Used to verify upstream certificates when no CA is configured.
+const SYSTEM_CA_BUNDLE_PATH: &str = "/etc/ssl/certs/ca-certificates.crt";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UpstreamTlsConfig {
+    /// Name sent as SNI and verified against the certificate, defaults to the machine IP.
+    pub server_name: Option<String>,
+    /// PEM bundle of the CAs trusted for the upstream, defaults to the system bundle.
+    pub ca_cert: Option<String>,
+    pub skip_verify: bool,
+    /// PEM chain presented to upstreams requiring client certificates, with its key.
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+}
+
+/// Connects to the machine over TLS for bindings whose upstream only serves HTTPS.
+#[derive(Debug)]
+pub struct UpstreamTls {
+    config: UpstreamTlsConfig,
+    connector: TlsConnector,
+}
+
+impl UpstreamTls {
+    pub fn new(config: UpstreamTlsConfig, upstream_protocol: &UpstreamProtocol) -> Result<Self> {
+        let builder = ClientConfig::builder();
+
+        let builder = if config.skip_verify {
+            let provider = builder.crypto_provider().clone();
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(SkipServerVerification(provider)))
+        } else {
+            let certs = match &config.ca_cert {
+                Some(ca_cert) => CertificateDer::pem_slice_iter(ca_cert.as_bytes())
+                    .collect::<Result<Vec<_>, _>>()?,
+                None => CertificateDer::pem_file_iter(SYSTEM_CA_BUNDLE_PATH)?
+                    .collect::<Result<Vec<_>, _>>()?,
+            };
+
+            let mut roots = RootCertStore::empty();
+            let (added, _) = roots.add_parsable_certificates(certs);
+            if added == 0 {
+                bail!("No valid CA certificates found for upstream TLS");
+            }
+
+            builder.with_root_certificates(roots)
+        };
+
+        let mut client_config = match (&config.client_cert, &config.client_key) {
+            (Some(client_cert), Some(client_key)) => {
+                let cert_chain = CertificateDer::pem_slice_iter(client_cert.as_bytes())
+                    .collect::<Result<Vec<_>, _>>()?;
+                if cert_chain.is_empty() {
+                    bail!("No valid client certificate found for upstream TLS");
+                }
+                let key = PrivateKeyDer::from_pem_slice(client_key.as_bytes())?;
+
+                builder.with_client_auth_cert(cert_chain, key)?
+            }
+            (None, None) => builder.with_no_client_auth(),
+            _ => bail!("Upstream TLS client certificate and key must be set together"),
+        };
+
+        client_config.alpn_protocols = match upstream_protocol {
+            UpstreamProtocol::H2c => vec![b"h2".to_vec()],
+            UpstreamProtocol::Http1 => vec![b"http/1.1".to_vec()],
+        };
+
+        Ok(Self {
+            config,
+            connector: TlsConnector::from(Arc::new(client_config)),
+        })
+    }
+
+    pub fn config(&self) -> &UpstreamTlsConfig {
+        &self.config
+    }
+
+    /// Sends the request over a fresh TLS connection to the machine.
+    pub async fn request(
+        &self,
+        ip_address: &str,
+        port: u16,
+        connect_timeout: Option<Duration>,
+        mut req: Request<BoxBody<Bytes, hyper::Error>>,
+    ) -> Result<Response<Incoming>> {
+        let server_name = self
+            .config
+            .server_name
+            .clone()
+            .unwrap_or_else(|| ip_address.to_string());
+        let server_name = ServerName::try_from(server_name)?;
+
+        let Some(stream) = bounded(connect_timeout, TcpStream::connect((ip_address, port))).await
+        else {
+            bail!("Timed out connecting to {}:{}", ip_address, port);
+        };
+        let stream = self.connector.connect(server_name, stream?).await?;
+        let io = TokioIo::new(stream);
+
+        if req.version() == Version::HTTP_2 {
+            let (mut sender, connection) =
+                hyper::client::conn::http2::handshake(TokioExecutor::new(), io).await?;
+            spawn(async move {
+                if let Err(e) = connection.await {
+                    warn!("Upstream TLS connection error: {}", e);
+                }
+            });
+
+            return Ok(sender.send_request(req).await?);
+        }
+
+        // HTTP/1.1 origin servers expect the origin form
+        let path_and_query = req
+            .uri()
+            .path_and_query()
+            .map(|pq| pq.as_str())
+            .unwrap_or("/")
+            .to_string();
+        *req.uri_mut() = Uri::try_from(path_and_query)?;
+
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(io).await?;
+        spawn(async move {
+            if let Err(e) = connection.with_upgrades().await {
+                warn!("Upstream TLS connection error: {}", e);
+            }
+        });
+
+        Ok(sender.send_request(req).await?)
+    }
+}
+
+#[derive(Debug)]
+struct SkipServerVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for SkipServerVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
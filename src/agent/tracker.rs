@@ -1,17 +1,33 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, VecDeque, hash_map::Entry},
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
 
 use crate::{
-    agent::data::Collections,
-    constants::DEFAULT_AGENT_TENANT,
-    machinery::store::{Key, Store},
+    agent::{
+        data::Collections,
+        machine::{
+            MachineAgent,
+            machine::{Machine, MachineUsageCounters},
+        },
+    },
+    constants::{
+        DEFAULT_AGENT_TENANT, DEFAULT_MACHINE_USAGE_SAMPLE_INTERVAL_SECS,
+        DEFAULT_MACHINE_USAGE_WINDOW_SAMPLES,
+    },
+    machinery::store::{Key, PartialKey, Store, now_millis},
+    resources::core::{MachineUsage, MachineUsageSample},
 };
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum TrackedResourceKind {
     ServiceDomain(String),
     CertificateDomain(String),
+    /// Name and type of a record published by a `DnsRecord` resource.
+    DnsRecord(String, String),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
@@ -41,17 +57,177 @@ impl TrackedResourceKind {
                     .as_ref()
                     .into()
             }
+            TrackedResourceKind::DnsRecord(name, record_type) => {
+                Key::<TrackedResourceOwner>::not_namespaced()
+                    .tenant(DEFAULT_AGENT_TENANT)
+                    .collection(Collections::TrackedResourceOwner)
+                    .key(format!(
+                        "dns_record:{}/{}",
+                        name,
+                        record_type.to_ascii_lowercase()
+                    ))
+                    .as_ref()
+                    .into()
+            }
         }
     }
 }
 
+/// Bytes proxied for a service since it was first seen, from the client's point of view.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BandwidthUsage {
+    pub namespace: String,
+    pub service: String,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub updated_at: u64,
+}
+
+impl BandwidthUsage {
+    fn key(tenant: &str, namespace: &str, service: &str) -> Key<BandwidthUsage> {
+        Key::<BandwidthUsage>::not_namespaced()
+            .tenant(tenant)
+            .collection(Collections::BandwidthUsage)
+            .key(format!("{}/{}", namespace, service))
+            .as_ref()
+            .into()
+    }
+}
+
+struct MachineUsageHistory {
+    counters: MachineUsageCounters,
+    sampled_at: Instant,
+    samples: VecDeque<MachineUsageSample>,
+}
+
 pub struct TrackerAgent {
     pub store: Arc<Store>,
+    /// Serializes the read-modify-write of bandwidth usage records.
+    bandwidth_usage_lock: Mutex<()>,
+    /// Recent usage of the machines running on this agent, by machine name.
+    machine_usage: Mutex<HashMap<String, MachineUsageHistory>>,
 }
 
 impl TrackerAgent {
     pub fn new(store: Arc<Store>) -> Self {
-        Self { store }
+        Self {
+            store,
+            bandwidth_usage_lock: Mutex::new(()),
+            machine_usage: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn start_usage_sampler(self: &Arc<Self>, machine: Arc<MachineAgent>) {
+        let agent = Arc::downgrade(self);
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(
+                DEFAULT_MACHINE_USAGE_SAMPLE_INTERVAL_SECS,
+            ));
+
+            loop {
+                interval.tick().await;
+
+                let Some(agent) = Weak::upgrade(&agent) else {
+                    break;
+                };
+
+                let counters = machine
+                    .list_machines()
+                    .into_iter()
+                    .map(|machine| {
+                        let counters = machine.usage_counters();
+                        (machine, counters)
+                    })
+                    .collect::<Vec<_>>();
+                agent.record_machine_usage(counters);
+            }
+        });
+    }
+
+    fn record_machine_usage(&self, counters: Vec<(Arc<Machine>, MachineUsageCounters)>) {
+        let now = Instant::now();
+        let timestamp = now_millis() / 1000;
+
+        let mut usage = self
+            .machine_usage
+            .lock()
+            .expect("machine usage lock poisoned");
+        usage.retain(|name, _| {
+            counters
+                .iter()
+                .any(|(machine, _)| &machine.config.name == name)
+        });
+
+        for (machine, counters) in counters {
+            let history = match usage.entry(machine.config.name.clone()) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    // rates need a previous sample
+                    entry.insert(MachineUsageHistory {
+                        counters,
+                        sampled_at: now,
+                        samples: VecDeque::new(),
+                    });
+                    continue;
+                }
+            };
+
+            let elapsed = now.duration_since(history.sampled_at).as_secs_f64();
+            if elapsed <= 0.0 {
+                continue;
+            }
+
+            let last = &history.counters;
+            let rate = |current: u64, last: u64| current.saturating_sub(last) as f64 / elapsed;
+            let cpu_time = counters
+                .cpu_time
+                .saturating_sub(last.cpu_time)
+                .as_secs_f64();
+            let vcpus = machine.config.resources.cpu.max(1) as f64;
+
+            let sample = MachineUsageSample {
+                timestamp,
+                cpu_utilization: (cpu_time / elapsed / vcpus).min(1.0),
+                memory_resident_bytes: counters.memory_resident_bytes,
+                network_rx_bytes_per_sec: rate(counters.network_rx_bytes, last.network_rx_bytes),
+                network_tx_bytes_per_sec: rate(counters.network_tx_bytes, last.network_tx_bytes),
+                disk_read_bytes_per_sec: rate(counters.disk_read_bytes, last.disk_read_bytes),
+                disk_write_bytes_per_sec: rate(
+                    counters.disk_written_bytes,
+                    last.disk_written_bytes,
+                ),
+            };
+
+            history.samples.push_back(sample);
+            if history.samples.len() > DEFAULT_MACHINE_USAGE_WINDOW_SAMPLES {
+                history.samples.pop_front();
+            }
+            history.counters = counters;
+            history.sampled_at = now;
+        }
+    }
+
+    /// Latest usage sample of a machine along with the average of the recent ones.
+    pub fn machine_usage(&self, name: String, machine: &Machine) -> MachineUsage {
+        let samples = self
+            .machine_usage
+            .lock()
+            .expect("machine usage lock poisoned")
+            .get(&machine.config.name)
+            .map(|history| history.samples.iter().cloned().collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        MachineUsage {
+            name,
+            memory_total_bytes: machine.config.resources.memory << 20,
+            current: samples.last().cloned(),
+            average: average_usage(&samples),
+            window_secs: samples.len() as u64 * DEFAULT_MACHINE_USAGE_SAMPLE_INTERVAL_SECS,
+            samples,
+            cpu_history: Vec::new(),
+            memory_used_history: Vec::new(),
+        }
     }
 
     pub async fn track_resource_owner(&self, resource: TrackedResourceOwner) -> Result<()> {
@@ -74,4 +250,61 @@ impl TrackerAgent {
         let resource = self.store.get(key)?;
         Ok(resource)
     }
+
+    pub async fn list_tracked_resource_owners(&self) -> Result<Vec<TrackedResourceOwner>> {
+        let key = PartialKey::<TrackedResourceOwner>::not_namespaced()
+            .tenant(DEFAULT_AGENT_TENANT)
+            .collection(Collections::TrackedResourceOwner);
+
+        let owners = self.store.list(&key)?;
+        Ok(owners)
+    }
+
+    /// Adds the bytes of `usage` to the stored usage of its service.
+    pub async fn add_bandwidth_usage(&self, tenant: &str, usage: BandwidthUsage) -> Result<()> {
+        let _guard = self
+            .bandwidth_usage_lock
+            .lock()
+            .expect("bandwidth usage lock poisoned");
+
+        let key = BandwidthUsage::key(tenant, &usage.namespace, &usage.service);
+        let mut total = self.store.get(key.clone())?.unwrap_or(BandwidthUsage {
+            bytes_in: 0,
+            bytes_out: 0,
+            ..usage.clone()
+        });
+
+        total.bytes_in += usage.bytes_in;
+        total.bytes_out += usage.bytes_out;
+        total.updated_at = now_millis();
+        self.store.put(key, total)?;
+
+        Ok(())
+    }
+
+    pub async fn list_bandwidth_usage(&self, tenant: &str) -> Result<Vec<BandwidthUsage>> {
+        let key = PartialKey::<BandwidthUsage>::not_namespaced()
+            .tenant(tenant)
+            .collection(Collections::BandwidthUsage);
+
+        let usage = self.store.list(&key)?;
+        Ok(usage)
+    }
+}
+
+fn average_usage(samples: &[MachineUsageSample]) -> Option<MachineUsageSample> {
+    let last = samples.last()?;
+    let count = samples.len() as f64;
+    let mean =
+        |value: fn(&MachineUsageSample) -> f64| samples.iter().map(value).sum::<f64>() / count;
+
+    Some(MachineUsageSample {
+        timestamp: last.timestamp,
+        cpu_utilization: mean(|sample| sample.cpu_utilization),
+        memory_resident_bytes: mean(|sample| sample.memory_resident_bytes as f64) as u64,
+        network_rx_bytes_per_sec: mean(|sample| sample.network_rx_bytes_per_sec),
+        network_tx_bytes_per_sec: mean(|sample| sample.network_tx_bytes_per_sec),
+        disk_read_bytes_per_sec: mean(|sample| sample.disk_read_bytes_per_sec),
+        disk_write_bytes_per_sec: mean(|sample| sample.disk_write_bytes_per_sec),
+    })
 }
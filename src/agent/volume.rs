@@ -1,19 +1,43 @@
+pub mod backup;
+pub mod encryption;
 pub mod fs;
+pub mod s3;
 
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    collections::HashSet,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
 
-use anyhow::Result;
+use anyhow::{Result, bail};
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
+use takeoff_proto::proto::Filesystem;
 
 use crate::{
-    agent::data::Collections,
+    agent::{
+        data::Collections,
+        volume::{
+            backup::{BackupDestination, VolumeBackup, VolumeBackupStorage},
+            encryption::VolumeKey,
+            s3::S3Client,
+        },
+    },
     constants::DEFAULT_AGENT_TENANT,
     machinery::store::{Key, PartialKey, Store},
+    utils::size::format_human_readable_size,
 };
 
 #[derive(Debug, Clone)]
 pub struct VolumeAgentConfig {
     pub base_path: String,
+    pub backup_storage: Option<VolumeBackupStorage>,
+    /// Master key the volume keys of tenants are derived from, volumes can't be encrypted
+    /// without it.
+    pub encryption_key: Option<[u8; 32]>,
+    /// Bytes of volumes a tenant can provision unless an admin set another quota for it,
+    /// unlimited when not set.
+    pub default_quota_bytes: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -23,11 +47,61 @@ pub struct Volume {
     pub path: String,
     pub ov_path: String,
     pub cloned_from: Option<String>,
+    /// Tenant whose key encrypts the volume.
+    #[serde(default)]
+    pub encrypted_for: Option<String>,
+    /// Id the key of the volume is derived from, the id of the volume it was encrypted as.
+    /// Copies and clones keep the key of their source, restores the key of the backup.
+    #[serde(default)]
+    pub encryption_key_id: Option<String>,
+    /// ext4 when not set.
+    #[serde(default)]
+    pub filesystem: Option<Filesystem>,
+    /// Size asked for, without the overhead of the files. Unknown (0) for volumes created
+    /// before it was tracked.
+    #[serde(default)]
+    pub size_bytes: u64,
+    /// Tenant whose quota the volume counts against, volumes of images and machines count
+    /// against none.
+    #[serde(default)]
+    pub owner: Option<String>,
+}
+
+impl Volume {
+    pub fn provisioned_bytes(&self) -> u64 {
+        if self.size_bytes == 0 {
+            self.sparse_size
+        } else {
+            self.size_bytes
+        }
+    }
+}
+
+/// Quota an admin set for a tenant, overriding the daemon default.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VolumeQuota {
+    pub tenant: String,
+    /// Unlimited when not set.
+    pub quota_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VolumeTenantUsage {
+    /// Sizes of the volumes of the tenant.
+    pub provisioned_bytes: u64,
+    /// What the volumes of the tenant take on disk.
+    pub used_bytes: u64,
+    pub quota_bytes: Option<u64>,
 }
 
 pub struct VolumeAgent {
     base_path: PathBuf,
     store: Arc<Store>,
+    backup_storage: Option<(VolumeBackupStorage, S3Client)>,
+    /// Volumes being backed up or restored.
+    backup_jobs: Mutex<HashSet<String>>,
+    encryption_key: Option<[u8; 32]>,
+    default_quota_bytes: Option<u64>,
 }
 
 impl VolumeAgent {
@@ -38,7 +112,22 @@ impl VolumeAgent {
             tokio::fs::create_dir_all(&base_path).await?;
         }
 
-        Ok(Self { base_path, store })
+        let backup_storage = match config.backup_storage {
+            Some(storage) => {
+                let client = S3Client::new(&storage)?;
+                Some((storage, client))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            base_path,
+            store,
+            backup_storage,
+            backup_jobs: Mutex::new(HashSet::new()),
+            encryption_key: config.encryption_key,
+            default_quota_bytes: config.default_quota_bytes,
+        })
     }
 
     pub fn volume(&self, id: &str) -> Result<Option<Volume>> {
@@ -61,9 +150,7 @@ impl VolumeAgent {
     }
 
     pub async fn volume_create_empty_sparse(&self, sparse_size: u64) -> Result<Volume> {
-        // at least 16MB
-        let size = sparse_size.max(16 * 1024 * 1024);
-        let size = (size as f64 * 1.2).ceil() as u64;
+        let size = sparse_size_with_overhead(sparse_size);
 
         let id = uuid::Uuid::new_v4().to_string();
         let path = self.base_path.join(&id).to_string_lossy().to_string();
@@ -82,6 +169,11 @@ impl VolumeAgent {
             path,
             ov_path,
             cloned_from: None,
+            encrypted_for: None,
+            encryption_key_id: None,
+            filesystem: None,
+            size_bytes: sparse_size,
+            owner: None,
         };
 
         let key = Key::<Volume>::not_namespaced()
@@ -95,13 +187,89 @@ impl VolumeAgent {
     }
 
     pub async fn volume_create_empty_ext4_sparse(&self, sparse_size: u64) -> Result<Volume> {
-        let volume = self.volume_create_empty_sparse(sparse_size).await?;
+        self.volume_create_empty_formatted_sparse(sparse_size, Filesystem::Ext4)
+            .await
+    }
 
-        if let Err(e) = fs::format_file_as_ext4_volume_empty(&volume.path).await {
+    pub async fn volume_create_empty_formatted_sparse(
+        &self,
+        sparse_size: u64,
+        filesystem: Filesystem,
+    ) -> Result<Volume> {
+        let mut volume = self.volume_create_empty_sparse(sparse_size).await?;
+
+        if let Err(e) = fs::format_file_as_volume_empty(&volume.path, filesystem).await {
             self.volume_delete(&volume.id).await?;
             return Err(e);
         }
 
+        volume.filesystem = Some(filesystem);
+
+        let key = Key::<Volume>::not_namespaced()
+            .tenant(DEFAULT_AGENT_TENANT)
+            .collection(Collections::Volume)
+            .key(&volume.id);
+
+        self.store.put(&key, &volume)?;
+
+        Ok(volume)
+    }
+
+    /// Creates an empty volume encrypted with the key of `tenant`.
+    pub async fn volume_create_encrypted_sparse(
+        &self,
+        sparse_size: u64,
+        filesystem: Filesystem,
+        tenant: &str,
+    ) -> Result<Volume> {
+        let volume = self
+            .volume_create_empty_formatted_sparse(sparse_size, filesystem)
+            .await?;
+
+        match self.volume_encrypt(&volume.id, tenant).await {
+            Ok(volume) => Ok(volume),
+            Err(e) => {
+                self.volume_delete(&volume.id).await?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Encrypts a volume in place with the key of `tenant`. The volume must not be in use.
+    pub async fn volume_encrypt(&self, id: &str, tenant: &str) -> Result<Volume> {
+        let Some(master_key) = self.encryption_key else {
+            bail!("volume encryption is not configured on this daemon");
+        };
+        let Some(mut volume) = self.volume(id)? else {
+            return Err(anyhow::anyhow!("Volume not found"));
+        };
+        if volume.cloned_from.is_some() {
+            bail!("cloned volumes share their base and can't be encrypted");
+        }
+        if volume.encrypted_for.is_some() {
+            bail!("volume is already encrypted");
+        }
+
+        let key = VolumeKey::for_volume(&master_key, tenant, &volume.id);
+        let paths = [PathBuf::from(&volume.path), PathBuf::from(&volume.ov_path)];
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            for path in &paths {
+                encryption::encrypt_file(path, &key)?;
+            }
+            Ok(())
+        })
+        .await??;
+
+        volume.encrypted_for = Some(tenant.to_string());
+        volume.encryption_key_id = Some(volume.id.clone());
+
+        let key = Key::<Volume>::not_namespaced()
+            .tenant(DEFAULT_AGENT_TENANT)
+            .collection(Collections::Volume)
+            .key(&volume.id);
+
+        self.store.put(&key, &volume)?;
+
         Ok(volume)
     }
 
@@ -145,6 +313,36 @@ impl VolumeAgent {
         Ok(())
     }
 
+    /// Grows the files of a volume, the filesystem on it is grown by the guest using it.
+    pub async fn volume_resize(&self, id: &str, sparse_size: u64) -> Result<Volume> {
+        let Some(mut volume) = self.volume(id)? else {
+            return Err(anyhow::anyhow!("Volume not found"));
+        };
+
+        if volume.cloned_from.is_some() {
+            bail!("cloned volumes share their base and can't be resized");
+        }
+
+        let size = sparse_size_with_overhead(sparse_size);
+        if size <= volume.sparse_size {
+            bail!("volumes can only grow");
+        }
+
+        fs::grow_sparse_file(&volume.path, size).await?;
+        fs::grow_sparse_file(&volume.ov_path, size).await?;
+        volume.sparse_size = size;
+        volume.size_bytes = sparse_size;
+
+        let key = Key::<Volume>::not_namespaced()
+            .tenant(DEFAULT_AGENT_TENANT)
+            .collection(Collections::Volume)
+            .key(&volume.id);
+
+        self.store.put(&key, &volume)?;
+
+        Ok(volume)
+    }
+
     pub async fn volume_clone_with_overlay(&self, source_id: &str) -> Result<Volume> {
         let Some(source_volume) = self.volume(source_id)? else {
             return Err(anyhow::anyhow!("Source volume not found"));
@@ -164,6 +362,11 @@ impl VolumeAgent {
             path: source_volume.path.clone(),
             ov_path,
             cloned_from: Some(source_id.to_string()),
+            encrypted_for: source_volume.encrypted_for.clone(),
+            encryption_key_id: source_volume.encryption_key_id.clone(),
+            filesystem: source_volume.filesystem,
+            size_bytes: source_volume.size_bytes,
+            owner: None,
         };
 
         let key = Key::<Volume>::not_namespaced()
@@ -175,6 +378,432 @@ impl VolumeAgent {
 
         Ok(new_volume)
     }
+
+    /// Creates a volume holding what `source_id` holds, reflinked when the filesystem of the
+    /// volumes directory supports it, and at least `sparse_size` large.
+    ///
+    /// The source can be in use, the copy then holds what a crash would have left on it.
+    pub async fn volume_copy(&self, source_id: &str, sparse_size: u64) -> Result<Volume> {
+        let Some(source) = self.volume(source_id)? else {
+            return Err(anyhow::anyhow!("Source volume not found"));
+        };
+
+        let id = uuid::Uuid::new_v4().to_string();
+        let path = self.base_path.join(&id).to_string_lossy().to_string();
+        let ov_path = self
+            .base_path
+            .join(format!("{}.ov", id))
+            .to_string_lossy()
+            .to_string();
+
+        let size = sparse_size_with_overhead(sparse_size).max(source.sparse_size);
+        // the overlay first, the base doesn't change while the source is in use
+        let copied: Result<()> = async {
+            fs::copy_sparse_file(&source.ov_path, &ov_path).await?;
+            fs::copy_sparse_file(&source.path, &path).await?;
+            if size > source.sparse_size {
+                fs::grow_sparse_file(&path, size).await?;
+                fs::grow_sparse_file(&ov_path, size).await?;
+            }
+            Ok(())
+        }
+        .await;
+        if let Err(e) = copied {
+            tokio::fs::remove_file(&path).await.ok();
+            tokio::fs::remove_file(&ov_path).await.ok();
+            return Err(e);
+        }
+
+        let volume = Volume {
+            id,
+            sparse_size: size,
+            path,
+            ov_path,
+            cloned_from: None,
+            encrypted_for: source.encrypted_for.clone(),
+            encryption_key_id: source.encryption_key_id.clone(),
+            filesystem: source.filesystem,
+            size_bytes: sparse_size.max(source.provisioned_bytes()),
+            owner: None,
+        };
+
+        let key = Key::<Volume>::not_namespaced()
+            .tenant(DEFAULT_AGENT_TENANT)
+            .collection(Collections::Volume)
+            .key(&volume.id);
+
+        self.store.put(&key, &volume)?;
+
+        Ok(volume)
+    }
+
+    /// Counts a volume against the quota of `tenant`.
+    pub fn volume_set_owner(&self, id: &str, tenant: &str) -> Result<Volume> {
+        let Some(mut volume) = self.volume(id)? else {
+            return Err(anyhow::anyhow!("Volume not found"));
+        };
+
+        volume.owner = Some(tenant.to_string());
+
+        let key = Key::<Volume>::not_namespaced()
+            .tenant(DEFAULT_AGENT_TENANT)
+            .collection(Collections::Volume)
+            .key(&volume.id);
+
+        self.store.put(&key, &volume)?;
+
+        Ok(volume)
+    }
+
+    /// What a volume takes on disk, the base of a clone counts for the volume it's cloned from.
+    pub fn volume_used_bytes(&self, volume: &Volume) -> Result<u64> {
+        let mut used_bytes = fs::allocated_bytes(&volume.ov_path)?;
+        if volume.cloned_from.is_none() {
+            used_bytes += fs::allocated_bytes(&volume.path)?;
+        }
+
+        Ok(used_bytes)
+    }
+
+    pub fn tenant_quota(&self, tenant: &str) -> Result<Option<u64>> {
+        let key = Key::<VolumeQuota>::not_namespaced()
+            .tenant(DEFAULT_AGENT_TENANT)
+            .collection(Collections::VolumeQuota)
+            .key(tenant);
+
+        match self.store.get(&key)? {
+            Some(quota) => Ok(quota.quota_bytes),
+            None => Ok(self.default_quota_bytes),
+        }
+    }
+
+    /// Overrides the default quota of a tenant, `None` lifting the limit.
+    pub fn set_tenant_quota(&self, tenant: &str, quota_bytes: Option<u64>) -> Result<()> {
+        let key = Key::<VolumeQuota>::not_namespaced()
+            .tenant(DEFAULT_AGENT_TENANT)
+            .collection(Collections::VolumeQuota)
+            .key(tenant);
+
+        self.store.put(
+            &key,
+            &VolumeQuota {
+                tenant: tenant.to_string(),
+                quota_bytes,
+            },
+        )?;
+
+        Ok(())
+    }
+
+    /// Puts a tenant back on the default quota.
+    pub fn reset_tenant_quota(&self, tenant: &str) -> Result<()> {
+        let key = Key::<VolumeQuota>::not_namespaced()
+            .tenant(DEFAULT_AGENT_TENANT)
+            .collection(Collections::VolumeQuota)
+            .key(tenant);
+
+        self.store.delete(&key)?;
+
+        Ok(())
+    }
+
+    pub fn tenant_usage(&self, tenant: &str) -> Result<VolumeTenantUsage> {
+        let mut usage = VolumeTenantUsage {
+            quota_bytes: self.tenant_quota(tenant)?,
+            ..Default::default()
+        };
+
+        for volume in self.volume_list()? {
+            if volume.owner.as_deref() != Some(tenant) {
+                continue;
+            }
+
+            usage.provisioned_bytes += volume.provisioned_bytes();
+            usage.used_bytes += self.volume_used_bytes(&volume)?;
+        }
+
+        Ok(usage)
+    }
+
+    /// Fails when provisioning `additional_bytes` more would take `tenant` over its quota.
+    pub fn check_quota(&self, tenant: &str, additional_bytes: u64) -> Result<()> {
+        let Some(quota_bytes) = self.tenant_quota(tenant)? else {
+            return Ok(());
+        };
+
+        let provisioned_bytes = self
+            .volume_list()?
+            .iter()
+            .filter(|volume| volume.owner.as_deref() == Some(tenant))
+            .map(|volume| volume.provisioned_bytes())
+            .sum::<u64>();
+
+        if provisioned_bytes + additional_bytes > quota_bytes {
+            bail!(
+                "volume quota exceeded: {} of {} provisioned, {} more asked for",
+                format_human_readable_size(provisioned_bytes),
+                format_human_readable_size(quota_bytes),
+                format_human_readable_size(additional_bytes)
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn encryption_enabled(&self) -> bool {
+        self.encryption_key.is_some()
+    }
+
+    /// The key the block device of an encrypted volume reads and writes it with.
+    pub fn volume_key(&self, volume: &Volume) -> Result<Option<VolumeKey>> {
+        let Some(tenant) = &volume.encrypted_for else {
+            return Ok(None);
+        };
+        let Some(master_key) = &self.encryption_key else {
+            bail!("volume is encrypted but volume encryption is not configured on this daemon");
+        };
+        let Some(key_id) = &volume.encryption_key_id else {
+            bail!("volume is encrypted but has no key id");
+        };
+
+        Ok(Some(VolumeKey::for_volume(master_key, tenant, key_id)))
+    }
+
+    /// Checks that backups can be stored at `destination` (`s3://<bucket>/<prefix>`).
+    pub fn backup_destination(&self, destination: &str) -> Result<BackupDestination> {
+        let Some((storage, _)) = &self.backup_storage else {
+            bail!("volume backups are not configured on this daemon");
+        };
+
+        let destination = BackupDestination::parse(destination)?;
+        if !storage.allowed_buckets.is_empty()
+            && !storage.allowed_buckets.contains(&destination.bucket)
+        {
+            bail!("backups can't be stored in bucket '{}'", destination.bucket);
+        }
+
+        Ok(destination)
+    }
+
+    /// Uploads an image of the volume to the directory of `destination` named by `path`, then
+    /// removes the backups past the `keep` most recent ones.
+    ///
+    /// The volume can be in use, the backup then holds what a crash would have left on it.
+    /// Backups of encrypted volumes are uploaded as they are on disk, encrypted.
+    pub async fn volume_backup(
+        &self,
+        id: &str,
+        destination: &str,
+        path: &str,
+        keep: Option<u32>,
+    ) -> Result<VolumeBackup> {
+        let destination = self.backup_destination(destination)?;
+        let client = self.backup_client()?;
+        let Some(volume) = self.volume(id)? else {
+            return Err(anyhow::anyhow!("Volume not found"));
+        };
+        let _job = self.begin_backup_job(id)?;
+
+        let now = Utc::now();
+        let directory = destination.directory(path);
+        let backup_id = backup::backup_id(now);
+        let filesystem = volume.filesystem.unwrap_or_default();
+        let key_id = volume.encryption_key_id.clone();
+
+        let snapshot_path = self.base_path.join(format!("{}.ov.backup", id));
+        let uploaded = match fs::copy_sparse_file(&volume.ov_path, &snapshot_path).await {
+            Ok(()) => {
+                backup::upload_volume(
+                    client,
+                    &destination.bucket,
+                    &backup::backup_key(&directory, &backup_id, filesystem, key_id.as_deref()),
+                    PathBuf::from(&volume.path),
+                    snapshot_path.clone(),
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        };
+        tokio::fs::remove_file(&snapshot_path).await.ok();
+        let size_bytes = uploaded?;
+
+        let backups = backup::backups_from_objects(
+            &directory,
+            client.list_objects(&destination.bucket, &directory).await?,
+        );
+        for expired in backup::expired_backups(&backups, keep) {
+            client
+                .delete_object(
+                    &destination.bucket,
+                    &backup::backup_key(
+                        &directory,
+                        &expired.id,
+                        expired.filesystem,
+                        expired.key_id.as_deref(),
+                    ),
+                )
+                .await?;
+        }
+
+        Ok(VolumeBackup {
+            id: backup_id,
+            created_at: now.timestamp() as u64 * 1000,
+            size_bytes,
+            filesystem,
+            key_id,
+        })
+    }
+
+    /// The backups stored in the directory of `destination` named by `path`, oldest first.
+    pub async fn volume_backup_list(
+        &self,
+        destination: &str,
+        path: &str,
+    ) -> Result<Vec<VolumeBackup>> {
+        let destination = self.backup_destination(destination)?;
+        let directory = destination.directory(path);
+        let objects = self
+            .backup_client()?
+            .list_objects(&destination.bucket, &directory)
+            .await?;
+
+        Ok(backup::backups_from_objects(&directory, objects))
+    }
+
+    /// Replaces the contents of the volume with a backup, the latest one when `backup_id` is
+    /// not set. The volume must not be in use.
+    pub async fn volume_restore(
+        &self,
+        id: &str,
+        destination: &str,
+        path: &str,
+        backup_id: Option<&str>,
+    ) -> Result<VolumeBackup> {
+        let destination = self.backup_destination(destination)?;
+        let client = self.backup_client()?;
+        let Some(mut volume) = self.volume(id)? else {
+            return Err(anyhow::anyhow!("Volume not found"));
+        };
+        if volume.cloned_from.is_some() {
+            bail!("cloned volumes share their base and can't be restored");
+        }
+        let _job = self.begin_backup_job(id)?;
+
+        let directory = destination.directory(path);
+        let backups = backup::backups_from_objects(
+            &directory,
+            client.list_objects(&destination.bucket, &directory).await?,
+        );
+        let backup = match backup_id {
+            Some(backup_id) => backups.into_iter().find(|backup| backup.id == backup_id),
+            None => backups.into_iter().last(),
+        };
+        let Some(backup) = backup else {
+            bail!("backup not found");
+        };
+        if backup.filesystem != volume.filesystem.unwrap_or_default() {
+            bail!(
+                "the backup holds a {} filesystem and the volume {}",
+                backup.filesystem.name(),
+                volume.filesystem.unwrap_or_default().name()
+            );
+        }
+        if backup.key_id.is_some() != volume.encrypted_for.is_some() {
+            bail!("the backup and the volume must both be encrypted or both not be");
+        }
+
+        let download_path = self.base_path.join(format!("{}.restore.gz", id));
+        let image_path = self.base_path.join(format!("{}.restore", id));
+        let restored: Result<u64> = async {
+            backup::download_backup(
+                client,
+                &destination.bucket,
+                &backup::backup_key(
+                    &directory,
+                    &backup.id,
+                    backup.filesystem,
+                    backup.key_id.as_deref(),
+                ),
+                &download_path,
+            )
+            .await?;
+
+            let (download_path, image_path) = (download_path.clone(), image_path.clone());
+            let min_len = volume.sparse_size;
+            tokio::task::spawn_blocking(move || {
+                backup::write_sparse_image(&download_path, &image_path, min_len)
+            })
+            .await?
+        }
+        .await;
+        tokio::fs::remove_file(&download_path).await.ok();
+
+        let image_len = match restored {
+            Ok(image_len) => image_len,
+            Err(e) => {
+                tokio::fs::remove_file(&image_path).await.ok();
+                return Err(e);
+            }
+        };
+        if image_len > volume.sparse_size {
+            tokio::fs::remove_file(&image_path).await.ok();
+            bail!("the backup is larger than the volume, resize the volume first");
+        }
+
+        tokio::fs::rename(&image_path, &volume.path).await?;
+        fs::clear_sparse_file(&volume.ov_path, volume.sparse_size).await?;
+
+        // the backup is encrypted with the key of the volume it was taken of
+        if volume.encryption_key_id != backup.key_id {
+            volume.encryption_key_id = backup.key_id.clone();
+
+            let key = Key::<Volume>::not_namespaced()
+                .tenant(DEFAULT_AGENT_TENANT)
+                .collection(Collections::Volume)
+                .key(&volume.id);
+
+            self.store.put(&key, &volume)?;
+        }
+
+        Ok(backup)
+    }
+
+    fn backup_client(&self) -> Result<&S3Client> {
+        let Some((_, client)) = &self.backup_storage else {
+            bail!("volume backups are not configured on this daemon");
+        };
+
+        Ok(client)
+    }
+
+    fn begin_backup_job(&self, id: &str) -> Result<BackupJob<'_>> {
+        if !self.backup_jobs.lock().unwrap().insert(id.to_string()) {
+            bail!("a backup or restore of the volume is already running");
+        }
+
+        Ok(BackupJob {
+            jobs: &self.backup_jobs,
+            id: id.to_string(),
+        })
+    }
+}
+
+/// Marks a volume as being backed up or restored until dropped.
+struct BackupJob<'a> {
+    jobs: &'a Mutex<HashSet<String>>,
+    id: String,
+}
+
+impl Drop for BackupJob<'_> {
+    fn drop(&mut self) {
+        self.jobs.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// At least 16MB, with 20% on top for the filesystem.
+fn sparse_size_with_overhead(size: u64) -> u64 {
+    let size = size.max(16 * 1024 * 1024);
+    (size as f64 * 1.2).ceil() as u64
 }
 
 #[cfg(test)]
@@ -185,6 +814,9 @@ mod tests {
         let agent = VolumeAgent::new(
             VolumeAgentConfig {
                 base_path: volumes_dir.to_string(),
+                backup_storage: None,
+                encryption_key: None,
+                default_quota_bytes: Some(100 * 1024 * 1024),
             },
             Arc::new(Store::new(store_dir.to_string()).await.unwrap()),
         )
@@ -231,6 +863,46 @@ mod tests {
         assert!(volumes.iter().any(|v| v.id == volume2.id));
     }
 
+    #[tokio::test]
+    async fn test_volume_resize() {
+        let store_temp_dir = tempfile::tempdir().unwrap();
+        let volumes_temp_dir = tempfile::tempdir().unwrap();
+        let agent = create_test_agent(
+            store_temp_dir.path().to_str().unwrap(),
+            volumes_temp_dir.path().to_str().unwrap(),
+        )
+        .await;
+
+        let volume = agent
+            .volume_create_empty_sparse(32 * 1024 * 1024)
+            .await
+            .unwrap();
+        let resized = agent
+            .volume_resize(&volume.id, 64 * 1024 * 1024)
+            .await
+            .unwrap();
+
+        assert!(resized.sparse_size > volume.sparse_size);
+        assert_eq!(
+            std::fs::metadata(&resized.path).unwrap().len(),
+            resized.sparse_size
+        );
+        assert_eq!(
+            std::fs::metadata(&resized.ov_path).unwrap().len(),
+            resized.sparse_size
+        );
+        assert_eq!(
+            agent.volume(&volume.id).unwrap().unwrap().sparse_size,
+            resized.sparse_size
+        );
+        assert!(
+            agent
+                .volume_resize(&volume.id, 16 * 1024 * 1024)
+                .await
+                .is_err()
+        );
+    }
+
     #[tokio::test]
     async fn test_volume_clone() {
         let store_temp_dir = tempfile::tempdir().unwrap();
@@ -251,4 +923,116 @@ mod tests {
         assert!(volumes.iter().any(|v| v.id == volume.id));
         assert!(volumes.iter().any(|v| v.id == volume2.id));
     }
+
+    #[tokio::test]
+    async fn test_volume_copy() {
+        let store_temp_dir = tempfile::tempdir().unwrap();
+        let volumes_temp_dir = tempfile::tempdir().unwrap();
+        let agent = create_test_agent(
+            store_temp_dir.path().to_str().unwrap(),
+            volumes_temp_dir.path().to_str().unwrap(),
+        )
+        .await;
+
+        let volume = agent.volume_create_empty_sparse(1024).await.unwrap();
+        std::fs::write(&volume.path, b"base").unwrap();
+        std::fs::write(&volume.ov_path, b"overlay").unwrap();
+
+        let copy = agent
+            .volume_copy(&volume.id, 32 * 1024 * 1024)
+            .await
+            .unwrap();
+
+        assert_ne!(copy.path, volume.path);
+        assert_eq!(copy.cloned_from, None);
+        assert!(copy.sparse_size > volume.sparse_size);
+        assert_eq!(
+            std::fs::metadata(&copy.path).unwrap().len(),
+            copy.sparse_size
+        );
+        assert_eq!(&std::fs::read(&copy.ov_path).unwrap()[..7], b"overlay");
+
+        // the source is left alone
+        std::fs::write(&copy.path, b"copy").unwrap();
+        assert_eq!(std::fs::read(&volume.path).unwrap(), b"base");
+    }
+
+    #[tokio::test]
+    async fn test_volume_quota() {
+        let store_temp_dir = tempfile::tempdir().unwrap();
+        let volumes_temp_dir = tempfile::tempdir().unwrap();
+        let agent = create_test_agent(
+            store_temp_dir.path().to_str().unwrap(),
+            volumes_temp_dir.path().to_str().unwrap(),
+        )
+        .await;
+
+        let volume = agent
+            .volume_create_empty_sparse(60 * 1024 * 1024)
+            .await
+            .unwrap();
+        // not owned yet
+        agent.check_quota("acme", 100 * 1024 * 1024).unwrap();
+
+        agent.volume_set_owner(&volume.id, "acme").unwrap();
+        std::fs::write(&volume.path, [1u8; 8192]).unwrap();
+
+        let usage = agent.tenant_usage("acme").unwrap();
+        assert_eq!(usage.provisioned_bytes, 60 * 1024 * 1024);
+        assert!(usage.used_bytes >= 8192);
+        assert_eq!(usage.quota_bytes, Some(100 * 1024 * 1024));
+        assert_eq!(agent.tenant_usage("globex").unwrap().provisioned_bytes, 0);
+
+        agent.check_quota("acme", 40 * 1024 * 1024).unwrap();
+        assert!(agent.check_quota("acme", 41 * 1024 * 1024).is_err());
+
+        agent.set_tenant_quota("acme", None).unwrap();
+        agent.check_quota("acme", 1024 * 1024 * 1024).unwrap();
+        agent
+            .set_tenant_quota("acme", Some(50 * 1024 * 1024))
+            .unwrap();
+        assert!(agent.check_quota("acme", 0).is_err());
+
+        agent.reset_tenant_quota("acme").unwrap();
+        assert_eq!(agent.tenant_quota("acme").unwrap(), Some(100 * 1024 * 1024));
+    }
+
+    #[tokio::test]
+    async fn test_backup_destination() {
+        let store_temp_dir = tempfile::tempdir().unwrap();
+        let volumes_temp_dir = tempfile::tempdir().unwrap();
+        let agent = create_test_agent(
+            store_temp_dir.path().to_str().unwrap(),
+            volumes_temp_dir.path().to_str().unwrap(),
+        )
+        .await;
+        assert!(agent.backup_destination("s3://backups/volumes").is_err());
+
+        let agent = VolumeAgent::new(
+            VolumeAgentConfig {
+                base_path: volumes_temp_dir.path().to_str().unwrap().to_string(),
+                backup_storage: Some(VolumeBackupStorage {
+                    endpoint: "http://localhost:9000".to_string(),
+                    region: None,
+                    access_key_id: "minioadmin".to_string(),
+                    secret_access_key: "minioadmin".to_string(),
+                    path_style: None,
+                    allowed_buckets: vec!["backups".to_string()],
+                }),
+                encryption_key: None,
+                default_quota_bytes: None,
+            },
+            agent.store.clone(),
+        )
+        .await
+        .unwrap();
+
+        let destination = agent.backup_destination("s3://backups/volumes").unwrap();
+        assert_eq!(destination.bucket, "backups");
+        assert_eq!(
+            destination.directory("acme/default/data"),
+            "volumes/acme/default/data/"
+        );
+        assert!(agent.backup_destination("s3://other/volumes").is_err());
+    }
 }
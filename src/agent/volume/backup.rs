@@ -0,0 +1,481 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read, Write},
+    os::{fd::AsFd, unix::fs::FileExt},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Result, anyhow, bail};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use nix::{
+    errno::Errno,
+    unistd::{Whence, lseek},
+};
+use serde::{Deserialize, Serialize};
+use takeoff_proto::proto::Filesystem;
+use tokio::{io::AsyncWriteExt, sync::mpsc};
+
+use crate::agent::volume::s3::{S3Client, S3Object};
+
+/// Parts are at least 5MiB for S3, the last one excepted.
+const PART_SIZE: usize = 16 * 1024 * 1024;
+const READ_CHUNK_SIZE: usize = 1024 * 1024;
+/// Blocks of zeroes this large are left as holes when restoring.
+const SPARSE_BLOCK_SIZE: usize = 64 * 1024;
+const BACKUP_SUFFIX: &str = ".gz";
+/// Backups of encrypted volumes hold the encrypted image, only restorable to encrypted volumes.
+const ENCRYPTED_SUFFIX: &str = ".enc";
+const BACKUP_ID_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// The object storage volume backups go to, shared by every destination bucket.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct VolumeBackupStorage {
+    /// eg. https://s3.eu-central-1.amazonaws.com or http://localhost:9000 for MinIO
+    pub endpoint: String,
+    /// Default: us-east-1
+    pub region: Option<String>,
+    #[serde(rename = "access-key-id")]
+    pub access_key_id: String,
+    #[serde(rename = "secret-access-key")]
+    pub secret_access_key: String,
+    /// Addresses buckets as `<endpoint>/<bucket>` rather than `<bucket>.<endpoint>`. Default: true
+    #[serde(rename = "path-style")]
+    pub path_style: Option<bool>,
+    /// Buckets volumes can be backed up to, any of them when empty.
+    #[serde(rename = "allowed-buckets", default)]
+    pub allowed_buckets: Vec<String>,
+}
+
+impl VolumeBackupStorage {
+    pub fn region(&self) -> String {
+        self.region
+            .clone()
+            .unwrap_or_else(|| "us-east-1".to_string())
+    }
+
+    pub fn path_style(&self) -> bool {
+        self.path_style.unwrap_or(true)
+    }
+}
+
+/// A `s3://<bucket>/<prefix>` destination of a backup policy.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackupDestination {
+    pub bucket: String,
+    pub prefix: String,
+}
+
+impl BackupDestination {
+    pub fn parse(destination: &str) -> Result<Self> {
+        let Some(path) = destination.strip_prefix("s3://") else {
+            bail!("backup destination must look like s3://<bucket>/<prefix>");
+        };
+
+        let (bucket, prefix) = path.split_once('/').unwrap_or((path, ""));
+        let valid_bucket = (3..=63).contains(&bucket.len())
+            && bucket
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '.');
+        if !valid_bucket {
+            bail!("invalid bucket name '{}'", bucket);
+        }
+
+        Ok(Self {
+            bucket: bucket.to_string(),
+            prefix: prefix.trim_matches('/').to_string(),
+        })
+    }
+
+    /// Where the backups of a volume are stored, `path` naming the volume.
+    pub fn directory(&self, path: &str) -> String {
+        if self.prefix.is_empty() {
+            format!("{}/", path)
+        } else {
+            format!("{}/{}/", self.prefix, path)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VolumeBackup {
+    /// UTC time the backup was taken at, eg. 20250101T030000Z
+    pub id: String,
+    pub created_at: u64,
+    /// Compressed size.
+    pub size_bytes: u64,
+    pub filesystem: Filesystem,
+    /// Id the key of an encrypted backup is derived from, see `VolumeKey::for_volume`.
+    pub key_id: Option<String>,
+}
+
+pub fn backup_id(now: DateTime<Utc>) -> String {
+    now.format(BACKUP_ID_FORMAT).to_string()
+}
+
+/// Backups are named `<id>.<filesystem>.gz`, `<id>.<filesystem>.<key id>.enc.gz` when
+/// encrypted.
+pub fn backup_key(
+    directory: &str,
+    id: &str,
+    filesystem: Filesystem,
+    key_id: Option<&str>,
+) -> String {
+    let encryption = match key_id {
+        Some(key_id) => format!(".{}{}", key_id, ENCRYPTED_SUFFIX),
+        None => String::new(),
+    };
+
+    format!(
+        "{}{}.{}{}{}",
+        directory,
+        id,
+        filesystem.name(),
+        encryption,
+        BACKUP_SUFFIX
+    )
+}
+
+/// The backups among the objects of a volume directory, oldest first.
+pub fn backups_from_objects(directory: &str, objects: Vec<S3Object>) -> Vec<VolumeBackup> {
+    let mut backups = objects
+        .into_iter()
+        .filter_map(|object| {
+            let name = object
+                .key
+                .strip_prefix(directory)?
+                .strip_suffix(BACKUP_SUFFIX)?;
+            let (name, key_id) = match name.strip_suffix(ENCRYPTED_SUFFIX) {
+                Some(name) => {
+                    let (name, key_id) = name.rsplit_once('.')?;
+                    (name, Some(key_id.to_string()))
+                }
+                None => (name, None),
+            };
+            let (id, filesystem) = name.rsplit_once('.')?;
+            let filesystem = Filesystem::from_name(filesystem)?;
+            let created_at = NaiveDateTime::parse_from_str(id, BACKUP_ID_FORMAT)
+                .ok()?
+                .and_utc()
+                .timestamp_millis() as u64;
+
+            Some(VolumeBackup {
+                id: id.to_string(),
+                created_at,
+                size_bytes: object.size,
+                filesystem,
+                key_id,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    backups.sort_by_key(|backup| backup.created_at);
+    backups
+}
+
+/// The backups past the `keep` most recent ones.
+pub fn expired_backups(backups: &[VolumeBackup], keep: Option<u32>) -> Vec<VolumeBackup> {
+    let Some(keep) = keep else {
+        return vec![];
+    };
+
+    let expired = backups.len().saturating_sub(keep as usize);
+    backups[..expired].to_vec()
+}
+
+/// Uploads the gzipped image of a volume. Returns the compressed size.
+pub async fn upload_volume(
+    client: &S3Client,
+    bucket: &str,
+    key: &str,
+    base_path: PathBuf,
+    overlay_path: PathBuf,
+) -> Result<u64> {
+    let (parts_tx, mut parts_rx) = mpsc::channel(1);
+    let compress =
+        tokio::task::spawn_blocking(move || compress_volume(&base_path, &overlay_path, parts_tx));
+
+    let upload_id = client.create_multipart_upload(bucket, key).await?;
+
+    let mut size = 0;
+    let mut etags = vec![];
+    let uploaded: Result<()> = async {
+        while let Some(part) = parts_rx.recv().await {
+            size += part.len() as u64;
+            let etag = client
+                .upload_part(bucket, key, &upload_id, etags.len() + 1, part)
+                .await?;
+            etags.push(etag);
+        }
+
+        compress.await??;
+        client
+            .complete_multipart_upload(bucket, key, &upload_id, &etags)
+            .await
+    }
+    .await;
+
+    if let Err(e) = uploaded {
+        drop(parts_rx);
+        client
+            .abort_multipart_upload(bucket, key, &upload_id)
+            .await
+            .ok();
+        return Err(e);
+    }
+
+    Ok(size)
+}
+
+/// Downloads a backup to `path`, still compressed.
+pub async fn download_backup(
+    client: &S3Client,
+    bucket: &str,
+    key: &str,
+    path: &Path,
+) -> Result<()> {
+    let mut response = client.get_object(bucket, key).await?;
+    let mut file = tokio::fs::File::create(path).await?;
+
+    while let Some(chunk) = response.chunk().await? {
+        file.write_all(&chunk).await?;
+    }
+    file.sync_all().await?;
+
+    Ok(())
+}
+
+/// Reads a volume as its guest sees it: from the overlay where it holds data, from the base
+/// everywhere else.
+struct MergedReader {
+    base: File,
+    overlay: File,
+    len: u64,
+    pos: u64,
+}
+
+impl MergedReader {
+    fn open(base_path: &Path, overlay_path: &Path) -> Result<Self> {
+        let base = File::open(base_path)?;
+        let overlay = File::open(overlay_path)?;
+        let len = base.metadata()?.len();
+
+        Ok(Self {
+            base,
+            overlay,
+            len,
+            pos: 0,
+        })
+    }
+
+    /// Whether the overlay holds data at the current position, and for how long.
+    fn next_run(&self) -> std::io::Result<(bool, u64)> {
+        let pos = self.pos as i64;
+        match lseek(self.overlay.as_fd(), pos, Whence::SeekData) {
+            Ok(data_at) if data_at == pos => {
+                let hole_at = lseek(self.overlay.as_fd(), pos, Whence::SeekHole)?;
+                Ok((true, (hole_at as u64).min(self.len) - self.pos))
+            }
+            Ok(data_at) => Ok((false, (data_at as u64).min(self.len) - self.pos)),
+            Err(Errno::ENXIO) => Ok((false, self.len - self.pos)),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Read for MergedReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let (in_overlay, run) = self.next_run()?;
+        let len = (run as usize).min(buf.len());
+        let file = if in_overlay {
+            &self.overlay
+        } else {
+            &self.base
+        };
+
+        let read = file.read_at(&mut buf[..len], self.pos)?;
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+/// Gzips the merged image of a volume, sending it in parts of at least `PART_SIZE` bytes.
+fn compress_volume(
+    base_path: &Path,
+    overlay_path: &Path,
+    parts: mpsc::Sender<Vec<u8>>,
+) -> Result<()> {
+    let mut reader = MergedReader::open(base_path, overlay_path)?;
+    let mut encoder = GzEncoder::new(Vec::with_capacity(PART_SIZE), Compression::fast());
+    let mut buffer = vec![0u8; READ_CHUNK_SIZE];
+
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+
+        encoder.write_all(&buffer[..read])?;
+        if encoder.get_ref().len() >= PART_SIZE {
+            let part = std::mem::replace(encoder.get_mut(), Vec::with_capacity(PART_SIZE));
+            parts
+                .blocking_send(part)
+                .map_err(|_| anyhow!("the upload stopped"))?;
+        }
+    }
+
+    parts
+        .blocking_send(encoder.finish()?)
+        .map_err(|_| anyhow!("the upload stopped"))?;
+
+    Ok(())
+}
+
+/// Decompresses a backup into a sparse file at least `min_len` bytes long. Returns the size
+/// of the image.
+pub fn write_sparse_image(compressed_path: &Path, path: &Path, min_len: u64) -> Result<u64> {
+    let mut decoder = GzDecoder::new(BufReader::new(File::open(compressed_path)?));
+    let file = File::create(path)?;
+    let mut buffer = vec![0u8; SPARSE_BLOCK_SIZE];
+    let mut len = 0u64;
+
+    loop {
+        let mut filled = 0;
+        while filled < buffer.len() {
+            let read = decoder.read(&mut buffer[filled..])?;
+            if read == 0 {
+                break;
+            }
+            filled += read;
+        }
+        if filled == 0 {
+            break;
+        }
+
+        if buffer[..filled].iter().any(|byte| *byte != 0) {
+            file.write_all_at(&buffer[..filled], len)?;
+        }
+        len += filled as u64;
+    }
+
+    file.set_len(len.max(min_len))?;
+    file.sync_all()?;
+
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backup_destination() {
+        assert_eq!(
+            BackupDestination::parse("s3://backups/volumes/").unwrap(),
+            BackupDestination {
+                bucket: "backups".to_string(),
+                prefix: "volumes".to_string(),
+            }
+        );
+        assert_eq!(
+            BackupDestination::parse("s3://backups")
+                .unwrap()
+                .directory("acme/default/data"),
+            "acme/default/data/"
+        );
+        assert!(BackupDestination::parse("https://backups/volumes").is_err());
+        assert!(BackupDestination::parse("s3://Backups/volumes").is_err());
+        assert!(BackupDestination::parse("s3:///volumes").is_err());
+    }
+
+    #[test]
+    fn test_backups_from_objects() {
+        let object = |key: &str| S3Object {
+            key: key.to_string(),
+            size: 10,
+        };
+
+        let backups = backups_from_objects(
+            "v/data/",
+            vec![
+                object("v/data/20250102T030000Z.ext4.gz"),
+                object("v/data/20250101T030000Z.ext4.gz"),
+                object("v/data/20250103T030000Z.xfs.v1.enc.gz"),
+                object("v/data/20250104T030000Z.zfs.gz"),
+                object("v/data/notes.txt"),
+                object("v/data/nested/20250103T030000Z.ext4.gz"),
+            ],
+        );
+
+        assert_eq!(
+            backups.iter().map(|b| b.id.as_str()).collect::<Vec<_>>(),
+            vec!["20250101T030000Z", "20250102T030000Z", "20250103T030000Z"]
+        );
+        assert_eq!(backups[0].created_at, 1_735_700_400_000);
+        assert_eq!(backups[0].filesystem, Filesystem::Ext4);
+        assert_eq!(backups[0].key_id, None);
+        assert_eq!(backups[2].filesystem, Filesystem::Xfs);
+        assert_eq!(backups[2].key_id.as_deref(), Some("v1"));
+        assert_eq!(
+            backup_key("v/data/", &backups[2].id, Filesystem::Xfs, Some("v1")),
+            "v/data/20250103T030000Z.xfs.v1.enc.gz"
+        );
+        assert_eq!(
+            backup_key("v/data/", &backups[0].id, Filesystem::Ext4, None),
+            "v/data/20250101T030000Z.ext4.gz"
+        );
+
+        assert_eq!(expired_backups(&backups, Some(2)), backups[..1].to_vec());
+        assert!(expired_backups(&backups, Some(5)).is_empty());
+        assert!(expired_backups(&backups, None).is_empty());
+    }
+
+    #[test]
+    fn test_compress_and_restore_merged_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let base_path = dir.path().join("base");
+        let overlay_path = dir.path().join("base.ov");
+        let len = 4 * 1024 * 1024;
+
+        let base = File::create(&base_path).unwrap();
+        base.set_len(len).unwrap();
+        base.write_all_at(&[1u8; 8192], 0).unwrap();
+        base.write_all_at(&[2u8; 8192], 1024 * 1024).unwrap();
+        let overlay = File::create(&overlay_path).unwrap();
+        overlay.set_len(len).unwrap();
+        overlay.write_all_at(&[3u8; 4096], 1024 * 1024).unwrap();
+
+        let (parts_tx, mut parts_rx) = mpsc::channel(16);
+        compress_volume(&base_path, &overlay_path, parts_tx).unwrap();
+        let mut compressed = vec![];
+        while let Ok(part) = parts_rx.try_recv() {
+            compressed.extend(part);
+        }
+        let compressed_path = dir.path().join("backup.ext4.gz");
+        std::fs::write(&compressed_path, compressed).unwrap();
+
+        let restored_path = dir.path().join("restored");
+        let restored_len = write_sparse_image(&compressed_path, &restored_path, 0).unwrap();
+        assert_eq!(restored_len, len);
+
+        let restored = std::fs::read(&restored_path).unwrap();
+        assert_eq!(restored.len() as u64, len);
+        assert!(restored[..8192].iter().all(|byte| *byte == 1));
+        assert!(restored[8192..1024 * 1024].iter().all(|byte| *byte == 0));
+        assert!(
+            restored[1024 * 1024..1024 * 1024 + 4096]
+                .iter()
+                .all(|byte| *byte == 3)
+        );
+        assert!(
+            restored[1024 * 1024 + 4096..1024 * 1024 + 8192]
+                .iter()
+                .all(|byte| *byte == 2)
+        );
+        assert!(restored[1024 * 1024 + 8192..].iter().all(|byte| *byte == 0));
+    }
+}
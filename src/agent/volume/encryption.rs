@@ -0,0 +1,217 @@
+use std::{
+    fmt,
+    fs::OpenOptions,
+    os::{fd::AsFd, unix::fs::FileExt},
+    path::Path,
+};
+
+use anyhow::Result;
+use nix::{
+    errno::Errno,
+    unistd::{Whence, lseek},
+};
+use openssl::{cipher::Cipher, cipher_ctx::CipherCtx};
+
+/// Volumes are encrypted sector by sector, the sector number being the XTS tweak.
+pub const SECTOR_SIZE: usize = 512;
+const ENCRYPT_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// AES-256-XTS key of a volume.
+#[derive(Clone)]
+pub struct VolumeKey([u8; 64]);
+
+impl VolumeKey {
+    /// Keys are derived from the daemon master key when a volume is opened and never stored.
+    /// Each volume gets its own key, `key_id` being the id of the volume it was encrypted as.
+    pub fn for_volume(master_key: &[u8; 32], tenant: &str, key_id: &str) -> Self {
+        let mut hasher = blake3::Hasher::new_derive_key("ignition volume encryption key");
+        hasher.update(master_key);
+        // length prefixed, ("ab", "c") and ("a", "bc") must not give the same key
+        hasher.update(&(tenant.len() as u64).to_le_bytes());
+        hasher.update(tenant.as_bytes());
+        hasher.update(key_id.as_bytes());
+
+        let mut key = [0u8; 64];
+        hasher.finalize_xof().fill(&mut key);
+        Self(key)
+    }
+}
+
+impl fmt::Debug for VolumeKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "VolumeKey(redacted)")
+    }
+}
+
+/// Encrypts sectors like dm-crypt's `aes-xts-plain64`.
+///
+/// Sectors of zeroes stay zeroes both ways, the holes of sparse volume files keep reading as
+/// empty sectors. Which sectors are empty is visible on disk as a result.
+pub struct SectorCipher {
+    encrypt: CipherCtx,
+    decrypt: CipherCtx,
+}
+
+impl SectorCipher {
+    pub fn new(key: &VolumeKey) -> Result<Self> {
+        let mut encrypt = CipherCtx::new()?;
+        encrypt.encrypt_init(Some(Cipher::aes_256_xts()), Some(&key.0), None)?;
+
+        let mut decrypt = CipherCtx::new()?;
+        decrypt.decrypt_init(Some(Cipher::aes_256_xts()), Some(&key.0), None)?;
+
+        Ok(Self { encrypt, decrypt })
+    }
+
+    /// Encrypts the whole sectors of `data`, which starts at sector `first_sector`.
+    pub fn encrypt(&mut self, first_sector: u64, data: &mut [u8]) -> Result<()> {
+        for (index, sector) in data.chunks_exact_mut(SECTOR_SIZE).enumerate() {
+            if is_empty(sector) {
+                continue;
+            }
+
+            let tweak = tweak(first_sector + index as u64);
+            self.encrypt.encrypt_init(None, None, Some(&tweak))?;
+            self.encrypt.cipher_update_inplace(sector, SECTOR_SIZE)?;
+        }
+
+        Ok(())
+    }
+
+    /// Decrypts the whole sectors of `data`, which starts at sector `first_sector`.
+    pub fn decrypt(&mut self, first_sector: u64, data: &mut [u8]) -> Result<()> {
+        for (index, sector) in data.chunks_exact_mut(SECTOR_SIZE).enumerate() {
+            if is_empty(sector) {
+                continue;
+            }
+
+            let tweak = tweak(first_sector + index as u64);
+            self.decrypt.decrypt_init(None, None, Some(&tweak))?;
+            self.decrypt.cipher_update_inplace(sector, SECTOR_SIZE)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn tweak(sector: u64) -> [u8; 16] {
+    let mut tweak = [0u8; 16];
+    tweak[..8].copy_from_slice(&sector.to_le_bytes());
+    tweak
+}
+
+fn is_empty(sector: &[u8]) -> bool {
+    sector.iter().all(|byte| *byte == 0)
+}
+
+/// Encrypts a volume file in place, eg. right after formatting it. Only the parts of the file
+/// holding data are read.
+pub fn encrypt_file(path: &Path, key: &VolumeKey) -> Result<()> {
+    let file = OpenOptions::new().read(true).write(true).open(path)?;
+    // a trailing partial sector is past the capacity the guest sees
+    let len = file.metadata()?.len() / SECTOR_SIZE as u64 * SECTOR_SIZE as u64;
+    let mut cipher = SectorCipher::new(key)?;
+    let mut buffer = vec![0u8; ENCRYPT_CHUNK_SIZE];
+
+    let mut pos = 0;
+    while pos < len {
+        let data_at = match lseek(file.as_fd(), pos as i64, Whence::SeekData) {
+            Ok(data_at) => data_at as u64,
+            Err(Errno::ENXIO) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let hole_at = lseek(file.as_fd(), data_at as i64, Whence::SeekHole)? as u64;
+
+        pos = data_at / SECTOR_SIZE as u64 * SECTOR_SIZE as u64;
+        let run_end = hole_at.min(len);
+        while pos < run_end {
+            let chunk = (run_end - pos).min(ENCRYPT_CHUNK_SIZE as u64) as usize;
+            let chunk = chunk.div_ceil(SECTOR_SIZE) * SECTOR_SIZE;
+            let data = &mut buffer[..chunk];
+
+            file.read_exact_at(data, pos)?;
+            cipher.encrypt(pos / SECTOR_SIZE as u64, data)?;
+            file.write_all_at(data, pos)?;
+
+            pos += chunk as u64;
+        }
+    }
+
+    file.sync_all()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MASTER_KEY: [u8; 32] = [7u8; 32];
+
+    #[test]
+    fn test_keys_per_volume() {
+        let acme = VolumeKey::for_volume(&MASTER_KEY, "acme", "v1");
+        assert_eq!(acme.0, VolumeKey::for_volume(&MASTER_KEY, "acme", "v1").0);
+        assert_ne!(acme.0, VolumeKey::for_volume(&MASTER_KEY, "acme", "v2").0);
+        assert_ne!(acme.0, VolumeKey::for_volume(&MASTER_KEY, "globex", "v1").0);
+        assert_ne!(acme.0, VolumeKey::for_volume(&MASTER_KEY, "acm", "ev1").0);
+        assert_ne!(acme.0, VolumeKey::for_volume(&[8u8; 32], "acme", "v1").0);
+        assert_eq!(format!("{:?}", acme), "VolumeKey(redacted)");
+    }
+
+    #[test]
+    fn test_sector_cipher() {
+        let mut cipher =
+            SectorCipher::new(&VolumeKey::for_volume(&MASTER_KEY, "acme", "v1")).unwrap();
+
+        let mut data = vec![0u8; 3 * SECTOR_SIZE];
+        data[..SECTOR_SIZE].fill(1);
+        data[2 * SECTOR_SIZE..].fill(1);
+        let plaintext = data.clone();
+
+        cipher.encrypt(10, &mut data).unwrap();
+        assert_ne!(data[..SECTOR_SIZE], plaintext[..SECTOR_SIZE]);
+        assert!(is_empty(&data[SECTOR_SIZE..2 * SECTOR_SIZE]));
+        // same plaintext, different sector
+        assert_ne!(data[..SECTOR_SIZE], data[2 * SECTOR_SIZE..]);
+
+        let mut shifted = data.clone();
+        cipher.decrypt(11, &mut shifted).unwrap();
+        assert_ne!(shifted, plaintext);
+
+        cipher.decrypt(10, &mut data).unwrap();
+        assert_eq!(data, plaintext);
+    }
+
+    #[test]
+    fn test_encrypt_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("volume");
+        let len = 4 * 1024 * 1024 + 100;
+
+        let file = std::fs::File::create(&path).unwrap();
+        file.set_len(len).unwrap();
+        file.write_all_at(&[1u8; 8192], 0).unwrap();
+        file.write_all_at(&[2u8; 4096], 3 * 1024 * 1024).unwrap();
+
+        let key = VolumeKey::for_volume(&MASTER_KEY, "acme", "v1");
+        encrypt_file(&path, &key).unwrap();
+
+        let mut data = std::fs::read(&path).unwrap();
+        assert_eq!(data.len() as u64, len);
+        assert!(data[..8192].iter().any(|byte| *byte != 1));
+        assert!(is_empty(&data[8192..3 * 1024 * 1024]));
+
+        SectorCipher::new(&key)
+            .unwrap()
+            .decrypt(0, &mut data[..len as usize / SECTOR_SIZE * SECTOR_SIZE])
+            .unwrap();
+        assert!(data[..8192].iter().all(|byte| *byte == 1));
+        assert!(is_empty(&data[8192..3 * 1024 * 1024]));
+        assert!(
+            data[3 * 1024 * 1024..3 * 1024 * 1024 + 4096]
+                .iter()
+                .all(|byte| *byte == 2)
+        );
+    }
+}
@@ -1,7 +1,8 @@
-use std::path::Path;
+use std::{os::unix::fs::MetadataExt, path::Path};
 
 use anyhow::{Result, bail};
 use caps::{CapSet, Capability};
+use takeoff_proto::proto::Filesystem;
 use tokio::{fs::OpenOptions, process::Command};
 
 pub fn dir_size_in_bytes_recursive(dir_path: impl AsRef<Path>) -> Result<u64> {
@@ -19,6 +20,11 @@ pub fn dir_size_in_bytes_recursive(dir_path: impl AsRef<Path>) -> Result<u64> {
     Ok(size)
 }
 
+/// Bytes a file takes on disk, the holes of sparse files excluded.
+pub fn allocated_bytes(path: impl AsRef<Path>) -> Result<u64> {
+    Ok(std::fs::metadata(path)?.blocks() * 512)
+}
+
 pub async fn create_sparse_file(path: impl AsRef<Path>, size: u64) -> Result<()> {
     let file = OpenOptions::new()
         .read(true)
@@ -31,18 +37,68 @@ pub async fn create_sparse_file(path: impl AsRef<Path>, size: u64) -> Result<()>
     Ok(())
 }
 
-pub async fn format_file_as_ext4_volume_empty(file: impl AsRef<Path>) -> Result<()> {
+pub async fn grow_sparse_file(path: impl AsRef<Path>, size: u64) -> Result<()> {
+    let file = OpenOptions::new().write(true).open(path.as_ref()).await?;
+    if file.metadata().await?.len() > size {
+        bail!("{} is larger than {} bytes", path.as_ref().display(), size);
+    }
+
+    file.set_len(size).await?;
+    Ok(())
+}
+
+/// Copies a sparse file keeping its holes, sharing its blocks when the filesystem supports it.
+pub async fn copy_sparse_file(src: impl AsRef<Path>, dest: impl AsRef<Path>) -> Result<()> {
+    let output = Command::new("cp")
+        .arg("--reflink=auto")
+        .arg("--sparse=always")
+        .arg(src.as_ref())
+        .arg(dest.as_ref())
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        bail!(
+            "failed to copy {}: {}",
+            src.as_ref().display(),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(())
+}
+
+/// Turns a file into a single hole of `size` bytes.
+pub async fn clear_sparse_file(path: impl AsRef<Path>, size: u64) -> Result<()> {
+    let file = OpenOptions::new().write(true).open(path).await?;
+    file.set_len(0).await?;
+    file.set_len(size).await?;
+    Ok(())
+}
+
+pub async fn format_file_as_volume_empty(
+    file: impl AsRef<Path>,
+    filesystem: Filesystem,
+) -> Result<()> {
     let file_path = file.as_ref();
 
-    let output = Command::new("mkfs.ext4")
-        .arg("-F")
+    // mkfs.ext4 asks before formatting a regular file without -F, the others need -f to
+    // overwrite an existing filesystem
+    let force = match filesystem {
+        Filesystem::Ext4 => "-F",
+        Filesystem::Xfs | Filesystem::Btrfs => "-f",
+    };
+
+    let output = Command::new(format!("mkfs.{}", filesystem.name()))
+        .arg(force)
         .arg(file_path)
         .output()
         .await?;
 
     if !output.status.success() {
         bail!(
-            "failed to format volume as ext4: {}",
+            "failed to format volume as {}: {}",
+            filesystem.name(),
             String::from_utf8_lossy(&output.stderr)
         );
     }
@@ -1,10 +1,11 @@
 use std::sync::Arc;
 
 use axum::{
-    extract::FromRequestParts,
+    extract::{FromRequestParts, Query},
     http::{StatusCode, request::Parts},
     response::{IntoResponse, Response},
 };
+use serde::Deserialize;
 
 use crate::{api::ApiState, resources::metadata::Namespace};
 
@@ -49,28 +50,75 @@ impl FromRequestParts<Arc<ApiState>> for ServiceRequestContext {
         } else {
             return Err(ServiceRequestContextError::InvalidToken);
         };
-        let claims = state
-            .auth_handler
-            .verify_token(&token)
-            .map_err(|_| ServiceRequestContextError::InvalidToken)?;
 
         let namespace_header = parts.headers.get("x-ignition-namespace");
         let namespace = if let Some(namespace_header) = namespace_header {
-            Namespace::from_value(
+            Some(
                 namespace_header
                     .to_str()
                     .map_err(|_| ServiceRequestContextError::InvalidNamespace)?
-                    .to_string()
-                    .into(),
+                    .to_string(),
             )
         } else {
-            Namespace::Unspecified
+            None
         };
 
+        ServiceRequestContext::from_token(state, &token, namespace)
+    }
+}
+
+impl ServiceRequestContext {
+    fn from_token(
+        state: &ApiState,
+        token: &str,
+        namespace: Option<String>,
+    ) -> Result<Self, ServiceRequestContextError> {
+        let claims = state
+            .auth_handler
+            .verify_token(token)
+            .map_err(|_| ServiceRequestContextError::InvalidToken)?;
+
         Ok(ServiceRequestContext {
             tenant: claims.tenant,
             sub: claims.sub,
-            namespace,
+            namespace: Namespace::from_value(namespace),
         })
     }
 }
+
+/// Context of requests from browser `EventSource` clients, which can't set headers, the token
+/// and namespace are taken from the `token` and `namespace` query params when the headers are
+/// missing.
+#[derive(Debug, Clone)]
+pub struct EventSourceRequestContext(pub ServiceRequestContext);
+
+#[derive(Debug, Deserialize)]
+struct EventSourceAuthParams {
+    token: Option<String>,
+    namespace: Option<String>,
+}
+
+impl FromRequestParts<Arc<ApiState>> for EventSourceRequestContext {
+    type Rejection = ServiceRequestContextError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<ApiState>,
+    ) -> Result<Self, Self::Rejection> {
+        if parts.headers.contains_key("x-ignition-token") {
+            return ServiceRequestContext::from_request_parts(parts, state)
+                .await
+                .map(EventSourceRequestContext);
+        }
+
+        let Ok(Query(params)) = Query::<EventSourceAuthParams>::try_from_uri(&parts.uri) else {
+            return Err(ServiceRequestContextError::InvalidToken);
+        };
+        let Some(token) = params.token else {
+            return Err(ServiceRequestContextError::InvalidToken);
+        };
+
+        ServiceRequestContext::from_token(state, &token, params.namespace)
+            .map(EventSourceRequestContext)
+    }
+}
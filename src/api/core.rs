@@ -1,54 +1,82 @@
-use std::{str::FromStr, sync::Arc};
+use std::{
+    str::FromStr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::{Result, bail};
 use axum::{
     Json, Router,
-    extract::{FromRequestParts, Query, State, WebSocketUpgrade, ws::Message},
+    extract::{
+        FromRequestParts, Path, Query, State, WebSocketUpgrade,
+        ws::{Message, WebSocket},
+    },
     http::request::Parts,
-    response::{IntoResponse, Response},
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
     routing::{get, put},
 };
 use base64::{DecodeError, Engine, prelude::BASE64_STANDARD};
 use cel::Context;
-use futures_util::{SinkExt, StreamExt};
+use futures_util::{SinkExt, Stream, StreamExt, stream};
 use hyper::HeaderMap;
+use oci_client::Reference;
 use reqwest::StatusCode;
 use serde::Serialize;
 use serde_json::Value;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tracing::error;
+use tracing::{error, warn};
 use url::form_urlencoded;
+use uuid::Uuid;
 
 use crate::{
     agent::logs::LogStreamOrigin,
     api::{
-        ApiState,
+        ApiState, audit,
         auth::RegistryRobotHmacClaims,
-        context::ServiceRequestContext,
+        context::{EventSourceRequestContext, ServiceRequestContext},
         resource_service::{ResourceService, ResourceServiceRouter},
     },
+    constants::DEFAULT_NAMESPACE,
     controller::{
-        AdmissionCheckBeforeDelete, context::ControllerKey, machine::machine_name_from_key,
+        AdmissionCheckBeforeDelete,
+        context::{ControllerEvent, ControllerKey},
+        machine::machine_name_from_key,
     },
     eval::{
         CelCtxExt, CelResourceExt,
         ctx::{GitInfo, LttleInfo},
     },
+    machinery::store::now_millis,
     repository::Repository,
     resource_index::ResourceKind,
     resources::{
-        ProvideMetadata,
+        Convert, ProvideMetadata,
         core::{
-            AllocatedBuilder, DeleteNamespaceParams, DeleteNamespaceResponse, DeletedResource,
-            ExecParams, ListNamespaces, LogStreamParams, Me, Namespace, QueryParams, QueryResponse,
-            RegistryRobot,
+            AllocatedBuilder, AuditLogParams, BandwidthUsage, CLIENT_COMPAT_VERSION, ConsoleParams,
+            CoreDump, DeleteNamespaceParams, DeleteNamespaceResponse, DeletedResource,
+            DownloadCoreDumpParams, ExecParams, ExecSession, ImageInspectParams, ImageLoadParams,
+            ImageLoadResult, ImagePullProgress, InternalCaRoot, InternalCertificateBundle,
+            ListEventsParams, ListNamespaces, LogFilter, LogStreamParams, Me, MetricSample,
+            Namespace, QueryParams, QueryResponse, RegistryRobot, ReleaseBuilderParams,
+            ServiceBandwidthUsage, SupportBundle, SupportBundleMachine, SupportBundleProxyBinding,
+            VolumeQuotaParams, VolumeResizeParams, VolumeRestoreParams, VolumeUsage,
+            VolumeUsageEntry,
         },
+        machine::MachinePhase,
         metadata,
     },
+    utils::size::parse_human_readable_size,
 };
 
 pub struct CoreService {}
 
+/// How far back `machine top` looks.
+const MACHINE_USAGE_HISTORY_SECS: u64 = 15 * 60;
+const CORE_DUMP_CHUNK_SIZE: usize = 256 * 1024;
+
 #[derive(Debug)]
 struct RegistryTokenQuery {
     service: String,    // must equal registry config "service"
@@ -289,6 +317,73 @@ impl ResourceService for CoreService {
                         )
                             .into_response();
                     };
+
+                    audit::record_mutation(
+                        &state.store,
+                        &ctx,
+                        "certificate",
+                        namespace.as_value(),
+                        &metadata.name,
+                        audit::snapshot(&certificate.latest()),
+                        None,
+                    );
+                }
+            }
+
+            // dns records
+            for dns_record in repository
+                .dns_record(ctx.tenant.clone())
+                .list(namespace.clone())
+                .unwrap_or_default()
+            {
+                let metadata = dns_record.metadata();
+
+                resources.push(DeletedResource {
+                    kind: "dns_record".to_string(),
+                    name: metadata.name.clone(),
+                });
+
+                if params.confirm {
+                    let Ok(_) = dns_record
+                        .before_delete(
+                            ctx.tenant.clone(),
+                            repository.clone(),
+                            state.scheduler.agent.clone(),
+                            metadata.clone(),
+                        )
+                        .await
+                    else {
+                        return (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            format!(
+                                "Failed to delete DNS record {}: Before delete hook failed",
+                                metadata.name
+                            ),
+                        )
+                            .into_response();
+                    };
+
+                    let Ok(_) = repository
+                        .dns_record(ctx.tenant.clone())
+                        .delete(namespace.clone(), metadata.name.clone())
+                        .await
+                    else {
+                        return (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            format!("Failed to delete DNS record: {}", metadata.name),
+                        )
+                            .into_response();
+                    };
+
+                    audit::record_mutation(
+                        &state.store,
+                        &ctx,
+                        "dns_record",
+                        namespace.as_value(),
+                        &metadata.name,
+                        audit::snapshot(&dns_record.latest()),
+                        None,
+                    );
                 }
             }
 
@@ -336,6 +431,16 @@ impl ResourceService for CoreService {
                         )
                             .into_response();
                     };
+
+                    audit::record_mutation(
+                        &state.store,
+                        &ctx,
+                        "service",
+                        namespace.as_value(),
+                        &metadata.name,
+                        audit::snapshot(&service.latest()),
+                        None,
+                    );
                 }
             }
 
@@ -364,6 +469,16 @@ impl ResourceService for CoreService {
                         )
                             .into_response();
                     };
+
+                    audit::record_mutation(
+                        &state.store,
+                        &ctx,
+                        "machine",
+                        namespace.as_value(),
+                        &metadata.name,
+                        audit::snapshot(&machine.latest()),
+                        None,
+                    );
                 }
             }
 
@@ -392,6 +507,92 @@ impl ResourceService for CoreService {
                         )
                             .into_response();
                     };
+
+                    audit::record_mutation(
+                        &state.store,
+                        &ctx,
+                        "app",
+                        namespace.as_value(),
+                        &metadata.name,
+                        audit::snapshot(&app.latest()),
+                        None,
+                    );
+                }
+            }
+
+            // crons
+            for cron in repository
+                .cron(ctx.tenant.clone())
+                .list(namespace.clone())
+                .unwrap_or_default()
+            {
+                let metadata = cron.metadata();
+
+                resources.push(DeletedResource {
+                    kind: "cron".to_string(),
+                    name: metadata.name.clone(),
+                });
+
+                if params.confirm {
+                    let Ok(_) = repository
+                        .cron(ctx.tenant.clone())
+                        .delete(namespace.clone(), metadata.name.clone())
+                        .await
+                    else {
+                        return (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            format!("Failed to delete cron: {}", metadata.name),
+                        )
+                            .into_response();
+                    };
+
+                    audit::record_mutation(
+                        &state.store,
+                        &ctx,
+                        "cron",
+                        namespace.as_value(),
+                        &metadata.name,
+                        audit::snapshot(&cron.latest()),
+                        None,
+                    );
+                }
+            }
+
+            // machine groups
+            for machine_group in repository
+                .machine_group(ctx.tenant.clone())
+                .list(namespace.clone())
+                .unwrap_or_default()
+            {
+                let metadata = machine_group.metadata();
+
+                resources.push(DeletedResource {
+                    kind: "machine_group".to_string(),
+                    name: metadata.name.clone(),
+                });
+
+                if params.confirm {
+                    let Ok(_) = repository
+                        .machine_group(ctx.tenant.clone())
+                        .delete(namespace.clone(), metadata.name.clone())
+                        .await
+                    else {
+                        return (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            format!("Failed to delete machine group: {}", metadata.name),
+                        )
+                            .into_response();
+                    };
+
+                    audit::record_mutation(
+                        &state.store,
+                        &ctx,
+                        "machine_group",
+                        namespace.as_value(),
+                        &metadata.name,
+                        audit::snapshot(&machine_group.latest()),
+                        None,
+                    );
                 }
             }
 
@@ -422,6 +623,16 @@ impl ResourceService for CoreService {
                         )
                             .into_response();
                     };
+
+                    audit::record_mutation(
+                        &state.store,
+                        &ctx,
+                        "volume",
+                        namespace.as_value(),
+                        &metadata.name,
+                        audit::snapshot(&volume.latest()),
+                        None,
+                    );
                 }
             }
 
@@ -455,34 +666,7 @@ impl ResourceService for CoreService {
             Query(params): Query<LogStreamParams>,
             ws: WebSocketUpgrade,
         ) -> impl IntoResponse {
-            let (origin, start_ts, end_ts) = match params {
-                LogStreamParams::Machine {
-                    machine_name,
-                    start_ts_ns,
-                    end_ts_ns,
-                } => (
-                    LogStreamOrigin::Machine {
-                        tenant: ctx.tenant.clone(),
-                        name: machine_name,
-                        namespace: ctx.namespace.as_value(),
-                    },
-                    start_ts_ns,
-                    end_ts_ns,
-                ),
-                LogStreamParams::Group {
-                    group_name,
-                    start_ts_ns,
-                    end_ts_ns,
-                } => (
-                    LogStreamOrigin::Group {
-                        tenant: ctx.tenant.clone(),
-                        name: group_name,
-                        namespace: ctx.namespace.as_value(),
-                    },
-                    start_ts_ns,
-                    end_ts_ns,
-                ),
-            };
+            let (origin, start_ts, end_ts, filter) = log_stream_request(&ctx, params);
 
             ws.on_upgrade(move |socket| async move {
                 let (mut write, _) = socket.split();
@@ -505,7 +689,7 @@ impl ResourceService for CoreService {
                             .scheduler
                             .agent
                             .logs()
-                            .query(origin, start_ts..=end_ts)
+                            .query(origin, start_ts..=end_ts, &filter)
                             .await
                         else {
                             return;
@@ -522,7 +706,8 @@ impl ResourceService for CoreService {
                         }
                     }
                     None => {
-                        let Ok(mut stream) = state.scheduler.agent.logs().stream(origin).await
+                        let Ok(mut stream) =
+                            state.scheduler.agent.logs().stream(origin, &filter).await
                         else {
                             return;
                         };
@@ -544,6 +729,35 @@ impl ResourceService for CoreService {
             })
         }
 
+        // server-sent events endpoint for tailing logs, for clients that can't use websockets
+        // browsers' EventSource can't set headers, so it also takes the token as a query param
+        async fn stream_logs_sse(
+            state: State<Arc<ApiState>>,
+            EventSourceRequestContext(ctx): EventSourceRequestContext,
+            Query(params): Query<LogStreamParams>,
+        ) -> impl IntoResponse {
+            let (origin, _, _, filter) = log_stream_request(&ctx, params);
+
+            let stream = match state.scheduler.agent.logs().stream(origin, &filter).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    return (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("Failed to stream logs: {}", e),
+                    )
+                        .into_response();
+                }
+            };
+
+            let events = stream
+                .flat_map(stream::iter)
+                .map(|log| Event::default().json_data(log));
+
+            Sse::new(events)
+                .keep_alive(KeepAlive::default())
+                .into_response()
+        }
+
         // websocket endpoint for machine exec
         async fn exec(
             state: State<Arc<ApiState>>,
@@ -554,6 +768,32 @@ impl ResourceService for CoreService {
             ws.on_upgrade(move |socket| async move {
                 let (mut ws_write, mut ws_read) = socket.split();
 
+                let mut session = ExecSession {
+                    id: Uuid::new_v4().to_string(),
+                    sub: ctx.sub.clone(),
+                    namespace: ctx
+                        .namespace
+                        .as_value()
+                        .unwrap_or(DEFAULT_NAMESPACE.to_string()),
+                    machine_name: params.machine_name.clone(),
+                    command: params.command.clone(),
+                    stdin: params.stdin.unwrap_or(false),
+                    tty: params.tty.unwrap_or(false),
+                    started_at: now_millis(),
+                    ended_at: None,
+                    bytes_in: 0,
+                    bytes_out: 0,
+                    error: None,
+                };
+
+                if let Err(e) = audit::record_exec_session(&state.store, &ctx.tenant, &session) {
+                    error!("Failed to record exec session {}: {}", session.id, e);
+                    let _ = ws_write
+                        .send(Message::Text("Failed to record exec session".into()))
+                        .await;
+                    return;
+                }
+
                 let machine_name = machine_name_from_key(&ControllerKey::new(
                     ctx.tenant.clone(),
                     ResourceKind::Machine,
@@ -561,186 +801,1204 @@ impl ResourceService for CoreService {
                     params.machine_name,
                 ));
 
-                // Find the machine
-                let Some(machine) = state.scheduler.agent.machine().get_machine(&machine_name)
-                else {
-                    let _ = ws_write
-                        .send(Message::Text("Machine not found".into()))
-                        .await;
-                    return;
-                };
+                let mut bytes_in = 0u64;
+                let mut bytes_out = 0u64;
 
-                // Get connection to the machine's exec server (port 50051)
-                let Ok(mut connection) = machine.get_connection(50051, None).await else {
-                    let _ = ws_write
-                        .send(Message::Text("Failed to connect to machine".into()))
-                        .await;
-                    return;
-                };
+                let result = async {
+                    // Find the machine
+                    let Some(machine) = state.scheduler.agent.machine().get_machine(&machine_name)
+                    else {
+                        let _ = ws_write
+                            .send(Message::Text("Machine not found".into()))
+                            .await;
+                        return Err("Machine not found");
+                    };
 
-                let tcp_stream = connection.upstream_socket();
+                    // Get connection to the machine's exec server (port 50051)
+                    let Ok(mut connection) = machine.get_connection(50051, None, None).await else {
+                        let _ = ws_write
+                            .send(Message::Text("Failed to connect to machine".into()))
+                            .await;
+                        return Err("Failed to connect to machine");
+                    };
 
-                // Send the exec request to the exec server
-                // Protocol: [cmd_len: u32][cmd: string][stdin_flag: u8][tty_flag: u8]
-                let cmd_bytes = params.command.as_bytes();
-                let cmd_len = cmd_bytes.len() as u32;
-                let stdin_flag = if params.stdin.unwrap_or(false) {
-                    1u8
-                } else {
-                    0u8
-                };
-                let tty_flag = if params.tty.unwrap_or(false) {
-                    1u8
-                } else {
-                    0u8
-                };
+                    let tcp_stream = connection.upstream_socket();
 
-                if tcp_stream.write_all(&cmd_len.to_le_bytes()).await.is_err() {
-                    return;
-                }
-                if tcp_stream.write_all(cmd_bytes).await.is_err() {
-                    return;
-                }
-                if tcp_stream.write_all(&[stdin_flag]).await.is_err() {
-                    return;
-                }
-                if tcp_stream.write_all(&[tty_flag]).await.is_err() {
-                    return;
-                }
+                    // Send the exec request to the exec server
+                    // Protocol: [cmd_len: u32][cmd: string][stdin_flag: u8][tty_flag: u8]
+                    let cmd_bytes = params.command.as_bytes();
+                    let cmd_len = cmd_bytes.len() as u32;
+                    let stdin_flag = if params.stdin.unwrap_or(false) {
+                        1u8
+                    } else {
+                        0u8
+                    };
+                    let tty_flag = if params.tty.unwrap_or(false) {
+                        1u8
+                    } else {
+                        0u8
+                    };
 
-                let (tcp_read, tcp_write) = tcp_stream.split();
-                let tcp_read = Arc::new(tokio::sync::Mutex::new(tcp_read));
-                let tcp_write = Arc::new(tokio::sync::Mutex::new(tcp_write));
+                    if tcp_stream.write_all(&cmd_len.to_le_bytes()).await.is_err() {
+                        return Err("Failed to send exec request");
+                    }
+                    if tcp_stream.write_all(cmd_bytes).await.is_err() {
+                        return Err("Failed to send exec request");
+                    }
+                    if tcp_stream.write_all(&[stdin_flag]).await.is_err() {
+                        return Err("Failed to send exec request");
+                    }
+                    if tcp_stream.write_all(&[tty_flag]).await.is_err() {
+                        return Err("Failed to send exec request");
+                    }
 
-                // Handle bidirectional data flow
-                let ws_to_tcp = async {
-                    while let Some(msg) = ws_read.next().await {
-                        match msg {
-                            Ok(Message::Binary(data)) => {
-                                if tcp_write.lock().await.write_all(&data).await.is_err() {
-                                    break;
+                    let (tcp_read, tcp_write) = tcp_stream.split();
+                    let tcp_read = Arc::new(tokio::sync::Mutex::new(tcp_read));
+                    let tcp_write = Arc::new(tokio::sync::Mutex::new(tcp_write));
+
+                    // Handle bidirectional data flow
+                    let ws_to_tcp = async {
+                        while let Some(msg) = ws_read.next().await {
+                            match msg {
+                                Ok(Message::Binary(data)) => {
+                                    if tcp_write.lock().await.write_all(&data).await.is_err() {
+                                        break;
+                                    }
+                                    bytes_in += data.len() as u64;
                                 }
+                                Ok(Message::Text(text)) => {
+                                    if tcp_write
+                                        .lock()
+                                        .await
+                                        .write_all(text.as_bytes())
+                                        .await
+                                        .is_err()
+                                    {
+                                        break;
+                                    }
+                                    bytes_in += text.len() as u64;
+                                }
+                                Ok(Message::Close(_)) => break,
+                                _ => {}
                             }
-                            Ok(Message::Text(text)) => {
-                                if tcp_write
-                                    .lock()
-                                    .await
-                                    .write_all(text.as_bytes())
-                                    .await
-                                    .is_err()
-                                {
+                        }
+                    };
+
+                    let tcp_to_ws = async {
+                        let mut buf = [0; 1024];
+                        loop {
+                            match tcp_read.lock().await.read(&mut buf).await {
+                                Ok(0) => {
+                                    // TCP connection closed (command finished)
+                                    break;
+                                }
+                                Ok(n) => {
+                                    bytes_out += n as u64;
+                                    if ws_write
+                                        .send(Message::Binary(buf[..n].to_vec().into()))
+                                        .await
+                                        .is_err()
+                                    {
+                                        break;
+                                    }
+                                }
+                                Err(_) => {
+                                    // TCP error (machine suspended or connection dropped)
                                     break;
                                 }
                             }
-                            Ok(Message::Close(_)) => break,
-                            _ => {}
+                        }
+                        // Always send close message when TCP ends
+                        let _ = ws_write.send(Message::Close(None)).await;
+                    };
+
+                    tokio::select! {
+                        _ = ws_to_tcp => {},
+                        _ = tcp_to_ws => {},
+                    }
+
+                    Ok(())
+                }
+                .await;
+
+                session.ended_at = Some(now_millis());
+                session.bytes_in = bytes_in;
+                session.bytes_out = bytes_out;
+                session.error = result.err().map(|e| e.to_string());
+
+                if let Err(e) = audit::record_exec_session(&state.store, &ctx.tenant, &session) {
+                    error!("Failed to record exec session {}: {}", session.id, e);
+                }
+            })
+        }
+
+        // websocket endpoint for the serial console of a machine, binary messages are typed into
+        // it and its output is sent back, starting with what it recently printed
+        async fn console(
+            state: State<Arc<ApiState>>,
+            ctx: ServiceRequestContext,
+            Query(params): Query<ConsoleParams>,
+            ws: WebSocketUpgrade,
+        ) -> impl IntoResponse {
+            let machine_name = machine_name_from_key(&ControllerKey::new(
+                ctx.tenant.clone(),
+                ResourceKind::Machine,
+                ctx.namespace.as_value(),
+                params.machine_name,
+            ));
+
+            ws.on_upgrade(move |socket| async move {
+                let (mut ws_write, mut ws_read) = socket.split();
+
+                let Some(machine) = state.scheduler.agent.machine().get_machine(&machine_name)
+                else {
+                    let _ = ws_write
+                        .send(Message::Text("Machine not found".into()))
+                        .await;
+                    return;
+                };
+
+                let (mut offset, mut written) = machine.attach_console();
+
+                let ws_to_console = async {
+                    while let Some(msg) = ws_read.next().await {
+                        let input = match msg {
+                            Ok(Message::Binary(data)) => data.to_vec(),
+                            Ok(Message::Text(text)) => text.as_bytes().to_vec(),
+                            Ok(Message::Close(_)) | Err(_) => break,
+                            _ => continue,
+                        };
+
+                        if let Err(e) = machine.write_console(&input).await {
+                            warn!("Failed to write to the console of {}: {}", machine_name, e);
+                            break;
                         }
                     }
                 };
 
-                let tcp_to_ws = async {
-                    let mut buf = [0; 1024];
+                let console_to_ws = async {
                     loop {
-                        match tcp_read.lock().await.read(&mut buf).await {
-                            Ok(0) => {
-                                // TCP connection closed (command finished)
-                                break;
-                            }
-                            Ok(n) => {
-                                if ws_write
-                                    .send(Message::Binary(buf[..n].to_vec().into()))
-                                    .await
-                                    .is_err()
-                                {
-                                    break;
-                                }
-                            }
-                            Err(_) => {
-                                // TCP error (machine suspended or connection dropped)
+                        // everything printed since the last read goes out at once
+                        let output = machine.read_console(offset);
+                        offset += output.dropped + output.data.len() as u64;
+
+                        // a slow client misses some output rather than stalling the guest
+                        if output.dropped > 0 {
+                            let notice = format!(
+                                "\r\n[{} bytes of console output dropped]\r\n",
+                                output.dropped
+                            );
+                            if ws_write
+                                .send(Message::Binary(notice.into_bytes().into()))
+                                .await
+                                .is_err()
+                            {
                                 break;
                             }
                         }
+
+                        if !output.data.is_empty()
+                            && ws_write
+                                .send(Message::Binary(output.data.into()))
+                                .await
+                                .is_err()
+                        {
+                            break;
+                        }
+
+                        if written.changed().await.is_err() {
+                            break;
+                        }
                     }
-                    // Always send close message when TCP ends
                     let _ = ws_write.send(Message::Close(None)).await;
                 };
 
                 tokio::select! {
-                    _ = ws_to_tcp => {},
-                    _ = tcp_to_ws => {},
+                    _ = ws_to_console => {},
+                    _ = console_to_ws => {},
                 }
             })
         }
 
-        async fn query(
+        async fn exec_history(
             state: State<Arc<ApiState>>,
             ctx: ServiceRequestContext,
-            Json(params): Json<QueryParams>,
         ) -> impl IntoResponse {
-            let value = match evaluate_query(state.repository.clone(), ctx, params) {
-                Ok(value) => value,
-                Err(e) => {
-                    return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
-                }
+            let Ok(sessions) =
+                audit::list_exec_sessions(&state.store, &ctx.tenant, ctx.namespace.as_value())
+            else {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Failed to list exec sessions",
+                )
+                    .into_response();
             };
 
-            (
-                StatusCode::OK,
-                Json(QueryResponse {
-                    query_result: value.clone(),
-                }),
-            )
-                .into_response()
+            (StatusCode::OK, Json(sessions)).into_response()
         }
 
-        async fn alloc_builder(
+        async fn audit_log(
             state: State<Arc<ApiState>>,
             ctx: ServiceRequestContext,
+            Json(params): Json<AuditLogParams>,
         ) -> impl IntoResponse {
-            let Ok(build_agent) = state.scheduler.agent.build() else {
+            let Ok(events) = audit::list_audit_events(
+                &state.store,
+                &ctx.tenant,
+                ctx.namespace.as_value(),
+                &params,
+            ) else {
                 return (
                     StatusCode::INTERNAL_SERVER_ERROR,
-                    "Builds are not configured",
+                    "Failed to list audit events",
                 )
                     .into_response();
             };
 
-            let Ok(builder) = build_agent.pick_and_authorize_builder(&ctx.tenant, &ctx.sub) else {
-                return (StatusCode::INTERNAL_SERVER_ERROR, "No available builder").into_response();
-            };
-
-            (
-                StatusCode::OK,
-                Json(AllocatedBuilder {
-                    host: builder.host,
-                    client_cert_pem: builder.client_cert_pem,
-                    client_key_pem: builder.client_key_pem,
-                    ca_cert_pem: builder.ca_cert_pem,
-                }),
-            )
-                .into_response()
+            (StatusCode::OK, Json(events)).into_response()
         }
 
-        let mut router = Router::new();
-        router = router.route("/me", get(me));
-        router = router.route("/registry/robot", get(registry_robot));
-        router = router.route("/registry/builder-robot", get(registry_builder_robot));
-        router = router.route("/registry/auth", get(registry_auth));
-        router = router.route("/namespaces", get(list_namespaces));
-        router = router.route("/namespaces/delete", put(delete_namespace));
-        router = router.route("/logs", get(stream_logs));
-        router = router.route("/exec", get(exec));
-        router = router.route("/query", put(query));
-        router = router.route("/build/alloc", put(alloc_builder));
+        async fn list_events(
+            state: State<Arc<ApiState>>,
+            ctx: ServiceRequestContext,
+            Json(params): Json<ListEventsParams>,
+        ) -> impl IntoResponse {
+            let namespace = metadata::Namespace::from_value_or_default(ctx.namespace.as_value());
+
+            let events = match state.scheduler.agent.events().list(
+                &ctx.tenant,
+                &params.kind,
+                namespace.as_value().as_deref(),
+                &params.name,
+            ) {
+                Ok(events) => events,
+                Err(e) => {
+                    error!("Failed to list events: {}", e);
+                    return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to list events")
+                        .into_response();
+                }
+            };
 
-        ResourceServiceRouter {
-            name: "Core".to_string(),
-            base_path: "/core".to_string(),
-            router,
+            (StatusCode::OK, Json(events)).into_response()
         }
+
+        async fn list_core_dumps(
+            state: State<Arc<ApiState>>,
+            ctx: ServiceRequestContext,
+            Path(name): Path<String>,
+        ) -> impl IntoResponse {
+            let machine_name = machine_name_from_key(&ControllerKey::new(
+                ctx.tenant.clone(),
+                ResourceKind::Machine,
+                ctx.namespace.as_value(),
+                name.clone(),
+            ));
+
+            let core_dumps = match state
+                .scheduler
+                .agent
+                .machine()
+                .list_core_dumps(&machine_name)
+            {
+                Ok(core_dumps) => core_dumps,
+                Err(e) => {
+                    error!("Failed to list core dumps of {}: {}", machine_name, e);
+                    return (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Failed to list core dumps",
+                    )
+                        .into_response();
+                }
+            };
+
+            let core_dumps = core_dumps
+                .into_iter()
+                .map(|core_dump| CoreDump {
+                    id: core_dump.id,
+                    machine_name: name.clone(),
+                    created_at: core_dump.created_at,
+                    size: core_dump.size,
+                })
+                .collect::<Vec<_>>();
+
+            (StatusCode::OK, Json(core_dumps)).into_response()
+        }
+
+        async fn machine_usage(
+            state: State<Arc<ApiState>>,
+            ctx: ServiceRequestContext,
+            Path(name): Path<String>,
+        ) -> impl IntoResponse {
+            let machine_name = machine_name_from_key(&ControllerKey::new(
+                ctx.tenant.clone(),
+                ResourceKind::Machine,
+                ctx.namespace.as_value(),
+                name.clone(),
+            ));
+
+            let Some(machine) = state.scheduler.agent.machine().get_machine(&machine_name) else {
+                return (StatusCode::NOT_FOUND, "Machine not found").into_response();
+            };
+
+            let mut usage = state
+                .scheduler
+                .agent
+                .tracker()
+                .machine_usage(name.clone(), &machine);
+
+            // the history the guest exported comes along when there's a metrics store
+            if let Ok(metrics) = state.scheduler.agent.metrics() {
+                let namespace = ctx
+                    .namespace
+                    .as_value()
+                    .unwrap_or(DEFAULT_NAMESPACE.to_string());
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or_default();
+
+                match metrics
+                    .machine_usage(
+                        &ctx.tenant,
+                        &namespace,
+                        &name,
+                        now.saturating_sub(MACHINE_USAGE_HISTORY_SECS)..=now,
+                    )
+                    .await
+                {
+                    Ok(history) => {
+                        let samples = |samples: Vec<(u64, f64)>| {
+                            samples
+                                .into_iter()
+                                .map(|(timestamp, value)| MetricSample { timestamp, value })
+                                .collect::<Vec<_>>()
+                        };
+                        usage.cpu_history = samples(history.cpu_utilization);
+                        usage.memory_used_history = samples(history.memory_used_bytes);
+                    }
+                    Err(e) => {
+                        warn!(
+                            "Failed to query the usage history of machine {}: {}",
+                            name, e
+                        );
+                    }
+                }
+            }
+
+            (StatusCode::OK, Json(usage)).into_response()
+        }
+
+        async fn image_pull_progress(
+            state: State<Arc<ApiState>>,
+            ctx: ServiceRequestContext,
+            Path(name): Path<String>,
+        ) -> impl IntoResponse {
+            let key = ControllerKey::new(
+                ctx.tenant.clone(),
+                ResourceKind::Machine,
+                ctx.namespace.as_value(),
+                name.clone(),
+            );
+
+            let status = match state
+                .repository
+                .machine(ctx.tenant.clone())
+                .get_status(key.metadata())
+            {
+                Ok(status) => status,
+                Err(e) => {
+                    error!("Failed to get the status of machine {}: {}", name, e);
+                    return (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Failed to get machine status",
+                    )
+                        .into_response();
+                }
+            };
+
+            let progress = status
+                .filter(|status| matches!(status.phase, MachinePhase::PullingImage))
+                .and_then(|status| status.image_resolved_reference)
+                .and_then(|reference| state.scheduler.agent.image().pull_progress(&reference))
+                .unwrap_or(ImagePullProgress {
+                    reference: None,
+                    layers: vec![],
+                });
+
+            (StatusCode::OK, Json(progress)).into_response()
+        }
+
+        // websocket endpoint for loading an image archive, streamed as binary messages and
+        // ended by an empty one
+        async fn load_image(
+            state: State<Arc<ApiState>>,
+            ctx: ServiceRequestContext,
+            Query(params): Query<ImageLoadParams>,
+            ws: WebSocketUpgrade,
+        ) -> impl IntoResponse {
+            ws.on_upgrade(move |socket| async move {
+                let (mut ws_write, mut ws_read) = socket.split();
+
+                let message =
+                    match load_image_archive(&state, &ctx.tenant, &params.reference, &mut ws_read)
+                        .await
+                    {
+                        Ok(result) => serde_json::to_string(&result).unwrap_or_default(),
+                        Err(e) => {
+                            error!("Failed to load image {}: {}", params.reference, e);
+                            format!("Failed to load image: {}", e)
+                        }
+                    };
+
+                let _ = ws_write.send(Message::Text(message.into())).await;
+                let _ = ws_write.close().await;
+            })
+        }
+
+        async fn inspect_image(
+            state: State<Arc<ApiState>>,
+            ctx: ServiceRequestContext,
+            Json(params): Json<ImageInspectParams>,
+        ) -> impl IntoResponse {
+            let Ok(reference) = Reference::from_str(&params.reference) else {
+                return (StatusCode::BAD_REQUEST, "Invalid image reference").into_response();
+            };
+
+            match state
+                .scheduler
+                .agent
+                .image()
+                .image_inspect(ctx.tenant.clone(), reference)
+                .await
+            {
+                Ok(inspection) => (StatusCode::OK, Json(inspection)).into_response(),
+                Err(e) => {
+                    error!("Failed to inspect image {}: {}", params.reference, e);
+                    (
+                        StatusCode::NOT_FOUND,
+                        format!("Failed to inspect image: {}", e),
+                    )
+                        .into_response()
+                }
+            }
+        }
+
+        async fn resize_volume(
+            state: State<Arc<ApiState>>,
+            ctx: ServiceRequestContext,
+            Json(params): Json<VolumeResizeParams>,
+        ) -> impl IntoResponse {
+            let namespace = metadata::Namespace::from_value_or_default(params.namespace.clone());
+            let volume_metadata = metadata::Metadata::new(&params.name, namespace.clone());
+            let volumes = state.repository.volume(ctx.tenant.clone());
+
+            let before = volumes
+                .get(namespace.clone(), params.name.clone())
+                .ok()
+                .flatten()
+                .and_then(|volume| audit::snapshot(&volume.latest()));
+
+            let resize = match crate::controller::volume::resize_volume(
+                ctx.tenant.clone(),
+                state.repository.clone(),
+                state.scheduler.agent.clone(),
+                volume_metadata,
+                params.size.clone(),
+            )
+            .await
+            {
+                Ok(resize) => resize,
+                Err(e) => {
+                    error!("Failed to resize volume {}: {}", params.name, e);
+                    return (
+                        StatusCode::BAD_REQUEST,
+                        format!("Failed to resize volume: {}", e),
+                    )
+                        .into_response();
+                }
+            };
+
+            let after = volumes
+                .get(namespace.clone(), params.name.clone())
+                .ok()
+                .flatten()
+                .and_then(|volume| audit::snapshot(&volume.latest()));
+            audit::record_mutation(
+                &state.store,
+                &ctx,
+                "volume",
+                namespace.as_value(),
+                &params.name,
+                before,
+                after,
+            );
+
+            (StatusCode::OK, Json(resize)).into_response()
+        }
+
+        async fn list_volume_backups(
+            state: State<Arc<ApiState>>,
+            ctx: ServiceRequestContext,
+            Path(name): Path<String>,
+        ) -> impl IntoResponse {
+            let volume_metadata = metadata::Metadata::new(&name, ctx.namespace.clone());
+
+            match crate::controller::volume::list_volume_backups(
+                ctx.tenant.clone(),
+                state.repository.clone(),
+                state.scheduler.agent.clone(),
+                volume_metadata,
+            )
+            .await
+            {
+                Ok(backups) => (StatusCode::OK, Json(backups)).into_response(),
+                Err(e) => {
+                    error!("Failed to list backups of volume {}: {}", name, e);
+                    (
+                        StatusCode::BAD_REQUEST,
+                        format!("Failed to list backups: {}", e),
+                    )
+                        .into_response()
+                }
+            }
+        }
+
+        async fn restore_volume(
+            state: State<Arc<ApiState>>,
+            ctx: ServiceRequestContext,
+            Json(params): Json<VolumeRestoreParams>,
+        ) -> impl IntoResponse {
+            let namespace = metadata::Namespace::from_value_or_default(params.namespace.clone());
+            let volume_metadata = metadata::Metadata::new(&params.name, namespace);
+
+            match crate::controller::volume::restore_volume(
+                ctx.tenant.clone(),
+                state.repository.clone(),
+                state.scheduler.agent.clone(),
+                volume_metadata,
+                params.backup.clone(),
+            )
+            .await
+            {
+                Ok(restore) => (StatusCode::OK, Json(restore)).into_response(),
+                Err(e) => {
+                    error!("Failed to restore volume {}: {}", params.name, e);
+                    (
+                        StatusCode::BAD_REQUEST,
+                        format!("Failed to restore volume: {}", e),
+                    )
+                        .into_response()
+                }
+            }
+        }
+
+        async fn download_core_dump(
+            state: State<Arc<ApiState>>,
+            ctx: ServiceRequestContext,
+            Query(params): Query<DownloadCoreDumpParams>,
+            ws: WebSocketUpgrade,
+        ) -> impl IntoResponse {
+            let machine_name = machine_name_from_key(&ControllerKey::new(
+                ctx.tenant.clone(),
+                ResourceKind::Machine,
+                params.namespace.clone(),
+                params.machine_name.clone(),
+            ));
+
+            let Ok(path) = state
+                .scheduler
+                .agent
+                .machine()
+                .core_dump_path(&machine_name, &params.id)
+            else {
+                return (StatusCode::NOT_FOUND, "Core dump not found").into_response();
+            };
+
+            // core dumps go up to the machine's memory size, they are streamed in chunks followed
+            // by an empty message marking the end
+            ws.on_upgrade(move |mut socket| async move {
+                if let Err(e) = send_core_dump(&path, &mut socket).await {
+                    error!("Failed to send core dump {}: {}", path.display(), e);
+                    let message = format!("Failed to read core dump: {}", e);
+                    let _ = socket.send(Message::Text(message.into())).await;
+                }
+                let _ = socket.close().await;
+            })
+            .into_response()
+        }
+
+        async fn query(
+            state: State<Arc<ApiState>>,
+            ctx: ServiceRequestContext,
+            Json(params): Json<QueryParams>,
+        ) -> impl IntoResponse {
+            let value = match evaluate_query(state.repository.clone(), ctx, params) {
+                Ok(value) => value,
+                Err(e) => {
+                    return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+                }
+            };
+
+            (
+                StatusCode::OK,
+                Json(QueryResponse {
+                    query_result: value.clone(),
+                }),
+            )
+                .into_response()
+        }
+
+        async fn alloc_builder(
+            state: State<Arc<ApiState>>,
+            ctx: ServiceRequestContext,
+        ) -> impl IntoResponse {
+            let Ok(build_agent) = state.scheduler.agent.build() else {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Builds are not configured",
+                )
+                    .into_response();
+            };
+
+            let Ok(builder) = build_agent.pick_and_authorize_builder(&ctx.tenant, &ctx.sub) else {
+                return (StatusCode::INTERNAL_SERVER_ERROR, "No available builder").into_response();
+            };
+
+            (
+                StatusCode::OK,
+                Json(AllocatedBuilder {
+                    allocation_id: builder.allocation_id,
+                    host: builder.host,
+                    client_cert_pem: builder.client_cert_pem,
+                    client_key_pem: builder.client_key_pem,
+                    ca_cert_pem: builder.ca_cert_pem,
+                }),
+            )
+                .into_response()
+        }
+
+        async fn release_builder(
+            state: State<Arc<ApiState>>,
+            ctx: ServiceRequestContext,
+            Json(params): Json<ReleaseBuilderParams>,
+        ) -> impl IntoResponse {
+            let Ok(build_agent) = state.scheduler.agent.build() else {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Builds are not configured",
+                )
+                    .into_response();
+            };
+
+            if let Err(e) = build_agent.release_builder(&ctx.tenant, &params.allocation_id) {
+                return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+            }
+
+            StatusCode::OK.into_response()
+        }
+
+        async fn image_gc_dry_run(
+            state: State<Arc<ApiState>>,
+            ctx: ServiceRequestContext,
+        ) -> impl IntoResponse {
+            if !state.admin_tenants.contains(&ctx.tenant) {
+                return (StatusCode::FORBIDDEN, "Admin access required").into_response();
+            }
+
+            match state.scheduler.agent.image().collect_garbage(true).await {
+                Ok(report) => (StatusCode::OK, Json(report)).into_response(),
+                Err(e) => {
+                    error!("Failed to plan image gc: {}", e);
+                    (StatusCode::INTERNAL_SERVER_ERROR, "Failed to plan image gc").into_response()
+                }
+            }
+        }
+
+        async fn support_bundle(
+            state: State<Arc<ApiState>>,
+            ctx: ServiceRequestContext,
+        ) -> impl IntoResponse {
+            if !state.admin_tenants.contains(&ctx.tenant) {
+                return (StatusCode::FORBIDDEN, "Admin access required").into_response();
+            }
+
+            let mut machines = vec![];
+            for machine in state.scheduler.agent.machine().list_machines() {
+                machines.push(SupportBundleMachine {
+                    name: machine.config.name.clone(),
+                    state: format!("{:?}", machine.get_state().await),
+                    network_tag: machine.config.network_tag.clone(),
+                    last_boot_duration_ms: machine
+                        .get_last_boot_duration()
+                        .await
+                        .map(|d| d.as_millis() as u64),
+                    last_exit_code: machine.get_last_exit_code().await,
+                });
+            }
+            machines.sort_by(|a, b| a.name.cmp(&b.name));
+
+            let proxy = state.scheduler.agent.proxy();
+            let mut proxy_bindings = proxy
+                .list_bindings()
+                .into_iter()
+                .map(|(name, binding)| SupportBundleProxyBinding {
+                    name,
+                    target_network_tag: binding.target_network_tag,
+                    target_port: binding.target_port,
+                    mode: format!("{:?}", binding.mode),
+                    service: binding.service.map(|service| {
+                        format!("{}/{}/{}", service.tenant, service.namespace, service.name)
+                    }),
+                })
+                .collect::<Vec<_>>();
+            proxy_bindings.sort_by(|a, b| a.name.cmp(&b.name));
+
+            let last_gc_report = state
+                .scheduler
+                .agent
+                .build()
+                .ok()
+                .and_then(|build_agent| build_agent.last_gc_report());
+
+            (
+                StatusCode::OK,
+                Json(SupportBundle {
+                    generated_at: now_millis(),
+                    version: env!("CARGO_PKG_VERSION").to_string(),
+                    compat_version: CLIENT_COMPAT_VERSION.to_string(),
+                    config: state.daemon_config.clone(),
+                    scheduler: state.scheduler.stats(),
+                    recent_events: state.scheduler.recent_events(),
+                    machines,
+                    proxy_bindings,
+                    proxy_metrics: proxy.metrics().render(),
+                    last_gc_report,
+                }),
+            )
+                .into_response()
+        }
+
+        async fn bandwidth_usage(
+            state: State<Arc<ApiState>>,
+            ctx: ServiceRequestContext,
+        ) -> impl IntoResponse {
+            let usage = match state
+                .scheduler
+                .agent
+                .tracker()
+                .list_bandwidth_usage(&ctx.tenant)
+                .await
+            {
+                Ok(usage) => usage,
+                Err(e) => {
+                    error!("Failed to list bandwidth usage of {}: {}", ctx.tenant, e);
+                    return (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Failed to list bandwidth usage",
+                    )
+                        .into_response();
+                }
+            };
+
+            let mut services = usage
+                .into_iter()
+                .map(|usage| ServiceBandwidthUsage {
+                    namespace: usage.namespace,
+                    service: usage.service,
+                    bytes_in: usage.bytes_in,
+                    bytes_out: usage.bytes_out,
+                    updated_at: usage.updated_at,
+                })
+                .collect::<Vec<_>>();
+            services.sort_by(|a, b| (&a.namespace, &a.service).cmp(&(&b.namespace, &b.service)));
+
+            (
+                StatusCode::OK,
+                Json(BandwidthUsage {
+                    bytes_in: services.iter().map(|s| s.bytes_in).sum(),
+                    bytes_out: services.iter().map(|s| s.bytes_out).sum(),
+                    services,
+                }),
+            )
+                .into_response()
+        }
+
+        async fn volume_usage(
+            state: State<Arc<ApiState>>,
+            ctx: ServiceRequestContext,
+        ) -> impl IntoResponse {
+            let volume_agent = state.scheduler.agent.volume();
+            let usage = match volume_agent.tenant_usage(&ctx.tenant) {
+                Ok(usage) => usage,
+                Err(e) => {
+                    error!("Failed to get volume usage of {}: {}", ctx.tenant, e);
+                    return (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Failed to get volume usage",
+                    )
+                        .into_response();
+                }
+            };
+
+            let volumes = state.repository.volume(ctx.tenant.clone());
+            let mut entries = vec![];
+            for volume in volumes
+                .list(metadata::Namespace::Unspecified)
+                .unwrap_or_default()
+            {
+                let metadata = volume.metadata();
+                let Some(volume_id) = volumes
+                    .get_status(metadata.clone())
+                    .ok()
+                    .flatten()
+                    .and_then(|status| status.volume_id)
+                else {
+                    continue;
+                };
+                let Ok(Some(agent_volume)) = volume_agent.volume(&volume_id) else {
+                    continue;
+                };
+
+                entries.push(VolumeUsageEntry {
+                    namespace: metadata.namespace.unwrap_or(DEFAULT_NAMESPACE.to_string()),
+                    name: metadata.name,
+                    provisioned_bytes: agent_volume.provisioned_bytes(),
+                    used_bytes: volume_agent
+                        .volume_used_bytes(&agent_volume)
+                        .unwrap_or_default(),
+                });
+            }
+            entries.sort_by(|a, b| (&a.namespace, &a.name).cmp(&(&b.namespace, &b.name)));
+
+            (
+                StatusCode::OK,
+                Json(VolumeUsage {
+                    provisioned_bytes: usage.provisioned_bytes,
+                    used_bytes: usage.used_bytes,
+                    quota_bytes: usage.quota_bytes,
+                    volumes: entries,
+                }),
+            )
+                .into_response()
+        }
+
+        async fn set_volume_quota(
+            state: State<Arc<ApiState>>,
+            ctx: ServiceRequestContext,
+            Json(params): Json<VolumeQuotaParams>,
+        ) -> impl IntoResponse {
+            if !state.admin_tenants.contains(&ctx.tenant) {
+                return (StatusCode::FORBIDDEN, "Admin access required").into_response();
+            }
+
+            let volume_agent = state.scheduler.agent.volume();
+            let result = match params.quota.as_deref() {
+                None => volume_agent.reset_tenant_quota(&params.tenant),
+                Some("unlimited") => volume_agent.set_tenant_quota(&params.tenant, None),
+                Some(quota) => match parse_human_readable_size(quota) {
+                    Ok(quota_bytes) => {
+                        volume_agent.set_tenant_quota(&params.tenant, Some(quota_bytes))
+                    }
+                    Err(e) => {
+                        return (StatusCode::BAD_REQUEST, format!("Invalid quota: {}", e))
+                            .into_response();
+                    }
+                },
+            };
+
+            if let Err(e) = result {
+                error!("Failed to set volume quota of {}: {}", params.tenant, e);
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Failed to set volume quota",
+                )
+                    .into_response();
+            }
+
+            StatusCode::OK.into_response()
+        }
+
+        async fn get_internal_ca(
+            state: State<Arc<ApiState>>,
+            ctx: ServiceRequestContext,
+        ) -> impl IntoResponse {
+            let ca = match state
+                .scheduler
+                .agent
+                .certificate()
+                .internal_ca(&ctx.tenant)
+                .await
+            {
+                Ok(ca) => ca,
+                Err(e) => {
+                    error!("Failed to get internal CA of {}: {}", ctx.tenant, e);
+                    return (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Failed to get internal CA",
+                    )
+                        .into_response();
+                }
+            };
+
+            (
+                StatusCode::OK,
+                Json(InternalCaRoot {
+                    cert_pem: ca.trust_bundle(now_millis()),
+                    not_after: ca.not_after,
+                }),
+            )
+                .into_response()
+        }
+
+        async fn rotate_internal_ca(
+            state: State<Arc<ApiState>>,
+            ctx: ServiceRequestContext,
+        ) -> impl IntoResponse {
+            let ca = match state
+                .scheduler
+                .agent
+                .certificate()
+                .rotate_internal_ca(&ctx.tenant)
+                .await
+            {
+                Ok(ca) => ca,
+                Err(e) => {
+                    error!("Failed to rotate internal CA of {}: {}", ctx.tenant, e);
+                    return (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Failed to rotate internal CA",
+                    )
+                        .into_response();
+                }
+            };
+
+            // the certificates signed by the previous root are issued again right away
+            let certificates = state
+                .repository
+                .certificate(ctx.tenant.clone())
+                .list(metadata::Namespace::Unspecified)
+                .unwrap_or_default();
+            for certificate in certificates {
+                if let Err(e) = state
+                    .scheduler
+                    .push(
+                        &ctx.tenant,
+                        ControllerEvent::ResourceChange(
+                            ResourceKind::Certificate,
+                            certificate.metadata(),
+                        ),
+                    )
+                    .await
+                {
+                    warn!("Failed to reissue certificate after CA rotation: {}", e);
+                }
+            }
+
+            (
+                StatusCode::OK,
+                Json(InternalCaRoot {
+                    cert_pem: ca.trust_bundle(now_millis()),
+                    not_after: ca.not_after,
+                }),
+            )
+                .into_response()
+        }
+
+        async fn get_internal_certificate(
+            state: State<Arc<ApiState>>,
+            ctx: ServiceRequestContext,
+            Path(name): Path<String>,
+        ) -> impl IntoResponse {
+            let namespace = ctx
+                .namespace
+                .as_value()
+                .unwrap_or(DEFAULT_NAMESPACE.to_string());
+
+            let certificate = match state
+                .scheduler
+                .agent
+                .certificate()
+                .get_internal_certificate(&ctx.tenant, &namespace, &name)
+            {
+                Ok(Some(certificate)) => certificate,
+                Ok(None) => {
+                    return (StatusCode::NOT_FOUND, "Internal certificate not issued")
+                        .into_response();
+                }
+                Err(e) => {
+                    error!("Failed to get internal certificate {}: {}", name, e);
+                    return (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Failed to get internal certificate",
+                    )
+                        .into_response();
+                }
+            };
+
+            (
+                StatusCode::OK,
+                Json(InternalCertificateBundle {
+                    name,
+                    domains: certificate.domains,
+                    cert_pem: certificate.cert_pem,
+                    key_pem: certificate.key_pem,
+                    ca_cert_pem: certificate.ca_cert_pem,
+                    not_before: certificate.not_before,
+                    not_after: certificate.not_after,
+                }),
+            )
+                .into_response()
+        }
+
+        let mut router = Router::new();
+        router = router.route("/me", get(me));
+        router = router.route("/registry/robot", get(registry_robot));
+        router = router.route("/registry/builder-robot", get(registry_builder_robot));
+        router = router.route("/registry/auth", get(registry_auth));
+        router = router.route("/namespaces", get(list_namespaces));
+        router = router.route("/namespaces/delete", put(delete_namespace));
+        router = router.route("/logs", get(stream_logs));
+        router = router.route("/logs/sse", get(stream_logs_sse));
+        router = router.route("/exec", get(exec));
+        router = router.route("/exec/history", get(exec_history));
+        router = router.route("/console", get(console));
+        router = router.route("/audit", put(audit_log));
+        router = router.route("/events", put(list_events));
+        router = router.route("/machine/{name}/cores", get(list_core_dumps));
+        router = router.route("/machine/{name}/usage", get(machine_usage));
+        router = router.route("/machine/{name}/pull", get(image_pull_progress));
+        router = router.route("/images/load", get(load_image));
+        router = router.route("/images/inspect", put(inspect_image));
+        router = router.route("/volumes/resize", put(resize_volume));
+        router = router.route("/volume/{name}/backups", get(list_volume_backups));
+        router = router.route("/volumes/restore", put(restore_volume));
+        router = router.route("/cores/download", get(download_core_dump));
+        router = router.route("/query", put(query));
+        router = router.route("/build/alloc", put(alloc_builder));
+        router = router.route("/build/release", put(release_builder));
+        router = router.route("/admin/support-bundle", get(support_bundle));
+        router = router.route("/admin/image-gc", get(image_gc_dry_run));
+        router = router.route("/admin/volume-quota", put(set_volume_quota));
+        router = router.route("/usage/bandwidth", get(bandwidth_usage));
+        router = router.route("/usage/volumes", get(volume_usage));
+        router = router.route("/ca", get(get_internal_ca));
+        router = router.route("/ca/rotate", put(rotate_internal_ca));
+        router = router.route("/certificate/{name}/bundle", get(get_internal_certificate));
+
+        ResourceServiceRouter {
+            name: "Core".to_string(),
+            base_path: "/core".to_string(),
+            router,
+        }
+    }
+}
+
+/// Where to read the logs of a stream request from, its time range and its filter.
+fn log_stream_request(
+    ctx: &ServiceRequestContext,
+    params: LogStreamParams,
+) -> (LogStreamOrigin, Option<String>, Option<String>, LogFilter) {
+    match params {
+        LogStreamParams::Machine {
+            machine_name,
+            start_ts_ns,
+            end_ts_ns,
+            filter,
+        } => (
+            LogStreamOrigin::Machine {
+                tenant: ctx.tenant.clone(),
+                name: machine_name,
+                namespace: ctx.namespace.as_value(),
+            },
+            start_ts_ns,
+            end_ts_ns,
+            filter,
+        ),
+        LogStreamParams::Group {
+            group_name,
+            start_ts_ns,
+            end_ts_ns,
+            filter,
+        } => (
+            LogStreamOrigin::Group {
+                tenant: ctx.tenant.clone(),
+                name: group_name,
+                namespace: ctx.namespace.as_value(),
+            },
+            start_ts_ns,
+            end_ts_ns,
+            filter,
+        ),
+        LogStreamParams::Service {
+            service_name,
+            start_ts_ns,
+            end_ts_ns,
+            filter,
+        } => (
+            LogStreamOrigin::Service {
+                tenant: ctx.tenant.clone(),
+                name: service_name,
+                namespace: ctx.namespace.as_value(),
+            },
+            start_ts_ns,
+            end_ts_ns,
+            filter,
+        ),
     }
 }
 
+/// Receives an image archive streamed over a websocket and loads it for `tenant`.
+async fn send_core_dump(path: &std::path::Path, socket: &mut WebSocket) -> Result<()> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut buffer = vec![0u8; CORE_DUMP_CHUNK_SIZE];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+
+        socket
+            .send(Message::Binary(buffer[..read].to_vec().into()))
+            .await?;
+    }
+
+    socket.send(Message::Binary(Vec::new().into())).await?;
+
+    Ok(())
+}
+
+async fn load_image_archive(
+    state: &ApiState,
+    tenant: &str,
+    reference: &str,
+    ws_read: &mut (impl Stream<Item = Result<Message, axum::Error>> + Unpin),
+) -> Result<ImageLoadResult> {
+    let reference = Reference::from_str(reference)?;
+    let image_agent = state.scheduler.agent.image();
+
+    let archive_file = image_agent.archive_file()?;
+    let mut file = tokio::fs::File::from_std(archive_file.reopen()?);
+    loop {
+        match ws_read.next().await {
+            Some(Ok(Message::Binary(data))) if data.is_empty() => break,
+            Some(Ok(Message::Binary(data))) => file.write_all(&data).await?,
+            Some(Ok(_)) => {}
+            Some(Err(e)) => return Err(e.into()),
+            None => bail!("connection closed before the end of the archive"),
+        }
+    }
+    file.flush().await?;
+
+    let image = image_agent
+        .image_load(tenant.to_string(), reference, archive_file.path())
+        .await?;
+
+    Ok(ImageLoadResult {
+        reference: image.reference,
+        digest: image.digest,
+    })
+}
+
 fn evaluate_query(
     repository: Arc<Repository>,
     ctx: ServiceRequestContext,
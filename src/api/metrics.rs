@@ -0,0 +1,41 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use axum::{Router, extract::State, http::header, response::IntoResponse, routing::get};
+use tokio::net::TcpListener;
+use tracing::info;
+
+use crate::controller::scheduler::Scheduler;
+
+pub struct MetricsServerConfig {
+    pub host: String,
+    pub port: u16,
+}
+
+/// Serves Prometheus metrics on a separate, unauthenticated listener so it can be kept off the
+/// public API address.
+pub async fn start_metrics_server(
+    scheduler: Arc<Scheduler>,
+    config: MetricsServerConfig,
+) -> Result<()> {
+    let app = Router::new()
+        .route("/metrics", get(metrics))
+        .with_state(scheduler);
+
+    let addr = format!("{}:{}", config.host, config.port);
+    info!("starting metrics server on {}", addr);
+
+    let listener = TcpListener::bind(addr).await?;
+
+    axum::serve(listener, app).await?;
+
+    Ok(())
+}
+
+async fn metrics(State(scheduler): State<Arc<Scheduler>>) -> impl IntoResponse {
+    let mut body = scheduler.agent.proxy().metrics().render();
+    body.push_str(&scheduler.agent.dns().metrics().render());
+    body.push_str(&scheduler.agent.machine().render_volume_metrics());
+
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
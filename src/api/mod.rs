@@ -1,8 +1,11 @@
+pub mod audit;
 pub mod auth;
 pub mod context;
 pub mod core;
 pub mod gadget;
+pub mod metrics;
 pub mod resource_service;
+pub mod ssh;
 
 use std::sync::Arc;
 
@@ -15,6 +18,7 @@ use axum::{
     response::Response,
 };
 use hyper::StatusCode;
+use serde_json::Value;
 use tokio::net::TcpListener;
 use tracing::info;
 
@@ -34,11 +38,17 @@ pub struct ApiState {
     pub repository: Arc<Repository>,
     pub scheduler: Arc<Scheduler>,
     pub auth_handler: Arc<AuthHandler>,
+    pub admin_tenants: Vec<String>,
+    pub daemon_config: Value,
 }
 
 pub struct ApiServerConfig {
     pub host: String,
     pub port: u16,
+    /// Tenants allowed to use the admin endpoints.
+    pub admin_tenants: Vec<String>,
+    /// Daemon config with secrets redacted, included in support bundles.
+    pub daemon_config: Value,
 }
 
 pub struct ApiServer {
@@ -61,6 +71,8 @@ impl ApiServer {
                 repository,
                 scheduler,
                 auth_handler,
+                admin_tenants: config.admin_tenants.clone(),
+                daemon_config: config.daemon_config.clone(),
             }),
             config,
             routers: vec![],
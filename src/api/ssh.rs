@@ -0,0 +1,366 @@
+use std::{collections::HashMap, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+
+use anyhow::{Result, anyhow, bail};
+use russh::{
+    Channel, ChannelId, CryptoVec,
+    server::{Auth, Config, Handle, Handler, Msg, Session, run_stream},
+};
+use tokio::{
+    io::{AsyncWriteExt, copy_bidirectional},
+    net::TcpListener,
+    spawn,
+};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    api::{
+        audit,
+        auth::{AuthHandler, AuthTokenClaims},
+    },
+    constants::DEFAULT_NAMESPACE,
+    controller::{context::ControllerKey, machine::machine_name_from_key, scheduler::Scheduler},
+    machinery::store::{Store, now_millis},
+    resource_index::ResourceKind,
+    resources::core::ExecSession,
+};
+
+/// Port of the exec server run by takeoff inside every machine.
+const EXEC_SERVER_PORT: u16 = 50051;
+
+/// Command run for sessions asking for a shell rather than a command, eg. a bare `ssh`.
+const SHELL_COMMAND: &str = "/bin/sh";
+
+pub struct SshGatewayConfig {
+    pub host: String,
+    pub port: u16,
+    /// OpenSSH private key the gateway identifies itself with, eg. from `ssh-keygen -t ed25519`.
+    pub host_key_path: PathBuf,
+}
+
+/// SSH front for the exec server of machines, so users get a shell with a plain
+/// `ssh <machine>[.<namespace>]@<gateway>` instead of a port per machine. The password is an API
+/// token, which tells the tenant of the machine.
+pub struct SshGateway {
+    store: Arc<Store>,
+    scheduler: Arc<Scheduler>,
+    auth_handler: Arc<AuthHandler>,
+    config: SshGatewayConfig,
+}
+
+/// Machine an SSH user asked for, `<machine>` or `<machine>.<namespace>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SshTarget {
+    machine: String,
+    namespace: Option<String>,
+}
+
+impl SshTarget {
+    fn parse(user: &str) -> Result<Self> {
+        let (machine, namespace) = match user.split_once('.') {
+            Some((machine, namespace)) => (machine, Some(namespace.to_string())),
+            None => (user, None),
+        };
+
+        if machine.is_empty() || namespace.as_ref().is_some_and(|n| n.is_empty()) {
+            bail!(
+                "invalid ssh user '{}', expected <machine>[.<namespace>]",
+                user
+            );
+        }
+
+        Ok(Self {
+            machine: machine.to_string(),
+            namespace,
+        })
+    }
+}
+
+impl SshGateway {
+    pub fn new(
+        store: Arc<Store>,
+        scheduler: Arc<Scheduler>,
+        auth_handler: Arc<AuthHandler>,
+        config: SshGatewayConfig,
+    ) -> Self {
+        Self {
+            store,
+            scheduler,
+            auth_handler,
+            config,
+        }
+    }
+
+    pub async fn start(self) -> Result<()> {
+        let host_key = russh::keys::load_secret_key(&self.config.host_key_path, None)?;
+        let ssh_config = Arc::new(Config {
+            keys: vec![host_key],
+            auth_rejection_time: Duration::from_secs(1),
+            auth_rejection_time_initial: Some(Duration::from_secs(0)),
+            inactivity_timeout: Some(Duration::from_secs(60 * 60)),
+            ..Default::default()
+        });
+
+        let addr = format!("{}:{}", self.config.host, self.config.port);
+        info!("starting ssh gateway on {}", addr);
+
+        let listener = TcpListener::bind(addr).await?;
+        let gateway = Arc::new(self);
+
+        loop {
+            let (stream, client_addr) = listener.accept().await?;
+            let handler = SshSession {
+                gateway: gateway.clone(),
+                client_addr,
+                claims: None,
+                target: None,
+                channels: HashMap::new(),
+            };
+
+            let ssh_config = ssh_config.clone();
+            spawn(async move {
+                let session = match run_stream(ssh_config, stream, handler).await {
+                    Ok(session) => session,
+                    Err(e) => {
+                        warn!("ssh handshake with {} failed: {}", client_addr, e);
+                        return;
+                    }
+                };
+
+                if let Err(e) = session.await {
+                    warn!("ssh session with {} failed: {}", client_addr, e);
+                }
+            });
+        }
+    }
+}
+
+struct SshSession {
+    gateway: Arc<SshGateway>,
+    client_addr: SocketAddr,
+    claims: Option<AuthTokenClaims>,
+    target: Option<SshTarget>,
+    channels: HashMap<ChannelId, SshChannel>,
+}
+
+struct SshChannel {
+    channel: Channel<Msg>,
+    tty: bool,
+}
+
+impl SshSession {
+    fn start_exec(&mut self, id: ChannelId, command: String, session: &mut Session) -> Result<()> {
+        let (Some(claims), Some(target)) = (self.claims.clone(), self.target.clone()) else {
+            bail!("ssh session isn't authenticated");
+        };
+        let Some(channel) = self.channels.remove(&id) else {
+            bail!("unknown ssh channel {}", id);
+        };
+
+        session.channel_success(id)?;
+
+        let gateway = self.gateway.clone();
+        let handle = session.handle();
+        let client_addr = self.client_addr;
+        spawn(async move {
+            run_exec(
+                gateway,
+                handle,
+                claims,
+                target,
+                channel,
+                command,
+                client_addr,
+            )
+            .await;
+        });
+
+        Ok(())
+    }
+}
+
+impl Handler for SshSession {
+    type Error = anyhow::Error;
+
+    async fn auth_password(&mut self, user: &str, password: &str) -> Result<Auth, Self::Error> {
+        let reject = Auth::Reject {
+            proceed_with_methods: None,
+            partial_success: false,
+        };
+
+        let Ok(target) = SshTarget::parse(user) else {
+            return Ok(reject);
+        };
+        let Ok(claims) = self.gateway.auth_handler.verify_token(password) else {
+            info!("rejected ssh login of {} from {}", user, self.client_addr);
+            return Ok(reject);
+        };
+
+        self.claims = Some(claims);
+        self.target = Some(target);
+        Ok(Auth::Accept)
+    }
+
+    async fn channel_open_session(
+        &mut self,
+        channel: Channel<Msg>,
+        _session: &mut Session,
+    ) -> Result<bool, Self::Error> {
+        self.channels.insert(
+            channel.id(),
+            SshChannel {
+                channel,
+                tty: false,
+            },
+        );
+        Ok(true)
+    }
+
+    async fn pty_request(
+        &mut self,
+        channel: ChannelId,
+        _term: &str,
+        _col_width: u32,
+        _row_height: u32,
+        _pix_width: u32,
+        _pix_height: u32,
+        _modes: &[(russh::Pty, u32)],
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        // the exec server picks the terminal size on its own
+        if let Some(ssh_channel) = self.channels.get_mut(&channel) {
+            ssh_channel.tty = true;
+        }
+        session.channel_success(channel)?;
+        Ok(())
+    }
+
+    async fn shell_request(
+        &mut self,
+        channel: ChannelId,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        self.start_exec(channel, SHELL_COMMAND.to_string(), session)
+    }
+
+    async fn exec_request(
+        &mut self,
+        channel: ChannelId,
+        data: &[u8],
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        let command = String::from_utf8(data.to_vec())
+            .map_err(|_| anyhow!("ssh exec command isn't valid utf-8"))?;
+        self.start_exec(channel, command, session)
+    }
+}
+
+async fn run_exec(
+    gateway: Arc<SshGateway>,
+    handle: Handle,
+    claims: AuthTokenClaims,
+    target: SshTarget,
+    channel: SshChannel,
+    command: String,
+    client_addr: SocketAddr,
+) {
+    let id = channel.channel.id();
+    let mut session = ExecSession {
+        id: Uuid::new_v4().to_string(),
+        sub: claims.sub.clone(),
+        namespace: target
+            .namespace
+            .clone()
+            .unwrap_or(DEFAULT_NAMESPACE.to_string()),
+        machine_name: target.machine.clone(),
+        command: command.clone(),
+        stdin: true,
+        tty: channel.tty,
+        started_at: now_millis(),
+        ended_at: None,
+        bytes_in: 0,
+        bytes_out: 0,
+        error: None,
+    };
+
+    let result = async {
+        audit::record_exec_session(&gateway.store, &claims.tenant, &session)?;
+
+        let machine_name = machine_name_from_key(&ControllerKey::new(
+            claims.tenant.clone(),
+            ResourceKind::Machine,
+            target.namespace.clone(),
+            target.machine.clone(),
+        ));
+        let Some(machine) = gateway.scheduler.agent.machine().get_machine(&machine_name) else {
+            bail!("machine {} not found", target.machine);
+        };
+
+        let mut connection = machine
+            .get_connection(EXEC_SERVER_PORT, None, None)
+            .await
+            .map_err(|_| anyhow!("failed to connect to machine {}", target.machine))?;
+        let upstream = connection.upstream_socket();
+
+        // [cmd_len: u32][cmd: string][stdin_flag: u8][tty_flag: u8], see `exec` in the core api
+        upstream
+            .write_all(&(command.len() as u32).to_le_bytes())
+            .await?;
+        upstream.write_all(command.as_bytes()).await?;
+        upstream.write_all(&[1u8, channel.tty as u8]).await?;
+
+        let mut stream = channel.channel.into_stream();
+        let (bytes_in, bytes_out) = copy_bidirectional(&mut stream, upstream).await?;
+        session.bytes_in = bytes_in;
+        session.bytes_out = bytes_out;
+
+        Ok(())
+    }
+    .await;
+
+    if let Err(e) = &result {
+        warn!(
+            "ssh exec on {} from {} failed: {}",
+            target.machine, client_addr, e
+        );
+        let _ = handle
+            .extended_data(id, 1, CryptoVec::from(format!("{}\r\n", e)))
+            .await;
+    }
+
+    let exit_status = if result.is_ok() { 0 } else { 1 };
+    let _ = handle.exit_status_request(id, exit_status).await;
+    let _ = handle.eof(id).await;
+    let _ = handle.close(id).await;
+
+    session.ended_at = Some(now_millis());
+    session.error = result.err().map(|e| e.to_string());
+    if let Err(e) = audit::record_exec_session(&gateway.store, &claims.tenant, &session) {
+        error!("Failed to record exec session {}: {}", session.id, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ssh_target() {
+        assert_eq!(
+            SshTarget::parse("myapp").unwrap(),
+            SshTarget {
+                machine: "myapp".to_string(),
+                namespace: None,
+            }
+        );
+        assert_eq!(
+            SshTarget::parse("myapp.staging").unwrap(),
+            SshTarget {
+                machine: "myapp".to_string(),
+                namespace: Some("staging".to_string()),
+            }
+        );
+        assert!(SshTarget::parse("").is_err());
+        assert!(SshTarget::parse("myapp.").is_err());
+    }
+}
@@ -17,6 +17,12 @@ pub async fn main() {
         .resource_with_config::<resources::machine::Machine>(|cfg| {
             cfg.add_admission_rule(AdmissionRule::BeforeSet)
         })
+        .resource_with_config::<resources::machine_group::MachineGroup>(|cfg| {
+            cfg.add_admission_rule(AdmissionRule::BeforeSet)
+        })
+        .resource_with_config::<resources::cron::Cron>(|cfg| {
+            cfg.add_admission_rule(AdmissionRule::BeforeSet)
+        })
         .resource_with_config::<resources::service::Service>(|cfg| {
             cfg.add_admission_rule(AdmissionRule::BeforeSet)
                 .add_admission_rule(AdmissionRule::BeforeDelete)
@@ -25,8 +31,13 @@ pub async fn main() {
             cfg.add_admission_rule(AdmissionRule::BeforeSet)
                 .add_admission_rule(AdmissionRule::BeforeDelete)
         })
+        .resource_with_config::<resources::dns_record::DnsRecord>(|cfg| {
+            cfg.add_admission_rule(AdmissionRule::BeforeSet)
+                .add_admission_rule(AdmissionRule::BeforeDelete)
+        })
         .resource_with_config::<resources::volume::Volume>(|cfg| {
-            cfg.add_admission_rule(AdmissionRule::BeforeDelete)
+            cfg.add_admission_rule(AdmissionRule::BeforeSet)
+                .add_admission_rule(AdmissionRule::BeforeDelete)
                 .add_admission_rule(AdmissionRule::StatusCheck)
         })
         .build()
@@ -12,9 +12,14 @@ use crate::{
     resources::{
         ResourceBuildInfo,
         core::{
-            AllocatedBuilder, CLIENT_COMPAT_VERSION, DeleteNamespaceParams,
-            DeleteNamespaceResponse, ExecParams, ListNamespaces, LogStreamItem, LogStreamParams,
-            Me, QueryParams, QueryResponse, RegistryRobot,
+            AllocatedBuilder, AuditEvent, AuditLogParams, BandwidthUsage, CLIENT_COMPAT_VERSION,
+            ConsoleParams, CoreDump, DeleteNamespaceParams, DeleteNamespaceResponse,
+            DownloadCoreDumpParams, ExecParams, ExecSession, ImageGcReport, ImageInspectParams,
+            ImageInspection, ImageLoadParams, ImagePullProgress, InternalCaRoot,
+            InternalCertificateBundle, ListEventsParams, ListNamespaces, LogStreamItem,
+            LogStreamParams, MachineUsage, Me, QueryParams, QueryResponse, RegistryRobot,
+            ReleaseBuilderParams, ResourceEvent, SupportBundle, VolumeBackup, VolumeQuotaParams,
+            VolumeResize, VolumeResizeParams, VolumeRestore, VolumeRestoreParams, VolumeUsage,
         },
         gadget::{GadgetInitRunParams, GadgetInitRunResponse},
     },
@@ -90,6 +95,130 @@ fn core_api_spec(spec: Spec) -> Spec {
                     .query(type_of!(ExecParams))
                     .response(Type::void().wrap_stream())
             })
+            .get("console", path!("core", "console"), |endpoint| {
+                endpoint
+                    .header("x-ignition-namespace", header_value!(namespace: String))
+                    .upgrade(Upgrade::Ws)
+                    .query(type_of!(ConsoleParams))
+                    .response(Type::void().wrap_stream())
+            })
+            .get(
+                "exec_history",
+                path!("core", "exec", "history"),
+                |endpoint| {
+                    endpoint
+                        .header(
+                            "x-ignition-namespace",
+                            header_value!(namespace: Option<String>),
+                        )
+                        .response(type_of!(ExecSession).wrap_list())
+                },
+            )
+            .get(
+                "list_core_dumps",
+                vec![
+                    PathSegment::Literal("core".to_string()),
+                    PathSegment::Literal("machine".to_string()),
+                    PathSegment::Type {
+                        name: "name".to_string(),
+                        r#type: type_of!(String),
+                    },
+                    PathSegment::Literal("cores".to_string()),
+                ],
+                |endpoint| {
+                    endpoint
+                        .header("x-ignition-namespace", header_value!(namespace: String))
+                        .response(type_of!(CoreDump).wrap_list())
+                },
+            )
+            .get(
+                "machine_usage",
+                vec![
+                    PathSegment::Literal("core".to_string()),
+                    PathSegment::Literal("machine".to_string()),
+                    PathSegment::Type {
+                        name: "name".to_string(),
+                        r#type: type_of!(String),
+                    },
+                    PathSegment::Literal("usage".to_string()),
+                ],
+                |endpoint| {
+                    endpoint
+                        .header("x-ignition-namespace", header_value!(namespace: String))
+                        .response(type_of!(MachineUsage))
+                },
+            )
+            .get(
+                "image_pull_progress",
+                vec![
+                    PathSegment::Literal("core".to_string()),
+                    PathSegment::Literal("machine".to_string()),
+                    PathSegment::Type {
+                        name: "name".to_string(),
+                        r#type: type_of!(String),
+                    },
+                    PathSegment::Literal("pull".to_string()),
+                ],
+                |endpoint| {
+                    endpoint
+                        .header("x-ignition-namespace", header_value!(namespace: String))
+                        .response(type_of!(ImagePullProgress))
+                },
+            )
+            .get(
+                "download_core_dump",
+                path!("core", "cores", "download"),
+                |endpoint| {
+                    endpoint
+                        .upgrade(Upgrade::Ws)
+                        .query(type_of!(DownloadCoreDumpParams))
+                        .response(Type::void().wrap_stream())
+                },
+            )
+    })
+    .service("image", |service| {
+        service
+            .get("load", path!("core", "images", "load"), |endpoint| {
+                endpoint
+                    .upgrade(Upgrade::Ws)
+                    .query(type_of!(ImageLoadParams))
+                    .response(Type::void().wrap_stream())
+            })
+            .put("inspect", path!("core", "images", "inspect"), |endpoint| {
+                endpoint
+                    .body(type_of!(ImageInspectParams))
+                    .response(type_of!(ImageInspection))
+            })
+    })
+    .service("volume", |service| {
+        service
+            .put("resize", path!("core", "volumes", "resize"), |endpoint| {
+                endpoint
+                    .body(type_of!(VolumeResizeParams))
+                    .response(type_of!(VolumeResize))
+            })
+            .get(
+                "list_backups",
+                vec![
+                    PathSegment::Literal("core".to_string()),
+                    PathSegment::Literal("volume".to_string()),
+                    PathSegment::Type {
+                        name: "name".to_string(),
+                        r#type: type_of!(String),
+                    },
+                    PathSegment::Literal("backups".to_string()),
+                ],
+                |endpoint| {
+                    endpoint
+                        .header("x-ignition-namespace", header_value!(namespace: String))
+                        .response(type_of!(VolumeBackup).wrap_list())
+                },
+            )
+            .put("restore", path!("core", "volumes", "restore"), |endpoint| {
+                endpoint
+                    .body(type_of!(VolumeRestoreParams))
+                    .response(type_of!(VolumeRestore))
+            })
     })
     .service("runtime", |service| {
         service.put("query", path!("core", "query"), |endpoint| {
@@ -99,11 +228,93 @@ fn core_api_spec(spec: Spec) -> Spec {
         })
     })
     .service("build", |service| {
-        service.put(
-            "alloc_builder",
-            path!("core", "build", "alloc"),
-            |endpoint| endpoint.response(type_of!(AllocatedBuilder)),
-        )
+        service
+            .put(
+                "alloc_builder",
+                path!("core", "build", "alloc"),
+                |endpoint| endpoint.response(type_of!(AllocatedBuilder)),
+            )
+            .put(
+                "release_builder",
+                path!("core", "build", "release"),
+                |endpoint| endpoint.body(type_of!(ReleaseBuilderParams)),
+            )
+    })
+    .service("admin", |service| {
+        service
+            .get(
+                "support_bundle",
+                path!("core", "admin", "support-bundle"),
+                |endpoint| endpoint.response(type_of!(SupportBundle)),
+            )
+            .get(
+                "image_gc_dry_run",
+                path!("core", "admin", "image-gc"),
+                |endpoint| endpoint.response(type_of!(ImageGcReport)),
+            )
+            .put(
+                "set_volume_quota",
+                path!("core", "admin", "volume-quota"),
+                |endpoint| endpoint.body(type_of!(VolumeQuotaParams)),
+            )
+    })
+    .service("audit", |service| {
+        service.put("audit_log", path!("core", "audit"), |endpoint| {
+            endpoint
+                .header(
+                    "x-ignition-namespace",
+                    header_value!(namespace: Option<String>),
+                )
+                .body(type_of!(AuditLogParams))
+                .response(type_of!(AuditEvent).wrap_list())
+        })
+    })
+    .service("events", |service| {
+        service.put("list_events", path!("core", "events"), |endpoint| {
+            endpoint
+                .header("x-ignition-namespace", header_value!(namespace: String))
+                .body(type_of!(ListEventsParams))
+                .response(type_of!(ResourceEvent).wrap_list())
+        })
+    })
+    .service("usage", |service| {
+        service
+            .get(
+                "bandwidth",
+                path!("core", "usage", "bandwidth"),
+                |endpoint| endpoint.response(type_of!(BandwidthUsage)),
+            )
+            .get("volumes", path!("core", "usage", "volumes"), |endpoint| {
+                endpoint.response(type_of!(VolumeUsage))
+            })
+    })
+    .service("certificate", |service| {
+        service
+            .get("internal_ca", path!("core", "ca"), |endpoint| {
+                endpoint.response(type_of!(InternalCaRoot))
+            })
+            .put(
+                "rotate_internal_ca",
+                path!("core", "ca", "rotate"),
+                |endpoint| endpoint.response(type_of!(InternalCaRoot)),
+            )
+            .get(
+                "internal_bundle",
+                vec![
+                    PathSegment::Literal("core".to_string()),
+                    PathSegment::Literal("certificate".to_string()),
+                    PathSegment::Type {
+                        name: "name".to_string(),
+                        r#type: type_of!(String),
+                    },
+                    PathSegment::Literal("bundle".to_string()),
+                ],
+                |endpoint| {
+                    endpoint
+                        .header("x-ignition-namespace", header_value!(namespace: String))
+                        .response(type_of!(InternalCertificateBundle))
+                },
+            )
     })
     .service("gadget", |service| {
         service.put("init", path!("gadget", "run", "init"), |endpoint| {
@@ -20,6 +20,19 @@ pub async fn build_resource_index(resources: &[ResourceBuildInfo]) -> Result<()>
     }
     src.push_str("}\n\n");
 
+    src.push_str("impl ResourceKind {\n");
+    src.push_str("    pub fn tag(&self) -> &'static str {\n");
+    src.push_str("        match self {\n");
+    for resource in resources {
+        src.push_str(&format!(
+            "            ResourceKind::{} => \"{}\",\n",
+            resource.name, resource.tag
+        ));
+    }
+    src.push_str("        }\n");
+    src.push_str("    }\n");
+    src.push_str("}\n\n");
+
     src.push_str(
         "#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]\n",
     );
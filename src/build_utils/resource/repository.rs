@@ -16,7 +16,7 @@ pub async fn build_repository(resources: &[ResourceBuildInfo]) -> Result<()> {
     src.push_str("use crate::{\n");
     src.push_str("    controller::{context::ControllerEvent, scheduler::Scheduler},\n");
     src.push_str("    machinery::store::Store,\n");
-    src.push_str("    resources::{Convert, FromResource, ProvideKey, ProvideMetadata, metadata::{Metadata, Namespace}, AdmissionRule},\n");
+    src.push_str("    resources::{Convert, FromResource, ProvideKey, ProvideMetadata, metadata::{Metadata, Namespace}, AdmissionRule, condition::ObserveConditions},\n");
 
     // Add resource imports
     for resource in resources {
@@ -217,13 +217,22 @@ fn generate_resource_repository(src: &mut String, resource: &ResourceBuildInfo)
     src.push_str("    }\n\n");
 
     src.push_str(&format!(
-        "    pub async fn set_status(&self, metadata: Metadata, status: {}) -> Result<()> {{\n",
+        "    pub async fn set_status(&self, metadata: Metadata, mut status: {}) -> Result<()> {{\n",
         status_name
     ));
     src.push_str(&format!(
         "        let key = {}::key(self.tenant.clone(), metadata.clone())?;\n",
         status_name
     ));
+    src.push_str("        \n");
+    src.push_str("        // Conditions keep their transition time until their status changes\n");
+    src.push_str(&format!(
+        "        let previous_conditions = self.store.get(key.clone())?.map(|previous: {}| previous.conditions).unwrap_or_default();\n",
+        status_name
+    ));
+    src.push_str(
+        "        status.refresh_conditions(&previous_conditions, &chrono::Utc::now().to_rfc3339());\n",
+    );
     src.push_str("        self.store.put(key, status)?;\n");
     src.push_str("        \n");
     src.push_str("        // Notify scheduler of status change\n");
@@ -21,7 +21,7 @@ pub async fn build_services(resources: &[ResourceBuildInfo]) -> Result<()> {
     src.push_str("};\n\n");
     src.push_str("use crate::{\n");
     src.push_str("    api::{\n");
-    src.push_str("        ApiState,\n");
+    src.push_str("        ApiState, audit,\n");
     src.push_str("        context::ServiceRequestContext,\n");
     src.push_str("        resource_service::{ResourceService, ResourceServiceRouter},\n");
     src.push_str("    },\n");
@@ -210,15 +210,29 @@ fn generate_resource_service(src: &mut String, resource: &ResourceBuildInfo) {
             .contains(&AdmissionRule::BeforeSet)
         {
             src.push_str("            use crate::controller::AdmissionCheckBeforeSet;\n");
-            src.push_str("            let result = resource.before_set(before.as_ref(), ctx.tenant, state.repository.clone(), state.scheduler.agent.clone(), resource.metadata()).await;\n");
+            src.push_str("            let result = resource.before_set(before.as_ref(), ctx.tenant.clone(), state.repository.clone(), state.scheduler.agent.clone(), resource.metadata()).await;\n");
             src.push_str("            if let Err(e) = result {\n");
             src.push_str("                return (StatusCode::BAD_REQUEST, e.to_string()).into_response();\n");
             src.push_str("            };\n\n");
         }
 
+        if namespaced {
+            src.push_str("            let audit_namespace = Namespace::from_value_or_default(resource.metadata().namespace).as_value();\n");
+        } else {
+            src.push_str("            let audit_namespace = None;\n");
+        }
+        src.push_str("            let audit_name = resource.metadata().name;\n");
+        src.push_str("            let audit_before = before.as_ref().and_then(|r| audit::snapshot(&r.latest()));\n");
+        src.push_str("            let audit_after = audit::snapshot(&resource.latest());\n\n");
         src.push_str("            let result = repo.set(resource).await;\n\n");
         src.push_str("            match result {\n");
-        src.push_str("                Ok(()) => StatusCode::OK.into_response(),\n");
+        src.push_str("                Ok(()) => {\n");
+        src.push_str(&format!(
+            "                    audit::record_mutation(&state.store, &ctx, \"{}\", audit_namespace, &audit_name, audit_before, audit_after);\n",
+            resource.tag
+        ));
+        src.push_str("                    StatusCode::OK.into_response()\n");
+        src.push_str("                }\n");
         src.push_str("                Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),\n");
         src.push_str("            }\n");
         src.push_str("        }\n\n");
@@ -259,7 +273,7 @@ fn generate_resource_service(src: &mut String, resource: &ResourceBuildInfo) {
             .contains(&AdmissionRule::BeforeDelete)
         {
             src.push_str(&format!(
-            "            let result = resource.before_delete(ctx.tenant, state.repository.clone(), state.scheduler.agent.clone(), metadata).await;\n",
+            "            let result = resource.before_delete(ctx.tenant.clone(), state.repository.clone(), state.scheduler.agent.clone(), metadata).await;\n",
         ));
             src.push_str("            if let Err(e) = result {\n");
             src.push_str("                return (StatusCode::BAD_REQUEST, e.to_string()).into_response();\n");
@@ -268,13 +282,24 @@ fn generate_resource_service(src: &mut String, resource: &ResourceBuildInfo) {
 
         if namespaced {
             src.push_str(&format!(
-                "            let result = repo.delete(ctx.namespace, name).await;\n",
+                "            let result = repo.delete(ctx.namespace.clone(), name).await;\n",
             ));
         } else {
             src.push_str("            let result = repo.delete(name).await;\n");
         }
         src.push_str("\n            match result {\n");
-        src.push_str("                Ok(()) => StatusCode::OK.into_response(),\n");
+        src.push_str("                Ok(()) => {\n");
+        if namespaced {
+            src.push_str("                    let audit_namespace = Namespace::from_value_or_default(resource.metadata().namespace).as_value();\n");
+        } else {
+            src.push_str("                    let audit_namespace = None;\n");
+        }
+        src.push_str(&format!(
+            "                    audit::record_mutation(&state.store, &ctx, \"{}\", audit_namespace, &resource.metadata().name, audit::snapshot(&resource.latest()), None);\n",
+            resource.tag
+        ));
+        src.push_str("                    StatusCode::OK.into_response()\n");
+        src.push_str("                }\n");
         src.push_str("                Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),\n");
         src.push_str("            }\n");
         src.push_str("        }\n\n");
@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+
+use crate::{config::BuildConfig, ui::message::message_warn};
+
+/// Never forwarded from the local environment, even when allowlisted.
+const DEFAULT_ENV_DENYLIST: &[&str] = &[
+    "*TOKEN*",
+    "*SECRET*",
+    "*PASSWORD*",
+    "*PASSWD*",
+    "*CREDENTIAL*",
+    "*_KEY",
+    "*_KEY_*",
+    "AWS_*",
+    "GITHUB_*",
+    "LTTLE_*",
+    "SSH_*",
+];
+
+/// Resolves the environment a build sees: allowlisted local variables, minus denylisted ones,
+/// overridden by the `build-env` declared in the manifest.
+pub fn resolve_build_env(
+    config: Option<&BuildConfig>,
+    manifest_build_env: BTreeMap<String, String>,
+) -> BTreeMap<String, String> {
+    let default_config = BuildConfig::default();
+    let config = config.unwrap_or(&default_config);
+
+    let mut build_env = BTreeMap::new();
+
+    if !config.env_allowlist.is_empty() {
+        for (key, value) in std::env::vars() {
+            if !config
+                .env_allowlist
+                .iter()
+                .any(|p| matches_pattern(p, &key))
+            {
+                continue;
+            }
+
+            let denied = DEFAULT_ENV_DENYLIST
+                .iter()
+                .any(|p| matches_pattern(p, &key))
+                || config.env_denylist.iter().any(|p| matches_pattern(p, &key));
+            if denied {
+                continue;
+            }
+
+            build_env.insert(key, value);
+        }
+    }
+
+    // declared explicitly, so the denylist doesn't apply
+    build_env.extend(manifest_build_env);
+
+    build_env.retain(|key, value| {
+        if is_valid_env_name(key) && !value.contains('\0') {
+            return true;
+        }
+
+        message_warn(format!("Ignoring invalid build env variable: {}", key));
+        false
+    });
+
+    build_env
+}
+
+fn is_valid_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    let Some(first) = chars.next() else {
+        return false;
+    };
+
+    (first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Case-insensitive glob match where `*` matches any run of characters.
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.to_ascii_uppercase();
+    let name = name.to_ascii_uppercase();
+
+    let parts = pattern.split('*').collect::<Vec<_>>();
+    if parts.len() == 1 {
+        return pattern == name;
+    }
+
+    let mut rest = name.as_str();
+    for (index, part) in parts.iter().enumerate() {
+        if part.is_empty() {
+            continue;
+        }
+
+        if index == 0 {
+            let Some(stripped) = rest.strip_prefix(part) else {
+                return false;
+            };
+            rest = stripped;
+        } else if index == parts.len() - 1 {
+            return rest.ends_with(part);
+        } else {
+            let Some(position) = rest.find(part) else {
+                return false;
+            };
+            rest = &rest[position + part.len()..];
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("NODE_ENV", "NODE_ENV"));
+        assert!(matches_pattern("node_env", "NODE_ENV"));
+        assert!(!matches_pattern("NODE_ENV", "NODE_ENVS"));
+
+        assert!(matches_pattern("NPM_*", "NPM_CONFIG_REGISTRY"));
+        assert!(matches_pattern("NPM_*", "NPM_"));
+        assert!(!matches_pattern("NPM_*", "PNPM_HOME"));
+
+        assert!(matches_pattern("*_KEY", "STRIPE_API_KEY"));
+        assert!(!matches_pattern("*_KEY", "KEYBOARD"));
+
+        assert!(matches_pattern("*TOKEN*", "GH_TOKEN"));
+        assert!(matches_pattern("*TOKEN*", "token_file"));
+        assert!(!matches_pattern("*TOKEN*", "TOKE"));
+
+        assert!(matches_pattern("A*B*C", "AXXBYYC"));
+        assert!(matches_pattern("A*B*C", "ABC"));
+        assert!(!matches_pattern("A*B*C", "ACB"));
+        assert!(!matches_pattern("A*A", "A"));
+
+        assert!(matches_pattern("*", "ANYTHING"));
+        assert!(matches_pattern("*", ""));
+    }
+
+    #[test]
+    fn test_is_valid_env_name() {
+        assert!(is_valid_env_name("NODE_ENV"));
+        assert!(is_valid_env_name("_private"));
+        assert!(!is_valid_env_name(""));
+        assert!(!is_valid_env_name("1PASSWORD"));
+        assert!(!is_valid_env_name("MY-VAR"));
+    }
+
+    #[test]
+    fn test_resolve_build_env_without_allowlist() {
+        let manifest_build_env = BTreeMap::from([
+            ("GITHUB_SHA".to_string(), "4f1c2e9".to_string()),
+            ("NOT-VALID".to_string(), "1".to_string()),
+        ]);
+
+        // nothing local without an allowlist, the manifest is taken as declared
+        let build_env = resolve_build_env(None, manifest_build_env);
+        assert_eq!(
+            build_env,
+            BTreeMap::from([("GITHUB_SHA".to_string(), "4f1c2e9".to_string())])
+        );
+    }
+}
@@ -1,4 +1,6 @@
 pub mod docker_auth;
+pub mod env;
+pub mod submodules;
 
 use std::{
     collections::BTreeMap,
@@ -10,9 +12,12 @@ use std::{
 use anyhow::{Result, bail};
 use ignition::{
     api_client::ApiClient,
-    resources::machine::{
-        MachineBuild, MachineBuildOptions, MachineBuildPlanPhase, MachineBuildPlanStartPhase,
-        MachineDockerOptions,
+    resources::{
+        core::ReleaseBuilderParams,
+        machine::{
+            MachineBuild, MachineBuildOptions, MachineBuildPlanPhase, MachineBuildPlanStartPhase,
+            MachineDockerOptions,
+        },
     },
 };
 use nixpacks::nixpacks::{
@@ -29,7 +34,7 @@ use tokio::{fs::create_dir_all, process::Command};
 use crate::{
     build::docker_auth::DockerAuthConfig,
     ui::{
-        message::message_detail,
+        message::{message_detail, message_warn},
         summary::{Summary, SummaryCellStyle, SummaryRow},
     },
 };
@@ -69,8 +74,10 @@ pub async fn build_and_push_image(
     dir: impl AsRef<Path>,
     tenant: &str,
     build: MachineBuild,
+    build_env: BTreeMap<String, String>,
     debug: bool,
     disable_build_cache: bool,
+    skip_submodules: bool,
     force_build_target: Option<BuildTarget>,
 ) -> Result<String> {
     let build_target = if let Some(force_build_target) = force_build_target {
@@ -91,9 +98,16 @@ pub async fn build_and_push_image(
 
     match build_target {
         BuildTarget::Local => {
-            let image =
-                local_build_image(dir, tenant, build, auth.clone(), debug, disable_build_cache)
-                    .await?;
+            let image = local_build_image(
+                dir,
+                tenant,
+                build,
+                build_env,
+                auth.clone(),
+                debug,
+                disable_build_cache,
+            )
+            .await?;
             message_detail(format!("Built image {}", image));
             message_detail(format!("Pushing image {}", image));
             push_image(image.clone(), auth).await?;
@@ -105,9 +119,11 @@ pub async fn build_and_push_image(
                 dir,
                 tenant,
                 build,
+                build_env,
                 auth,
                 debug,
                 disable_build_cache,
+                skip_submodules,
             )
             .await?;
             Ok(image)
@@ -130,13 +146,17 @@ async fn remote_build_and_push_image(
     dir: impl AsRef<Path>,
     tenant: &str,
     build: MachineBuild,
+    build_env: BTreeMap<String, String>,
     auth: DockerAuthConfig,
     debug: bool,
     disable_build_cache: bool,
+    skip_submodules: bool,
 ) -> Result<String> {
     message_detail("Building image remotely");
 
-    let builder = api_client.core().alloc_builder().await?;
+    if !skip_submodules {
+        submodules::resolve_submodules(dir.as_ref(), debug).await?;
+    }
 
     let remote_build_context = match build {
         MachineBuild::Nixpacks(options) => {
@@ -144,6 +164,7 @@ async fn remote_build_and_push_image(
                 dir,
                 tenant,
                 options,
+                &build_env,
                 auth.clone(),
                 debug,
                 disable_build_cache,
@@ -151,7 +172,8 @@ async fn remote_build_and_push_image(
             .await?
         }
         MachineBuild::Docker(options) => {
-            get_remote_build_context_docker(dir, tenant, auth.clone(), options, debug).await?
+            get_remote_build_context_docker(dir, tenant, auth.clone(), options, &build_env, debug)
+                .await?
         }
         MachineBuild::NixpacksAuto => {
             get_remote_build_context_nixpacks(
@@ -170,6 +192,7 @@ async fn remote_build_and_push_image(
                     phases: None,
                     start_phase: None,
                 },
+                &build_env,
                 auth.clone(),
                 debug,
                 disable_build_cache,
@@ -183,6 +206,10 @@ async fn remote_build_and_push_image(
     let certs_dir = tempfile::tempdir()?;
     let certs_dir_path = certs_dir.path().to_path_buf();
 
+    // allocated as late as possible, the allocation is released once buildctl exits
+    let builder = api_client.core().alloc_builder().await?;
+    let allocation_id = builder.allocation_id.clone();
+
     let (ca_cert, client_cert, client_key) = (
         certs_dir_path.join("ca.cert"),
         certs_dir_path.join("client.cert"),
@@ -251,7 +278,14 @@ async fn remote_build_and_push_image(
     if debug {
         message_detail("Buildkit args: ");
         for arg in buildkit_args.iter() {
-            println!("{}", arg);
+            // build args may carry secrets, only print their names
+            match arg
+                .strip_prefix("build-arg:")
+                .and_then(|arg| arg.split_once('='))
+            {
+                Some((key, _)) => println!("build-arg:{}=<redacted>", key),
+                None => println!("{}", arg),
+            }
         }
     }
 
@@ -268,9 +302,18 @@ async fn remote_build_and_push_image(
         cmd.stderr(std::process::Stdio::null());
     }
 
-    let status = cmd.status().await?;
+    let status = cmd.status().await;
 
-    if !status.success() {
+    if let Err(e) = api_client
+        .core()
+        .release_builder(ReleaseBuilderParams { allocation_id })
+        .await
+    {
+        // the daemon reclaims it once it expires
+        message_warn(format!("Failed to release builder: {}", e));
+    }
+
+    if !status?.success() {
         bail!(
             "Failed to build image. Run the same command with --debug-build to see the full output"
         );
@@ -299,6 +342,7 @@ async fn get_remote_build_context_nixpacks(
     dir: impl AsRef<Path>,
     tenant: &str,
     options: MachineBuildOptions,
+    build_env: &BTreeMap<String, String>,
     auth: DockerAuthConfig,
     debug: bool,
     disable_build_cache: bool,
@@ -307,7 +351,8 @@ async fn get_remote_build_context_nixpacks(
 
     let out_dir_path = out_dir.path().to_path_buf();
 
-    let (plan, _) = get_build_plan(dir.as_ref()).await?;
+    let plan_env = merge_build_env(build_env, options.envs.clone());
+    let (plan, _) = get_build_plan(dir.as_ref(), &plan_env).await?;
 
     let build_args = if let Some(args) = serde_json::to_value(&plan)?.get("variables") {
         serde_json::from_value(args.clone())?
@@ -319,6 +364,7 @@ async fn get_remote_build_context_nixpacks(
         dir,
         tenant,
         options,
+        build_env,
         auth,
         debug,
         disable_build_cache,
@@ -342,6 +388,7 @@ async fn get_remote_build_context_docker(
     tenant: &str,
     auth: DockerAuthConfig,
     options: MachineDockerOptions,
+    build_env: &BTreeMap<String, String>,
     debug: bool,
 ) -> Result<RemoteBuildContext> {
     let Some(registry) = auth.get_registry() else {
@@ -379,7 +426,7 @@ async fn get_remote_build_context_docker(
         bail!("Dockerfile not found");
     }
 
-    let args = options.args.unwrap_or_default();
+    let args = merge_build_env(build_env, options.args);
     Ok(RemoteBuildContext {
         temp_dir: None,
         image,
@@ -393,16 +440,36 @@ async fn local_build_image(
     dir: impl AsRef<Path>,
     tenant: &str,
     build: MachineBuild,
+    build_env: BTreeMap<String, String>,
     auth: DockerAuthConfig,
     debug: bool,
     disable_build_cache: bool,
 ) -> Result<String> {
     let image = match build {
         MachineBuild::Nixpacks(options) => {
-            build_image_nixpacks(dir, tenant, options, auth, debug, disable_build_cache, None).await
+            build_image_nixpacks(
+                dir,
+                tenant,
+                options,
+                &build_env,
+                auth,
+                debug,
+                disable_build_cache,
+                None,
+            )
+            .await
         }
         MachineBuild::Docker(options) => {
-            build_image_docker(dir, tenant, auth, options, debug, disable_build_cache).await
+            build_image_docker(
+                dir,
+                tenant,
+                auth,
+                options,
+                &build_env,
+                debug,
+                disable_build_cache,
+            )
+            .await
         }
         MachineBuild::NixpacksAuto => {
             build_image_nixpacks(
@@ -421,6 +488,7 @@ async fn local_build_image(
                     phases: None,
                     start_phase: None,
                 },
+                &build_env,
                 auth,
                 debug,
                 disable_build_cache,
@@ -437,6 +505,7 @@ async fn build_image_nixpacks(
     dir: impl AsRef<Path>,
     tenant: &str,
     options: MachineBuildOptions,
+    build_env: &BTreeMap<String, String>,
     auth: DockerAuthConfig,
     debug: bool,
     disable_build_cache: bool,
@@ -468,9 +537,7 @@ async fn build_image_nixpacks(
         message_detail(format!("Generated image reference: {}", image));
     }
 
-    let envs = options
-        .envs
-        .unwrap_or_default()
+    let envs = merge_build_env(build_env, options.envs)
         .into_iter()
         .map(|(k, v)| format!("{}={}", k, v))
         .collect::<Vec<String>>();
@@ -597,9 +664,12 @@ async fn build_image_nixpacks(
     Ok(image)
 }
 
-pub async fn get_build_plan(path: impl AsRef<Path>) -> Result<(BuildPlan, Vec<String>)> {
-    let envs = std::env::vars()
-        .into_iter()
+pub async fn get_build_plan(
+    path: impl AsRef<Path>,
+    build_env: &BTreeMap<String, String>,
+) -> Result<(BuildPlan, Vec<String>)> {
+    let envs = build_env
+        .iter()
         .map(|(k, v)| format!("{}={}", k, v))
         .collect::<Vec<String>>();
 
@@ -639,6 +709,7 @@ async fn build_image_docker(
     tenant: &str,
     auth: DockerAuthConfig,
     options: MachineDockerOptions,
+    build_env: &BTreeMap<String, String>,
     debug: bool,
     disable_build_cache: bool,
 ) -> Result<String> {
@@ -701,11 +772,9 @@ async fn build_image_docker(
         cmd.stderr(std::process::Stdio::null());
     }
 
-    if let Some(args) = options.args {
-        for (key, value) in args {
-            cmd.arg("--build-arg");
-            cmd.arg(format!("{}={}", key, value));
-        }
+    for (key, value) in merge_build_env(build_env, options.args) {
+        cmd.arg("--build-arg");
+        cmd.arg(format!("{}={}", key, value));
     }
 
     let status = cmd.status().await?;
@@ -719,6 +788,16 @@ async fn build_image_docker(
     Ok(image)
 }
 
+/// Build-specific values (nixpacks `envs`, docker `args`) take precedence over `build-env`.
+fn merge_build_env(
+    build_env: &BTreeMap<String, String>,
+    overrides: Option<BTreeMap<String, String>>,
+) -> BTreeMap<String, String> {
+    let mut merged = build_env.clone();
+    merged.extend(overrides.unwrap_or_default());
+    merged
+}
+
 pub async fn push_image(image: impl AsRef<str>, auth: DockerAuthConfig) -> Result<()> {
     let output = Command::new("docker")
         .env("DOCKER_AUTH_CONFIG", auth.to_json()?)
@@ -0,0 +1,99 @@
+//! Git submodules for remote builds.
+//!
+//! Remote builders never receive git credentials. Instead, submodules that are not checked out
+//! yet are fetched on this machine with the user's own git setup (ssh agent, credential helpers),
+//! and their contents are then uploaded with the rest of the build context like any other file.
+//! Nothing but the checked out files leaves the machine.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, bail};
+use tokio::process::Command;
+
+use crate::ui::message::message_detail;
+
+pub async fn resolve_submodules(dir: impl AsRef<Path>, debug: bool) -> Result<()> {
+    let dir = dir.as_ref();
+
+    // no git (or not a repository) means there are no submodules to resolve
+    let Ok(output) = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["rev-parse", "--show-toplevel"])
+        .output()
+        .await
+    else {
+        return Ok(());
+    };
+
+    if !output.status.success() {
+        return Ok(());
+    }
+
+    let root = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+    if !root.join(".gitmodules").exists() {
+        return Ok(());
+    }
+
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(&root)
+        .args(["submodule", "status", "--recursive"])
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        bail!(
+            "Failed to read git submodules: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    let context_dir = dir.canonicalize()?;
+    let root = root.canonicalize()?;
+
+    // uninitialized submodules are prefixed with '-', only the ones inside the context matter
+    let missing = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.strip_prefix('-'))
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(|path| path.to_string())
+        .filter(|path| {
+            let path = root.join(path);
+            path.starts_with(&context_dir) || context_dir.starts_with(&path)
+        })
+        .collect::<Vec<_>>();
+
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    message_detail(format!(
+        "Fetching {} git submodule(s) locally for the build context",
+        missing.len()
+    ));
+
+    let mut cmd = Command::new("git");
+    cmd.arg("-C")
+        .arg(&root)
+        .args(["submodule", "update", "--init", "--recursive", "--"])
+        .args(&missing);
+
+    if debug {
+        cmd.stdout(std::process::Stdio::inherit());
+        cmd.stderr(std::process::Stdio::inherit());
+    } else {
+        cmd.stdout(std::process::Stdio::null());
+        cmd.stderr(std::process::Stdio::null());
+    }
+
+    let status = cmd.status().await?;
+    if !status.success() {
+        bail!(
+            "Failed to fetch git submodules ({}). Make sure your git credentials can access them, or pass --no-submodules to skip this step",
+            missing.join(", ")
+        );
+    }
+
+    Ok(())
+}
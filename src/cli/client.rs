@@ -12,20 +12,30 @@ pub fn get_api_client(config: ApiClientConfig) -> ApiClient {
 #[async_trait]
 pub trait MachineClientExt {
     async fn add_tag(&self, namespace: Namespace, name: String, tag: String) -> Result<()>;
+    async fn add_tags(&self, namespace: Namespace, name: String, tags: Vec<String>) -> Result<()>;
 }
 
 #[async_trait]
 impl MachineClientExt for MachineApiClient {
     async fn add_tag(&self, namespace: Namespace, name: String, tag: String) -> Result<()> {
+        self.add_tags(namespace, name, vec![tag]).await
+    }
+
+    async fn add_tags(
+        &self,
+        namespace: Namespace,
+        name: String,
+        new_tags: Vec<String>,
+    ) -> Result<()> {
         let (mut machine, _) = self.get(namespace, name).await?;
 
         let mut tags = machine
             .tags
             .unwrap_or_default()
             .into_iter()
-            .filter(|tag| tag.to_string() != tag.to_string())
+            .filter(|tag| !new_tags.contains(tag))
             .collect::<Vec<String>>();
-        tags.push(tag.to_string());
+        tags.extend(new_tags);
         machine.tags = Some(tags);
 
         let machine: Machine = machine.into();
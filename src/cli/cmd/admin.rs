@@ -0,0 +1,183 @@
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use clap::Args;
+use flate2::{Compression, write::GzEncoder};
+use ignition::{
+    machinery::store::now_millis,
+    resources::core::{SupportBundle, VolumeQuotaParams},
+    utils::size::format_human_readable_size,
+};
+use meta::table;
+use serde::Serialize;
+use serde_json::json;
+use tar::{Builder, Header};
+
+use crate::{
+    client::get_api_client,
+    cmd::event::format_age,
+    config::Config,
+    ui::message::{message_info, message_warn},
+};
+
+#[table]
+pub struct ImageGcTable {
+    #[field(name = "reference")]
+    reference: String,
+
+    #[field(name = "digest")]
+    digest: String,
+
+    #[field(name = "pulled")]
+    pulled: String,
+
+    #[field(name = "size")]
+    size: String,
+
+    #[field(name = "reason", cell_style = important)]
+    reason: String,
+}
+
+#[derive(Args)]
+pub struct SupportBundleArgs {
+    /// Where to write the archive, defaults to ./lttle-support-<timestamp>.tar.gz
+    #[arg(long = "output", short = 'o')]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct VolumeQuotaArgs {
+    /// Tenant to set the quota of
+    tenant: String,
+
+    /// Volume space the tenant can provision (eg. 100GB), or unlimited
+    #[arg(conflicts_with = "reset", required_unless_present = "reset")]
+    quota: Option<String>,
+
+    /// Put the tenant back on the default quota of the daemon
+    #[arg(long = "reset")]
+    reset: bool,
+}
+
+pub async fn run_admin_support_bundle(config: &Config, args: SupportBundleArgs) -> Result<()> {
+    let api_client = get_api_client(config.try_into()?);
+    let bundle = api_client.core().support_bundle().await?;
+
+    let output = args
+        .output
+        .unwrap_or_else(|| PathBuf::from(format!("lttle-support-{}.tar.gz", bundle.generated_at)));
+
+    write_bundle(&output, &bundle)?;
+
+    message_info(format!("Support bundle written to {}", output.display()));
+
+    Ok(())
+}
+
+pub async fn run_admin_image_gc(config: &Config) -> Result<()> {
+    let api_client = get_api_client(config.try_into()?);
+    let report = api_client.core().image_gc_dry_run().await?;
+
+    if !report.enabled {
+        message_warn("Image GC is not enabled on this daemon, see the [image-gc] config section");
+    }
+
+    message_info(format!(
+        "Images use {}, {} would be removed",
+        format_human_readable_size(report.disk_usage_bytes),
+        report.removed.len()
+    ));
+
+    if report.removed.is_empty() {
+        return Ok(());
+    }
+
+    let mut table = ImageGcTable::new();
+    for removal in report.removed {
+        table.add_row(ImageGcTableRow {
+            reference: removal.reference,
+            digest: removal.digest,
+            pulled: format_age(removal.pulled_at, report.ran_at),
+            size: format_human_readable_size(removal.size_bytes),
+            reason: removal.reason,
+        });
+    }
+    table.print();
+
+    Ok(())
+}
+
+pub async fn run_admin_volume_quota(config: &Config, args: VolumeQuotaArgs) -> Result<()> {
+    let api_client = get_api_client(config.try_into()?);
+    api_client
+        .core()
+        .set_volume_quota(VolumeQuotaParams {
+            tenant: args.tenant.clone(),
+            quota: args.quota.clone(),
+        })
+        .await?;
+
+    match args.quota {
+        Some(quota) => message_info(format!(
+            "Volume quota of '{}' set to {}.",
+            args.tenant, quota
+        )),
+        None => message_info(format!(
+            "Volume quota of '{}' reset to the default.",
+            args.tenant
+        )),
+    }
+
+    Ok(())
+}
+
+fn write_bundle(output: &Path, bundle: &SupportBundle) -> Result<()> {
+    let file = File::create(output)?;
+    let mut archive = Builder::new(GzEncoder::new(file, Compression::default()));
+
+    let versions = json!({
+        "server": bundle.version,
+        "server-compat": bundle.compat_version,
+        "cli": env!("CARGO_PKG_VERSION"),
+    });
+
+    append_json(&mut archive, "versions.json", &versions)?;
+    append_json(&mut archive, "config.json", &bundle.config)?;
+    append_json(&mut archive, "scheduler.json", &bundle.scheduler)?;
+    append_json(&mut archive, "events.json", &bundle.recent_events)?;
+    append_json(&mut archive, "machines.json", &bundle.machines)?;
+    append_json(&mut archive, "proxy-bindings.json", &bundle.proxy_bindings)?;
+    append_json(&mut archive, "gc-report.json", &bundle.last_gc_report)?;
+    append_file(
+        &mut archive,
+        "proxy-metrics.txt",
+        bundle.proxy_metrics.as_bytes(),
+    )?;
+
+    archive.into_inner()?.finish()?;
+
+    Ok(())
+}
+
+fn append_json<T: Serialize>(
+    archive: &mut Builder<GzEncoder<File>>,
+    name: &str,
+    value: &T,
+) -> Result<()> {
+    append_file(archive, name, &serde_json::to_vec_pretty(value)?)
+}
+
+fn append_file(archive: &mut Builder<GzEncoder<File>>, name: &str, data: &[u8]) -> Result<()> {
+    let mut header = Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(now_millis() / 1000);
+    header.set_cksum();
+
+    archive.append_data(&mut header, name, data)?;
+
+    Ok(())
+}
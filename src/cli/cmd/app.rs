@@ -15,7 +15,10 @@ use ordinal::Ordinal;
 
 use crate::{
     client::get_api_client,
-    cmd::{DeleteNamespacedArgs, GetNamespacedArgs, ListNamespacedArgs},
+    cmd::{
+        DeleteNamespacedArgs, GetNamespacedArgs, ListNamespacedArgs, event::print_resource_events,
+        machine::format_expiry,
+    },
     config::Config,
     ui::message::{message_info, message_warn},
 };
@@ -55,6 +58,9 @@ pub struct AppSummary {
     #[field(name = "tags")]
     tags: Vec<String>,
 
+    #[field(name = "conditions")]
+    conditions: Vec<String>,
+
     #[field(name = "mode", cell_style = important)]
     mode: String,
 
@@ -88,6 +94,9 @@ pub struct AppSummary {
     #[field(name = "dependencies")]
     depends_on: Vec<String>,
 
+    #[field(name = "expires")]
+    expires: Option<String>,
+
     #[field(name = "services", clip_value = false)]
     services: Vec<String>,
 }
@@ -150,6 +159,7 @@ impl From<(AppLatest, AppStatus)> for AppSummary {
             Some(MachineMode::Flash {
                 strategy: MachineSnapshotStrategy::Manual,
                 timeout,
+                ..
             }) => (
                 Some("manual".to_string()),
                 Some(timeout.unwrap_or(DEFAULT_SUSPEND_TIMEOUT_SECS)),
@@ -157,6 +167,7 @@ impl From<(AppLatest, AppStatus)> for AppSummary {
             Some(MachineMode::Flash {
                 strategy: MachineSnapshotStrategy::WaitForUserSpaceReady,
                 timeout,
+                ..
             }) => (
                 Some("user-space ready".to_string()),
                 Some(timeout.unwrap_or(DEFAULT_SUSPEND_TIMEOUT_SECS)),
@@ -164,6 +175,7 @@ impl From<(AppLatest, AppStatus)> for AppSummary {
             Some(MachineMode::Flash {
                 strategy: MachineSnapshotStrategy::WaitForFirstListen,
                 timeout,
+                ..
             }) => (
                 Some("first listen".to_string()),
                 Some(timeout.unwrap_or(DEFAULT_SUSPEND_TIMEOUT_SECS)),
@@ -171,6 +183,7 @@ impl From<(AppLatest, AppStatus)> for AppSummary {
             Some(MachineMode::Flash {
                 strategy: MachineSnapshotStrategy::WaitForNthListen(n),
                 timeout,
+                ..
             }) => (
                 Some(format!("{} listen", Ordinal(n))),
                 Some(timeout.unwrap_or(DEFAULT_SUSPEND_TIMEOUT_SECS)),
@@ -178,6 +191,7 @@ impl From<(AppLatest, AppStatus)> for AppSummary {
             Some(MachineMode::Flash {
                 strategy: MachineSnapshotStrategy::WaitForListenOnPort(port),
                 timeout,
+                ..
             }) => (
                 Some(format!("listen on port {port}")),
                 Some(timeout.unwrap_or(DEFAULT_SUSPEND_TIMEOUT_SECS)),
@@ -216,14 +230,18 @@ impl From<(AppLatest, AppStatus)> for AppSummary {
                         ServiceBindExternalProtocol::Http => None,
                         ServiceBindExternalProtocol::Https => None,
                         ServiceBindExternalProtocol::Tls => Some(service.port),
+                        ServiceBindExternalProtocol::TlsPassthrough => None,
                         ServiceBindExternalProtocol::Tcp => None, // TCP uses dynamic allocation
+                        ServiceBindExternalProtocol::Udp => Some(service.port),
                     });
 
                     let protocol = match external.protocol {
                         ServiceBindExternalProtocol::Http => "http",
                         ServiceBindExternalProtocol::Https => "https",
                         ServiceBindExternalProtocol::Tls => "tls",
+                        ServiceBindExternalProtocol::TlsPassthrough => "tls-passthrough",
                         ServiceBindExternalProtocol::Tcp => "tcp",
+                        ServiceBindExternalProtocol::Udp => "udp",
                     };
 
                     // Handle TCP services specially
@@ -301,6 +319,7 @@ impl From<(AppLatest, AppStatus)> for AppSummary {
             name: app.name,
             namespace: app.namespace,
             tags: app.tags.unwrap_or_default(),
+            conditions: status.conditions.iter().map(|c| c.to_string()).collect(),
             mode,
             snapshot_strategy,
             restart_policy: app.restart_policy.map(|r| r.to_string()),
@@ -312,6 +331,7 @@ impl From<(AppLatest, AppStatus)> for AppSummary {
             volumes,
             depends_on,
             suspend_timeout: timeout,
+            expires: format_expiry(app.ttl.as_deref(), status.ttl_started_at),
             services,
         }
     }
@@ -335,10 +355,16 @@ pub async fn run_app_list(config: &Config, args: ListNamespacedArgs) -> Result<(
 pub async fn run_app_get(config: &Config, args: GetNamespacedArgs) -> Result<()> {
     let api_client = get_api_client(config.try_into()?);
     let (app, status) = api_client.app().get(args.clone().into(), args.name).await?;
+    let machine_name = status.machine_name.clone();
 
     let summary = AppSummary::from((app, status));
     summary.print();
 
+    // the app controller delegates to its machine, which is where the events are recorded
+    if let Some(machine_name) = machine_name {
+        print_resource_events(&api_client, args.into(), "machine", machine_name).await?;
+    }
+
     Ok(())
 }
 
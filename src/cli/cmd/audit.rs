@@ -0,0 +1,187 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use clap::Args;
+use ignition::resources::{
+    core::{AuditAction, AuditChange, AuditEvent, AuditLogParams},
+    metadata::Namespace,
+};
+use meta::table;
+use serde_json::Value;
+
+use crate::{
+    client::get_api_client,
+    config::Config,
+    ui::message::{message_detail, message_info},
+};
+
+#[derive(Clone, Debug, Args)]
+pub struct AuditArgs {
+    /// Only show changes in this namespace (short: --ns) [default: all namespaces]
+    #[arg(long = "namespace", alias = "ns")]
+    namespace: Option<String>,
+
+    /// Only show changes to this kind of resource (eg. machine, service, volume)
+    #[arg(long = "kind", short = 'k')]
+    kind: Option<String>,
+
+    /// Only show changes made by this user
+    #[arg(long = "user", short = 'u')]
+    user: Option<String>,
+
+    /// Only show changes made since (eg. 1d, 1h, 1m, 10s)
+    #[arg(long = "since", short = 's')]
+    since: Option<String>,
+
+    /// Maximum number of changes to show, most recent first
+    #[arg(long = "limit", short = 'l', default_value_t = 50)]
+    limit: u32,
+
+    /// Show the changed fields with their previous and new values
+    #[arg(long = "diff", short = 'd')]
+    diff: bool,
+
+    /// Only show changes to resources with this name
+    name: Option<String>,
+}
+
+#[table]
+pub struct AuditEventTable {
+    #[field(name = "time")]
+    time: String,
+
+    #[field(name = "namespace")]
+    namespace: String,
+
+    #[field(name = "kind")]
+    kind: String,
+
+    #[field(name = "name")]
+    name: String,
+
+    #[field(name = "action", cell_style = important)]
+    action: String,
+
+    #[field(name = "user")]
+    user: String,
+
+    #[field(name = "changes", max_width = 60)]
+    changes: String,
+}
+
+fn format_time(timestamp: u64) -> String {
+    chrono::DateTime::from_timestamp_millis(timestamp as i64)
+        .unwrap_or_default()
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+fn format_action(action: AuditAction) -> &'static str {
+    match action {
+        AuditAction::Create => "create",
+        AuditAction::Update => "update",
+        AuditAction::Delete => "delete",
+    }
+}
+
+fn format_value(value: &Option<Value>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => "-".to_string(),
+    }
+}
+
+fn format_change(change: &AuditChange) -> String {
+    format!(
+        "{}: {} -> {}",
+        change.path,
+        format_value(&change.before),
+        format_value(&change.after)
+    )
+}
+
+impl From<AuditEvent> for AuditEventTableRow {
+    fn from(event: AuditEvent) -> Self {
+        let changes = match event.action {
+            AuditAction::Update if event.changes.is_empty() => "unchanged".to_string(),
+            AuditAction::Update => event
+                .changes
+                .iter()
+                .map(|c| c.path.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+            _ => "-".to_string(),
+        };
+
+        Self {
+            time: format_time(event.timestamp),
+            namespace: event.namespace.unwrap_or("-".to_string()),
+            kind: event.kind,
+            name: event.name,
+            action: format_action(event.action).to_string(),
+            user: event.sub,
+            changes,
+        }
+    }
+}
+
+pub async fn run_audit(config: &Config, args: AuditArgs) -> Result<()> {
+    let api_client = get_api_client(config.try_into()?);
+
+    let since = match args.since {
+        Some(since) => {
+            let since = humantime::parse_duration(&since)?;
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+            Some(now.saturating_sub(since).as_millis() as u64)
+        }
+        None => None,
+    };
+
+    let events = api_client
+        .core()
+        .audit_log(
+            Namespace::from_value(args.namespace),
+            AuditLogParams {
+                kind: args.kind,
+                name: args.name,
+                sub: args.user,
+                since,
+                limit: Some(args.limit),
+            },
+        )
+        .await?;
+
+    if args.diff {
+        for event in events {
+            let target = match &event.namespace {
+                Some(namespace) => format!("{}/{}", namespace, event.name),
+                None => event.name.clone(),
+            };
+
+            message_info(format!(
+                "{} {} {} {} by {}",
+                format_time(event.timestamp),
+                format_action(event.action),
+                event.kind,
+                target,
+                event.sub
+            ));
+
+            for change in &event.changes {
+                message_detail(format_change(change));
+            }
+        }
+
+        return Ok(());
+    }
+
+    let mut table = AuditEventTable::new();
+
+    for event in events {
+        table.add_row(event.into());
+    }
+
+    table.print();
+
+    Ok(())
+}
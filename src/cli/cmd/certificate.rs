@@ -1,12 +1,21 @@
+use std::{fs, path::PathBuf};
+
 use anyhow::Result;
-use ignition::resources::certificate::{
-    CertificateIssuer, CertificateLatest, CertificateState, CertificateStatus,
+use clap::Args;
+use ignition::resources::{
+    certificate::{
+        CertificateIssuer, CertificateKeyAlgorithm, CertificateLatest, CertificateState,
+        CertificateStatus,
+    },
+    metadata::Namespace,
 };
 use meta::{summary, table};
 
 use crate::{
     client::get_api_client,
-    cmd::{DeleteNamespacedArgs, GetNamespacedArgs, ListNamespacedArgs},
+    cmd::{
+        DeleteNamespacedArgs, GetNamespacedArgs, ListNamespacedArgs, event::print_resource_events,
+    },
     config::Config,
     ui::message::{message_info, message_warn},
 };
@@ -40,6 +49,9 @@ pub struct CertificateSummary {
     #[field(name = "tags")]
     tags: Vec<String>,
 
+    #[field(name = "conditions")]
+    conditions: Vec<String>,
+
     #[field(name = "state", cell_style = important)]
     state: String,
 
@@ -55,6 +67,9 @@ pub struct CertificateSummary {
     #[field(name = "email")]
     email: Option<String>,
 
+    #[field(name = "key algorithm")]
+    key_algorithm: Option<String>,
+
     #[field(name = "not before")]
     not_before: Option<String>,
 
@@ -64,8 +79,26 @@ pub struct CertificateSummary {
     #[field(name = "renewal time")]
     renewal_time: Option<String>,
 
+    #[field(name = "renewing since")]
+    renewing_since: Option<String>,
+
+    #[field(name = "renewal attempts")]
+    renewal_attempts: Option<String>,
+
+    #[field(name = "issued by")]
+    issuer_name: Option<String>,
+
+    #[field(name = "names")]
+    subject_alt_names: Vec<String>,
+
+    #[field(name = "chain")]
+    chain: Vec<String>,
+
     #[field(name = "last failure reason")]
     last_failure_reason: Option<String>,
+
+    #[field(name = "last failure time")]
+    last_failure_time: Option<String>,
 }
 
 impl From<(CertificateLatest, CertificateStatus)> for CertificateTableRow {
@@ -77,6 +110,7 @@ impl From<(CertificateLatest, CertificateStatus)> for CertificateTableRow {
                 (format!("auto ({})", provider), Some(provider.clone()))
             }
             CertificateIssuer::Manual { .. } => ("manual".to_string(), None),
+            CertificateIssuer::Internal { .. } => ("internal".to_string(), None),
         };
 
         let state = format_certificate_state(&status.state);
@@ -106,6 +140,7 @@ impl From<(CertificateLatest, CertificateStatus)> for CertificateSummary {
             CertificateIssuer::Manual { cert_path, .. } => {
                 (format!("manual ({})", cert_path), None, None)
             }
+            CertificateIssuer::Internal { .. } => ("internal".to_string(), None, None),
         };
 
         let state = format_certificate_state(&status.state);
@@ -114,19 +149,56 @@ impl From<(CertificateLatest, CertificateStatus)> for CertificateSummary {
             name: certificate.name,
             namespace: certificate.namespace,
             tags: certificate.tags.unwrap_or_default(),
+            conditions: status.conditions.iter().map(|c| c.to_string()).collect(),
             state,
             domains,
             issuer,
             provider,
             email,
+            key_algorithm: status.key_algorithm.as_ref().map(format_key_algorithm),
             not_before: status.not_before,
             not_after: status.not_after,
             renewal_time: status.renewal_time,
+            renewing_since: status.renewing_since,
+            renewal_attempts: status.renewal_attempts.map(|a| a.to_string()),
+            issuer_name: status.issuer_name,
+            subject_alt_names: status.subject_alt_names.unwrap_or_default(),
+            chain: status
+                .chain
+                .unwrap_or_default()
+                .iter()
+                .map(|entry| format!("{} (until {})", entry.subject, entry.not_after))
+                .collect(),
             last_failure_reason: status.last_failure_reason,
+            last_failure_time: status.last_failure_time,
         }
     }
 }
 
+#[derive(Args)]
+pub struct CertificateBundleArgs {
+    /// Namespace of the certificate (short: --ns)
+    #[arg(long = "namespace", alias = "ns")]
+    namespace: Option<String>,
+
+    /// Name of the internal certificate
+    name: String,
+
+    /// Directory to write <name>.crt, <name>.key and ca.crt to, defaults to the current one
+    #[arg(long = "output", short = 'o')]
+    output: Option<PathBuf>,
+}
+
+fn format_key_algorithm(algorithm: &CertificateKeyAlgorithm) -> String {
+    match algorithm {
+        CertificateKeyAlgorithm::EcdsaP256 => "ecdsa-p256".to_string(),
+        CertificateKeyAlgorithm::EcdsaP384 => "ecdsa-p384".to_string(),
+        CertificateKeyAlgorithm::Rsa2048 => "rsa-2048".to_string(),
+        CertificateKeyAlgorithm::Rsa3072 => "rsa-3072".to_string(),
+        CertificateKeyAlgorithm::Rsa4096 => "rsa-4096".to_string(),
+    }
+}
+
 fn format_certificate_state(state: &CertificateState) -> String {
     match state {
         CertificateState::Pending => "pending".to_string(),
@@ -163,12 +235,14 @@ pub async fn run_certificate_get(config: &Config, args: GetNamespacedArgs) -> Re
     let api_client = get_api_client(config.try_into()?);
     let (certificate, status) = api_client
         .certificate()
-        .get(args.clone().into(), args.name)
+        .get(args.clone().into(), args.name.clone())
         .await?;
 
     let summary = CertificateSummary::from((certificate, status));
     summary.print();
 
+    print_resource_events(&api_client, args.clone().into(), "certificate", args.name).await?;
+
     Ok(())
 }
 
@@ -191,3 +265,52 @@ pub async fn run_certificate_delete(config: &Config, args: DeleteNamespacedArgs)
 
     Ok(())
 }
+
+pub async fn run_certificate_ca(config: &Config) -> Result<()> {
+    let api_client = get_api_client(config.try_into()?);
+    let ca = api_client.core().get_internal_ca().await?;
+
+    print!("{}", ca.cert_pem);
+
+    Ok(())
+}
+
+pub async fn run_certificate_rotate_ca(config: &Config) -> Result<()> {
+    let api_client = get_api_client(config.try_into()?);
+    let ca = api_client.core().rotate_internal_ca().await?;
+
+    message_info("Internal CA rotated, images have to trust the new root within a week");
+    print!("{}", ca.cert_pem);
+
+    Ok(())
+}
+
+pub async fn run_certificate_bundle(config: &Config, args: CertificateBundleArgs) -> Result<()> {
+    let api_client = get_api_client(config.try_into()?);
+    let bundle = api_client
+        .core()
+        .get_internal_certificate(Namespace::from_value_or_default(args.namespace), &args.name)
+        .await?;
+
+    let output = args.output.unwrap_or_else(|| PathBuf::from("."));
+    fs::create_dir_all(&output)?;
+
+    let key_path = output.join(format!("{}.key", args.name));
+    fs::write(output.join(format!("{}.crt", args.name)), &bundle.cert_pem)?;
+    fs::write(&key_path, &bundle.key_pem)?;
+    fs::write(output.join("ca.crt"), &bundle.ca_cert_pem)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&key_path, fs::Permissions::from_mode(0o600))?;
+    }
+
+    message_info(format!(
+        "Certificate '{}' written to {}",
+        args.name,
+        output.display()
+    ));
+
+    Ok(())
+}
@@ -0,0 +1,203 @@
+use anyhow::Result;
+use ignition::resources::cron::{CronLatest, CronRun, CronStatus};
+use meta::{summary, table};
+
+use crate::{
+    client::get_api_client,
+    cmd::{DeleteNamespacedArgs, GetNamespacedArgs, ListNamespacedArgs},
+    config::Config,
+    ui::message::{message_info, message_warn},
+};
+
+#[table]
+pub struct CronTable {
+    #[field(name = "name")]
+    name: String,
+
+    #[field(name = "namespace")]
+    namespace: Option<String>,
+
+    #[field(name = "schedule", cell_style = important)]
+    schedule: String,
+
+    #[field(name = "next run")]
+    next_run: Option<String>,
+
+    #[field(name = "last run", cell_style = important)]
+    last_run: Option<String>,
+}
+
+#[summary]
+pub struct CronSummary {
+    #[field(name = "name")]
+    name: String,
+
+    #[field(name = "namespace")]
+    namespace: Option<String>,
+
+    #[field(name = "tags")]
+    tags: Vec<String>,
+
+    #[field(name = "conditions")]
+    conditions: Vec<String>,
+
+    #[field(name = "schedule", cell_style = important)]
+    schedule: String,
+
+    #[field(name = "next run")]
+    next_run: Option<String>,
+
+    #[field(name = "timeout")]
+    timeout: Option<String>,
+
+    #[field(name = "image")]
+    image: Option<String>,
+
+    #[field(name = "command")]
+    command: Option<String>,
+
+    #[field(name = "last run", cell_style = important)]
+    last_run: Option<String>,
+}
+
+#[table]
+pub struct CronRunTable {
+    #[field(name = "machine")]
+    machine: String,
+
+    #[field(name = "state", cell_style = important)]
+    state: String,
+
+    #[field(name = "exit code")]
+    exit_code: Option<String>,
+
+    #[field(name = "started")]
+    started: String,
+
+    #[field(name = "duration")]
+    duration: Option<String>,
+}
+
+fn format_time(timestamp: u64) -> String {
+    chrono::DateTime::from_timestamp_millis(timestamp as i64)
+        .unwrap_or_default()
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+fn format_last_run(status: &CronStatus) -> Option<String> {
+    let run = status.runs.first()?;
+    Some(format!(
+        "{} at {}",
+        run.state.to_string(),
+        format_time(run.started_at)
+    ))
+}
+
+impl From<(CronLatest, CronStatus)> for CronTableRow {
+    fn from((cron, status): (CronLatest, CronStatus)) -> Self {
+        Self {
+            next_run: status.next_run_at.map(format_time),
+            last_run: format_last_run(&status),
+            name: cron.name,
+            namespace: cron.namespace,
+            schedule: cron.schedule,
+        }
+    }
+}
+
+impl From<(CronLatest, CronStatus)> for CronSummary {
+    fn from((cron, status): (CronLatest, CronStatus)) -> Self {
+        Self {
+            conditions: status.conditions.iter().map(|c| c.to_string()).collect(),
+            next_run: status.next_run_at.map(format_time),
+            last_run: format_last_run(&status),
+            timeout: cron.timeout.map(|timeout| format!("{}s", timeout)),
+            command: cron.command.map(|command| command.join(" ")),
+            name: cron.name,
+            namespace: cron.namespace,
+            tags: cron.tags.unwrap_or_default(),
+            schedule: cron.schedule,
+            image: cron.image,
+        }
+    }
+}
+
+impl From<CronRun> for CronRunTableRow {
+    fn from(run: CronRun) -> Self {
+        Self {
+            state: run.state.to_string(),
+            exit_code: run.exit_code.map(|code| code.to_string()),
+            started: format_time(run.started_at),
+            duration: run.finished_at.map(|finished_at| {
+                let secs = finished_at.saturating_sub(run.started_at) / 1000;
+                format!("{}s", secs)
+            }),
+            machine: run.machine,
+        }
+    }
+}
+
+pub async fn run_cron_list(config: &Config, args: ListNamespacedArgs) -> Result<()> {
+    let api_client = get_api_client(config.try_into()?);
+    let crons = api_client.cron().list(args.into()).await?;
+
+    let mut table = CronTable::new();
+
+    for (cron, status) in crons {
+        table.add_row(CronTableRow::from((cron, status)));
+    }
+
+    table.print();
+
+    Ok(())
+}
+
+pub async fn run_cron_get(config: &Config, args: GetNamespacedArgs) -> Result<()> {
+    let api_client = get_api_client(config.try_into()?);
+    let (cron, status) = api_client
+        .cron()
+        .get(args.clone().into(), args.name)
+        .await?;
+
+    let summary = CronSummary::from((cron, status));
+    summary.print();
+
+    Ok(())
+}
+
+pub async fn run_cron_runs(config: &Config, args: GetNamespacedArgs) -> Result<()> {
+    let api_client = get_api_client(config.try_into()?);
+    let (_cron, status) = api_client
+        .cron()
+        .get(args.clone().into(), args.name)
+        .await?;
+
+    let mut table = CronRunTable::new();
+    for run in status.runs {
+        table.add_row(run.into());
+    }
+    table.print();
+
+    Ok(())
+}
+
+pub async fn run_cron_delete(config: &Config, args: DeleteNamespacedArgs) -> Result<()> {
+    let api_client = get_api_client(config.try_into()?);
+    if !args.confirm {
+        message_warn(format!(
+            "You are about to delete the cron '{}', a run in progress is stopped. This action cannot be undone. To confirm, run the command with --yes (or -y).",
+            args.name
+        ));
+        return Ok(());
+    }
+
+    api_client
+        .cron()
+        .delete(args.clone().into(), args.name.clone())
+        .await?;
+
+    message_info(format!("Cron '{}' has been deleted.", args.name));
+
+    Ok(())
+}
@@ -1,7 +1,11 @@
-use std::path::PathBuf;
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 
 use ansi_term::{Color, Style};
 use anyhow::{Result, bail};
+use atty::Stream;
 use clap::{ArgAction, Args};
 use ignition::{
     api_client::ApiClient,
@@ -11,7 +15,11 @@ use ignition::{
         ProvideMetadata,
         app::App,
         certificate::Certificate,
-        machine::Machine,
+        core::ImagePullProgress,
+        cron::Cron,
+        dns_record::DnsRecord,
+        machine::{Machine, MachinePhase},
+        machine_group::MachineGroup,
         metadata::{Metadata, Namespace},
         service::Service,
         volume::Volume,
@@ -22,16 +30,23 @@ use serde_yaml::Value;
 use tokio::fs::{read_dir, read_to_string};
 
 use crate::{
-    build::{BuildTarget, build_and_push_image},
+    build::{BuildTarget, build_and_push_image, env::resolve_build_env},
     client::get_api_client,
     config::Config,
     expr::{
         ctx::{EnvAmbientOverrideBehavior, ExprEvalContext, ExprEvalContextConfig},
         eval::{eval_expr, transform_eval_expressions_root},
     },
-    ui::message::{message_detail, message_info, message_warn},
+    ui::message::{
+        message_detail, message_info, message_progress, message_progress_end, message_warn,
+    },
 };
 
+/// How long a deployed machine has to start pulling its image before the deploy moves on.
+const IMAGE_PULL_START_TIMEOUT: Duration = Duration::from_secs(2);
+const IMAGE_PULL_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const PROGRESS_BAR_WIDTH: usize = 30;
+
 /// Find deployment path using fallback logic
 fn find_deployment_path(provided_path: Option<PathBuf>) -> Result<PathBuf> {
     // If path was provided, try to use it
@@ -126,6 +141,10 @@ pub struct DeployArgs {
     #[arg(long = "no-build-cache")]
     disable_build_cache: bool,
 
+    /// Don't fetch uninitialized git submodules locally before remote builds
+    #[arg(long = "no-submodules")]
+    skip_submodules: bool,
+
     /// Debug the expression evaluation context
     #[arg(long = "debug-context")]
     debug_context: bool,
@@ -232,22 +251,64 @@ pub async fn run_deploy(config: &Config, args: DeployArgs) -> Result<()> {
     let me = api_client.core().me().await?;
 
     for (path, resource) in resources.iter_mut() {
-        let (resource_name, mut_image, mut_build) = match resource {
+        let (resource_name, mut_image, mut_build, mut_build_env) = match resource {
             Resources::Machine(machine) => (
                 machine.metadata().to_string(),
                 &mut machine.image,
                 &mut machine.build,
+                &mut machine.build_env,
             ),
             Resources::MachineV1(machine) => (
                 machine.metadata().to_string(),
                 &mut machine.image,
                 &mut machine.build,
+                &mut machine.build_env,
+            ),
+            Resources::App(app) => (
+                app.metadata().to_string(),
+                &mut app.image,
+                &mut app.build,
+                &mut app.build_env,
+            ),
+            Resources::AppV1(app) => (
+                app.metadata().to_string(),
+                &mut app.image,
+                &mut app.build,
+                &mut app.build_env,
+            ),
+            Resources::MachineGroup(group) => (
+                group.metadata().to_string(),
+                &mut group.image,
+                &mut group.build,
+                &mut group.build_env,
+            ),
+            Resources::MachineGroupV1(group) => (
+                group.metadata().to_string(),
+                &mut group.image,
+                &mut group.build,
+                &mut group.build_env,
+            ),
+            Resources::Cron(cron) => (
+                cron.metadata().to_string(),
+                &mut cron.image,
+                &mut cron.build,
+                &mut cron.build_env,
+            ),
+            Resources::CronV1(cron) => (
+                cron.metadata().to_string(),
+                &mut cron.image,
+                &mut cron.build,
+                &mut cron.build_env,
             ),
-            Resources::App(app) => (app.metadata().to_string(), &mut app.image, &mut app.build),
-            Resources::AppV1(app) => (app.metadata().to_string(), &mut app.image, &mut app.build),
             _ => continue,
         };
 
+        // build env only matters to the build, never send it (or its secrets) to the server
+        let build_env = resolve_build_env(
+            config.build.as_ref(),
+            mut_build_env.take().unwrap_or_default(),
+        );
+
         let Some(build) = mut_build.clone() else {
             continue;
         };
@@ -270,8 +331,10 @@ pub async fn run_deploy(config: &Config, args: DeployArgs) -> Result<()> {
             dir,
             &me.tenant,
             build,
+            build_env,
             args.debug_build,
             args.disable_build_cache,
+            args.skip_submodules,
             force_build_target,
         )
         .await?;
@@ -281,6 +344,9 @@ pub async fn run_deploy(config: &Config, args: DeployArgs) -> Result<()> {
         *mut_image = Some(image);
     }
 
+    // resources are checked against the ones they reference when applied
+    resources.sort_by_key(|(_, resource)| deploy_order(resource));
+
     for (_path, resource) in resources {
         match resource {
             Resources::Certificate(certificate) | Resources::CertificateV1(certificate) => {
@@ -296,6 +362,19 @@ pub async fn run_deploy(config: &Config, args: DeployArgs) -> Result<()> {
                 }
                 deploy_certificate(config, &api_client, certificate.into()).await?;
             }
+            Resources::DnsRecord(dns_record) | Resources::DnsRecordV1(dns_record) => {
+                if args.dry_run {
+                    deploy_dry_run::<DnsRecord>(
+                        config,
+                        &api_client,
+                        "dns record",
+                        dns_record.metadata(),
+                        dns_record.into(),
+                    )?;
+                    continue;
+                }
+                deploy_dns_record(config, &api_client, dns_record.into()).await?;
+            }
             Resources::App(app) | Resources::AppV1(app) => {
                 if args.dry_run {
                     deploy_dry_run::<App>(config, &api_client, "app", app.metadata(), app.into())?;
@@ -317,6 +396,33 @@ pub async fn run_deploy(config: &Config, args: DeployArgs) -> Result<()> {
 
                 deploy_machine(config, &api_client, machine.into()).await?;
             }
+            Resources::MachineGroup(group) | Resources::MachineGroupV1(group) => {
+                if args.dry_run {
+                    deploy_dry_run::<MachineGroup>(
+                        config,
+                        &api_client,
+                        "machine group",
+                        group.metadata(),
+                        group.into(),
+                    )?;
+                    continue;
+                }
+
+                deploy_machine_group(config, &api_client, group.into()).await?;
+            }
+            Resources::Cron(cron) | Resources::CronV1(cron) => {
+                if args.dry_run {
+                    deploy_dry_run::<Cron>(
+                        config,
+                        &api_client,
+                        "cron",
+                        cron.metadata(),
+                        cron.into(),
+                    )?;
+                    continue;
+                }
+                deploy_cron(config, &api_client, cron.into()).await?;
+            }
             Resources::Service(service) | Resources::ServiceV1(service) => {
                 if args.dry_run {
                     deploy_dry_run::<Service>(
@@ -349,6 +455,21 @@ pub async fn run_deploy(config: &Config, args: DeployArgs) -> Result<()> {
     Ok(())
 }
 
+/// Volumes and certificates go first, then machines, machine groups, crons and apps, then the
+/// services targeting them and the DNS records pointing at them.
+fn deploy_order(resource: &Resources) -> u8 {
+    match resource {
+        Resources::Volume(_) | Resources::VolumeV1(_) => 0,
+        Resources::Certificate(_) | Resources::CertificateV1(_) => 0,
+        Resources::Machine(_) | Resources::MachineV1(_) => 1,
+        Resources::MachineGroup(_) | Resources::MachineGroupV1(_) => 1,
+        Resources::Cron(_) | Resources::CronV1(_) => 1,
+        Resources::App(_) | Resources::AppV1(_) => 1,
+        Resources::Service(_) | Resources::ServiceV1(_) => 2,
+        Resources::DnsRecord(_) | Resources::DnsRecordV1(_) => 2,
+    }
+}
+
 fn parse_all_resources_in_dir<'a>(
     path: &'a PathBuf,
     resources: &'a mut Vec<(PathBuf, Resources)>,
@@ -411,6 +532,8 @@ async fn deploy_machine(_config: &Config, api_client: &ApiClient, machine: Machi
     let metadata = machine.metadata();
     api_client.machine().apply(machine).await?;
 
+    show_image_pull_progress(api_client, metadata.namespace.clone(), &metadata.name).await?;
+
     let (machine, _status) = api_client
         .machine()
         .get(
@@ -427,6 +550,66 @@ async fn deploy_machine(_config: &Config, api_client: &ApiClient, machine: Machi
     Ok(())
 }
 
+async fn deploy_machine_group(
+    _config: &Config,
+    api_client: &ApiClient,
+    group: MachineGroup,
+) -> Result<()> {
+    let metadata = group.metadata();
+    api_client.machine_group().apply(group).await?;
+
+    // the replicas share the image, following the first one is enough
+    show_image_pull_progress(
+        api_client,
+        metadata.namespace.clone(),
+        &format!("{}-0", metadata.name),
+    )
+    .await?;
+
+    let (group, _status) = api_client
+        .machine_group()
+        .get(
+            Namespace::from_value_or_default(metadata.namespace),
+            metadata.name,
+        )
+        .await?;
+
+    message_info(format!(
+        "Successfully deployed machine group: {}",
+        group.metadata().to_string()
+    ));
+
+    Ok(())
+}
+
+async fn deploy_cron(_config: &Config, api_client: &ApiClient, cron: Cron) -> Result<()> {
+    let metadata = cron.metadata();
+    api_client.cron().apply(cron).await?;
+
+    let (cron, status) = api_client
+        .cron()
+        .get(
+            Namespace::from_value_or_default(metadata.namespace),
+            metadata.name,
+        )
+        .await?;
+
+    message_info(format!(
+        "Successfully deployed cron: {}",
+        cron.metadata().to_string()
+    ));
+    if let Some(next_run_at) = status.next_run_at {
+        message_detail(format!(
+            "Next run at {}",
+            chrono::DateTime::from_timestamp_millis(next_run_at as i64)
+                .unwrap_or_default()
+                .format("%Y-%m-%d %H:%M:%S")
+        ));
+    }
+
+    Ok(())
+}
+
 async fn deploy_certificate(
     _config: &Config,
     api_client: &ApiClient,
@@ -451,6 +634,30 @@ async fn deploy_certificate(
     Ok(())
 }
 
+async fn deploy_dns_record(
+    _config: &Config,
+    api_client: &ApiClient,
+    dns_record: DnsRecord,
+) -> Result<()> {
+    let metadata = dns_record.metadata();
+    api_client.dns_record().apply(dns_record).await?;
+
+    let (dns_record, _status) = api_client
+        .dns_record()
+        .get(
+            Namespace::from_value_or_default(metadata.namespace),
+            metadata.name,
+        )
+        .await?;
+
+    message_info(format!(
+        "Successfully deployed DNS record: {}",
+        dns_record.metadata().to_string()
+    ));
+
+    Ok(())
+}
+
 async fn deploy_service(_config: &Config, api_client: &ApiClient, service: Service) -> Result<()> {
     let metadata = service.metadata();
     api_client.service().apply(service).await?;
@@ -495,14 +702,18 @@ async fn deploy_app(_config: &Config, api_client: &ApiClient, app: App) -> Resul
     let metadata = app.metadata();
     api_client.app().apply(app).await?;
 
-    let (app, _status) = api_client
+    let (app, status) = api_client
         .app()
         .get(
-            Namespace::from_value_or_default(metadata.namespace),
+            Namespace::from_value_or_default(metadata.namespace.clone()),
             metadata.name,
         )
         .await?;
 
+    if let Some(machine_name) = status.machine_name {
+        show_image_pull_progress(api_client, metadata.namespace, &machine_name).await?;
+    }
+
     message_info(format!(
         "Successfully deployed app: {}",
         app.metadata().to_string()
@@ -511,6 +722,87 @@ async fn deploy_app(_config: &Config, api_client: &ApiClient, app: App) -> Resul
     Ok(())
 }
 
+/// Follows the image pull a deploy started, so large images don't look like a hang.
+async fn show_image_pull_progress(
+    api_client: &ApiClient,
+    namespace: Option<String>,
+    machine_name: &str,
+) -> Result<()> {
+    let started_at = Instant::now();
+    let show_progress = atty::is(Stream::Stderr);
+    let mut pulling = false;
+
+    loop {
+        let (_, status) = api_client
+            .machine()
+            .get(
+                Namespace::from_value_or_default(namespace.clone()),
+                machine_name.to_string(),
+            )
+            .await?;
+
+        if matches!(status.phase, MachinePhase::PullingImage) {
+            pulling = true;
+
+            let progress = api_client
+                .core()
+                .image_pull_progress(
+                    Namespace::from_value_or_default(namespace.clone()),
+                    machine_name.to_string(),
+                )
+                .await?;
+
+            if show_progress {
+                message_progress(format_image_pull_progress(machine_name, &progress));
+            }
+        } else if pulling || started_at.elapsed() > IMAGE_PULL_START_TIMEOUT {
+            break;
+        }
+
+        tokio::time::sleep(IMAGE_PULL_POLL_INTERVAL).await;
+    }
+
+    if pulling && show_progress {
+        message_progress_end();
+    }
+
+    Ok(())
+}
+
+fn format_image_pull_progress(machine_name: &str, progress: &ImagePullProgress) -> String {
+    let Some(reference) = &progress.reference else {
+        return format!("Pulling image for {}", machine_name);
+    };
+
+    let total_bytes: u64 = progress.layers.iter().map(|l| l.size_bytes).sum();
+    let downloaded_bytes: u64 = progress
+        .layers
+        .iter()
+        .map(|l| l.downloaded_bytes.min(l.size_bytes))
+        .sum();
+    let unpacked_layers = progress.layers.iter().filter(|l| l.unpacked).count();
+
+    let ratio = if total_bytes > 0 {
+        downloaded_bytes as f64 / total_bytes as f64
+    } else {
+        0.0
+    };
+    let filled = (ratio * PROGRESS_BAR_WIDTH as f64).round() as usize;
+
+    let mib = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+
+    format!(
+        "Pulling {} [{}{}] {:.1}/{:.1} MiB, {}/{} layers unpacked",
+        reference,
+        "#".repeat(filled),
+        "-".repeat(PROGRESS_BAR_WIDTH - filled),
+        mib(downloaded_bytes),
+        mib(total_bytes),
+        unpacked_layers,
+        progress.layers.len()
+    )
+}
+
 fn deploy_dry_run<T: Serialize>(
     _config: &Config,
     _api_client: &ApiClient,
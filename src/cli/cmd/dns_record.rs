@@ -0,0 +1,160 @@
+use anyhow::Result;
+use ignition::resources::dns_record::{
+    DnsRecordLatest, DnsRecordState, DnsRecordStatus, DnsRecordValue,
+};
+use meta::{summary, table};
+
+use crate::{
+    client::get_api_client,
+    cmd::{DeleteNamespacedArgs, GetNamespacedArgs, ListNamespacedArgs},
+    config::Config,
+    ui::message::{message_info, message_warn},
+};
+
+#[table]
+pub struct DnsRecordTable {
+    #[field(name = "name")]
+    name: String,
+
+    #[field(name = "namespace")]
+    namespace: Option<String>,
+
+    #[field(name = "state", cell_style = important)]
+    state: String,
+
+    #[field(name = "domain")]
+    domain: String,
+
+    #[field(name = "type")]
+    record_type: String,
+}
+
+#[summary]
+pub struct DnsRecordSummary {
+    #[field(name = "name")]
+    name: String,
+
+    #[field(name = "namespace")]
+    namespace: Option<String>,
+
+    #[field(name = "tags")]
+    tags: Vec<String>,
+
+    #[field(name = "conditions")]
+    conditions: Vec<String>,
+
+    #[field(name = "state", cell_style = important)]
+    state: String,
+
+    #[field(name = "domain", cell_style = important)]
+    domain: String,
+
+    #[field(name = "type")]
+    record_type: String,
+
+    #[field(name = "ttl")]
+    ttl: Option<String>,
+
+    #[field(name = "values")]
+    values: Vec<String>,
+
+    #[field(name = "last failure reason")]
+    last_failure_reason: Option<String>,
+}
+
+fn format_dns_record_state(state: &DnsRecordState) -> String {
+    match state {
+        DnsRecordState::Pending => "pending".to_string(),
+        DnsRecordState::Published => "published".to_string(),
+        DnsRecordState::Failed => "failed".to_string(),
+    }
+}
+
+fn format_dns_record_values(value: &DnsRecordValue) -> Vec<String> {
+    match value {
+        DnsRecordValue::A { addresses } | DnsRecordValue::Aaaa { addresses } => addresses.clone(),
+        DnsRecordValue::Cname { target } => vec![target.clone()],
+        DnsRecordValue::Txt { values } => values.clone(),
+        DnsRecordValue::Mx { exchanges } => exchanges
+            .iter()
+            .map(|exchange| format!("{} {}", exchange.preference, exchange.exchange))
+            .collect(),
+    }
+}
+
+impl From<(DnsRecordLatest, DnsRecordStatus)> for DnsRecordTableRow {
+    fn from((dns_record, status): (DnsRecordLatest, DnsRecordStatus)) -> Self {
+        Self {
+            name: dns_record.name,
+            namespace: dns_record.namespace,
+            state: format_dns_record_state(&status.state),
+            domain: dns_record.domain,
+            record_type: dns_record.record.record_type().to_string(),
+        }
+    }
+}
+
+impl From<(DnsRecordLatest, DnsRecordStatus)> for DnsRecordSummary {
+    fn from((dns_record, status): (DnsRecordLatest, DnsRecordStatus)) -> Self {
+        Self {
+            name: dns_record.name,
+            namespace: dns_record.namespace,
+            tags: dns_record.tags.unwrap_or_default(),
+            conditions: status.conditions.iter().map(|c| c.to_string()).collect(),
+            state: format_dns_record_state(&status.state),
+            domain: dns_record.domain,
+            record_type: dns_record.record.record_type().to_string(),
+            ttl: dns_record.ttl.map(|ttl| format!("{}s", ttl)),
+            values: format_dns_record_values(&dns_record.record),
+            last_failure_reason: status.last_failure_reason,
+        }
+    }
+}
+
+pub async fn run_dns_record_list(config: &Config, args: ListNamespacedArgs) -> Result<()> {
+    let api_client = get_api_client(config.try_into()?);
+    let dns_records = api_client.dns_record().list(args.into()).await?;
+
+    let mut table = DnsRecordTable::new();
+
+    for (dns_record, status) in dns_records {
+        table.add_row(DnsRecordTableRow::from((dns_record, status)));
+    }
+
+    table.print();
+
+    Ok(())
+}
+
+pub async fn run_dns_record_get(config: &Config, args: GetNamespacedArgs) -> Result<()> {
+    let api_client = get_api_client(config.try_into()?);
+    let (dns_record, status) = api_client
+        .dns_record()
+        .get(args.clone().into(), args.name)
+        .await?;
+
+    let summary = DnsRecordSummary::from((dns_record, status));
+    summary.print();
+
+    Ok(())
+}
+
+pub async fn run_dns_record_delete(config: &Config, args: DeleteNamespacedArgs) -> Result<()> {
+    let api_client = get_api_client(config.try_into()?);
+    if !args.confirm {
+        message_warn(format!(
+            "You are about to delete the DNS record '{}'. This action cannot be undone. To confirm, run the command with --yes (or -y).",
+            args.name
+        ));
+        return Ok(());
+    }
+
+    api_client
+        .dns_record()
+        .delete(args.clone().into(), args.name.clone())
+        .await?;
+
+    message_info(format!("DNS record '{}' has been deleted.", args.name));
+
+    Ok(())
+}
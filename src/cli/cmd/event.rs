@@ -0,0 +1,104 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use ignition::{
+    api_client::ApiClient,
+    resources::{
+        core::{ListEventsParams, ResourceEvent},
+        metadata::Namespace,
+    },
+};
+use meta::table;
+
+#[table]
+pub struct ResourceEventTable {
+    #[field(name = "type")]
+    event_type: String,
+
+    #[field(name = "reason", cell_style = important)]
+    reason: String,
+
+    #[field(name = "age")]
+    age: String,
+
+    #[field(name = "message", max_width = 80)]
+    message: String,
+}
+
+/// Compact age of a timestamp in milliseconds, in its largest unit (eg. 45s, 12m, 3h, 2d).
+pub fn format_age(timestamp: u64, now: u64) -> String {
+    let secs = Duration::from_millis(now.saturating_sub(timestamp)).as_secs();
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+impl From<ResourceEvent> for ResourceEventTableRow {
+    fn from(event: ResourceEvent) -> Self {
+        let now = now_millis();
+        let age = if event.count > 1 {
+            format!(
+                "{} (x{} over {})",
+                format_age(event.last_seen, now),
+                event.count,
+                format_age(event.first_seen, now)
+            )
+        } else {
+            format_age(event.last_seen, now)
+        };
+
+        Self {
+            event_type: if event.reason.is_warning() {
+                "Warning".to_string()
+            } else {
+                "Normal".to_string()
+            },
+            reason: format!("{:?}", event.reason),
+            age,
+            message: event.message,
+        }
+    }
+}
+
+/// Prints the recent events of a resource below its summary, if it has any.
+pub async fn print_resource_events(
+    api_client: &ApiClient,
+    namespace: Namespace,
+    kind: &str,
+    name: String,
+) -> Result<()> {
+    let events = api_client
+        .core()
+        .list_events(
+            namespace,
+            ListEventsParams {
+                kind: kind.to_string(),
+                name,
+            },
+        )
+        .await?;
+
+    if events.is_empty() {
+        return Ok(());
+    }
+
+    let mut table = ResourceEventTable::new();
+    for event in events {
+        table.add_row(event.into());
+    }
+
+    println!();
+    table.print();
+
+    Ok(())
+}
@@ -27,7 +27,7 @@ use ignition::{
 use serde_json::json;
 
 use crate::{
-    build::get_build_plan,
+    build::{env::resolve_build_env, get_build_plan},
     client::get_api_client,
     config::Config,
     ui::message::{message_detail, message_error, message_info, message_warn},
@@ -317,7 +317,7 @@ async fn list_dir(_config: &Config, base_dir: &str, args: &ListDirArgs) -> Resul
 }
 
 async fn dir_build_plan(
-    _config: &Config,
+    config: &Config,
     base_dir: &str,
     args: &DirBuildPlanArgs,
 ) -> Result<DirBuildPlan> {
@@ -330,7 +330,11 @@ async fn dir_build_plan(
         .to_string();
 
     let path = path.join(&rel);
-    let (plan, providers) = get_build_plan(&path).await?;
+    let (plan, providers) = get_build_plan(
+        &path,
+        &resolve_build_env(config.build.as_ref(), BTreeMap::new()),
+    )
+    .await?;
 
     let descriptions = plan.get_phase_info_desc()?;
 
@@ -406,6 +410,10 @@ async fn write_config_to_disk(
             tags: None,
             mode: VolumeMode::Writeable,
             size: "100Mi".to_string(),
+            filesystem: None,
+            encrypted: None,
+            from: None,
+            backup: None,
         }));
     }
 
@@ -428,17 +436,28 @@ async fn write_config_to_disk(
             tags: None,
             image: None,
             build: None,
+            build_env: None,
             resources: MachineResources {
                 cpu: 1,
                 memory: 256,
+                cpu_limit: None,
             },
             command: None,
             depends_on: None,
+            health: None,
             environment: None,
             expose: None,
             restart_policy: None,
             mode: None,
             volumes: None,
+            scratch: None,
+            ephemeral_volumes: None,
+            kernel_params: None,
+            rollout: None,
+            suspend: None,
+            ttl: None,
+            placement: None,
+            network: None,
         };
 
         match app.source {
@@ -490,21 +509,25 @@ async fn write_config_to_disk(
             Some(InitAppSnapshotStrategy::SuspendManually) => Some(MachineMode::Flash {
                 strategy: MachineSnapshotStrategy::Manual,
                 timeout: None,
+                prewarm: None,
             }),
             Some(InitAppSnapshotStrategy::SuspendBeforeStart) => Some(MachineMode::Flash {
                 strategy: MachineSnapshotStrategy::WaitForUserSpaceReady,
                 timeout: None,
+                prewarm: None,
             }),
             Some(InitAppSnapshotStrategy::SuspendAfterListenOnAnyPort) => {
                 Some(MachineMode::Flash {
                     strategy: MachineSnapshotStrategy::WaitForFirstListen,
                     timeout: None,
+                    prewarm: None,
                 })
             }
             Some(InitAppSnapshotStrategy::SuspendAfterListenOnPort(port)) => {
                 Some(MachineMode::Flash {
                     strategy: MachineSnapshotStrategy::WaitForListenOnPort(port),
                     timeout: None,
+                    prewarm: None,
                 })
             }
         };
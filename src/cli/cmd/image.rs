@@ -0,0 +1,220 @@
+use std::path::PathBuf;
+
+use anyhow::{Result, anyhow, bail};
+use clap::Args;
+use futures_util::{SinkExt, StreamExt};
+use ignition::{
+    resources::core::{ImageInspectParams, ImageInspection, ImageLoadParams, ImageLoadResult},
+    utils::size::format_human_readable_size,
+};
+use meta::{summary, table};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tungstenite::Message;
+
+use crate::{
+    client::get_api_client,
+    config::Config,
+    ui::message::{message_info, message_progress, message_progress_end},
+};
+
+/// Size of the messages the archive is streamed in.
+const UPLOAD_CHUNK_SIZE: usize = 1024 * 1024;
+
+#[summary]
+pub struct ImageSummary {
+    #[field(name = "reference")]
+    reference: String,
+
+    #[field(name = "digest")]
+    digest: String,
+
+    #[field(name = "entrypoint")]
+    entrypoint: Vec<String>,
+
+    #[field(name = "cmd")]
+    cmd: Vec<String>,
+
+    #[field(name = "env")]
+    env: Vec<String>,
+
+    #[field(name = "exposed ports")]
+    exposed_ports: Vec<String>,
+
+    #[field(name = "user")]
+    user: Option<String>,
+
+    #[field(name = "working dir")]
+    working_dir: Option<String>,
+
+    #[field(name = "labels")]
+    labels: Vec<String>,
+
+    #[field(name = "size", cell_style = important)]
+    size: String,
+
+    #[field(name = "pulled")]
+    pulled: String,
+
+    #[field(name = "image id (internal)")]
+    image_id: Option<String>,
+
+    #[field(name = "volume id (internal)")]
+    volume_id: Option<String>,
+
+    #[field(name = "volume size (internal)")]
+    volume_size: Option<String>,
+}
+
+#[table]
+pub struct ImageLayerTable {
+    #[field(name = "digest")]
+    digest: String,
+
+    #[field(name = "media type")]
+    media_type: String,
+
+    #[field(name = "size")]
+    size: String,
+}
+
+impl From<&ImageInspection> for ImageSummary {
+    fn from(inspection: &ImageInspection) -> Self {
+        let size = inspection
+            .layers
+            .iter()
+            .map(|layer| layer.size_bytes)
+            .sum::<u64>();
+
+        Self {
+            reference: inspection.reference.clone(),
+            digest: inspection.digest.clone(),
+            entrypoint: inspection.entrypoint.clone().unwrap_or_default(),
+            cmd: inspection.cmd.clone().unwrap_or_default(),
+            env: inspection.env.clone(),
+            exposed_ports: inspection.exposed_ports.clone(),
+            user: inspection.user.clone(),
+            working_dir: inspection.working_dir.clone(),
+            labels: inspection
+                .labels
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect(),
+            size: format_human_readable_size(size),
+            pulled: if inspection.image_id.is_some() {
+                "yes".to_string()
+            } else {
+                "no".to_string()
+            },
+            image_id: inspection.image_id.clone(),
+            volume_id: inspection.volume_id.clone(),
+            volume_size: inspection.volume_size_bytes.map(format_human_readable_size),
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct ImageInspectArgs {
+    /// Reference of the image (eg. nginx:latest)
+    reference: String,
+}
+
+#[derive(Args)]
+pub struct ImageLoadArgs {
+    /// Archive created by `docker save` or holding an OCI layout, read from stdin when omitted
+    #[arg(long = "input", short = 'i')]
+    input: Option<PathBuf>,
+
+    /// Reference machines use to run the image (eg. my-app:dev)
+    reference: String,
+}
+
+pub async fn run_image_inspect(config: &Config, args: ImageInspectArgs) -> Result<()> {
+    let api_client = get_api_client(config.try_into()?);
+    let inspection = api_client
+        .core()
+        .inspect_image(ImageInspectParams {
+            reference: args.reference,
+        })
+        .await?;
+
+    ImageSummary::from(&inspection).print();
+
+    let mut table = ImageLayerTable::new();
+    for layer in inspection.layers {
+        table.add_row(ImageLayerTableRow {
+            digest: layer.digest,
+            media_type: layer.media_type,
+            size: format_human_readable_size(layer.size_bytes),
+        });
+    }
+
+    println!();
+    table.print();
+
+    Ok(())
+}
+
+pub async fn run_image_load(config: &Config, args: ImageLoadArgs) -> Result<()> {
+    let reader: Box<dyn AsyncRead + Unpin> = match &args.input {
+        Some(input) => Box::new(tokio::fs::File::open(input).await?),
+        None => Box::new(tokio::io::stdin()),
+    };
+
+    let api_client = get_api_client(config.try_into()?);
+    let ws_stream = api_client
+        .core()
+        .load_image(ImageLoadParams {
+            reference: args.reference,
+        })
+        .await?;
+    let (mut ws_write, mut ws_read) = ws_stream.split();
+
+    let uploaded = upload(reader, &mut ws_write).await?;
+    message_progress_end();
+    message_info(format!(
+        "Uploaded {:.1} MB, loading the image...",
+        uploaded as f64 / 1024.0 / 1024.0
+    ));
+
+    let result = loop {
+        match ws_read.next().await {
+            Some(Ok(Message::Text(text))) => break text.to_string(),
+            Some(Ok(_)) => {}
+            Some(Err(e)) => return Err(e.into()),
+            None => bail!("connection closed before the image was loaded"),
+        }
+    };
+    let result: ImageLoadResult = serde_json::from_str(&result).map_err(|_| anyhow!(result))?;
+
+    message_info(format!("Loaded {} ({})", result.reference, result.digest));
+
+    Ok(())
+}
+
+/// Streams the archive, then the empty message marking its end. Returns the bytes sent.
+async fn upload(
+    mut reader: impl AsyncRead + Unpin,
+    ws_write: &mut (impl SinkExt<Message, Error = tungstenite::Error> + Unpin),
+) -> Result<u64> {
+    let mut uploaded = 0u64;
+    let mut buffer = vec![0u8; UPLOAD_CHUNK_SIZE];
+    loop {
+        let read = reader.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+
+        ws_write
+            .send(Message::Binary(buffer[..read].to_vec().into()))
+            .await?;
+        uploaded += read as u64;
+        message_progress(format!(
+            "Uploading... {:.1} MB",
+            uploaded as f64 / 1024.0 / 1024.0
+        ));
+    }
+
+    ws_write.send(Message::Binary(Vec::new().into())).await?;
+
+    Ok(uploaded)
+}
@@ -1,28 +1,41 @@
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{
+    io::stdout,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use anyhow::Result;
+use anyhow::{Result, bail};
 use chrono;
-use clap::Args;
+use clap::{Args, ValueEnum};
 use crossterm::{
+    cursor::MoveTo,
     event::{self, Event, KeyCode, KeyEvent},
-    terminal::{disable_raw_mode, enable_raw_mode},
+    execute,
+    terminal::{Clear, ClearType, disable_raw_mode, enable_raw_mode},
 };
 use ignition::{
     constants::{DEFAULT_NAMESPACE, DEFAULT_SUSPEND_TIMEOUT_SECS},
     resources::{
-        core::{ExecParams, LogStreamParams, LogStreamTarget},
+        core::{
+            ConsoleParams, CoreDump, DownloadCoreDumpParams, ExecParams, ExecSession, LogFilter,
+            LogStreamParams, LogStreamTarget, MachineUsage, MachineUsageSample,
+        },
         machine::{
-            MachineLatest, MachineMode, MachinePhase, MachineSnapshotStrategy, MachineStatus,
+            MachineLatest, MachineMode, MachinePhase, MachineRolloutStrategy,
+            MachineSnapshotStrategy, MachineStatus,
         },
         metadata::Namespace,
     },
+    utils::size::format_human_readable_size,
 };
 use meta::{summary, table};
 use ordinal::Ordinal;
 
 use crate::{
     client::{MachineClientExt, get_api_client},
-    cmd::{DeleteNamespacedArgs, GetNamespacedArgs, ListNamespacedArgs},
+    cmd::{
+        DeleteNamespacedArgs, GetNamespacedArgs, ListNamespacedArgs, event::print_resource_events,
+    },
     config::Config,
     ui::message::{message_info, message_log_stderr, message_log_stdout, message_warn},
 };
@@ -49,6 +62,22 @@ pub struct MachineLogsArgs {
     #[arg(long = "follow", short = 'f')]
     follow: bool,
 
+    /// Only show lines written to this stream (stdout, stderr)
+    #[arg(long = "stream")]
+    stream: Option<String>,
+
+    /// Only show lines containing this text
+    #[arg(long = "grep", short = 'g')]
+    grep: Option<String>,
+
+    /// Only show lines matching this regular expression
+    #[arg(long = "regex")]
+    regex: Option<String>,
+
+    /// Only show lines at this level or above (debug, info, warn, error)
+    #[arg(long = "level", short = 'l')]
+    level: Option<String>,
+
     /// Name of the machine to fetch logs for
     name: String,
 }
@@ -75,16 +104,104 @@ pub struct MachineExecArgs {
     command: Vec<String>,
 }
 
+#[derive(Clone, Debug, Args)]
+pub struct MachineConsoleArgs {
+    /// Namespace of the machine (short: --ns)
+    #[arg(long = "namespace", alias = "ns")]
+    namespace: Option<String>,
+
+    /// Name of the machine to attach to
+    name: String,
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct MachineExecHistoryArgs {
+    /// Namespace of the machine (short: --ns) [default: all namespaces]
+    #[arg(long = "namespace", alias = "ns")]
+    namespace: Option<String>,
+
+    /// Maximum number of sessions to show, most recent first
+    #[arg(long = "limit", short = 'l', default_value_t = 50)]
+    limit: usize,
+
+    /// Only show sessions for this machine
+    name: Option<String>,
+}
+
 #[derive(Clone, Debug, Args)]
 pub struct RestartNamespacedArgs {
     /// Namespace of the machine (short: --ns)
     #[arg(long = "namespace", alias = "ns")]
     namespace: Option<String>,
 
+    /// Run the entrypoint under a tracer for the next boot only, output goes to the machine logs
+    #[arg(long = "trace")]
+    trace: Option<MachineDebugTraceArg>,
+
     /// Name of the machine to restart
     name: String,
 }
 
+#[derive(Clone, Debug, Args)]
+pub struct MachineSuspendArgs {
+    /// Namespace of the machine (short: --ns)
+    #[arg(long = "namespace", alias = "ns")]
+    namespace: Option<String>,
+
+    /// Name of the machine to suspend
+    name: String,
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct MachineResumeArgs {
+    /// Namespace of the machine (short: --ns)
+    #[arg(long = "namespace", alias = "ns")]
+    namespace: Option<String>,
+
+    /// Name of the machine to resume
+    name: String,
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct MachineDebugCoresArgs {
+    /// Namespace of the machine (short: --ns)
+    #[arg(long = "namespace", alias = "ns")]
+    namespace: Option<String>,
+
+    /// Download the core dump with this id instead of listing them
+    #[arg(long = "download", short = 'd')]
+    download: Option<String>,
+
+    /// Where to write the downloaded core dump, defaults to ./<machine>.<id>.core
+    #[arg(long = "output", short = 'o')]
+    output: Option<PathBuf>,
+
+    /// Name of the machine
+    name: String,
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct MachineTopArgs {
+    /// Namespace of the machine (short: --ns)
+    #[arg(long = "namespace", alias = "ns")]
+    namespace: Option<String>,
+
+    /// Refresh the usage until interrupted
+    #[arg(long = "watch", short = 'w')]
+    watch: bool,
+
+    /// Name of the machine
+    name: String,
+}
+
+#[derive(Clone, Debug, ValueEnum)]
+pub enum MachineDebugTraceArg {
+    #[value(name = "strace")]
+    Strace,
+    #[value(name = "ltrace")]
+    Ltrace,
+}
+
 #[table]
 pub struct MachineTable {
     #[field(name = "name")]
@@ -112,6 +229,48 @@ pub struct MachineTable {
     last_boot_time: Option<String>,
 }
 
+#[table]
+pub struct ExecSessionTable {
+    #[field(name = "started")]
+    started: String,
+
+    #[field(name = "namespace")]
+    namespace: String,
+
+    #[field(name = "machine")]
+    machine: String,
+
+    #[field(name = "user")]
+    user: String,
+
+    #[field(name = "command", max_width = 50)]
+    command: String,
+
+    #[field(name = "duration")]
+    duration: String,
+
+    #[field(name = "bytes in")]
+    bytes_in: String,
+
+    #[field(name = "bytes out")]
+    bytes_out: String,
+
+    #[field(name = "status", cell_style = important)]
+    status: String,
+}
+
+#[table]
+pub struct CoreDumpTable {
+    #[field(name = "id")]
+    id: String,
+
+    #[field(name = "created")]
+    created: String,
+
+    #[field(name = "size")]
+    size: String,
+}
+
 #[summary]
 pub struct MachineSummary {
     #[field(name = "name")]
@@ -123,6 +282,9 @@ pub struct MachineSummary {
     #[field(name = "tags")]
     tags: Vec<String>,
 
+    #[field(name = "conditions")]
+    conditions: Vec<String>,
+
     #[field(name = "status", cell_style = important)]
     status: String,
 
@@ -135,18 +297,51 @@ pub struct MachineSummary {
     #[field(name = "suspend timeout")]
     suspend_timeout: Option<String>,
 
+    #[field(name = "prewarmed clones")]
+    prewarm: Vec<String>,
+
     #[field(name = "restart policy")]
     restart_policy: Option<String>,
 
+    #[field(name = "rollout")]
+    rollout: Option<String>,
+
+    #[field(name = "rolling out on")]
+    rollout_surge: Option<String>,
+
+    #[field(name = "suspend after idle")]
+    suspend_after_idle: Option<String>,
+
+    #[field(name = "expires")]
+    expires: Option<String>,
+
+    #[field(name = "placement")]
+    placement: Vec<String>,
+
+    #[field(name = "node")]
+    node: Option<String>,
+
+    #[field(name = "bandwidth")]
+    bandwidth: Option<String>,
+
     #[field(name = "internal ip")]
     internal_ip: Option<String>,
 
     #[field(name = "image")]
     image: String,
 
+    #[field(name = "image labels")]
+    image_labels: Vec<String>,
+
+    #[field(name = "image annotations")]
+    image_annotations: Vec<String>,
+
     #[field(name = "cpus")]
     cpu: String,
 
+    #[field(name = "cpu limit")]
+    cpu_limit: Option<String>,
+
     #[field(name = "memory")]
     memory: String,
 
@@ -156,6 +351,9 @@ pub struct MachineSummary {
     #[field(name = "command")]
     cmd: Option<String>,
 
+    #[field(name = "kernel params")]
+    kernel_params: Vec<String>,
+
     #[field(name = "volumes")]
     volumes: Vec<String>,
 
@@ -171,6 +369,9 @@ pub struct MachineSummary {
     #[field(name = "last exit code")]
     last_exit_code: Option<String>,
 
+    #[field(name = "last core dump")]
+    last_core_dump: Option<String>,
+
     #[field(name = "last restarting time")]
     last_restarting_time: Option<String>,
 
@@ -187,8 +388,26 @@ pub struct MachineSummary {
     hypervisor_tap_device: Option<String>,
 }
 
+/// When a resource with a `ttl` is deleted, once its TTL started counting.
+pub fn format_expiry(ttl: Option<&str>, started_at: Option<u64>) -> Option<String> {
+    let ttl = humantime::parse_duration(ttl?).ok()?;
+    let expires_at = started_at? + ttl.as_millis() as u64;
+
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    if expires_at <= now_ms {
+        return Some("now".to_string());
+    }
+
+    let remaining = Duration::from_secs((expires_at - now_ms) / 1_000);
+    Some(format!("in {}", humantime::format_duration(remaining)))
+}
+
 impl From<(MachineLatest, MachineStatus)> for MachineSummary {
     fn from((machine, status): (MachineLatest, MachineStatus)) -> Self {
+        let ephemeral_volumes = machine.ephemeral_volumes();
         let env = machine
             .environment
             .unwrap_or_default()
@@ -196,7 +415,7 @@ impl From<(MachineLatest, MachineStatus)> for MachineSummary {
             .map(|(k, v)| format!("{k} = {v}"))
             .collect();
 
-        let volumes: Vec<_> = machine
+        let mut volumes: Vec<_> = machine
             .volumes
             .unwrap_or_default()
             .into_iter()
@@ -209,6 +428,12 @@ impl From<(MachineLatest, MachineStatus)> for MachineSummary {
                 format!("{}/{} → {}", namespace, v.name, v.path)
             })
             .collect();
+        for ephemeral in ephemeral_volumes {
+            volumes.push(format!(
+                "ephemeral ({} MiB) → {}",
+                ephemeral.size_mib, ephemeral.path
+            ));
+        }
 
         let mode = match machine.mode {
             None | Some(MachineMode::Regular) => "regular".to_string(),
@@ -219,6 +444,7 @@ impl From<(MachineLatest, MachineStatus)> for MachineSummary {
             Some(MachineMode::Flash {
                 strategy: MachineSnapshotStrategy::Manual,
                 timeout,
+                ..
             }) => (
                 Some("manual".to_string()),
                 Some(timeout.unwrap_or(DEFAULT_SUSPEND_TIMEOUT_SECS)),
@@ -226,6 +452,7 @@ impl From<(MachineLatest, MachineStatus)> for MachineSummary {
             Some(MachineMode::Flash {
                 strategy: MachineSnapshotStrategy::WaitForUserSpaceReady,
                 timeout,
+                ..
             }) => (
                 Some("user-space ready".to_string()),
                 Some(timeout.unwrap_or(DEFAULT_SUSPEND_TIMEOUT_SECS)),
@@ -233,6 +460,7 @@ impl From<(MachineLatest, MachineStatus)> for MachineSummary {
             Some(MachineMode::Flash {
                 strategy: MachineSnapshotStrategy::WaitForFirstListen,
                 timeout,
+                ..
             }) => (
                 Some("first listen".to_string()),
                 Some(timeout.unwrap_or(DEFAULT_SUSPEND_TIMEOUT_SECS)),
@@ -240,6 +468,7 @@ impl From<(MachineLatest, MachineStatus)> for MachineSummary {
             Some(MachineMode::Flash {
                 strategy: MachineSnapshotStrategy::WaitForNthListen(n),
                 timeout,
+                ..
             }) => (
                 Some(format!("{} listen", Ordinal(n))),
                 Some(timeout.unwrap_or(DEFAULT_SUSPEND_TIMEOUT_SECS)),
@@ -247,6 +476,7 @@ impl From<(MachineLatest, MachineStatus)> for MachineSummary {
             Some(MachineMode::Flash {
                 strategy: MachineSnapshotStrategy::WaitForListenOnPort(port),
                 timeout,
+                ..
             }) => (
                 Some(format!("listen on port {port}")),
                 Some(timeout.unwrap_or(DEFAULT_SUSPEND_TIMEOUT_SECS)),
@@ -260,6 +490,8 @@ impl From<(MachineLatest, MachineStatus)> for MachineSummary {
             duration.to_string()
         });
 
+        let prewarm = status.prewarm_pool.clone().unwrap_or_default();
+
         let depends_on = machine
             .depends_on
             .unwrap_or_default()
@@ -290,22 +522,63 @@ impl From<(MachineLatest, MachineStatus)> for MachineSummary {
             name: machine.name,
             namespace: machine.namespace,
             tags: machine.tags.unwrap_or_default(),
+            conditions: status.conditions.iter().map(|c| c.to_string()).collect(),
             mode,
             snapshot_strategy,
             restart_policy: machine.restart_policy.map(|r| r.to_string()),
+            rollout: machine.rollout.map(|r| match r.strategy {
+                MachineRolloutStrategy::Recreate => "recreate".to_string(),
+                MachineRolloutStrategy::Rolling => "rolling".to_string(),
+            }),
+            rollout_surge: status.rollout_surge.clone(),
+            suspend_after_idle: machine.suspend.map(|policy| policy.after_idle),
+            expires: format_expiry(machine.ttl.as_deref(), status.ttl_started_at),
+            placement: machine
+                .placement
+                .and_then(|placement| placement.constraints)
+                .unwrap_or_default(),
+            node: status.node.clone(),
+            bandwidth: machine
+                .network
+                .and_then(|network| network.bandwidth)
+                .map(|bandwidth| {
+                    format!(
+                        "ingress {}, egress {}",
+                        bandwidth.ingress.as_deref().unwrap_or("unlimited"),
+                        bandwidth.egress.as_deref().unwrap_or("unlimited")
+                    )
+                }),
             internal_ip: status.machine_ip.clone(),
             status: status.phase.to_string(),
             image: status
                 .image_resolved_reference
                 .or(machine.image)
                 .unwrap_or_default(),
+            image_labels: status
+                .image_labels
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(k, v)| format!("{k} = {v}"))
+                .collect(),
+            image_annotations: status
+                .image_annotations
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(k, v)| format!("{k} = {v}"))
+                .collect(),
             cpu: machine.resources.cpu.to_string(),
+            cpu_limit: machine
+                .resources
+                .cpu_limit
+                .map(|millicores| format!("{} millicores", millicores)),
             memory: format!("{} MiB", machine.resources.memory),
             env,
             cmd: machine.command.clone().map(|c| c.join(" ")),
+            kernel_params: machine.kernel_params.clone().unwrap_or_default(),
             volumes,
             depends_on,
             suspend_timeout: timeout,
+            prewarm,
             hypervisor_machine_id: status.machine_id.clone(),
             hypervisor_root_volume_id: status.machine_image_volume_id.clone(),
             hypervisor_tap_device: status.machine_tap.clone(),
@@ -322,6 +595,7 @@ impl From<(MachineLatest, MachineStatus)> for MachineSummary {
             last_restarting_time,
             restart_count: status.restart_count.map(|c| c.to_string()),
             last_exit_code: status.last_exit_code.map(|c| c.to_string()),
+            last_core_dump: status.last_core_dump.clone(),
         }
     }
 }
@@ -336,6 +610,9 @@ impl From<(MachineLatest, MachineStatus)> for MachineTableRow {
         let status_str = match (status.phase, status.last_exit_code) {
             (MachinePhase::Stopped, Some(code)) => format!("stopped (exit: {})", code),
             (MachinePhase::Error { message }, _) => format!("error ({})", message),
+            (MachinePhase::Suspended, _) if status.user_suspended == Some(true) => {
+                "suspended (by user)".to_string()
+            }
             (phase, _) => phase.to_string(),
         };
 
@@ -359,6 +636,58 @@ impl From<(MachineLatest, MachineStatus)> for MachineTableRow {
     }
 }
 
+impl From<CoreDump> for CoreDumpTableRow {
+    fn from(core_dump: CoreDump) -> Self {
+        let created = chrono::DateTime::from_timestamp_millis(core_dump.created_at as i64)
+            .unwrap_or_default()
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string();
+
+        Self {
+            id: core_dump.id,
+            created,
+            size: format!("{:.1} MiB", core_dump.size as f64 / (1024.0 * 1024.0)),
+        }
+    }
+}
+
+impl From<ExecSession> for ExecSessionTableRow {
+    fn from(session: ExecSession) -> Self {
+        let started = chrono::DateTime::from_timestamp_millis(session.started_at as i64)
+            .unwrap_or_default()
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string();
+
+        let duration = session.ended_at.map(|ended_at| {
+            let duration = Duration::from_secs(ended_at.saturating_sub(session.started_at) / 1000);
+            humantime::format_duration(duration).to_string()
+        });
+
+        let mut command = session.command;
+        if session.tty {
+            command = format!("[tty] {command}");
+        }
+
+        let status = match (session.ended_at, session.error) {
+            (_, Some(error)) => format!("failed ({error})"),
+            (Some(_), None) => "ended".to_string(),
+            (None, None) => "active".to_string(),
+        };
+
+        Self {
+            started,
+            namespace: session.namespace,
+            machine: session.machine_name,
+            user: session.sub,
+            command,
+            duration: duration.unwrap_or("-".to_string()),
+            bytes_in: session.bytes_in.to_string(),
+            bytes_out: session.bytes_out.to_string(),
+            status,
+        }
+    }
+}
+
 pub async fn run_machine_list(config: &Config, args: ListNamespacedArgs) -> Result<()> {
     let api_client = get_api_client(config.try_into()?);
     let machines = api_client.machine().list(args.into()).await?;
@@ -378,43 +707,95 @@ pub async fn run_machine_get(config: &Config, args: GetNamespacedArgs) -> Result
     let api_client = get_api_client(config.try_into()?);
     let (machine, status) = api_client
         .machine()
-        .get(args.clone().into(), args.name)
+        .get(args.clone().into(), args.name.clone())
         .await?;
 
     let summary = MachineSummary::from((machine, status));
     summary.print();
 
+    print_resource_events(&api_client, args.clone().into(), "machine", args.name).await?;
+
     Ok(())
 }
 
 pub async fn run_machine_get_logs(config: &Config, args: MachineLogsArgs) -> Result<()> {
+    let name = args.name;
+
+    print_logs(
+        config,
+        LogsOptions {
+            namespace: args.namespace,
+            since: args.since,
+            show_timestamps: args.show_timestamps,
+            show_elapsed: args.show_elapsed,
+            follow: args.follow,
+            stream: args.stream,
+            grep: args.grep,
+            regex: args.regex,
+            level: args.level,
+        },
+        |start_ts_ns, end_ts_ns, filter| LogStreamParams::Machine {
+            machine_name: name,
+            start_ts_ns,
+            end_ts_ns,
+            filter,
+        },
+    )
+    .await
+}
+
+/// Flags shared by every `logs` command.
+pub struct LogsOptions {
+    pub namespace: Option<String>,
+    pub since: Option<String>,
+    pub show_timestamps: bool,
+    pub show_elapsed: bool,
+    pub follow: bool,
+    pub stream: Option<String>,
+    pub grep: Option<String>,
+    pub regex: Option<String>,
+    pub level: Option<String>,
+}
+
+pub async fn print_logs(
+    config: &Config,
+    options: LogsOptions,
+    params: impl FnOnce(Option<String>, Option<String>, LogFilter) -> LogStreamParams,
+) -> Result<()> {
     let api_client = get_api_client(config.try_into()?);
 
-    let namespace = Namespace::from_value_or_default(args.namespace);
+    let namespace = Namespace::from_value_or_default(options.namespace);
 
-    if args.follow && args.since.is_some() {
+    if options.follow && options.since.is_some() {
         message_warn("Cannot use --follow and --since together");
         return Ok(());
     }
 
-    if args.follow && args.show_elapsed {
+    if options.follow && options.show_elapsed {
         message_warn("Cannot use --follow and --elapsed together");
         return Ok(());
     }
 
+    let filter = LogFilter {
+        stream: options.stream.map(|s| s.parse()).transpose()?,
+        contains: options.grep,
+        regex: options.regex,
+        level: options.level.map(|s| s.parse()).transpose()?,
+    };
+
     let now_ns = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64;
 
-    let since = args.since.unwrap_or("1d".to_string());
+    let since = options.since.unwrap_or("1d".to_string());
     let since = humantime::parse_duration(&since)?;
     let since_ns = since.as_nanos() as u64;
 
-    let start_ts = if args.follow {
+    let start_ts = if options.follow {
         None
     } else {
         Some((now_ns - since_ns).to_string())
     };
 
-    let end_ts = if args.follow {
+    let end_ts = if options.follow {
         None
     } else {
         Some(now_ns.to_string())
@@ -422,18 +803,11 @@ pub async fn run_machine_get_logs(config: &Config, args: MachineLogsArgs) -> Res
 
     let mut stream = api_client
         .core()
-        .stream_logs(
-            namespace,
-            LogStreamParams::Machine {
-                machine_name: args.name,
-                start_ts_ns: start_ts,
-                end_ts_ns: end_ts,
-            },
-        )
+        .stream_logs(namespace, params(start_ts, end_ts, filter))
         .await?;
 
     while let Some(result) = stream.next().await {
-        let timestamp = if args.show_timestamps {
+        let timestamp = if options.show_timestamps {
             let secs = result.timestamp / 1_000_000_000;
             let nanos = result.timestamp % 1_000_000_000;
 
@@ -441,7 +815,7 @@ pub async fn run_machine_get_logs(config: &Config, args: MachineLogsArgs) -> Res
                 chrono::DateTime::from_timestamp(secs as i64, nanos as u32).unwrap_or_default();
 
             Some(dt.format("%Y-%m-%d %H:%M:%S%.3f").to_string())
-        } else if args.show_elapsed {
+        } else if options.show_elapsed {
             let duration = Duration::from_secs((now_ns - result.timestamp) as u64 / 1_000_000_000);
             let duration = humantime::format_duration(duration);
             Some(format!("{} ago", duration))
@@ -458,6 +832,95 @@ pub async fn run_machine_get_logs(config: &Config, args: MachineLogsArgs) -> Res
     Ok(())
 }
 
+/// Ctrl-], like telnet and virsh.
+const CONSOLE_DETACH_KEY: u8 = 0x1d;
+
+pub async fn run_machine_console(config: &Config, args: MachineConsoleArgs) -> Result<()> {
+    use futures_util::{SinkExt, StreamExt};
+    use std::io::Write;
+    use tokio::io::AsyncReadExt;
+    use tungstenite::Message;
+
+    let api_client = get_api_client(config.try_into()?);
+    let ws_stream = api_client
+        .core()
+        .console(
+            Namespace::from_value_or_default(args.namespace),
+            ConsoleParams {
+                machine_name: args.name.clone(),
+            },
+        )
+        .await?;
+    let (mut ws_write, mut ws_read) = ws_stream.split();
+
+    message_info(format!(
+        "Attached to the console of {}, press Ctrl-] to detach",
+        args.name
+    ));
+
+    enable_raw_mode()?;
+    let raw_mode = scopeguard::guard((), |_| {
+        let _ = disable_raw_mode();
+    });
+
+    let stdin_to_ws = async {
+        let mut stdin = tokio::io::stdin();
+        let mut buf = [0; 1024];
+        loop {
+            let n = match stdin.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+
+            let input = &buf[..n];
+            let detach = input.iter().position(|b| *b == CONSOLE_DETACH_KEY);
+            let input = &input[..detach.unwrap_or(n)];
+
+            if !input.is_empty()
+                && ws_write
+                    .send(Message::Binary(input.to_vec().into()))
+                    .await
+                    .is_err()
+            {
+                break;
+            }
+            if detach.is_some() {
+                break;
+            }
+        }
+
+        let _ = ws_write.send(Message::Close(None)).await;
+    };
+
+    let ws_to_stdout = async {
+        while let Some(msg) = ws_read.next().await {
+            let data = match msg {
+                Ok(Message::Binary(data)) => data.to_vec(),
+                Ok(Message::Text(text)) => text.as_bytes().to_vec(),
+                Ok(Message::Close(_)) | Err(_) => break,
+                _ => continue,
+            };
+
+            let mut stdout = stdout();
+            if stdout.write_all(&data).is_err() || stdout.flush().is_err() {
+                break;
+            }
+        }
+    };
+
+    tokio::select! {
+        _ = stdin_to_ws => {},
+        _ = ws_to_stdout => {},
+    }
+
+    drop(raw_mode);
+    println!();
+    message_info("Detached from the console");
+
+    // stdin is read by a blocking thread that only returns on the next key press
+    std::process::exit(0);
+}
+
 pub async fn run_machine_exec(config: &Config, args: MachineExecArgs) -> Result<()> {
     let cmd = args.command.join(" ");
     let stdin_enabled = args.stdin;
@@ -663,6 +1126,225 @@ pub async fn run_machine_exec(config: &Config, args: MachineExecArgs) -> Result<
     std::process::exit(0);
 }
 
+pub async fn run_machine_exec_history(config: &Config, args: MachineExecHistoryArgs) -> Result<()> {
+    let api_client = get_api_client(config.try_into()?);
+    let sessions = api_client
+        .core()
+        .exec_history(Namespace::from_value(args.namespace))
+        .await?;
+
+    let mut table = ExecSessionTable::new();
+
+    for session in sessions
+        .into_iter()
+        .rev()
+        .filter(|s| {
+            args.name
+                .as_ref()
+                .is_none_or(|name| &s.machine_name == name)
+        })
+        .take(args.limit)
+    {
+        table.add_row(session.into());
+    }
+
+    table.print();
+
+    Ok(())
+}
+
+const SPARKLINE_TICKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+const MACHINE_TOP_REFRESH: Duration = Duration::from_secs(5);
+
+fn sparkline(values: impl Iterator<Item = f64>, max: f64) -> String {
+    values
+        .map(|value| {
+            let level = if max > 0.0 {
+                (value / max).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            SPARKLINE_TICKS[(level * (SPARKLINE_TICKS.len() - 1) as f64).round() as usize]
+        })
+        .collect()
+}
+
+fn format_mib(bytes: f64) -> String {
+    format!("{:.1} MiB", bytes / (1024.0 * 1024.0))
+}
+
+fn format_rate(bytes_per_sec: f64) -> String {
+    format!("{}/s", format_human_readable_size(bytes_per_sec as u64))
+}
+
+fn print_machine_top(usage: &MachineUsage) {
+    let (Some(current), Some(average)) = (&usage.current, &usage.average) else {
+        message_warn(format!("Machine '{}' hasn't been sampled yet", usage.name));
+        return;
+    };
+
+    let print_row = |label: &str,
+                     value: fn(&MachineUsageSample) -> f64,
+                     max: Option<f64>,
+                     format: &dyn Fn(f64) -> String| {
+        let max = max.unwrap_or_else(|| usage.samples.iter().map(value).fold(0.0, f64::max));
+        println!(
+            "{:<9}{}  {} (avg {})",
+            label,
+            sparkline(usage.samples.iter().map(value), max),
+            format(value(current)),
+            format(value(average))
+        );
+    };
+
+    print_row(
+        "cpu",
+        |sample| sample.cpu_utilization,
+        Some(1.0),
+        &|value| format!("{:.1}%", value * 100.0),
+    );
+    print_row(
+        "memory",
+        |sample| sample.memory_resident_bytes as f64,
+        Some(usage.memory_total_bytes as f64),
+        &|value| {
+            format!(
+                "{} / {}",
+                format_mib(value),
+                format_mib(usage.memory_total_bytes as f64)
+            )
+        },
+    );
+    print_row(
+        "net rx",
+        |sample| sample.network_rx_bytes_per_sec,
+        None,
+        &format_rate,
+    );
+    print_row(
+        "net tx",
+        |sample| sample.network_tx_bytes_per_sec,
+        None,
+        &format_rate,
+    );
+    print_row(
+        "disk rd",
+        |sample| sample.disk_read_bytes_per_sec,
+        None,
+        &format_rate,
+    );
+    print_row(
+        "disk wr",
+        |sample| sample.disk_write_bytes_per_sec,
+        None,
+        &format_rate,
+    );
+    println!("averages over the last {}s", usage.window_secs);
+
+    // what the guest exported to the metrics store, when there's one
+    let (Some(cpu), Some(memory)) = (usage.cpu_history.last(), usage.memory_used_history.last())
+    else {
+        return;
+    };
+
+    println!();
+    println!("reported by the guest over the last 15 minutes");
+    println!(
+        "{:<9}{}  {:.1}%",
+        "cpu",
+        sparkline(usage.cpu_history.iter().map(|sample| sample.value), 1.0),
+        cpu.value * 100.0
+    );
+    println!(
+        "{:<9}{}  {} / {}",
+        "memory",
+        sparkline(
+            usage.memory_used_history.iter().map(|sample| sample.value),
+            usage.memory_total_bytes as f64
+        ),
+        format_mib(memory.value),
+        format_mib(usage.memory_total_bytes as f64)
+    );
+}
+
+pub async fn run_machine_top(config: &Config, args: MachineTopArgs) -> Result<()> {
+    let api_client = get_api_client(config.try_into()?);
+
+    loop {
+        let usage = api_client
+            .core()
+            .machine_usage(
+                Namespace::from_value_or_default(args.namespace.clone()),
+                &args.name,
+            )
+            .await?;
+
+        if args.watch {
+            execute!(stdout(), Clear(ClearType::All), MoveTo(0, 0))?;
+        }
+        print_machine_top(&usage);
+
+        if !args.watch {
+            return Ok(());
+        }
+        tokio::time::sleep(MACHINE_TOP_REFRESH).await;
+    }
+}
+
+pub async fn run_machine_debug_cores(config: &Config, args: MachineDebugCoresArgs) -> Result<()> {
+    let api_client = get_api_client(config.try_into()?);
+
+    let Some(id) = args.download else {
+        let core_dumps = api_client
+            .core()
+            .list_core_dumps(Namespace::from_value_or_default(args.namespace), &args.name)
+            .await?;
+
+        let mut table = CoreDumpTable::new();
+        for core_dump in core_dumps {
+            table.add_row(core_dump.into());
+        }
+        table.print();
+
+        return Ok(());
+    };
+
+    use futures_util::StreamExt;
+    use tokio::io::AsyncWriteExt;
+    use tungstenite::Message;
+
+    let mut ws_stream = api_client
+        .core()
+        .download_core_dump(DownloadCoreDumpParams {
+            namespace: args.namespace,
+            machine_name: args.name.clone(),
+            id: id.clone(),
+        })
+        .await?;
+
+    let output = args
+        .output
+        .unwrap_or_else(|| PathBuf::from(format!("{}.{}.core", args.name, id)));
+    let mut file = tokio::fs::File::create(&output).await?;
+
+    // chunks of the core file, then the empty message marking its end
+    loop {
+        match ws_stream.next().await {
+            Some(Ok(Message::Binary(data))) if data.is_empty() => break,
+            Some(Ok(Message::Binary(data))) => file.write_all(&data).await?,
+            Some(Ok(Message::Text(text))) => bail!("{}", text),
+            Some(Ok(_)) => {}
+            Some(Err(e)) => return Err(e.into()),
+            None => bail!("connection closed before the end of the core dump"),
+        }
+    }
+    file.flush().await?;
+
+    message_info(format!("Core dump written to {}", output.display()));
+
+    Ok(())
+}
+
 pub async fn run_machine_delete(config: &Config, args: DeleteNamespacedArgs) -> Result<()> {
     let api_client = get_api_client(config.try_into()?);
     if !args.confirm {
@@ -688,14 +1370,82 @@ pub async fn run_machine_restart(config: &Config, args: RestartNamespacedArgs) -
 
     let namespace = Namespace::from_value_or_default(args.namespace);
 
+    let mut tags = vec!["ignitiond.restart".to_string()];
+    match args.trace {
+        Some(MachineDebugTraceArg::Strace) => tags.push("ignitiond.trace.strace".to_string()),
+        Some(MachineDebugTraceArg::Ltrace) => tags.push("ignitiond.trace.ltrace".to_string()),
+        None => {}
+    };
+
+    api_client
+        .machine()
+        .add_tags(namespace, args.name.clone(), tags)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn run_machine_suspend(config: &Config, args: MachineSuspendArgs) -> Result<()> {
+    let api_client = get_api_client(config.try_into()?);
+
+    let namespace = Namespace::from_value_or_default(args.namespace);
+
+    let (_, status) = api_client
+        .machine()
+        .get(namespace.clone(), args.name.clone())
+        .await?;
+
+    if !matches!(status.phase, MachinePhase::Ready | MachinePhase::Booting) {
+        message_warn(format!(
+            "Machine '{}' is {}, only running machines can be suspended.",
+            args.name,
+            status.phase.to_string()
+        ));
+        return Ok(());
+    }
+
+    api_client
+        .machine()
+        .add_tags(
+            namespace,
+            args.name.clone(),
+            vec!["ignitiond.suspend".to_string()],
+        )
+        .await?;
+
+    message_info(format!(
+        "Machine '{}' is being suspended, it stays suspended until resumed.",
+        args.name
+    ));
+
+    Ok(())
+}
+
+pub async fn run_machine_resume(config: &Config, args: MachineResumeArgs) -> Result<()> {
+    let api_client = get_api_client(config.try_into()?);
+
+    let namespace = Namespace::from_value_or_default(args.namespace);
+
+    let (_, status) = api_client
+        .machine()
+        .get(namespace.clone(), args.name.clone())
+        .await?;
+
+    if status.user_suspended != Some(true) {
+        message_warn(format!("Machine '{}' isn't suspended.", args.name));
+        return Ok(());
+    }
+
     api_client
         .machine()
-        .add_tag(
+        .add_tags(
             namespace,
             args.name.clone(),
-            "ignitiond.restart".to_string(),
+            vec!["ignitiond.resume".to_string()],
         )
         .await?;
 
+    message_info(format!("Machine '{}' is being resumed.", args.name));
+
     Ok(())
 }
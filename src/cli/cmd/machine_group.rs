@@ -0,0 +1,181 @@
+use anyhow::Result;
+use ignition::resources::machine_group::{MachineGroupLatest, MachineGroupStatus};
+use meta::{summary, table};
+
+use crate::{
+    client::get_api_client,
+    cmd::{DeleteNamespacedArgs, GetNamespacedArgs, ListNamespacedArgs},
+    config::Config,
+    ui::message::{message_info, message_warn},
+};
+
+#[table]
+pub struct MachineGroupTable {
+    #[field(name = "name")]
+    name: String,
+
+    #[field(name = "namespace")]
+    namespace: Option<String>,
+
+    #[field(name = "replicas", cell_style = important)]
+    replicas: String,
+
+    #[field(name = "autoscale")]
+    autoscale: Option<String>,
+
+    #[field(name = "image")]
+    image: Option<String>,
+}
+
+#[summary]
+pub struct MachineGroupSummary {
+    #[field(name = "name")]
+    name: String,
+
+    #[field(name = "namespace")]
+    namespace: Option<String>,
+
+    #[field(name = "tags")]
+    tags: Vec<String>,
+
+    #[field(name = "conditions")]
+    conditions: Vec<String>,
+
+    #[field(name = "replicas", cell_style = important)]
+    replicas: String,
+
+    #[field(name = "autoscale")]
+    autoscale: Option<String>,
+
+    #[field(name = "load")]
+    load: Option<String>,
+
+    #[field(name = "image")]
+    image: Option<String>,
+
+    #[field(name = "machines")]
+    machines: Vec<String>,
+
+    #[field(name = "draining")]
+    draining: Vec<String>,
+}
+
+fn format_replicas(status: &MachineGroupStatus) -> String {
+    format!("{}/{}", status.machines.len(), status.replicas)
+}
+
+fn format_autoscale(group: &MachineGroupLatest) -> Option<String> {
+    let autoscale = group.autoscale.as_ref()?;
+
+    let mut targets = vec![];
+    if let Some(target_rps) = autoscale.target_rps {
+        targets.push(format!("{} rps", target_rps));
+    }
+    if let Some(target_cpu) = autoscale.target_cpu {
+        targets.push(format!("{}% cpu", target_cpu));
+    }
+
+    Some(format!(
+        "{}-{} ({})",
+        autoscale.min,
+        autoscale.max,
+        targets.join(", ")
+    ))
+}
+
+fn format_load(status: &MachineGroupStatus) -> Option<String> {
+    let mut load = vec![];
+    if let Some(rps) = status.observed_rps {
+        load.push(format!("{} rps", rps));
+    }
+    if let Some(cpu) = status.observed_cpu {
+        load.push(format!("{}% cpu", cpu));
+    }
+
+    if load.is_empty() {
+        return None;
+    }
+
+    Some(load.join(", "))
+}
+
+impl From<(MachineGroupLatest, MachineGroupStatus)> for MachineGroupTableRow {
+    fn from((group, status): (MachineGroupLatest, MachineGroupStatus)) -> Self {
+        Self {
+            replicas: format_replicas(&status),
+            autoscale: format_autoscale(&group),
+            name: group.name,
+            namespace: group.namespace,
+            image: group.image,
+        }
+    }
+}
+
+impl From<(MachineGroupLatest, MachineGroupStatus)> for MachineGroupSummary {
+    fn from((group, status): (MachineGroupLatest, MachineGroupStatus)) -> Self {
+        Self {
+            replicas: format_replicas(&status),
+            autoscale: format_autoscale(&group),
+            load: format_load(&status),
+            conditions: status.conditions.iter().map(|c| c.to_string()).collect(),
+            machines: status.machines,
+            draining: status
+                .draining
+                .into_iter()
+                .map(|replica| replica.name)
+                .collect(),
+            name: group.name,
+            namespace: group.namespace,
+            tags: group.tags.unwrap_or_default(),
+            image: group.image,
+        }
+    }
+}
+
+pub async fn run_machine_group_list(config: &Config, args: ListNamespacedArgs) -> Result<()> {
+    let api_client = get_api_client(config.try_into()?);
+    let groups = api_client.machine_group().list(args.into()).await?;
+
+    let mut table = MachineGroupTable::new();
+
+    for (group, status) in groups {
+        table.add_row(MachineGroupTableRow::from((group, status)));
+    }
+
+    table.print();
+
+    Ok(())
+}
+
+pub async fn run_machine_group_get(config: &Config, args: GetNamespacedArgs) -> Result<()> {
+    let api_client = get_api_client(config.try_into()?);
+    let (group, status) = api_client
+        .machine_group()
+        .get(args.clone().into(), args.name)
+        .await?;
+
+    let summary = MachineGroupSummary::from((group, status));
+    summary.print();
+
+    Ok(())
+}
+
+pub async fn run_machine_group_delete(config: &Config, args: DeleteNamespacedArgs) -> Result<()> {
+    let api_client = get_api_client(config.try_into()?);
+    if !args.confirm {
+        message_warn(format!(
+            "You are about to delete the machine group '{}' and all of its replicas. This action cannot be undone. To confirm, run the command with --yes (or -y).",
+            args.name
+        ));
+        return Ok(());
+    }
+
+    api_client
+        .machine_group()
+        .delete(args.clone().into(), args.name.clone())
+        .await?;
+
+    message_info(format!("Machine group '{}' has been deleted.", args.name));
+
+    Ok(())
+}
@@ -1,13 +1,20 @@
+pub mod admin;
 pub mod app;
+pub mod audit;
 pub mod certificate;
 pub mod completion;
+pub mod cron;
 pub mod deploy;
+pub mod dns_record;
 pub mod docker;
+pub mod event;
 pub mod gadget;
+pub mod image;
 #[cfg(feature = "lovable")]
 pub mod import;
 pub mod login;
 pub mod machine;
+pub mod machine_group;
 pub mod namespace;
 pub mod profile;
 pub mod query;
@@ -70,10 +77,22 @@ pub enum Command {
     #[command(subcommand)]
     Machine(MachineCommand),
 
+    /// Machine group management (short: group)
+    #[command(subcommand, alias = "group")]
+    MachineGroup(MachineGroupCommand),
+
+    /// Scheduled machines management
+    #[command(subcommand)]
+    Cron(CronCommand),
+
     /// Volume management
     #[command(subcommand)]
     Volume(VolumeCommand),
 
+    /// Image management
+    #[command(subcommand)]
+    Image(ImageCommand),
+
     /// Service management (short: svc)
     #[command(subcommand, alias = "svc")]
     Service(ServiceCommand),
@@ -82,13 +101,24 @@ pub enum Command {
     #[command(subcommand, alias = "cert")]
     Certificate(CertificateCommand),
 
+    /// DNS record management (short: dns)
+    #[command(subcommand, alias = "dns")]
+    DnsRecord(DnsRecordCommand),
+
     /// Query resources
     Query(query::QueryArgs),
 
+    /// Show who created, updated or deleted resources
+    Audit(audit::AuditArgs),
+
     /// Docker management
     #[command(subcommand)]
     Docker(DockerCommand),
 
+    /// Daemon administration
+    #[command(subcommand)]
+    Admin(AdminCommand),
+
     /// Install completions for your shell (run with root permissions)
     Completions {
         #[arg(value_enum)]
@@ -122,15 +152,40 @@ pub enum MachineCommand {
     /// Get logs for a machine
     Logs(MachineLogsArgs),
 
+    /// Show the CPU, memory, network and disk usage of a machine
+    Top(machine::MachineTopArgs),
+
     /// Execute a command in a machine
     Exec(machine::MachineExecArgs),
 
+    /// Show the audit trail of exec sessions
+    ExecHistory(machine::MachineExecHistoryArgs),
+
+    /// Attach to the serial console of a machine, to debug boot failures
+    Console(machine::MachineConsoleArgs),
+
     /// Delete a machine (short: rm)
     #[command(alias = "rm")]
     Delete(DeleteNamespacedArgs),
 
     /// Restart a machine
     Restart(RestartNamespacedArgs),
+
+    /// Suspend a machine, keeping its state until it's resumed
+    Suspend(machine::MachineSuspendArgs),
+
+    /// Resume a suspended machine
+    Resume(machine::MachineResumeArgs),
+
+    /// Debugging tools for crashed machines
+    #[command(subcommand)]
+    Debug(MachineDebugCommand),
+}
+
+#[derive(Subcommand)]
+pub enum MachineDebugCommand {
+    /// List the core dumps collected from a machine, or download one
+    Cores(machine::MachineDebugCoresArgs),
 }
 
 #[derive(Subcommand)]
@@ -142,6 +197,9 @@ pub enum ServiceCommand {
     /// Get a service
     Get(GetNamespacedArgs),
 
+    /// Get access logs for a service
+    Logs(service::ServiceLogsArgs),
+
     /// Delete a service (short: rm)
     #[command(alias = "rm")]
     Delete(DeleteNamespacedArgs),
@@ -159,6 +217,27 @@ pub enum VolumeCommand {
     /// Delete a volume (short: rm)
     #[command(alias = "rm")]
     Delete(DeleteNamespacedArgs),
+
+    /// Grow a volume, running machines using it see the new size right away
+    Resize(volume::VolumeResizeArgs),
+
+    /// List the backups of a volume, newest first
+    Backups(volume::VolumeBackupsArgs),
+
+    /// Replace the contents of a volume with one of its backups
+    Restore(volume::VolumeRestoreArgs),
+
+    /// Show the volume space used by the tenant and its quota
+    Usage,
+}
+
+#[derive(Subcommand)]
+pub enum ImageCommand {
+    /// Show the manifest and config an image reference resolves to
+    Inspect(image::ImageInspectArgs),
+
+    /// Load an image from a `docker save` or OCI layout archive, without a registry
+    Load(image::ImageLoadArgs),
 }
 
 #[derive(Subcommand)]
@@ -173,6 +252,61 @@ pub enum CertificateCommand {
     /// Delete a certificate (short: rm)
     #[command(alias = "rm")]
     Delete(DeleteNamespacedArgs),
+
+    /// Print the root of the internal CA, to trust inside images
+    Ca,
+
+    /// Replace the root of the internal CA and issue the internal certificates again, the
+    /// previous root stays trusted for a week
+    RotateCa,
+
+    /// Write an internal certificate, its key and the CA root to files
+    Bundle(certificate::CertificateBundleArgs),
+}
+
+#[derive(Subcommand)]
+pub enum MachineGroupCommand {
+    /// List machine groups (short: ls)
+    #[command(alias = "ls")]
+    List(ListNamespacedArgs),
+
+    /// Get a machine group
+    Get(GetNamespacedArgs),
+
+    /// Delete a machine group and its replicas (short: rm)
+    #[command(alias = "rm")]
+    Delete(DeleteNamespacedArgs),
+}
+
+#[derive(Subcommand)]
+pub enum CronCommand {
+    /// List crons (short: ls)
+    #[command(alias = "ls")]
+    List(ListNamespacedArgs),
+
+    /// Get a cron
+    Get(GetNamespacedArgs),
+
+    /// List the runs of a cron, newest first
+    Runs(GetNamespacedArgs),
+
+    /// Delete a cron (short: rm)
+    #[command(alias = "rm")]
+    Delete(DeleteNamespacedArgs),
+}
+
+#[derive(Subcommand)]
+pub enum DnsRecordCommand {
+    /// List DNS records (short: ls)
+    #[command(alias = "ls")]
+    List(ListNamespacedArgs),
+
+    /// Get a DNS record
+    Get(GetNamespacedArgs),
+
+    /// Delete a DNS record (short: rm)
+    #[command(alias = "rm")]
+    Delete(DeleteNamespacedArgs),
 }
 
 #[derive(Subcommand)]
@@ -211,6 +345,18 @@ pub enum DockerCommand {
     Login(docker::DockerLoginArgs),
 }
 
+#[derive(Subcommand)]
+pub enum AdminCommand {
+    /// Collect a sanitized support bundle to attach to bug reports
+    SupportBundle(admin::SupportBundleArgs),
+
+    /// Show which images the image GC would remove, without removing them
+    ImageGc,
+
+    /// Set the volume quota of a tenant
+    VolumeQuota(admin::VolumeQuotaArgs),
+}
+
 #[derive(Subcommand)]
 pub enum NamespaceCommand {
     /// List namespaces (short: ls)
@@ -262,19 +408,40 @@ pub async fn run_cli() -> Result<()> {
             MachineCommand::List(args) => machine::run_machine_list(&config, args).await,
             MachineCommand::Get(args) => machine::run_machine_get(&config, args).await,
             MachineCommand::Logs(args) => machine::run_machine_get_logs(&config, args).await,
+            MachineCommand::Top(args) => machine::run_machine_top(&config, args).await,
             MachineCommand::Exec(args) => machine::run_machine_exec(&config, args).await,
+            MachineCommand::ExecHistory(args) => {
+                machine::run_machine_exec_history(&config, args).await
+            }
+            MachineCommand::Console(args) => machine::run_machine_console(&config, args).await,
             MachineCommand::Delete(args) => machine::run_machine_delete(&config, args).await,
             MachineCommand::Restart(args) => machine::run_machine_restart(&config, args).await,
+            MachineCommand::Suspend(args) => machine::run_machine_suspend(&config, args).await,
+            MachineCommand::Resume(args) => machine::run_machine_resume(&config, args).await,
+            MachineCommand::Debug(cmd) => match cmd {
+                MachineDebugCommand::Cores(args) => {
+                    machine::run_machine_debug_cores(&config, args).await
+                }
+            },
         },
         Command::Service(cmd) => match cmd {
             ServiceCommand::List(args) => service::run_service_list(&config, args).await,
             ServiceCommand::Get(args) => service::run_service_get(&config, args).await,
+            ServiceCommand::Logs(args) => service::run_service_logs(&config, args).await,
             ServiceCommand::Delete(args) => service::run_service_delete(&config, args).await,
         },
         Command::Volume(cmd) => match cmd {
             VolumeCommand::List(args) => volume::run_volume_list(&config, args).await,
             VolumeCommand::Get(args) => volume::run_volume_get(&config, args).await,
             VolumeCommand::Delete(args) => volume::run_volume_delete(&config, args).await,
+            VolumeCommand::Resize(args) => volume::run_volume_resize(&config, args).await,
+            VolumeCommand::Backups(args) => volume::run_volume_backups(&config, args).await,
+            VolumeCommand::Restore(args) => volume::run_volume_restore(&config, args).await,
+            VolumeCommand::Usage => volume::run_volume_usage(&config).await,
+        },
+        Command::Image(cmd) => match cmd {
+            ImageCommand::Inspect(args) => image::run_image_inspect(&config, args).await,
+            ImageCommand::Load(args) => image::run_image_load(&config, args).await,
         },
         Command::Certificate(cmd) => match cmd {
             CertificateCommand::List(args) => {
@@ -284,11 +451,48 @@ pub async fn run_cli() -> Result<()> {
             CertificateCommand::Delete(args) => {
                 certificate::run_certificate_delete(&config, args).await
             }
+            CertificateCommand::Ca => certificate::run_certificate_ca(&config).await,
+            CertificateCommand::RotateCa => certificate::run_certificate_rotate_ca(&config).await,
+            CertificateCommand::Bundle(args) => {
+                certificate::run_certificate_bundle(&config, args).await
+            }
+        },
+        Command::MachineGroup(cmd) => match cmd {
+            MachineGroupCommand::List(args) => {
+                machine_group::run_machine_group_list(&config, args).await
+            }
+            MachineGroupCommand::Get(args) => {
+                machine_group::run_machine_group_get(&config, args).await
+            }
+            MachineGroupCommand::Delete(args) => {
+                machine_group::run_machine_group_delete(&config, args).await
+            }
+        },
+        Command::Cron(cmd) => match cmd {
+            CronCommand::List(args) => cron::run_cron_list(&config, args).await,
+            CronCommand::Get(args) => cron::run_cron_get(&config, args).await,
+            CronCommand::Runs(args) => cron::run_cron_runs(&config, args).await,
+            CronCommand::Delete(args) => cron::run_cron_delete(&config, args).await,
+        },
+        Command::DnsRecord(cmd) => match cmd {
+            DnsRecordCommand::List(args) => dns_record::run_dns_record_list(&config, args).await,
+            DnsRecordCommand::Get(args) => dns_record::run_dns_record_get(&config, args).await,
+            DnsRecordCommand::Delete(args) => {
+                dns_record::run_dns_record_delete(&config, args).await
+            }
         },
         Command::Query(args) => query::run_query(&config, args).await,
+        Command::Audit(args) => audit::run_audit(&config, args).await,
         Command::Docker(cmd) => match cmd {
             DockerCommand::Login(args) => docker::run_docker_login(&config, args).await,
         },
+        Command::Admin(cmd) => match cmd {
+            AdminCommand::SupportBundle(args) => {
+                admin::run_admin_support_bundle(&config, args).await
+            }
+            AdminCommand::ImageGc => admin::run_admin_image_gc(&config).await,
+            AdminCommand::VolumeQuota(args) => admin::run_admin_volume_quota(&config, args).await,
+        },
         Command::Completions { .. } => unreachable!(),
     }
 }
@@ -1,7 +1,9 @@
 use anyhow::Result;
+use clap::Args;
 use ignition::{
     constants::DEFAULT_TRAFFIC_AWARE_INACTIVITY_TIMEOUT_SECS,
     resources::{
+        core::LogStreamParams,
         metadata::Namespace,
         service::{ServiceBind, ServiceLatest, ServiceStatus, ServiceTargetConnectionTracking},
     },
@@ -10,11 +12,56 @@ use meta::{summary, table};
 
 use crate::{
     client::get_api_client,
-    cmd::{DeleteNamespacedArgs, GetNamespacedArgs, ListNamespacedArgs},
+    cmd::{
+        DeleteNamespacedArgs, GetNamespacedArgs, ListNamespacedArgs,
+        machine::{LogsOptions, print_logs},
+    },
     config::Config,
     ui::message::{message_info, message_warn},
 };
 
+#[derive(Clone, Debug, Args)]
+pub struct ServiceLogsArgs {
+    /// Namespace of the service (short: --ns)
+    #[arg(long = "namespace", alias = "ns")]
+    namespace: Option<String>,
+
+    /// Since when to fetch logs [default: 1d] (eg. 1d, 1h, 1m, 10s)
+    #[arg(long = "since", short = 's')]
+    since: Option<String>,
+
+    /// Show timestamps (always in UTC)
+    #[arg(long = "timestamps", short = 't')]
+    show_timestamps: bool,
+
+    /// Show elapsed time since log entry
+    #[arg(long = "elapsed", short = 'e')]
+    show_elapsed: bool,
+
+    /// Follow the logs
+    #[arg(long = "follow", short = 'f')]
+    follow: bool,
+
+    /// Only show lines written to this stream (stdout, stderr)
+    #[arg(long = "stream")]
+    stream: Option<String>,
+
+    /// Only show lines containing this text
+    #[arg(long = "grep", short = 'g')]
+    grep: Option<String>,
+
+    /// Only show lines matching this regular expression
+    #[arg(long = "regex")]
+    regex: Option<String>,
+
+    /// Only show lines at this level or above (debug, info, warn, error)
+    #[arg(long = "level", short = 'l')]
+    level: Option<String>,
+
+    /// Name of the service to fetch access logs for
+    name: String,
+}
+
 #[table]
 pub struct ServiceTable {
     #[field(name = "name")]
@@ -47,6 +94,9 @@ pub struct ServiceSummary {
     #[field(name = "tags")]
     tags: Vec<String>,
 
+    #[field(name = "conditions")]
+    conditions: Vec<String>,
+
     #[field(name = "mode", cell_style = important)]
     mode: String,
 
@@ -67,12 +117,21 @@ pub struct ServiceSummary {
 
     #[field(name = "connection tracking")]
     connection_tracking: String,
+
+    #[field(name = "maintenance")]
+    maintenance: Option<String>,
 }
 
 impl From<(ServiceLatest, ServiceStatus)> for ServiceTableRow {
     fn from((service, status): (ServiceLatest, ServiceStatus)) -> Self {
         let host = match &service.bind {
-            ServiceBind::External { host, .. } => Some(host.clone()),
+            ServiceBind::External {
+                host, path_prefix, ..
+            } => Some(format!(
+                "{}{}",
+                host,
+                path_prefix.as_deref().unwrap_or_default()
+            )),
             ServiceBind::Internal { .. } => status
                 .internal_dns_hostname
                 .clone()
@@ -142,7 +201,13 @@ impl From<(ServiceLatest, ServiceStatus)> for ServiceTableRow {
 impl From<(ServiceLatest, ServiceStatus)> for ServiceSummary {
     fn from((service, status): (ServiceLatest, ServiceStatus)) -> Self {
         let host = match &service.bind {
-            ServiceBind::External { host, .. } => Some(host.clone()),
+            ServiceBind::External {
+                host, path_prefix, ..
+            } => Some(format!(
+                "{}{}",
+                host,
+                path_prefix.as_deref().unwrap_or_default()
+            )),
             ServiceBind::Internal { .. } => status
                 .internal_dns_hostname
                 .clone()
@@ -212,6 +277,7 @@ impl From<(ServiceLatest, ServiceStatus)> for ServiceSummary {
             name: service.name,
             namespace: service.namespace,
             tags: service.tags.clone().unwrap_or_default(),
+            conditions: status.conditions.iter().map(|c| c.to_string()).collect(),
             target,
             target_port: service.target.port.to_string(),
             host,
@@ -219,6 +285,10 @@ impl From<(ServiceLatest, ServiceStatus)> for ServiceSummary {
             mode: service.bind.to_string(),
             route,
             connection_tracking,
+            maintenance: service
+                .maintenance
+                .unwrap_or(false)
+                .then(|| "on".to_string()),
         }
     }
 }
@@ -270,3 +340,29 @@ pub async fn run_service_delete(config: &Config, args: DeleteNamespacedArgs) ->
 
     Ok(())
 }
+
+pub async fn run_service_logs(config: &Config, args: ServiceLogsArgs) -> Result<()> {
+    let name = args.name;
+
+    print_logs(
+        config,
+        LogsOptions {
+            namespace: args.namespace,
+            since: args.since,
+            show_timestamps: args.show_timestamps,
+            show_elapsed: args.show_elapsed,
+            follow: args.follow,
+            stream: args.stream,
+            grep: args.grep,
+            regex: args.regex,
+            level: args.level,
+        },
+        |start_ts_ns, end_ts_ns, filter| LogStreamParams::Service {
+            service_name: name,
+            start_ts_ns,
+            end_ts_ns,
+            filter,
+        },
+    )
+    .await
+}
@@ -1,6 +1,11 @@
 use anyhow::Result;
+use clap::Args;
 use ignition::{
-    resources::volume::{VolumeLatest, VolumeMode, VolumeStatus},
+    resources::{
+        core::{VolumeBackup, VolumeResizeParams, VolumeRestoreParams, VolumeUsageEntry},
+        metadata::Namespace,
+        volume::{VolumeFilesystem, VolumeLatest, VolumeMode, VolumeStatus},
+    },
     utils::size::format_human_readable_size,
 };
 use meta::{summary, table};
@@ -38,12 +43,42 @@ pub struct VolumeSummary {
     #[field(name = "tags")]
     tags: Vec<String>,
 
+    #[field(name = "conditions")]
+    conditions: Vec<String>,
+
     #[field(name = "mode", cell_style = important)]
     mode: String,
 
     #[field(name = "size")]
     size: String,
 
+    #[field(name = "filesystem")]
+    filesystem: String,
+
+    #[field(name = "used")]
+    used: Option<String>,
+
+    #[field(name = "encrypted")]
+    encrypted: String,
+
+    #[field(name = "source")]
+    source: Option<String>,
+
+    #[field(name = "backup schedule")]
+    backup_schedule: Option<String>,
+
+    #[field(name = "backup destination")]
+    backup_destination: Option<String>,
+
+    #[field(name = "last backup")]
+    last_backup: Option<String>,
+
+    #[field(name = "next backup")]
+    next_backup: Option<String>,
+
+    #[field(name = "backup error")]
+    backup_error: Option<String>,
+
     #[field(name = "volume id (internal)")]
     volume_id: Option<String>,
 
@@ -51,6 +86,61 @@ pub struct VolumeSummary {
     size_bytes: String,
 }
 
+#[table]
+pub struct VolumeBackupTable {
+    #[field(name = "id")]
+    id: String,
+
+    #[field(name = "created")]
+    created: String,
+
+    #[field(name = "size")]
+    size: String,
+}
+
+#[table]
+pub struct VolumeUsageTable {
+    #[field(name = "name")]
+    name: String,
+
+    #[field(name = "namespace")]
+    namespace: String,
+
+    #[field(name = "size")]
+    size: String,
+
+    #[field(name = "used", cell_style = important)]
+    used: String,
+}
+
+impl From<VolumeUsageEntry> for VolumeUsageTableRow {
+    fn from(entry: VolumeUsageEntry) -> Self {
+        Self {
+            name: entry.name,
+            namespace: entry.namespace,
+            size: format_human_readable_size(entry.provisioned_bytes),
+            used: format_human_readable_size(entry.used_bytes),
+        }
+    }
+}
+
+impl From<VolumeBackup> for VolumeBackupTableRow {
+    fn from(backup: VolumeBackup) -> Self {
+        Self {
+            id: backup.id,
+            created: format_time(backup.created_at),
+            size: format_human_readable_size(backup.size_bytes),
+        }
+    }
+}
+
+fn format_time(timestamp: u64) -> String {
+    chrono::DateTime::from_timestamp_millis(timestamp as i64)
+        .unwrap_or_default()
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
 impl From<(VolumeLatest, VolumeStatus)> for VolumeTableRow {
     fn from((volume, status): (VolumeLatest, VolumeStatus)) -> Self {
         let mode = match volume.mode {
@@ -76,6 +166,35 @@ impl From<(VolumeLatest, VolumeStatus)> for VolumeSummary {
             VolumeMode::Writeable => "writeable".to_string(),
         };
         let size = format_human_readable_size(status.size_bytes);
+        let filesystem = match volume.filesystem {
+            Some(VolumeFilesystem::Ext4) | None => "ext4".to_string(),
+            Some(VolumeFilesystem::Xfs) => "xfs".to_string(),
+            Some(VolumeFilesystem::Btrfs) => "btrfs".to_string(),
+        };
+
+        let source = volume
+            .from
+            .as_ref()
+            .and_then(|from| match (&from.volume, &from.image) {
+                (Some(name), _) => Some(match &from.namespace {
+                    Some(namespace) => format!("volume {}/{}", namespace, name),
+                    None => format!("volume {}", name),
+                }),
+                (None, Some(image)) => Some(format!("image {}", image)),
+                (None, None) => None,
+            });
+
+        let used = match (status.used_bytes, status.filesystem_size_bytes) {
+            (Some(used_bytes), Some(filesystem_size_bytes)) if filesystem_size_bytes > 0 => {
+                Some(format!(
+                    "{} of {} ({}%)",
+                    format_human_readable_size(used_bytes),
+                    format_human_readable_size(filesystem_size_bytes),
+                    used_bytes * 100 / filesystem_size_bytes
+                ))
+            }
+            _ => None,
+        };
 
         let volume_id = status.volume_id.clone();
         let size_bytes = status.size_bytes;
@@ -84,14 +203,75 @@ impl From<(VolumeLatest, VolumeStatus)> for VolumeSummary {
             name: volume.name,
             namespace: volume.namespace,
             tags: volume.tags.unwrap_or_default(),
+            conditions: status.conditions.iter().map(|c| c.to_string()).collect(),
             mode,
             size,
+            filesystem,
+            used,
+            encrypted: if volume.encrypted == Some(true) {
+                "yes".to_string()
+            } else {
+                "no".to_string()
+            },
+            source,
+            backup_schedule: volume.backup.as_ref().map(|b| b.schedule.clone()),
+            backup_destination: volume.backup.as_ref().map(|b| b.destination.clone()),
+            last_backup: status.last_backup.as_ref().map(|id| {
+                format!(
+                    "{} ({})",
+                    id,
+                    format_time(status.last_backup_at.unwrap_or_default())
+                )
+            }),
+            next_backup: status.next_backup_at.map(format_time),
+            backup_error: status.last_backup_error.clone(),
             volume_id,
             size_bytes: size_bytes.to_string(),
         }
     }
 }
 
+#[derive(Args)]
+pub struct VolumeResizeArgs {
+    /// Namespace of the volume (short: --ns)
+    #[arg(long = "namespace", alias = "ns")]
+    namespace: Option<String>,
+
+    /// Name of the volume
+    name: String,
+
+    /// New size of the volume, larger than the current one (eg. 10GB)
+    size: String,
+}
+
+#[derive(Args)]
+pub struct VolumeBackupsArgs {
+    /// Namespace of the volume (short: --ns)
+    #[arg(long = "namespace", alias = "ns")]
+    namespace: Option<String>,
+
+    /// Name of the volume
+    name: String,
+}
+
+#[derive(Args)]
+pub struct VolumeRestoreArgs {
+    /// Namespace of the volume (short: --ns)
+    #[arg(long = "namespace", alias = "ns")]
+    namespace: Option<String>,
+
+    /// Name of the volume
+    name: String,
+
+    /// Id of the backup to restore (see `volume backups`), or latest
+    #[arg(long = "from-backup")]
+    from_backup: String,
+
+    /// Confirm replacing the contents of the volume
+    #[arg(long = "yes", short = 'y')]
+    confirm: bool,
+}
+
 pub async fn run_volume_list(config: &Config, args: ListNamespacedArgs) -> Result<()> {
     let api_client = get_api_client(config.try_into()?);
     let volumes = api_client.volume().list(args.into()).await?;
@@ -139,3 +319,107 @@ pub async fn run_volume_delete(config: &Config, args: DeleteNamespacedArgs) -> R
 
     Ok(())
 }
+
+pub async fn run_volume_resize(config: &Config, args: VolumeResizeArgs) -> Result<()> {
+    let api_client = get_api_client(config.try_into()?);
+    let resize = api_client
+        .core()
+        .resize_volume(VolumeResizeParams {
+            namespace: args.namespace,
+            name: args.name,
+            size: args.size,
+        })
+        .await?;
+
+    message_info(format!(
+        "Volume '{}' has been resized to {}.",
+        resize.name,
+        format_human_readable_size(resize.size_bytes)
+    ));
+    if resize.machines.is_empty() {
+        message_info("Machines using it will see the new size when they start.");
+    } else {
+        message_info(format!(
+            "Running machines using it: {}",
+            resize.machines.join(", ")
+        ));
+    }
+
+    Ok(())
+}
+
+pub async fn run_volume_usage(config: &Config) -> Result<()> {
+    let api_client = get_api_client(config.try_into()?);
+    let usage = api_client.core().volume_usage().await?;
+
+    let quota = match usage.quota_bytes {
+        Some(quota_bytes) => format_human_readable_size(quota_bytes),
+        None => "unlimited".to_string(),
+    };
+    message_info(format!(
+        "Volumes are {} in size, of a quota of {}, and use {} on disk",
+        format_human_readable_size(usage.provisioned_bytes),
+        quota,
+        format_human_readable_size(usage.used_bytes)
+    ));
+
+    if usage.volumes.is_empty() {
+        return Ok(());
+    }
+
+    let mut table = VolumeUsageTable::new();
+    for entry in usage.volumes {
+        table.add_row(entry.into());
+    }
+    table.print();
+
+    Ok(())
+}
+
+pub async fn run_volume_backups(config: &Config, args: VolumeBackupsArgs) -> Result<()> {
+    let api_client = get_api_client(config.try_into()?);
+    let backups = api_client
+        .core()
+        .list_volume_backups(Namespace::from_value_or_default(args.namespace), &args.name)
+        .await?;
+
+    let mut table = VolumeBackupTable::new();
+    for backup in backups.into_iter().rev() {
+        table.add_row(backup.into());
+    }
+    table.print();
+
+    Ok(())
+}
+
+pub async fn run_volume_restore(config: &Config, args: VolumeRestoreArgs) -> Result<()> {
+    if !args.confirm {
+        message_warn(format!(
+            "You are about to replace the contents of the volume '{}' with a backup. Machines using it have to be stopped. To confirm, run the command with --yes (or -y).",
+            args.name
+        ));
+        return Ok(());
+    }
+
+    let backup = match args.from_backup.as_str() {
+        "latest" => None,
+        id => Some(id.to_string()),
+    };
+
+    let api_client = get_api_client(config.try_into()?);
+    let restore = api_client
+        .core()
+        .restore_volume(VolumeRestoreParams {
+            namespace: args.namespace,
+            name: args.name,
+            backup,
+        })
+        .await?;
+
+    message_info(format!(
+        "Volume '{}' has been restored from backup {}.",
+        restore.name, restore.backup
+    ));
+
+    Ok(())
+}
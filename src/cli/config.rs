@@ -15,6 +15,19 @@ pub struct Config {
 
     #[serde(rename = "profile")]
     pub profiles: Vec<Profile>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build: Option<BuildConfig>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct BuildConfig {
+    /// Local environment variables (glob patterns) forwarded to builds, nothing is forwarded by default.
+    #[serde(rename = "env-allowlist", default)]
+    pub env_allowlist: Vec<String>,
+    /// Patterns that are never forwarded, on top of the built-in secret patterns.
+    #[serde(rename = "env-denylist", default)]
+    pub env_denylist: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -63,6 +76,7 @@ impl Config {
                 config_path: config_path.clone(),
                 current_profile: "default".to_string(),
                 profiles: vec![],
+                build: None,
             };
 
             config.save().await?;
@@ -1,3 +1,5 @@
+use std::io::{Write, stderr};
+
 use ansi_term::{Color, Style};
 
 use crate::ui::{LOG_PADDING, MESSAGE_PADDING};
@@ -26,6 +28,21 @@ pub fn message_warn(message: impl AsRef<str>) {
     eprintln!("{}", message.as_ref())
 }
 
+/// Rewrites the current line, for progress updated in place. End it with `message_progress_end`.
+pub fn message_progress(message: impl AsRef<str>) {
+    let padding = "█".repeat(MESSAGE_PADDING) + " ";
+    eprint!(
+        "\r\x1b[2K{}",
+        Style::new().fg(Color::Blue).bold().paint(padding)
+    );
+    eprint!("{}", message.as_ref());
+    let _ = stderr().flush();
+}
+
+pub fn message_progress_end() {
+    eprintln!();
+}
+
 pub fn message_log_stdout(message: impl AsRef<str>, timestamp: Option<String>) {
     let padding = "█".repeat(LOG_PADDING) + " ";
     eprint!("{}", Style::new().fg(Color::Blue).bold().paint(padding));
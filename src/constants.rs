@@ -7,3 +7,28 @@ pub const DEFAULT_SUSPEND_TIMEOUT_SECS: u64 = 10;
 pub const DEFAULT_TRAFFIC_AWARE_INACTIVITY_TIMEOUT_SECS: u64 = 5;
 pub const DEFAULT_NAMESPACE: &str = "default";
 pub const DEFAULT_AGENT_TENANT: &str = "agent";
+pub const DEFAULT_DEBUG_TRACE_MAX_LINES: u32 = 2000;
+pub const DEFAULT_HEALTH_PORT: u16 = 8099;
+pub const SCRATCH_VOLUME_MOUNT_PATH: &str = "/scratch";
+pub const DEFAULT_CORE_DUMP_MAX_SIZE_MIB: u64 = 128;
+pub const DEFAULT_BUILDER_CLIENT_CERT_TTL_MINUTES: i64 = 10;
+pub const DEFAULT_INTERNAL_CA_TTL_DAYS: i64 = 10 * 365;
+pub const DEFAULT_INTERNAL_CA_ROTATION_DAYS_BEFORE_EXPIRY: u64 = 90;
+pub const DEFAULT_INTERNAL_CA_ROTATION_OVERLAP_HOURS: i64 = 7 * 24;
+pub const DEFAULT_INTERNAL_CERT_TTL_HOURS: u32 = 24;
+pub const DEFAULT_BUILDER_ALLOCATION_TTL_SECS: u64 = 60 * 60;
+pub const DEFAULT_BUILDER_GC_INTERVAL_SECS: u64 = 60;
+pub const DEFAULT_METRICS_EXPORT_INTERVAL_SECS: u32 = 15;
+pub const DEFAULT_MACHINE_USAGE_SAMPLE_INTERVAL_SECS: u64 = 5;
+pub const DEFAULT_MACHINE_USAGE_WINDOW_SAMPLES: usize = 60;
+pub const DEFAULT_EVENT_TTL_MINS: u64 = 60;
+pub const DEFAULT_EVENT_GC_INTERVAL_SECS: u64 = 60;
+pub const DEFAULT_IMAGE_GC_INTERVAL_SECS: u64 = 10 * 60;
+pub const DEFAULT_IMAGE_GC_KEEP_PER_REFERENCE: usize = 2;
+pub const DEFAULT_IMAGE_GC_MIN_AGE_MINS: u64 = 60;
+pub const DEFAULT_ROLLOUT_MAX_SURGE: u32 = 1;
+pub const DEFAULT_ROLLOUT_MAX_UNAVAILABLE: u32 = 0;
+pub const DEFAULT_ROLLOUT_READY_TIMEOUT_SECS: u64 = 5 * 60;
+pub const DEFAULT_ROLLOUT_DRAIN_TIMEOUT_SECS: u64 = 30;
+pub const DEFAULT_IDLE_SUSPEND_MAX_CPU: u8 = 5;
+pub const DEFAULT_TTL_WARNING_MINS: u64 = 60;
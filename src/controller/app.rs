@@ -10,6 +10,10 @@ use crate::{
     controller::{
         AdmissionCheckBeforeDelete, AdmissionCheckBeforeSet, Controller, ReconcileNext,
         context::{ControllerContext, ControllerEvent, ControllerKey},
+        expiry::parse_ttl,
+        machine::bandwidth_limits,
+        placement::parse_constraints,
+        references::{MissingReferences, missing_volumes},
     },
     repository::Repository,
     resource_index::ResourceKind,
@@ -145,13 +149,24 @@ impl Controller for AppController {
             tags: Some(tags.clone()),
             image: app.image.clone(),
             build: None,
+            build_env: None,
             resources: app.resources.clone(),
             restart_policy: app.restart_policy.clone(),
             mode: app.mode.clone(),
             volumes: app.volumes.clone(),
+            scratch: app.scratch.clone(),
+            ephemeral_volumes: app.ephemeral_volumes.clone(),
             command: app.command.clone(),
             environment: app.environment.clone(),
             depends_on: app.depends_on.clone(),
+            health: app.health.clone(),
+            kernel_params: app.kernel_params.clone(),
+            rollout: app.rollout.clone(),
+            suspend: app.suspend.clone(),
+            // the app expires as a whole
+            ttl: None,
+            placement: app.placement.clone(),
+            network: app.network.clone(),
         };
 
         let exposed = app.expose.clone().unwrap_or_default();
@@ -300,6 +315,13 @@ fn generate_service_from_expose(
             port: expose.port,
             protocol: ServiceTargetProtocol::Tcp,
             connection_tracking: expose.connection_tracking.clone(),
+            weight: None,
+            backends: None,
+            canary: None,
+            mirror: None,
+            proxy_protocol: None,
+            tls: None,
+            host_header: None,
         },
         (None, Some(external)) => ServiceTarget {
             name: app.name.clone(),
@@ -309,9 +331,18 @@ fn generate_service_from_expose(
                 ServiceBindExternalProtocol::Http => ServiceTargetProtocol::Http,
                 ServiceBindExternalProtocol::Https => ServiceTargetProtocol::Http,
                 ServiceBindExternalProtocol::Tls => ServiceTargetProtocol::Tcp,
+                ServiceBindExternalProtocol::TlsPassthrough => ServiceTargetProtocol::Tcp,
                 ServiceBindExternalProtocol::Tcp => ServiceTargetProtocol::Tcp,
+                ServiceBindExternalProtocol::Udp => ServiceTargetProtocol::Udp,
             },
             connection_tracking: expose.connection_tracking.clone(),
+            weight: None,
+            backends: None,
+            canary: None,
+            mirror: None,
+            proxy_protocol: None,
+            tls: None,
+            host_header: None,
         },
         _ => bail!(
             "invalid expose configuration for app: {} {} - only one of internal or external can be specified",
@@ -348,6 +379,7 @@ fn generate_service_from_expose(
                     host,
                     port: external.port,
                     protocol: external.protocol,
+                    path_prefix: None,
                 }
             }
         }
@@ -364,6 +396,17 @@ fn generate_service_from_expose(
         tags: Some(app.tags.clone().unwrap_or_default()),
         target: service_target,
         bind: service_bind,
+        rate_limit: None,
+        timeouts: None,
+        circuit_breaker: None,
+        retry: None,
+        firewall: None,
+        auth: None,
+        compression: None,
+        sticky_sessions: None,
+        headers: None,
+        maintenance: None,
+        error_pages: None,
     };
 
     Ok(service)
@@ -377,7 +420,7 @@ impl AdmissionCheckBeforeSet for App {
         tenant: String,
         repo: Arc<Repository>,
         agent: Arc<Agent>,
-        _metadata: Metadata,
+        metadata: Metadata,
     ) -> Result<()> {
         let resource = self.latest();
 
@@ -389,6 +432,31 @@ impl AdmissionCheckBeforeSet for App {
             bail!("image is not set for app: {}", resource.name);
         }
 
+        if let Some(ttl) = &resource.ttl {
+            parse_ttl(ttl)?;
+        }
+
+        if let Some(constraints) = resource
+            .placement
+            .as_ref()
+            .and_then(|placement| placement.constraints.as_ref())
+        {
+            parse_constraints(constraints)?;
+        }
+
+        bandwidth_limits(resource.network.as_ref())?;
+
+        MissingReferences::check(
+            "app",
+            metadata,
+            missing_volumes(
+                &repo,
+                &tenant,
+                &resource.namespace,
+                resource.volumes.as_deref().unwrap_or_default(),
+            )?,
+        )?;
+
         for (expose_name, expose) in resource.expose.clone().unwrap_or_default().iter() {
             if expose.internal.is_some() && expose.external.is_some() {
                 bail!(
@@ -423,13 +491,7 @@ impl AdmissionCheckBeforeSet for App {
             let service_resource = Service::V1(service);
 
             if let Err(e) = service_resource
-                .before_set(
-                    Some(&service_resource),
-                    tenant.clone(),
-                    repo.clone(),
-                    agent.clone(),
-                    service_resource.metadata(),
-                )
+                .validate(Some(&service_resource), tenant.clone(), agent.clone())
                 .await
             {
                 bail!(
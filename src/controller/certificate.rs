@@ -3,37 +3,96 @@ use async_trait::async_trait;
 use futures_util::future::join_all;
 use hickory_resolver::{
     TokioAsyncResolver,
-    config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts},
+    config::{ResolverConfig, ResolverOpts},
 };
 use instant_acme::{AuthorizationStatus, ChallengeType, OrderStatus, RetryPolicy};
-use std::{
-    net::{IpAddr, SocketAddr},
-    sync::Arc,
-    time::Duration,
-};
+use std::{net::IpAddr, sync::Arc, time::Duration};
 use tracing::{error, info, warn};
 
 use crate::{
     agent::{
         Agent,
+        certificate::{
+            CertificateDetails, DEFAULT_CERTIFICATE_KEY_ALGORITHM, certificate_signing_request,
+            generate_certificate_key, normalize_certificate_domains,
+        },
+        dns::{DnsAgent, config::upstream_resolver_config},
         tracker::{TrackedResourceKind, TrackedResourceOwner},
     },
-    constants::DEFAULT_NAMESPACE,
+    constants::{DEFAULT_INTERNAL_CERT_TTL_HOURS, DEFAULT_NAMESPACE},
     controller::{
         AdmissionCheckBeforeDelete, AdmissionCheckBeforeSet, Controller, ReconcileNext,
+        certificate_renewal::{DEFAULT_RENEWAL_RETRY_INTERVAL_HOURS, certificate_renewal_due},
         context::{ControllerContext, ControllerEvent, ControllerKey},
     },
     repository::Repository,
     resource_index::ResourceKind,
     resources::{
         Convert,
-        certificate::{Certificate, CertificateIssuer, CertificateState, CertificateStatus},
+        certificate::{
+            Certificate, CertificateIssuer, CertificateKeyAlgorithm, CertificateRenewalConfig,
+            CertificateState, CertificateStatus,
+        },
+        core::EventReason,
         metadata::{Metadata, Namespace},
     },
 };
 
 pub struct CertificateController;
 
+/// Shows the parsed certificate in the status, so it's clear what is actually served.
+fn set_certificate_details(status: &mut CertificateStatus, details: CertificateDetails) {
+    status.issuer_name = Some(details.issuer_name);
+    status.subject_alt_names = Some(details.subject_alt_names);
+    status.chain = Some(details.chain);
+}
+
+fn count_issuance_attempt(status: &mut CertificateStatus) {
+    status.renewal_attempts = Some(status.renewal_attempts.unwrap_or(0) + 1);
+}
+
+/// Event recorded when a reconcile issued a certificate or failed to.
+fn issuance_event(
+    previous: &CertificateStatus,
+    status: &CertificateStatus,
+) -> Option<(EventReason, String)> {
+    // a certificate was already issued when renewing
+    let renewing = previous.not_after.is_some();
+
+    if status.state == CertificateState::Ready && status.not_after != previous.not_after {
+        let reason = if renewing {
+            EventReason::CertRenewed
+        } else {
+            EventReason::CertIssued
+        };
+        return Some((
+            reason,
+            format!(
+                "Certificate for {} issued, valid until {}",
+                status.domains.join(", "),
+                status.not_after.as_deref().unwrap_or_default()
+            ),
+        ));
+    }
+
+    if status.state == CertificateState::Failed && previous.state != CertificateState::Failed {
+        let reason = if renewing {
+            EventReason::CertRenewFailed
+        } else {
+            EventReason::CertIssueFailed
+        };
+        return Some((
+            reason,
+            status
+                .last_failure_reason
+                .clone()
+                .unwrap_or("Certificate issuance failed".to_string()),
+        ));
+    }
+
+    None
+}
+
 impl CertificateController {
     pub fn new() -> Self {
         Self
@@ -61,18 +120,7 @@ impl CertificateController {
         let resolver = if upstream_dns_servers.is_empty() {
             TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default())
         } else {
-            let mut resolver_config = ResolverConfig::new();
-            for server in upstream_dns_servers {
-                if let Ok(addr) = server.parse::<SocketAddr>() {
-                    resolver_config.add_name_server(NameServerConfig {
-                        socket_addr: addr,
-                        protocol: Protocol::Udp,
-                        tls_dns_name: None,
-                        trust_negative_responses: true,
-                        bind_addr: None,
-                    });
-                }
-            }
+            let resolver_config = upstream_resolver_config(&upstream_dns_servers);
 
             let opts = ResolverOpts::default();
             TokioAsyncResolver::tokio(resolver_config, opts)
@@ -119,16 +167,129 @@ impl CertificateController {
         Ok(())
     }
 
+    /// dns-01 for the domains whose challenges the DNS agent can publish, http-01 otherwise.
+    fn acme_challenge_type(ctx: &ControllerContext, domain: &str) -> ChallengeType {
+        if ctx.agent.dns().can_publish_acme_challenge(domain) {
+            ChallengeType::Dns01
+        } else {
+            ChallengeType::Http01
+        }
+    }
+
+    fn remove_acme_challenge_records(ctx: &ControllerContext, domains: &[String]) -> Result<()> {
+        let dns = ctx.agent.dns();
+        for domain in domains {
+            if dns.can_publish_acme_challenge(domain) {
+                dns.remove_txt_record(&DnsAgent::acme_challenge_record_name(domain))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn reconcile_internal_certificate(
+        &self,
+        ctx: &ControllerContext,
+        status: &mut CertificateStatus,
+        metadata: &Metadata,
+        ttl_hours: u32,
+        key_algorithm: &CertificateKeyAlgorithm,
+        domains: &[String],
+    ) -> Result<ReconcileNext> {
+        let cert_agent = ctx.agent.certificate();
+        let namespace = metadata
+            .namespace
+            .clone()
+            .unwrap_or(DEFAULT_NAMESPACE.to_string());
+
+        let now = chrono::Utc::now();
+        let ca = cert_agent.internal_ca(&ctx.tenant).await?;
+        let issued =
+            cert_agent.get_internal_certificate(&ctx.tenant, &namespace, &metadata.name)?;
+        let reissue = match &issued {
+            // signed by a root rotated out since, trusted only for a while longer
+            Some(certificate) if !ca.signed(certificate) => true,
+            Some(certificate) => {
+                certificate.domains != domains
+                    || status.key_algorithm.as_ref() != Some(key_algorithm)
+                    || status.state != CertificateState::Ready
+                    || certificate_renewal_due(status, now)
+            }
+            None => true,
+        };
+
+        if reissue {
+            // signed locally, so there is no order to go through
+            let certificate = cert_agent
+                .issue_internal_certificate(
+                    &ctx.tenant,
+                    &namespace,
+                    &metadata.name,
+                    domains,
+                    ttl_hours,
+                    key_algorithm,
+                )
+                .await?;
+
+            let not_before = chrono::DateTime::from_timestamp_millis(certificate.not_before as i64)
+                .ok_or_else(|| anyhow!("Invalid internal certificate not_before"))?;
+            let not_after = chrono::DateTime::from_timestamp_millis(certificate.not_after as i64)
+                .ok_or_else(|| anyhow!("Invalid internal certificate not_after"))?;
+            let renewal_time = not_before + (not_after - not_before) * 2 / 3;
+            let details = cert_agent.inspect_certificate_chain(&certificate.cert_pem)?;
+
+            status.state = CertificateState::Ready;
+            status.domains = domains.to_vec();
+            status.not_before = Some(not_before.to_rfc3339());
+            status.not_after = Some(not_after.to_rfc3339());
+            status.renewal_time = Some(renewal_time.to_rfc3339());
+            status.renewing_since = None;
+            status.last_failure_reason = None;
+            status.auto_provider_name = None;
+            status.key_algorithm = Some(key_algorithm.clone());
+            status.last_failure_time = None;
+            status.renewal_attempts = None;
+            set_certificate_details(status, details);
+
+            info!(
+                "Internal certificate issued for {:?}, valid until {}",
+                domains, not_after
+            );
+        }
+
+        let until_renewal = status
+            .renewal_time
+            .as_deref()
+            .and_then(|renewal_time| chrono::DateTime::parse_from_rfc3339(renewal_time).ok())
+            .and_then(|renewal_time| {
+                (renewal_time.with_timezone(&chrono::Utc) - now)
+                    .to_std()
+                    .ok()
+            })
+            .unwrap_or(Duration::from_secs(60));
+
+        Ok(ReconcileNext::After(until_renewal))
+    }
+
     async fn reconcile_auto_certificate(
         &self,
         ctx: &ControllerContext,
         status: &mut CertificateStatus,
         provider: &str,
         email: Option<&str>,
+        renewal: Option<&CertificateRenewalConfig>,
+        key_algorithm: &CertificateKeyAlgorithm,
         domains: &[String],
     ) -> Result<ReconcileNext> {
         let cert_agent = ctx.agent.certificate();
 
+        let days_before_expiry = renewal
+            .and_then(|r| r.days_before_expiry)
+            .unwrap_or(cert_agent.config().renewal_days_before_expiry);
+        let retry_interval_hours = renewal
+            .and_then(|r| r.retry_interval_hours)
+            .unwrap_or(DEFAULT_RENEWAL_RETRY_INTERVAL_HOURS);
+
         let resolved_email = cert_agent.resolve_email(provider, email)?;
 
         // Check if any of the requested domains are different from what we have
@@ -210,8 +371,19 @@ impl CertificateController {
                     "Certificate in PendingDnsResolution state, validating DNS resolution for domains: {:?}",
                     domains
                 );
-                // Validate that all domains resolve via DNS before creating ACME order
-                match self.validate_domain_dns_resolution(ctx, domains).await {
+                // Validate that all domains resolve via DNS before creating ACME order, dns-01
+                // domains don't need to receive traffic yet
+                let http01_domains = domains
+                    .iter()
+                    .filter(|domain| {
+                        Self::acme_challenge_type(ctx, domain) == ChallengeType::Http01
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>();
+                match self
+                    .validate_domain_dns_resolution(ctx, &http01_domains)
+                    .await
+                {
                     Ok(()) => {
                         info!("DNS validation successful, transitioning to PendingOrder");
                         status.state = CertificateState::PendingOrder(None);
@@ -292,13 +464,14 @@ impl CertificateController {
                                     continue;
                                 }
                                 AuthorizationStatus::Pending => {
-                                    if authz
-                                        .challenges
-                                        .iter()
-                                        .any(|c| c.r#type == ChallengeType::Http01)
-                                    {
+                                    let challenge_type =
+                                        Self::acme_challenge_type(ctx, &identifier);
+                                    if authz.challenges.iter().any(|c| c.r#type == challenge_type) {
                                         needs_challenge = true;
-                                        info!("Found HTTP-01 challenge for {}", identifier);
+                                        info!(
+                                            "Found {:?} challenge for {}",
+                                            challenge_type, identifier
+                                        );
                                     } else {
                                         return Err(anyhow!(
                                             "No supported challenge type for {}",
@@ -333,7 +506,7 @@ impl CertificateController {
 
                 // Determine next state based on authorizations (pass order URL along)
                 if needs_challenge {
-                    info!("Setting up HTTP-01 challenges");
+                    info!("Setting up ACME challenges");
                     status.state = CertificateState::PendingChallenge(order_url);
                 } else {
                     return Err(anyhow!("No valid authorization path found"));
@@ -343,10 +516,13 @@ impl CertificateController {
             }
 
             CertificateState::PendingChallenge(order_url) => {
-                // HTTP challenge set up, waiting for validation
-                info!("Certificate in PendingChallenge state, checking HTTP challenge");
+                // Publish the challenges and tell the ACME server they're ready
+                info!("Certificate in PendingChallenge state, setting up challenges");
                 info!("Order URL: {}", order_url);
 
+                // leftovers of a previous attempt would fail the validation
+                Self::remove_acme_challenge_records(ctx, domains)?;
+
                 let account = cert_agent.get_acme_account(provider, email).await?.unwrap();
                 let mut order = account.order(order_url.clone()).await?;
                 let mut authorizations = order.authorizations();
@@ -358,27 +534,47 @@ impl CertificateController {
                         _ => todo!(),
                     }
 
-                    let mut challenge = authz
-                        .challenge(ChallengeType::Http01)
-                        .ok_or_else(|| anyhow::anyhow!("no http01 challenge found"))?;
+                    let challenge_type =
+                        Self::acme_challenge_type(ctx, &authz.identifier().to_string());
+                    let mut challenge =
+                        authz.challenge(challenge_type.clone()).ok_or_else(|| {
+                            anyhow::anyhow!("no {:?} challenge found", challenge_type)
+                        })?;
 
-                    let identifier = challenge.identifier();
+                    let identifier = challenge.identifier().to_string();
 
-                    let key_authorization = challenge.key_authorization();
-                    let key_authorization = key_authorization.as_str();
+                    if challenge_type == ChallengeType::Dns01 {
+                        let record_name = DnsAgent::acme_challenge_record_name(&identifier);
+                        let dns_value = challenge.key_authorization().dns_value();
 
-                    info!(
-                        "HTTP challenge for {} is pending, key authorization: {}",
-                        identifier, key_authorization
-                    );
+                        info!(
+                            "DNS challenge for {} is pending, publishing {}",
+                            identifier, record_name
+                        );
+
+                        ctx.agent
+                            .dns()
+                            .publish_txt_record(&record_name, &dns_value)?;
+                        cert_agent
+                            .store_challenge(identifier, dns_value, "dns-01".to_string())
+                            .await?;
+                    } else {
+                        let key_authorization = challenge.key_authorization();
+                        let key_authorization = key_authorization.as_str();
+
+                        info!(
+                            "HTTP challenge for {} is pending, key authorization: {}",
+                            identifier, key_authorization
+                        );
 
-                    cert_agent
-                        .store_challenge(
-                            identifier.to_string(),
-                            key_authorization.to_string(),
-                            "http-01".to_string(),
-                        )
-                        .await?;
+                        cert_agent
+                            .store_challenge(
+                                identifier,
+                                key_authorization.to_string(),
+                                "http-01".to_string(),
+                            )
+                            .await?;
+                    }
 
                     challenge.set_ready().await?;
                 }
@@ -394,6 +590,7 @@ impl CertificateController {
                 let account = cert_agent.get_acme_account(provider, email).await?.unwrap();
                 let mut order = account.order(order_url.clone()).await?;
                 let order_status = order.poll_ready(&RetryPolicy::default()).await?;
+                Self::remove_acme_challenge_records(ctx, domains)?;
                 if order_status != OrderStatus::Ready {
                     status.state = CertificateState::Failed;
                     status.last_failure_reason = Some("Order not ready".to_string());
@@ -410,7 +607,12 @@ impl CertificateController {
 
                 let account = cert_agent.get_acme_account(provider, email).await?.unwrap();
                 let mut order = account.order(order_url.clone()).await?;
-                let private_key_pem = order.finalize().await?;
+                // our own key rather than the one instant-acme would pick, so the algorithm is ours
+                let key = generate_certificate_key(key_algorithm)?;
+                order
+                    .finalize_csr(&certificate_signing_request(&key, domains)?)
+                    .await?;
+                let private_key_pem = key.serialize_pem();
                 let cert_chain_pem = order.poll_certificate(&RetryPolicy::default()).await?;
                 cert_agent
                     .store_certificate(
@@ -422,10 +624,18 @@ impl CertificateController {
 
                 let (not_before, not_after) =
                     cert_agent.parse_certificate_validity(&cert_chain_pem)?;
+                let renewal_time = not_after - chrono::Duration::days(days_before_expiry as i64);
+                let details = cert_agent.inspect_certificate_chain(&cert_chain_pem)?;
                 status.state = CertificateState::Ready;
                 status.not_before = Some(not_before.to_rfc3339());
                 status.not_after = Some(not_after.to_rfc3339());
+                status.renewal_time = Some(renewal_time.to_rfc3339());
+                status.renewing_since = None;
                 status.last_failure_reason = None;
+                status.last_failure_time = None;
+                status.renewal_attempts = None;
+                status.key_algorithm = Some(key_algorithm.clone());
+                set_certificate_details(status, details);
 
                 ctx.agent
                     .proxy()
@@ -439,11 +649,34 @@ impl CertificateController {
             }
 
             CertificateState::Ready => {
-                // Certificate is active, check for renewal
-                info!("Certificate in Ready state, checking renewal requirements");
+                // Certificate is active, the renewal loop schedules it again once renewal is due
+                if certificate_renewal_due(status, chrono::Utc::now()) {
+                    info!(
+                        "Certificate for {:?} expires on {}, renewing",
+                        domains,
+                        status.not_after.as_deref().unwrap_or_default()
+                    );
+                    status.state = CertificateState::Renewing;
+                    status.renewing_since = Some(chrono::Utc::now().to_rfc3339());
+                    return Ok(ReconcileNext::Immediate);
+                }
 
-                // TODO: Check certificate expiry and trigger renewal if needed
-                Ok(ReconcileNext::After(Duration::from_secs(3600))) // Check hourly
+                // certificates issued before the algorithm was recorded got the default one
+                let issued_key_algorithm = status
+                    .key_algorithm
+                    .clone()
+                    .unwrap_or(DEFAULT_CERTIFICATE_KEY_ALGORITHM);
+                if &issued_key_algorithm != key_algorithm {
+                    info!(
+                        "Certificate for {:?} key algorithm changed from {:?} to {:?}, reissuing",
+                        domains, issued_key_algorithm, key_algorithm
+                    );
+                    status.state = CertificateState::Renewing;
+                    status.renewing_since = Some(chrono::Utc::now().to_rfc3339());
+                    return Ok(ReconcileNext::Immediate);
+                }
+
+                Ok(ReconcileNext::done())
             }
 
             CertificateState::Renewing => {
@@ -452,6 +685,7 @@ impl CertificateController {
 
                 // Renewal follows similar flow to initial issuance
                 status.state = CertificateState::Pending;
+                count_issuance_attempt(status);
                 Ok(ReconcileNext::Immediate)
             }
 
@@ -461,6 +695,14 @@ impl CertificateController {
 
                 // Reset to initial state to retry
                 status.state = CertificateState::Pending;
+                count_issuance_attempt(status);
+
+                // a failed renewal still has a valid certificate, no need to hammer the provider
+                if status.renewing_since.is_some() {
+                    return Ok(ReconcileNext::After(Duration::from_secs(
+                        retry_interval_hours as u64 * 3600,
+                    )));
+                }
                 Ok(ReconcileNext::After(Duration::from_secs(60)))
             }
 
@@ -533,6 +775,18 @@ impl Controller for CertificateController {
         else {
             // cleanup status if resource is deleted
 
+            ctx.agent
+                .certificate()
+                .delete_internal_certificate(
+                    &tenant,
+                    &metadata
+                        .namespace
+                        .clone()
+                        .unwrap_or(DEFAULT_NAMESPACE.to_string()),
+                    &metadata.name,
+                )
+                .ok();
+
             let status = cert_repo.get_status(metadata.clone())?;
             if let Some(status) = status {
                 ctx.agent
@@ -552,6 +806,7 @@ impl Controller for CertificateController {
         };
 
         let cert = cert.latest();
+        let domains = normalize_certificate_domains(&cert.domains)?;
 
         // Get current status
         let mut status =
@@ -562,22 +817,41 @@ impl Controller for CertificateController {
                     not_before: None,
                     not_after: None,
                     last_failure_reason: None,
+                    last_failure_time: None,
+                    renewal_attempts: None,
+                    issuer_name: None,
+                    subject_alt_names: None,
+                    chain: None,
                     renewal_time: None,
-                    domains: cert.domains.clone(),
+                    renewing_since: None,
+                    domains: domains.clone(),
                     auto_provider_name: None,
+                    key_algorithm: None,
+                    conditions: vec![],
                 });
 
+        let key_algorithm = cert
+            .key_algorithm
+            .clone()
+            .unwrap_or(DEFAULT_CERTIFICATE_KEY_ALGORITHM);
+
+        let previous_status = status.clone();
+
         // Handle based on issuer type and current state
         let next_reconcile = match &cert.issuer {
             CertificateIssuer::Auto {
-                provider, email, ..
+                provider,
+                email,
+                renewal,
             } => {
                 self.reconcile_auto_certificate(
                     &ctx,
                     &mut status,
                     provider.as_str(),
                     email.as_deref(),
-                    &cert.domains,
+                    renewal.as_ref(),
+                    &key_algorithm,
+                    &domains,
                 )
                 .await?
             }
@@ -590,12 +864,46 @@ impl Controller for CertificateController {
                     "Manual certificate configured with cert: {} and key: {}",
                     cert_path, key_path
                 );
+
+                let cert_agent = ctx.agent.certificate();
+                match tokio::fs::read_to_string(cert_path).await {
+                    Ok(cert_chain_pem) => {
+                        let (not_before, not_after) =
+                            cert_agent.parse_certificate_validity(&cert_chain_pem)?;
+                        status.not_before = Some(not_before.to_rfc3339());
+                        status.not_after = Some(not_after.to_rfc3339());
+                        set_certificate_details(
+                            &mut status,
+                            cert_agent.inspect_certificate_chain(&cert_chain_pem)?,
+                        );
+                    }
+                    Err(e) => warn!("Failed to read manual certificate {}: {}", cert_path, e),
+                }
+
                 status.state = CertificateState::Ready;
                 status.last_failure_reason = None;
                 ReconcileNext::After(Duration::from_secs(3600)) // Check hourly for manual certs
             }
+            CertificateIssuer::Internal { ttl_hours } => {
+                self.reconcile_internal_certificate(
+                    &ctx,
+                    &mut status,
+                    &metadata,
+                    ttl_hours.unwrap_or(DEFAULT_INTERNAL_CERT_TTL_HOURS),
+                    &key_algorithm,
+                    &domains,
+                )
+                .await?
+            }
         };
 
+        // manual certificates are only read, there is nothing to report
+        if !matches!(cert.issuer, CertificateIssuer::Manual { .. }) {
+            if let Some((reason, message)) = issuance_event(&previous_status, &status) {
+                ctx.agent.events().emit(&key, reason, message);
+            }
+        }
+
         // Update status
         cert_repo.set_status(metadata, status).await?;
 
@@ -622,13 +930,20 @@ impl Controller for CertificateController {
             .certificate(ctx.tenant.clone())
             .get_status(metadata.clone())
         {
+            let previous_status = status.clone();
             status.state = CertificateState::Failed;
             status.last_failure_reason = Some(error.to_string());
+            status.last_failure_time = Some(chrono::Utc::now().to_rfc3339());
+
+            if let Some((reason, message)) = issuance_event(&previous_status, &status) {
+                ctx.agent.events().emit(&key, reason, message);
+            }
 
             let _ = ctx
                 .repository
                 .certificate(ctx.tenant)
-                .set_status(metadata, status);
+                .set_status(metadata, status)
+                .await;
         }
 
         // Retry after 30 seconds
@@ -663,12 +978,49 @@ impl AdmissionCheckBeforeSet for Certificate {
             }
         }
 
-        let domains = resource.domains.clone();
+        let domains = normalize_certificate_domains(&resource.domains)?;
+
+        let cert_agent = agent.certificate();
+        let internal = matches!(resource.issuer, CertificateIssuer::Internal { .. });
+        for domain in domains.iter() {
+            if internal && !cert_agent.is_internal_domain(domain) {
+                bail!(
+                    "Internal certificates only cover internal service hostnames, got: {}",
+                    domain
+                );
+            }
+            if !internal && cert_agent.is_internal_domain(domain) {
+                bail!(
+                    "Internal service hostname {} can only be issued by the internal issuer",
+                    domain
+                );
+            }
+        }
+
+        let dns = agent.dns();
+        for domain in domains.iter() {
+            let host = domain.trim_start_matches("*.");
+            if dns.is_region_domain(host) && !dns.is_tenant_owned_region_domain(&tenant, host) {
+                bail!("Your tenant does not own the domain: {}", domain);
+            }
+
+            // wildcard domains can only be validated over dns-01
+            if domain.starts_with("*.")
+                && matches!(resource.issuer, CertificateIssuer::Auto { .. })
+                && !dns.can_publish_acme_challenge(domain)
+            {
+                bail!(
+                    "Wildcard domain {} can only be issued automatically in the region root domain",
+                    domain
+                );
+            }
+        }
 
         if let Some(before) = before {
             let before = before.latest();
             // figure out which domains were before and not in the new domains
-            let old_domains = before.domains.clone();
+            let old_domains =
+                normalize_certificate_domains(&before.domains).unwrap_or(before.domains.clone());
             let new_domains = domains.clone();
             let old_domains = old_domains
                 .iter()
@@ -681,6 +1033,11 @@ impl AdmissionCheckBeforeSet for Certificate {
             }
         }
 
+        // internal hostnames resolve within the tenant, there is no ownership to claim
+        if internal {
+            return Ok(());
+        }
+
         let kinds = domains
             .iter()
             .map(|domain| TrackedResourceKind::CertificateDomain(domain.clone()))
@@ -724,8 +1081,12 @@ impl AdmissionCheckBeforeDelete for Certificate {
         _metadata: Metadata,
     ) -> Result<()> {
         let resource = self.latest();
+        if matches!(resource.issuer, CertificateIssuer::Internal { .. }) {
+            return Ok(());
+        }
 
-        let domains = resource.domains.clone();
+        let domains =
+            normalize_certificate_domains(&resource.domains).unwrap_or(resource.domains.clone());
         let kinds = domains
             .iter()
             .map(|domain| TrackedResourceKind::CertificateDomain(domain.clone()))
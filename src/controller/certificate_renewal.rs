@@ -0,0 +1,135 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use tracing::{info, warn};
+
+use crate::{
+    controller::{context::ControllerEvent, scheduler::Scheduler},
+    resource_index::ResourceKind,
+    resources::{
+        ProvideMetadata,
+        certificate::{CertificateIssuer, CertificateState, CertificateStatus},
+        metadata::Namespace,
+    },
+};
+
+/// Days before expiry to renew a certificate, unless configured on it or in the daemon config.
+pub const DEFAULT_RENEWAL_DAYS_BEFORE_EXPIRY: u32 = 30;
+
+/// Hours between renewal attempts of a certificate whose renewal failed, unless configured on it.
+pub const DEFAULT_RENEWAL_RETRY_INTERVAL_HOURS: u32 = 12;
+
+pub const DEFAULT_RENEWAL_SCAN_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Whether the certificate is issued and its renewal time has come.
+pub fn certificate_renewal_due(status: &CertificateStatus, now: DateTime<Utc>) -> bool {
+    if status.state != CertificateState::Ready {
+        return false;
+    }
+
+    status
+        .renewal_time
+        .as_deref()
+        .and_then(|renewal_time| DateTime::parse_from_rfc3339(renewal_time).ok())
+        .is_some_and(|renewal_time| renewal_time <= now)
+}
+
+/// Periodically looks for certificates due for renewal and hands them over to the certificate
+/// controller, which re-orders them while the current certificate keeps being served.
+pub fn start_certificate_renewal(scheduler: Arc<Scheduler>, scan_interval: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(scan_interval);
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = schedule_due_renewals(&scheduler).await {
+                warn!("Failed to scan certificates for renewal: {}", e);
+            }
+        }
+    });
+}
+
+async fn schedule_due_renewals(scheduler: &Scheduler) -> Result<()> {
+    let now = Utc::now();
+
+    for tenant in scheduler.store.list_tenants()? {
+        let cert_repo = scheduler.repository.certificate(tenant.clone());
+        for certificate in cert_repo.list(Namespace::Unspecified)? {
+            if matches!(
+                certificate.latest().issuer,
+                CertificateIssuer::Manual { .. }
+            ) {
+                continue;
+            }
+
+            let metadata = certificate.metadata();
+            let Some(status) = cert_repo.get_status(metadata.clone())? else {
+                continue;
+            };
+            if !certificate_renewal_due(&status, now) {
+                continue;
+            }
+
+            info!(
+                "Certificate {} of tenant {} is due for renewal",
+                metadata.to_string(),
+                tenant
+            );
+            scheduler
+                .push(
+                    tenant.clone(),
+                    ControllerEvent::ResourceChange(ResourceKind::Certificate, metadata),
+                )
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ready_status(renewal_time: Option<&str>) -> CertificateStatus {
+        CertificateStatus {
+            state: CertificateState::Ready,
+            not_before: None,
+            not_after: None,
+            last_failure_reason: None,
+            last_failure_time: None,
+            renewal_attempts: None,
+            issuer_name: None,
+            subject_alt_names: None,
+            chain: None,
+            renewal_time: renewal_time.map(|t| t.to_string()),
+            renewing_since: None,
+            domains: vec!["example.com".to_string()],
+            auto_provider_name: Some("letsencrypt".to_string()),
+            key_algorithm: None,
+            conditions: vec![],
+        }
+    }
+
+    #[test]
+    fn test_certificate_renewal_due() {
+        let now = DateTime::parse_from_rfc3339("2025-06-01T00:00:00+00:00")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        assert!(certificate_renewal_due(
+            &ready_status(Some("2025-05-31T00:00:00+00:00")),
+            now
+        ));
+        assert!(!certificate_renewal_due(
+            &ready_status(Some("2025-06-02T00:00:00+00:00")),
+            now
+        ));
+        assert!(!certificate_renewal_due(&ready_status(None), now));
+
+        let mut renewing = ready_status(Some("2025-05-31T00:00:00+00:00"));
+        renewing.state = CertificateState::Renewing;
+        assert!(!certificate_renewal_due(&renewing, now));
+    }
+}
@@ -1,4 +1,4 @@
-use std::{sync::Arc, time::Duration};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
 
 use crate::{
     agent::{Agent, machine::machine::MachineState},
@@ -37,10 +37,22 @@ impl ControllerKey {
                 self.name.clone(),
                 Namespace::from_value_or_default(self.namespace.clone()),
             ),
+            ResourceKind::DnsRecord => Metadata::new(
+                self.name.clone(),
+                Namespace::from_value_or_default(self.namespace.clone()),
+            ),
             ResourceKind::Machine => Metadata::new(
                 self.name.clone(),
                 Namespace::from_value_or_default(self.namespace.clone()),
             ),
+            ResourceKind::Cron => Metadata::new(
+                self.name.clone(),
+                Namespace::from_value_or_default(self.namespace.clone()),
+            ),
+            ResourceKind::MachineGroup => Metadata::new(
+                self.name.clone(),
+                Namespace::from_value_or_default(self.namespace.clone()),
+            ),
             ResourceKind::Service => Metadata::new(
                 self.name.clone(),
                 Namespace::from_value_or_default(self.namespace.clone()),
@@ -99,6 +111,8 @@ pub enum AsyncWork {
     ImagePullComplete {
         id: String,
         reference: String,
+        labels: BTreeMap<String, String>,
+        annotations: BTreeMap<String, String>,
     },
     ImageNeedsPull,
     MachineStateChange {
@@ -0,0 +1,388 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{Result, anyhow, bail};
+use async_trait::async_trait;
+use tracing::{error, info, warn};
+
+use crate::{
+    agent::Agent,
+    constants::DEFAULT_NAMESPACE,
+    controller::{
+        AdmissionCheckBeforeSet, Controller, ReconcileNext,
+        context::{ControllerContext, ControllerEvent, ControllerKey},
+    },
+    repository::Repository,
+    resource_index::ResourceKind,
+    resources::{
+        Convert,
+        core::EventReason,
+        cron::{
+            Cron, CronLatest, CronRun, CronRunState, DEFAULT_CRON_HISTORY,
+            DEFAULT_CRON_TIMEOUT_SECS,
+        },
+        machine::{Machine, MachinePhase, MachineRestartPolicy, MachineV1},
+        metadata::{Metadata, Namespace},
+    },
+    utils::{cron::CronSchedule, time::now_millis},
+};
+
+/// Tag of the machines running a cron, followed by the cron name.
+pub const CRON_TAG_PREFIX: &str = "ignitiond.cron=";
+
+/// Runs are checked this often for their timeout, they report stopping on their own.
+const RUN_POLL_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_SCHEDULE_WAIT: Duration = Duration::from_secs(5 * 60);
+
+pub struct CronController;
+
+impl CronController {
+    pub fn new_boxed() -> Box<Self> {
+        Box::new(Self)
+    }
+}
+
+#[async_trait]
+impl Controller for CronController {
+    async fn schedule(
+        &self,
+        ctx: ControllerContext,
+        event: ControllerEvent,
+    ) -> Result<Option<ControllerKey>> {
+        let key = match event {
+            ControllerEvent::ResourceChange(ResourceKind::Cron, metadata)
+            | ControllerEvent::BringUp(ResourceKind::Cron, metadata) => Some(ControllerKey::new(
+                ctx.tenant.clone(),
+                ResourceKind::Cron,
+                metadata.namespace,
+                metadata.name,
+            )),
+            ControllerEvent::ResourceStatusChange(ResourceKind::Machine, metadata) => {
+                // the machine of a run changed phase, it may have finished
+                let Some(machine) = ctx.repository.machine(ctx.tenant.clone()).get(
+                    Namespace::from_value(metadata.namespace.clone()),
+                    &metadata.name,
+                )?
+                else {
+                    return Ok(None);
+                };
+
+                machine
+                    .latest()
+                    .tags
+                    .unwrap_or_default()
+                    .iter()
+                    .find_map(|tag| tag.strip_prefix(CRON_TAG_PREFIX))
+                    .map(|cron| {
+                        ControllerKey::new(
+                            ctx.tenant.clone(),
+                            ResourceKind::Cron,
+                            metadata.namespace.clone(),
+                            cron,
+                        )
+                    })
+            }
+            _ => None,
+        };
+        Ok(key)
+    }
+
+    async fn should_reconcile(&self, _ctx: ControllerContext, key: ControllerKey) -> bool {
+        key.kind == ResourceKind::Cron
+    }
+
+    async fn reconcile(&self, ctx: ControllerContext, key: ControllerKey) -> Result<ReconcileNext> {
+        info!("reconciling cron controller for key: {}", key.to_string());
+
+        let cron_repo = ctx.repository.cron(ctx.tenant.clone());
+        let machine_repo = ctx.repository.machine(ctx.tenant.clone());
+
+        let Some((cron, status)) = cron_repo.get_with_status(key.metadata())? else {
+            // the cron was deleted, so is its run in progress
+            let Some(status) = cron_repo.get_status(key.metadata())? else {
+                return Ok(ReconcileNext::done());
+            };
+
+            if let Some(run) = status.active_run() {
+                machine_repo
+                    .delete(
+                        Namespace::from_value(key.metadata().namespace),
+                        &run.machine,
+                    )
+                    .await
+                    .ok();
+            }
+
+            cron_repo.delete_status(key.metadata()).await?;
+
+            return Ok(ReconcileNext::done());
+        };
+
+        let hash = cron.hash_with_updated_metadata();
+        let cron = cron.latest();
+        let namespace = Namespace::from_value(cron.namespace.clone())
+            .as_value()
+            .unwrap_or(DEFAULT_NAMESPACE.to_string());
+        let schedule = CronSchedule::parse(&cron.schedule)?;
+        let now = now_millis();
+        let mut status = status;
+
+        if let Some(run) = status
+            .runs
+            .iter_mut()
+            .find(|run| run.state == CronRunState::Running)
+        {
+            finish_run(&ctx, &key, &cron, &namespace, run, now).await?;
+        }
+
+        // a new schedule moves the next run
+        if status.hash != hash || status.next_run_at.is_none() {
+            status.next_run_at = next_run_at(&schedule, now);
+        }
+        status.hash = hash;
+
+        if status.next_run_at.is_some_and(|at| at <= now) {
+            if status.active_run().is_some() {
+                warn!(
+                    "skipping run of cron {}, the previous one is still going",
+                    key.to_string()
+                );
+            } else {
+                let run = start_run(&ctx, &key, &cron, &namespace, now).await?;
+                status.runs.insert(0, run);
+            }
+
+            status.next_run_at = next_run_at(&schedule, now);
+        }
+
+        let history = cron.history.unwrap_or(DEFAULT_CRON_HISTORY) as usize;
+        let mut finished = 0;
+        status.runs.retain(|run| {
+            if run.state == CronRunState::Running {
+                return true;
+            }
+            finished += 1;
+            finished <= history
+        });
+
+        let active = status.active_run().is_some();
+        let next_run_at = status.next_run_at;
+        cron_repo.set_status(key.metadata(), status).await?;
+
+        if active {
+            return Ok(ReconcileNext::after(RUN_POLL_INTERVAL));
+        }
+
+        match next_run_at {
+            Some(at) => Ok(ReconcileNext::after(
+                Duration::from_millis(at.saturating_sub(now)).min(MAX_SCHEDULE_WAIT),
+            )),
+            None => Ok(ReconcileNext::done()),
+        }
+    }
+
+    async fn handle_error(
+        &self,
+        _ctx: ControllerContext,
+        key: ControllerKey,
+        err: anyhow::Error,
+    ) -> ReconcileNext {
+        error!(
+            "handling error for cron controller for key: {} error: {}",
+            key.to_string(),
+            err
+        );
+
+        ReconcileNext::after(MAX_SCHEDULE_WAIT)
+    }
+}
+
+fn next_run_at(schedule: &CronSchedule, now: u64) -> Option<u64> {
+    schedule.next_after(now / 1000).map(|secs| secs * 1000)
+}
+
+/// The machine of a run, it runs the command once and stays stopped.
+fn run_machine(cron: &CronLatest, namespace: &str, name: String) -> MachineV1 {
+    let mut tags = cron.tags.clone().unwrap_or_default();
+    tags.push(format!("{}{}", CRON_TAG_PREFIX, cron.name));
+
+    MachineV1 {
+        name,
+        namespace: Some(namespace.to_string()),
+        tags: Some(tags),
+        image: cron.image.clone(),
+        build: None,
+        build_env: None,
+        resources: cron.resources.clone(),
+        restart_policy: Some(MachineRestartPolicy::Never),
+        mode: None,
+        volumes: cron.volumes.clone(),
+        scratch: None,
+        ephemeral_volumes: None,
+        command: cron.command.clone(),
+        environment: cron.environment.clone(),
+        depends_on: None,
+        health: None,
+        kernel_params: None,
+        rollout: None,
+        suspend: None,
+        ttl: None,
+        placement: None,
+        network: None,
+    }
+}
+
+async fn start_run(
+    ctx: &ControllerContext,
+    key: &ControllerKey,
+    cron: &CronLatest,
+    namespace: &str,
+    now: u64,
+) -> Result<CronRun> {
+    let machine_repo = ctx.repository.machine(ctx.tenant.clone());
+    let name = format!("{}-{}", cron.name, now / 1000);
+    let machine_namespace = Namespace::from_value(Some(namespace.to_string()));
+
+    if machine_repo
+        .get(machine_namespace.clone(), &name)?
+        .is_some()
+    {
+        bail!(
+            "machine {} already exists, it can't run cron {}",
+            name,
+            cron.name
+        );
+    }
+
+    // the runs are written by the controller, they go through the checks the user's machines do
+    let machine = Machine::V1(run_machine(cron, namespace, name.clone()));
+    machine
+        .before_set(
+            None,
+            ctx.tenant.clone(),
+            ctx.repository.clone(),
+            ctx.agent.clone(),
+            Metadata::new(&name, machine_namespace),
+        )
+        .await
+        .map_err(|e| anyhow!("run {} of cron {} can't start: {}", name, cron.name, e))?;
+
+    info!("starting run {} of cron {}", name, key.to_string());
+    machine_repo.set(machine).await?;
+
+    ctx.agent.events().emit(
+        key,
+        EventReason::CronRunStarted,
+        format!("Started run {}", name),
+    );
+
+    Ok(CronRun {
+        machine: name,
+        started_at: now,
+        finished_at: None,
+        state: CronRunState::Running,
+        exit_code: None,
+    })
+}
+
+/// Records how the run ended once its machine stopped, failed or ran out of time, and removes
+/// the machine.
+async fn finish_run(
+    ctx: &ControllerContext,
+    key: &ControllerKey,
+    cron: &CronLatest,
+    namespace: &str,
+    run: &mut CronRun,
+    now: u64,
+) -> Result<()> {
+    let machine_repo = ctx.repository.machine(ctx.tenant.clone());
+    let machine_namespace = Namespace::from_value(Some(namespace.to_string()));
+
+    let machine_status =
+        machine_repo.get_status(Metadata::new(&run.machine, machine_namespace.clone()))?;
+    let timeout_millis = cron.timeout.unwrap_or(DEFAULT_CRON_TIMEOUT_SECS) * 1000;
+
+    let (state, exit_code) = match machine_status {
+        Some(status) if status.phase == MachinePhase::Stopped => match status.last_exit_code {
+            Some(0) => (CronRunState::Succeeded, Some(0)),
+            exit_code => (CronRunState::Failed, exit_code),
+        },
+        Some(status) if matches!(status.phase, MachinePhase::Error { .. }) => {
+            (CronRunState::Failed, status.last_exit_code)
+        }
+        None => (CronRunState::Failed, None),
+        Some(_) if now.saturating_sub(run.started_at) >= timeout_millis => {
+            (CronRunState::TimedOut, None)
+        }
+        Some(_) => return Ok(()),
+    };
+
+    info!(
+        "run {} of cron {} {}",
+        run.machine,
+        key.to_string(),
+        state.to_string()
+    );
+
+    let (reason, message) = match (&state, exit_code) {
+        (CronRunState::Succeeded, _) => (
+            EventReason::CronRunSucceeded,
+            format!("Run {} succeeded", run.machine),
+        ),
+        (CronRunState::TimedOut, _) => (
+            EventReason::CronRunFailed,
+            format!("Run {} timed out", run.machine),
+        ),
+        (_, Some(exit_code)) => (
+            EventReason::CronRunFailed,
+            format!("Run {} failed with exit code {}", run.machine, exit_code),
+        ),
+        (_, None) => (
+            EventReason::CronRunFailed,
+            format!("Run {} failed", run.machine),
+        ),
+    };
+    ctx.agent.events().emit(key, reason, message);
+
+    machine_repo
+        .delete(machine_namespace, &run.machine)
+        .await
+        .ok();
+
+    run.state = state;
+    run.exit_code = exit_code;
+    run.finished_at = Some(now);
+
+    Ok(())
+}
+
+#[async_trait]
+impl AdmissionCheckBeforeSet for Cron {
+    async fn before_set(
+        &self,
+        _before: Option<&Self>,
+        tenant: String,
+        repo: Arc<Repository>,
+        agent: Arc<Agent>,
+        metadata: Metadata,
+    ) -> Result<()> {
+        let cron = self.latest();
+
+        CronSchedule::parse(&cron.schedule).map_err(|e| anyhow!("invalid schedule: {}", e))?;
+
+        if cron.timeout == Some(0) {
+            bail!("timeout must be greater than 0");
+        }
+
+        if cron.history == Some(0) {
+            bail!("history must keep at least 1 run");
+        }
+
+        // the runs are checked again when they start, against the quotas of that time
+        let namespace = Namespace::from_value_or_default(cron.namespace.clone())
+            .as_value()
+            .unwrap_or(DEFAULT_NAMESPACE.to_string());
+        Machine::V1(run_machine(&cron, &namespace, cron.name.clone()))
+            .before_set(None, tenant, repo, agent, metadata)
+            .await
+    }
+}
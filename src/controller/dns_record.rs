@@ -0,0 +1,262 @@
+use anyhow::{Result, bail};
+use async_trait::async_trait;
+use std::{sync::Arc, time::Duration};
+use tracing::error;
+
+use crate::{
+    agent::{
+        Agent,
+        tracker::{TrackedResourceKind, TrackedResourceOwner},
+    },
+    constants::DEFAULT_NAMESPACE,
+    controller::{
+        AdmissionCheckBeforeDelete, AdmissionCheckBeforeSet, Controller, ReconcileNext,
+        context::{ControllerContext, ControllerEvent, ControllerKey},
+    },
+    repository::Repository,
+    resource_index::ResourceKind,
+    resources::{
+        Convert,
+        dns_record::{DnsRecord, DnsRecordState, DnsRecordStatus, normalize_dns_name},
+        metadata::{Metadata, Namespace},
+    },
+};
+
+/// Record types that can share a name, an alias can't share it with anything.
+const NON_ALIAS_RECORD_TYPES: [&str; 4] = ["A", "AAAA", "TXT", "MX"];
+
+pub struct DnsRecordController;
+
+impl DnsRecordController {
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn new_boxed() -> Box<dyn Controller> {
+        Box::new(Self::new())
+    }
+}
+
+#[async_trait]
+impl Controller for DnsRecordController {
+    async fn schedule(
+        &self,
+        ctx: ControllerContext,
+        event: ControllerEvent,
+    ) -> Result<Option<ControllerKey>> {
+        match event {
+            ControllerEvent::ResourceChange(ResourceKind::DnsRecord, metadata) => {
+                Ok(Some(ControllerKey::new(
+                    ctx.tenant,
+                    ResourceKind::DnsRecord,
+                    metadata.namespace.clone(),
+                    metadata.name.clone(),
+                )))
+            }
+            ControllerEvent::BringUp(ResourceKind::DnsRecord, metadata) => {
+                Ok(Some(ControllerKey::new(
+                    ctx.tenant,
+                    ResourceKind::DnsRecord,
+                    metadata.namespace.clone(),
+                    metadata.name.clone(),
+                )))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    async fn should_reconcile(&self, _ctx: ControllerContext, key: ControllerKey) -> bool {
+        key.kind == ResourceKind::DnsRecord
+    }
+
+    async fn reconcile(&self, ctx: ControllerContext, key: ControllerKey) -> Result<ReconcileNext> {
+        if key.kind != ResourceKind::DnsRecord {
+            return Ok(ReconcileNext::done());
+        }
+
+        let metadata = key.metadata();
+        let record_repo = ctx.repository.dns_record(ctx.tenant.clone());
+        let dns = ctx.agent.dns();
+
+        let status = record_repo.get_status(metadata.clone())?;
+        let published = status
+            .as_ref()
+            .and_then(|status| Some((status.domain.clone()?, status.record_type.clone()?)));
+
+        let Some(record) = record_repo.get(
+            Namespace::from_value_or_default(metadata.namespace.clone()),
+            metadata.name.clone(),
+        )?
+        else {
+            // unpublish the record once the resource is deleted
+            if let Some((domain, record_type)) = published {
+                dns.remove_zone_record(&domain, &record_type)?;
+            }
+            record_repo.delete_status(metadata).await.ok();
+
+            return Ok(ReconcileNext::done());
+        };
+
+        let record = record.latest();
+        let domain = normalize_dns_name(&record.domain);
+        let record_type = record.record.record_type();
+
+        // the domain or the type changed, the previous record is no longer owned by the resource
+        if let Some((published_domain, published_type)) = published {
+            if published_domain != domain || published_type != record_type {
+                dns.remove_zone_record(&published_domain, &published_type)?;
+            }
+        }
+
+        dns.publish_zone_record(&domain, record.ttl, &record.record)?;
+
+        let mut status = status.unwrap_or(DnsRecordStatus {
+            state: DnsRecordState::Pending,
+            domain: None,
+            record_type: None,
+            last_failure_reason: None,
+            conditions: vec![],
+        });
+        status.state = DnsRecordState::Published;
+        status.domain = Some(domain);
+        status.record_type = Some(record_type.to_string());
+        status.last_failure_reason = None;
+
+        record_repo.set_status(metadata, status).await?;
+
+        Ok(ReconcileNext::done())
+    }
+
+    async fn handle_error(
+        &self,
+        ctx: ControllerContext,
+        key: ControllerKey,
+        error: anyhow::Error,
+    ) -> ReconcileNext {
+        if key.kind != ResourceKind::DnsRecord {
+            return ReconcileNext::done();
+        }
+
+        let metadata = key.metadata();
+
+        error!("DNS record controller error for {:?}: {}", metadata, error);
+
+        let record_repo = ctx.repository.dns_record(ctx.tenant.clone());
+        if let Ok(Some(mut status)) = record_repo.get_status(metadata.clone()) {
+            status.state = DnsRecordState::Failed;
+            status.last_failure_reason = Some(error.to_string());
+
+            let _ = record_repo.set_status(metadata, status).await;
+        }
+
+        ReconcileNext::After(Duration::from_secs(30))
+    }
+}
+
+#[async_trait]
+impl AdmissionCheckBeforeSet for DnsRecord {
+    async fn before_set(
+        &self,
+        before: Option<&Self>,
+        tenant: String,
+        _repo: Arc<Repository>,
+        agent: Arc<Agent>,
+        _metadata: Metadata,
+    ) -> Result<()> {
+        let resource = self.latest();
+        resource.validate()?;
+
+        let dns = agent.dns();
+        if !dns.serves_region_zone() {
+            bail!(
+                "DNS records can't be published, the region root domain isn't served by this node"
+            );
+        }
+
+        let domain = normalize_dns_name(&resource.domain);
+        if !dns.is_tenant_owned_region_domain(&tenant, &domain) {
+            bail!("Your tenant does not own the domain: {}", domain);
+        }
+
+        let record_type = resource.record.record_type();
+        let resource_owner = TrackedResourceOwner {
+            kind: TrackedResourceKind::DnsRecord(domain.clone(), record_type.to_string()),
+            tenant: tenant.clone(),
+            resource_name: resource.name.clone(),
+            resource_namespace: resource
+                .namespace
+                .clone()
+                .unwrap_or(DEFAULT_NAMESPACE.to_string()),
+        };
+
+        // an alias can't share its name with other records
+        let conflicting_types = if record_type == "CNAME" {
+            NON_ALIAS_RECORD_TYPES.to_vec()
+        } else {
+            vec!["CNAME"]
+        };
+        for conflicting_type in conflicting_types {
+            let kind = TrackedResourceKind::DnsRecord(domain.clone(), conflicting_type.to_string());
+            if let Some(owner) = agent.tracker().get_tracked_resource_owner(kind).await? {
+                if owner.resource_name != resource_owner.resource_name
+                    || owner.resource_namespace != resource_owner.resource_namespace
+                {
+                    bail!(
+                        "{} record can't be published next to the {} record of {}",
+                        record_type,
+                        conflicting_type,
+                        domain
+                    );
+                }
+            }
+        }
+
+        if let Some(owner) = agent
+            .tracker()
+            .get_tracked_resource_owner(resource_owner.kind.clone())
+            .await?
+        {
+            if owner != resource_owner {
+                bail!(
+                    "{} record of {} is already published by another resource",
+                    record_type,
+                    domain
+                );
+            }
+        }
+
+        if let Some(before) = before {
+            let before = before.latest();
+            let before_kind = TrackedResourceKind::DnsRecord(
+                normalize_dns_name(&before.domain),
+                before.record.record_type().to_string(),
+            );
+            if before_kind != resource_owner.kind {
+                agent.tracker().untrack_resource_owner(before_kind).await?;
+            }
+        }
+
+        agent.tracker().track_resource_owner(resource_owner).await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AdmissionCheckBeforeDelete for DnsRecord {
+    async fn before_delete(
+        &self,
+        _tenant: String,
+        _repo: Arc<Repository>,
+        agent: Arc<Agent>,
+        _metadata: Metadata,
+    ) -> Result<()> {
+        let resource = self.latest();
+        let kind = TrackedResourceKind::DnsRecord(
+            normalize_dns_name(&resource.domain),
+            resource.record.record_type().to_string(),
+        );
+
+        agent.tracker().untrack_resource_owner(kind).await
+    }
+}
@@ -0,0 +1,298 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{Result, anyhow, bail};
+use tracing::{info, warn};
+
+use crate::{
+    constants::DEFAULT_TTL_WARNING_MINS,
+    controller::{context::ControllerKey, scheduler::Scheduler},
+    resource_index::ResourceKind,
+    resources::{
+        Convert, ProvideMetadata,
+        core::EventReason,
+        metadata::{Metadata, Namespace},
+    },
+    utils::time::now_millis,
+};
+
+pub const DEFAULT_EXPIRY_SCAN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Parses the `ttl` of a machine or an app, eg. `3d` or `12h`.
+pub fn parse_ttl(ttl: &str) -> Result<Duration> {
+    let ttl = humantime::parse_duration(ttl).map_err(|e| anyhow!("invalid ttl {}: {}", ttl, e))?;
+    if ttl.is_zero() {
+        bail!("ttl must be greater than 0");
+    }
+
+    Ok(ttl)
+}
+
+/// Where a resource is in its TTL, as kept in its status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ExpiryState {
+    started_at: Option<u64>,
+    warned: Option<bool>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum ExpiryAction {
+    Keep,
+    Warn(Duration),
+    Delete,
+}
+
+/// The warning goes out an hour before the deletion, or halfway through shorter TTLs.
+fn warning_window(ttl: Duration) -> Duration {
+    Duration::from_secs(DEFAULT_TTL_WARNING_MINS * 60).min(ttl / 2)
+}
+
+/// The TTL counts from the first scan that sees it, changing it moves the deletion but not the
+/// start.
+fn next_expiry(ttl: Option<Duration>, state: ExpiryState, now: u64) -> (ExpiryState, ExpiryAction) {
+    let Some(ttl) = ttl else {
+        let cleared = ExpiryState {
+            started_at: None,
+            warned: None,
+        };
+        return (cleared, ExpiryAction::Keep);
+    };
+
+    let started_at = state.started_at.unwrap_or(now);
+    let expires_at = started_at.saturating_add(ttl.as_millis() as u64);
+    if expires_at <= now {
+        let next = ExpiryState {
+            started_at: Some(started_at),
+            warned: state.warned,
+        };
+        return (next, ExpiryAction::Delete);
+    }
+
+    let remaining = Duration::from_millis(expires_at - now);
+    let in_window = remaining <= warning_window(ttl);
+    let action = if in_window && state.warned != Some(true) {
+        ExpiryAction::Warn(remaining)
+    } else {
+        ExpiryAction::Keep
+    };
+
+    let next = ExpiryState {
+        started_at: Some(started_at),
+        // a longer TTL takes the warning back
+        warned: in_window.then_some(true),
+    };
+    (next, action)
+}
+
+/// Periodically deletes the machines and apps whose TTL ran out, warning about it first.
+pub fn start_expiry(scheduler: Arc<Scheduler>, scan_interval: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(scan_interval);
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = expire_resources(&scheduler).await {
+                warn!("Failed to scan resources for expiry: {}", e);
+            }
+        }
+    });
+}
+
+fn resource_ttl(kind: &str, metadata: &Metadata, ttl: Option<&str>) -> Option<Duration> {
+    match ttl.map(parse_ttl).transpose() {
+        Ok(ttl) => ttl,
+        Err(e) => {
+            warn!("Ignoring ttl of {} {}: {}", kind, metadata.to_string(), e);
+            None
+        }
+    }
+}
+
+fn emit_expiry_event(
+    scheduler: &Scheduler,
+    tenant: &str,
+    kind: ResourceKind,
+    metadata: &Metadata,
+    action: &ExpiryAction,
+) {
+    let key = ControllerKey::new(
+        tenant.to_string(),
+        kind,
+        metadata.namespace.clone(),
+        metadata.name.clone(),
+    );
+
+    match action {
+        ExpiryAction::Keep => {}
+        ExpiryAction::Warn(remaining) => scheduler.agent.events().emit(
+            &key,
+            EventReason::Expiring,
+            format!(
+                "TTL runs out in {}, deleting afterwards",
+                humantime::format_duration(Duration::from_secs(remaining.as_secs()))
+            ),
+        ),
+        ExpiryAction::Delete => {
+            scheduler
+                .agent
+                .events()
+                .emit(&key, EventReason::Expired, "TTL ran out, deleting");
+        }
+    }
+}
+
+async fn expire_resources(scheduler: &Scheduler) -> Result<()> {
+    let now = now_millis();
+
+    for tenant in scheduler.store.list_tenants()? {
+        let machine_repo = scheduler.repository.machine(tenant.clone());
+        for machine in machine_repo.list(Namespace::Unspecified)? {
+            let metadata = machine.metadata();
+            let Some(status) = machine_repo.get_status(metadata.clone())? else {
+                continue;
+            };
+
+            let ttl = resource_ttl("machine", &metadata, machine.latest().ttl.as_deref());
+            let state = ExpiryState {
+                started_at: status.ttl_started_at,
+                warned: status.ttl_warned,
+            };
+            let (next, action) = next_expiry(ttl, state, now);
+
+            if next != state {
+                machine_repo
+                    .patch_status(metadata.clone(), move |status| {
+                        status.ttl_started_at = next.started_at;
+                        status.ttl_warned = next.warned;
+                    })
+                    .await?;
+            }
+
+            emit_expiry_event(
+                scheduler,
+                &tenant,
+                ResourceKind::Machine,
+                &metadata,
+                &action,
+            );
+            if action == ExpiryAction::Delete {
+                info!(
+                    "Machine {} of tenant {} expired, deleting it",
+                    metadata.to_string(),
+                    tenant
+                );
+                machine_repo
+                    .delete(
+                        Namespace::from_value(metadata.namespace.clone()),
+                        &metadata.name,
+                    )
+                    .await?;
+            }
+        }
+
+        let app_repo = scheduler.repository.app(tenant.clone());
+        for app in app_repo.list(Namespace::Unspecified)? {
+            let metadata = app.metadata();
+            let Some(status) = app_repo.get_status(metadata.clone())? else {
+                continue;
+            };
+
+            let ttl = resource_ttl("app", &metadata, app.latest().ttl.as_deref());
+            let state = ExpiryState {
+                started_at: status.ttl_started_at,
+                warned: status.ttl_warned,
+            };
+            let (next, action) = next_expiry(ttl, state, now);
+
+            if next != state {
+                app_repo
+                    .patch_status(metadata.clone(), move |status| {
+                        status.ttl_started_at = next.started_at;
+                        status.ttl_warned = next.warned;
+                    })
+                    .await?;
+            }
+
+            // the app takes its machine and services along
+            emit_expiry_event(scheduler, &tenant, ResourceKind::App, &metadata, &action);
+            if action == ExpiryAction::Delete {
+                info!(
+                    "App {} of tenant {} expired, deleting it",
+                    metadata.to_string(),
+                    tenant
+                );
+                app_repo
+                    .delete(
+                        Namespace::from_value(metadata.namespace.clone()),
+                        &metadata.name,
+                    )
+                    .await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR_MS: u64 = 60 * 60 * 1000;
+
+    fn state(started_at: Option<u64>, warned: Option<bool>) -> ExpiryState {
+        ExpiryState { started_at, warned }
+    }
+
+    #[test]
+    fn test_ttl_starts_on_first_scan() {
+        let ttl = Some(Duration::from_secs(24 * 60 * 60));
+
+        assert_eq!(
+            next_expiry(ttl, state(None, None), 1_000),
+            (state(Some(1_000), None), ExpiryAction::Keep)
+        );
+        assert_eq!(
+            next_expiry(None, state(Some(1_000), Some(true)), 2_000),
+            (state(None, None), ExpiryAction::Keep)
+        );
+    }
+
+    #[test]
+    fn test_warns_once_before_deleting() {
+        let ttl = Some(Duration::from_secs(24 * 60 * 60));
+
+        assert_eq!(
+            next_expiry(ttl, state(Some(0), None), 23 * HOUR_MS),
+            (
+                state(Some(0), Some(true)),
+                ExpiryAction::Warn(Duration::from_secs(60 * 60))
+            )
+        );
+        assert_eq!(
+            next_expiry(ttl, state(Some(0), Some(true)), 23 * HOUR_MS + 1),
+            (state(Some(0), Some(true)), ExpiryAction::Keep)
+        );
+        assert_eq!(
+            next_expiry(ttl, state(Some(0), Some(true)), 24 * HOUR_MS),
+            (state(Some(0), Some(true)), ExpiryAction::Delete)
+        );
+
+        // extending the TTL takes the warning back
+        let ttl = Some(Duration::from_secs(48 * 60 * 60));
+        assert_eq!(
+            next_expiry(ttl, state(Some(0), Some(true)), 23 * HOUR_MS),
+            (state(Some(0), None), ExpiryAction::Keep)
+        );
+    }
+
+    #[test]
+    fn test_short_ttl_warns_halfway() {
+        let ttl = Duration::from_secs(30 * 60);
+
+        assert_eq!(warning_window(ttl), Duration::from_secs(15 * 60));
+        assert_eq!(
+            warning_window(Duration::from_secs(7 * 24 * 60 * 60)),
+            Duration::from_secs(DEFAULT_TTL_WARNING_MINS * 60)
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use futures_util::future::join_all;
+use tracing::{info, warn};
+
+use crate::{
+    controller::{machine::is_healthy, scheduler::Scheduler},
+    resources::{
+        Convert, ProvideMetadata,
+        machine::{MachinePhase, MachineStatus},
+        metadata::Namespace,
+    },
+};
+
+pub const DEFAULT_HEALTH_PROBE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Consecutive failed probes after which a machine counts as failing, one failure can be a
+/// hiccup.
+const HEALTH_PROBE_FAILURE_THRESHOLD: u32 = 3;
+
+/// Whether the machine failed enough health probes in a row to be kept out of DNS answers.
+pub fn failing_health_probes(status: &MachineStatus) -> bool {
+    status
+        .health_probe_failures
+        .is_some_and(|failures| failures >= HEALTH_PROBE_FAILURE_THRESHOLD)
+}
+
+/// The count of failed probes once one more probe answered `healthy`, reset on success.
+fn next_probe_failures(failures: Option<u32>, healthy: bool) -> Option<u32> {
+    if healthy {
+        None
+    } else {
+        Some(failures.unwrap_or(0).saturating_add(1))
+    }
+}
+
+/// Probes the health endpoint of the ready machines with `health` periodically and keeps the
+/// count of failed probes in their status.
+pub fn start_health_probes(scheduler: Arc<Scheduler>, probe_interval: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(probe_interval);
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = probe_machines(&scheduler).await {
+                warn!("Failed to probe machine health: {}", e);
+            }
+        }
+    });
+}
+
+async fn probe_machines(scheduler: &Scheduler) -> Result<()> {
+    for tenant in scheduler.store.list_tenants()? {
+        let machine_repo = scheduler.repository.machine(tenant.clone());
+
+        let mut probes = vec![];
+        for machine in machine_repo.list(Namespace::Unspecified)? {
+            let metadata = machine.metadata();
+            let Some(status) = machine_repo.get_status(metadata.clone())? else {
+                continue;
+            };
+
+            let health = machine.latest().health;
+            if health.is_none() || status.phase != MachinePhase::Ready {
+                // counted again from scratch once the machine is back
+                if status.health_probe_failures.is_some() {
+                    machine_repo
+                        .patch_status(metadata, |status| {
+                            status.health_probe_failures = None;
+                        })
+                        .await?;
+                }
+                continue;
+            }
+
+            probes.push(async move {
+                let healthy = is_healthy(status.machine_ip.as_deref(), health.as_ref()).await;
+                (metadata, status.health_probe_failures, healthy)
+            });
+        }
+
+        for (metadata, failures, healthy) in join_all(probes).await {
+            let next = next_probe_failures(failures, healthy);
+            if next == failures {
+                continue;
+            }
+
+            if next == Some(HEALTH_PROBE_FAILURE_THRESHOLD) {
+                info!(
+                    "Machine {} of tenant {} failed {} health probes in a row",
+                    metadata.to_string(),
+                    tenant,
+                    HEALTH_PROBE_FAILURE_THRESHOLD
+                );
+            }
+
+            machine_repo
+                .patch_status(metadata, move |status| {
+                    status.health_probe_failures = next;
+                })
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_probe_failures() {
+        assert_eq!(next_probe_failures(None, true), None);
+        assert_eq!(next_probe_failures(Some(2), true), None);
+        assert_eq!(next_probe_failures(None, false), Some(1));
+        assert_eq!(next_probe_failures(Some(2), false), Some(3));
+        assert_eq!(next_probe_failures(Some(u32::MAX), false), Some(u32::MAX));
+    }
+}
@@ -1,35 +1,60 @@
-use std::{str::FromStr, sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 
 use anyhow::{Result, anyhow, bail};
 use async_trait::async_trait;
 use chrono::Utc;
 use oci_client::Reference;
-use takeoff_proto::proto::LogsTelemetryConfig;
+use takeoff_proto::proto::{
+    DebugTraceConfig, DebugTraceTool, HealthConfig, LogsTelemetryConfig, MetricsTelemetryConfig,
+};
 use tokio::{runtime, task::spawn_blocking};
 use tracing::{error, info, warn};
 
 use crate::{
     agent::{
         Agent,
-        machine::machine::{
-            MachineConfig, MachineMode, MachineResources, MachineState, MachineStateRetentionMode,
-            NetworkConfig, SnapshotStrategy, VolumeMountConfig,
+        machine::{
+            machine::{
+                MachineConfig, MachineMode, MachineResources, MachineState,
+                MachineStateRetentionMode, NetworkConfig, SnapshotStrategy, VolumeMountConfig,
+            },
+            vm::kernel::validate_kernel_param,
         },
-        net::{IpReservationKind, compute_mac_for_ip},
+        net::{IpReservationKind, bandwidth::parse_rate, compute_mac_for_ip},
+    },
+    constants::{
+        DEFAULT_DEBUG_TRACE_MAX_LINES, DEFAULT_HEALTH_PORT, DEFAULT_IDLE_SUSPEND_MAX_CPU,
+        DEFAULT_METRICS_EXPORT_INTERVAL_SECS, DEFAULT_NAMESPACE,
+        DEFAULT_ROLLOUT_DRAIN_TIMEOUT_SECS, DEFAULT_ROLLOUT_MAX_SURGE,
+        DEFAULT_ROLLOUT_MAX_UNAVAILABLE, DEFAULT_ROLLOUT_READY_TIMEOUT_SECS,
+        DEFAULT_SUSPEND_TIMEOUT_SECS,
     },
-    constants::{DEFAULT_NAMESPACE, DEFAULT_SUSPEND_TIMEOUT_SECS},
     controller::{
         AdmissionCheckBeforeSet, Controller, ReconcileNext,
         context::{AsyncWork, ControllerContext, ControllerEvent, ControllerKey},
+        expiry::parse_ttl,
+        placement::{parse_constraints, pick_node},
+        references::{MissingReferences, missing_volumes},
     },
     repository::Repository,
     resource_index::ResourceKind,
     resources::{
         self, Convert,
-        machine::{Machine, MachinePhase, MachineStatus},
+        core::EventReason,
+        machine::{
+            Machine, MachineDebugTrace, MachineEphemeralVolumeStatus, MachineHealth, MachineLatest,
+            MachineNetwork, MachinePhase, MachineRollout, MachineRolloutStrategy, MachineStatus,
+            MachineSuspendPolicy,
+        },
         metadata::{Metadata, Namespace},
         volume::VolumeMode,
     },
+    utils::time::now_millis,
 };
 
 // Restart policy constants
@@ -44,10 +69,56 @@ impl MachineController {
     }
 }
 
-fn pull_image_job_key(reference: &Reference) -> String {
+pub fn pull_image_job_key(reference: &Reference) -> String {
     format!("pull-image-{}", reference)
 }
 
+/// Pulls an image in the background, `key` is notified with `ImagePullComplete` or `Error`.
+/// Controllers pulling the same reference at the same time share the pull.
+pub async fn start_image_pull(
+    ctx: &ControllerContext,
+    key: ControllerKey,
+    reference: Reference,
+    image: String,
+) -> Result<()> {
+    let image_agent = ctx.agent.image();
+    let tenant = ctx.tenant.clone();
+    let job_key = pull_image_job_key(&reference);
+
+    ctx.agent
+        .job()
+        .run_with_notify(
+            key,
+            job_key,
+            async move {
+                let image = image_agent
+                    .image_pull(tenant.clone(), reference)
+                    .await
+                    .map_err(|e| {
+                        warn!("failed to pull image: {}", e);
+                        format!("failed to pull image: {}", &image)
+                    })?;
+
+                let reference = format!("{}@{}", image.reference, image.digest);
+
+                Ok((image.id, reference, image.labels, image.annotations))
+            },
+            |result, key| match result {
+                Ok((id, reference, labels, annotations)) => Some(ControllerEvent::AsyncWorkChange(
+                    key,
+                    AsyncWork::ImagePullComplete {
+                        id,
+                        reference,
+                        labels,
+                        annotations,
+                    },
+                )),
+                Err(e) => Some(ControllerEvent::AsyncWorkChange(key, AsyncWork::Error(e))),
+            },
+        )
+        .await
+}
+
 fn image_is_latest_available_job_key(reference: &Reference) -> String {
     format!("image-is-latest-available-{}", reference)
 }
@@ -56,6 +127,533 @@ pub fn machine_name_from_key(key: &ControllerKey) -> String {
     format!("{}-{}", key.tenant, key.metadata().to_string())
 }
 
+/// Tag of the replicas of a machine group, followed by the group name.
+pub const MACHINE_GROUP_TAG_PREFIX: &str = "ignitiond.group=";
+
+/// Tag of the machine started next to another one during a rolling rollout, followed by the
+/// name of the machine it replaces.
+pub const MACHINE_SURGE_TAG_PREFIX: &str = "ignitiond.surge-of=";
+
+/// Tag of the clones kept resumed next to a flash machine with `prewarm`, followed by the name
+/// of the machine.
+pub const MACHINE_PREWARM_TAG_PREFIX: &str = "ignitiond.prewarm-of=";
+
+const ROLLOUT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const ROLLOUT_HEALTH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Idle machines busy on their own are checked again this often.
+const IDLE_SUSPEND_POLL_INTERVAL: Duration = Duration::from_secs(30);
+const IDLE_SUSPEND_CPU_WINDOW_SECS: u64 = 60;
+
+/// Machines no node satisfies the placement constraints of are placed again this often.
+const PLACEMENT_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Replicas of a machine group share the network tag of the group, so services targeting the
+/// group name reach all of them. The surge of a rolling rollout and prewarmed clones share the
+/// network tag of their machine.
+fn network_tag_for(key: &ControllerKey, tags: &[String]) -> String {
+    let group = tags
+        .iter()
+        .find_map(|tag| tag.strip_prefix(MACHINE_GROUP_TAG_PREFIX))
+        .or_else(|| {
+            tags.iter()
+                .find_map(|tag| tag.strip_prefix(MACHINE_SURGE_TAG_PREFIX))
+        })
+        .or_else(|| {
+            tags.iter()
+                .find_map(|tag| tag.strip_prefix(MACHINE_PREWARM_TAG_PREFIX))
+        });
+
+    match group {
+        Some(group) => machine_name_from_key(&ControllerKey::new(
+            key.tenant.clone(),
+            ResourceKind::Machine,
+            key.namespace.clone(),
+            group,
+        )),
+        None => machine_name_from_key(key),
+    }
+}
+
+/// The rollout of a machine if it rolls, a `max-surge` of 0 recreates it.
+fn rolling_rollout(machine: &MachineLatest) -> Option<&MachineRollout> {
+    let rollout = machine.rollout.as_ref()?;
+    if rollout.strategy != MachineRolloutStrategy::Rolling {
+        return None;
+    }
+    if rollout.max_surge.unwrap_or(DEFAULT_ROLLOUT_MAX_SURGE) == 0 {
+        return None;
+    }
+
+    Some(rollout)
+}
+
+/// Copy of a machine started with its new spec next to it during a rolling rollout.
+fn surge_machine(machine: &MachineLatest) -> MachineLatest {
+    let mut tags = machine.tags.clone().unwrap_or_default();
+    tags.push(format!("{}{}", MACHINE_SURGE_TAG_PREFIX, machine.name));
+
+    let mut surge = machine.clone();
+    surge.name = format!("{}-surge", machine.name);
+    surge.tags = Some(tags);
+    surge.rollout = None;
+    surge
+}
+
+fn prewarm_count(machine: &MachineLatest) -> u32 {
+    match &machine.mode {
+        Some(resources::machine::MachineMode::Flash { prewarm, .. }) => prewarm.unwrap_or(0),
+        _ => 0,
+    }
+}
+
+fn is_prewarm_clone(tags: &[String]) -> bool {
+    tags.iter()
+        .any(|tag| tag.starts_with(MACHINE_PREWARM_TAG_PREFIX))
+}
+
+/// Clone of a flash machine kept resumed next to it, requests go to it while the machine is
+/// suspended.
+fn prewarm_machine(machine: &MachineLatest, index: u32) -> MachineLatest {
+    let mut tags = machine.tags.clone().unwrap_or_default();
+    tags.push(format!("{}{}", MACHINE_PREWARM_TAG_PREFIX, machine.name));
+
+    let mut clone = machine.clone();
+    clone.name = format!("{}-prewarm-{}", machine.name, index);
+    clone.tags = Some(tags);
+    clone.rollout = None;
+    clone.suspend = None;
+    clone.ttl = None;
+    if let Some(resources::machine::MachineMode::Flash { prewarm, .. }) = &mut clone.mode {
+        *prewarm = None;
+    }
+    clone
+}
+
+/// Keeps as many clones as the machine asks for with its current spec, deleting the rest.
+async fn sync_prewarm_pool(
+    ctx: &ControllerContext,
+    key: &ControllerKey,
+    machine: &MachineLatest,
+    status: &MachineStatus,
+) -> Result<()> {
+    let repo = ctx.repository.machine(ctx.tenant.clone());
+    let namespace = Namespace::from_value(key.metadata().namespace);
+    let prewarm_tag = format!("{}{}", MACHINE_PREWARM_TAG_PREFIX, machine.name);
+
+    let mut pool = vec![];
+    for index in 0..prewarm_count(machine) {
+        let clone = prewarm_machine(machine, index);
+        let clone_hash = Machine::V1(clone.clone()).hash_with_updated_metadata();
+
+        let existing = repo.get(namespace.clone(), &clone.name)?;
+        if let Some(existing) = &existing {
+            if !existing
+                .latest()
+                .tags
+                .unwrap_or_default()
+                .contains(&prewarm_tag)
+            {
+                bail!(
+                    "machine {} already exists, it can't be prewarmed for machine {}",
+                    clone.name,
+                    machine.name
+                );
+            }
+        }
+
+        if existing
+            .as_ref()
+            .is_none_or(|existing| existing.hash_with_updated_metadata() != clone_hash)
+        {
+            // the clones are written by the controller, they go through the checks the user's
+            // machines do
+            let clone_resource = Machine::V1(clone.clone());
+            clone_resource
+                .before_set(
+                    existing.as_ref(),
+                    ctx.tenant.clone(),
+                    ctx.repository.clone(),
+                    ctx.agent.clone(),
+                    Metadata::new(&clone.name, namespace.clone()),
+                )
+                .await
+                .map_err(|e| anyhow!("machine {} can't be prewarmed: {}", machine.name, e))?;
+
+            info!("prewarming {} for machine {}", clone.name, key.to_string());
+            repo.set(clone_resource).await?;
+        }
+        pool.push(clone.name);
+    }
+
+    for name in status.prewarm_pool.iter().flatten() {
+        if !pool.contains(name) {
+            repo.delete(namespace.clone(), name).await.ok();
+        }
+    }
+
+    let pool = (!pool.is_empty()).then_some(pool);
+    if pool != status.prewarm_pool {
+        repo.patch_status(key.metadata(), move |status| {
+            status.prewarm_pool = pool;
+        })
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Machines without `health` are healthy once ready, the others once the `/healthz` of the
+/// init answers.
+pub async fn is_healthy(machine_ip: Option<&str>, health: Option<&MachineHealth>) -> bool {
+    let Some(health) = health else {
+        return true;
+    };
+    let Some(machine_ip) = machine_ip else {
+        return false;
+    };
+
+    let url = format!(
+        "http://{}:{}/healthz",
+        machine_ip,
+        health.port.unwrap_or(DEFAULT_HEALTH_PORT)
+    );
+    let Ok(client) = reqwest::Client::builder()
+        .timeout(ROLLOUT_HEALTH_TIMEOUT)
+        .build()
+    else {
+        return false;
+    };
+
+    client
+        .get(url)
+        .send()
+        .await
+        .is_ok_and(|response| response.status().is_success())
+}
+
+fn suspend_after_idle(policy: &MachineSuspendPolicy) -> Result<Duration> {
+    let after_idle = humantime::parse_duration(&policy.after_idle)
+        .map_err(|e| anyhow!("invalid suspend after-idle {}: {}", policy.after_idle, e))?;
+    if after_idle.is_zero() {
+        bail!("suspend after-idle must be greater than 0");
+    }
+
+    Ok(after_idle)
+}
+
+/// The `network.bandwidth` of a machine as ingress and egress bits per second.
+pub fn bandwidth_limits(network: Option<&MachineNetwork>) -> Result<(Option<u64>, Option<u64>)> {
+    let Some(bandwidth) = network.and_then(|network| network.bandwidth.as_ref()) else {
+        return Ok((None, None));
+    };
+
+    let ingress = bandwidth.ingress.as_deref().map(parse_rate).transpose()?;
+    let egress = bandwidth.egress.as_deref().map(parse_rate).transpose()?;
+    Ok((ingress, egress))
+}
+
+/// Whether the CPU utilization of the machine stayed at or below `max_cpu` percent lately.
+/// Without a metrics store only the traffic tells if the machine is idle, a machine without
+/// samples yet isn't.
+async fn is_cpu_idle(ctx: &ControllerContext, machine: &MachineLatest, max_cpu: u8) -> bool {
+    let Ok(metrics) = ctx.agent.metrics() else {
+        return true;
+    };
+
+    let namespace = Namespace::from_value(machine.namespace.clone())
+        .as_value()
+        .unwrap_or(DEFAULT_NAMESPACE.to_string());
+    let now_secs = now_millis() / 1000;
+
+    match metrics
+        .machine_usage(
+            &ctx.tenant,
+            &namespace,
+            &machine.name,
+            now_secs.saturating_sub(IDLE_SUSPEND_CPU_WINDOW_SECS)..=now_secs,
+        )
+        .await
+    {
+        Ok(usage) => {
+            !usage.cpu_utilization.is_empty()
+                && usage
+                    .cpu_utilization
+                    .iter()
+                    .all(|(_, cpu)| cpu * 100.0 <= max_cpu as f64)
+        }
+        Err(e) => {
+            warn!(
+                "failed to query the CPU utilization of machine {}: {}",
+                machine.name, e
+            );
+            false
+        }
+    }
+}
+
+/// Starts the surge with the new spec and waits for it to be ready and healthy, then drains
+/// the running machine and recreates it with the new spec while the surge serves.
+async fn roll_out(
+    ctx: &ControllerContext,
+    key: &ControllerKey,
+    machine: &MachineLatest,
+    rollout: &MachineRollout,
+    status: &MachineStatus,
+    hash: u64,
+) -> Result<ReconcileNext> {
+    let repo = ctx.repository.machine(ctx.tenant.clone());
+    let namespace = Namespace::from_value(key.metadata().namespace);
+    let now = now_millis();
+
+    let surge = surge_machine(machine);
+    let surge_hash = Machine::V1(surge.clone()).hash_with_updated_metadata();
+    let surge_tag = format!("{}{}", MACHINE_SURGE_TAG_PREFIX, machine.name);
+
+    let existing = repo.get(namespace.clone(), &surge.name)?;
+    if let Some(existing) = &existing {
+        if !existing
+            .latest()
+            .tags
+            .unwrap_or_default()
+            .contains(&surge_tag)
+        {
+            bail!(
+                "machine {} already exists, it can't be used to roll out machine {}",
+                surge.name,
+                machine.name
+            );
+        }
+    }
+
+    let stale = existing
+        .as_ref()
+        .is_none_or(|existing| existing.hash_with_updated_metadata() != surge_hash);
+    if stale || status.rollout_surge.is_none() {
+        info!(
+            "starting {} to roll out machine {}",
+            surge.name,
+            key.to_string()
+        );
+        ctx.agent.events().emit(
+            key,
+            EventReason::RolloutStarted,
+            format!("Starting {} with the new spec", surge.name),
+        );
+
+        if stale {
+            repo.set(surge.clone().into()).await?;
+        }
+        repo.patch_status(key.metadata(), |status| {
+            status.rollout_surge = Some(surge.name.clone());
+            status.rollout_started_at = Some(now);
+            status.rollout_draining_since = None;
+        })
+        .await?;
+
+        return Ok(ReconcileNext::after(ROLLOUT_POLL_INTERVAL));
+    }
+
+    let surge_key = ControllerKey::new(
+        ctx.tenant.clone(),
+        ResourceKind::Machine,
+        key.namespace.clone(),
+        &surge.name,
+    );
+    let surge_status = repo.get_status(surge_key.metadata())?;
+    let surge_ready = match &surge_status {
+        Some(surge_status)
+            if surge_status.phase == MachinePhase::Ready && surge_status.hash == surge_hash =>
+        {
+            is_healthy(surge_status.machine_ip.as_deref(), machine.health.as_ref()).await
+        }
+        _ => false,
+    };
+
+    if !surge_ready {
+        let ready_timeout_millis = rollout
+            .ready_timeout
+            .unwrap_or(DEFAULT_ROLLOUT_READY_TIMEOUT_SECS)
+            * 1000;
+        let started_at = status.rollout_started_at.unwrap_or(now);
+        if now.saturating_sub(started_at) < ready_timeout_millis {
+            return Ok(ReconcileNext::after(ROLLOUT_POLL_INTERVAL));
+        }
+
+        warn!(
+            "rollout of machine {} gave up, {} is not ready",
+            key.to_string(),
+            surge.name
+        );
+        ctx.agent.events().emit(
+            key,
+            EventReason::RolloutFailed,
+            format!(
+                "{} did not become ready in time, keeping the previous spec",
+                surge.name
+            ),
+        );
+
+        repo.delete(namespace, &surge.name).await?;
+        repo.patch_status(key.metadata(), |status| {
+            status.rollout_surge = None;
+            status.rollout_started_at = None;
+            status.rollout_draining_since = None;
+            status.rollout_failed_hash = Some(hash);
+        })
+        .await?;
+
+        return Ok(ReconcileNext::done());
+    }
+
+    // the surge gets the new connections from here on
+    let running_machine = ctx.agent.machine().get_machine(&machine_name_from_key(key));
+    if let Some(running_machine) = &running_machine {
+        running_machine.set_draining(true);
+    }
+
+    let draining_since = match status.rollout_draining_since {
+        Some(draining_since) => draining_since,
+        None => {
+            repo.patch_status(key.metadata(), |status| {
+                status.rollout_draining_since = Some(now);
+            })
+            .await?;
+            now
+        }
+    };
+
+    let drain_timeout_millis = rollout
+        .drain_timeout
+        .unwrap_or(DEFAULT_ROLLOUT_DRAIN_TIMEOUT_SECS)
+        * 1000;
+    let open_connections = running_machine
+        .as_ref()
+        .map_or(0, |running_machine| running_machine.open_connections());
+    if open_connections > 0 && now.saturating_sub(draining_since) < drain_timeout_millis {
+        return Ok(ReconcileNext::after(ROLLOUT_POLL_INTERVAL));
+    }
+
+    info!(
+        "replacing machine {}, {} serves meanwhile",
+        key.to_string(),
+        surge.name
+    );
+
+    repo.patch_status(key.metadata(), |status| {
+        status.hash = hash;
+        status.phase = MachinePhase::Restarting;
+        status.last_restarting_time_us = Some(Utc::now().timestamp_millis() as u64);
+        status.restart_count = Some(0);
+        status.rollout_draining_since = None;
+    })
+    .await?;
+
+    Ok(ReconcileNext::immediate())
+}
+
+/// Once the machine runs the new spec, moves the traffic back from the surge and removes it.
+async fn finish_rollout(
+    ctx: &ControllerContext,
+    key: &ControllerKey,
+    machine: &MachineLatest,
+    status: &MachineStatus,
+    surge_name: &str,
+) -> Result<ReconcileNext> {
+    let repo = ctx.repository.machine(ctx.tenant.clone());
+    let now = now_millis();
+
+    if !is_healthy(status.machine_ip.as_deref(), machine.health.as_ref()).await {
+        return Ok(ReconcileNext::after(ROLLOUT_POLL_INTERVAL));
+    }
+
+    let surge_key = ControllerKey::new(
+        ctx.tenant.clone(),
+        ResourceKind::Machine,
+        key.namespace.clone(),
+        surge_name,
+    );
+    let surge_machine = ctx
+        .agent
+        .machine()
+        .get_machine(&machine_name_from_key(&surge_key));
+    if let Some(surge_machine) = &surge_machine {
+        surge_machine.set_draining(true);
+    }
+
+    let draining_since = match status.rollout_draining_since {
+        Some(draining_since) => draining_since,
+        None => {
+            repo.patch_status(key.metadata(), |status| {
+                status.rollout_draining_since = Some(now);
+            })
+            .await?;
+            now
+        }
+    };
+
+    let drain_timeout_millis = machine
+        .rollout
+        .as_ref()
+        .and_then(|rollout| rollout.drain_timeout)
+        .unwrap_or(DEFAULT_ROLLOUT_DRAIN_TIMEOUT_SECS)
+        * 1000;
+    let open_connections = surge_machine
+        .as_ref()
+        .map_or(0, |surge_machine| surge_machine.open_connections());
+    if open_connections > 0 && now.saturating_sub(draining_since) < drain_timeout_millis {
+        return Ok(ReconcileNext::after(ROLLOUT_POLL_INTERVAL));
+    }
+
+    repo.delete(Namespace::from_value(key.metadata().namespace), surge_name)
+        .await?;
+    repo.patch_status(key.metadata(), |status| {
+        status.rollout_surge = None;
+        status.rollout_started_at = None;
+        status.rollout_draining_since = None;
+    })
+    .await?;
+
+    info!("rolled out machine {}", key.to_string());
+    ctx.agent.events().emit(
+        key,
+        EventReason::RolloutCompleted,
+        "Rolled out the new spec",
+    );
+
+    Ok(ReconcileNext::done())
+}
+
+/// Event recorded when a running machine reaches a new phase, if it is worth one.
+fn phase_event(
+    phase: &MachinePhase,
+    last_boot_duration_us: Option<u64>,
+    last_exit_code: Option<i32>,
+) -> Option<(EventReason, String)> {
+    match phase {
+        MachinePhase::Ready => Some((
+            EventReason::MachineStarted,
+            match last_boot_duration_us {
+                Some(us) => format!("Machine is ready, booted in {:.1}ms", us as f64 / 1000.0),
+                None => "Machine is ready".to_string(),
+            },
+        )),
+        MachinePhase::Suspended => Some((
+            EventReason::MachineSuspended,
+            "Machine suspended".to_string(),
+        )),
+        MachinePhase::Stopped => Some((
+            EventReason::MachineStopped,
+            match last_exit_code {
+                Some(code) => format!("Machine stopped with exit code {}", code),
+                None => "Machine stopped".to_string(),
+            },
+        )),
+        MachinePhase::Error { message } => Some((EventReason::MachineFailed, message.clone())),
+        _ => None,
+    }
+}
+
 fn calculate_restart_backoff(restart_count: u64) -> Duration {
     // Exponential backoff: 2^restart_count * BASE_RESTART_BACKOFF_SECS seconds
     // restart_count=0: 2s, restart_count=1: 4s, restart_count=2: 8s, restart_count=3: 16s
@@ -248,12 +846,20 @@ impl Controller for MachineController {
                         ctx.agent.volume().volume_delete(&volume_id).await?;
                     }
 
+                    // delete ephemeral volumes
+                    for volume in status.machine_ephemeral_volumes.iter().flatten() {
+                        ctx.agent.volume().volume_delete(&volume.volume_id).await?;
+                    }
+
                     ctx.repository
                         .machine(ctx.tenant.clone())
                         .patch_status(key.metadata(), |status| {
                             status.machine_ip = None;
                             status.machine_tap = None;
                             status.machine_image_volume_id = None;
+                            status.machine_ephemeral_volumes = None;
+                            // the new machine boots up, whatever the old one was doing
+                            status.user_suspended = None;
                         })
                         .await?;
 
@@ -286,9 +892,18 @@ impl Controller for MachineController {
                         .and_then(|duration| Some(duration.as_micros() as u64));
 
                     let last_exit_code = running_machine.get_last_exit_code().await;
+                    let last_core_dump = running_machine.get_last_core_dump().await;
 
                     if let Some(new_phase) = new_phase {
                         if new_phase != status.phase {
+                            if let Some((reason, message)) = phase_event(
+                                &new_phase,
+                                last_boot_duration_us,
+                                last_exit_code.or(status.last_exit_code),
+                            ) {
+                                ctx.agent.events().emit(&key, reason, message);
+                            }
+
                             let new_status = ctx
                                 .repository
                                 .machine(ctx.tenant.clone())
@@ -299,6 +914,9 @@ impl Controller for MachineController {
                                     if let Some(last_exit_code) = last_exit_code {
                                         status.last_exit_code = Some(last_exit_code);
                                     }
+                                    if let Some(last_core_dump) = last_core_dump {
+                                        status.last_core_dump = Some(last_core_dump);
+                                    }
                                     // Don't reset restart counter immediately on Ready - let it reset after stability period
                                 })
                                 .await?;
@@ -352,6 +970,30 @@ impl Controller for MachineController {
                         ctx.agent.volume().volume_delete(&volume_id).await?;
                     }
 
+                    // delete ephemeral volumes
+                    for volume in status.machine_ephemeral_volumes.iter().flatten() {
+                        ctx.agent.volume().volume_delete(&volume.volume_id).await?;
+                    }
+
+                    ctx.agent.machine().delete_core_dumps(&machine_name).await?;
+
+                    // a rollout in progress leaves its surge behind otherwise
+                    if let Some(surge_name) = status.rollout_surge {
+                        ctx.repository
+                            .machine(ctx.tenant.clone())
+                            .delete(Namespace::from_value(key.metadata().namespace), surge_name)
+                            .await
+                            .ok();
+                    }
+
+                    for clone_name in status.prewarm_pool.iter().flatten() {
+                        ctx.repository
+                            .machine(ctx.tenant.clone())
+                            .delete(Namespace::from_value(key.metadata().namespace), clone_name)
+                            .await
+                            .ok();
+                    }
+
                     ctx.repository
                         .machine(ctx.tenant.clone())
                         .delete_status(key.metadata())
@@ -365,9 +1007,27 @@ impl Controller for MachineController {
                         .machine(ctx.tenant.clone())
                         .get_status(key.metadata())?;
 
-                    if status.is_some() {
+                    if let Some(status) = status {
                         warn!("cleaning up machine status for key: {}", key.to_string());
 
+                        ctx.agent.machine().delete_core_dumps(&machine_name).await?;
+
+                        if let Some(surge_name) = status.rollout_surge {
+                            ctx.repository
+                                .machine(ctx.tenant.clone())
+                                .delete(Namespace::from_value(key.metadata().namespace), surge_name)
+                                .await
+                                .ok();
+                        }
+
+                        for clone_name in status.prewarm_pool.iter().flatten() {
+                            ctx.repository
+                                .machine(ctx.tenant.clone())
+                                .delete(Namespace::from_value(key.metadata().namespace), clone_name)
+                                .await
+                                .ok();
+                        }
+
                         ctx.repository
                             .machine(ctx.tenant.clone())
                             .delete_status(key.metadata())
@@ -397,9 +1057,20 @@ impl Controller for MachineController {
 
         // remove the tag and restart the machine if it's set
         if tags.contains(&"ignitiond.restart".to_string()) {
+            // trace tags only ever come along with a restart and apply to the next boot
+            let debug_trace = if tags.contains(&"ignitiond.trace.strace".to_string()) {
+                Some(MachineDebugTrace::Strace)
+            } else if tags.contains(&"ignitiond.trace.ltrace".to_string()) {
+                Some(MachineDebugTrace::Ltrace)
+            } else {
+                None
+            };
+
             machine.tags = Some(
                 tags.into_iter()
-                    .filter(|tag| tag != "ignitiond.restart")
+                    .filter(|tag| {
+                        tag != "ignitiond.restart" && !tag.starts_with("ignitiond.trace.")
+                    })
                     .collect(),
             );
 
@@ -415,13 +1086,64 @@ impl Controller for MachineController {
                     status.last_restarting_time_us = Some(Utc::now().timestamp_millis() as u64);
                     // Reset restart counter for manual restarts
                     status.restart_count = Some(0);
+                    status.debug_trace = debug_trace;
                 })
                 .await?;
 
             return Ok(ReconcileNext::immediate());
         }
 
-        if hash != status.hash && status.hash != 0 {
+        // suspend or resume the machine on behalf of the user, it keeps its state in between
+        let suspend = tags.contains(&"ignitiond.suspend".to_string());
+        let resume = tags.contains(&"ignitiond.resume".to_string());
+        if suspend || resume {
+            machine.tags = Some(
+                tags.into_iter()
+                    .filter(|tag| tag != "ignitiond.suspend" && tag != "ignitiond.resume")
+                    .collect(),
+            );
+
+            ctx.repository
+                .machine(key.tenant.clone())
+                .set(machine.into())
+                .await?;
+
+            let Some(running_machine) = ctx.agent.machine().get_machine(&machine_name) else {
+                warn!(
+                    "machine {} isn't running, it can't be suspended or resumed",
+                    machine_name
+                );
+                return Ok(ReconcileNext::immediate());
+            };
+
+            // resuming wins when both were asked for before we got to them
+            if resume {
+                running_machine.user_resume().await?;
+            } else {
+                running_machine.user_suspend().await?;
+            }
+
+            ctx.repository
+                .machine(key.tenant.clone())
+                .patch_status(key.metadata(), |status| {
+                    status.user_suspended = (!resume).then_some(true);
+                })
+                .await?;
+
+            return Ok(ReconcileNext::immediate());
+        }
+
+        // a rollout that was given up keeps the previous spec running
+        let rollout_failed = status.rollout_failed_hash == Some(hash);
+
+        if hash != status.hash && status.hash != 0 && !rollout_failed {
+            // a running machine keeps serving until its replacement is ready
+            if let Some(rollout) = rolling_rollout(&machine) {
+                if status.phase == MachinePhase::Ready {
+                    return roll_out(&ctx, &key, &machine, rollout, &status, hash).await;
+                }
+            }
+
             // the resource has changed, let's recreate the machine
             ctx.repository
                 .machine(key.tenant.clone())
@@ -431,6 +1153,7 @@ impl Controller for MachineController {
                     status.last_restarting_time_us = Some(Utc::now().timestamp_millis() as u64);
                     // Reset restart counter for spec changes
                     status.restart_count = Some(0);
+                    status.rollout_draining_since = None;
                 })
                 .await?;
 
@@ -464,53 +1187,43 @@ impl Controller for MachineController {
             return Ok(ReconcileNext::immediate());
         };
 
-        ctx.repository
-            .machine(key.tenant.clone())
-            .patch_status(key.metadata(), |status| {
-                status.hash = hash;
-            })
-            .await?;
+        if !rollout_failed {
+            ctx.repository
+                .machine(key.tenant.clone())
+                .patch_status(key.metadata(), |status| {
+                    status.hash = hash;
+                })
+                .await?;
+        }
+
+        if let Some(surge_name) = &status.rollout_surge {
+            if status.phase == MachinePhase::Ready && status.hash == hash {
+                return finish_rollout(&ctx, &key, &machine, &status, surge_name).await;
+            }
+        }
+
+        // the pool is brought up once the machine is, its image is pulled by then
+        let up = matches!(
+            status.phase,
+            MachinePhase::Ready | MachinePhase::Suspending | MachinePhase::Suspended
+        );
+        if up && (prewarm_count(&machine) > 0 || status.prewarm_pool.is_some()) {
+            sync_prewarm_pool(&ctx, &key, &machine, &status).await?;
+        }
 
         'phase_match: {
             match status.phase {
                 MachinePhase::Idle => {
-                    let image_agent = ctx.agent.image();
-                    let tenant = ctx.tenant.clone();
-                    ctx.agent
-                        .job()
-                        .run_with_notify(
-                            key.clone(),
-                            pull_image_job_key(&reference),
-                            async move {
-                                let image = image_agent
-                                    .image_pull(tenant.clone(), reference)
-                                    .await
-                                    .map_err(|e| {
-                                        warn!("failed to pull image: {}", e);
-                                        format!("failed to pull image: {}", &image)
-                                    })?;
-
-                                let reference = format!("{}@{}", image.reference, image.digest);
-
-                                Ok((image.id, reference))
-                            },
-                            |result, key| match result {
-                                Ok((id, reference)) => Some(ControllerEvent::AsyncWorkChange(
-                                    key,
-                                    AsyncWork::ImagePullComplete { id, reference },
-                                )),
-                                Err(e) => {
-                                    Some(ControllerEvent::AsyncWorkChange(key, AsyncWork::Error(e)))
-                                }
-                            },
-                        )
-                        .await?;
+                    start_image_pull(&ctx, key.clone(), reference, image).await?;
 
                     ctx.repository
                         .machine(ctx.tenant.clone())
                         .patch_status(key.metadata(), |status| {
                             status.phase = MachinePhase::PullingImage;
                             status.image_resolved_reference = Some(resolved_reference_str.clone());
+                            // the machine is created with the current spec from here on
+                            status.hash = hash;
+                            status.rollout_failed_hash = None;
                         })
                         .await?;
 
@@ -534,19 +1247,40 @@ impl Controller for MachineController {
                     match event {
                         ControllerEvent::AsyncWorkChange(
                             _,
-                            AsyncWork::ImagePullComplete { id, reference },
+                            AsyncWork::ImagePullComplete {
+                                id,
+                                reference,
+                                labels,
+                                annotations,
+                            },
                         ) => {
+                            ctx.agent.events().emit(
+                                &key,
+                                EventReason::ImagePulled,
+                                format!("Pulled image {}", reference),
+                            );
+
                             ctx.repository
                                 .machine(ctx.tenant.clone())
                                 .patch_status(key.metadata(), |status| {
                                     status.image_id = Some(id.clone());
                                     status.image_resolved_reference = Some(reference.clone());
+                                    status.image_labels =
+                                        (!labels.is_empty()).then(|| labels.clone());
+                                    status.image_annotations =
+                                        (!annotations.is_empty()).then(|| annotations.clone());
                                     status.phase = MachinePhase::Waiting;
                                 })
                                 .await?;
                             return Ok(ReconcileNext::Immediate);
                         }
                         ControllerEvent::AsyncWorkChange(_, AsyncWork::Error(err)) => {
+                            ctx.agent.events().emit(
+                                &key,
+                                EventReason::ImagePullFailed,
+                                err.clone(),
+                            );
+
                             ctx.repository
                                 .machine(ctx.tenant.clone())
                                 .patch_status(key.metadata(), |status| {
@@ -609,10 +1343,32 @@ impl Controller for MachineController {
                         }
                     }
 
+                    let constraints = machine
+                        .placement
+                        .as_ref()
+                        .and_then(|placement| placement.constraints.clone())
+                        .unwrap_or_default();
+                    let constraints = parse_constraints(&constraints)?;
+                    let nodes = ctx.agent.machine().nodes();
+                    let Some(node) = pick_node(&nodes, &constraints) else {
+                        info!(
+                            "no node satisfies the placement constraints of machine {}",
+                            key.to_string()
+                        );
+                        ctx.agent.events().emit(
+                            &key,
+                            EventReason::Unschedulable,
+                            "No node satisfies the placement constraints",
+                        );
+                        return Ok(ReconcileNext::after(PLACEMENT_RETRY_INTERVAL));
+                    };
+                    let node = node.name.clone();
+
                     ctx.repository
                         .machine(ctx.tenant.clone())
                         .patch_status(key.metadata(), |status| {
                             status.phase = MachinePhase::Creating;
+                            status.node = Some(node);
                         })
                         .await?;
 
@@ -654,9 +1410,10 @@ impl Controller for MachineController {
                         mount_at: "/".to_string(),
                         read_only: false,
                         root: true,
+                        encryption_key: None,
                     }];
 
-                    let volume_bindings = machine.volumes.unwrap_or_default();
+                    let volume_bindings = machine.volumes.clone().unwrap_or_default();
                     for volume_bind in volume_bindings {
                         let volume_resource_namespace = Namespace::from_value_or_default(
                             volume_bind.namespace.or_else(|| machine.namespace.clone()),
@@ -688,14 +1445,75 @@ impl Controller for MachineController {
                             );
                         };
 
+                        let encryption_key = ctx.agent.volume().volume_key(&volume)?;
                         machine_volume_mounts.push(VolumeMountConfig {
                             volume,
                             mount_at: volume_bind.path,
                             read_only: volume_resource.mode == VolumeMode::ReadOnly,
                             root: false,
+                            encryption_key,
                         });
                     }
 
+                    // reuse the volumes left from an earlier attempt at this boot, the ones
+                    // the spec dropped or resized since are deleted
+                    let ephemeral_volumes = machine.ephemeral_volumes();
+                    let mut reusable_volume_ids = HashMap::new();
+                    for volume in status.machine_ephemeral_volumes.clone().unwrap_or_default() {
+                        let still_wanted = ephemeral_volumes.iter().any(|ephemeral| {
+                            ephemeral.path == volume.path && ephemeral.size_mib == volume.size_mib
+                        });
+                        if still_wanted {
+                            reusable_volume_ids.insert(volume.path, volume.volume_id);
+                        } else {
+                            ctx.agent.volume().volume_delete(&volume.volume_id).await?;
+                        }
+                    }
+
+                    let mut ephemeral_volume_status = vec![];
+                    for ephemeral in ephemeral_volumes {
+                        let ephemeral_volume = match reusable_volume_ids.get(&ephemeral.path) {
+                            Some(volume_id) => ctx.agent.volume().volume(volume_id)?,
+                            None => match ctx
+                                .agent
+                                .volume()
+                                .volume_create_empty_ext4_sparse(ephemeral.size_bytes()?)
+                                .await
+                            {
+                                // counted in the tenant's volume usage
+                                Ok(volume) => ctx
+                                    .agent
+                                    .volume()
+                                    .volume_set_owner(&volume.id, &ctx.tenant)
+                                    .ok(),
+                                Err(_) => None,
+                            },
+                        };
+
+                        let Some(ephemeral_volume) = ephemeral_volume else {
+                            bail!(
+                                "failed to get or create ephemeral volume {} for machine: {}",
+                                ephemeral.path,
+                                name
+                            );
+                        };
+
+                        ephemeral_volume_status.push(MachineEphemeralVolumeStatus {
+                            path: ephemeral.path.clone(),
+                            size_mib: ephemeral.size_mib,
+                            volume_id: ephemeral_volume.id.clone(),
+                        });
+                        machine_volume_mounts.push(VolumeMountConfig {
+                            volume: ephemeral_volume,
+                            mount_at: ephemeral.path,
+                            read_only: false,
+                            root: false,
+                            encryption_key: None,
+                        });
+                    }
+                    let ephemeral_volume_status =
+                        (!ephemeral_volume_status.is_empty()).then_some(ephemeral_volume_status);
+
                     // alloc ip for machine
                     let ip = match status.machine_ip {
                         Some(ip) => ip.clone(),
@@ -732,10 +1550,22 @@ impl Controller for MachineController {
                         anyhow!("failed to create tap device for machine: {}: {}", name, e)
                     })?;
 
+                    let (ingress_bits, egress_bits) = bandwidth_limits(machine.network.as_ref())?;
+                    ctx.agent
+                        .net()
+                        .device_set_bandwidth(&tap.name, ingress_bits, egress_bits)
+                        .await
+                        .map_err(|e| {
+                            anyhow!("failed to limit the bandwidth of machine: {}: {}", name, e)
+                        })?;
+
                     let mode = match machine.mode {
-                    None | Some(resources::machine::MachineMode::Regular) => MachineMode::Regular,
-                    Some(resources::machine::MachineMode::Flash { strategy, timeout }) => {
-                        match strategy {
+                        None | Some(resources::machine::MachineMode::Regular) => {
+                            MachineMode::Regular
+                        }
+                        Some(resources::machine::MachineMode::Flash {
+                            strategy, timeout, ..
+                        }) => match strategy {
                             resources::machine::MachineSnapshotStrategy::WaitForUserSpaceReady => {
                                 MachineMode::Flash {
                                     snapshot_strategy: SnapshotStrategy::WaitForUserSpaceReady,
@@ -776,9 +1606,8 @@ impl Controller for MachineController {
                                     ),
                                 }
                             }
-                        }
-                    }
-                };
+                        },
+                    };
 
                     let mac = compute_mac_for_ip(&ip)
                         .map_err(|_| anyhow!("failed to compute MAC address for IP: {}", ip))?;
@@ -793,14 +1622,17 @@ impl Controller for MachineController {
                         .machine()
                         .create_machine(MachineConfig {
                             name: name.clone(),
-                            // TODO: network tag should be something similar to the app name (for scaling issues)
-                            network_tag: name.clone(),
+                            network_tag: network_tag_for(
+                                &key,
+                                machine.tags.as_deref().unwrap_or_default(),
+                            ),
                             controller_key: key.clone(),
                             image,
                             mode,
                             resources: MachineResources {
                                 cpu: machine.resources.cpu,
                                 memory: machine.resources.memory,
+                                cpu_limit: machine.resources.cpu_limit,
                             },
                             cmd: machine.command.clone(),
                             envs: machine
@@ -831,6 +1663,29 @@ impl Controller for MachineController {
                                     .unwrap_or(DEFAULT_NAMESPACE.to_string()),
                                 service_group: machine.name.clone(),
                             },
+                            metrics_telemetry_config: ctx
+                                .agent
+                                .logs()
+                                .get_otel_metrics_endpoint()
+                                .map(|endpoint| MetricsTelemetryConfig {
+                                    endpoint,
+                                    interval_secs: DEFAULT_METRICS_EXPORT_INTERVAL_SECS,
+                                }),
+                            debug_trace: status.debug_trace.as_ref().map(|trace| {
+                                DebugTraceConfig {
+                                    tool: match trace {
+                                        MachineDebugTrace::Strace => DebugTraceTool::Strace,
+                                        MachineDebugTrace::Ltrace => DebugTraceTool::Ltrace,
+                                    },
+                                    max_lines: DEFAULT_DEBUG_TRACE_MAX_LINES,
+                                }
+                            }),
+                            health: machine.health.as_ref().map(|health| HealthConfig {
+                                port: health.port.unwrap_or(DEFAULT_HEALTH_PORT),
+                                app_port: health.app_port,
+                            }),
+                            kernel_params: machine.kernel_params.clone().unwrap_or_default(),
+                            prewarm: is_prewarm_clone(machine.tags.as_deref().unwrap_or_default()),
                         })
                         .await
                         .map_err(|e| {
@@ -849,10 +1704,27 @@ impl Controller for MachineController {
                             status.machine_ip = Some(ip_addr.clone());
                             status.machine_tap = Some(tap_name.clone());
                             status.machine_image_volume_id = Some(image_volume_id.clone());
+                            status.machine_ephemeral_volumes = ephemeral_volume_status.clone();
+                            // tracing only applies to a single boot
+                            status.debug_trace = None;
                         })
                         .await?;
                 }
                 MachinePhase::Stopped => {
+                    // ephemeral volumes don't outlive the boot they were created for
+                    if let Some(volumes) = &status.machine_ephemeral_volumes {
+                        for volume in volumes {
+                            ctx.agent.volume().volume_delete(&volume.volume_id).await?;
+                        }
+
+                        ctx.repository
+                            .machine(ctx.tenant.clone())
+                            .patch_status(key.metadata(), |status| {
+                                status.machine_ephemeral_volumes = None;
+                            })
+                            .await?;
+                    }
+
                     let should_restart = match machine
                         .restart_policy
                         .unwrap_or(resources::machine::MachineRestartPolicy::Always)
@@ -890,6 +1762,14 @@ impl Controller for MachineController {
                             "Machine {} exceeded max restart count ({}/{}), entering error state",
                             machine_name, restart_count, MAX_RESTART_COUNT
                         );
+                        ctx.agent.events().emit(
+                            &key,
+                            EventReason::MachineFailed,
+                            format!(
+                                "Max restart count exceeded ({}/{})",
+                                restart_count, MAX_RESTART_COUNT
+                            ),
+                        );
                         ctx.repository
                             .machine(ctx.tenant.clone())
                             .patch_status(key.metadata(), |status| {
@@ -904,6 +1784,16 @@ impl Controller for MachineController {
                         break 'phase_match;
                     }
 
+                    ctx.agent.events().emit(
+                        &key,
+                        EventReason::MachineRestarting,
+                        format!(
+                            "Restarting, attempt {}/{}",
+                            restart_count + 1,
+                            MAX_RESTART_COUNT
+                        ),
+                    );
+
                     ctx.repository
                         .machine(ctx.tenant.clone())
                         .patch_status(key.metadata(), |status| {
@@ -994,6 +1884,38 @@ impl Controller for MachineController {
                         return Ok(ReconcileNext::immediate());
                     }
                 }
+                MachinePhase::Ready => {
+                    let Some(policy) = &machine.suspend else {
+                        break 'phase_match;
+                    };
+                    let Some(running_machine) = ctx.agent.machine().get_machine(&machine_name)
+                    else {
+                        break 'phase_match;
+                    };
+
+                    let after_idle = suspend_after_idle(policy)?;
+                    let idle_for = running_machine
+                        .get_idle_duration()
+                        .await
+                        .unwrap_or_default();
+                    if idle_for < after_idle {
+                        return Ok(ReconcileNext::after(after_idle - idle_for));
+                    }
+
+                    // no traffic for a while, but the machine may still be busy on its own
+                    let max_cpu = policy.max_cpu.unwrap_or(DEFAULT_IDLE_SUSPEND_MAX_CPU);
+                    if !is_cpu_idle(&ctx, &machine, max_cpu).await {
+                        return Ok(ReconcileNext::after(IDLE_SUSPEND_POLL_INTERVAL));
+                    }
+
+                    info!(
+                        "machine {} has been idle for {}s, suspending it",
+                        machine_name,
+                        idle_for.as_secs()
+                    );
+                    // the proxy wakes it up on the next request
+                    running_machine.suspend().await?;
+                }
                 _ => {}
             }
         };
@@ -1013,6 +1935,10 @@ impl Controller for MachineController {
             err
         );
 
+        ctx.agent
+            .events()
+            .emit(&key, EventReason::MachineFailed, err.to_string());
+
         ctx.repository
             .machine(ctx.tenant.clone())
             .patch_status(key.metadata(), |status| {
@@ -1034,8 +1960,8 @@ impl AdmissionCheckBeforeSet for Machine {
         _before: Option<&Self>,
         tenant: String,
         repo: Arc<Repository>,
-        _agent: Arc<Agent>,
-        _metadata: Metadata,
+        agent: Arc<Agent>,
+        metadata: Metadata,
     ) -> Result<()> {
         let resource = self.latest();
         let resource_namespace = Namespace::from_value_or_default(resource.namespace.clone());
@@ -1048,12 +1974,156 @@ impl AdmissionCheckBeforeSet for Machine {
             bail!("image is not set for machine: {}", resource.name);
         }
 
-        // see if the volumes are being used by other machines
+        for param in resource.kernel_params.iter().flatten() {
+            validate_kernel_param(param)?;
+        }
+
+        if let Some(rollout) = &resource.rollout {
+            if rollout.strategy == MachineRolloutStrategy::Rolling
+                && rollout.max_surge.unwrap_or(DEFAULT_ROLLOUT_MAX_SURGE) == 0
+                && rollout
+                    .max_unavailable
+                    .unwrap_or(DEFAULT_ROLLOUT_MAX_UNAVAILABLE)
+                    == 0
+            {
+                bail!("a rolling rollout needs max-surge or max-unavailable greater than 0");
+            }
+        }
+        let surges = rolling_rollout(&resource).is_some();
+        let prewarms = prewarm_count(&resource) > 0;
+
+        if let Some(cpu_limit) = resource.resources.cpu_limit {
+            if cpu_limit < 10 || cpu_limit > resource.resources.cpu as u32 * 1000 {
+                bail!(
+                    "cpu-limit must be between 10 and {} millicores, the machine has {} vcpus",
+                    resource.resources.cpu as u32 * 1000,
+                    resource.resources.cpu
+                );
+            }
+        }
+
+        if let Some(ttl) = &resource.ttl {
+            parse_ttl(ttl)?;
+        }
+
+        if let Some(constraints) = resource
+            .placement
+            .as_ref()
+            .and_then(|placement| placement.constraints.as_ref())
+        {
+            parse_constraints(constraints)?;
+        }
+
+        bandwidth_limits(resource.network.as_ref())?;
+
+        if let Some(policy) = &resource.suspend {
+            suspend_after_idle(policy)?;
+
+            if policy.max_cpu.is_some_and(|max_cpu| max_cpu > 100) {
+                bail!("suspend max-cpu is a percentage, it can't be over 100");
+            }
+        }
+
+        let ephemeral_volumes = resource.ephemeral_volumes();
         let volumes = resource.volumes.unwrap_or_default();
+        let status = repo.machine(tenant.clone()).get_status(metadata.clone())?;
+        MissingReferences::check(
+            "machine",
+            metadata,
+            missing_volumes(&repo, &tenant, &resource.namespace, &volumes)?,
+        )?;
+
+        let mut mount_paths = volumes
+            .iter()
+            .map(|volume| volume.path.trim_end_matches('/'))
+            .collect::<HashSet<_>>();
+        for ephemeral in &ephemeral_volumes {
+            if ephemeral.size_mib == 0 {
+                bail!(
+                    "ephemeral volume {} size must be greater than 0",
+                    ephemeral.path
+                );
+            }
+            ephemeral.size_bytes()?;
+
+            let path = ephemeral.path.trim_end_matches('/');
+            if !path.starts_with('/') {
+                bail!(
+                    "ephemeral volume path {} must be absolute and can't be /",
+                    ephemeral.path
+                );
+            }
+            if !mount_paths.insert(path) {
+                bail!(
+                    "ephemeral volume path {} is already used by another volume",
+                    ephemeral.path
+                );
+            }
+        }
+
+        // the ephemeral volumes count against the volume quota, the ones of the current boot
+        // already do
+        let ephemeral_bytes = ephemeral_volumes
+            .iter()
+            .map(|ephemeral| ephemeral.size_bytes())
+            .sum::<Result<u64>>()?;
+        if ephemeral_bytes > 0 {
+            let provisioned_bytes = status
+                .and_then(|status| status.machine_ephemeral_volumes)
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|volume| agent.volume().volume(&volume.volume_id).ok().flatten())
+                .map(|volume| volume.provisioned_bytes())
+                .sum::<u64>();
+            agent
+                .volume()
+                .check_quota(&tenant, ephemeral_bytes.saturating_sub(provisioned_bytes))?;
+        }
+
+        // see if the volumes are being used by other machines
         if volumes.is_empty() {
             return Ok(());
         }
 
+        // read-only volumes can be shared, the others are attached to one machine at a time
+        let mut writeable_volumes = vec![];
+        for volume in volumes.iter() {
+            let volume_namespace = Namespace::from_value_or_default(
+                volume
+                    .namespace
+                    .clone()
+                    .or_else(|| resource.namespace.clone()),
+            );
+
+            let read_only = repo
+                .volume(tenant.clone())
+                .get(volume_namespace.clone(), volume.name.clone())?
+                .is_some_and(|volume| volume.latest().mode == VolumeMode::ReadOnly);
+            if !read_only {
+                writeable_volumes.push((volume, volume_namespace));
+            }
+        }
+
+        if writeable_volumes.is_empty() {
+            return Ok(());
+        }
+
+        // the surge of a rolling rollout runs next to the machine, with the same volumes
+        if surges {
+            bail!(
+                "volume {} is writeable, a rolling rollout with max-surge needs read-only volumes",
+                writeable_volumes[0].0.name
+            );
+        }
+
+        // and so do the prewarmed clones of a flash machine
+        if prewarms {
+            bail!(
+                "volume {} is writeable, prewarmed clones need read-only volumes",
+                writeable_volumes[0].0.name
+            );
+        }
+
         let machines = repo.machine(tenant.clone()).list(Namespace::Unspecified)?;
         for machine in machines {
             let machine = machine.latest();
@@ -1064,14 +2134,7 @@ impl AdmissionCheckBeforeSet for Machine {
 
             let machine_volumes = machine.volumes.unwrap_or_default();
 
-            for volume in volumes.iter() {
-                let volume_namespace = Namespace::from_value_or_default(
-                    volume
-                        .namespace
-                        .clone()
-                        .or_else(|| resource.namespace.clone()),
-                );
-
+            for (volume, volume_namespace) in writeable_volumes.iter() {
                 for machine_volume in machine_volumes.iter() {
                     if machine_volume.name != volume.name {
                         continue;
@@ -1084,12 +2147,12 @@ impl AdmissionCheckBeforeSet for Machine {
                             .or_else(|| machine.namespace.clone()),
                     );
 
-                    if machine_volume_namespace != volume_namespace {
+                    if &machine_volume_namespace != volume_namespace {
                         continue;
                     }
 
                     bail!(
-                        "Volume {} is being used by machine {} in namespace {}",
+                        "Volume {} is being used by machine {} in namespace {}, only read-only volumes can be shared",
                         volume.name,
                         machine.name,
                         machine_volume_namespace
@@ -0,0 +1,714 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{Result, anyhow, bail};
+use async_trait::async_trait;
+use tracing::{error, info, warn};
+
+use crate::{
+    agent::{Agent, machine::machine::MachineRef},
+    constants::{DEFAULT_NAMESPACE, DEFAULT_ROLLOUT_MAX_SURGE, DEFAULT_ROLLOUT_MAX_UNAVAILABLE},
+    controller::{
+        AdmissionCheckBeforeSet, Controller, ReconcileNext,
+        context::{ControllerContext, ControllerEvent, ControllerKey},
+        machine::{MACHINE_GROUP_TAG_PREFIX, machine_name_from_key},
+    },
+    repository::Repository,
+    resource_index::ResourceKind,
+    resources::{
+        Convert, ProvideMetadata,
+        machine::{Machine, MachinePhase, MachineRollout, MachineRolloutStrategy, MachineV1},
+        machine_group::{
+            DEFAULT_MACHINE_GROUP_DRAIN_TIMEOUT_SECS, DEFAULT_MACHINE_GROUP_REPLICAS,
+            DEFAULT_MACHINE_GROUP_SCALE_DOWN_DELAY_SECS, MachineGroup, MachineGroupAutoscale,
+            MachineGroupDrainingReplica, MachineGroupLatest, MachineGroupStatus,
+        },
+        metadata::{Metadata, Namespace},
+        volume::VolumeMode,
+    },
+    utils::time::now_millis,
+};
+
+/// How often the load of the replicas is sampled, and missing replicas recreated.
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(15);
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const ROLLOUT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// Request rates measured over less time than this are too noisy, the previous one is kept.
+const MIN_RPS_SAMPLE_MILLIS: u64 = 5_000;
+const CPU_WINDOW_SECS: u64 = 60;
+
+pub struct MachineGroupController;
+
+impl MachineGroupController {
+    pub fn new_boxed() -> Box<Self> {
+        Box::new(Self)
+    }
+}
+
+#[async_trait]
+impl Controller for MachineGroupController {
+    async fn schedule(
+        &self,
+        ctx: ControllerContext,
+        event: ControllerEvent,
+    ) -> Result<Option<ControllerKey>> {
+        let key = match event {
+            ControllerEvent::ResourceChange(ResourceKind::MachineGroup, metadata)
+            | ControllerEvent::BringUp(ResourceKind::MachineGroup, metadata) => {
+                Some(ControllerKey::new(
+                    ctx.tenant.clone(),
+                    ResourceKind::MachineGroup,
+                    metadata.namespace,
+                    metadata.name,
+                ))
+            }
+            _ => None,
+        };
+        Ok(key)
+    }
+
+    async fn should_reconcile(&self, _ctx: ControllerContext, key: ControllerKey) -> bool {
+        key.kind == ResourceKind::MachineGroup
+    }
+
+    async fn reconcile(&self, ctx: ControllerContext, key: ControllerKey) -> Result<ReconcileNext> {
+        info!(
+            "reconciling machine group controller for key: {}",
+            key.to_string()
+        );
+
+        let group_repo = ctx.repository.machine_group(ctx.tenant.clone());
+        let machine_repo = ctx.repository.machine(ctx.tenant.clone());
+
+        let Some((group, status)) = group_repo.get_with_status(key.metadata())? else {
+            // the group was deleted, so are its replicas
+            let Some(status) = group_repo.get_status(key.metadata())? else {
+                return Ok(ReconcileNext::done());
+            };
+
+            let namespace = Namespace::from_value(key.metadata().namespace.clone());
+            let replicas = status
+                .machines
+                .iter()
+                .chain(status.draining.iter().map(|replica| &replica.name));
+            for name in replicas {
+                machine_repo.delete(namespace.clone(), name).await.ok();
+            }
+
+            group_repo.delete_status(key.metadata()).await?;
+
+            return Ok(ReconcileNext::done());
+        };
+
+        let group = group.latest();
+        let namespace = Namespace::from_value(group.namespace.clone())
+            .as_value()
+            .unwrap_or(DEFAULT_NAMESPACE.to_string());
+        let now = now_millis();
+        let mut status = status;
+
+        let replicas = match &group.autoscale {
+            Some(autoscale) => {
+                sample_load(&ctx, &group, &namespace, autoscale, &mut status, now).await;
+                autoscale_replicas(autoscale, &mut status, now)
+            }
+            None => group.replicas.unwrap_or(DEFAULT_MACHINE_GROUP_REPLICAS),
+        };
+        if replicas != status.replicas {
+            info!(
+                "scaling machine group {} from {} to {} replicas",
+                key.to_string(),
+                status.replicas,
+                replicas
+            );
+        }
+        status.replicas = replicas;
+
+        // the mode of a volume can change after the group was admitted, replicas created now
+        // would all get it read-write
+        check_shared_volumes(&ctx.repository, &ctx.tenant, &group)?;
+
+        let group_tag = format!("{}{}", MACHINE_GROUP_TAG_PREFIX, group.name);
+        let hash = Machine::V1(replica_machine(&group, &namespace, group.name.clone()))
+            .hash_with_updated_metadata();
+        let names = (0..replicas)
+            .map(|index| format!("{}-{}", group.name, index))
+            .collect::<Vec<_>>();
+
+        let mut outdated = vec![];
+        let mut updating = 0;
+        for name in names.iter() {
+            // scaled back up before the replica was drained
+            if let Some(position) = status.draining.iter().position(|r| &r.name == name) {
+                status.draining.remove(position);
+                if let Some(machine) = replica_agent_machine(&ctx, &namespace, name) {
+                    machine.set_draining(false);
+                }
+            }
+
+            let replica = replica_machine(&group, &namespace, name.clone());
+            let existing =
+                machine_repo.get(Namespace::from_value(Some(namespace.clone())), name)?;
+            let Some(existing) = existing else {
+                set_replica(&ctx, None, replica, &namespace).await?;
+                continue;
+            };
+
+            let existing_latest = existing.latest();
+            if !existing_latest
+                .tags
+                .clone()
+                .unwrap_or_default()
+                .contains(&group_tag)
+            {
+                bail!(
+                    "machine {} already exists and is not a replica of machine group {}",
+                    name,
+                    group.name
+                );
+            }
+
+            if existing_latest != replica {
+                outdated.push((existing, replica));
+                continue;
+            }
+
+            let replica = Machine::V1(replica);
+            let settled = machine_repo
+                .get_status(replica.metadata())?
+                .is_some_and(|status| {
+                    status.hash == replica.hash_with_updated_metadata()
+                        && status.rollout_surge.is_none()
+                        && matches!(
+                            status.phase,
+                            MachinePhase::Ready
+                                | MachinePhase::Suspending
+                                | MachinePhase::Suspended
+                        )
+                });
+            if !settled {
+                updating += 1;
+            }
+        }
+
+        // a rolling rollout replaces a few replicas at a time, the others keep serving
+        let rolling_out = !outdated.is_empty() || updating > 0;
+        let budget = rollout_budget(group.rollout.as_ref()).saturating_sub(updating);
+        for (existing, replica) in outdated.into_iter().take(budget) {
+            info!(
+                "updating replica {} of machine group {}",
+                replica.name,
+                key.to_string()
+            );
+            set_replica(&ctx, Some(&existing), replica, &namespace).await?;
+        }
+        status.hash = hash;
+
+        // replicas removed by a scale-down stop getting new connections first
+        let removed = status
+            .machines
+            .iter()
+            .filter(|name| !names.contains(name))
+            .cloned()
+            .collect::<Vec<_>>();
+        for name in removed {
+            status
+                .draining
+                .push(MachineGroupDrainingReplica { name, since: now });
+        }
+        status.machines = names;
+
+        let drain_timeout_millis = group
+            .drain_timeout
+            .unwrap_or(DEFAULT_MACHINE_GROUP_DRAIN_TIMEOUT_SECS)
+            * 1000;
+        let mut draining = vec![];
+        for replica in std::mem::take(&mut status.draining) {
+            let drained = match replica_agent_machine(&ctx, &namespace, &replica.name) {
+                Some(machine) => {
+                    machine.set_draining(true);
+                    machine.open_connections() == 0
+                }
+                None => true,
+            };
+
+            if drained || now.saturating_sub(replica.since) >= drain_timeout_millis {
+                info!(
+                    "removing drained replica {} of machine group {}",
+                    replica.name,
+                    key.to_string()
+                );
+                machine_repo
+                    .delete(
+                        Namespace::from_value(Some(namespace.clone())),
+                        &replica.name,
+                    )
+                    .await
+                    .ok();
+            } else {
+                draining.push(replica);
+            }
+        }
+        status.draining = draining;
+
+        let drained = status.draining.is_empty();
+        group_repo.set_status(key.metadata(), status).await?;
+
+        if !drained {
+            Ok(ReconcileNext::after(DRAIN_POLL_INTERVAL))
+        } else if rolling_out {
+            Ok(ReconcileNext::after(ROLLOUT_POLL_INTERVAL))
+        } else {
+            Ok(ReconcileNext::after(RECONCILE_INTERVAL))
+        }
+    }
+
+    async fn handle_error(
+        &self,
+        _ctx: ControllerContext,
+        key: ControllerKey,
+        err: anyhow::Error,
+    ) -> ReconcileNext {
+        error!(
+            "handling error for machine group controller for key: {} error: {}",
+            key.to_string(),
+            err
+        );
+
+        ReconcileNext::after(RECONCILE_INTERVAL)
+    }
+}
+
+/// The machine resource of a replica, tagged with the group so it gets the group network tag.
+fn replica_machine(group: &MachineGroupLatest, namespace: &str, name: String) -> MachineV1 {
+    let mut tags = group.tags.clone().unwrap_or_default();
+    tags.push(format!("{}{}", MACHINE_GROUP_TAG_PREFIX, group.name));
+
+    MachineV1 {
+        name,
+        namespace: Some(namespace.to_string()),
+        tags: Some(tags),
+        image: group.image.clone(),
+        build: None,
+        build_env: None,
+        resources: group.resources.clone(),
+        restart_policy: group.restart_policy.clone(),
+        mode: group.mode.clone(),
+        volumes: group.volumes.clone(),
+        scratch: group.scratch.clone(),
+        ephemeral_volumes: group.ephemeral_volumes.clone(),
+        command: group.command.clone(),
+        environment: group.environment.clone(),
+        depends_on: None,
+        health: group.health.clone(),
+        kernel_params: group.kernel_params.clone(),
+        rollout: group.rollout.clone(),
+        // replicas are scaled down instead
+        suspend: None,
+        ttl: None,
+        placement: group.placement.clone(),
+        network: group.network.clone(),
+    }
+}
+
+fn replica_agent_machine(
+    ctx: &ControllerContext,
+    namespace: &str,
+    name: &str,
+) -> Option<MachineRef> {
+    let key = ControllerKey::new(
+        ctx.tenant.clone(),
+        ResourceKind::Machine,
+        Some(namespace),
+        name,
+    );
+    ctx.agent
+        .machine()
+        .get_machine(&machine_name_from_key(&key))
+}
+
+/// Updates the request rate and the CPU utilization of the group in its status.
+async fn sample_load(
+    ctx: &ControllerContext,
+    group: &MachineGroupLatest,
+    namespace: &str,
+    autoscale: &MachineGroupAutoscale,
+    status: &mut MachineGroupStatus,
+    now: u64,
+) {
+    if autoscale.target_rps.is_some() {
+        let network_tag = machine_name_from_key(&ControllerKey::new(
+            ctx.tenant.clone(),
+            ResourceKind::Machine,
+            Some(namespace),
+            &group.name,
+        ));
+        let count = ctx
+            .agent
+            .proxy()
+            .request_count_for_network_tag(&network_tag);
+
+        let elapsed = status.sampled_at.map(|at| now.saturating_sub(at));
+        if elapsed.is_none_or(|elapsed| elapsed >= MIN_RPS_SAMPLE_MILLIS) {
+            // counts restart from zero with the daemon, the first sample after that is skipped
+            status.observed_rps = match (status.request_count, elapsed) {
+                (Some(previous), Some(elapsed)) if count >= previous => {
+                    Some((count - previous) * 1000 / elapsed)
+                }
+                _ => None,
+            };
+            status.request_count = Some(count);
+            status.sampled_at = Some(now);
+        }
+    }
+
+    if autoscale.target_cpu.is_some() {
+        status.observed_cpu = match replicas_cpu(ctx, namespace, &status.machines, now).await {
+            Ok(cpu) => cpu,
+            Err(e) => {
+                warn!(
+                    "failed to query the CPU utilization of machine group {}: {}",
+                    group.name, e
+                );
+                None
+            }
+        };
+    }
+}
+
+/// Average CPU utilization of the replicas, in percent, over the ones that exported any.
+async fn replicas_cpu(
+    ctx: &ControllerContext,
+    namespace: &str,
+    machines: &[String],
+    now: u64,
+) -> Result<Option<u8>> {
+    let metrics = ctx.agent.metrics()?;
+    let now_secs = now / 1000;
+
+    let mut total = 0.0;
+    let mut count = 0;
+    for name in machines {
+        let usage = metrics
+            .machine_usage(
+                &ctx.tenant,
+                namespace,
+                name,
+                now_secs.saturating_sub(CPU_WINDOW_SECS)..=now_secs,
+            )
+            .await?;
+
+        if let Some((_, cpu)) = usage.cpu_utilization.last() {
+            total += cpu;
+            count += 1;
+        }
+    }
+
+    if count == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(
+        (total / count as f64 * 100.0).round().clamp(0.0, 100.0) as u8,
+    ))
+}
+
+/// Replicas replaced at once when the template changes, all of them unless the group rolls.
+fn rollout_budget(rollout: Option<&MachineRollout>) -> usize {
+    let Some(rollout) = rollout else {
+        return usize::MAX;
+    };
+    if rollout.strategy != MachineRolloutStrategy::Rolling {
+        return usize::MAX;
+    }
+
+    let budget = match rollout.max_surge.unwrap_or(DEFAULT_ROLLOUT_MAX_SURGE) {
+        0 => rollout
+            .max_unavailable
+            .unwrap_or(DEFAULT_ROLLOUT_MAX_UNAVAILABLE),
+        max_surge => max_surge,
+    };
+
+    budget.max(1) as usize
+}
+
+/// Scales up right away, and down only once the load stayed low for the scale-down delay.
+fn autoscale_replicas(
+    autoscale: &MachineGroupAutoscale,
+    status: &mut MachineGroupStatus,
+    now: u64,
+) -> u32 {
+    let current = status.replicas.clamp(autoscale.min, autoscale.max);
+    let wanted = wanted_replicas(autoscale, current, status.observed_rps, status.observed_cpu);
+
+    if wanted > current {
+        status.low_load_since = None;
+        status.last_scaled_at = Some(now);
+        return wanted;
+    }
+
+    if wanted == current {
+        status.low_load_since = None;
+        return current;
+    }
+
+    let delay_millis = autoscale
+        .scale_down_delay
+        .unwrap_or(DEFAULT_MACHINE_GROUP_SCALE_DOWN_DELAY_SECS)
+        * 1000;
+    let low_load_since = *status.low_load_since.get_or_insert(now);
+    if now.saturating_sub(low_load_since) < delay_millis {
+        return current;
+    }
+
+    status.low_load_since = None;
+    status.last_scaled_at = Some(now);
+    wanted
+}
+
+/// Replicas needed to bring the load of each one under the targets, the current count when
+/// there is no sample yet.
+fn wanted_replicas(
+    autoscale: &MachineGroupAutoscale,
+    current: u32,
+    rps: Option<u64>,
+    cpu: Option<u8>,
+) -> u32 {
+    let mut wanted = None;
+
+    if let (Some(target), Some(rps)) = (autoscale.target_rps, rps) {
+        let replicas = rps.div_ceil(target.max(1) as u64) as u32;
+        wanted = Some(wanted.unwrap_or(0).max(replicas));
+    }
+
+    if let (Some(target), Some(cpu)) = (autoscale.target_cpu, cpu) {
+        let replicas = (current as u64 * cpu as u64).div_ceil(target.max(1) as u64) as u32;
+        wanted = Some(wanted.unwrap_or(0).max(replicas));
+    }
+
+    wanted
+        .unwrap_or(current)
+        .clamp(autoscale.min, autoscale.max)
+}
+
+#[async_trait]
+impl AdmissionCheckBeforeSet for MachineGroup {
+    async fn before_set(
+        &self,
+        _before: Option<&Self>,
+        tenant: String,
+        repo: Arc<Repository>,
+        agent: Arc<Agent>,
+        metadata: Metadata,
+    ) -> Result<()> {
+        let group = self.latest();
+        let namespace = Namespace::from_value_or_default(group.namespace.clone());
+
+        if let Some(autoscale) = &group.autoscale {
+            if group.replicas.is_some() {
+                bail!("replicas can't be set on an autoscaled machine group");
+            }
+            if autoscale.max == 0 || autoscale.min > autoscale.max {
+                bail!(
+                    "autoscale min ({}) must be at most max ({}), which must be greater than 0",
+                    autoscale.min,
+                    autoscale.max
+                );
+            }
+            if autoscale.target_rps.is_none() && autoscale.target_cpu.is_none() {
+                bail!("autoscale needs a target-rps or a target-cpu");
+            }
+            if autoscale.target_rps == Some(0) {
+                bail!("autoscale target-rps must be greater than 0");
+            }
+            if let Some(target_cpu) = autoscale.target_cpu {
+                if target_cpu == 0 || target_cpu > 100 {
+                    bail!("autoscale target-cpu must be between 1 and 100");
+                }
+                if agent.metrics().is_err() {
+                    bail!("autoscale target-cpu needs a metrics store, none is configured");
+                }
+            }
+        }
+
+        // a machine of the same name would get the network tag of the group
+        if repo
+            .machine(tenant.clone())
+            .get(namespace.clone(), group.name.clone())?
+            .is_some()
+        {
+            bail!(
+                "machine {} already exists, a machine group can't have the same name",
+                group.name
+            );
+        }
+
+        check_shared_volumes(&repo, &tenant, &group)?;
+
+        // the replicas are checked again when they are written, against the quotas of that time
+        let namespace = namespace
+            .as_value()
+            .unwrap_or(DEFAULT_NAMESPACE.to_string());
+        Machine::V1(replica_machine(&group, &namespace, group.name.clone()))
+            .before_set(None, tenant, repo, agent, metadata)
+            .await
+    }
+}
+
+/// The replicas of a group all attach its volumes, only read-only ones can be shared.
+fn check_shared_volumes(repo: &Repository, tenant: &str, group: &MachineGroupLatest) -> Result<()> {
+    for volume in group.volumes.iter().flatten() {
+        let volume_namespace = Namespace::from_value_or_default(
+            volume.namespace.clone().or_else(|| group.namespace.clone()),
+        );
+        let writeable = repo
+            .volume(tenant.to_string())
+            .get(volume_namespace, volume.name.clone())?
+            .is_some_and(|volume| volume.latest().mode != VolumeMode::ReadOnly);
+        if writeable {
+            bail!(
+                "volume {} is writeable, the replicas of a machine group can only share read-only volumes",
+                volume.name
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a replica, which goes through the checks the user's machines do.
+async fn set_replica(
+    ctx: &ControllerContext,
+    existing: Option<&Machine>,
+    replica: MachineV1,
+    namespace: &str,
+) -> Result<()> {
+    let metadata = Metadata::new(
+        &replica.name,
+        Namespace::from_value(Some(namespace.to_string())),
+    );
+    let replica = Machine::V1(replica);
+    replica
+        .before_set(
+            existing,
+            ctx.tenant.clone(),
+            ctx.repository.clone(),
+            ctx.agent.clone(),
+            metadata,
+        )
+        .await
+        .map_err(|e| anyhow!("replica {} can't be written: {}", replica.latest().name, e))?;
+
+    ctx.repository
+        .machine(ctx.tenant.clone())
+        .set(replica)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn autoscale(target_rps: Option<u32>, target_cpu: Option<u8>) -> MachineGroupAutoscale {
+        MachineGroupAutoscale {
+            min: 1,
+            max: 10,
+            target_rps,
+            target_cpu,
+            scale_down_delay: Some(60),
+        }
+    }
+
+    fn status(replicas: u32, rps: Option<u64>, cpu: Option<u8>) -> MachineGroupStatus {
+        MachineGroupStatus {
+            hash: 0,
+            replicas,
+            machines: vec![],
+            draining: vec![],
+            request_count: None,
+            sampled_at: None,
+            observed_rps: rps,
+            observed_cpu: cpu,
+            low_load_since: None,
+            last_scaled_at: None,
+            conditions: vec![],
+        }
+    }
+
+    #[test]
+    fn test_wanted_replicas() {
+        let rps = autoscale(Some(100), None);
+        assert_eq!(wanted_replicas(&rps, 2, None, None), 2);
+        assert_eq!(wanted_replicas(&rps, 2, Some(0), None), 1);
+        assert_eq!(wanted_replicas(&rps, 2, Some(450), None), 5);
+        assert_eq!(wanted_replicas(&rps, 2, Some(5000), None), 10);
+
+        let cpu = autoscale(None, Some(50));
+        assert_eq!(wanted_replicas(&cpu, 4, None, Some(75)), 6);
+        assert_eq!(wanted_replicas(&cpu, 4, None, Some(10)), 1);
+
+        // the busiest metric wins
+        let both = autoscale(Some(100), Some(50));
+        assert_eq!(wanted_replicas(&both, 2, Some(50), Some(100)), 4);
+    }
+
+    #[test]
+    fn test_rollout_budget() {
+        let rollout = |strategy, max_surge, max_unavailable| MachineRollout {
+            strategy,
+            max_surge,
+            max_unavailable,
+            ready_timeout: None,
+            drain_timeout: None,
+        };
+
+        assert_eq!(rollout_budget(None), usize::MAX);
+        assert_eq!(
+            rollout_budget(Some(&rollout(MachineRolloutStrategy::Recreate, None, None))),
+            usize::MAX
+        );
+        assert_eq!(
+            rollout_budget(Some(&rollout(MachineRolloutStrategy::Rolling, None, None))),
+            1
+        );
+        assert_eq!(
+            rollout_budget(Some(&rollout(
+                MachineRolloutStrategy::Rolling,
+                Some(3),
+                Some(1)
+            ))),
+            3
+        );
+        assert_eq!(
+            rollout_budget(Some(&rollout(
+                MachineRolloutStrategy::Rolling,
+                Some(0),
+                Some(2)
+            ))),
+            2
+        );
+    }
+
+    #[test]
+    fn test_scale_down_waits_for_delay() {
+        let autoscale = autoscale(Some(100), None);
+
+        let mut up = status(2, Some(450), None);
+        assert_eq!(autoscale_replicas(&autoscale, &mut up, 1_000), 5);
+        assert_eq!(up.last_scaled_at, Some(1_000));
+
+        let mut down = status(5, Some(90), None);
+        assert_eq!(autoscale_replicas(&autoscale, &mut down, 1_000), 5);
+        assert_eq!(down.low_load_since, Some(1_000));
+        assert_eq!(autoscale_replicas(&autoscale, &mut down, 30_000), 5);
+        assert_eq!(autoscale_replicas(&autoscale, &mut down, 61_000), 1);
+        assert_eq!(down.low_load_since, None);
+
+        // a busy sample in between restarts the delay
+        let mut bursty = status(5, Some(90), None);
+        autoscale_replicas(&autoscale, &mut bursty, 1_000);
+        bursty.observed_rps = Some(500);
+        assert_eq!(autoscale_replicas(&autoscale, &mut bursty, 30_000), 5);
+        assert_eq!(bursty.low_load_since, None);
+    }
+}
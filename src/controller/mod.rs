@@ -3,7 +3,15 @@ pub mod scheduler;
 
 pub mod app;
 pub mod certificate;
+pub mod certificate_renewal;
+pub mod cron;
+pub mod dns_record;
+pub mod expiry;
+pub mod health_probe;
 pub mod machine;
+pub mod machine_group;
+pub mod placement;
+pub mod references;
 pub mod service;
 pub mod volume;
 
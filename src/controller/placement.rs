@@ -0,0 +1,128 @@
+use anyhow::{Result, bail};
+
+use crate::agent::machine::NodeInfo;
+
+/// A `key=value` or `key!=value` requirement on the labels of the node a machine runs on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlacementConstraint {
+    key: String,
+    value: String,
+    equal: bool,
+}
+
+impl PlacementConstraint {
+    pub fn parse(constraint: &str) -> Result<Self> {
+        let (key, value, equal) = match constraint.split_once("!=") {
+            Some((key, value)) => (key, value, false),
+            None => match constraint.split_once('=') {
+                Some((key, value)) => (key, value, true),
+                None => bail!(
+                    "invalid placement constraint {}, expected key=value or key!=value",
+                    constraint
+                ),
+            },
+        };
+
+        let (key, value) = (key.trim(), value.trim());
+        if key.is_empty() || value.is_empty() {
+            bail!(
+                "invalid placement constraint {}, the key and the value can't be empty",
+                constraint
+            );
+        }
+
+        Ok(Self {
+            key: key.to_string(),
+            value: value.to_string(),
+            equal,
+        })
+    }
+
+    /// Nodes without the label only satisfy `!=` constraints.
+    pub fn matches(&self, node: &NodeInfo) -> bool {
+        let label = node.labels.get(&self.key);
+        if self.equal {
+            label == Some(&self.value)
+        } else {
+            label != Some(&self.value)
+        }
+    }
+}
+
+pub fn parse_constraints(constraints: &[String]) -> Result<Vec<PlacementConstraint>> {
+    constraints
+        .iter()
+        .map(|constraint| PlacementConstraint::parse(constraint))
+        .collect()
+}
+
+/// First of the nodes satisfying all the constraints.
+pub fn pick_node<'a>(
+    nodes: &'a [NodeInfo],
+    constraints: &[PlacementConstraint],
+) -> Option<&'a NodeInfo> {
+    nodes.iter().find(|node| {
+        constraints
+            .iter()
+            .all(|constraint| constraint.matches(node))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    fn node(name: &str, labels: &[(&str, &str)]) -> NodeInfo {
+        NodeInfo {
+            name: name.to_string(),
+            labels: labels
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect::<BTreeMap<_, _>>(),
+        }
+    }
+
+    #[test]
+    fn test_parse_constraint() {
+        let constraint = PlacementConstraint::parse("disk = nvme").unwrap();
+        assert_eq!(constraint.key, "disk");
+        assert_eq!(constraint.value, "nvme");
+        assert!(constraint.equal);
+
+        let constraint = PlacementConstraint::parse("zone!=a").unwrap();
+        assert_eq!(constraint.key, "zone");
+        assert!(!constraint.equal);
+
+        assert!(PlacementConstraint::parse("disk").is_err());
+        assert!(PlacementConstraint::parse("=nvme").is_err());
+        assert!(PlacementConstraint::parse("disk=").is_err());
+    }
+
+    #[test]
+    fn test_pick_node() {
+        let nodes = vec![
+            node("a", &[("disk", "ssd"), ("zone", "a")]),
+            node("b", &[("disk", "nvme"), ("zone", "b")]),
+        ];
+
+        let constraints = parse_constraints(&["disk=nvme".to_string()]).unwrap();
+        assert_eq!(pick_node(&nodes, &constraints).unwrap().name, "b");
+
+        let constraints = parse_constraints(&["zone!=a".to_string()]).unwrap();
+        assert_eq!(pick_node(&nodes, &constraints).unwrap().name, "b");
+
+        let constraints =
+            parse_constraints(&["disk=nvme".to_string(), "zone=a".to_string()]).unwrap();
+        assert!(pick_node(&nodes, &constraints).is_none());
+
+        assert_eq!(pick_node(&nodes, &[]).unwrap().name, "a");
+
+        // nodes without the label only satisfy !=
+        let constraints = parse_constraints(&["gpu!=true".to_string()]).unwrap();
+        assert_eq!(pick_node(&nodes, &constraints).unwrap().name, "a");
+        let constraints = parse_constraints(&["gpu=true".to_string()]).unwrap();
+        assert!(pick_node(&nodes, &constraints).is_none());
+    }
+}
@@ -0,0 +1,170 @@
+use std::fmt;
+
+use anyhow::Result;
+
+use crate::{
+    repository::Repository,
+    resources::{
+        machine::MachineVolumeBinding,
+        metadata::{Metadata, Namespace},
+        service::ServiceTarget,
+    },
+};
+
+/// A resource named by another one, eg. a volume mounted by a machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourceReference {
+    pub kind: &'static str,
+    pub name: String,
+    pub namespace: Namespace,
+    /// Field of the referencing resource naming it, eg. `volumes[0]`.
+    pub field: String,
+}
+
+/// Admission error for a resource referencing resources that don't exist.
+#[derive(Debug, Clone)]
+pub struct MissingReferences {
+    pub kind: &'static str,
+    pub metadata: Metadata,
+    pub missing: Vec<ResourceReference>,
+}
+
+impl MissingReferences {
+    pub fn check(
+        kind: &'static str,
+        metadata: Metadata,
+        missing: Vec<ResourceReference>,
+    ) -> Result<()> {
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        Err(Self {
+            kind,
+            metadata,
+            missing,
+        }
+        .into())
+    }
+}
+
+impl fmt::Display for MissingReferences {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} references resources that don't exist:",
+            self.kind,
+            self.metadata.to_string()
+        )?;
+
+        for reference in self.missing.iter() {
+            write!(
+                f,
+                "\n  {}: {} {}",
+                reference.field,
+                reference.kind,
+                Metadata::new(&reference.name, reference.namespace.clone()).to_string()
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for MissingReferences {}
+
+/// Volumes among `volumes` that don't exist, namespaces default to the one of the machine.
+pub fn missing_volumes(
+    repo: &Repository,
+    tenant: &str,
+    namespace: &Option<String>,
+    volumes: &[MachineVolumeBinding],
+) -> Result<Vec<ResourceReference>> {
+    let mut missing = vec![];
+    for (i, volume) in volumes.iter().enumerate() {
+        let volume_namespace =
+            Namespace::from_value_or_default(volume.namespace.clone().or(namespace.clone()));
+
+        if repo
+            .volume(tenant)
+            .get(volume_namespace.clone(), volume.name.clone())?
+            .is_none()
+        {
+            missing.push(ResourceReference {
+                kind: "volume",
+                name: volume.name.clone(),
+                namespace: volume_namespace,
+                field: format!("volumes[{}]", i),
+            });
+        }
+    }
+
+    Ok(missing)
+}
+
+/// Machines targeted by a service that don't exist. A machine of an app counts as existing as
+/// soon as the app does, the app controller creates it later. Targeting a machine group reaches
+/// all of its replicas.
+pub fn missing_target_machines(
+    repo: &Repository,
+    tenant: &str,
+    namespace: &Option<String>,
+    target: &ServiceTarget,
+) -> Result<Vec<ResourceReference>> {
+    let mut targets = vec![(
+        "target".to_string(),
+        target.name.clone(),
+        target.namespace.clone(),
+    )];
+    for (i, backend) in target.backends.iter().flatten().enumerate() {
+        targets.push((
+            format!("target.backends[{}]", i),
+            backend.name.clone(),
+            backend.namespace.clone(),
+        ));
+    }
+    if let Some(canary) = &target.canary {
+        targets.push((
+            "target.canary".to_string(),
+            canary.name.clone(),
+            canary.namespace.clone(),
+        ));
+    }
+    if let Some(mirror) = &target.mirror {
+        targets.push((
+            "target.mirror".to_string(),
+            mirror.name.clone(),
+            mirror.namespace.clone(),
+        ));
+    }
+
+    let mut missing = vec![];
+    for (field, name, machine_namespace) in targets {
+        let machine_namespace =
+            Namespace::from_value_or_default(machine_namespace.or(namespace.clone()));
+
+        let exists = repo
+            .machine(tenant)
+            .get(machine_namespace.clone(), name.clone())?
+            .is_some()
+            || repo
+                .app(tenant)
+                .get(machine_namespace.clone(), name.clone())?
+                .is_some()
+            || repo
+                .machine_group(tenant)
+                .get(machine_namespace.clone(), name.clone())?
+                .is_some();
+
+        if !exists {
+            missing.push(ResourceReference {
+                kind: "machine",
+                name,
+                namespace: machine_namespace,
+                field,
+            });
+        }
+    }
+
+    Ok(missing)
+}
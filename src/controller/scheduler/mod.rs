@@ -1,6 +1,9 @@
 pub mod queue;
 
-use std::sync::Arc;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
 
 use anyhow::Result;
 use async_channel::Receiver;
@@ -13,12 +16,19 @@ use crate::{
         context::{ControllerContext, ControllerEvent, ControllerKey},
         scheduler::queue::WorkQueue,
     },
-    machinery::store::Store,
+    machinery::store::{Store, now_millis},
     repository::Repository,
     resource_index::ResourceKind,
-    resources::{ProvideMetadata, metadata::Namespace},
+    resources::{
+        ProvideMetadata,
+        core::{SchedulerEvent, SchedulerStats},
+        metadata::Namespace,
+    },
 };
 
+/// Reconcile failures kept around for support bundles.
+const MAX_RECENT_EVENTS: usize = 100;
+
 pub struct SchedulerConfig {
     pub worker_count: usize,
 }
@@ -31,6 +41,7 @@ pub struct Scheduler {
     queue: WorkQueue,
     rx: Receiver<ControllerKey>,
     ctrl: Arc<Vec<Box<dyn Controller>>>,
+    recent_events: Arc<Mutex<VecDeque<SchedulerEvent>>>,
 }
 
 impl Scheduler {
@@ -51,9 +62,27 @@ impl Scheduler {
             queue,
             rx,
             ctrl: Arc::new(ctrls),
+            recent_events: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    pub fn stats(&self) -> SchedulerStats {
+        SchedulerStats {
+            worker_count: self.config.worker_count,
+            queued_keys: self.queue.queued_keys(),
         }
     }
 
+    /// The latest reconcile failures, oldest first.
+    pub fn recent_events(&self) -> Vec<SchedulerEvent> {
+        self.recent_events
+            .lock()
+            .expect("recent events poisoned")
+            .iter()
+            .cloned()
+            .collect()
+    }
+
     pub fn start_workers(&self) {
         info!("starting {} workers", self.config.worker_count);
         for _ in 0..self.config.worker_count {
@@ -63,6 +92,7 @@ impl Scheduler {
             let agent = self.agent.clone();
             let ctrl = self.ctrl.clone();
             let rx = self.rx.clone();
+            let recent_events = self.recent_events.clone();
 
             tokio::spawn(async move {
                 while let Ok(key) = rx.recv().await {
@@ -82,7 +112,10 @@ impl Scheduler {
 
                         let next = match reconcile {
                             Ok(next) => next,
-                            Err(e) => ctrl.handle_error(ctx, key.clone(), e).await,
+                            Err(e) => {
+                                record_event(&recent_events, &key, e.to_string());
+                                ctrl.handle_error(ctx, key.clone(), e).await
+                            }
                         };
 
                         match next {
@@ -197,8 +230,90 @@ impl Scheduler {
                 )
                 .await?;
             }
+
+            let dns_records = self
+                .repository
+                .dns_record(tenant.clone())
+                .list(Namespace::Unspecified)?;
+            for dns_record in dns_records {
+                let metadata = dns_record.metadata();
+
+                let key = ControllerKey::new(
+                    tenant.clone(),
+                    ResourceKind::DnsRecord,
+                    metadata.namespace.clone(),
+                    metadata.name.clone(),
+                );
+
+                info!("scheduled bringup for resource {}", key.to_string());
+
+                self.push(
+                    tenant.clone(),
+                    ControllerEvent::BringUp(ResourceKind::DnsRecord, metadata),
+                )
+                .await?;
+            }
+
+            let machine_groups = self
+                .repository
+                .machine_group(tenant.clone())
+                .list(Namespace::Unspecified)?;
+            for machine_group in machine_groups {
+                let metadata = machine_group.metadata();
+
+                let key = ControllerKey::new(
+                    tenant.clone(),
+                    ResourceKind::MachineGroup,
+                    metadata.namespace.clone(),
+                    metadata.name.clone(),
+                );
+
+                info!("scheduled bringup for resource {}", key.to_string());
+
+                self.push(
+                    tenant.clone(),
+                    ControllerEvent::BringUp(ResourceKind::MachineGroup, metadata),
+                )
+                .await?;
+            }
+
+            let crons = self
+                .repository
+                .cron(tenant.clone())
+                .list(Namespace::Unspecified)?;
+            for cron in crons {
+                let metadata = cron.metadata();
+
+                let key = ControllerKey::new(
+                    tenant.clone(),
+                    ResourceKind::Cron,
+                    metadata.namespace.clone(),
+                    metadata.name.clone(),
+                );
+
+                info!("scheduled bringup for resource {}", key.to_string());
+
+                self.push(
+                    tenant.clone(),
+                    ControllerEvent::BringUp(ResourceKind::Cron, metadata),
+                )
+                .await?;
+            }
         }
 
         Ok(())
     }
 }
+
+fn record_event(events: &Mutex<VecDeque<SchedulerEvent>>, key: &ControllerKey, message: String) {
+    let mut events = events.lock().expect("recent events poisoned");
+    if events.len() >= MAX_RECENT_EVENTS {
+        events.pop_front();
+    }
+
+    events.push_back(SchedulerEvent {
+        at: now_millis(),
+        key: key.to_string(),
+        message,
+    });
+}
@@ -31,6 +31,11 @@ impl WorkQueue {
         )
     }
 
+    /// Keys waiting for or being reconciled.
+    pub fn queued_keys(&self) -> usize {
+        self.keys.len()
+    }
+
     pub async fn push(&self, key: &ControllerKey) {
         let keys = self.keys.pin_owned();
 
@@ -1,17 +1,34 @@
 use std::{sync::Arc, time::Duration};
 
-use anyhow::{Result, bail};
+use anyhow::{Result, anyhow, bail};
 use async_trait::async_trait;
+use bytes::Bytes;
 use tokio::{runtime, task::spawn_blocking};
 use tracing::{error, info};
+use url::Url;
 
 use crate::{
     agent::{
         Agent,
         net::IpReservationKind,
+        port_allocator::{PortConflict, PortConflictReason},
         proxy::{
-            BindingMode, ExternalBindingRouting, ExternnalBindingRoutingTlsNestedProtocol,
-            ProxyBinding,
+            BindingMode, CanaryTarget, ExternalBindingRouting,
+            ExternnalBindingRoutingTlsNestedProtocol, ProxyBalancing, ProxyBinding,
+            ProxyBindingService, ProxyTransport, UpstreamHostHeader, UpstreamProtocol,
+            WeightedTarget,
+            auth::{BasicAuth, ForwardAuth, ProxyAuth},
+            circuit_breaker::{CircuitBreaker, CircuitBreakerConfig},
+            compression::ResponseCompression,
+            error_pages::ErrorPages,
+            firewall::{IpFirewall, IpNetwork},
+            headers::{HeaderRewrite, HeaderRules},
+            mirror::MirrorTarget,
+            rate_limit::{RateLimitConfig, RateLimiter},
+            retry::{RetryConfig, RetryPolicy},
+            sticky::StickySessions,
+            timeouts::ProxyTimeouts,
+            upstream_tls::{UpstreamTls, UpstreamTlsConfig},
         },
         tracker::{TrackedResourceKind, TrackedResourceOwner},
     },
@@ -20,6 +37,7 @@ use crate::{
         AdmissionCheckBeforeDelete, AdmissionCheckBeforeSet, Controller, ReconcileNext,
         context::{ControllerContext, ControllerEvent, ControllerKey},
         machine::machine_name_from_key,
+        references::{MissingReferences, missing_target_machines},
     },
     repository::Repository,
     resource_index::ResourceKind,
@@ -27,8 +45,9 @@ use crate::{
         Convert,
         metadata::{Metadata, Namespace},
         service::{
-            Service, ServiceBind, ServiceBindExternalProtocol, ServiceTargetConnectionTracking,
-            ServiceTargetProtocol,
+            Service, ServiceAuth, ServiceBind, ServiceBindExternalProtocol, ServiceFirewall,
+            ServiceHeaderRules, ServiceHeaders, ServiceTarget, ServiceTargetConnectionTracking,
+            ServiceTargetHostHeader, ServiceTargetProtocol,
         },
     },
 };
@@ -151,20 +170,46 @@ impl Controller for ServiceController {
                 .clone()
         };
 
-        let target_namespace = service
-            .target
-            .namespace
-            .clone()
-            .or(service.namespace.clone());
-        let target_namespace = Namespace::from_value_or_default(target_namespace);
-
-        let target_machine_key = ControllerKey::new(
-            key.tenant.clone(),
-            ResourceKind::Machine,
-            target_namespace.as_value(),
-            service.target.name.clone(),
-        );
-        let target_network_tag = machine_name_from_key(&target_machine_key);
+        let network_tag_for = |name: &str, namespace: Option<String>| {
+            let namespace = namespace.or(service.namespace.clone());
+            let machine_key = ControllerKey::new(
+                key.tenant.clone(),
+                ResourceKind::Machine,
+                Namespace::from_value_or_default(namespace).as_value(),
+                name.to_string(),
+            );
+            machine_name_from_key(&machine_key)
+        };
+
+        let target_network_tag =
+            network_tag_for(&service.target.name, service.target.namespace.clone());
+
+        let backends = service.target.backends.clone().unwrap_or_default();
+        let weighted_targets = if backends.is_empty() {
+            vec![]
+        } else {
+            let mut weighted_targets = vec![WeightedTarget {
+                network_tag: target_network_tag.clone(),
+                weight: service.target.weight.unwrap_or(1),
+            }];
+            for backend in backends.iter() {
+                weighted_targets.push(WeightedTarget {
+                    network_tag: network_tag_for(&backend.name, backend.namespace.clone()),
+                    weight: backend.weight.unwrap_or(1),
+                });
+            }
+            weighted_targets
+        };
+
+        let canary = service.target.canary.as_ref().map(|canary| CanaryTarget {
+            network_tag: network_tag_for(&canary.name, canary.namespace.clone()),
+            percentage: canary.percentage,
+        });
+
+        let mirror = service.target.mirror.as_ref().map(|mirror| MirrorTarget {
+            network_tag: network_tag_for(&mirror.name, mirror.namespace.clone()),
+            percentage: mirror.percentage,
+        });
 
         let internal_dns_hostname = match &service.bind {
             ServiceBind::Internal { .. } => {
@@ -188,26 +233,57 @@ impl Controller for ServiceController {
                 host,
                 port,
                 protocol,
+                path_prefix,
             } => {
                 let port = port.unwrap_or(protocol.default_port(&service.target));
 
-                let routing = match (protocol, service.target.protocol) {
-                    (ServiceBindExternalProtocol::Http, ServiceTargetProtocol::Http) => {
-                        ExternalBindingRouting::HttpHostHeader { host: host.clone() }
-                    }
-                    (ServiceBindExternalProtocol::Https, ServiceTargetProtocol::Http) => {
-                        ExternalBindingRouting::TlsSni {
-                            host: host.clone(),
-                            nested_protocol: ExternnalBindingRoutingTlsNestedProtocol::Http,
-                        }
+                let routing = match (protocol, &service.target.protocol, path_prefix) {
+                    (ServiceBindExternalProtocol::Udp, _, _) => {
+                        ExternalBindingRouting::UdpDirect { port }
                     }
-                    (ServiceBindExternalProtocol::Tls, ServiceTargetProtocol::Http) => {
-                        ExternalBindingRouting::TlsSni {
-                            host: host.clone(),
-                            nested_protocol: ExternnalBindingRoutingTlsNestedProtocol::Http,
-                        }
+                    (ServiceBindExternalProtocol::TlsPassthrough, _, _) => {
+                        ExternalBindingRouting::TlsPassthrough { host: host.clone() }
                     }
-                    (_, _) => ExternalBindingRouting::TlsSni {
+                    (
+                        ServiceBindExternalProtocol::Http,
+                        ServiceTargetProtocol::Http | ServiceTargetProtocol::Http2,
+                        Some(path_prefix),
+                    ) => ExternalBindingRouting::HttpHostPath {
+                        host: host.clone(),
+                        path_prefix,
+                        tls: false,
+                    },
+                    (
+                        ServiceBindExternalProtocol::Https | ServiceBindExternalProtocol::Tls,
+                        ServiceTargetProtocol::Http | ServiceTargetProtocol::Http2,
+                        Some(path_prefix),
+                    ) => ExternalBindingRouting::HttpHostPath {
+                        host: host.clone(),
+                        path_prefix,
+                        tls: true,
+                    },
+                    (
+                        ServiceBindExternalProtocol::Http,
+                        ServiceTargetProtocol::Http | ServiceTargetProtocol::Http2,
+                        _,
+                    ) => ExternalBindingRouting::HttpHostHeader { host: host.clone() },
+                    (
+                        ServiceBindExternalProtocol::Https,
+                        ServiceTargetProtocol::Http | ServiceTargetProtocol::Http2,
+                        _,
+                    ) => ExternalBindingRouting::TlsSni {
+                        host: host.clone(),
+                        nested_protocol: ExternnalBindingRoutingTlsNestedProtocol::Http,
+                    },
+                    (
+                        ServiceBindExternalProtocol::Tls,
+                        ServiceTargetProtocol::Http | ServiceTargetProtocol::Http2,
+                        _,
+                    ) => ExternalBindingRouting::TlsSni {
+                        host: host.clone(),
+                        nested_protocol: ExternnalBindingRoutingTlsNestedProtocol::Http,
+                    },
+                    (_, _, _) => ExternalBindingRouting::TlsSni {
                         host: host.clone(),
                         nested_protocol: ExternnalBindingRoutingTlsNestedProtocol::Unknown,
                     },
@@ -264,6 +340,47 @@ impl Controller for ServiceController {
             _ => None,
         };
 
+        let circuit_breaker = service.circuit_breaker.as_ref().map(|circuit_breaker| {
+            Arc::new(CircuitBreaker::new(CircuitBreakerConfig {
+                consecutive_failures: circuit_breaker.consecutive_failures,
+                ejection_time: circuit_breaker.ejection_time.map(Duration::from_secs),
+            }))
+        });
+
+        let firewall = match service.firewall.as_ref() {
+            Some(firewall) => Some(Arc::new(ip_firewall(firewall)?)),
+            None => None,
+        };
+
+        let auth = match service.auth.as_ref() {
+            Some(auth) => Some(Arc::new(proxy_auth(auth)?)),
+            None => None,
+        };
+
+        let headers = match service.headers.as_ref() {
+            Some(headers) => Some(Arc::new(header_rewrite(headers)?)),
+            None => None,
+        };
+
+        let retry_policy = service.retry.as_ref().map(|retry| {
+            Arc::new(RetryPolicy::new(RetryConfig {
+                attempts: retry.attempts,
+                per_try_timeout: retry.per_try_timeout.map(Duration::from_secs),
+                budget_percent: retry.budget_percent,
+            }))
+        });
+
+        let timeouts = service
+            .timeouts
+            .as_ref()
+            .map(|timeouts| ProxyTimeouts {
+                connect: timeouts.connect.map(Duration::from_secs),
+                request: timeouts.request.map(Duration::from_secs),
+                idle: timeouts.idle.map(Duration::from_secs),
+                upstream_response: timeouts.upstream_response.map(Duration::from_secs),
+            })
+            .unwrap_or_default();
+
         // Store allocated TCP port in status for tracking
         let allocated_tcp_port = match &binding_mode {
             BindingMode::External { port, routing } => match routing {
@@ -273,12 +390,75 @@ impl Controller for ServiceController {
             _ => None,
         };
 
+        let upstream_protocol = match service.target.protocol {
+            ServiceTargetProtocol::Http2 => UpstreamProtocol::H2c,
+            _ => UpstreamProtocol::Http1,
+        };
+
+        let transport = match service.target.protocol {
+            ServiceTargetProtocol::Udp => ProxyTransport::Udp,
+            _ => ProxyTransport::Tcp,
+        };
+
+        let rate_limiter = service.rate_limit.as_ref().map(|rate_limit| {
+            Arc::new(RateLimiter::new(RateLimitConfig {
+                requests_per_second: rate_limit.requests_per_second,
+                burst: rate_limit.burst,
+                max_connections: rate_limit.max_connections,
+            }))
+        });
+
+        let upstream_tls = upstream_tls_config(&service.target)
+            .map(|config| UpstreamTls::new(config, &upstream_protocol).map(Arc::new))
+            .transpose()?;
+
         let binding_name = service_name_from_key(&key);
         let proxy_binding = ProxyBinding {
             target_network_tag,
             target_port: service.target.port,
+            transport,
             mode: binding_mode,
             inactivity_timeout,
+            timeouts,
+            upstream_protocol,
+            balancing: ProxyBalancing::new(weighted_targets, canary),
+            rate_limiter,
+            circuit_breaker,
+            firewall,
+            auth,
+            compression: service.compression.as_ref().map(|compression| {
+                ResponseCompression::new(compression.min_size, compression.content_types.clone())
+            }),
+            sticky_sessions: service.sticky_sessions.as_ref().map(|sticky| {
+                StickySessions::new(
+                    sticky.cookie.clone(),
+                    sticky.max_age.map(Duration::from_secs),
+                )
+            }),
+            headers,
+            maintenance: service.maintenance.unwrap_or(false),
+            error_pages: service.error_pages.as_ref().map(|error_pages| {
+                let page = |page: &Option<String>| page.clone().map(Bytes::from);
+                Arc::new(ErrorPages {
+                    bad_gateway: page(&error_pages.bad_gateway),
+                    service_unavailable: page(&error_pages.service_unavailable),
+                    maintenance: page(&error_pages.maintenance),
+                })
+            }),
+            retry_policy,
+            mirror,
+            proxy_protocol: service.target.proxy_protocol.is_some(),
+            upstream_tls,
+            host_header: upstream_host_header(&service.target),
+            service: Some(ProxyBindingService {
+                tenant: key.tenant.clone(),
+                namespace: service
+                    .namespace
+                    .clone()
+                    .unwrap_or(DEFAULT_NAMESPACE.to_string()),
+                name: service.name.clone(),
+            }),
+            metrics: None,
         };
 
         let proxy_agent = ctx.agent.proxy();
@@ -321,18 +501,276 @@ impl Controller for ServiceController {
     }
 }
 
+async fn port_conflict(
+    agent: &Agent,
+    tenant: &str,
+    host: &str,
+    port: u16,
+    reason: PortConflictReason,
+) -> anyhow::Error {
+    let mut suggested_ports = vec![];
+
+    for candidate in agent.proxy().external_ports() {
+        if candidate == port
+            || agent.port_allocator().is_tcp_port_in_range(candidate)
+            || agent.proxy().check_external_port(candidate).await.is_some()
+        {
+            continue;
+        }
+
+        let kind = TrackedResourceKind::ServiceDomain(format!("{}:{}", host, candidate));
+        if let Ok(None) = agent.tracker().get_tracked_resource_owner(kind).await {
+            suggested_ports.push(candidate);
+        }
+    }
+    suggested_ports.sort();
+
+    let suggested_tcp_ports = agent
+        .port_allocator()
+        .find_free_tcp_ports(3)
+        .unwrap_or_default();
+
+    info!(
+        "Rejecting external binding {}:{} for tenant {}: {:?}",
+        host, port, tenant, reason
+    );
+
+    PortConflict {
+        host: host.to_string(),
+        port,
+        reason,
+        suggested_ports,
+        suggested_tcp_ports,
+    }
+    .into()
+}
+
 #[async_trait]
 impl AdmissionCheckBeforeSet for Service {
     async fn before_set(
         &self,
         before: Option<&Self>,
         tenant: String,
-        _repo: Arc<Repository>,
+        repo: Arc<Repository>,
         agent: Arc<Agent>,
-        _metadata: Metadata,
+        metadata: Metadata,
     ) -> Result<()> {
         let resource = self.latest();
 
+        MissingReferences::check(
+            "service",
+            metadata,
+            missing_target_machines(&repo, &tenant, &resource.namespace, &resource.target)?,
+        )?;
+
+        self.validate(before, tenant, agent).await
+    }
+}
+
+impl Service {
+    /// The admission checks not involving other resources, run on their own for the services
+    /// of an app, whose machine doesn't exist yet.
+    pub async fn validate(
+        &self,
+        before: Option<&Self>,
+        tenant: String,
+        agent: Arc<Agent>,
+    ) -> Result<()> {
+        let resource = self.latest();
+
+        if let Some(rate_limit) = &resource.rate_limit {
+            if rate_limit.requests_per_second == Some(0) || rate_limit.max_connections == Some(0) {
+                bail!("Rate limits must be greater than 0, omit them to disable limiting");
+            }
+        }
+
+        if let Some(circuit_breaker) = &resource.circuit_breaker {
+            if circuit_breaker.consecutive_failures == Some(0)
+                || circuit_breaker.ejection_time == Some(0)
+            {
+                bail!("Circuit breaker settings must be greater than 0");
+            }
+        }
+
+        if let Some(firewall) = &resource.firewall {
+            ip_firewall(firewall)?;
+        }
+
+        if let Some(auth) = &resource.auth {
+            let is_http_bind = matches!(
+                resource.bind,
+                ServiceBind::External {
+                    protocol: ServiceBindExternalProtocol::Http
+                        | ServiceBindExternalProtocol::Https
+                        | ServiceBindExternalProtocol::Tls,
+                    ..
+                }
+            );
+            if !is_http_bind
+                || !matches!(
+                    resource.target.protocol,
+                    ServiceTargetProtocol::Http | ServiceTargetProtocol::Http2
+                )
+            {
+                bail!("Authentication is only supported for http targets on external binds");
+            }
+
+            proxy_auth(auth)?;
+        }
+
+        if let Some(compression) = &resource.compression {
+            if !matches!(
+                resource.target.protocol,
+                ServiceTargetProtocol::Http | ServiceTargetProtocol::Http2
+            ) {
+                bail!("Compression is only supported for http targets");
+            }
+
+            for content_type in compression.content_types.iter().flatten() {
+                if !content_type.contains('/') {
+                    bail!(
+                        "Invalid compression content type {:?}, expected eg. text/html or text/*",
+                        content_type
+                    );
+                }
+            }
+        }
+
+        if let Some(sticky) = &resource.sticky_sessions {
+            if !matches!(
+                resource.target.protocol,
+                ServiceTargetProtocol::Http | ServiceTargetProtocol::Http2
+            ) {
+                bail!("Sticky sessions are only supported for http targets");
+            }
+
+            let valid_cookie = sticky.cookie.as_ref().is_none_or(|cookie| {
+                !cookie.is_empty()
+                    && cookie
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            });
+            if !valid_cookie {
+                bail!("Invalid sticky sessions cookie name, use letters, digits, '-' and '_' only");
+            }
+        }
+
+        if let Some(headers) = &resource.headers {
+            if !matches!(
+                resource.target.protocol,
+                ServiceTargetProtocol::Http | ServiceTargetProtocol::Http2
+            ) {
+                bail!("Header rules are only supported for http targets");
+            }
+
+            header_rewrite(headers)?;
+        }
+
+        if resource.maintenance.is_some() || resource.error_pages.is_some() {
+            if !matches!(
+                resource.target.protocol,
+                ServiceTargetProtocol::Http | ServiceTargetProtocol::Http2
+            ) {
+                bail!("Maintenance mode and error pages are only supported for http targets");
+            }
+        }
+
+        if let Some(retry) = &resource.retry {
+            if retry.attempts == Some(0) || retry.per_try_timeout == Some(0) {
+                bail!("Retry attempts and per-try timeout must be greater than 0");
+            }
+
+            if retry.budget_percent.is_some_and(|percent| percent > 100) {
+                bail!("Retry budget must be between 0 and 100 percent");
+            }
+        }
+
+        if let Some(timeouts) = &resource.timeouts {
+            let values = [
+                timeouts.connect,
+                timeouts.request,
+                timeouts.idle,
+                timeouts.upstream_response,
+            ];
+            if values.contains(&Some(0)) {
+                bail!("Timeouts must be greater than 0, omit them to keep the defaults");
+            }
+        }
+
+        if let Some(canary) = &resource.target.canary {
+            if canary.percentage > 100 {
+                bail!(
+                    "Canary percentage must be between 0 and 100, got {}",
+                    canary.percentage
+                );
+            }
+        }
+
+        if let Some(mirror) = &resource.target.mirror {
+            if mirror.percentage > 100 {
+                bail!(
+                    "Mirror percentage must be between 0 and 100, got {}",
+                    mirror.percentage
+                );
+            }
+
+            if !matches!(
+                resource.target.protocol,
+                ServiceTargetProtocol::Http | ServiceTargetProtocol::Http2
+            ) {
+                bail!("Request mirroring is only supported for http targets");
+            }
+        }
+
+        if let Some(config) = upstream_tls_config(&resource.target) {
+            if !matches!(
+                resource.target.protocol,
+                ServiceTargetProtocol::Http | ServiceTargetProtocol::Http2
+            ) {
+                bail!("Target TLS is only supported for http targets");
+            }
+
+            let upstream_protocol = match resource.target.protocol {
+                ServiceTargetProtocol::Http2 => UpstreamProtocol::H2c,
+                _ => UpstreamProtocol::Http1,
+            };
+            if let Err(e) = UpstreamTls::new(config, &upstream_protocol) {
+                bail!("Invalid target TLS configuration: {}", e);
+            }
+        }
+
+        if let Some(ServiceTargetHostHeader::Custom { host }) = &resource.target.host_header {
+            if host.trim().is_empty() || host.parse::<axum::http::HeaderValue>().is_err() {
+                bail!("Invalid custom host header: {:?}", host);
+            }
+        }
+
+        if let Some(backends) = &resource.target.backends {
+            let total_weight = backends
+                .iter()
+                .map(|backend| backend.weight.unwrap_or(1) as u64)
+                .sum::<u64>()
+                + resource.target.weight.unwrap_or(1) as u64;
+
+            if total_weight == 0 {
+                bail!(
+                    "At least one of the service target or its backends must have a non-zero weight"
+                );
+            }
+        }
+
+        let is_udp_target = matches!(resource.target.protocol, ServiceTargetProtocol::Udp);
+        let is_udp_bind = matches!(
+            resource.bind,
+            ServiceBind::External {
+                protocol: ServiceBindExternalProtocol::Udp,
+                ..
+            }
+        );
+        if is_udp_target != is_udp_bind && !matches!(resource.bind, ServiceBind::Internal { .. }) {
+            bail!("UDP targets can only be bound internally or with the external udp protocol");
+        }
+
         match &resource.bind {
             ServiceBind::Tcp => {
                 // TCP services don't need additional validation - they use dynamic allocation
@@ -342,22 +780,64 @@ impl AdmissionCheckBeforeSet for Service {
                 host,
                 port,
                 protocol,
+                path_prefix,
             } => {
-                // For external protocols, validate port range restrictions
-                let port_allocator = agent.port_allocator();
-                let actual_port = port.unwrap_or(protocol.default_port(&resource.target));
+                let dns = agent.dns();
+                if dns.is_region_domain(host) && !dns.is_tenant_owned_region_domain(&tenant, host) {
+                    bail!("Your tenant does not own the domain: {}", host);
+                }
 
-                if port_allocator.is_tcp_port_in_range(actual_port) {
-                    bail!(
-                        "Port {} is in the reserved TCP port range and cannot be used for {} services",
-                        actual_port,
-                        protocol.to_string()
+                if host.contains('*') {
+                    let suffix = host.strip_prefix("*.").unwrap_or_default();
+                    if suffix.is_empty() || suffix.contains('*') || !suffix.contains('.') {
+                        bail!(
+                            "Wildcards are only allowed as the first label of a host: {}",
+                            host
+                        );
+                    }
+
+                    if matches!(
+                        protocol,
+                        ServiceBindExternalProtocol::Tcp | ServiceBindExternalProtocol::Udp
+                    ) {
+                        bail!("Wildcard hosts are only supported for http and tls bindings");
+                    }
+                }
+
+                if let Some(path_prefix) = path_prefix {
+                    if !path_prefix.starts_with('/') {
+                        bail!("Path prefix must start with '/': {}", path_prefix);
+                    }
+
+                    let is_http_target = matches!(
+                        resource.target.protocol,
+                        ServiceTargetProtocol::Http | ServiceTargetProtocol::Http2
                     );
+                    if !is_http_target
+                        || matches!(
+                            protocol,
+                            ServiceBindExternalProtocol::Tcp
+                                | ServiceBindExternalProtocol::TlsPassthrough
+                                | ServiceBindExternalProtocol::Udp
+                        )
+                    {
+                        bail!("Path prefix routing is only supported for http targets");
+                    }
                 }
 
-                let dns = agent.dns();
-                if dns.is_region_domain(host) && !dns.is_tenant_owned_region_domain(&tenant, host) {
-                    bail!("Your tenant does not own the domain: {}", host);
+                // For external protocols, validate port range restrictions
+                let actual_port = port.unwrap_or(protocol.default_port(&resource.target));
+
+                let reason = if matches!(protocol, ServiceBindExternalProtocol::Udp) {
+                    agent.proxy().check_external_udp_port(actual_port).await
+                } else if agent.port_allocator().is_tcp_port_in_range(actual_port) {
+                    Some(PortConflictReason::ReservedTcpRange)
+                } else {
+                    agent.proxy().check_external_port(actual_port).await
+                };
+
+                if let Some(reason) = reason {
+                    return Err(port_conflict(&agent, &tenant, host, actual_port, reason).await);
                 }
             }
             ServiceBind::Internal { .. } => {
@@ -370,6 +850,7 @@ impl AdmissionCheckBeforeSet for Service {
             host,
             port,
             protocol,
+            path_prefix,
         } = &resource.bind
         {
             if let Some(before) = before {
@@ -378,27 +859,47 @@ impl AdmissionCheckBeforeSet for Service {
                     host: before_host,
                     port: before_port,
                     protocol: before_protocol,
+                    path_prefix: before_path_prefix,
                 } = &before.bind
                 {
-                    if before_host != host || before_port != port {
+                    if before_host != host
+                        || before_port != port
+                        || before_protocol != protocol
+                        || before_path_prefix != path_prefix
+                    {
                         let before_port =
                             before_port.unwrap_or(before_protocol.default_port(&before.target));
 
-                        let before_kind = TrackedResourceKind::ServiceDomain(format!(
-                            "{}:{}",
-                            before_host, before_port
-                        ));
+                        let before_kind = service_domain_kind(
+                            before_host,
+                            before_port,
+                            before_protocol,
+                            before_path_prefix.as_deref(),
+                        );
                         agent.tracker().untrack_resource_owner(before_kind).await?;
                     }
                 }
             }
 
             let port = port.unwrap_or(protocol.default_port(&resource.target));
-            let kind = TrackedResourceKind::ServiceDomain(format!("{}:{}", host, port));
+
+            // the routes of a host, with or without a prefix, can only be shared within a tenant
+            if *protocol != ServiceBindExternalProtocol::Udp {
+                let owners = agent.tracker().list_tracked_resource_owners().await?;
+                if owners
+                    .iter()
+                    .any(|owner| owner.tenant != tenant && is_route_of(&owner.kind, host, port))
+                {
+                    let reason = PortConflictReason::BoundByResource { owner: None };
+                    return Err(port_conflict(&agent, &tenant, host, port, reason).await);
+                }
+            }
+
+            let kind = service_domain_kind(host, port, protocol, path_prefix.as_deref());
 
             let resource_owner = TrackedResourceOwner {
                 kind: kind.clone(),
-                tenant,
+                tenant: tenant.clone(),
                 resource_name: resource.name,
                 resource_namespace: resource.namespace.unwrap_or(DEFAULT_NAMESPACE.to_string()),
             };
@@ -409,7 +910,12 @@ impl AdmissionCheckBeforeSet for Service {
                 .await?
             {
                 if owner != resource_owner {
-                    bail!("Service domain and port is already bound to another resource")
+                    // only reveal the owner to the tenant that owns it
+                    let owner = (owner.tenant == tenant)
+                        .then(|| format!("{}/{}", owner.resource_namespace, owner.resource_name));
+
+                    let reason = PortConflictReason::BoundByResource { owner };
+                    return Err(port_conflict(&agent, &tenant, host, port, reason).await);
                 }
             };
 
@@ -436,9 +942,10 @@ impl AdmissionCheckBeforeDelete for Service {
                 host,
                 port,
                 protocol,
+                path_prefix,
             } => {
                 let port = port.unwrap_or(protocol.default_port(&resource.target));
-                let kind = TrackedResourceKind::ServiceDomain(format!("{}:{}", host, port));
+                let kind = service_domain_kind(host, port, protocol, path_prefix.as_deref());
                 agent.tracker().untrack_resource_owner(kind).await?;
             }
             ServiceBind::Tcp => {
@@ -453,3 +960,124 @@ impl AdmissionCheckBeforeDelete for Service {
         Ok(())
     }
 }
+
+fn upstream_tls_config(target: &ServiceTarget) -> Option<UpstreamTlsConfig> {
+    let tls = target.tls.as_ref()?;
+
+    Some(UpstreamTlsConfig {
+        server_name: tls.server_name.clone(),
+        ca_cert: tls.ca_cert.clone(),
+        skip_verify: tls.skip_verify.unwrap_or(false),
+        client_cert: tls.client_cert.clone(),
+        client_key: tls.client_key.clone(),
+    })
+}
+
+fn upstream_host_header(target: &ServiceTarget) -> Option<UpstreamHostHeader> {
+    let host_header = match target.host_header.as_ref()? {
+        ServiceTargetHostHeader::Preserve => UpstreamHostHeader::Preserve,
+        ServiceTargetHostHeader::Bind => UpstreamHostHeader::Binding,
+        ServiceTargetHostHeader::Custom { host } => UpstreamHostHeader::Custom(host.clone()),
+    };
+
+    Some(host_header)
+}
+
+fn proxy_auth(auth: &ServiceAuth) -> Result<ProxyAuth> {
+    match (&auth.basic, &auth.forward) {
+        (Some(basic), None) => {
+            if basic.users.is_empty() {
+                bail!("Basic auth needs at least one user");
+            }
+
+            let mut credentials = vec![];
+            for user in &basic.users {
+                if user.username.is_empty() || user.username.contains(':') {
+                    bail!("Invalid basic auth username: {:?}", user.username);
+                }
+                credentials.push((user.username.clone(), user.password.clone()));
+            }
+
+            Ok(ProxyAuth::Basic(BasicAuth::new(
+                basic.realm.clone(),
+                credentials,
+            )))
+        }
+        (None, Some(forward)) => {
+            let url = Url::parse(&forward.url)
+                .map_err(|e| anyhow!("Invalid forward auth url {:?}: {}", forward.url, e))?;
+            if url.scheme() != "http" && url.scheme() != "https" {
+                bail!("Forward auth url must be http or https: {}", forward.url);
+            }
+
+            Ok(ProxyAuth::Forward(ForwardAuth::new(
+                forward.url.clone(),
+                forward.response_headers.clone().unwrap_or_default(),
+            )?))
+        }
+        _ => bail!("Service auth needs exactly one of basic or forward"),
+    }
+}
+
+fn header_rewrite(headers: &ServiceHeaders) -> Result<HeaderRewrite> {
+    let rules = |rules: &Option<ServiceHeaderRules>| match rules {
+        Some(rules) => HeaderRules::new(
+            rules.remove.as_deref().unwrap_or_default(),
+            &rules.set.clone().unwrap_or_default(),
+            &rules.add.clone().unwrap_or_default(),
+        ),
+        None => Ok(HeaderRules::default()),
+    };
+
+    Ok(HeaderRewrite {
+        request: rules(&headers.request)?,
+        response: rules(&headers.response)?,
+    })
+}
+
+fn ip_firewall(firewall: &ServiceFirewall) -> Result<IpFirewall> {
+    let parse = |cidrs: &Option<Vec<String>>| {
+        cidrs
+            .iter()
+            .flatten()
+            .map(|cidr| IpNetwork::parse(cidr))
+            .collect::<Result<Vec<_>>>()
+    };
+
+    Ok(IpFirewall {
+        allow: parse(&firewall.allow)?,
+        deny: parse(&firewall.deny)?,
+    })
+}
+
+/// Ownership key of an external host, narrowed to the path prefix when routing on one. UDP
+/// ports are tracked apart, as they don't collide with the TCP listeners.
+/// Whether `kind` is a route of `host` and `port`, with or without a path prefix.
+fn is_route_of(kind: &TrackedResourceKind, host: &str, port: u16) -> bool {
+    let TrackedResourceKind::ServiceDomain(domain) = kind else {
+        return false;
+    };
+
+    match domain.strip_prefix(&format!("{}:{}", host, port)) {
+        Some(rest) => rest.is_empty() || (rest.starts_with('/') && rest != "/udp"),
+        None => false,
+    }
+}
+
+fn service_domain_kind(
+    host: &str,
+    port: u16,
+    protocol: &ServiceBindExternalProtocol,
+    path_prefix: Option<&str>,
+) -> TrackedResourceKind {
+    if *protocol == ServiceBindExternalProtocol::Udp {
+        return TrackedResourceKind::ServiceDomain(format!("{}:{}/udp", host, port));
+    }
+
+    match path_prefix.map(|p| p.trim_end_matches('/')) {
+        Some(path_prefix) if !path_prefix.is_empty() => {
+            TrackedResourceKind::ServiceDomain(format!("{}:{}{}", host, port, path_prefix))
+        }
+        _ => TrackedResourceKind::ServiceDomain(format!("{}:{}", host, port)),
+    }
+}
@@ -1,24 +1,44 @@
-use std::sync::Arc;
+use std::{str::FromStr, sync::Arc, time::Duration};
 
-use anyhow::{Result, bail};
+use anyhow::{Result, anyhow, bail};
 use async_trait::async_trait;
-use tracing::{error, info};
+use oci_client::Reference;
+use takeoff_proto::proto::Filesystem;
+use tracing::{error, info, warn};
 
 use crate::{
-    agent::Agent,
+    agent::{Agent, machine::machine::MachineState, volume},
+    constants::DEFAULT_NAMESPACE,
     controller::{
-        AdmissionCheckBeforeDelete, Controller, ReconcileNext,
-        context::{ControllerContext, ControllerEvent, ControllerKey},
+        AdmissionCheckBeforeDelete, AdmissionCheckBeforeSet, Controller, ReconcileNext,
+        context::{AsyncWork, ControllerContext, ControllerEvent, ControllerKey},
+        machine::{pull_image_job_key, start_image_pull},
     },
     repository::Repository,
     resource_index::ResourceKind,
     resources::{
         Convert,
+        core::{VolumeBackup, VolumeResize, VolumeRestore},
         metadata::{Metadata, Namespace},
-        volume::Volume,
+        volume::{
+            Volume, VolumeBackupPolicy, VolumeFilesystem, VolumeLatest, VolumeMode, VolumeSource,
+        },
+    },
+    utils::{
+        cron::CronSchedule,
+        size::{format_human_readable_size, parse_human_readable_size},
+        time::now_millis,
     },
 };
 
+/// mkfs.xfs refuses smaller filesystems.
+const XFS_MIN_SIZE_BYTES: u64 = 300 * 1024 * 1024;
+/// mkfs.btrfs needs a bit more than 109MiB with the default profiles.
+const BTRFS_MIN_SIZE_BYTES: u64 = 128 * 1024 * 1024;
+const SOURCE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// Machines report the usage of their volumes every 30s, no point in looking more often.
+const USAGE_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
 pub struct VolumeController;
 
 impl VolumeController {
@@ -38,7 +58,7 @@ impl Controller for VolumeController {
         let key = match event {
             ControllerEvent::BringUp(ResourceKind::Volume, metadata) => Some(ControllerKey::new(
                 ctx.tenant.clone(),
-                ResourceKind::Service,
+                ResourceKind::Volume,
                 metadata.namespace,
                 metadata.name,
             )),
@@ -50,6 +70,10 @@ impl Controller for VolumeController {
                     metadata.name,
                 ))
             }
+            ControllerEvent::AsyncWorkChange(
+                key,
+                AsyncWork::ImagePullComplete { .. } | AsyncWork::Error(_),
+            ) if key.kind == ResourceKind::Volume => Some(key),
             _ => None,
         };
         Ok(key)
@@ -93,26 +117,110 @@ impl Controller for VolumeController {
             return Ok(ReconcileNext::done());
         };
 
+        if status.volume_id.is_none() {
+            ctx.agent
+                .volume()
+                .check_quota(&ctx.tenant, status.size_bytes)?;
+        }
+
+        let encrypted = volume.latest().encrypted == Some(true);
         let volume_id = if let Some(volume_id) = status.volume_id {
             volume_id
+        } else if let Some(source) = volume.latest().from {
+            let Some(copy) = copy_source(&ctx, &key, &source, status.size_bytes).await? else {
+                info!("waiting for the source of volume {}", key.to_string());
+                return Ok(ReconcileNext::after(SOURCE_POLL_INTERVAL));
+            };
+
+            if encrypted && copy.encrypted_for.is_none() {
+                if let Err(e) = ctx
+                    .agent
+                    .volume()
+                    .volume_encrypt(&copy.id, &ctx.tenant)
+                    .await
+                {
+                    ctx.agent.volume().volume_delete(&copy.id).await.ok();
+                    return Err(e);
+                }
+            }
+
+            copy.id
+        } else if encrypted {
+            ctx.agent
+                .volume()
+                .volume_create_encrypted_sparse(
+                    status.size_bytes,
+                    filesystem(&volume.latest()),
+                    &ctx.tenant,
+                )
+                .await?
+                .id
         } else {
             ctx.agent
                 .volume()
-                .volume_create_empty_ext4_sparse(status.size_bytes)
+                .volume_create_empty_formatted_sparse(
+                    status.size_bytes,
+                    filesystem(&volume.latest()),
+                )
                 .await?
                 .id
         };
 
+        // volumes created before quotas were tracked are counted from their next reconcile
+        let owned = ctx
+            .agent
+            .volume()
+            .volume(&volume_id)?
+            .is_some_and(|volume| volume.owner.is_some());
+        if !owned {
+            ctx.agent
+                .volume()
+                .volume_set_owner(&volume_id, &ctx.tenant)?;
+        }
+
         let hash = volume.hash_with_updated_metadata();
+
+        let now = now_millis();
+        let policy = volume.latest().backup;
+        let next_backup_at = match &policy {
+            Some(policy) => {
+                if status.next_backup_at.is_some_and(|at| at <= now) {
+                    start_backup(
+                        ctx.clone(),
+                        key.metadata(),
+                        volume_id.clone(),
+                        policy.clone(),
+                    );
+                }
+
+                CronSchedule::parse(&policy.schedule)?
+                    .next_after(now / 1000)
+                    .map(|secs| secs * 1000)
+            }
+            None => None,
+        };
+
+        // the last known usage stays in the status while no machine mounts the volume
+        let usage = ctx.agent.machine().volume_usage(&volume_id);
+
         ctx.repository
             .volume(ctx.tenant.clone())
             .patch_status(key.metadata().clone(), |status| {
                 status.hash = hash;
                 status.volume_id = Some(volume_id.clone());
+                status.next_backup_at = next_backup_at;
+                if let Some(usage) = usage {
+                    status.used_bytes = Some(usage.used_bytes);
+                    status.filesystem_size_bytes = Some(usage.size_bytes);
+                }
             })
             .await?;
 
-        Ok(ReconcileNext::done())
+        let next_reconcile = match next_backup_at {
+            Some(at) => Duration::from_millis(at - now).min(USAGE_REFRESH_INTERVAL),
+            None => USAGE_REFRESH_INTERVAL,
+        };
+        Ok(ReconcileNext::after(next_reconcile))
     }
 
     async fn handle_error(
@@ -131,50 +239,450 @@ impl Controller for VolumeController {
     }
 }
 
+fn filesystem(volume: &VolumeLatest) -> Filesystem {
+    match volume.filesystem {
+        Some(VolumeFilesystem::Ext4) | None => Filesystem::Ext4,
+        Some(VolumeFilesystem::Xfs) => Filesystem::Xfs,
+        Some(VolumeFilesystem::Btrfs) => Filesystem::Btrfs,
+    }
+}
+
+/// Copies the volume or image a volume is created from, `None` while the source isn't ready.
+async fn copy_source(
+    ctx: &ControllerContext,
+    key: &ControllerKey,
+    source: &VolumeSource,
+    size_bytes: u64,
+) -> Result<Option<volume::Volume>> {
+    let source_volume_id = match (&source.volume, &source.image) {
+        (Some(name), None) => {
+            let namespace = Namespace::from_value_or_default(
+                source
+                    .namespace
+                    .clone()
+                    .or_else(|| key.metadata().namespace),
+            );
+            let Some(status) = ctx
+                .repository
+                .volume(ctx.tenant.clone())
+                .get_status(Metadata::new(name, namespace))?
+            else {
+                bail!("source volume {} not found", name);
+            };
+
+            let Some(volume_id) = status.volume_id else {
+                return Ok(None);
+            };
+            volume_id
+        }
+        (None, Some(image)) => {
+            let reference = Reference::from_str(image)
+                .map_err(|_| anyhow!("invalid image reference: {}", image))?;
+            let job_key = pull_image_job_key(&reference);
+
+            let Some(event) = ctx.agent.job().get_result(&job_key, key.clone()).await? else {
+                start_image_pull(ctx, key.clone(), reference, image.clone()).await?;
+                return Ok(None);
+            };
+            ctx.agent
+                .job()
+                .consume_result(&job_key, key.clone())
+                .await?;
+
+            match event {
+                ControllerEvent::AsyncWorkChange(_, AsyncWork::ImagePullComplete { id, .. }) => {
+                    let Some(pulled) = ctx.agent.image().image(&id)? else {
+                        bail!("image {} not found", image);
+                    };
+                    pulled.volume_id
+                }
+                ControllerEvent::AsyncWorkChange(_, AsyncWork::Error(e)) => bail!("{}", e),
+                _ => return Ok(None),
+            }
+        }
+        _ => bail!("volume source must set either a volume or an image"),
+    };
+
+    let copy = ctx
+        .agent
+        .volume()
+        .volume_copy(&source_volume_id, size_bytes)
+        .await?;
+
+    Ok(Some(copy))
+}
+
+/// Directory of the backups of a volume, below the prefix of its backup destination.
+fn backup_path(tenant: &str, metadata: &Metadata) -> String {
+    let namespace = metadata
+        .namespace
+        .clone()
+        .unwrap_or_else(|| DEFAULT_NAMESPACE.to_string());
+
+    format!("{}/{}/{}", tenant, namespace, metadata.name)
+}
+
+/// Backs up a volume in the background, the outcome lands in its status.
+fn start_backup(
+    ctx: ControllerContext,
+    metadata: Metadata,
+    volume_id: String,
+    policy: VolumeBackupPolicy,
+) {
+    tokio::spawn(async move {
+        let result = ctx
+            .agent
+            .volume()
+            .volume_backup(
+                &volume_id,
+                &policy.destination,
+                &backup_path(&ctx.tenant, &metadata),
+                policy.keep,
+            )
+            .await;
+
+        match &result {
+            Ok(backup) => info!(
+                "backed up volume {} as {} ({} bytes)",
+                metadata.to_string(),
+                backup.id,
+                backup.size_bytes
+            ),
+            Err(e) => error!("failed to back up volume {}: {}", metadata.to_string(), e),
+        }
+
+        let patched = ctx
+            .repository
+            .volume(ctx.tenant.clone())
+            .patch_status(metadata.clone(), |status| match &result {
+                Ok(backup) => {
+                    status.last_backup_at = Some(backup.created_at);
+                    status.last_backup = Some(backup.id.clone());
+                    status.last_backup_error = None;
+                }
+                Err(e) => status.last_backup_error = Some(e.to_string()),
+            })
+            .await;
+        if let Err(e) = patched {
+            warn!(
+                "failed to record the backup of volume {}: {}",
+                metadata.to_string(),
+                e
+            );
+        }
+    });
+}
+
+/// The backups of a volume stored at the destination of its backup policy, oldest first.
+pub async fn list_volume_backups(
+    tenant: String,
+    repo: Arc<Repository>,
+    agent: Arc<Agent>,
+    metadata: Metadata,
+) -> Result<Vec<VolumeBackup>> {
+    let Some(volume) = repo.volume(tenant.clone()).get(
+        Namespace::from_value_or_default(metadata.namespace.clone()),
+        metadata.name.clone(),
+    )?
+    else {
+        bail!("volume not found");
+    };
+
+    let Some(policy) = volume.latest().backup else {
+        bail!("volume has no backup policy");
+    };
+
+    let backups = agent
+        .volume()
+        .volume_backup_list(&policy.destination, &backup_path(&tenant, &metadata))
+        .await?;
+
+    Ok(backups
+        .into_iter()
+        .map(|backup| VolumeBackup {
+            id: backup.id,
+            created_at: backup.created_at,
+            size_bytes: backup.size_bytes,
+        })
+        .collect())
+}
+
+/// Replaces the contents of a volume with one of its backups, the latest one when `backup` is
+/// not set. Machines using the volume have to be stopped first.
+pub async fn restore_volume(
+    tenant: String,
+    repo: Arc<Repository>,
+    agent: Arc<Agent>,
+    metadata: Metadata,
+    backup: Option<String>,
+) -> Result<VolumeRestore> {
+    let Some((volume, status)) = repo
+        .volume(tenant.clone())
+        .get_with_status(metadata.clone())?
+    else {
+        bail!("volume not found");
+    };
+
+    let Some(volume_id) = status.volume_id else {
+        bail!("volume is not provisioned yet");
+    };
+
+    let Some(policy) = volume.latest().backup else {
+        bail!("volume has no backup policy");
+    };
+
+    for machine in agent.machine().list_machines() {
+        let uses_volume = machine
+            .config
+            .volume_mounts
+            .iter()
+            .any(|mount| mount.volume.id == volume_id);
+        if !uses_volume {
+            continue;
+        }
+
+        match machine.get_state().await {
+            MachineState::Idle | MachineState::Stopped | MachineState::Error(_) => {}
+            _ => bail!(
+                "volume is used by machine {}, stop it first",
+                machine.config.controller_key.name
+            ),
+        }
+    }
+
+    let restored = agent
+        .volume()
+        .volume_restore(
+            &volume_id,
+            &policy.destination,
+            &backup_path(&tenant, &metadata),
+            backup.as_deref(),
+        )
+        .await?;
+
+    Ok(VolumeRestore {
+        name: metadata.name,
+        backup: restored.id,
+    })
+}
+
+/// Grows a volume and the filesystems of the running machines using it, the others grow
+/// theirs when they boot.
+pub async fn resize_volume(
+    tenant: String,
+    repo: Arc<Repository>,
+    agent: Arc<Agent>,
+    metadata: Metadata,
+    size: String,
+) -> Result<VolumeResize> {
+    let Some((volume, status)) = repo
+        .volume(tenant.clone())
+        .get_with_status(metadata.clone())?
+    else {
+        bail!("volume not found");
+    };
+
+    let Some(volume_id) = status.volume_id else {
+        bail!("volume is not provisioned yet");
+    };
+
+    let mut latest = volume.latest();
+    if latest.mode == VolumeMode::ReadOnly {
+        bail!("read-only volumes can't be resized");
+    }
+
+    let size_bytes = parse_human_readable_size(&size)?;
+    if size_bytes <= status.size_bytes {
+        bail!("volumes can only grow");
+    }
+    agent
+        .volume()
+        .check_quota(&tenant, size_bytes - status.size_bytes)?;
+
+    agent.volume().volume_resize(&volume_id, size_bytes).await?;
+
+    let mut machines = vec![];
+    for machine in agent.machine().list_machines() {
+        match machine.notify_volume_resized(&volume_id) {
+            Ok(true) => machines.push(machine.config.controller_key.name.clone()),
+            Ok(false) => {}
+            Err(e) => error!(
+                "failed to notify machine {} of the resize of volume {}: {}",
+                machine.config.name, volume_id, e
+            ),
+        }
+    }
+
+    latest.size = size;
+    let volume: Volume = latest.into();
+    let hash = volume.hash_with_updated_metadata();
+    repo.volume(tenant.clone()).set(volume).await?;
+
+    repo.volume(tenant)
+        .patch_status(metadata.clone(), |status| {
+            status.hash = hash;
+            status.size_bytes = size_bytes;
+        })
+        .await?;
+
+    Ok(VolumeResize {
+        name: metadata.name,
+        size_bytes,
+        machines,
+    })
+}
+
 #[async_trait]
-impl AdmissionCheckBeforeDelete for Volume {
-    async fn before_delete(
+impl AdmissionCheckBeforeSet for Volume {
+    async fn before_set(
         &self,
+        before: Option<&Self>,
         tenant: String,
         repo: Arc<Repository>,
-        _agent: Arc<Agent>,
+        agent: Arc<Agent>,
         metadata: Metadata,
     ) -> Result<()> {
-        let Some(volume) = repo.volume(tenant.clone()).get(
-            Namespace::from_value_or_default(metadata.namespace.clone()),
-            metadata.name.clone(),
-        )?
-        else {
-            bail!("volume not found");
+        let volume = self.latest();
+        // shared read-only volumes would end up written by several machines at once
+        if let Some(before) = before {
+            if before.latest().mode == VolumeMode::ReadOnly
+                && volume.mode == VolumeMode::Writeable
+                && attached_machine_count(&repo, &tenant, &metadata)? > 1
+            {
+                bail!("volume is shared by several machines and can't be made writeable");
+            }
+        }
+
+        if before.is_none() {
+            agent
+                .volume()
+                .check_quota(&tenant, parse_human_readable_size(&volume.size)?)?;
+
+            // the source only matters when the volume is created
+            if let Some(source) = &volume.from {
+                check_source(&volume, source, &tenant, &repo, &metadata)?;
+            }
+        }
+
+        if volume.encrypted == Some(true) && !agent.volume().encryption_enabled() {
+            bail!("volume encryption is not configured on this daemon");
+        }
+
+        let min_size_bytes = match filesystem(&volume) {
+            Filesystem::Ext4 => 0,
+            Filesystem::Xfs => XFS_MIN_SIZE_BYTES,
+            Filesystem::Btrfs => BTRFS_MIN_SIZE_BYTES,
         };
-        let volume = volume.latest();
+        if parse_human_readable_size(&volume.size)? < min_size_bytes {
+            bail!(
+                "{} volumes must be at least {}",
+                filesystem(&volume).name(),
+                format_human_readable_size(min_size_bytes)
+            );
+        }
 
-        let machines = repo.machine(tenant.clone()).list(Namespace::Unspecified)?;
+        let Some(backup) = volume.backup else {
+            return Ok(());
+        };
 
-        let mut usage_count = 0;
-        for machine in machines {
-            let machine = machine.latest();
-            let Some(volumes) = machine.volumes else {
-                continue;
+        CronSchedule::parse(&backup.schedule)
+            .map_err(|e| anyhow!("invalid backup schedule: {}", e))?;
+        agent.volume().backup_destination(&backup.destination)?;
+
+        if backup.keep == Some(0) {
+            bail!("backups to keep must be at least 1");
+        }
+
+        Ok(())
+    }
+}
+
+fn check_source(
+    volume: &VolumeLatest,
+    source: &VolumeSource,
+    tenant: &str,
+    repo: &Repository,
+    metadata: &Metadata,
+) -> Result<()> {
+    match (&source.volume, &source.image) {
+        (Some(name), None) => {
+            let namespace = Namespace::from_value_or_default(
+                source
+                    .namespace
+                    .clone()
+                    .or_else(|| metadata.namespace.clone()),
+            );
+            if name == &metadata.name
+                && namespace == Namespace::from_value_or_default(metadata.namespace.clone())
+            {
+                bail!("a volume can't be created from itself");
+            }
+
+            let Some(source_volume) = repo
+                .volume(tenant.to_string())
+                .get(namespace, name.clone())?
+            else {
+                bail!("source volume {} not found", name);
             };
+            let source_volume = source_volume.latest();
 
-            volumes.iter().for_each(|volume_bind| {
-                if volume_bind.name == volume.name {
-                    let namespace = Namespace::from_value_or_default(
-                        volume_bind
-                            .namespace
-                            .clone()
-                            .or_else(|| machine.namespace.clone()),
-                    )
-                    .as_value();
-
-                    if namespace == metadata.namespace {
-                        usage_count += 1;
-                    }
-                }
-            });
+            if parse_human_readable_size(&source_volume.size)?
+                > parse_human_readable_size(&volume.size)?
+            {
+                bail!(
+                    "volume must be at least as large as its source ({})",
+                    source_volume.size
+                );
+            }
+            if filesystem(&source_volume) != filesystem(volume) {
+                bail!(
+                    "source volume {} is formatted as {}",
+                    name,
+                    filesystem(&source_volume).name()
+                );
+            }
+            if source_volume.encrypted == Some(true) && volume.encrypted != Some(true) {
+                bail!("volumes created from an encrypted volume must be encrypted");
+            }
+        }
+        (None, Some(image)) => {
+            if source.namespace.is_some() {
+                bail!("namespace only applies to a source volume");
+            }
+            // image root filesystems are ext4
+            if filesystem(volume) != Filesystem::Ext4 {
+                bail!("volumes created from an image must be ext4");
+            }
+            Reference::from_str(image)
+                .map_err(|_| anyhow!("invalid image reference: {}", image))?;
+        }
+        _ => bail!("volume source must set either a volume or an image"),
+    }
+
+    Ok(())
+}
+
+#[async_trait]
+impl AdmissionCheckBeforeDelete for Volume {
+    async fn before_delete(
+        &self,
+        tenant: String,
+        repo: Arc<Repository>,
+        _agent: Arc<Agent>,
+        metadata: Metadata,
+    ) -> Result<()> {
+        if repo
+            .volume(tenant.clone())
+            .get(
+                Namespace::from_value_or_default(metadata.namespace.clone()),
+                metadata.name.clone(),
+            )?
+            .is_none()
+        {
+            bail!("volume not found");
         }
 
+        let usage_count = attached_machine_count(&repo, &tenant, &metadata)?;
         if usage_count > 0 {
             bail!("volume is still in use by {} machines", usage_count);
         }
@@ -182,3 +690,36 @@ impl AdmissionCheckBeforeDelete for Volume {
         Ok(())
     }
 }
+
+/// Machines the volume is attached to.
+fn attached_machine_count(repo: &Repository, tenant: &str, metadata: &Metadata) -> Result<usize> {
+    let machines = repo
+        .machine(tenant.to_string())
+        .list(Namespace::Unspecified)?;
+
+    let mut usage_count = 0;
+    for machine in machines {
+        let machine = machine.latest();
+        let Some(volumes) = machine.volumes else {
+            continue;
+        };
+
+        volumes.iter().for_each(|volume_bind| {
+            if volume_bind.name == metadata.name {
+                let namespace = Namespace::from_value_or_default(
+                    volume_bind
+                        .namespace
+                        .clone()
+                        .or_else(|| machine.namespace.clone()),
+                )
+                .as_value();
+
+                if namespace == metadata.namespace {
+                    usage_count += 1;
+                }
+            }
+        });
+    }
+
+    Ok(usage_count)
+}
@@ -1,10 +1,20 @@
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
 use anyhow::{Result, bail};
+use base64::{Engine, prelude::BASE64_STANDARD};
 use ignition::agent::certificate::config::CertProvider;
+use ignition::agent::image::gc::ImageGcPolicy;
+use ignition::agent::image::labels::ImageLabelPolicy;
+use ignition::agent::image::signature::ImageSignaturePolicy;
 use ignition::agent::logs::LogsStoreConfig;
+use ignition::agent::logs::retention::LogRetentionConfig;
+use ignition::agent::metrics::MetricsStoreConfig;
 use ignition::agent::port_allocator::TcpPortRange;
+use ignition::agent::volume::backup::VolumeBackupStorage;
+use ignition::utils::redact::redact_secrets;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use tokio::fs::read_to_string;
 use tracing::warn;
 
@@ -39,6 +49,12 @@ pub struct Config {
     #[serde(rename = "cert-provider", default)]
     pub cert_providers: Vec<CertProvider>,
 
+    #[serde(rename = "cert-renewal")]
+    pub cert_renewal_config: Option<CertRenewalConfig>,
+
+    #[serde(rename = "cert-storage")]
+    pub cert_storage_config: Option<CertStorageConfig>,
+
     #[serde(rename = "logs")]
     pub logs_config: LogsConfig,
 
@@ -47,6 +63,36 @@ pub struct Config {
 
     #[serde(rename = "build")]
     pub build_config: Option<BuildConfig>,
+
+    #[serde(rename = "metrics")]
+    pub metrics_config: Option<MetricsConfig>,
+
+    #[serde(rename = "metrics-store")]
+    pub metrics_store_config: Option<MetricsStoreConfig>,
+
+    #[serde(rename = "events")]
+    pub events_config: Option<EventsConfig>,
+
+    #[serde(rename = "image-signatures")]
+    pub image_signature_policy: Option<ImageSignaturePolicy>,
+
+    #[serde(rename = "image-labels")]
+    pub image_label_policy: Option<ImageLabelPolicy>,
+
+    #[serde(rename = "image-gc")]
+    pub image_gc_policy: Option<ImageGcPolicy>,
+
+    #[serde(rename = "volume-backups")]
+    pub volume_backup_storage: Option<VolumeBackupStorage>,
+
+    #[serde(rename = "volume-encryption")]
+    pub volume_encryption_config: Option<VolumeEncryptionConfig>,
+
+    #[serde(rename = "volume-quota")]
+    pub volume_quota_config: Option<VolumeQuotaConfig>,
+
+    #[serde(rename = "node")]
+    pub node_config: Option<NodeConfig>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -69,6 +115,32 @@ pub struct ProxyConfig {
     pub default_tls_key_path: String,
     #[serde(rename = "tcp-port-range")]
     pub tcp_port_range: Option<TcpPortRange>,
+    #[serde(rename = "default-backend")]
+    pub default_backend: Option<DefaultBackendConfig>,
+    pub http3: Option<bool>,
+    #[serde(rename = "ssh-gateway")]
+    pub ssh_gateway: Option<SshGatewayConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SshGatewayConfig {
+    pub port: u16,
+    #[serde(rename = "host-key-path")]
+    pub host_key_path: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DefaultBackendConfig {
+    #[serde(rename = "not-found-page-path")]
+    pub not_found_page_path: Option<PathBuf>,
+    pub service: Option<DefaultBackendServiceConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DefaultBackendServiceConfig {
+    pub tenant: String,
+    pub namespace: String,
+    pub name: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -79,6 +151,19 @@ pub struct MachineConfig {
     pub initrd_path: PathBuf,
     #[serde(rename = "append-cmd-line")]
     pub append_cmd_line: Option<String>,
+    /// cgroup v2 directory to run the machines' vcpus in, eg. `/sys/fs/cgroup/ignitiond`, so
+    /// each machine gets at most its share of the host CPU. Not enforced when unset.
+    #[serde(rename = "cgroup-root")]
+    pub cgroup_root: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct NodeConfig {
+    /// Name machines placed on this host report in their status. Default: the hostname.
+    pub name: Option<String>,
+    /// Matched against the placement constraints of machines, eg. `disk = "nvme"`.
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -89,6 +174,17 @@ pub struct ApiServerConfig {
     pub port: u16,
     #[serde(rename = "jwt-secret")]
     pub jwt_secret: String,
+    /// Tenants allowed to use the admin endpoints, eg. `lttle admin support-bundle`.
+    #[serde(rename = "admin-tenants", default)]
+    pub admin_tenants: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MetricsConfig {
+    #[serde(rename = "host")]
+    pub host: String,
+    #[serde(rename = "port")]
+    pub port: u16,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -109,17 +205,84 @@ pub struct DnsConfig {
     pub zone_suffix: String,
     #[serde(rename = "default-ttl")]
     pub default_ttl: u32,
+    /// Tried in order: `8.8.8.8:53`, `tls://1.1.1.1#cloudflare-dns.com` (DNS over TLS) or
+    /// `https://1.1.1.1#cloudflare-dns.com` (DNS over HTTPS).
     #[serde(rename = "upstream-dns-servers", default)]
     pub upstream_dns_servers: Vec<String>,
     #[serde(rename = "region-root-domain")]
     pub region_root_domain: String,
+    #[serde(rename = "zone-bind-address")]
+    pub zone_bind_address: Option<String>,
+    /// Answer the hosts of external services with the service gateway to machines, and with the
+    /// ingress address to everyone else. Default: true.
+    #[serde(rename = "split-horizon")]
+    pub split_horizon: Option<bool>,
+    /// Public address of the proxy, when it's not the external bind address (eg. behind NAT).
+    #[serde(rename = "ingress-address")]
+    pub ingress_address: Option<String>,
+    /// Log every query answered, to debug resolution issues. Default: false.
+    #[serde(rename = "log-queries")]
+    pub log_queries: Option<bool>,
+    /// Sign the region root domain with DNSSEC. The DS record to publish at the parent zone is
+    /// logged when the zone server starts. Default: false.
+    pub dnssec: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CertRenewalConfig {
+    /// Renewal window of certificates that don't configure their own.
+    #[serde(rename = "days-before-expiry")]
+    pub days_before_expiry: Option<u32>,
+    #[serde(rename = "scan-interval-mins")]
+    pub scan_interval_mins: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CertStorageConfig {
+    /// Base64 of the 32 byte key sealing certificate private keys in the store. Derived from the
+    /// api jwt-secret when not set, which then can't be rotated without reissuing certificates.
+    #[serde(rename = "encryption-secret")]
+    pub encryption_secret: Option<String>,
+    /// Also write certificates to <data-dir>/agent/certs. Default: true
+    #[serde(rename = "disk-cache")]
+    pub disk_cache: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct VolumeEncryptionConfig {
+    /// Base64 of the 32 byte key the volume keys of tenants are derived from. Encrypted volumes
+    /// can't be read anymore once it's lost or changed.
+    #[serde(rename = "master-secret")]
+    pub master_secret: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct VolumeQuotaConfig {
+    /// Volume space a tenant can provision unless an admin set another quota for it.
+    /// Default: unlimited
+    #[serde(rename = "default-gib")]
+    pub default_gib: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct LogsConfig {
+    /// External OTLP endpoint machines export their logs to. Default: the built-in ingest.
     #[serde(rename = "otel-ingest-endpoint")]
-    pub otel_ingest_endpoint: String,
+    pub otel_ingest_endpoint: Option<String>,
+    /// Port of the built-in OTLP ingest, served on the service gateway. Default: 4318 when no
+    /// external endpoint is set.
+    #[serde(rename = "otlp-ingest-port")]
+    pub otlp_ingest_port: Option<u16>,
     pub store: LogsStoreConfig,
+    /// How long logs are kept, per tenant or namespace. Default: forever.
+    pub retention: Option<LogRetentionConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EventsConfig {
+    /// Minutes an event is kept after it was last seen. Default: 60
+    #[serde(rename = "ttl-mins")]
+    pub ttl_mins: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -138,6 +301,8 @@ pub struct BuildConfig {
     #[serde(rename = "ca-key-path")]
     pub ca_key_path: String,
     pub pool: Vec<String>,
+    #[serde(rename = "crl-path")]
+    pub crl_path: Option<String>,
 }
 
 async fn resolve_config_path(path_override: Option<PathBuf>) -> Result<PathBuf> {
@@ -200,4 +365,66 @@ impl Config {
     pub fn absolute_data_dir(&self) -> PathBuf {
         self.config_dir.join(self.data_dir.clone())
     }
+
+    /// Name of this node, the configured one or the hostname.
+    pub fn node_name(&self) -> String {
+        self.node_config
+            .as_ref()
+            .and_then(|node| node.name.clone())
+            .or_else(|| {
+                std::fs::read_to_string("/proc/sys/kernel/hostname")
+                    .ok()
+                    .map(|hostname| hostname.trim().to_string())
+                    .filter(|hostname| !hostname.is_empty())
+            })
+            .unwrap_or_else(|| "local".to_string())
+    }
+
+    /// Key sealing certificate private keys in the store.
+    pub fn certificate_store_key(&self) -> Result<[u8; 32]> {
+        let encryption_secret = self
+            .cert_storage_config
+            .as_ref()
+            .and_then(|c| c.encryption_secret.as_ref());
+
+        let Some(encryption_secret) = encryption_secret else {
+            return Ok(blake3::derive_key(
+                "ignition certificate store key",
+                self.api_server_config.jwt_secret.as_bytes(),
+            ));
+        };
+
+        let Ok(key) = BASE64_STANDARD.decode(encryption_secret.trim()) else {
+            bail!("cert-storage encryption-secret must be base64");
+        };
+        let Ok(key) = <[u8; 32]>::try_from(key) else {
+            bail!("cert-storage encryption-secret must be 32 bytes long");
+        };
+
+        Ok(key)
+    }
+
+    /// Master key of encrypted volumes, they can't be created without it.
+    pub fn volume_encryption_key(&self) -> Result<Option<[u8; 32]>> {
+        let Some(config) = &self.volume_encryption_config else {
+            return Ok(None);
+        };
+
+        let Ok(key) = BASE64_STANDARD.decode(config.master_secret.trim()) else {
+            bail!("volume-encryption master-secret must be base64");
+        };
+        let Ok(key) = <[u8; 32]>::try_from(key) else {
+            bail!("volume-encryption master-secret must be 32 bytes long");
+        };
+
+        Ok(Some(key))
+    }
+
+    /// The config as JSON with secrets redacted, safe to hand out in support bundles.
+    pub fn redacted(&self) -> Result<Value> {
+        let mut value = serde_json::to_value(self)?;
+        redact_secrets(&mut value);
+
+        Ok(value)
+    }
 }
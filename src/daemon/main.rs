@@ -1,25 +1,50 @@
 mod cmd;
 mod config;
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use clap::Parser;
 use ignition::{
     agent::{
-        Agent, AgentConfig, build::BuildAgentConfig, certificate::config::CertificateAgentConfig,
-        dns::config::DnsAgentConfig, image::ImageAgentConfig, logs::LogsAgentConfig,
-        machine::MachineAgentConfig, net::NetAgentConfig, openai::OpenAIAgentConfig,
-        proxy::ProxyAgentConfig, volume::VolumeAgentConfig,
+        Agent, AgentConfig,
+        build::BuildAgentConfig,
+        certificate::config::CertificateAgentConfig,
+        dns::config::{DnsAgentConfig, DnsUpstream},
+        events::EventsAgentConfig,
+        image::ImageAgentConfig,
+        logs::LogsAgentConfig,
+        machine::{MachineAgentConfig, NodeInfo},
+        metrics::MetricsAgentConfig,
+        net::NetAgentConfig,
+        openai::OpenAIAgentConfig,
+        proxy::{ProxyAgentConfig, ProxyBindingService},
+        volume::VolumeAgentConfig,
     },
     api::{
-        ApiServer, ApiServerConfig, auth::AuthHandler, core::CoreService, gadget::GadgetService,
+        ApiServer, ApiServerConfig,
+        auth::AuthHandler,
+        core::CoreService,
+        gadget::GadgetService,
+        metrics::{MetricsServerConfig, start_metrics_server},
+        ssh::{SshGateway, SshGatewayConfig},
+    },
+    constants::{
+        DEFAULT_EVENT_TTL_MINS, DEFAULT_KERNEL_CMD_LINE_INIT, DEFAULT_METRICS_EXPORT_INTERVAL_SECS,
     },
-    constants::DEFAULT_KERNEL_CMD_LINE_INIT,
     controller::{
         app::AppController,
         certificate::CertificateController,
+        certificate_renewal::{
+            DEFAULT_RENEWAL_DAYS_BEFORE_EXPIRY, DEFAULT_RENEWAL_SCAN_INTERVAL,
+            start_certificate_renewal,
+        },
+        cron::CronController,
+        dns_record::DnsRecordController,
+        expiry::{DEFAULT_EXPIRY_SCAN_INTERVAL, start_expiry},
+        health_probe::{DEFAULT_HEALTH_PROBE_INTERVAL, start_health_probes},
         machine::MachineController,
+        machine_group::MachineGroupController,
         scheduler::{Scheduler, SchedulerConfig},
         service::ServiceController,
         volume::VolumeController,
@@ -30,7 +55,7 @@ use ignition::{
     utils::tracing::init_tracing,
 };
 use tokio::{runtime, task::block_in_place};
-use tracing::info;
+use tracing::{info, warn};
 
 use crate::config::Config;
 
@@ -41,14 +66,34 @@ async fn main() -> Result<()> {
     let args = cmd::Cli::parse();
 
     let config = Config::load(args.config_path).await?;
-    info!("Loaded config from {}", config.config_path.display());
-    dbg!(&config);
+    info!(
+        version = env!("CARGO_PKG_VERSION"),
+        config = %config.config_path.display(),
+        data_dir = %config.absolute_data_dir().display(),
+        api = %format!("{}:{}", config.api_server_config.host, config.api_server_config.port),
+        metrics = config.metrics_config.is_some(),
+        builds = config.build_config.is_some(),
+        openai = config.openai_config.is_some(),
+        cert_providers = config.cert_providers.len(),
+        "starting ignitiond"
+    );
 
     if !config.absolute_data_dir().exists() {
         tokio::fs::create_dir_all(&config.absolute_data_dir()).await?;
     }
 
     let store = Arc::new(Store::new(&config.absolute_data_dir()).await?);
+    let certificate_store_key = config.certificate_store_key()?;
+    let volume_encryption_key = config.volume_encryption_key()?;
+    let upstream_dns_servers = config
+        .dns_config
+        .upstream_dns_servers
+        .iter()
+        .map(|server| {
+            DnsUpstream::parse(server)
+                .map_err(|e| anyhow!("invalid upstream DNS server {}: {}", server, e))
+        })
+        .collect::<Result<Vec<_>>>()?;
 
     let auth_handler = Arc::new(AuthHandler::new(
         &config.api_server_config.jwt_secret.clone(),
@@ -84,6 +129,14 @@ async fn main() -> Result<()> {
                 }
 
                 let agent_dir = scheduler_config.absolute_data_dir().join("agent");
+                let node = NodeInfo {
+                    name: scheduler_config.node_name(),
+                    labels: scheduler_config
+                        .node_config
+                        .as_ref()
+                        .map(|node| node.labels.clone())
+                        .unwrap_or_default(),
+                };
 
                 Arc::new(
                     Agent::new(
@@ -96,6 +149,13 @@ async fn main() -> Result<()> {
                             },
                             volume_config: VolumeAgentConfig {
                                 base_path: agent_dir.join("volumes").to_string_lossy().to_string(),
+                                backup_storage: scheduler_config.volume_backup_storage.clone(),
+                                encryption_key: volume_encryption_key,
+                                default_quota_bytes: scheduler_config
+                                    .volume_quota_config
+                                    .as_ref()
+                                    .and_then(|quota| quota.default_gib)
+                                    .map(|gib| gib * 1024 * 1024 * 1024),
                             },
                             image_config: ImageAgentConfig {
                                 base_path: agent_dir.join("images").to_string_lossy().to_string(),
@@ -103,9 +163,15 @@ async fn main() -> Result<()> {
                                     .registry_config
                                     .service
                                     .clone(),
+                                signature_policy: scheduler_config.image_signature_policy.clone(),
+                                label_policy: scheduler_config.image_label_policy.clone(),
+                                gc_policy: scheduler_config.image_gc_policy.clone(),
                             },
                             machine_config: MachineAgentConfig {
                                 transient_state_path: transient_dir.to_path_buf().join("machines"),
+                                core_dumps_path: agent_dir.join("cores"),
+                                cgroup_root: scheduler_config.machine_config.cgroup_root.clone(),
+                                node,
                                 kernel_path: scheduler_config
                                     .config_dir
                                     .join(&scheduler_config.machine_config.kernel_path)
@@ -143,14 +209,47 @@ async fn main() -> Result<()> {
                                     .dns_config
                                     .region_root_domain
                                     .clone(),
+                                default_backend_service: scheduler_config
+                                    .proxy_config
+                                    .default_backend
+                                    .as_ref()
+                                    .and_then(|b| b.service.clone())
+                                    .map(|s| ProxyBindingService {
+                                        tenant: s.tenant,
+                                        namespace: s.namespace,
+                                        name: s.name,
+                                    }),
+                                not_found_page_path: scheduler_config
+                                    .proxy_config
+                                    .default_backend
+                                    .as_ref()
+                                    .and_then(|b| b.not_found_page_path.as_ref())
+                                    .map(|path| {
+                                        scheduler_config
+                                            .config_dir
+                                            .join(path)
+                                            .to_string_lossy()
+                                            .to_string()
+                                    }),
+                                http3: scheduler_config.proxy_config.http3.unwrap_or(false),
+                                internal_bind_address: None,
                             },
                             dns_config: DnsAgentConfig {
-                                zone_suffix: scheduler_config.dns_config.zone_suffix,
+                                zone_suffix: scheduler_config.dns_config.zone_suffix.clone(),
                                 default_ttl: scheduler_config.dns_config.default_ttl,
-                                upstream_dns_servers: scheduler_config
-                                    .dns_config
-                                    .upstream_dns_servers,
+                                upstream_dns_servers,
                                 region_root_domain: scheduler_config.dns_config.region_root_domain,
+                                zone_bind_address: scheduler_config.dns_config.zone_bind_address,
+                                split_horizon: scheduler_config
+                                    .dns_config
+                                    .split_horizon
+                                    .unwrap_or(true),
+                                ingress_address: scheduler_config.dns_config.ingress_address,
+                                log_queries: scheduler_config
+                                    .dns_config
+                                    .log_queries
+                                    .unwrap_or(false),
+                                dnssec: scheduler_config.dns_config.dnssec.unwrap_or(false),
                             },
                             cert_config: CertificateAgentConfig {
                                 providers: scheduler_config.cert_providers,
@@ -158,13 +257,51 @@ async fn main() -> Result<()> {
                                     .join("certs")
                                     .to_string_lossy()
                                     .to_string(),
+                                renewal_days_before_expiry: scheduler_config
+                                    .cert_renewal_config
+                                    .as_ref()
+                                    .and_then(|c| c.days_before_expiry)
+                                    .unwrap_or(DEFAULT_RENEWAL_DAYS_BEFORE_EXPIRY),
+                                disk_cache: scheduler_config
+                                    .cert_storage_config
+                                    .as_ref()
+                                    .and_then(|c| c.disk_cache)
+                                    .unwrap_or(true),
+                                store_key: certificate_store_key,
+                                internal_domain_suffix: format!(
+                                    "svc.{}",
+                                    scheduler_config.dns_config.zone_suffix
+                                ),
                             },
                             logs_config: LogsAgentConfig {
                                 store: scheduler_config.logs_config.store,
                                 otel_ingest_endpoint: scheduler_config
                                     .logs_config
                                     .otel_ingest_endpoint,
+                                otlp_ingest_port: scheduler_config.logs_config.otlp_ingest_port,
+                                retention: scheduler_config
+                                    .logs_config
+                                    .retention
+                                    .unwrap_or_default(),
                             },
+                            events_config: EventsAgentConfig {
+                                ttl: Duration::from_secs(
+                                    scheduler_config
+                                        .events_config
+                                        .as_ref()
+                                        .and_then(|c| c.ttl_mins)
+                                        .unwrap_or(DEFAULT_EVENT_TTL_MINS)
+                                        * 60,
+                                ),
+                            },
+                            metrics_config: scheduler_config.metrics_store_config.map(|store| {
+                                MetricsAgentConfig {
+                                    store,
+                                    export_interval: Duration::from_secs(
+                                        DEFAULT_METRICS_EXPORT_INTERVAL_SECS as u64,
+                                    ),
+                                }
+                            }),
                             openai_config: scheduler_config.openai_config.map(|c| {
                                 OpenAIAgentConfig {
                                     api_key: c.api_key,
@@ -175,6 +312,7 @@ async fn main() -> Result<()> {
                                 remote_build_ca_cert_path: c.ca_cert_path,
                                 remote_build_ca_key_path: c.ca_key_path,
                                 builders_pool: c.pool,
+                                remote_build_crl_path: c.crl_path,
                             }),
                             tcp_port_range: scheduler_config.proxy_config.tcp_port_range.clone(),
                         },
@@ -195,7 +333,10 @@ async fn main() -> Result<()> {
             SchedulerConfig { worker_count: 4 },
             vec![
                 CertificateController::new_boxed(),
+                DnsRecordController::new_boxed(),
                 MachineController::new_boxed(),
+                MachineGroupController::new_boxed(),
+                CronController::new_boxed(),
                 ServiceController::new_boxed(),
                 VolumeController::new_boxed(),
                 AppController::new_boxed(),
@@ -215,6 +356,8 @@ async fn main() -> Result<()> {
         ApiServerConfig {
             host: config.api_server_config.host.clone(),
             port: config.api_server_config.port,
+            admin_tenants: config.api_server_config.admin_tenants.clone(),
+            daemon_config: config.redacted()?,
         },
     )
     .add_service::<CoreService>()
@@ -228,6 +371,51 @@ async fn main() -> Result<()> {
     scheduler.start_workers();
     scheduler.schedule_bringup().await?;
 
+    let renewal_scan_interval = config
+        .cert_renewal_config
+        .as_ref()
+        .and_then(|c| c.scan_interval_mins)
+        .map(|mins| Duration::from_secs(mins * 60))
+        .unwrap_or(DEFAULT_RENEWAL_SCAN_INTERVAL);
+    start_certificate_renewal(scheduler.clone(), renewal_scan_interval);
+    start_expiry(scheduler.clone(), DEFAULT_EXPIRY_SCAN_INTERVAL);
+    start_health_probes(scheduler.clone(), DEFAULT_HEALTH_PROBE_INTERVAL);
+
+    if let Some(metrics_config) = config.metrics_config.clone() {
+        let metrics_scheduler = scheduler.clone();
+        tokio::spawn(async move {
+            if let Err(e) = start_metrics_server(
+                metrics_scheduler,
+                MetricsServerConfig {
+                    host: metrics_config.host,
+                    port: metrics_config.port,
+                },
+            )
+            .await
+            {
+                warn!("metrics server stopped: {}", e);
+            }
+        });
+    }
+
+    if let Some(ssh_config) = config.proxy_config.ssh_gateway.clone() {
+        let ssh_gateway = SshGateway::new(
+            store.clone(),
+            scheduler.clone(),
+            auth_handler.clone(),
+            SshGatewayConfig {
+                host: config.proxy_config.external_bind_address.clone(),
+                port: ssh_config.port,
+                host_key_path: config.config_dir.join(ssh_config.host_key_path),
+            },
+        );
+        tokio::spawn(async move {
+            if let Err(e) = ssh_gateway.start().await {
+                warn!("ssh gateway stopped: {}", e);
+            }
+        });
+    }
+
     api_server.start().await?;
 
     Ok(())
@@ -4,9 +4,11 @@ use std::collections::BTreeMap;
 
 use crate::resources::{
     Convert, FromResource,
+    condition::{Condition, ObserveConditions},
     machine::{
-        MachineBuild, MachineDependency, MachineMode, MachineResources, MachineRestartPolicy,
-        MachineVolumeBinding,
+        MachineBuild, MachineDependency, MachineEphemeralVolume, MachineHealth, MachineMode,
+        MachineNetwork, MachinePlacement, MachineResources, MachineRestartPolicy, MachineRollout,
+        MachineScratch, MachineSuspendPolicy, MachineVolumeBinding,
     },
     service::{ServiceBindExternalProtocol, ServiceTargetConnectionTracking},
 };
@@ -17,15 +19,33 @@ mod app {
     struct V1 {
         image: Option<String>,
         build: Option<MachineBuild>,
+        #[serde(rename = "build-env")]
+        build_env: Option<BTreeMap<String, String>>,
         resources: MachineResources,
         #[serde(rename = "restart-policy")]
         restart_policy: Option<MachineRestartPolicy>,
         mode: Option<MachineMode>,
         volumes: Option<Vec<MachineVolumeBinding>>,
+        scratch: Option<MachineScratch>,
+        #[serde(rename = "ephemeral-volumes")]
+        ephemeral_volumes: Option<Vec<MachineEphemeralVolume>>,
         command: Option<Vec<String>>,
         environment: Option<BTreeMap<String, String>>,
         #[serde(rename = "depends-on")]
         depends_on: Option<Vec<MachineDependency>>,
+        health: Option<MachineHealth>,
+        /// Extra kernel parameters appended to the boot cmdline, eg. `quiet`, `mitigations=off`
+        /// or module options like `nf_conntrack.hashsize=65536`. Only an allowlisted subset is
+        /// accepted.
+        #[serde(rename = "kernel-params")]
+        kernel_params: Option<Vec<String>>,
+        rollout: Option<MachineRollout>,
+        suspend: Option<MachineSuspendPolicy>,
+        /// Deletes the app, along with its machine and services, once it's been around for
+        /// this long, eg. `3d` for a preview environment.
+        ttl: Option<String>,
+        placement: Option<MachinePlacement>,
+        network: Option<MachineNetwork>,
         expose: Option<BTreeMap<String, AppExpose>>,
     }
 
@@ -55,6 +75,9 @@ mod app {
         machine_hash: u64,
         machine_name: Option<String>,
         allocated_services: BTreeMap<String, AppAllocatedService>,
+        /// Unix millis the TTL started counting from.
+        ttl_started_at: Option<u64>,
+        ttl_warned: Option<bool>,
     }
 
     #[schema]
@@ -73,6 +96,22 @@ impl FromResource<App> for AppStatus {
             machine_hash: 0,
             machine_name: None,
             allocated_services: BTreeMap::new(),
+            ttl_started_at: None,
+            ttl_warned: None,
+            conditions: vec![],
         })
     }
 }
+
+impl ObserveConditions for AppStatus {
+    fn observe_conditions(&self) -> Vec<Condition> {
+        let created = self.machine_name.is_some();
+        let reason = if created {
+            "MachineCreated"
+        } else {
+            "CreatingMachine"
+        };
+
+        Condition::readiness(created, !created, reason, None)
+    }
+}
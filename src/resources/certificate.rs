@@ -1,15 +1,37 @@
 use anyhow::Result;
 use meta::resource;
 
-use crate::resources::{Convert, FromResource};
+use crate::resources::{
+    Convert, FromResource,
+    condition::{Condition, ObserveConditions},
+};
 
 #[resource(name = "Certificate", tag = "certificate")]
 mod certificate {
     #[version(stored + served + latest)]
     struct V1 {
+        /// Names covered by the certificate, eg. `*.customer.example.com` along with
+        /// `customer.example.com`. Wildcards are issued over dns-01.
         #[serde(deserialize_with = "super::de_vec_trim_non_empty_string")]
         domains: Vec<String>,
         issuer: CertificateIssuer,
+        /// Algorithm of the private key generated for the certificate. Default: `ecdsa-p256`.
+        #[serde(rename = "key-algorithm")]
+        key_algorithm: Option<CertificateKeyAlgorithm>,
+    }
+
+    #[schema]
+    enum CertificateKeyAlgorithm {
+        #[serde(rename = "ecdsa-p256")]
+        EcdsaP256,
+        #[serde(rename = "ecdsa-p384")]
+        EcdsaP384,
+        #[serde(rename = "rsa-2048")]
+        Rsa2048,
+        #[serde(rename = "rsa-3072")]
+        Rsa3072,
+        #[serde(rename = "rsa-4096")]
+        Rsa4096,
     }
 
     #[schema]
@@ -46,6 +68,15 @@ mod certificate {
             )]
             ca_path: Option<String>,
         },
+        /// Issued by the internal CA of the tenant, for internal service hostnames like
+        /// `api.default.svc.<zone-suffix>`. The certificate is valid both for serving and as a
+        /// client certificate, for machine to machine mTLS.
+        #[serde(rename = "internal")]
+        Internal {
+            /// Lifetime of the issued certificate, it's renewed after two thirds of it. Default: 24 hours.
+            #[serde(rename = "ttl-hours")]
+            ttl_hours: Option<u32>,
+        },
     }
 
     #[schema]
@@ -64,9 +95,33 @@ mod certificate {
         not_before: Option<String>,
         not_after: Option<String>,
         last_failure_reason: Option<String>,
+        /// RFC 3339 time of the last failure.
+        last_failure_time: Option<String>,
+        /// Attempts to issue the certificate since it was last issued, failed or in flight.
+        renewal_attempts: Option<u32>,
+        /// Issuer of the served certificate, eg. `CN=R11, O=Let's Encrypt, C=US`.
+        issuer_name: Option<String>,
+        /// Names the served certificate is valid for, as parsed from it.
+        subject_alt_names: Option<Vec<String>>,
+        /// Certificates of the served chain, leaf first.
+        chain: Option<Vec<CertificateChainEntry>>,
         renewal_time: Option<String>,
+        /// Set while a renewal is in progress, the previous certificate is served meanwhile.
+        renewing_since: Option<String>,
         domains: Vec<String>,
         auto_provider_name: Option<String>,
+        /// Algorithm of the key of the issued certificate.
+        key_algorithm: Option<CertificateKeyAlgorithm>,
+    }
+
+    #[schema]
+    struct CertificateChainEntry {
+        subject: String,
+        issuer: String,
+        /// Hex encoded
+        serial: String,
+        not_before: String,
+        not_after: String,
     }
 
     #[schema]
@@ -117,12 +172,60 @@ impl FromResource<Certificate> for CertificateStatus {
             not_before: None,
             not_after: None,
             last_failure_reason: None,
+            last_failure_time: None,
+            renewal_attempts: None,
+            issuer_name: None,
+            subject_alt_names: None,
+            chain: None,
             renewal_time: None,
+            renewing_since: None,
             domains: certificate.domains,
             auto_provider_name: match certificate.issuer {
                 CertificateIssuer::Auto { provider, .. } => Some(provider),
                 _ => None,
             },
+            key_algorithm: None,
+            conditions: vec![],
         })
     }
 }
+
+impl ObserveConditions for CertificateStatus {
+    fn observe_conditions(&self) -> Vec<Condition> {
+        let reason = match &self.state {
+            CertificateState::Failed if self.renewing_since.is_some() => "RenewalFailed",
+            CertificateState::Pending => "Pending",
+            CertificateState::PendingAcmeAccount => "PendingAcmeAccount",
+            CertificateState::PendingDnsResolution => "PendingDnsResolution",
+            CertificateState::PendingOrder(_) => "PendingOrder",
+            CertificateState::PendingChallenge(_) => "PendingChallenge",
+            CertificateState::Validating(_) => "Validating",
+            CertificateState::Issuing(_) => "Issuing",
+            CertificateState::Ready => "Ready",
+            CertificateState::Renewing => "Renewing",
+            CertificateState::Failed => "Failed",
+            CertificateState::Expired => "Expired",
+            CertificateState::Revoked => "Revoked",
+        };
+        // a renewing certificate keeps serving the previous one
+        let renewing = self.renewing_since.is_some();
+        let ready = renewing
+            || matches!(
+                self.state,
+                CertificateState::Ready | CertificateState::Renewing
+            );
+        let progressing = !matches!(
+            self.state,
+            CertificateState::Ready
+                | CertificateState::Failed
+                | CertificateState::Expired
+                | CertificateState::Revoked
+        );
+        let message = match self.state {
+            CertificateState::Failed => self.last_failure_reason.clone(),
+            _ => None,
+        };
+
+        Condition::readiness(ready, progressing, reason, message)
+    }
+}
@@ -0,0 +1,189 @@
+use std::fmt;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Kinds of conditions reported on resource statuses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+pub enum ConditionType {
+    /// The resource is fully reconciled and usable.
+    Ready,
+    /// The resource is being worked on, eg. a machine booting or a certificate being issued.
+    Progressing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+pub enum ConditionStatus {
+    True,
+    False,
+    Unknown,
+}
+
+/// A condition of a resource status. Every resource status carries a list of them, so readiness
+/// reads the same way whatever the kind of resource.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Condition {
+    #[serde(rename = "type")]
+    pub condition_type: ConditionType,
+    pub status: ConditionStatus,
+    /// Short machine readable cause of the status, eg. `Booting`.
+    pub reason: String,
+    pub message: Option<String>,
+    /// RFC 3339 time of the last change of `status`.
+    pub last_transition_time: String,
+}
+
+impl Condition {
+    pub fn new(
+        condition_type: ConditionType,
+        status: ConditionStatus,
+        reason: impl AsRef<str>,
+        message: Option<String>,
+    ) -> Self {
+        Self {
+            condition_type,
+            status,
+            reason: reason.as_ref().to_string(),
+            message,
+            last_transition_time: String::new(),
+        }
+    }
+
+    /// `Ready` when `ready` holds, along with `Progressing` while work is in flight.
+    pub fn readiness(
+        ready: bool,
+        progressing: bool,
+        reason: impl AsRef<str>,
+        message: Option<String>,
+    ) -> Vec<Self> {
+        vec![
+            Self::new(
+                ConditionType::Ready,
+                ConditionStatus::from(ready),
+                reason.as_ref(),
+                message,
+            ),
+            Self::new(
+                ConditionType::Progressing,
+                ConditionStatus::from(progressing),
+                reason,
+                None,
+            ),
+        ]
+    }
+
+    pub fn is_true(&self) -> bool {
+        self.status == ConditionStatus::True
+    }
+}
+
+impl From<bool> for ConditionStatus {
+    fn from(value: bool) -> Self {
+        if value {
+            ConditionStatus::True
+        } else {
+            ConditionStatus::False
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?}={:?} ({}) since {}",
+            self.condition_type, self.status, self.reason, self.last_transition_time
+        )?;
+        if let Some(message) = &self.message {
+            write!(f, ": {}", message)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Access to the `conditions` of a status, generated for every resource status.
+pub trait ProvideConditions {
+    fn conditions(&self) -> &[Condition];
+    fn set_conditions(&mut self, conditions: Vec<Condition>);
+
+    fn condition(&self, condition_type: ConditionType) -> Option<&Condition> {
+        self.conditions()
+            .iter()
+            .find(|condition| condition.condition_type == condition_type)
+    }
+
+    fn is_ready(&self) -> bool {
+        self.condition(ConditionType::Ready)
+            .is_some_and(|condition| condition.is_true())
+    }
+}
+
+/// Conditions derived from the other fields of a status, implemented by every resource status.
+pub trait ObserveConditions: ProvideConditions {
+    /// Current conditions, transition times are filled in by `refresh_conditions`.
+    fn observe_conditions(&self) -> Vec<Condition>;
+
+    fn refresh_conditions(&mut self, previous: &[Condition], now: &str) {
+        let conditions = merge_conditions(previous, self.observe_conditions(), now);
+        self.set_conditions(conditions);
+    }
+}
+
+/// Stamps `observed` conditions with their transition time, kept from `previous` for the ones
+/// whose status didn't change.
+pub fn merge_conditions(
+    previous: &[Condition],
+    observed: Vec<Condition>,
+    now: &str,
+) -> Vec<Condition> {
+    observed
+        .into_iter()
+        .map(|mut condition| {
+            condition.last_transition_time = previous
+                .iter()
+                .find(|prev| {
+                    prev.condition_type == condition.condition_type
+                        && prev.status == condition.status
+                })
+                .map(|prev| prev.last_transition_time.clone())
+                .unwrap_or_else(|| now.to_string());
+            condition
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_conditions() {
+        let previous = merge_conditions(
+            &[],
+            Condition::readiness(false, true, "Booting", None),
+            "2025-01-01T00:00:00+00:00",
+        );
+        assert!(
+            previous
+                .iter()
+                .all(|c| c.last_transition_time == "2025-01-01T00:00:00+00:00")
+        );
+
+        let conditions = merge_conditions(
+            &previous,
+            Condition::readiness(true, true, "Ready", None),
+            "2025-01-01T00:01:00+00:00",
+        );
+        assert_eq!(conditions[0].condition_type, ConditionType::Ready);
+        assert_eq!(
+            conditions[0].last_transition_time,
+            "2025-01-01T00:01:00+00:00"
+        );
+        assert_eq!(
+            conditions[1].last_transition_time,
+            "2025-01-01T00:00:00+00:00"
+        );
+    }
+}
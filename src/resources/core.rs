@@ -73,6 +73,47 @@ impl FromStr for LogStreamTarget {
     }
 }
 
+/// Severity of a log line, as detected by the log store.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum LogLevel {
+    #[serde(rename = "debug")]
+    Debug,
+    #[serde(rename = "info")]
+    Info,
+    #[serde(rename = "warn")]
+    Warn,
+    #[serde(rename = "error")]
+    Error,
+}
+
+impl FromStr for LogLevel {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let level = match s {
+            "debug" => LogLevel::Debug,
+            "info" => LogLevel::Info,
+            "warn" | "warning" => LogLevel::Warn,
+            "error" => LogLevel::Error,
+            _ => bail!("Invalid log level: {}", s),
+        };
+
+        Ok(level)
+    }
+}
+
+/// Narrows the lines returned by a log query, applied by the log store.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct LogFilter {
+    /// Only lines written to this stream.
+    pub stream: Option<LogStreamTarget>,
+    /// Only lines containing this text.
+    pub contains: Option<String>,
+    /// Only lines matching this regular expression.
+    pub regex: Option<String>,
+    /// Only lines at this level or above.
+    pub level: Option<LogLevel>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct LogStreamItem {
     pub timestamp: u64,
@@ -87,11 +128,22 @@ pub enum LogStreamParams {
         machine_name: String,
         start_ts_ns: Option<String>,
         end_ts_ns: Option<String>,
+        #[serde(flatten)]
+        filter: LogFilter,
     },
     Group {
         group_name: String,
         start_ts_ns: Option<String>,
         end_ts_ns: Option<String>,
+        #[serde(flatten)]
+        filter: LogFilter,
+    },
+    Service {
+        service_name: String,
+        start_ts_ns: Option<String>,
+        end_ts_ns: Option<String>,
+        #[serde(flatten)]
+        filter: LogFilter,
     },
 }
 
@@ -103,6 +155,149 @@ pub struct ExecParams {
     pub tty: Option<bool>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ConsoleParams {
+    pub machine_name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ExecSession {
+    pub id: String,
+    pub sub: String,
+    pub namespace: String,
+    pub machine_name: String,
+    pub command: String,
+    pub stdin: bool,
+    pub tty: bool,
+    pub started_at: u64,
+    pub ended_at: Option<u64>,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum AuditAction {
+    #[serde(rename = "create")]
+    Create,
+    #[serde(rename = "update")]
+    Update,
+    #[serde(rename = "delete")]
+    Delete,
+}
+
+/// A field changed by a mutation, `before` is not set for added fields and `after` for removed ones.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AuditChange {
+    /// Dotted path of the field, `spec.image` for instance.
+    pub path: String,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+/// A create, update or delete of a resource performed through the API.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AuditEvent {
+    pub id: String,
+    pub sub: String,
+    /// Not set for resources that are not namespaced.
+    pub namespace: Option<String>,
+    /// Tag of the resource, `machine` for instance.
+    pub kind: String,
+    pub name: String,
+    pub action: AuditAction,
+    pub changes: Vec<AuditChange>,
+    /// Unix millis
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct AuditLogParams {
+    pub kind: Option<String>,
+    pub name: Option<String>,
+    pub sub: Option<String>,
+    /// Unix millis
+    pub since: Option<u64>,
+    /// Maximum number of events to return, most recent first.
+    pub limit: Option<u32>,
+}
+
+/// Why an event was recorded for a resource.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum EventReason {
+    ImagePulled,
+    ImagePullFailed,
+    MachineStarted,
+    MachineSuspended,
+    MachineStopped,
+    MachineRestarting,
+    MachineFailed,
+    RolloutStarted,
+    RolloutCompleted,
+    RolloutFailed,
+    CronRunStarted,
+    CronRunSucceeded,
+    CronRunFailed,
+    Expiring,
+    Expired,
+    Unschedulable,
+    CertIssued,
+    CertRenewed,
+    CertIssueFailed,
+    CertRenewFailed,
+}
+
+impl EventReason {
+    pub fn is_warning(&self) -> bool {
+        matches!(
+            self,
+            EventReason::ImagePullFailed
+                | EventReason::MachineFailed
+                | EventReason::RolloutFailed
+                | EventReason::CronRunFailed
+                | EventReason::Expiring
+                | EventReason::Unschedulable
+                | EventReason::CertIssueFailed
+                | EventReason::CertRenewFailed
+        )
+    }
+}
+
+/// Something that happened to a resource, kept for a while after it was last seen.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct ResourceEvent {
+    pub reason: EventReason,
+    pub message: String,
+    /// Times the same reason and message were recorded in a row.
+    pub count: u32,
+    /// Unix millis
+    pub first_seen: u64,
+    /// Unix millis
+    pub last_seen: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ListEventsParams {
+    /// Tag of the resource, `machine` for instance.
+    pub kind: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CoreDump {
+    pub id: String,
+    pub machine_name: String,
+    pub created_at: u64,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DownloadCoreDumpParams {
+    pub namespace: Option<String>,
+    pub machine_name: String,
+    pub id: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct QueryParams {
     pub query: String,
@@ -129,12 +324,303 @@ pub struct QueryResponse {
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct AllocatedBuilder {
+    pub allocation_id: String,
     pub host: String,
     pub client_cert_pem: String,
     pub client_key_pem: String,
     pub ca_cert_pem: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ReleaseBuilderParams {
+    pub allocation_id: String,
+}
+
+/// Daemon state collected for bug reports, secrets are redacted before it leaves the daemon.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SupportBundle {
+    pub generated_at: u64,
+    pub version: String,
+    pub compat_version: String,
+    pub config: Value,
+    pub scheduler: SchedulerStats,
+    pub recent_events: Vec<SchedulerEvent>,
+    pub machines: Vec<SupportBundleMachine>,
+    pub proxy_bindings: Vec<SupportBundleProxyBinding>,
+    pub proxy_metrics: String,
+    pub last_gc_report: Option<GcReport>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SchedulerStats {
+    pub worker_count: usize,
+    pub queued_keys: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SchedulerEvent {
+    pub at: u64,
+    pub key: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SupportBundleMachine {
+    pub name: String,
+    pub state: String,
+    pub network_tag: String,
+    pub last_boot_duration_ms: Option<u64>,
+    pub last_exit_code: Option<i32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SupportBundleProxyBinding {
+    pub name: String,
+    pub target_network_tag: String,
+    pub target_port: u16,
+    pub mode: String,
+    pub service: Option<String>,
+}
+
+/// Bytes proxied for the services of the tenant, as counted by the proxy.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BandwidthUsage {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub services: Vec<ServiceBandwidthUsage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ServiceBandwidthUsage {
+    pub namespace: String,
+    pub service: String,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub updated_at: u64,
+}
+
+/// Root of the internal CA of the tenant, to be trusted inside images calling internal services.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct InternalCaRoot {
+    /// Along with the root it replaced for a while after a rotation.
+    pub cert_pem: String,
+    /// Unix millis
+    pub not_after: u64,
+}
+
+/// Certificate issued by the internal CA, along with its private key.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct InternalCertificateBundle {
+    pub name: String,
+    pub domains: Vec<String>,
+    pub cert_pem: String,
+    pub key_pem: String,
+    pub ca_cert_pem: String,
+    /// Unix millis
+    pub not_before: u64,
+    /// Unix millis
+    pub not_after: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MetricSample {
+    /// Unix seconds
+    pub timestamp: u64,
+    pub value: f64,
+}
+
+/// Resource usage of a machine over one sampling interval, measured by its agent.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MachineUsageSample {
+    /// Unix seconds
+    pub timestamp: u64,
+    /// Share of the vCPU time spent busy, from 0 to 1.
+    pub cpu_utilization: f64,
+    /// Guest memory resident on the host.
+    pub memory_resident_bytes: u64,
+    pub network_rx_bytes_per_sec: f64,
+    pub network_tx_bytes_per_sec: f64,
+    pub disk_read_bytes_per_sec: f64,
+    pub disk_write_bytes_per_sec: f64,
+}
+
+/// Latest and rolling resource usage of a machine, empty until it's been sampled twice.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MachineUsage {
+    pub name: String,
+    pub memory_total_bytes: u64,
+    pub current: Option<MachineUsageSample>,
+    /// Average of the samples, over `window_secs`.
+    pub average: Option<MachineUsageSample>,
+    pub window_secs: u64,
+    pub samples: Vec<MachineUsageSample>,
+    /// CPU utilization the guest exported to the metrics store in the last 15 minutes, empty
+    /// without a metrics store.
+    #[serde(default)]
+    pub cpu_history: Vec<MetricSample>,
+    /// Memory the guest reported using, from the metrics store as well.
+    #[serde(default)]
+    pub memory_used_history: Vec<MetricSample>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ImageLayerProgress {
+    pub digest: String,
+    pub size_bytes: u64,
+    pub downloaded_bytes: u64,
+    pub unpacked: bool,
+}
+
+/// Progress of the image pull of a machine, without layers when no pull is in progress.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ImagePullProgress {
+    pub reference: Option<String>,
+    pub layers: Vec<ImageLayerProgress>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ImageLoadParams {
+    pub reference: String,
+}
+
+/// Sent back once the archive streamed to `load_image` is loaded.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ImageLoadResult {
+    pub reference: String,
+    pub digest: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ImageInspectParams {
+    pub reference: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ImageInspectionLayer {
+    pub digest: String,
+    pub media_type: String,
+    pub size_bytes: u64,
+}
+
+/// What a machine running an image gets: the manifest and config the reference resolves to, and
+/// the image and volume stored for it once pulled.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ImageInspection {
+    pub reference: String,
+    pub digest: String,
+    pub entrypoint: Option<Vec<String>>,
+    pub cmd: Option<Vec<String>>,
+    pub env: Vec<String>,
+    pub exposed_ports: Vec<String>,
+    pub user: Option<String>,
+    pub working_dir: Option<String>,
+    pub labels: BTreeMap<String, String>,
+    pub annotations: BTreeMap<String, String>,
+    pub layers: Vec<ImageInspectionLayer>,
+    /// Not set until the image is pulled.
+    pub image_id: Option<String>,
+    pub pulled_at: Option<u64>,
+    pub volume_id: Option<String>,
+    pub volume_size_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ImageGcRemoval {
+    pub image_id: String,
+    pub reference: String,
+    pub digest: String,
+    pub pulled_at: u64,
+    pub size_bytes: u64,
+    /// `superseded` or `disk-usage`.
+    pub reason: String,
+}
+
+/// Images an image collection removed, or would remove on a dry run.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ImageGcReport {
+    pub ran_at: u64,
+    pub dry_run: bool,
+    /// Whether the daemon collects images periodically.
+    pub enabled: bool,
+    pub disk_usage_bytes: u64,
+    pub removed: Vec<ImageGcRemoval>,
+    pub removed_layers: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct VolumeResizeParams {
+    pub namespace: Option<String>,
+    pub name: String,
+    /// The new size of the volume in human readable format, larger than the current one
+    pub size: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct VolumeResize {
+    pub name: String,
+    pub size_bytes: u64,
+    /// Running machines told about the new size, the others see it when they boot.
+    pub machines: Vec<String>,
+}
+
+/// Volume space of the tenant, provisioned being the sizes of its volumes and used what they
+/// take on disk.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct VolumeUsage {
+    pub provisioned_bytes: u64,
+    pub used_bytes: u64,
+    /// Unlimited when not set.
+    pub quota_bytes: Option<u64>,
+    pub volumes: Vec<VolumeUsageEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct VolumeUsageEntry {
+    pub namespace: String,
+    pub name: String,
+    pub provisioned_bytes: u64,
+    pub used_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct VolumeQuotaParams {
+    pub tenant: String,
+    /// Human readable size or `unlimited`, the daemon default when not set
+    pub quota: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct VolumeBackup {
+    /// UTC time the backup was taken at, eg. 20250101T030000Z
+    pub id: String,
+    pub created_at: u64,
+    /// Compressed size of the backup.
+    pub size_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct VolumeRestoreParams {
+    pub namespace: Option<String>,
+    pub name: String,
+    /// Id of the backup to restore, the latest one when not set
+    pub backup: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct VolumeRestore {
+    pub name: String,
+    /// Id of the restored backup.
+    pub backup: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GcReport {
+    pub ran_at: u64,
+    pub reclaimed_allocations: usize,
+    pub expired_revocations: usize,
+    pub error: Option<String>,
+}
+
 pub fn core_api_service() -> ApiService {
     ApiService {
         name: "Core".to_string(),
@@ -299,69 +785,580 @@ pub fn core_api_service() -> ApiService {
                 response: Some(crate::machinery::api_schema::ApiResponse::RawSocket),
             },
             ApiMethod {
-                name: "query".to_string(),
+                name: "console".to_string(),
                 path: vec![
                     ApiPathSegment::Static {
                         value: "core".to_string(),
                     },
                     ApiPathSegment::Static {
-                        value: "query".to_string(),
+                        value: "console".to_string(),
                     },
                 ],
-                namespaced: false,
-                verb: ApiVerb::Put,
+                namespaced: true,
+                verb: ApiVerb::WebSocket,
                 request: Some(crate::machinery::api_schema::ApiRequest::SchemaDefinition {
-                    name: "QueryParams".to_string(),
+                    name: "ConsoleParams".to_string(),
                 }),
+                response: Some(crate::machinery::api_schema::ApiResponse::RawSocket),
+            },
+            ApiMethod {
+                name: "exec_history".to_string(),
+                path: vec![
+                    ApiPathSegment::Static {
+                        value: "core".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "exec".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "history".to_string(),
+                    },
+                ],
+                namespaced: true,
+                verb: ApiVerb::Get,
+                request: None,
                 response: Some(
                     crate::machinery::api_schema::ApiResponse::SchemaDefinition {
-                        list: false,
+                        list: true,
                         optional: false,
-                        name: "QueryResponse".to_string(),
+                        name: "ExecSession".to_string(),
                     },
                 ),
             },
             ApiMethod {
-                name: "alloc_builder".to_string(),
+                name: "audit_log".to_string(),
                 path: vec![
                     ApiPathSegment::Static {
                         value: "core".to_string(),
                     },
                     ApiPathSegment::Static {
-                        value: "build".to_string(),
+                        value: "audit".to_string(),
+                    },
+                ],
+                namespaced: true,
+                verb: ApiVerb::Put,
+                request: Some(crate::machinery::api_schema::ApiRequest::SchemaDefinition {
+                    name: "AuditLogParams".to_string(),
+                }),
+                response: Some(
+                    crate::machinery::api_schema::ApiResponse::SchemaDefinition {
+                        list: true,
+                        optional: false,
+                        name: "AuditEvent".to_string(),
+                    },
+                ),
+            },
+            ApiMethod {
+                name: "list_events".to_string(),
+                path: vec![
+                    ApiPathSegment::Static {
+                        value: "core".to_string(),
                     },
                     ApiPathSegment::Static {
-                        value: "alloc".to_string(),
+                        value: "events".to_string(),
                     },
                 ],
-                namespaced: false,
+                namespaced: true,
                 verb: ApiVerb::Put,
+                request: Some(crate::machinery::api_schema::ApiRequest::SchemaDefinition {
+                    name: "ListEventsParams".to_string(),
+                }),
+                response: Some(
+                    crate::machinery::api_schema::ApiResponse::SchemaDefinition {
+                        list: true,
+                        optional: false,
+                        name: "ResourceEvent".to_string(),
+                    },
+                ),
+            },
+            ApiMethod {
+                name: "list_core_dumps".to_string(),
+                path: vec![
+                    ApiPathSegment::Static {
+                        value: "core".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "machine".to_string(),
+                    },
+                    ApiPathSegment::ResourceName,
+                    ApiPathSegment::Static {
+                        value: "cores".to_string(),
+                    },
+                ],
+                namespaced: true,
+                verb: ApiVerb::Get,
                 request: None,
                 response: Some(
                     crate::machinery::api_schema::ApiResponse::SchemaDefinition {
-                        name: "AllocatedBuilder".to_string(),
-                        list: false,
+                        list: true,
                         optional: false,
+                        name: "CoreDump".to_string(),
                     },
                 ),
             },
-        ],
-    }
-}
-
-pub fn add_core_service_schema_defs(
-    _schema_generator: &mut SchemaGenerator,
-    defs: &mut Map<String, Value>,
-) -> Result<()> {
-    defs.insert("Me".to_string(), schema_for!(Me).into());
-    defs.insert("Namespace".to_string(), schema_for!(Namespace).into());
-    defs.insert(
-        "ListNamespaces".to_string(),
-        schema_for!(ListNamespaces).into(),
-    );
-    defs.insert(
-        "DeleteNamespaceParams".to_string(),
-        schema_for!(DeleteNamespaceParams).into(),
+            ApiMethod {
+                name: "machine_usage".to_string(),
+                path: vec![
+                    ApiPathSegment::Static {
+                        value: "core".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "machine".to_string(),
+                    },
+                    ApiPathSegment::ResourceName,
+                    ApiPathSegment::Static {
+                        value: "usage".to_string(),
+                    },
+                ],
+                namespaced: true,
+                verb: ApiVerb::Get,
+                request: None,
+                response: Some(
+                    crate::machinery::api_schema::ApiResponse::SchemaDefinition {
+                        list: false,
+                        optional: false,
+                        name: "MachineUsage".to_string(),
+                    },
+                ),
+            },
+            ApiMethod {
+                name: "image_pull_progress".to_string(),
+                path: vec![
+                    ApiPathSegment::Static {
+                        value: "core".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "machine".to_string(),
+                    },
+                    ApiPathSegment::ResourceName,
+                    ApiPathSegment::Static {
+                        value: "pull".to_string(),
+                    },
+                ],
+                namespaced: true,
+                verb: ApiVerb::Get,
+                request: None,
+                response: Some(
+                    crate::machinery::api_schema::ApiResponse::SchemaDefinition {
+                        list: false,
+                        optional: false,
+                        name: "ImagePullProgress".to_string(),
+                    },
+                ),
+            },
+            ApiMethod {
+                name: "load_image".to_string(),
+                path: vec![
+                    ApiPathSegment::Static {
+                        value: "core".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "images".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "load".to_string(),
+                    },
+                ],
+                namespaced: false,
+                verb: ApiVerb::WebSocket,
+                request: Some(crate::machinery::api_schema::ApiRequest::SchemaDefinition {
+                    name: "ImageLoadParams".to_string(),
+                }),
+                response: Some(crate::machinery::api_schema::ApiResponse::RawSocket),
+            },
+            ApiMethod {
+                name: "inspect_image".to_string(),
+                path: vec![
+                    ApiPathSegment::Static {
+                        value: "core".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "images".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "inspect".to_string(),
+                    },
+                ],
+                namespaced: false,
+                verb: ApiVerb::Put,
+                request: Some(crate::machinery::api_schema::ApiRequest::SchemaDefinition {
+                    name: "ImageInspectParams".to_string(),
+                }),
+                response: Some(
+                    crate::machinery::api_schema::ApiResponse::SchemaDefinition {
+                        list: false,
+                        optional: false,
+                        name: "ImageInspection".to_string(),
+                    },
+                ),
+            },
+            ApiMethod {
+                name: "download_core_dump".to_string(),
+                path: vec![
+                    ApiPathSegment::Static {
+                        value: "core".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "cores".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "download".to_string(),
+                    },
+                ],
+                namespaced: false,
+                verb: ApiVerb::WebSocket,
+                request: Some(crate::machinery::api_schema::ApiRequest::SchemaDefinition {
+                    name: "DownloadCoreDumpParams".to_string(),
+                }),
+                response: Some(crate::machinery::api_schema::ApiResponse::RawSocket),
+            },
+            ApiMethod {
+                name: "query".to_string(),
+                path: vec![
+                    ApiPathSegment::Static {
+                        value: "core".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "query".to_string(),
+                    },
+                ],
+                namespaced: false,
+                verb: ApiVerb::Put,
+                request: Some(crate::machinery::api_schema::ApiRequest::SchemaDefinition {
+                    name: "QueryParams".to_string(),
+                }),
+                response: Some(
+                    crate::machinery::api_schema::ApiResponse::SchemaDefinition {
+                        list: false,
+                        optional: false,
+                        name: "QueryResponse".to_string(),
+                    },
+                ),
+            },
+            ApiMethod {
+                name: "alloc_builder".to_string(),
+                path: vec![
+                    ApiPathSegment::Static {
+                        value: "core".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "build".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "alloc".to_string(),
+                    },
+                ],
+                namespaced: false,
+                verb: ApiVerb::Put,
+                request: None,
+                response: Some(
+                    crate::machinery::api_schema::ApiResponse::SchemaDefinition {
+                        name: "AllocatedBuilder".to_string(),
+                        list: false,
+                        optional: false,
+                    },
+                ),
+            },
+            ApiMethod {
+                name: "release_builder".to_string(),
+                path: vec![
+                    ApiPathSegment::Static {
+                        value: "core".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "build".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "release".to_string(),
+                    },
+                ],
+                namespaced: false,
+                verb: ApiVerb::Put,
+                request: Some(crate::machinery::api_schema::ApiRequest::SchemaDefinition {
+                    name: "ReleaseBuilderParams".to_string(),
+                }),
+                response: None,
+            },
+            ApiMethod {
+                name: "support_bundle".to_string(),
+                path: vec![
+                    ApiPathSegment::Static {
+                        value: "core".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "admin".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "support-bundle".to_string(),
+                    },
+                ],
+                namespaced: false,
+                verb: ApiVerb::Get,
+                request: None,
+                response: Some(
+                    crate::machinery::api_schema::ApiResponse::SchemaDefinition {
+                        name: "SupportBundle".to_string(),
+                        list: false,
+                        optional: false,
+                    },
+                ),
+            },
+            ApiMethod {
+                name: "resize_volume".to_string(),
+                path: vec![
+                    ApiPathSegment::Static {
+                        value: "core".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "volumes".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "resize".to_string(),
+                    },
+                ],
+                namespaced: false,
+                verb: ApiVerb::Put,
+                request: Some(crate::machinery::api_schema::ApiRequest::SchemaDefinition {
+                    name: "VolumeResizeParams".to_string(),
+                }),
+                response: Some(
+                    crate::machinery::api_schema::ApiResponse::SchemaDefinition {
+                        list: false,
+                        optional: false,
+                        name: "VolumeResize".to_string(),
+                    },
+                ),
+            },
+            ApiMethod {
+                name: "list_volume_backups".to_string(),
+                path: vec![
+                    ApiPathSegment::Static {
+                        value: "core".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "volume".to_string(),
+                    },
+                    ApiPathSegment::ResourceName,
+                    ApiPathSegment::Static {
+                        value: "backups".to_string(),
+                    },
+                ],
+                namespaced: true,
+                verb: ApiVerb::Get,
+                request: None,
+                response: Some(
+                    crate::machinery::api_schema::ApiResponse::SchemaDefinition {
+                        list: true,
+                        optional: false,
+                        name: "VolumeBackup".to_string(),
+                    },
+                ),
+            },
+            ApiMethod {
+                name: "restore_volume".to_string(),
+                path: vec![
+                    ApiPathSegment::Static {
+                        value: "core".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "volumes".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "restore".to_string(),
+                    },
+                ],
+                namespaced: false,
+                verb: ApiVerb::Put,
+                request: Some(crate::machinery::api_schema::ApiRequest::SchemaDefinition {
+                    name: "VolumeRestoreParams".to_string(),
+                }),
+                response: Some(
+                    crate::machinery::api_schema::ApiResponse::SchemaDefinition {
+                        list: false,
+                        optional: false,
+                        name: "VolumeRestore".to_string(),
+                    },
+                ),
+            },
+            ApiMethod {
+                name: "image_gc_dry_run".to_string(),
+                path: vec![
+                    ApiPathSegment::Static {
+                        value: "core".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "admin".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "image-gc".to_string(),
+                    },
+                ],
+                namespaced: false,
+                verb: ApiVerb::Get,
+                request: None,
+                response: Some(
+                    crate::machinery::api_schema::ApiResponse::SchemaDefinition {
+                        name: "ImageGcReport".to_string(),
+                        list: false,
+                        optional: false,
+                    },
+                ),
+            },
+            ApiMethod {
+                name: "bandwidth_usage".to_string(),
+                path: vec![
+                    ApiPathSegment::Static {
+                        value: "core".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "usage".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "bandwidth".to_string(),
+                    },
+                ],
+                namespaced: false,
+                verb: ApiVerb::Get,
+                request: None,
+                response: Some(
+                    crate::machinery::api_schema::ApiResponse::SchemaDefinition {
+                        name: "BandwidthUsage".to_string(),
+                        list: false,
+                        optional: false,
+                    },
+                ),
+            },
+            ApiMethod {
+                name: "volume_usage".to_string(),
+                path: vec![
+                    ApiPathSegment::Static {
+                        value: "core".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "usage".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "volumes".to_string(),
+                    },
+                ],
+                namespaced: false,
+                verb: ApiVerb::Get,
+                request: None,
+                response: Some(
+                    crate::machinery::api_schema::ApiResponse::SchemaDefinition {
+                        name: "VolumeUsage".to_string(),
+                        list: false,
+                        optional: false,
+                    },
+                ),
+            },
+            ApiMethod {
+                name: "set_volume_quota".to_string(),
+                path: vec![
+                    ApiPathSegment::Static {
+                        value: "core".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "admin".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "volume-quota".to_string(),
+                    },
+                ],
+                namespaced: false,
+                verb: ApiVerb::Put,
+                request: Some(crate::machinery::api_schema::ApiRequest::SchemaDefinition {
+                    name: "VolumeQuotaParams".to_string(),
+                }),
+                response: None,
+            },
+            ApiMethod {
+                name: "get_internal_ca".to_string(),
+                path: vec![
+                    ApiPathSegment::Static {
+                        value: "core".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "ca".to_string(),
+                    },
+                ],
+                namespaced: false,
+                verb: ApiVerb::Get,
+                request: None,
+                response: Some(
+                    crate::machinery::api_schema::ApiResponse::SchemaDefinition {
+                        name: "InternalCaRoot".to_string(),
+                        list: false,
+                        optional: false,
+                    },
+                ),
+            },
+            ApiMethod {
+                name: "rotate_internal_ca".to_string(),
+                path: vec![
+                    ApiPathSegment::Static {
+                        value: "core".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "ca".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "rotate".to_string(),
+                    },
+                ],
+                namespaced: false,
+                verb: ApiVerb::Put,
+                request: None,
+                response: Some(
+                    crate::machinery::api_schema::ApiResponse::SchemaDefinition {
+                        name: "InternalCaRoot".to_string(),
+                        list: false,
+                        optional: false,
+                    },
+                ),
+            },
+            ApiMethod {
+                name: "get_internal_certificate".to_string(),
+                path: vec![
+                    ApiPathSegment::Static {
+                        value: "core".to_string(),
+                    },
+                    ApiPathSegment::Static {
+                        value: "certificate".to_string(),
+                    },
+                    ApiPathSegment::ResourceName,
+                    ApiPathSegment::Static {
+                        value: "bundle".to_string(),
+                    },
+                ],
+                namespaced: true,
+                verb: ApiVerb::Get,
+                request: None,
+                response: Some(
+                    crate::machinery::api_schema::ApiResponse::SchemaDefinition {
+                        name: "InternalCertificateBundle".to_string(),
+                        list: false,
+                        optional: false,
+                    },
+                ),
+            },
+        ],
+    }
+}
+
+pub fn add_core_service_schema_defs(
+    _schema_generator: &mut SchemaGenerator,
+    defs: &mut Map<String, Value>,
+) -> Result<()> {
+    defs.insert("Me".to_string(), schema_for!(Me).into());
+    defs.insert("Namespace".to_string(), schema_for!(Namespace).into());
+    defs.insert(
+        "ListNamespaces".to_string(),
+        schema_for!(ListNamespaces).into(),
+    );
+    defs.insert(
+        "DeleteNamespaceParams".to_string(),
+        schema_for!(DeleteNamespaceParams).into(),
     );
     defs.insert(
         "DeleteNamespaceResponse".to_string(),
@@ -380,6 +1377,30 @@ pub fn add_core_service_schema_defs(
         schema_for!(LogStreamParams).into(),
     );
     defs.insert("ExecParams".to_string(), schema_for!(ExecParams).into());
+    defs.insert("ExecSession".to_string(), schema_for!(ExecSession).into());
+    defs.insert(
+        "ConsoleParams".to_string(),
+        schema_for!(ConsoleParams).into(),
+    );
+    defs.insert("AuditChange".to_string(), schema_for!(AuditChange).into());
+    defs.insert("AuditEvent".to_string(), schema_for!(AuditEvent).into());
+    defs.insert(
+        "AuditLogParams".to_string(),
+        schema_for!(AuditLogParams).into(),
+    );
+    defs.insert(
+        "ResourceEvent".to_string(),
+        schema_for!(ResourceEvent).into(),
+    );
+    defs.insert(
+        "ListEventsParams".to_string(),
+        schema_for!(ListEventsParams).into(),
+    );
+    defs.insert("CoreDump".to_string(), schema_for!(CoreDump).into());
+    defs.insert(
+        "DownloadCoreDumpParams".to_string(),
+        schema_for!(DownloadCoreDumpParams).into(),
+    );
     defs.insert("QueryParams".to_string(), schema_for!(QueryParams).into());
     defs.insert(
         "QueryResponse".to_string(),
@@ -389,6 +1410,91 @@ pub fn add_core_service_schema_defs(
         "AllocatedBuilder".to_string(),
         schema_for!(AllocatedBuilder).into(),
     );
+    defs.insert(
+        "ReleaseBuilderParams".to_string(),
+        schema_for!(ReleaseBuilderParams).into(),
+    );
+    defs.insert(
+        "SupportBundle".to_string(),
+        schema_for!(SupportBundle).into(),
+    );
+    defs.insert(
+        "BandwidthUsage".to_string(),
+        schema_for!(BandwidthUsage).into(),
+    );
+    defs.insert(
+        "InternalCaRoot".to_string(),
+        schema_for!(InternalCaRoot).into(),
+    );
+    defs.insert(
+        "InternalCertificateBundle".to_string(),
+        schema_for!(InternalCertificateBundle).into(),
+    );
+    defs.insert("MetricSample".to_string(), schema_for!(MetricSample).into());
+    defs.insert(
+        "MachineUsageSample".to_string(),
+        schema_for!(MachineUsageSample).into(),
+    );
+    defs.insert("MachineUsage".to_string(), schema_for!(MachineUsage).into());
+    defs.insert(
+        "ImageLayerProgress".to_string(),
+        schema_for!(ImageLayerProgress).into(),
+    );
+    defs.insert(
+        "ImagePullProgress".to_string(),
+        schema_for!(ImagePullProgress).into(),
+    );
+    defs.insert(
+        "ImageLoadParams".to_string(),
+        schema_for!(ImageLoadParams).into(),
+    );
+    defs.insert(
+        "ImageLoadResult".to_string(),
+        schema_for!(ImageLoadResult).into(),
+    );
+    defs.insert(
+        "ImageGcRemoval".to_string(),
+        schema_for!(ImageGcRemoval).into(),
+    );
+    defs.insert(
+        "ImageGcReport".to_string(),
+        schema_for!(ImageGcReport).into(),
+    );
+    defs.insert(
+        "ImageInspectParams".to_string(),
+        schema_for!(ImageInspectParams).into(),
+    );
+    defs.insert(
+        "ImageInspectionLayer".to_string(),
+        schema_for!(ImageInspectionLayer).into(),
+    );
+    defs.insert(
+        "ImageInspection".to_string(),
+        schema_for!(ImageInspection).into(),
+    );
+    defs.insert(
+        "VolumeResizeParams".to_string(),
+        schema_for!(VolumeResizeParams).into(),
+    );
+    defs.insert("VolumeResize".to_string(), schema_for!(VolumeResize).into());
+    defs.insert("VolumeBackup".to_string(), schema_for!(VolumeBackup).into());
+    defs.insert(
+        "VolumeRestoreParams".to_string(),
+        schema_for!(VolumeRestoreParams).into(),
+    );
+    defs.insert(
+        "VolumeRestore".to_string(),
+        schema_for!(VolumeRestore).into(),
+    );
+    defs.insert("VolumeUsage".to_string(), schema_for!(VolumeUsage).into());
+    defs.insert(
+        "VolumeUsageEntry".to_string(),
+        schema_for!(VolumeUsageEntry).into(),
+    );
+    defs.insert(
+        "VolumeQuotaParams".to_string(),
+        schema_for!(VolumeQuotaParams).into(),
+    );
 
     Ok(())
 }
@@ -0,0 +1,125 @@
+use anyhow::Result;
+use meta::resource;
+use std::collections::BTreeMap;
+
+use crate::resources::{
+    Convert, FromResource, ProvideMetadata,
+    condition::{Condition, ObserveConditions},
+    machine::{MachineBuild, MachineResources, MachineVolumeBinding},
+};
+
+pub const DEFAULT_CRON_TIMEOUT_SECS: u64 = 60 * 60;
+pub const DEFAULT_CRON_HISTORY: u32 = 10;
+
+#[resource(name = "Cron", tag = "cron")]
+mod cron {
+    #[version(stored + served + latest)]
+    struct V1 {
+        image: Option<String>,
+        build: Option<MachineBuild>,
+        #[serde(rename = "build-env")]
+        build_env: Option<BTreeMap<String, String>>,
+        /// 5-field cron expression, in UTC, eg. `0 3 * * *`. Each run gets a short-lived
+        /// machine, a run due while the previous one is still going is skipped.
+        schedule: String,
+        /// Seconds a run may take before its machine is stopped. Defaults to 3600.
+        timeout: Option<u64>,
+        resources: MachineResources,
+        volumes: Option<Vec<MachineVolumeBinding>>,
+        command: Option<Vec<String>>,
+        environment: Option<BTreeMap<String, String>>,
+        /// Finished runs kept in the status. Defaults to 10.
+        history: Option<u32>,
+    }
+
+    #[status]
+    struct Status {
+        hash: u64,
+        next_run_at: Option<u64>,
+        /// Newest first.
+        runs: Vec<CronRun>,
+    }
+
+    #[schema]
+    struct CronRun {
+        machine: String,
+        started_at: u64,
+        finished_at: Option<u64>,
+        state: CronRunState,
+        exit_code: Option<i32>,
+    }
+
+    #[schema]
+    enum CronRunState {
+        #[serde(rename = "running")]
+        Running,
+        #[serde(rename = "succeeded")]
+        Succeeded,
+        #[serde(rename = "failed")]
+        Failed,
+        #[serde(rename = "timed-out")]
+        TimedOut,
+    }
+}
+
+impl ToString for CronRunState {
+    fn to_string(&self) -> String {
+        match self {
+            CronRunState::Running => "running".to_string(),
+            CronRunState::Succeeded => "succeeded".to_string(),
+            CronRunState::Failed => "failed".to_string(),
+            CronRunState::TimedOut => "timed-out".to_string(),
+        }
+    }
+}
+
+impl Cron {
+    pub fn hash_with_updated_metadata(&self) -> u64 {
+        use std::hash::{DefaultHasher, Hash, Hasher};
+
+        let metadata = self.metadata();
+        let mut cron = self.stored();
+        cron.namespace = metadata.namespace;
+        let cron: Cron = cron.into();
+
+        let mut hasher = DefaultHasher::new();
+        cron.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+impl CronStatus {
+    /// The run still going, there's at most one.
+    pub fn active_run(&self) -> Option<&CronRun> {
+        self.runs
+            .iter()
+            .find(|run| run.state == CronRunState::Running)
+    }
+}
+
+impl FromResource<Cron> for CronStatus {
+    fn from_resource(_cron: Cron) -> Result<Self> {
+        Ok(CronStatus {
+            hash: 0,
+            next_run_at: None,
+            runs: vec![],
+            conditions: vec![],
+        })
+    }
+}
+
+impl ObserveConditions for CronStatus {
+    fn observe_conditions(&self) -> Vec<Condition> {
+        let scheduled = self.next_run_at.is_some();
+        let running = self.active_run().is_some();
+        let reason = if running {
+            "Running"
+        } else if scheduled {
+            "Scheduled"
+        } else {
+            "Pending"
+        };
+
+        Condition::readiness(scheduled, running, reason, None)
+    }
+}
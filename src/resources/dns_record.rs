@@ -0,0 +1,188 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use anyhow::{Result, bail};
+use meta::resource;
+
+use crate::resources::{
+    FromResource,
+    condition::{Condition, ObserveConditions},
+};
+
+#[resource(name = "DnsRecord", tag = "dns_record")]
+mod dns_record {
+    #[version(stored + served + latest)]
+    struct V1 {
+        /// Name of the record, in a zone delegated to the tenant, eg.
+        /// `www-<tenant>.<region-root-domain>`.
+        #[serde(deserialize_with = "super::de_trim_non_empty_string")]
+        domain: String,
+        /// Seconds resolvers may cache the record. Default: the TTL of the DNS agent.
+        ttl: Option<u32>,
+        record: DnsRecordValue,
+    }
+
+    #[schema]
+    enum DnsRecordValue {
+        #[serde(rename = "a")]
+        A { addresses: Vec<String> },
+        #[serde(rename = "aaaa")]
+        Aaaa { addresses: Vec<String> },
+        #[serde(rename = "cname")]
+        Cname {
+            #[serde(deserialize_with = "super::de_trim_non_empty_string")]
+            target: String,
+        },
+        #[serde(rename = "txt")]
+        Txt { values: Vec<String> },
+        #[serde(rename = "mx")]
+        Mx { exchanges: Vec<DnsMxExchange> },
+    }
+
+    #[schema]
+    struct DnsMxExchange {
+        /// Lower values are preferred.
+        preference: u16,
+        #[serde(deserialize_with = "super::de_trim_non_empty_string")]
+        exchange: String,
+    }
+
+    #[status]
+    struct Status {
+        state: DnsRecordState,
+        /// Name the record is published under, kept to unpublish it when the domain changes.
+        domain: Option<String>,
+        /// Type the record is published as, eg. `A`.
+        record_type: Option<String>,
+        last_failure_reason: Option<String>,
+    }
+
+    #[schema]
+    enum DnsRecordState {
+        #[serde(rename = "pending")]
+        Pending,
+        #[serde(rename = "published")]
+        Published,
+        #[serde(rename = "failed")]
+        Failed,
+    }
+}
+
+/// Lowercase name without the trailing dot, the form records are published and looked up by.
+pub fn normalize_dns_name(name: &str) -> String {
+    name.trim().trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn validate_dns_name(name: &str) -> Result<()> {
+    let name = normalize_dns_name(name);
+    if name.is_empty() || name.len() > 253 {
+        bail!("Invalid DNS name: {}", name);
+    }
+
+    for label in name.split('.') {
+        if label.is_empty()
+            || label.len() > 63
+            || label.starts_with('-')
+            || label.ends_with('-')
+            || !label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            bail!("Invalid DNS name: {}", name);
+        }
+    }
+
+    Ok(())
+}
+
+impl DnsRecordValue {
+    pub fn record_type(&self) -> &'static str {
+        match self {
+            DnsRecordValue::A { .. } => "A",
+            DnsRecordValue::Aaaa { .. } => "AAAA",
+            DnsRecordValue::Cname { .. } => "CNAME",
+            DnsRecordValue::Txt { .. } => "TXT",
+            DnsRecordValue::Mx { .. } => "MX",
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            DnsRecordValue::A { addresses } => {
+                if addresses.is_empty() {
+                    bail!("A record needs at least one address");
+                }
+                for address in addresses {
+                    if address.trim().parse::<Ipv4Addr>().is_err() {
+                        bail!("Invalid IPv4 address in A record: {}", address);
+                    }
+                }
+            }
+            DnsRecordValue::Aaaa { addresses } => {
+                if addresses.is_empty() {
+                    bail!("AAAA record needs at least one address");
+                }
+                for address in addresses {
+                    if address.trim().parse::<Ipv6Addr>().is_err() {
+                        bail!("Invalid IPv6 address in AAAA record: {}", address);
+                    }
+                }
+            }
+            DnsRecordValue::Cname { target } => validate_dns_name(target)?,
+            DnsRecordValue::Txt { values } => {
+                if values.is_empty() {
+                    bail!("TXT record needs at least one value");
+                }
+            }
+            DnsRecordValue::Mx { exchanges } => {
+                if exchanges.is_empty() {
+                    bail!("MX record needs at least one exchange");
+                }
+                for exchange in exchanges {
+                    validate_dns_name(&exchange.exchange)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl DnsRecordV1 {
+    pub fn validate(&self) -> Result<()> {
+        validate_dns_name(&self.domain)?;
+        self.record.validate()
+    }
+}
+
+impl FromResource<DnsRecord> for DnsRecordStatus {
+    fn from_resource(_resource: DnsRecord) -> Result<Self> {
+        Ok(DnsRecordStatus {
+            state: DnsRecordState::Pending,
+            domain: None,
+            record_type: None,
+            last_failure_reason: None,
+            conditions: vec![],
+        })
+    }
+}
+
+impl ObserveConditions for DnsRecordStatus {
+    fn observe_conditions(&self) -> Vec<Condition> {
+        let (ready, reason) = match self.state {
+            DnsRecordState::Pending => (false, "Pending"),
+            DnsRecordState::Published => (true, "Published"),
+            DnsRecordState::Failed => (false, "Failed"),
+        };
+        let message = match self.state {
+            DnsRecordState::Failed => self.last_failure_reason.clone(),
+            _ => None,
+        };
+
+        Condition::readiness(
+            ready,
+            self.state == DnsRecordState::Pending,
+            reason,
+            message,
+        )
+    }
+}
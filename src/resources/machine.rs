@@ -1,8 +1,14 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use meta::resource;
 use std::collections::BTreeMap;
 
-use crate::resources::{Convert, FromResource, ProvideMetadata};
+use crate::{
+    constants::SCRATCH_VOLUME_MOUNT_PATH,
+    resources::{
+        Convert, FromResource, ProvideMetadata,
+        condition::{Condition, ObserveConditions},
+    },
+};
 
 #[resource(name = "Machine", tag = "machine")]
 mod machine {
@@ -11,15 +17,111 @@ mod machine {
     struct V1 {
         image: Option<String>,
         build: Option<MachineBuild>,
+        #[serde(rename = "build-env")]
+        build_env: Option<BTreeMap<String, String>>,
         resources: MachineResources,
         #[serde(rename = "restart-policy")]
         restart_policy: Option<MachineRestartPolicy>,
         mode: Option<MachineMode>,
         volumes: Option<Vec<MachineVolumeBinding>>,
+        scratch: Option<MachineScratch>,
+        #[serde(rename = "ephemeral-volumes")]
+        ephemeral_volumes: Option<Vec<MachineEphemeralVolume>>,
         command: Option<Vec<String>>,
         environment: Option<BTreeMap<String, String>>,
         #[serde(rename = "depends-on")]
         depends_on: Option<Vec<MachineDependency>>,
+        health: Option<MachineHealth>,
+        /// Extra kernel parameters appended to the boot cmdline, eg. `quiet`, `mitigations=off`
+        /// or module options like `nf_conntrack.hashsize=65536`. Only an allowlisted subset is
+        /// accepted.
+        #[serde(rename = "kernel-params")]
+        kernel_params: Option<Vec<String>>,
+        /// How the machine is replaced when its spec changes. Defaults to `recreate`.
+        rollout: Option<MachineRollout>,
+        /// Suspends the machine once it's idle, the next request through a service wakes it
+        /// back up with its state intact.
+        suspend: Option<MachineSuspendPolicy>,
+        /// Deletes the machine once it's been around for this long, eg. `3d` for a preview
+        /// environment. An `Expiring` event is recorded an hour before.
+        ttl: Option<String>,
+        /// Where the machine may run, it waits until a node satisfies all the constraints.
+        placement: Option<MachinePlacement>,
+        network: Option<MachineNetwork>,
+    }
+
+    #[schema]
+    struct MachinePlacement {
+        /// Required node labels, `key=value` or `key!=value`, eg. `disk=nvme` or `zone!=a`.
+        constraints: Option<Vec<String>>,
+    }
+
+    #[schema]
+    struct MachineNetwork {
+        bandwidth: Option<MachineBandwidth>,
+    }
+
+    /// Rate limits of the machine's network traffic, eg. `100mbit` or `1gbit`. Unset directions
+    /// aren't limited.
+    #[schema]
+    struct MachineBandwidth {
+        /// Traffic the machine receives.
+        ingress: Option<String>,
+        /// Traffic the machine sends.
+        egress: Option<String>,
+    }
+
+    /// A machine is idle once no traffic went through the proxy to it for `after-idle` and its
+    /// CPU utilization stays at or below `max-cpu` percent (defaults to 5).
+    #[schema]
+    struct MachineSuspendPolicy {
+        /// Human readable duration, eg. `10m` or `1h 30m`.
+        #[serde(rename = "after-idle")]
+        after_idle: String,
+        #[serde(rename = "max-cpu")]
+        max_cpu: Option<u8>,
+    }
+
+    /// `recreate` stops the machine and starts it again with the new spec. `rolling` starts a
+    /// machine with the new spec next to it first, moves the traffic over once it is ready (and
+    /// healthy, with `health` set), and only then replaces the old one.
+    #[schema]
+    struct MachineRollout {
+        strategy: MachineRolloutStrategy,
+        /// Machines started next to the ones being replaced. Defaults to 1, a machine rolls
+        /// with a surge of 0 or 1.
+        #[serde(rename = "max-surge")]
+        max_surge: Option<u32>,
+        /// Replicas of a machine group that may be down at once. Defaults to 0.
+        #[serde(rename = "max-unavailable")]
+        max_unavailable: Option<u32>,
+        /// Seconds the new machine has to become ready before the rollout is given up and the
+        /// old one kept. Defaults to 300.
+        #[serde(rename = "ready-timeout")]
+        ready_timeout: Option<u64>,
+        /// Seconds the old machine keeps serving its open connections once the new one gets
+        /// the traffic. Defaults to 30.
+        #[serde(rename = "drain-timeout")]
+        drain_timeout: Option<u64>,
+    }
+
+    #[schema]
+    enum MachineRolloutStrategy {
+        #[serde(rename = "recreate")]
+        Recreate,
+        #[serde(rename = "rolling")]
+        Rolling,
+    }
+
+    /// Opt-in `/healthz` served by the init next to the app, for images without a health
+    /// endpoint of their own. It answers 200 while the app process runs (and listens on
+    /// `app-port`, when set), 503 otherwise.
+    #[schema]
+    struct MachineHealth {
+        /// Port of the endpoint. Defaults to 8099.
+        port: Option<u16>,
+        #[serde(rename = "app-port")]
+        app_port: Option<u16>,
     }
 
     #[schema]
@@ -112,6 +214,10 @@ mod machine {
     struct MachineResources {
         cpu: u8,
         memory: u64,
+        /// CPU time the machine may use, in millicores: `500` is half a core, `1500` one and a
+        /// half. Defaults to all of its vcpus.
+        #[serde(rename = "cpu-limit")]
+        cpu_limit: Option<u32>,
     }
 
     #[schema]
@@ -122,6 +228,9 @@ mod machine {
         Flash {
             strategy: MachineSnapshotStrategy,
             timeout: Option<u64>,
+            /// Clones of the machine kept resumed next to it. Requests go to them while the
+            /// machine itself is suspended, so bursts don't wait for it to wake up.
+            prewarm: Option<u32>,
         },
     }
 
@@ -149,6 +258,32 @@ mod machine {
         path: String,
     }
 
+    /// Shorthand for an ephemeral volume mounted at `/scratch`.
+    #[schema]
+    struct MachineScratch {
+        #[serde(rename = "size-mib")]
+        size_mib: u64,
+    }
+
+    /// Empty ext4 volume mounted at `path`, created when the machine starts and deleted when it
+    /// stops, so every boot starts from an empty one. Unlike volume resources, it never shows up
+    /// in the volume list.
+    #[schema]
+    struct MachineEphemeralVolume {
+        #[serde(deserialize_with = "super::de_trim_non_empty_string")]
+        path: String,
+        #[serde(rename = "size-mib")]
+        size_mib: u64,
+    }
+
+    /// Ephemeral volume created for the current boot of the machine.
+    #[schema]
+    struct MachineEphemeralVolumeStatus {
+        path: String,
+        size_mib: u64,
+        volume_id: String,
+    }
+
     #[schema]
     struct MachineDependency {
         #[serde(deserialize_with = "super::de_trim_non_empty_string")]
@@ -163,15 +298,47 @@ mod machine {
         phase: MachinePhase,
         image_id: Option<String>,
         image_resolved_reference: Option<String>,
+        image_labels: Option<BTreeMap<String, String>>,
+        image_annotations: Option<BTreeMap<String, String>>,
         machine_id: Option<String>,
         machine_ip: Option<String>,
         machine_tap: Option<String>,
         machine_image_volume_id: Option<String>,
+        machine_ephemeral_volumes: Option<Vec<MachineEphemeralVolumeStatus>>,
         last_boot_time_us: Option<u64>,
         first_boot_time_us: Option<u64>,
         last_restarting_time_us: Option<u64>,
         last_exit_code: Option<i32>,
+        /// Id of the last core collected from a crashed process of this machine.
+        last_core_dump: Option<String>,
         restart_count: Option<u64>,
+        debug_trace: Option<MachineDebugTrace>,
+        /// Machine started with the new spec while this one keeps serving, during a rolling
+        /// rollout.
+        rollout_surge: Option<String>,
+        rollout_started_at: Option<u64>,
+        rollout_draining_since: Option<u64>,
+        /// Spec of the last rollout given up, it isn't retried until the spec changes again.
+        rollout_failed_hash: Option<u64>,
+        /// Suspended with `lttle machine suspend`, it stays suspended until resumed.
+        user_suspended: Option<bool>,
+        /// Unix millis the TTL started counting from.
+        ttl_started_at: Option<u64>,
+        ttl_warned: Option<bool>,
+        /// Node the machine was placed on.
+        node: Option<String>,
+        /// Clones kept resumed next to the machine, with flash `prewarm`.
+        prewarm_pool: Option<Vec<String>>,
+        /// Health probes failed in a row while the machine is ready, for machines with `health`.
+        health_probe_failures: Option<u32>,
+    }
+
+    #[schema]
+    enum MachineDebugTrace {
+        #[serde(rename = "strace")]
+        Strace,
+        #[serde(rename = "ltrace")]
+        Ltrace,
     }
 
     #[schema]
@@ -203,6 +370,29 @@ mod machine {
     }
 }
 
+impl MachineV1 {
+    /// Ephemeral volumes of the machine, with the scratch volume among them.
+    pub fn ephemeral_volumes(&self) -> Vec<MachineEphemeralVolume> {
+        let mut volumes = self.ephemeral_volumes.clone().unwrap_or_default();
+        if let Some(scratch) = &self.scratch {
+            volumes.push(MachineEphemeralVolume {
+                path: SCRATCH_VOLUME_MOUNT_PATH.to_string(),
+                size_mib: scratch.size_mib,
+            });
+        }
+
+        volumes
+    }
+}
+
+impl MachineEphemeralVolume {
+    pub fn size_bytes(&self) -> Result<u64> {
+        self.size_mib
+            .checked_mul(1024 * 1024)
+            .ok_or_else(|| anyhow!("ephemeral volume {} is too large", self.path))
+    }
+}
+
 impl ToString for MachinePhase {
     fn to_string(&self) -> String {
         match self {
@@ -240,19 +430,60 @@ impl FromResource<Machine> for MachineStatus {
             phase: MachinePhase::Idle,
             image_id: None,
             image_resolved_reference: None,
+            image_labels: None,
+            image_annotations: None,
             machine_id: None,
             machine_ip: None,
             machine_tap: None,
             machine_image_volume_id: None,
+            machine_ephemeral_volumes: None,
             last_boot_time_us: None,
             first_boot_time_us: None,
             last_restarting_time_us: None,
             last_exit_code: None,
+            last_core_dump: None,
             restart_count: Some(0),
+            debug_trace: None,
+            rollout_surge: None,
+            rollout_started_at: None,
+            rollout_draining_since: None,
+            rollout_failed_hash: None,
+            user_suspended: None,
+            ttl_started_at: None,
+            ttl_warned: None,
+            node: None,
+            prewarm_pool: None,
+            health_probe_failures: None,
+            conditions: vec![],
         })
     }
 }
 
+impl ObserveConditions for MachineStatus {
+    fn observe_conditions(&self) -> Vec<Condition> {
+        let (reason, message) = match &self.phase {
+            MachinePhase::Error { message } => ("Error".to_string(), Some(message.clone())),
+            phase => (format!("{:?}", phase), None),
+        };
+        // suspended machines wake up on the next connection, they count as ready
+        let ready = matches!(
+            self.phase,
+            MachinePhase::Ready | MachinePhase::Suspending | MachinePhase::Suspended
+        );
+        let progressing = matches!(
+            self.phase,
+            MachinePhase::PullingImage
+                | MachinePhase::Waiting
+                | MachinePhase::Creating
+                | MachinePhase::Booting
+                | MachinePhase::Stopping
+                | MachinePhase::Restarting
+        ) || self.rollout_surge.is_some();
+
+        Condition::readiness(ready, progressing, reason, message)
+    }
+}
+
 impl Machine {
     pub fn hash_with_updated_metadata(&self) -> u64 {
         use std::hash::{DefaultHasher, Hash, Hasher};
@@ -260,6 +491,14 @@ impl Machine {
         let metadata = self.metadata();
         let mut machine = self.stored();
         machine.namespace = metadata.namespace;
+        // changing how the machine rolls out, suspends, expires or how many clones it keeps
+        // prewarmed is no reason to roll it out
+        machine.rollout = None;
+        machine.suspend = None;
+        machine.ttl = None;
+        if let Some(MachineMode::Flash { prewarm, .. }) = &mut machine.mode {
+            *prewarm = None;
+        }
         let machine: Machine = machine.into();
 
         let mut hasher = DefaultHasher::new();
@@ -0,0 +1,131 @@
+use anyhow::Result;
+use meta::resource;
+use std::collections::BTreeMap;
+
+use crate::resources::{
+    Convert, FromResource,
+    condition::{Condition, ObserveConditions},
+    machine::{
+        MachineBuild, MachineEphemeralVolume, MachineHealth, MachineMode, MachineNetwork,
+        MachinePlacement, MachineResources, MachineRestartPolicy, MachineRollout, MachineScratch,
+        MachineVolumeBinding,
+    },
+};
+
+pub const DEFAULT_MACHINE_GROUP_REPLICAS: u32 = 1;
+pub const DEFAULT_MACHINE_GROUP_DRAIN_TIMEOUT_SECS: u64 = 30;
+pub const DEFAULT_MACHINE_GROUP_SCALE_DOWN_DELAY_SECS: u64 = 300;
+
+#[resource(name = "MachineGroup", tag = "machine_group")]
+mod machine_group {
+    #[version(stored + served + latest)]
+    struct V1 {
+        image: Option<String>,
+        build: Option<MachineBuild>,
+        #[serde(rename = "build-env")]
+        build_env: Option<BTreeMap<String, String>>,
+        resources: MachineResources,
+        #[serde(rename = "restart-policy")]
+        restart_policy: Option<MachineRestartPolicy>,
+        mode: Option<MachineMode>,
+        /// Only read-only volumes, the replicas share them.
+        volumes: Option<Vec<MachineVolumeBinding>>,
+        scratch: Option<MachineScratch>,
+        #[serde(rename = "ephemeral-volumes")]
+        ephemeral_volumes: Option<Vec<MachineEphemeralVolume>>,
+        command: Option<Vec<String>>,
+        environment: Option<BTreeMap<String, String>>,
+        health: Option<MachineHealth>,
+        #[serde(rename = "kernel-params")]
+        kernel_params: Option<Vec<String>>,
+        /// Also how many replicas are replaced at once, `max-surge` replicas rolling next to
+        /// new ones or, without surge, `max-unavailable` replicas recreated.
+        rollout: Option<MachineRollout>,
+        placement: Option<MachinePlacement>,
+        network: Option<MachineNetwork>,
+        /// Number of replicas when the group isn't autoscaled. Defaults to 1.
+        replicas: Option<u32>,
+        autoscale: Option<MachineGroupAutoscale>,
+        /// Seconds a replica removed by a scale-down keeps serving its open connections before
+        /// it is deleted. Defaults to 30.
+        #[serde(rename = "drain-timeout")]
+        drain_timeout: Option<u64>,
+    }
+
+    /// Keeps the number of replicas between `min` and `max`, adding replicas as soon as the
+    /// load per replica goes over a target and removing them once it stayed under it for
+    /// `scale-down-delay` seconds (300 by default).
+    #[schema]
+    struct MachineGroupAutoscale {
+        min: u32,
+        max: u32,
+        /// Requests per second per replica, counted by the proxy for the services targeting
+        /// the group.
+        #[serde(rename = "target-rps")]
+        target_rps: Option<u32>,
+        /// Average CPU utilization of the replicas, in percent. Needs a metrics store.
+        #[serde(rename = "target-cpu")]
+        target_cpu: Option<u8>,
+        #[serde(rename = "scale-down-delay")]
+        scale_down_delay: Option<u64>,
+    }
+
+    #[status]
+    struct Status {
+        hash: u64,
+        replicas: u32,
+        machines: Vec<String>,
+        draining: Vec<MachineGroupDrainingReplica>,
+        request_count: Option<u64>,
+        sampled_at: Option<u64>,
+        observed_rps: Option<u64>,
+        observed_cpu: Option<u8>,
+        low_load_since: Option<u64>,
+        last_scaled_at: Option<u64>,
+    }
+
+    #[schema]
+    struct MachineGroupDrainingReplica {
+        name: String,
+        since: u64,
+    }
+}
+
+impl MachineGroupV1 {
+    /// Replicas the group starts with.
+    pub fn initial_replicas(&self) -> u32 {
+        match &self.autoscale {
+            Some(autoscale) => autoscale.min,
+            None => self.replicas.unwrap_or(DEFAULT_MACHINE_GROUP_REPLICAS),
+        }
+    }
+}
+
+impl FromResource<MachineGroup> for MachineGroupStatus {
+    fn from_resource(group: MachineGroup) -> Result<Self> {
+        let group = group.latest();
+
+        Ok(MachineGroupStatus {
+            hash: 0,
+            replicas: group.initial_replicas(),
+            machines: vec![],
+            draining: vec![],
+            request_count: None,
+            sampled_at: None,
+            observed_rps: None,
+            observed_cpu: None,
+            low_load_since: None,
+            last_scaled_at: None,
+            conditions: vec![],
+        })
+    }
+}
+
+impl ObserveConditions for MachineGroupStatus {
+    fn observe_conditions(&self) -> Vec<Condition> {
+        let scaled = self.machines.len() as u32 == self.replicas;
+        let reason = if scaled { "Scaled" } else { "Scaling" };
+
+        Condition::readiness(scaled, !scaled, reason, None)
+    }
+}
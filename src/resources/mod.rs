@@ -13,9 +13,13 @@ use crate::{
 
 pub mod app;
 pub mod certificate;
+pub mod condition;
 pub mod core;
+pub mod cron;
+pub mod dns_record;
 pub mod gadget;
 pub mod machine;
+pub mod machine_group;
 pub mod metadata;
 pub mod service;
 pub mod volume;
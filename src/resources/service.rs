@@ -1,7 +1,12 @@
+use std::collections::BTreeMap;
+
 use anyhow::Result;
 use meta::resource;
 
-use crate::resources::{Convert, FromResource, ProvideMetadata};
+use crate::resources::{
+    Convert, FromResource, ProvideMetadata,
+    condition::{Condition, ObserveConditions},
+};
 
 #[resource(name = "Service", tag = "service")]
 mod service {
@@ -10,6 +15,169 @@ mod service {
     struct V1 {
         target: ServiceTarget,
         bind: ServiceBind,
+        #[serde(rename = "rate-limit")]
+        rate_limit: Option<ServiceRateLimit>,
+        timeouts: Option<ServiceTimeouts>,
+        #[serde(rename = "circuit-breaker")]
+        circuit_breaker: Option<ServiceCircuitBreaker>,
+        retry: Option<ServiceRetry>,
+        firewall: Option<ServiceFirewall>,
+        auth: Option<ServiceAuth>,
+        compression: Option<ServiceCompression>,
+        #[serde(rename = "sticky-sessions")]
+        sticky_sessions: Option<ServiceStickySessions>,
+        headers: Option<ServiceHeaders>,
+        /// Answers HTTP requests with the maintenance page instead of waking the target up.
+        maintenance: Option<bool>,
+        #[serde(rename = "error-pages")]
+        error_pages: Option<ServiceErrorPages>,
+    }
+
+    /// HTML pages served to browsers in place of the builtin error pages. Clients not accepting
+    /// `text/html` get a plain text error.
+    #[schema]
+    struct ServiceErrorPages {
+        /// Served when the target fails to answer a request.
+        #[serde(rename = "bad-gateway")]
+        bad_gateway: Option<String>,
+        /// Served when the target can't be reached, eg. while it is starting up.
+        #[serde(rename = "service-unavailable")]
+        service_unavailable: Option<String>,
+        /// Served while the service is in maintenance.
+        maintenance: Option<String>,
+    }
+
+    /// Header rules for the HTTP requests sent to the target and the responses sent back, eg.
+    /// CORS headers or `x-frame-options`.
+    #[schema]
+    struct ServiceHeaders {
+        request: Option<ServiceHeaderRules>,
+        response: Option<ServiceHeaderRules>,
+    }
+
+    /// Applied in order: `remove`, then `set`, then `add`.
+    #[schema]
+    struct ServiceHeaderRules {
+        /// Headers to drop.
+        remove: Option<Vec<String>>,
+        /// Headers to set, replacing any existing value.
+        set: Option<BTreeMap<String, String>>,
+        /// Headers to add next to any existing value.
+        add: Option<BTreeMap<String, String>>,
+    }
+
+    /// Sends the HTTP requests of a client to the same machine through a cookie, for apps
+    /// keeping sessions in memory. A client whose machine goes away is pinned to a new one.
+    #[schema]
+    struct ServiceStickySessions {
+        /// Name of the affinity cookie. Defaults to `lttle-affinity`.
+        cookie: Option<String>,
+        /// Seconds the cookie lives, it lasts for the browser session when unset.
+        #[serde(rename = "max-age")]
+        max_age: Option<u64>,
+    }
+
+    /// Compresses HTTP responses the target sent uncompressed, with brotli, gzip or deflate
+    /// depending on what the client accepts.
+    #[schema]
+    struct ServiceCompression {
+        /// Responses with a smaller `content-length` are sent as is. Defaults to 1024 bytes.
+        #[serde(rename = "min-size")]
+        min_size: Option<u64>,
+        /// Content types to compress, eg. `application/json` or `text/*`. Defaults to text,
+        /// JSON, JavaScript, XML, WebAssembly and SVG.
+        #[serde(rename = "content-types")]
+        content_types: Option<Vec<String>>,
+    }
+
+    /// Authentication required before HTTP requests reach the target, either `basic` or
+    /// `forward`.
+    #[schema]
+    struct ServiceAuth {
+        basic: Option<ServiceBasicAuth>,
+        forward: Option<ServiceForwardAuth>,
+    }
+
+    #[schema]
+    struct ServiceBasicAuth {
+        realm: Option<String>,
+        users: Vec<ServiceBasicAuthUser>,
+    }
+
+    #[schema]
+    struct ServiceBasicAuthUser {
+        username: String,
+        password: String,
+    }
+
+    /// Asks `url` about every request, passing its headers along with `x-forwarded-method`,
+    /// `-proto`, `-host` and `-uri`. A 2xx answer lets the request through, anything else is
+    /// returned to the client.
+    #[schema]
+    struct ServiceForwardAuth {
+        url: String,
+        /// Headers of the auth answer added to the request, eg. `x-auth-user`.
+        #[serde(rename = "response-headers")]
+        response_headers: Option<Vec<String>>,
+    }
+
+    /// Client addresses allowed to reach the service, as CIDRs (eg. `203.0.113.0/24`) or bare
+    /// addresses. Denied networks always win, and once `allow` is set any other address is
+    /// rejected.
+    #[schema]
+    struct ServiceFirewall {
+        allow: Option<Vec<String>>,
+        deny: Option<Vec<String>>,
+    }
+
+    /// Retries idempotent HTTP requests (GET, HEAD, PUT, DELETE, ...) that failed upstream.
+    #[schema]
+    struct ServiceRetry {
+        /// Sends of a request in total, including the first one. Defaults to 3.
+        attempts: Option<u32>,
+        /// Seconds a single attempt may take before being retried.
+        #[serde(rename = "per-try-timeout")]
+        per_try_timeout: Option<u64>,
+        /// Retries allowed as a percentage of the requests, so a failing target doesn't get its
+        /// load multiplied. Defaults to 20.
+        #[serde(rename = "budget-percent")]
+        budget_percent: Option<u8>,
+    }
+
+    /// Takes machines failing in a row out of rotation for a while, then lets a single request
+    /// probe them before putting them back.
+    #[schema]
+    struct ServiceCircuitBreaker {
+        /// Failed connections or requests in a row that eject a machine. Defaults to 5.
+        #[serde(rename = "consecutive-failures")]
+        consecutive_failures: Option<u32>,
+        /// Seconds an ejected machine is left out. Defaults to 30.
+        #[serde(rename = "ejection-time")]
+        ejection_time: Option<u64>,
+    }
+
+    /// All in seconds, unset ones keep the proxy defaults.
+    #[schema]
+    struct ServiceTimeouts {
+        /// Each attempt at connecting to the target. Defaults to 5.
+        connect: Option<u64>,
+        /// Whole HTTP request until the response headers, including waking the target up.
+        request: Option<u64>,
+        /// Close TCP connections and UDP sessions without traffic in either direction.
+        idle: Option<u64>,
+        /// Waiting for the response headers once the request was sent to the target.
+        #[serde(rename = "upstream-response")]
+        upstream_response: Option<u64>,
+    }
+
+    #[schema]
+    struct ServiceRateLimit {
+        #[serde(rename = "requests-per-second")]
+        requests_per_second: Option<u32>,
+        /// Requests allowed above the steady rate. Defaults to `requests-per-second`.
+        burst: Option<u32>,
+        #[serde(rename = "max-connections")]
+        max_connections: Option<u32>,
     }
 
     #[schema]
@@ -22,14 +190,104 @@ mod service {
         protocol: ServiceTargetProtocol,
         #[serde(rename = "connection-tracking")]
         connection_tracking: Option<ServiceTargetConnectionTracking>,
+        /// Weight of the target machine against `backends`. Defaults to 1.
+        weight: Option<u32>,
+        /// Additional machines sharing the traffic with weighted round-robin.
+        backends: Option<Vec<ServiceTargetBackend>>,
+        /// Machine receiving a fixed percentage of the traffic, eg. for gradual rollouts.
+        canary: Option<ServiceTargetCanary>,
+        /// Machine receiving a copy of a percentage of the HTTP requests, its responses being
+        /// discarded. Used to try a new version under production traffic.
+        mirror: Option<ServiceTargetMirror>,
+        /// Prepend a PROXY protocol header to upstream connections, so TCP backends see the
+        /// client address. HTTP requests carry it in `x-forwarded-for` instead.
+        #[serde(rename = "proxy-protocol")]
+        proxy_protocol: Option<ServiceTargetProxyProtocol>,
+        /// Connect to an http target over TLS, for apps that only serve HTTPS.
+        tls: Option<ServiceTargetTls>,
+        /// Host header the target receives. Defaults to the bind host for http and the
+        /// requested host for https.
+        #[serde(rename = "host-header")]
+        host_header: Option<ServiceTargetHostHeader>,
+    }
+
+    #[schema]
+    enum ServiceTargetHostHeader {
+        /// The host requested by the client.
+        #[serde(rename = "preserve")]
+        Preserve,
+        /// The external bind host.
+        #[serde(rename = "bind")]
+        Bind,
+        #[serde(rename = "custom")]
+        Custom { host: String },
+    }
+
+    #[schema]
+    struct ServiceTargetTls {
+        /// Name sent as SNI and verified against the certificate. Defaults to the machine IP.
+        #[serde(rename = "server-name")]
+        server_name: Option<String>,
+        /// PEM encoded CA certificates trusted for the target. Defaults to the system CAs.
+        #[serde(rename = "ca-cert")]
+        ca_cert: Option<String>,
+        #[serde(rename = "skip-verify")]
+        skip_verify: Option<bool>,
+        /// PEM encoded certificate chain presented to targets requiring client certificates.
+        #[serde(rename = "client-cert")]
+        client_cert: Option<String>,
+        /// PEM encoded private key of `client-cert`.
+        #[serde(rename = "client-key")]
+        client_key: Option<String>,
+    }
+
+    #[schema]
+    struct ServiceTargetBackend {
+        #[serde(deserialize_with = "super::de_trim_non_empty_string")]
+        name: String,
+        #[serde(default, deserialize_with = "super::de_opt_trim_non_empty_string")]
+        namespace: Option<String>,
+        /// Defaults to 1.
+        weight: Option<u32>,
+    }
+
+    #[schema]
+    struct ServiceTargetCanary {
+        #[serde(deserialize_with = "super::de_trim_non_empty_string")]
+        name: String,
+        #[serde(default, deserialize_with = "super::de_opt_trim_non_empty_string")]
+        namespace: Option<String>,
+        /// Percentage of the requests (or connections) routed to the canary, 0-100.
+        percentage: u8,
+    }
+
+    #[schema]
+    struct ServiceTargetMirror {
+        #[serde(deserialize_with = "super::de_trim_non_empty_string")]
+        name: String,
+        #[serde(default, deserialize_with = "super::de_opt_trim_non_empty_string")]
+        namespace: Option<String>,
+        /// Percentage of the requests copied to the mirror, 0-100.
+        percentage: u8,
     }
 
     #[schema]
     enum ServiceTargetProtocol {
         #[serde(rename = "http")]
         Http,
+        /// HTTP/2 over cleartext (h2c), eg. for gRPC servers.
+        #[serde(rename = "http2")]
+        Http2,
         #[serde(rename = "tcp")]
         Tcp,
+        #[serde(rename = "udp")]
+        Udp,
+    }
+
+    #[schema]
+    enum ServiceTargetProxyProtocol {
+        #[serde(rename = "v2")]
+        V2,
     }
 
     #[schema]
@@ -57,6 +315,10 @@ mod service {
             /// If not provided, the port will be inferred from protocol or target port.
             port: Option<u16>,
             protocol: ServiceBindExternalProtocol,
+            /// Only route requests under this path (eg. `/api`), so several services can share
+            /// the host. The longest matching prefix wins. Requires an http target.
+            #[serde(rename = "path-prefix")]
+            path_prefix: Option<String>,
         },
         #[serde(rename = "tcp")]
         Tcp,
@@ -70,8 +332,14 @@ mod service {
         Https,
         #[serde(rename = "tls")]
         Tls,
+        /// Routes on SNI and forwards the TLS bytes untouched, the machine terminates TLS itself.
+        #[serde(rename = "tls-passthrough")]
+        TlsPassthrough,
         #[serde(rename = "tcp")]
         Tcp,
+        /// Datagrams are relayed on the bind port, each client sticking to one machine.
+        #[serde(rename = "udp")]
+        Udp,
     }
 
     #[status]
@@ -88,17 +356,29 @@ impl FromResource<Service> for ServiceStatus {
             service_ip: None,
             internal_dns_hostname: None,
             allocated_tcp_port: None,
+            conditions: vec![],
         })
     }
 }
 
+impl ObserveConditions for ServiceStatus {
+    fn observe_conditions(&self) -> Vec<Condition> {
+        let bound = self.service_ip.is_some();
+        let reason = if bound { "Bound" } else { "Binding" };
+
+        Condition::readiness(bound, !bound, reason, None)
+    }
+}
+
 impl ServiceBindExternalProtocol {
     pub fn default_port(&self, target: &ServiceTarget) -> u16 {
         match self {
             ServiceBindExternalProtocol::Http => 80,
             ServiceBindExternalProtocol::Https => 443,
             ServiceBindExternalProtocol::Tls => target.port,
+            ServiceBindExternalProtocol::TlsPassthrough => 443,
             ServiceBindExternalProtocol::Tcp => target.port,
+            ServiceBindExternalProtocol::Udp => target.port,
         }
     }
 }
@@ -109,7 +389,9 @@ impl ToString for ServiceBindExternalProtocol {
             ServiceBindExternalProtocol::Http => "http".to_string(),
             ServiceBindExternalProtocol::Https => "https".to_string(),
             ServiceBindExternalProtocol::Tls => "tls".to_string(),
+            ServiceBindExternalProtocol::TlsPassthrough => "tls-passthrough".to_string(),
             ServiceBindExternalProtocol::Tcp => "tcp".to_string(),
+            ServiceBindExternalProtocol::Udp => "udp".to_string(),
         }
     }
 }
@@ -118,7 +400,9 @@ impl ToString for ServiceTargetProtocol {
     fn to_string(&self) -> String {
         match self {
             ServiceTargetProtocol::Http => "http".to_string(),
+            ServiceTargetProtocol::Http2 => "http2".to_string(),
             ServiceTargetProtocol::Tcp => "tcp".to_string(),
+            ServiceTargetProtocol::Udp => "udp".to_string(),
         }
     }
 }
@@ -2,7 +2,10 @@ use anyhow::{Result, bail};
 use meta::resource;
 
 use crate::{
-    resources::{AdmissionCheckStatus, Convert, FromResource, ProvideMetadata},
+    resources::{
+        AdmissionCheckStatus, Convert, FromResource, ProvideMetadata,
+        condition::{Condition, ObserveConditions},
+    },
     utils::size::parse_human_readable_size,
 };
 
@@ -11,9 +14,48 @@ mod volume {
 
     #[version(stored + served + latest)]
     struct V1 {
+        /// Read-only volumes can be attached to several machines at once, writeable ones to one
         mode: VolumeMode,
         /// The size of the volume in human readable format
         size: String,
+        /// Filesystem the volume is formatted with, ext4 when not set
+        filesystem: Option<VolumeFilesystem>,
+        /// Encrypts the volume at rest with a key of the tenant managed by the daemon
+        encrypted: Option<bool>,
+        /// Creates the volume as a copy of another volume or of the root filesystem of an image
+        from: Option<VolumeSource>,
+        /// Scheduled backups of the volume to object storage
+        backup: Option<VolumeBackupPolicy>,
+    }
+
+    #[schema]
+    struct VolumeSource {
+        /// Name of the volume to copy
+        volume: Option<String>,
+        /// Namespace of the volume to copy, the one of the new volume when not set
+        namespace: Option<String>,
+        /// Image whose root filesystem is copied (eg. "postgres:16")
+        image: Option<String>,
+    }
+
+    #[schema]
+    struct VolumeBackupPolicy {
+        /// When backups run, as a 5-field cron expression in UTC (eg. "0 3 * * *")
+        schedule: String,
+        /// Where backups are stored, as "s3://<bucket>/<prefix>"
+        destination: String,
+        /// How many backups are kept, all of them when not set
+        keep: Option<u32>,
+    }
+
+    #[schema]
+    enum VolumeFilesystem {
+        #[serde(rename = "ext4")]
+        Ext4,
+        #[serde(rename = "xfs")]
+        Xfs,
+        #[serde(rename = "btrfs")]
+        Btrfs,
     }
 
     #[schema]
@@ -29,6 +71,12 @@ mod volume {
         hash: u64,
         volume_id: Option<String>,
         size_bytes: u64,
+        last_backup_at: Option<u64>,
+        last_backup: Option<String>,
+        last_backup_error: Option<String>,
+        next_backup_at: Option<u64>,
+        used_bytes: Option<u64>,
+        filesystem_size_bytes: Option<u64>,
     }
 }
 
@@ -41,17 +89,40 @@ impl FromResource<Volume> for VolumeStatus {
             volume_id: None,
             hash: 0,
             size_bytes,
+            last_backup_at: None,
+            last_backup: None,
+            last_backup_error: None,
+            next_backup_at: None,
+            used_bytes: None,
+            filesystem_size_bytes: None,
+            conditions: vec![],
         })
     }
 }
 
+impl ObserveConditions for VolumeStatus {
+    fn observe_conditions(&self) -> Vec<Condition> {
+        let provisioned = self.volume_id.is_some();
+        let reason = if provisioned {
+            "Provisioned"
+        } else {
+            "Provisioning"
+        };
+
+        Condition::readiness(provisioned, !provisioned, reason, None)
+    }
+}
+
 impl Volume {
+    /// Hash of the configuration volumes can't change after creation, the backup policy is
+    /// left out.
     pub fn hash_with_updated_metadata(&self) -> u64 {
         use std::hash::{DefaultHasher, Hash, Hasher};
 
         let metadata = self.metadata();
         let mut volume = self.stored();
         volume.namespace = metadata.namespace;
+        volume.backup = None;
         let volume: Volume = volume.into();
 
         let mut hasher = DefaultHasher::new();
@@ -13,9 +13,17 @@ pub struct TakeoffInitArgs {
     pub mount_points: Vec<MountPoint>,
     #[serde(rename = "l")]
     pub logs_telemetry_config: LogsTelemetryConfig,
+    #[serde(rename = "d", default, skip_serializing_if = "Option::is_none")]
+    pub debug_trace: Option<DebugTraceConfig>,
+    #[serde(rename = "h", default, skip_serializing_if = "Option::is_none")]
+    pub health: Option<HealthConfig>,
+    #[serde(rename = "k", default, skip_serializing_if = "Option::is_none")]
+    pub core_dump: Option<CoreDumpConfig>,
+    #[serde(rename = "o", default, skip_serializing_if = "Option::is_none")]
+    pub metrics_telemetry_config: Option<MetricsTelemetryConfig>,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub struct MountPoint {
     #[serde(rename = "s")]
     pub source: String,
@@ -23,6 +31,20 @@ pub struct MountPoint {
     pub target: String,
     #[serde(rename = "r")]
     pub read_only: bool,
+    /// ext4 when not set.
+    #[serde(rename = "f", default, skip_serializing_if = "Option::is_none")]
+    pub filesystem: Option<Filesystem>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Filesystem {
+    #[default]
+    #[serde(rename = "e")]
+    Ext4,
+    #[serde(rename = "x")]
+    Xfs,
+    #[serde(rename = "b")]
+    Btrfs,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
@@ -39,6 +61,131 @@ pub struct LogsTelemetryConfig {
     pub service_group: String,
 }
 
+/// OTLP metrics export of the machine resource usage, labelled like the logs.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct MetricsTelemetryConfig {
+    #[serde(rename = "e")]
+    pub endpoint: String,
+    #[serde(rename = "i")]
+    pub interval_secs: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct DebugTraceConfig {
+    #[serde(rename = "t")]
+    pub tool: DebugTraceTool,
+    #[serde(rename = "m")]
+    pub max_lines: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub enum DebugTraceTool {
+    #[serde(rename = "s")]
+    Strace,
+    #[serde(rename = "l")]
+    Ltrace,
+}
+
+/// Synthetic health endpoint served by takeoff next to the app.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct HealthConfig {
+    #[serde(rename = "p")]
+    pub port: u16,
+    /// Reported unhealthy until the app listens on this port.
+    #[serde(rename = "a", default, skip_serializing_if = "Option::is_none")]
+    pub app_port: Option<u16>,
+}
+
+/// Collects the cores of crashed processes and hands them to the host.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct CoreDumpConfig {
+    /// Cores are truncated past this many bytes.
+    #[serde(rename = "s")]
+    pub max_size: u64,
+}
+
+/// Space used on the filesystem of a mounted volume, reported by takeoff to the host.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct VolumeUsageReport {
+    /// Position of the volume in `TakeoffInitArgs::mount_points`.
+    pub mount_index: u8,
+    pub used_bytes: u64,
+    pub size_bytes: u64,
+}
+
+impl Filesystem {
+    /// Name of the filesystem for `mount` and `mkfs.<name>`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Filesystem::Ext4 => "ext4",
+            Filesystem::Xfs => "xfs",
+            Filesystem::Btrfs => "btrfs",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ext4" => Some(Filesystem::Ext4),
+            "xfs" => Some(Filesystem::Xfs),
+            "btrfs" => Some(Filesystem::Btrfs),
+            _ => None,
+        }
+    }
+}
+
+impl MountPoint {
+    pub fn filesystem(&self) -> Filesystem {
+        self.filesystem.unwrap_or_default()
+    }
+}
+
+impl DebugTraceTool {
+    pub fn binary_name(&self) -> &'static str {
+        match self {
+            DebugTraceTool::Strace => "strace",
+            DebugTraceTool::Ltrace => "ltrace",
+        }
+    }
+
+    /// Arguments of the tracer running `cmd`, following forks and writing to `output`.
+    pub fn command(&self, tracer: String, output: &str, cmd: Vec<String>) -> Vec<String> {
+        let mut args = vec![tracer, "-f".to_string(), "-tt".to_string()];
+        match self {
+            DebugTraceTool::Strace => {
+                args.extend(["-s", "256", "-o", output, "--"].map(String::from));
+            }
+            // ltrace takes the command right after its options, it has no `--`
+            DebugTraceTool::Ltrace => {
+                args.extend(["-s", "256", "-o", output].map(String::from));
+            }
+        }
+        args.extend(cmd);
+        args
+    }
+}
+
+impl VolumeUsageReport {
+    const MIB: u64 = 1024 * 1024;
+    const FIELD_MASK: u64 = (1 << 28) - 1;
+
+    /// Packs the report into a single MMIO write: the mount index in the top byte, then the
+    /// size and the used space in MiB on 28 bits each (up to 256 TiB).
+    pub fn encode(&self) -> u64 {
+        let size_mib = (self.size_bytes / Self::MIB).min(Self::FIELD_MASK);
+        let used_mib = self.used_bytes.div_ceil(Self::MIB).min(size_mib);
+
+        ((self.mount_index as u64) << 56) | (size_mib << 28) | used_mib
+    }
+
+    pub fn decode(value: u64) -> Self {
+        Self {
+            mount_index: (value >> 56) as u8,
+            used_bytes: (value & Self::FIELD_MASK) * Self::MIB,
+            size_bytes: ((value >> 28) & Self::FIELD_MASK) * Self::MIB,
+        }
+    }
+}
+
 impl TakeoffInitArgs {
     pub fn encode(&self) -> Result<String> {
         let bytes = serde_json::to_string(&self)?;
@@ -80,6 +227,7 @@ mod tests {
                 source: "/dev/vdb".to_string(),
                 target: "/mnt/data".to_string(),
                 read_only: true,
+                filesystem: Some(Filesystem::Xfs),
             }],
             logs_telemetry_config: LogsTelemetryConfig {
                 endpoint: "http://localhost:3100/otlp/v1/logs".to_string(),
@@ -88,9 +236,37 @@ mod tests {
                 service_namespace: "test".to_string(),
                 service_group: "test".to_string(),
             },
+            debug_trace: Some(DebugTraceConfig {
+                tool: DebugTraceTool::Strace,
+                max_lines: 100,
+            }),
+            health: Some(HealthConfig {
+                port: 8099,
+                app_port: Some(3000),
+            }),
+            core_dump: Some(CoreDumpConfig {
+                max_size: 128 * 1024 * 1024,
+            }),
+            metrics_telemetry_config: Some(MetricsTelemetryConfig {
+                endpoint: "http://localhost:9090/api/v1/otlp/v1/metrics".to_string(),
+                interval_secs: 15,
+            }),
         };
         let encoded = args.encode().unwrap();
         let decoded = TakeoffInitArgs::decode(&encoded).unwrap();
         assert_eq!(args, decoded);
     }
+
+    #[test]
+    fn test_volume_usage_report() {
+        let report = VolumeUsageReport {
+            mount_index: 3,
+            used_bytes: 5 * 1024 * 1024 * 1024 + 1,
+            size_bytes: 20 * 1024 * 1024 * 1024,
+        };
+        let decoded = VolumeUsageReport::decode(report.encode());
+        assert_eq!(decoded.mount_index, 3);
+        assert_eq!(decoded.used_bytes, 5 * 1024 * 1024 * 1024 + 1024 * 1024);
+        assert_eq!(decoded.size_bytes, report.size_bytes);
+    }
 }
@@ -0,0 +1,76 @@
+use std::{fs::Permissions, os::unix::fs::PermissionsExt, sync::Arc};
+
+use anyhow::{Result, bail};
+use nix::libc;
+use takeoff_proto::proto::CoreDumpConfig;
+use tokio::fs;
+use tracing::{info, warn};
+
+use crate::guest::GuestManager;
+
+/// Where the kernel writes cores, on the root volume rather than a tmpfs so a large core doesn't
+/// eat into the guest memory.
+const CORE_DUMP_DIR: &str = "/.lttle-cores";
+
+/// Has the kernel write the cores of crashed processes to `CORE_DUMP_DIR`, truncated to the
+/// configured size. The limit is inherited by every process started afterwards.
+pub async fn prepare_core_dumps(config: &CoreDumpConfig) -> Result<()> {
+    fs::create_dir_all(CORE_DUMP_DIR).await?;
+    // crashed processes may run as any user
+    fs::set_permissions(CORE_DUMP_DIR, Permissions::from_mode(0o1777)).await?;
+    fs::write(
+        "/proc/sys/kernel/core_pattern",
+        format!("{}/core.%e.%p.%t", CORE_DUMP_DIR),
+    )
+    .await?;
+
+    let limit = libc::rlimit {
+        rlim_cur: config.max_size as libc::rlim_t,
+        rlim_max: config.max_size as libc::rlim_t,
+    };
+    if unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) } != 0 {
+        bail!(
+            "failed to set core size limit: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+
+    Ok(())
+}
+
+/// Hands the cores left behind by crashed processes to the host, oldest first, and removes
+/// them from the guest. Returns how many were sent.
+pub async fn upload_core_dumps(guest_manager: &Arc<GuestManager>) -> Result<usize> {
+    let mut cores = vec![];
+    let mut entries = fs::read_dir(CORE_DUMP_DIR).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let metadata = entry.metadata().await?;
+        if metadata.is_file() {
+            cores.push((metadata.modified()?, entry.path()));
+        }
+    }
+    cores.sort();
+
+    let mut uploaded = 0;
+    for (_, path) in cores {
+        let guest_manager = guest_manager.clone();
+        let core_path = path.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let mut core = std::fs::File::open(&core_path)?;
+            guest_manager.send_core_dump(&mut core)
+        })
+        .await?;
+
+        match result {
+            Ok(size) => {
+                info!("uploaded core dump {} ({} bytes)", path.display(), size);
+                uploaded += 1;
+            }
+            Err(e) => warn!("failed to upload core dump {}: {}", path.display(), e),
+        }
+
+        let _ = fs::remove_file(&path).await;
+    }
+
+    Ok(uploaded)
+}
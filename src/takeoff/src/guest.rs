@@ -3,17 +3,19 @@ use std::{
     fs::File,
     io::{Read, Seek, SeekFrom},
     os::fd::{AsRawFd, FromRawFd},
+    ptr::NonNull,
+    sync::atomic::{Ordering, compiler_fence},
 };
 
-use anyhow::Result;
+use anyhow::{Result, bail};
 use nix::{
     fcntl::{OFlag, open},
     sys::{
-        mman::{MapFlags, ProtFlags, mmap},
+        mman::{MapFlags, ProtFlags, mlock, mmap, munlock},
         stat::Mode,
     },
 };
-use takeoff_proto::proto::TakeoffInitArgs;
+use takeoff_proto::proto::{TakeoffInitArgs, VolumeUsageReport};
 use tracing::info;
 
 const PAGE_SIZE: usize = 4096;
@@ -126,6 +128,77 @@ impl GuestManager {
         TakeoffInitArgs::decode(&str)
     }
 
+    /// Streams a core dump to the host a page at a time, the host copies every chunk straight
+    /// out of guest memory before the write returns.
+    pub fn send_core_dump(&self, core: &mut impl Read) -> Result<u64> {
+        // a page aligned buffer, so a chunk never spans two physical pages
+        let mut buffer = vec![0u8; PAGE_SIZE * 2];
+        let offset = buffer.as_ptr().align_offset(PAGE_SIZE);
+        let page = &mut buffer[offset..offset + PAGE_SIZE];
+
+        // the zeroed allocation may not be backed by a page of its own until written to, and the
+        // page mustn't move while the host reads from its physical address
+        page.fill(0xff);
+        let page_ptr = NonNull::from(&mut *page).cast::<c_void>();
+        unsafe { mlock(page_ptr, PAGE_SIZE)? };
+
+        let phys_addr = match self.virt_to_phys(page.as_ptr() as u64) {
+            Ok(phys_addr) => phys_addr,
+            Err(e) => {
+                let _ = unsafe { munlock(page_ptr, PAGE_SIZE) };
+                return Err(e);
+            }
+        };
+        if phys_addr > u32::MAX as u64 {
+            let _ = unsafe { munlock(page_ptr, PAGE_SIZE) };
+            bail!(
+                "core dump buffer at {:x} is out of reach of the host",
+                phys_addr
+            );
+        }
+
+        let mut sent = 0;
+        let result = loop {
+            let len = match core.read(page) {
+                Ok(0) => break Ok(sent),
+                Ok(len) => len,
+                Err(e) => break Err(e.into()),
+            };
+
+            compiler_fence(Ordering::SeqCst);
+            unsafe {
+                let ptr = self.map_base.as_ptr().add(24) as *mut u64;
+                // upper 32 bits = chunk length, lower 32 bits = physical address
+                ptr.write_volatile(((len as u64) << 32) | phys_addr);
+            }
+            sent += len as u64;
+        };
+
+        // ends the dump even when reading failed, the host keeps what it got so far
+        unsafe {
+            let ptr = self.map_base.as_ptr().add(32) as *mut u64;
+            ptr.write_volatile(0);
+        }
+        let _ = unsafe { munlock(page_ptr, PAGE_SIZE) };
+
+        result
+    }
+
+    /// Counter the host bumps every time one of the volumes of the machine grows.
+    pub fn read_volumes_resized(&self) -> u64 {
+        unsafe {
+            let ptr = self.map_base.as_ptr().add(24) as *const u64;
+            ptr.read_volatile()
+        }
+    }
+
+    pub fn report_volume_usage(&self, report: VolumeUsageReport) {
+        unsafe {
+            let ptr = self.map_base.as_ptr().add(40) as *mut u64;
+            ptr.write_volatile(report.encode());
+        }
+    }
+
     #[allow(dead_code)]
     pub fn trigger_manual_snapshot(&self) {
         unsafe {
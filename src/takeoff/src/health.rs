@@ -0,0 +1,201 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use nix::libc;
+use takeoff_proto::proto::HealthConfig;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+use tracing::{info, warn};
+
+const HEALTH_PATH: &str = "/healthz";
+const PORT_CHECK_TIMEOUT: Duration = Duration::from_secs(1);
+const REQUEST_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serves `GET /healthz` for apps without a health endpoint: 200 while the app process is alive
+/// and, when configured, accepts connections on its port.
+pub async fn run_health_server(config: HealthConfig, pid: Option<u32>) -> Result<()> {
+    let listener = TcpListener::bind(("0.0.0.0", config.port)).await?;
+    info!("serving {} on port {}", HEALTH_PATH, config.port);
+
+    while let Ok((stream, _)) = listener.accept().await {
+        let config = config.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_health_request(stream, &config, pid).await {
+                warn!("health request failed: {}", e);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+async fn handle_health_request(
+    mut stream: TcpStream,
+    config: &HealthConfig,
+    pid: Option<u32>,
+) -> Result<()> {
+    let mut buf = vec![0u8; 1024];
+    let mut read = 0;
+    while read < buf.len() && !buf[..read].windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = timeout(REQUEST_READ_TIMEOUT, stream.read(&mut buf[read..])).await??;
+        if n == 0 {
+            break;
+        }
+        read += n;
+    }
+
+    let request = String::from_utf8_lossy(&buf[..read]);
+    let mut request_line = request
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .split_whitespace();
+    let method = request_line.next().unwrap_or_default();
+    let path = request_line.next().unwrap_or_default();
+    let path = path.split('?').next().unwrap_or_default();
+
+    let (status, content_type, body) = if path != HEALTH_PATH {
+        ("404 Not Found", "text/plain", "not found\n".to_string())
+    } else {
+        let process = pid.is_some_and(is_process_alive);
+        let port = match config.app_port {
+            Some(port) => Some(is_port_open(port).await),
+            None => None,
+        };
+
+        let healthy = process && port.unwrap_or(true);
+        let status = if healthy {
+            "200 OK"
+        } else {
+            "503 Service Unavailable"
+        };
+        let body = serde_json::json!({
+            "status": if healthy { "ok" } else { "unhealthy" },
+            "process": process,
+            "port": port,
+        });
+
+        (status, "application/json", format!("{}\n", body))
+    };
+
+    let content_length = body.len();
+    let body = if method == "HEAD" { "" } else { body.as_str() };
+    let response = format!(
+        "HTTP/1.1 {}\r\ncontent-type: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+        status, content_type, content_length, body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
+
+fn is_process_alive(pid: u32) -> bool {
+    // signal 0 only checks that the process exists, takeoff reaps it once it exits
+    unsafe { libc::kill(pid as libc::pid_t, 0) == 0 }
+}
+
+async fn is_port_open(port: u16) -> bool {
+    matches!(
+        timeout(PORT_CHECK_TIMEOUT, TcpStream::connect(("127.0.0.1", port))).await,
+        Ok(Ok(_))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sends `request` to the health handler and returns the raw response.
+    async fn send(config: HealthConfig, pid: Option<u32>, request: &str) -> String {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            handle_health_request(stream, &config, pid).await.unwrap();
+        });
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        server.await.unwrap();
+
+        response
+    }
+
+    fn config(app_port: Option<u16>) -> HealthConfig {
+        HealthConfig { port: 0, app_port }
+    }
+
+    async fn closed_port() -> u16 {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        listener.local_addr().unwrap().port()
+    }
+
+    #[tokio::test]
+    async fn test_healthy_while_process_alive() {
+        let response = send(
+            config(None),
+            Some(std::process::id()),
+            "GET /healthz HTTP/1.1\r\nhost: localhost\r\n\r\n",
+        )
+        .await;
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("{\"port\":null,\"process\":true,\"status\":\"ok\"}\n"));
+    }
+
+    #[tokio::test]
+    async fn test_unhealthy_without_process() {
+        let response = send(config(None), None, "GET /healthz HTTP/1.1\r\n\r\n").await;
+
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(response.contains("\"process\":false"));
+    }
+
+    #[tokio::test]
+    async fn test_app_port_checked() {
+        let app = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let open_port = app.local_addr().unwrap().port();
+
+        let response = send(
+            config(Some(open_port)),
+            Some(std::process::id()),
+            "GET /healthz?probe=1 HTTP/1.1\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\"port\":true"));
+
+        let response = send(
+            config(Some(closed_port().await)),
+            Some(std::process::id()),
+            "GET /healthz HTTP/1.1\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(response.contains("\"port\":false"));
+    }
+
+    #[tokio::test]
+    async fn test_head_and_unknown_path() {
+        let response = send(
+            config(None),
+            Some(std::process::id()),
+            "HEAD /healthz HTTP/1.1\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("content-length: 43\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+
+        let response = send(config(None), None, "GET /metrics HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(response.ends_with("not found\n"));
+    }
+}
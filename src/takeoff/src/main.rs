@@ -1,4 +1,7 @@
+mod core_dump;
 mod guest;
+mod health;
+mod metrics;
 mod mount;
 mod oci_config;
 mod serial;
@@ -17,13 +20,14 @@ use nix::{
 };
 use oci_config::{EnvVar, OciConfig};
 use serial::SerialWriter;
-use takeoff_proto::proto::LogsTelemetryConfig;
+use takeoff_proto::proto::{DebugTraceConfig, LogsTelemetryConfig};
 
 use tokio::{
     fs,
     io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
-    net::{TcpListener, TcpStream},
+    net::{TcpListener, TcpStream, unix::pipe},
     process::Command,
+    sync::oneshot,
     task::JoinHandle,
     time::sleep,
 };
@@ -65,6 +69,14 @@ async fn takeoff() -> Result<()> {
     let real_root = args.mount_points.first().expect("real root mount point");
     mount(&real_root.source, "/real_root", Some("ext4")).await;
 
+    // a statically linked tracer may be bundled in the initrd, images rarely ship one
+    let bundled_tracer = match &args.debug_trace {
+        Some(trace) => fs::read(format!("/lttle/bin/{}", trace.tool.binary_name()))
+            .await
+            .ok(),
+        None => None,
+    };
+
     chroot("/real_root").expect("chroot");
     chdir("/").expect("chdir");
 
@@ -103,11 +115,32 @@ async fn takeoff() -> Result<()> {
             "mounting {} to {} (read-only: {})",
             mount_point.source, mount_point.target, mount_point.read_only
         );
-        mount(&mount_point.source, &mount_point.target, Some("ext4")).await;
+        let filesystem = mount_point.filesystem();
+        mount(
+            &mount_point.source,
+            &mount_point.target,
+            Some(filesystem.name()),
+        )
+        .await;
         if !mount_point.read_only {
             let _ = fs::remove_dir_all(format!("{}/lost+found", mount_point.target)).await;
+
+            // the volume may have grown while the machine was stopped
+            if let Err(e) =
+                mount::grow_filesystem(&mount_point.source, &mount_point.target, filesystem).await
+            {
+                warn!("{}", e);
+            }
         }
     }
+    tokio::spawn(mount::watch_volume_resizes(
+        guest_manager.clone(),
+        args.mount_points.iter().skip(1).cloned().collect(),
+    ));
+    tokio::spawn(mount::report_volume_usage(
+        guest_manager.clone(),
+        args.mount_points.clone(),
+    ));
 
     let config = fs::read_to_string("/etc/lttle/oci-config.json")
         .await
@@ -123,6 +156,22 @@ async fn takeoff() -> Result<()> {
     };
     info!("cmd: {:?}", cmd);
 
+    let trace_fifo = match &args.debug_trace {
+        Some(trace) => match prepare_debug_trace(trace, bundled_tracer).await {
+            Ok((tracer, fifo, receiver)) => {
+                cmd = trace.tool.command(tracer, &fifo, cmd);
+                info!("tracing cmd: {:?}", cmd);
+
+                Some(receiver)
+            }
+            Err(e) => {
+                warn!("debug trace requested but not available: {}", e);
+                None
+            }
+        },
+        None => None,
+    };
+
     let mut envs = HashMap::new();
     if let Some(config_envs) = config.env {
         for env in config_envs {
@@ -152,12 +201,38 @@ async fn takeoff() -> Result<()> {
         let _ = fs::remove_file(link).await;
         let _ = fs::symlink(target, link).await;
     }
+
+    if let Some(core_dump) = &args.core_dump
+        && let Err(e) = core_dump::prepare_core_dumps(core_dump).await
+    {
+        warn!("core dumps are not available: {}", e);
+    }
+
     let telemetry_config = args.logs_telemetry_config.clone();
     let otel_provider = tokio::task::spawn_blocking(move || {
         tokio::runtime::Handle::current().block_on(init_otel_logger(telemetry_config))
     })
     .await??;
 
+    let meter_provider = match args.metrics_telemetry_config.clone() {
+        Some(metrics_config) => {
+            let logs_config = args.logs_telemetry_config.clone();
+            let provider = tokio::task::spawn_blocking(move || {
+                metrics::init_otel_meter(&logs_config, &metrics_config)
+            })
+            .await?;
+
+            match provider {
+                Ok(provider) => Some(provider),
+                Err(e) => {
+                    warn!("machine metrics are not exported: {}", e);
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+
     let stdout_logger = otel_provider.logger(format!(
         "{}/stdout",
         args.logs_telemetry_config.service_name
@@ -169,6 +244,21 @@ async fn takeoff() -> Result<()> {
     let cmd_logger =
         otel_provider.logger(format!("{}/cmd", args.logs_telemetry_config.service_name));
 
+    let (trace_exited_tx, trace_exited_rx) = oneshot::channel();
+    let trace_task = match (trace_fifo, &args.debug_trace) {
+        (Some(receiver), Some(trace)) => {
+            let trace_logger =
+                otel_provider.logger(format!("{}/trace", args.logs_telemetry_config.service_name));
+            Some(tokio::spawn(forward_debug_trace(
+                receiver,
+                trace.max_lines,
+                trace_logger,
+                trace_exited_rx,
+            )))
+        }
+        _ => None,
+    };
+
     let working_dir = config.working_dir.clone().unwrap_or("/".to_string());
 
     if cmd.is_empty() {
@@ -412,6 +502,14 @@ async fn takeoff() -> Result<()> {
 
     let pid = child.id();
 
+    if let Some(health) = args.health.clone() {
+        tokio::spawn(async move {
+            if let Err(e) = health::run_health_server(health, pid).await {
+                error!("health server failed: {}", e);
+            }
+        });
+    }
+
     let stdout = child.stdout.take().expect("piped stdout");
     let stderr = child.stderr.take().expect("piped stderr");
 
@@ -466,8 +564,25 @@ async fn takeoff() -> Result<()> {
     let status = child.wait().await?;
     let _ = out_task.await;
     let _ = err_task.await;
+    if let Some(trace_task) = trace_task {
+        let _ = trace_exited_tx.send(());
+        let _ = tokio::time::timeout(Duration::from_secs(2), trace_task).await;
+    }
 
     info!("command exited with code {:?}", status.code());
+
+    // the host stops the machine once it has the exit code, the cores have to be out by then
+    let core_dumps = match &args.core_dump {
+        Some(_) => match core_dump::upload_core_dumps(&guest_manager).await {
+            Ok(uploaded) => uploaded,
+            Err(e) => {
+                warn!("failed to collect core dumps: {}", e);
+                0
+            }
+        },
+        None => 0,
+    };
+
     guest_manager.set_exit_code(status.code().unwrap_or(1));
 
     {
@@ -491,10 +606,16 @@ async fn takeoff() -> Result<()> {
         if let Some(code) = status.code() {
             rec.add_attribute("process.exit_code", code as i64);
         }
+        if core_dumps > 0 {
+            rec.add_attribute("process.core_dumps", core_dumps as i64);
+        }
         cmd_logger.emit(rec);
     }
 
     otel_provider.force_flush()?;
+    if let Some(meter_provider) = &meter_provider {
+        let _ = meter_provider.force_flush();
+    }
 
     if !status.success() {
         info!("command failed: {}", status);
@@ -504,6 +625,127 @@ async fn takeoff() -> Result<()> {
     Ok(())
 }
 
+/// The tracer to run and the FIFO it writes to, already open for reading.
+async fn prepare_debug_trace(
+    trace: &DebugTraceConfig,
+    bundled_tracer: Option<Vec<u8>>,
+) -> Result<(String, String, pipe::Receiver)> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::create_dir_all("/run/lttle").await?;
+
+    let tracer = match bundled_tracer {
+        Some(bytes) => {
+            let path = format!("/run/lttle/{}", trace.tool.binary_name());
+            fs::write(&path, bytes).await?;
+            fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).await?;
+            path
+        }
+        None => {
+            let mut found = None;
+            for dir in ["/usr/local/bin", "/usr/bin", "/bin", "/usr/sbin", "/sbin"] {
+                let path = format!("{}/{}", dir, trace.tool.binary_name());
+                if fs::metadata(&path).await.is_ok() {
+                    found = Some(path);
+                    break;
+                }
+            }
+
+            let Some(path) = found else {
+                bail!("{} not found in image", trace.tool.binary_name());
+            };
+            path
+        }
+    };
+
+    let fifo = "/run/lttle/trace.fifo".to_string();
+    let _ = fs::remove_file(&fifo).await;
+    nix::unistd::mkfifo(
+        fifo.as_str(),
+        nix::sys::stat::Mode::from_bits_truncate(0o666),
+    )?;
+    // the entrypoint might not run as root
+    fs::set_permissions(&fifo, std::fs::Permissions::from_mode(0o666)).await?;
+
+    // opened read-write, neither this open nor the one of the tracer waits for the other end,
+    // and there is no EOF before the tracer opens it
+    let receiver = pipe::OpenOptions::new()
+        .read_write(true)
+        .open_receiver(&fifo)?;
+
+    Ok((tracer, fifo, receiver))
+}
+
+/// Forwards the lines of the tracer until the traced command exited and the FIFO is drained,
+/// the FIFO never reaches EOF as it's open for writing here as well.
+async fn forward_debug_trace(
+    receiver: pipe::Receiver,
+    max_lines: u32,
+    logger: impl Logger,
+    mut exited: oneshot::Receiver<()>,
+) {
+    let mut emitted = 0u32;
+    let mut dropped = 0u64;
+
+    let mut lines = BufReader::new(receiver).lines();
+    loop {
+        let line = tokio::select! {
+            line = lines.next_line() => line,
+            _ = &mut exited => break,
+        };
+        let Ok(Some(line)) = line else {
+            break;
+        };
+        forward_trace_line(&logger, line, max_lines, &mut emitted, &mut dropped);
+    }
+
+    // what the tracer wrote last is still buffered in the FIFO
+    while let Ok(Ok(Some(line))) =
+        tokio::time::timeout(Duration::from_millis(200), lines.next_line()).await
+    {
+        forward_trace_line(&logger, line, max_lines, &mut emitted, &mut dropped);
+    }
+
+    if dropped > 0 {
+        let mut rec = logger.create_log_record();
+        rec.set_severity_number(Severity::Warn);
+        rec.set_severity_text("WARN");
+        rec.set_body(AnyValue::String(
+            format!(
+                "trace output truncated after {} lines, {} lines dropped",
+                max_lines, dropped
+            )
+            .into(),
+        ));
+        rec.add_attribute("log.stream", "stderr");
+        rec.add_attribute("log.source", "trace");
+        logger.emit(rec);
+    }
+}
+
+fn forward_trace_line(
+    logger: &impl Logger,
+    line: String,
+    max_lines: u32,
+    emitted: &mut u32,
+    dropped: &mut u64,
+) {
+    // keep draining once over the limit so the tracer never blocks
+    if *emitted >= max_lines {
+        *dropped += 1;
+        return;
+    }
+    *emitted += 1;
+
+    let mut rec = logger.create_log_record();
+    rec.set_severity_number(Severity::Debug);
+    rec.set_severity_text("DEBUG");
+    rec.set_body(AnyValue::String(line.into()));
+    rec.add_attribute("log.stream", "stderr");
+    rec.add_attribute("log.source", "trace");
+    logger.emit(rec);
+}
+
 async fn setup_additional_devices() -> Result<()> {
     info!("Setting up additional devices for application compatibility");
 
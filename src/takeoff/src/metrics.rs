@@ -0,0 +1,129 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Result;
+use opentelemetry::{
+    KeyValue,
+    metrics::{Meter, MeterProvider},
+};
+use opentelemetry_otlp::{Protocol, WithExportConfig};
+use opentelemetry_sdk::{
+    Resource,
+    metrics::{PeriodicReader, SdkMeterProvider},
+};
+use takeoff_proto::proto::{LogsTelemetryConfig, MetricsTelemetryConfig};
+
+/// Exports the resource usage of the machine, labelled like its logs.
+pub fn init_otel_meter(
+    logs_cfg: &LogsTelemetryConfig,
+    cfg: &MetricsTelemetryConfig,
+) -> Result<SdkMeterProvider> {
+    let exporter = opentelemetry_otlp::MetricExporter::builder()
+        .with_http()
+        .with_protocol(Protocol::HttpBinary)
+        .with_endpoint(&cfg.endpoint)
+        .build()?;
+
+    let reader = PeriodicReader::builder(exporter)
+        .with_interval(Duration::from_secs(cfg.interval_secs.max(1) as u64))
+        .build();
+
+    let resource = Resource::builder()
+        .with_attributes(vec![
+            KeyValue::new("service.name", logs_cfg.service_name.clone()),
+            KeyValue::new("service.namespace", logs_cfg.service_namespace.clone()),
+            KeyValue::new("service.group", logs_cfg.service_group.clone()),
+            KeyValue::new("service.tenant", logs_cfg.tenant_id.clone()),
+        ])
+        .build();
+
+    let provider = SdkMeterProvider::builder()
+        .with_reader(reader)
+        .with_resource(resource)
+        .build();
+
+    register_resource_usage(&provider.meter("takeoff"));
+
+    Ok(provider)
+}
+
+fn register_resource_usage(meter: &Meter) {
+    // busy and total jiffies of the previous observation
+    let previous_cpu = Arc::new(Mutex::new(None::<(u64, u64)>));
+    meter
+        .f64_observable_gauge("machine.cpu.utilization")
+        .with_description("Share of the vCPU time spent busy since the last export")
+        .with_callback(move |observer| {
+            let Some((busy, total)) = read_cpu_jiffies() else {
+                return;
+            };
+
+            let mut previous = previous_cpu.lock().expect("cpu sample poisoned");
+            if let Some((previous_busy, previous_total)) = previous.replace((busy, total)) {
+                let elapsed = total.saturating_sub(previous_total);
+                if elapsed > 0 {
+                    observer.observe(
+                        busy.saturating_sub(previous_busy) as f64 / elapsed as f64,
+                        &[],
+                    );
+                }
+            }
+        })
+        .build();
+
+    meter
+        .u64_observable_gauge("machine.memory.used")
+        .with_description("Bytes of memory in use, page cache excluded")
+        .with_callback(|observer| {
+            if let Some((total, available)) = read_memory_bytes() {
+                observer.observe(total.saturating_sub(available), &[]);
+            }
+        })
+        .build();
+
+    meter
+        .u64_observable_gauge("machine.memory.total")
+        .with_description("Bytes of memory of the machine")
+        .with_callback(|observer| {
+            if let Some((total, _)) = read_memory_bytes() {
+                observer.observe(total, &[]);
+            }
+        })
+        .build();
+}
+
+/// Busy and total jiffies of all the vCPUs, from the aggregate line of `/proc/stat`.
+fn read_cpu_jiffies() -> Option<(u64, u64)> {
+    let stat = std::fs::read_to_string("/proc/stat").ok()?;
+    let line = stat.lines().find(|line| line.starts_with("cpu "))?;
+
+    let values = line
+        .split_whitespace()
+        .skip(1)
+        .filter_map(|value| value.parse::<u64>().ok())
+        .collect::<Vec<_>>();
+
+    // user nice system idle iowait irq softirq steal
+    let total = values.iter().take(8).sum::<u64>();
+    let idle = values.get(3).copied().unwrap_or(0) + values.get(4).copied().unwrap_or(0);
+
+    Some((total.saturating_sub(idle), total))
+}
+
+/// Total and available bytes of memory, from `/proc/meminfo`.
+fn read_memory_bytes() -> Option<(u64, u64)> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+
+    let field = |name: &str| {
+        meminfo
+            .lines()
+            .find(|line| line.starts_with(name))
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|kib| kib.parse::<u64>().ok())
+            .map(|kib| kib * 1024)
+    };
+
+    Some((field("MemTotal:")?, field("MemAvailable:")?))
+}
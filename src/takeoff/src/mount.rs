@@ -1,8 +1,69 @@
-use std::path::PathBuf;
+use std::{os::fd::AsRawFd, path::PathBuf, sync::Arc, time::Duration};
 
-use nix::mount::{self, MsFlags};
-use tokio::fs;
-use tracing::warn;
+use anyhow::{Result, bail};
+use nix::{
+    libc,
+    mount::{self, MsFlags},
+    sys::statvfs::statvfs,
+};
+use takeoff_proto::proto::{Filesystem, MountPoint, VolumeUsageReport};
+use tokio::{fs, time::sleep};
+use tracing::{info, warn};
+
+use crate::guest::GuestManager;
+
+/// `_IOW('f', 16, __u64)`, grows a mounted ext4 filesystem to the given block count.
+const EXT4_IOC_RESIZE_FS: u32 = 0x40086610;
+/// `_IOR('X', 100, struct xfs_fsop_geom_v1)`, geometry of a mounted xfs filesystem.
+const XFS_IOC_FSGEOMETRY_V1: u32 = 0x80705864;
+/// `_IOW('X', 110, struct xfs_growfs_data)`, grows a mounted xfs filesystem.
+const XFS_IOC_FSGROWFSDATA: u32 = 0x4010586e;
+/// `_IOW(0x94, 3, struct btrfs_ioctl_vol_args)`, resizes a mounted btrfs filesystem.
+const BTRFS_IOC_RESIZE: u32 = 0x50009403;
+/// `_IOR(0x12, 114, size_t)`, size of a block device in bytes.
+const BLKGETSIZE64: u32 = 0x80081272;
+const VOLUME_RESIZE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+const VOLUME_USAGE_REPORT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// `struct xfs_fsop_geom_v1`
+#[repr(C)]
+#[derive(Default)]
+struct XfsGeometry {
+    blocksize: u32,
+    rtextsize: u32,
+    agblocks: u32,
+    agcount: u32,
+    logblocks: u32,
+    sectsize: u32,
+    inodesize: u32,
+    imaxpct: u32,
+    datablocks: u64,
+    rtblocks: u64,
+    rtextents: u64,
+    logstart: u64,
+    uuid: [u8; 16],
+    sunit: u32,
+    swidth: u32,
+    version: i32,
+    flags: u32,
+    logsectsize: u32,
+    rtsectsize: u32,
+    dirblocksize: u32,
+}
+
+/// `struct xfs_growfs_data`
+#[repr(C)]
+struct XfsGrowData {
+    newblocks: u64,
+    imaxpct: u32,
+}
+
+/// `struct btrfs_ioctl_vol_args`
+#[repr(C)]
+struct BtrfsVolumeArgs {
+    fd: i64,
+    name: [u8; 4088],
+}
 
 pub async fn mount(device: &str, mount_point: &str, fs_type: Option<&str>) {
     mount_with_options(device, mount_point, fs_type, MsFlags::empty(), None).await;
@@ -33,3 +94,176 @@ pub async fn mount_with_options(
         warn!("mount {} failed: {:?}", mount_point, e);
     }
 }
+
+fn device_size(device: &str) -> Result<u64> {
+    let mut device_size = 0u64;
+    let device_file = std::fs::File::open(device)?;
+    let result =
+        unsafe { libc::ioctl(device_file.as_raw_fd(), BLKGETSIZE64 as _, &mut device_size) };
+    if result != 0 {
+        bail!(
+            "failed to read the size of {}: {}",
+            device,
+            std::io::Error::last_os_error()
+        );
+    }
+
+    Ok(device_size)
+}
+
+/// Grows the filesystem mounted at `mount_point` to the size of `device`, like an online
+/// `resize2fs`, `xfs_growfs` or `btrfs filesystem resize max`. Nothing changes when the
+/// filesystem already fills the device.
+pub async fn grow_filesystem(
+    device: &str,
+    mount_point: &str,
+    filesystem: Filesystem,
+) -> Result<()> {
+    match filesystem {
+        Filesystem::Ext4 => grow_ext4(device, mount_point),
+        Filesystem::Xfs => grow_xfs(device, mount_point),
+        Filesystem::Btrfs => grow_btrfs(mount_point),
+    }
+}
+
+fn grow_ext4(device: &str, mount_point: &str) -> Result<()> {
+    let device_size = device_size(device)?;
+    let block_size = statvfs(mount_point)?.block_size() as u64;
+    if block_size == 0 {
+        bail!("invalid block size for {}", mount_point);
+    }
+    let block_count = device_size / block_size;
+
+    let dir = std::fs::File::open(mount_point)?;
+    let result = unsafe { libc::ioctl(dir.as_raw_fd(), EXT4_IOC_RESIZE_FS as _, &block_count) };
+    if result != 0 {
+        bail!(
+            "failed to grow the filesystem at {}: {}",
+            mount_point,
+            std::io::Error::last_os_error()
+        );
+    }
+
+    info!(
+        "filesystem at {} fills {} blocks of {} bytes",
+        mount_point, block_count, block_size
+    );
+
+    Ok(())
+}
+
+fn grow_xfs(device: &str, mount_point: &str) -> Result<()> {
+    let device_size = device_size(device)?;
+
+    let dir = std::fs::File::open(mount_point)?;
+    let mut geometry = XfsGeometry::default();
+    let result = unsafe { libc::ioctl(dir.as_raw_fd(), XFS_IOC_FSGEOMETRY_V1 as _, &mut geometry) };
+    if result != 0 || geometry.blocksize == 0 {
+        bail!(
+            "failed to read the geometry of {}: {}",
+            mount_point,
+            std::io::Error::last_os_error()
+        );
+    }
+
+    let block_count = device_size / geometry.blocksize as u64;
+    if block_count <= geometry.datablocks {
+        return Ok(());
+    }
+
+    let grow = XfsGrowData {
+        newblocks: block_count,
+        imaxpct: geometry.imaxpct,
+    };
+    let result = unsafe { libc::ioctl(dir.as_raw_fd(), XFS_IOC_FSGROWFSDATA as _, &grow) };
+    if result != 0 {
+        bail!(
+            "failed to grow the filesystem at {}: {}",
+            mount_point,
+            std::io::Error::last_os_error()
+        );
+    }
+
+    info!(
+        "filesystem at {} fills {} blocks of {} bytes",
+        mount_point, block_count, geometry.blocksize
+    );
+
+    Ok(())
+}
+
+fn grow_btrfs(mount_point: &str) -> Result<()> {
+    let mut args = BtrfsVolumeArgs {
+        fd: 0,
+        name: [0; 4088],
+    };
+    args.name[..3].copy_from_slice(b"max");
+
+    let dir = std::fs::File::open(mount_point)?;
+    let result = unsafe { libc::ioctl(dir.as_raw_fd(), BTRFS_IOC_RESIZE as _, &args) };
+    if result != 0 {
+        bail!(
+            "failed to grow the filesystem at {}: {}",
+            mount_point,
+            std::io::Error::last_os_error()
+        );
+    }
+
+    info!("filesystem at {} fills its device", mount_point);
+
+    Ok(())
+}
+
+/// Grows the writeable volumes every time the host reports that one of them got bigger.
+pub async fn watch_volume_resizes(guest_manager: Arc<GuestManager>, mount_points: Vec<MountPoint>) {
+    let mut last_seen = guest_manager.read_volumes_resized();
+    loop {
+        sleep(VOLUME_RESIZE_POLL_INTERVAL).await;
+
+        let resized = guest_manager.read_volumes_resized();
+        if resized == last_seen {
+            continue;
+        }
+        last_seen = resized;
+
+        for mount_point in mount_points.iter().filter(|m| !m.read_only) {
+            if let Err(e) = grow_filesystem(
+                &mount_point.source,
+                &mount_point.target,
+                mount_point.filesystem(),
+            )
+            .await
+            {
+                warn!("{}", e);
+            }
+        }
+    }
+}
+
+/// Periodically tells the host how full the filesystems of the volumes are. The root
+/// filesystem (the first mount point) is skipped, it isn't a volume of the user.
+pub async fn report_volume_usage(guest_manager: Arc<GuestManager>, mount_points: Vec<MountPoint>) {
+    loop {
+        for (index, mount_point) in mount_points.iter().enumerate().skip(1) {
+            let stat = match statvfs(mount_point.target.as_str()) {
+                Ok(stat) => stat,
+                Err(e) => {
+                    warn!("failed to stat {}: {}", mount_point.target, e);
+                    continue;
+                }
+            };
+
+            let fragment_size = stat.fragment_size() as u64;
+            let blocks = stat.blocks() as u64;
+            let blocks_free = stat.blocks_free() as u64;
+
+            guest_manager.report_volume_usage(VolumeUsageReport {
+                mount_index: index as u8,
+                used_bytes: blocks.saturating_sub(blocks_free) * fragment_size,
+                size_bytes: blocks * fragment_size,
+            });
+        }
+
+        sleep(VOLUME_USAGE_REPORT_INTERVAL).await;
+    }
+}
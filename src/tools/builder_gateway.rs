@@ -0,0 +1,131 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use anyhow::{Result, anyhow};
+use ignition::utils::tracing::init_tracing;
+use rustls::{
+    RootCertStore, ServerConfig,
+    pki_types::{CertificateDer, CertificateRevocationListDer, PrivateKeyDer, pem::PemObject},
+    server::WebPkiClientVerifier,
+};
+use tokio::net::{TcpListener, UnixStream};
+use tokio_rustls::TlsAcceptor;
+use tracing::{error, info, warn};
+
+/// How often the revocation list is read again, the daemon rewrites it on every release.
+const CRL_RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+
+struct GatewayTls {
+    ca_cert_path: PathBuf,
+    server_cert_path: PathBuf,
+    server_key_path: PathBuf,
+    crl_path: PathBuf,
+}
+
+impl GatewayTls {
+    /// Accepts the client certs issued by the build CA, unless the revocation list has them.
+    fn acceptor(&self) -> Result<TlsAcceptor> {
+        let mut roots = RootCertStore::empty();
+        for cert in CertificateDer::pem_file_iter(&self.ca_cert_path)? {
+            roots.add(cert?)?;
+        }
+
+        let crls = CertificateRevocationListDer::pem_file_iter(&self.crl_path)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+            .with_crls(crls)
+            .build()?;
+
+        let certs = CertificateDer::pem_file_iter(&self.server_cert_path)?
+            .collect::<Result<Vec<_>, _>>()?;
+        let key = PrivateKeyDer::from_pem_file(&self.server_key_path)?;
+
+        let mut config = ServerConfig::builder()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(certs, key)?;
+        // buildctl talks gRPC, forwarded as is to buildkitd
+        config.alpn_protocols = vec![b"h2".to_vec()];
+
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+/// Terminates the TLS of builder clients in front of buildkitd, which can't check the revocation
+/// list of released client certs on its own, and forwards them to its unix socket.
+#[tokio::main]
+async fn main() -> Result<()> {
+    init_tracing();
+
+    let args = std::env::args().collect::<Vec<String>>();
+
+    if args.len() != 7 {
+        error!(
+            "Usage: builder-gateway-tool <listen-address> <buildkitd-socket> <ca.pem> <server.pem> <server.key> <clients.crl>"
+        );
+        return Ok(());
+    }
+
+    let listen_address = args[1].clone();
+    let upstream_socket = PathBuf::from(&args[2]);
+    let tls = GatewayTls {
+        ca_cert_path: PathBuf::from(&args[3]),
+        server_cert_path: PathBuf::from(&args[4]),
+        server_key_path: PathBuf::from(&args[5]),
+        crl_path: PathBuf::from(&args[6]),
+    };
+
+    let acceptor = Arc::new(RwLock::new(tls.acceptor()?));
+
+    tokio::spawn({
+        let acceptor = acceptor.clone();
+        async move {
+            let mut interval = tokio::time::interval(CRL_RELOAD_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+
+                // a list that fails to load keeps the previous one in place
+                match tls.acceptor() {
+                    Ok(reloaded) => {
+                        *acceptor.write().expect("acceptor poisoned") = reloaded;
+                    }
+                    Err(e) => warn!("failed to reload {}: {}", tls.crl_path.display(), e),
+                }
+            }
+        }
+    });
+
+    let listener = TcpListener::bind(&listen_address).await?;
+    info!(
+        "forwarding builder clients from {} to {}",
+        listen_address,
+        upstream_socket.display()
+    );
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let acceptor = acceptor.read().expect("acceptor poisoned").clone();
+        let upstream_socket = upstream_socket.clone();
+
+        tokio::spawn(async move {
+            let result: Result<()> = async {
+                let mut client = acceptor
+                    .accept(stream)
+                    .await
+                    .map_err(|e| anyhow!("handshake failed: {}", e))?;
+                let mut upstream = UnixStream::connect(&upstream_socket).await?;
+                tokio::io::copy_bidirectional(&mut client, &mut upstream).await?;
+                Ok(())
+            }
+            .await;
+
+            if let Err(e) = result {
+                warn!("builder client {}: {}", peer, e);
+            }
+        });
+    }
+}
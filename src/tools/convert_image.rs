@@ -0,0 +1,53 @@
+use std::str::FromStr;
+
+use anyhow::Result;
+use ignition::{
+    agent::image::{
+        credentials::DockerCredentialsProvider,
+        extraction::{ExtractionCache, ExtractionProgress},
+        oci,
+    },
+    utils::tracing::init_tracing,
+};
+use oci_client::Reference;
+use tracing::{error, info};
+
+/// Extracts an image into the image directory of an agent (`<data-dir>/agent/images`), so the
+/// daemon can use it without pulling it again.
+#[tokio::main]
+async fn main() -> Result<()> {
+    init_tracing();
+
+    let args = std::env::args().collect::<Vec<String>>();
+
+    if args.len() != 3 {
+        error!("Usage: convert-image-tool <image> <images-dir>");
+        return Ok(());
+    }
+
+    let reference = Reference::from_str(&args[1])?;
+    let extraction_cache = ExtractionCache::new(&args[2]).await?;
+
+    let credentials_provider = DockerCredentialsProvider {};
+    let (manifest, digest, config) = oci::fetch_manifest(&credentials_provider, &reference).await?;
+
+    let image = extraction_cache
+        .extract(
+            &credentials_provider,
+            None,
+            &reference,
+            &manifest,
+            &digest,
+            config,
+            &ExtractionProgress::default(),
+        )
+        .await?;
+    info!(
+        "{} ({}) extracted to {}",
+        image.reference,
+        image.digest,
+        image.rootfs_path.display()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,251 @@
+use anyhow::{Result, anyhow, bail};
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+/// Every combination of month and day comes back within 4 years, a schedule that doesn't
+/// match in that window never does (eg. `0 0 30 2 *`).
+const MAX_SEARCH_DAYS: u64 = 4 * 366;
+
+/// A 5-field cron expression (`minute hour day-of-month month day-of-week`), evaluated in UTC.
+///
+/// Fields accept `*`, values, ranges (`1-5`), steps (`*/15`, `0-30/10`) and lists of those.
+/// Days of the week go from 0 (sunday) to 7 (sunday again). Like cron, a time matches when
+/// either of the day fields matches if both are restricted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    any_day_of_month: bool,
+    any_day_of_week: bool,
+}
+
+impl CronSchedule {
+    pub fn parse(expression: &str) -> Result<Self> {
+        let fields = expression.split_whitespace().collect::<Vec<_>>();
+        let [minute, hour, day_of_month, month, day_of_week] = fields.as_slice() else {
+            bail!(
+                "expected 5 fields (minute hour day-of-month month day-of-week), got {}",
+                fields.len()
+            );
+        };
+
+        let mut days_of_week = parse_field(day_of_week, 0, 7)
+            .map_err(|e| anyhow!("invalid day-of-week field: {}", e))?;
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week = (days_of_week | 1) & !(1 << 7);
+        }
+
+        Ok(Self {
+            minutes: parse_field(minute, 0, 59)
+                .map_err(|e| anyhow!("invalid minute field: {}", e))?,
+            hours: parse_field(hour, 0, 23).map_err(|e| anyhow!("invalid hour field: {}", e))?,
+            days_of_month: parse_field(day_of_month, 1, 31)
+                .map_err(|e| anyhow!("invalid day-of-month field: {}", e))?,
+            months: parse_field(month, 1, 12).map_err(|e| anyhow!("invalid month field: {}", e))?,
+            days_of_week,
+            any_day_of_month: day_of_month.starts_with('*'),
+            any_day_of_week: day_of_week.starts_with('*'),
+        })
+    }
+
+    /// The first time matching the schedule strictly after `unix_secs`, in seconds.
+    pub fn next_after(&self, unix_secs: u64) -> Option<u64> {
+        let start = unix_secs / 60 * 60 + 60;
+        let first_day = start / SECS_PER_DAY;
+
+        for day in first_day..first_day + MAX_SEARCH_DAYS {
+            if !self.matches_day(day) {
+                continue;
+            }
+
+            let day_start = day * SECS_PER_DAY;
+            for hour in 0..24 {
+                if self.hours & (1 << hour) == 0 {
+                    continue;
+                }
+
+                for minute in 0..60 {
+                    if self.minutes & (1 << minute) == 0 {
+                        continue;
+                    }
+
+                    let time = day_start + hour * 3600 + minute * 60;
+                    if time >= start {
+                        return Some(time);
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    fn matches_day(&self, days_since_epoch: u64) -> bool {
+        let (_, month, day) = civil_from_days(days_since_epoch);
+        if self.months & (1 << month) == 0 {
+            return false;
+        }
+
+        // 1970-01-01 was a thursday
+        let weekday = (days_since_epoch + 4) % 7;
+        let day_of_month = self.days_of_month & (1 << day) != 0;
+        let day_of_week = self.days_of_week & (1 << weekday) != 0;
+
+        match (self.any_day_of_month, self.any_day_of_week) {
+            (true, true) => true,
+            (false, true) => day_of_month,
+            (true, false) => day_of_week,
+            (false, false) => day_of_month || day_of_week,
+        }
+    }
+}
+
+/// Bits of the values a field matches.
+fn parse_field(field: &str, min: u64, max: u64) -> Result<u64> {
+    let mut bits = 0u64;
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u64 = step
+                    .parse()
+                    .map_err(|_| anyhow!("invalid step '{}'", step))?;
+                if step == 0 {
+                    bail!("step can't be 0");
+                }
+                (range, step)
+            }
+            None => (part, 1),
+        };
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (parse_value(start, min, max)?, parse_value(end, min, max)?)
+        } else {
+            let value = parse_value(range, min, max)?;
+            // `5/15` runs from 5 to the end of the range, like `5-max/15`
+            if step > 1 {
+                (value, max)
+            } else {
+                (value, value)
+            }
+        };
+
+        if start > end {
+            bail!("range {} is reversed", range);
+        }
+
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+
+    Ok(bits)
+}
+
+fn parse_value(value: &str, min: u64, max: u64) -> Result<u64> {
+    let parsed: u64 = value
+        .parse()
+        .map_err(|_| anyhow!("invalid value '{}'", value))?;
+    if parsed < min || parsed > max {
+        bail!("{} is out of range {}-{}", parsed, min, max);
+    }
+
+    Ok(parsed)
+}
+
+/// (year, month, day) of a day counted from 1970-01-01, from Howard Hinnant's date algorithms.
+fn civil_from_days(days_since_epoch: u64) -> (u64, u64, u64) {
+    let z = days_since_epoch + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2025-01-01T00:00:00Z, a wednesday
+    const JAN_1_2025: u64 = 1_735_689_600;
+
+    #[test]
+    fn test_parse() {
+        assert!(CronSchedule::parse("0 3 * * *").is_ok());
+        assert!(CronSchedule::parse("*/15 0-6,22,23 1 */2 1-5").is_ok());
+        assert!(CronSchedule::parse("0 3 * *").is_err());
+        assert!(CronSchedule::parse("60 * * * *").is_err());
+        assert!(CronSchedule::parse("0 24 * * *").is_err());
+        assert!(CronSchedule::parse("0 0 0 * *").is_err());
+        assert!(CronSchedule::parse("*/0 * * * *").is_err());
+        assert!(CronSchedule::parse("0 5-1 * * *").is_err());
+        assert!(CronSchedule::parse("@daily").is_err());
+    }
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(JAN_1_2025 / SECS_PER_DAY), (2025, 1, 1));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+    }
+
+    #[test]
+    fn test_next_after() {
+        let daily = CronSchedule::parse("30 3 * * *").unwrap();
+        assert_eq!(
+            daily.next_after(JAN_1_2025),
+            Some(JAN_1_2025 + 3 * 3600 + 1800)
+        );
+        assert_eq!(
+            daily.next_after(JAN_1_2025 + 3 * 3600 + 1800),
+            Some(JAN_1_2025 + SECS_PER_DAY + 3 * 3600 + 1800)
+        );
+
+        let quarter_hour = CronSchedule::parse("*/15 * * * *").unwrap();
+        assert_eq!(
+            quarter_hour.next_after(JAN_1_2025 + 61),
+            Some(JAN_1_2025 + 900)
+        );
+
+        // first monday of 2025 is the 6th
+        let mondays = CronSchedule::parse("0 0 * * 1").unwrap();
+        assert_eq!(
+            mondays.next_after(JAN_1_2025),
+            Some(JAN_1_2025 + 5 * SECS_PER_DAY)
+        );
+
+        let sundays = CronSchedule::parse("0 0 * * 7").unwrap();
+        assert_eq!(
+            sundays.next_after(JAN_1_2025),
+            Some(JAN_1_2025 + 4 * SECS_PER_DAY)
+        );
+
+        // the 3rd or any monday
+        let either = CronSchedule::parse("0 0 3 * 1").unwrap();
+        assert_eq!(
+            either.next_after(JAN_1_2025),
+            Some(JAN_1_2025 + 2 * SECS_PER_DAY)
+        );
+
+        let leap_day = CronSchedule::parse("0 0 29 2 *").unwrap();
+        assert_eq!(
+            leap_day
+                .next_after(JAN_1_2025)
+                .map(|t| civil_from_days(t / SECS_PER_DAY)),
+            Some((2028, 2, 29))
+        );
+
+        let never = CronSchedule::parse("0 0 30 2 *").unwrap();
+        assert_eq!(never.next_after(JAN_1_2025), None);
+    }
+}
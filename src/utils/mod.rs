@@ -1,4 +1,6 @@
+pub mod cron;
 pub mod id;
+pub mod redact;
 pub mod size;
 pub mod time;
 pub mod tracing;